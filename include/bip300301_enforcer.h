@@ -0,0 +1,89 @@
+/*
+ * C API for the BIP300/301 enforcer.
+ *
+ * Build with `cargo rustc --lib --release --features cdylib --crate-type cdylib`.
+ *
+ * Functions returning `int` return a negative value on failure, and set an
+ * error message that can be retrieved with `enforcer_last_error`. Hashes are
+ * 32-byte arrays in consensus (internal) byte order. Strings returned by the
+ * API must be freed with `enforcer_string_free`.
+ */
+
+#ifndef BIP300301_ENFORCER_H
+#define BIP300301_ENFORCER_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Handle enforcer_client;
+
+typedef struct {
+    uint8_t block_hash[32];
+    uint8_t prev_block_hash[32];
+    uint32_t height;
+    /* Cumulative chain work, little-endian */
+    uint8_t work[32];
+} EnforcerHeaderInfo;
+
+typedef struct {
+    uint8_t txid[32];
+    uint32_t vout;
+    uint64_t value_sats;
+    uint64_t sequence_number;
+} EnforcerCtip;
+
+/* Last error on the current thread, or NULL. Valid until the next call. */
+const char *enforcer_last_error(void);
+
+void enforcer_string_free(char *s);
+
+/* Returns NULL on failure */
+enforcer_client *enforcer_client_connect(const char *url);
+
+void enforcer_client_free(enforcer_client *client);
+
+/* Returns 0 on success */
+int enforcer_get_chain_tip(enforcer_client *client, EnforcerHeaderInfo *out);
+
+/* Returns 1 if the sidechain has a CTIP, 0 if not */
+int enforcer_get_ctip(enforcer_client *client, uint8_t sidechain_number, EnforcerCtip *out);
+
+/*
+ * Two-way peg data as JSON, for blocks after `start_block_hash` (from genesis
+ * if NULL), up to and including `end_block_hash`. Returns 0 on success.
+ */
+int enforcer_get_two_way_peg_data(
+    enforcer_client *client,
+    uint8_t sidechain_number,
+    const uint8_t *start_block_hash,
+    const uint8_t *end_block_hash,
+    char **out_json);
+
+/*
+ * Subscribe to events, replacing any existing subscription on the client.
+ * Events are replayed from `start_sequence` if it is non-negative.
+ * Returns 0 on success.
+ */
+int enforcer_subscribe_events(
+    enforcer_client *client,
+    uint8_t sidechain_number,
+    int64_t start_sequence);
+
+/*
+ * Wait up to `timeout_ms` for the next event. Returns 1 if an event was
+ * received, 0 on timeout. On failure, the subscription is dropped.
+ */
+int enforcer_poll_event(
+    enforcer_client *client,
+    uint64_t timeout_ms,
+    uint64_t *out_sequence,
+    char **out_json);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BIP300301_ENFORCER_H */
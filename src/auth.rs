@@ -0,0 +1,175 @@
+//! Client authentication for the gRPC API.
+//!
+//! Clients authenticate either with a TLS client certificate signed by the
+//! configured CA, or with a static bearer token in the `authorization`
+//! header.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use thiserror::Error;
+use tonic::{service::Interceptor, Request, Status};
+
+#[derive(Debug, Error)]
+pub enum LoadTokensError {
+    #[error("No tokens in auth tokens file `{path}`")]
+    Empty { path: PathBuf },
+    #[error("Error reading auth tokens file `{path}`")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Read bearer tokens from a file, with one token per line.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn load_tokens(path: &Path) -> Result<Vec<String>, LoadTokensError> {
+    let contents = std::fs::read_to_string(path).map_err(|err| LoadTokensError::Read {
+        path: path.to_owned(),
+        source: err,
+    })?;
+    let tokens: Vec<String> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect();
+    if tokens.is_empty() {
+        return Err(LoadTokensError::Empty {
+            path: path.to_owned(),
+        });
+    }
+    Ok(tokens)
+}
+
+/// Compare without short-circuiting on the first differing byte, so that
+/// response times do not leak how much of a token was guessed correctly
+fn constant_time_eq(lhs: &[u8], rhs: &[u8]) -> bool {
+    lhs.len() == rhs.len()
+        && lhs
+            .iter()
+            .zip(rhs)
+            .fold(0u8, |acc, (lhs, rhs)| acc | (lhs ^ rhs))
+            == 0
+}
+
+/// Interceptor that rejects unauthenticated requests
+#[derive(Clone, Debug, Default)]
+pub struct Authenticator {
+    tokens: Arc<Vec<String>>,
+    /// Accept requests from clients with verified TLS client certificates
+    client_certs: bool,
+    required: bool,
+}
+
+impl Authenticator {
+    pub fn new(tokens: Vec<String>, client_certs: bool) -> Self {
+        Self {
+            tokens: Arc::new(tokens),
+            client_certs,
+            required: true,
+        }
+    }
+
+    /// Accepts all requests
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Accepts all requests, for services that are open to unauthenticated
+    /// clients
+    pub fn optional(&self) -> Self {
+        Self {
+            required: false,
+            ..self.clone()
+        }
+    }
+
+    /// Reject the request unless it is authenticated, or authentication is
+    /// not required.
+    /// Used by RPCs that modify state, which always require authentication
+    /// with the strict authenticator, even if their service uses an
+    /// [optional](Self::optional) authenticator as its interceptor.
+    pub fn check<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        if !self.required || self.is_authenticated(request) {
+            Ok(())
+        } else {
+            Err(Status::unauthenticated(
+                "a valid client certificate or bearer token is required",
+            ))
+        }
+    }
+
+    fn is_authenticated<T>(&self, request: &Request<T>) -> bool {
+        // Client certs are verified against the client CA during the TLS
+        // handshake, so any cert that is present is valid
+        if self.client_certs && request.peer_certs().is_some_and(|certs| !certs.is_empty()) {
+            return true;
+        }
+        let Some(token) = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        self.tokens.iter().fold(false, |found, expected| {
+            found | constant_time_eq(token.as_bytes(), expected.as_bytes())
+        })
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let () = self.check(&request)?;
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::{service::Interceptor as _, Code, Request};
+
+    use super::Authenticator;
+
+    fn request(token: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(token) = token {
+            request
+                .metadata_mut()
+                .insert("authorization", format!("Bearer {token}").parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_bearer_tokens() {
+        let mut auth = Authenticator::new(vec!["secret".to_owned(), "other".to_owned()], false);
+        assert!(auth.call(request(Some("secret"))).is_ok());
+        assert!(auth.call(request(Some("other"))).is_ok());
+        for token in [None, Some("secre"), Some("secrets"), Some("")] {
+            let err = auth.call(request(token)).unwrap_err();
+            assert_eq!(err.code(), Code::Unauthenticated);
+        }
+        assert!(auth.optional().call(request(None)).is_ok());
+        assert!(Authenticator::disabled().call(request(None)).is_ok());
+    }
+
+    /// RPCs that modify state check the strict authenticator, so they are
+    /// rejected without credentials even if unauthenticated reads are
+    /// allowed by the service's interceptor
+    #[test]
+    fn test_writes_require_auth() {
+        let auth = Authenticator::new(vec!["secret".to_owned()], false);
+        let mut read_auth = auth.optional();
+        let unauthenticated = read_auth.call(request(None)).unwrap();
+        let err = auth.check(&unauthenticated).unwrap_err();
+        assert_eq!(err.code(), Code::Unauthenticated);
+        let authenticated = read_auth.call(request(Some("secret"))).unwrap();
+        assert!(auth.check(&authenticated).is_ok());
+        assert!(Authenticator::disabled().check(&request(None)).is_ok());
+    }
+}
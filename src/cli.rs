@@ -1,14 +1,25 @@
 use std::{
     env,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs},
+    num::NonZeroU32,
     path::{Path, PathBuf},
 };
 
-use clap::{Args, Parser};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use thiserror::Error;
 
-const DEFAULT_NODE_RPC_ADDR: SocketAddr =
-    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 18443));
+use crate::{validator::DepositConfirmationDepth, wallet::backend::WalletBackendKind};
+
+/// Default Bitcoin Core RPC port for a network
+fn default_node_rpc_port(network: bitcoin::Network) -> u16 {
+    match network {
+        bitcoin::Network::Bitcoin => 8332,
+        bitcoin::Network::Testnet => 18332,
+        bitcoin::Network::Signet => 38332,
+        // Regtest
+        _ => 18443,
+    }
+}
 
 #[derive(Debug, Error)]
 enum HostAddrError {
@@ -25,6 +36,29 @@ fn parse_host_addr(s: &str) -> Result<SocketAddr, HostAddrError> {
         .ok_or(HostAddrError::FailedResolution)
 }
 
+#[derive(Debug, Error)]
+enum BackupNodeError {
+    #[error("Expected `<rpc addr>,<zmq sequence addr>`")]
+    Format,
+    #[error(transparent)]
+    RpcAddr(#[from] HostAddrError),
+}
+
+/// Backup Bitcoin node, used if the primary node is unreachable
+#[derive(Clone, Debug)]
+pub struct BackupNode {
+    pub rpc_addr: SocketAddr,
+    pub zmq_addr_sequence: String,
+}
+
+fn parse_backup_node(s: &str) -> Result<BackupNode, BackupNodeError> {
+    let (rpc_addr, zmq_addr_sequence) = s.split_once(',').ok_or(BackupNodeError::Format)?;
+    Ok(BackupNode {
+        rpc_addr: parse_host_addr(rpc_addr)?,
+        zmq_addr_sequence: zmq_addr_sequence.to_owned(),
+    })
+}
+
 fn get_data_dir() -> Result<PathBuf, String> {
     const APP_NAME: &str = "bip300301_enforcer";
 
@@ -59,66 +93,512 @@ fn get_data_dir() -> Result<PathBuf, String> {
 
 #[derive(Args, Clone)]
 pub struct NodeRpcConfig {
+    /// Bitcoin Core RPC address. Defaults to localhost, on the default RPC
+    /// port for `--network`.
     #[arg(
-        default_value_t = DEFAULT_NODE_RPC_ADDR,
+        env = "ENFORCER_NODE_RPC_ADDR",
         long = "node-rpc-addr",
         value_parser = parse_host_addr
     )]
-    pub addr: SocketAddr,
+    pub addr: Option<SocketAddr>,
     /// Path to Bitcoin Core cookie. Cannot be set together with user + password.
-    #[arg(long = "node-rpc-cookie-path")]
+    #[arg(env = "ENFORCER_NODE_RPC_COOKIE_PATH", long = "node-rpc-cookie-path")]
     pub cookie_path: Option<String>,
     /// RPC user for Bitcoin Core. Implies also setting password.
     /// Cannot be set together with cookie path.
-    #[arg(long = "node-rpc-user")]
+    #[arg(env = "ENFORCER_NODE_RPC_USER", long = "node-rpc-user")]
     pub user: Option<String>,
     /// RPC password for Bitcoin Core. Implies also setting user. Cannot
     /// be set together with cookie path.
-    #[arg(long = "node-rpc-pass")]
+    #[arg(
+        env = "ENFORCER_NODE_RPC_PASS",
+        hide_env_values = true,
+        long = "node-rpc-pass"
+    )]
     pub pass: Option<String>,
+    /// Timeout for RPC requests to Bitcoin Core, in seconds
+    #[arg(
+        default_value_t = 60,
+        env = "ENFORCER_NODE_RPC_TIMEOUT_SECS",
+        long = "node-rpc-timeout-secs"
+    )]
+    pub timeout_secs: u64,
+    /// Max number of times to retry read-only RPC requests to Bitcoin Core
+    /// that fail due to connection errors or timeouts. Retries are made with
+    /// exponential backoff.
+    #[arg(
+        default_value_t = 5,
+        env = "ENFORCER_NODE_RPC_MAX_RETRIES",
+        long = "node-rpc-max-retries"
+    )]
+    pub max_retries: u32,
+    /// Base URL of Bitcoin Core's REST interface, eg.
+    /// `http://localhost:38332`. If set, block bodies are downloaded from the
+    /// primary node via REST, rather than with `getblock`.
+    /// Requires Bitcoin Core to be running with `-rest`.
+    #[arg(env = "ENFORCER_NODE_REST_URL", long = "node-rest-url")]
+    pub rest_url: Option<reqwest::Url>,
+    /// Address of Bitcoin Core's P2P port, eg. `localhost:38333`. If set,
+    /// headers and blocks are fetched from the primary node over P2P, rather
+    /// than via RPC.
+    #[arg(
+        env = "ENFORCER_NODE_P2P_ADDR",
+        long = "node-p2p-addr",
+        value_parser = parse_host_addr
+    )]
+    pub p2p_addr: Option<SocketAddr>,
+    /// Network magic bytes for P2P messages, as hex. Defaults to the magic
+    /// for the selected network. Must be set for custom signets, which use a
+    /// magic derived from the signet challenge.
+    #[arg(env = "ENFORCER_NODE_P2P_MAGIC", long = "node-p2p-magic")]
+    pub p2p_magic: Option<bitcoin::p2p::Magic>,
+}
+
+impl NodeRpcConfig {
+    /// RPC address of the node, on the default RPC port for `network` if not
+    /// set
+    pub fn rpc_addr(&self, network: bitcoin::Network) -> SocketAddr {
+        self.addr.unwrap_or_else(|| {
+            SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::LOCALHOST,
+                default_node_rpc_port(network),
+            ))
+        })
+    }
 }
 
 #[derive(Clone, Args)]
 pub struct WalletConfig {
+    /// Wallet used to fund and sign transactions.
+    /// The Bitcoin Core backend uses the default wallet of the node
+    /// configured with `--node-rpc-addr`, and requires Bitcoin Core v24 or
+    /// later.
+    #[arg(
+        default_value_t,
+        env = "ENFORCER_WALLET_BACKEND",
+        long = "wallet-backend",
+        value_enum
+    )]
+    pub backend: WalletBackendKind,
+
+    /// Electrum server for the BDK wallet backend.
     /// If no host is provided, a default value is used based on the network
     /// we're on.
     ///
     /// Signet: drivechain.live, regtest: 127.0.0.1  
-    #[arg(long = "wallet-electrum-host")]
+    #[arg(env = "ENFORCER_WALLET_ELECTRUM_HOST", long = "wallet-electrum-host")]
     pub electrum_host: Option<String>,
 
     /// If no port is provided, a default value is used based on the network
     /// we're on.
     ///
     /// Signet: 50001, regtest: 60401
-    #[arg(long = "wallet-electrum-port")]
+    #[arg(env = "ENFORCER_WALLET_ELECTRUM_PORT", long = "wallet-electrum-port")]
     pub electrum_port: Option<u16>,
+
+    /// Unconfirmed BMM request txs are replaced with higher fee rate txs
+    /// if the estimated fee rate for the next block increases, up to this
+    /// fee rate in sat/vB.
+    #[arg(
+        default_value_t = 100,
+        env = "ENFORCER_WALLET_BMM_MAX_FEE_RATE",
+        long = "wallet-bmm-max-fee-rate"
+    )]
+    pub bmm_max_fee_rate: u64,
+
+    /// Fee rate in sat/vB, used if the node cannot estimate fees.
+    /// Defaults to 10 sat/vB on mainnet, and 1 sat/vB on other networks.
+    #[arg(
+        env = "ENFORCER_WALLET_FALLBACK_FEE_RATE",
+        long = "wallet-fallback-fee-rate"
+    )]
+    pub fallback_fee_rate: Option<u64>,
+
+    /// File containing the BIP39 mnemonic for the wallet.
+    /// If not set, the mnemonic is read from the wallet data directory, and
+    /// a new mnemonic is generated there if none exists.
+    #[arg(env = "ENFORCER_WALLET_MNEMONIC_FILE", long = "wallet-mnemonic-file")]
+    pub mnemonic_file: Option<PathBuf>,
+
+    /// JSON file with the voting policy for sidechain proposals and
+    /// withdrawal bundles. The policy is persisted in the wallet DB on
+    /// startup, and used when producing coinbase messages.
+    #[arg(
+        env = "ENFORCER_WALLET_VOTING_POLICY_FILE",
+        long = "wallet-voting-policy-file"
+    )]
+    pub voting_policy_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable, single-line logs
+    #[default]
+    Compact,
+    /// JSON lines, including fields from the current span
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    #[default]
+    Signet,
+    Regtest,
+}
+
+impl From<Network> for bitcoin::Network {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Mainnet => Self::Bitcoin,
+            Network::Testnet => Self::Testnet,
+            Network::Signet => Self::Signet,
+            Network::Regtest => Self::Regtest,
+        }
+    }
 }
 
 const DEFAULT_SERVE_RPC_ADDR: SocketAddr =
     SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 50_051));
 
+#[derive(Args, Clone)]
+pub struct ServeConfig {
+    /// Enable the wallet service (deposits, BMM transactions, address
+    /// generation). Clients can check if the wallet is enabled via
+    /// `GetCapabilities`.
+    #[arg(env = "ENFORCER_ENABLE_WALLET", long)]
+    pub enable_wallet: bool,
+    #[command(flatten)]
+    pub node_rpc_opts: NodeRpcConfig,
+    /// Bitcoin node ZMQ endpoint for `sequence`. If not set, the endpoint is
+    /// discovered via `getzmqnotifications`, with wildcard binds rewritten to
+    /// the node RPC host.
+    #[arg(env = "ENFORCER_NODE_ZMQ_ADDR_SEQUENCE", long)]
+    pub node_zmq_addr_sequence: Option<String>,
+    /// Bitcoin node ZMQ endpoint for `rawtx`. If set along with
+    /// `--node-p2p-addr`, mempool transactions are tracked, and new blocks are
+    /// fetched over P2P as compact blocks.
+    #[arg(env = "ENFORCER_NODE_ZMQ_ADDR_RAWTX", long)]
+    pub node_zmq_addr_rawtx: Option<String>,
+    /// Backup Bitcoin node, as `<rpc addr>,<zmq sequence addr>`.
+    /// Can be specified multiple times. If the primary node becomes
+    /// unreachable, the enforcer fails over to the first reachable backup
+    /// node, and fails back once the primary node is reachable again.
+    /// Backup nodes must accept the same RPC credentials as the primary
+    /// node.
+    #[arg(
+        env = "ENFORCER_BACKUP_NODES",
+        long = "backup-node",
+        value_delimiter = ';',
+        value_parser = parse_backup_node
+    )]
+    pub backup_nodes: Vec<BackupNode>,
+    /// If set, block info (BMM commitments, deposits, sidechain proposals
+    /// and withdrawal bundle events) is deleted for blocks more than this
+    /// many blocks below the tip.
+    /// Headers, CTIPs and sidechain state are always retained.
+    #[arg(env = "ENFORCER_PRUNE_BLOCK_INFO_DEPTH", long)]
+    pub prune_block_info_depth: Option<u32>,
+    /// Number of confirmations at which a `DepositConfirmed` event is
+    /// emitted for a deposit, as `<depth>` for all sidechains, or
+    /// `<sidechain slot>:<depth>` for a single sidechain.
+    /// Can be specified multiple times. Sidechain-specific depths take
+    /// precedence. If a reorg removes confirmations from a confirmed deposit,
+    /// a `DepositUnconfirmed` event is emitted.
+    /// If unset, deposit confirmations are not tracked.
+    #[arg(
+        env = "ENFORCER_DEPOSIT_CONFIRMATIONS",
+        long = "deposit-confirmations",
+        value_delimiter = ';'
+    )]
+    pub deposit_confirmations: Vec<DepositConfirmationDepth>,
+    /// If set, `DepositConfirmed` and `DepositUnconfirmed` events are POSTed
+    /// to this URL as JSON
+    #[arg(env = "ENFORCER_DEPOSIT_WEBHOOK_URL", long)]
+    pub deposit_webhook_url: Option<reqwest::Url>,
+    /// If set, a fixture is written to this directory for each connected
+    /// block that contains BIP300/301 messages or transactions.
+    /// Fixtures can be replayed without a node, with `fixtures replay`.
+    #[arg(env = "ENFORCER_RECORD_FIXTURES_DIR", long)]
+    pub record_fixtures_dir: Option<PathBuf>,
+    /// PEM-encoded certificate chain for serving gRPC over TLS.
+    /// Must be set along with `--rpc-tls-key`.
+    #[arg(env = "ENFORCER_RPC_TLS_CERT", long, requires = "rpc_tls_key")]
+    pub rpc_tls_cert: Option<PathBuf>,
+    /// PEM-encoded private key for serving gRPC over TLS.
+    /// Must be set along with `--rpc-tls-cert`.
+    #[arg(env = "ENFORCER_RPC_TLS_KEY", long, requires = "rpc_tls_cert")]
+    pub rpc_tls_key: Option<PathBuf>,
+    /// PEM-encoded CA certificate for verifying gRPC client certificates.
+    /// If set, clients with a certificate signed by this CA are
+    /// authenticated. Requires `--rpc-tls-cert`.
+    #[arg(env = "ENFORCER_RPC_TLS_CLIENT_CA", long, requires = "rpc_tls_cert")]
+    pub rpc_tls_client_ca: Option<PathBuf>,
+    /// File containing bearer tokens for authenticating gRPC clients, one
+    /// per line. Clients authenticate by sending an
+    /// `authorization: Bearer <token>` header.
+    /// If this or `--rpc-tls-client-ca` is set, all gRPC requests must be
+    /// authenticated, unless `--rpc-allow-unauthenticated-reads` is set.
+    #[arg(env = "ENFORCER_RPC_AUTH_TOKENS_FILE", long)]
+    pub rpc_auth_tokens_file: Option<PathBuf>,
+    /// Allow unauthenticated reads from the validator and crypto services.
+    /// Wallet service requests, and the validator service's
+    /// `CompactDatabase` and `BroadcastTransaction` RPCs, must still be
+    /// authenticated.
+    #[arg(env = "ENFORCER_RPC_ALLOW_UNAUTHENTICATED_READS", long)]
+    pub rpc_allow_unauthenticated_reads: bool,
+    /// Max gRPC requests per second from each client IP address.
+    /// Requests over the limit are rejected with `RESOURCE_EXHAUSTED`.
+    #[arg(env = "ENFORCER_RPC_MAX_REQUESTS_PER_SEC", long)]
+    pub rpc_max_requests_per_sec: Option<NonZeroU32>,
+    /// Max in-flight gRPC requests from each client IP address, including
+    /// open streams such as event subscriptions.
+    /// Requests over the limit are rejected with `RESOURCE_EXHAUSTED`.
+    #[arg(env = "ENFORCER_RPC_MAX_CONCURRENT_REQUESTS", long)]
+    pub rpc_max_concurrent_requests: Option<NonZeroU32>,
+    #[arg(default_value_t = DEFAULT_SERVE_RPC_ADDR, env = "ENFORCER_SERVE_RPC_ADDR", long)]
+    pub serve_rpc_addr: SocketAddr,
+    /// If set, enforcer events are published on a ZMQ PUB socket bound to
+    /// this address, eg. `tcp://127.0.0.1:29100`
+    #[arg(env = "ENFORCER_SERVE_ZMQ_ADDR", long)]
+    pub serve_zmq_addr: Option<String>,
+    /// If set, the read-only query API (chain tip, CTIPs, sidechains,
+    /// sidechain proposals and two-way peg data) is also served as
+    /// JSON-RPC over HTTP at this address. The JSON-RPC server does not
+    /// support TLS or authentication, so it should only be bound to a
+    /// trusted interface.
+    #[arg(env = "ENFORCER_SERVE_JSON_RPC_ADDR", long)]
+    pub serve_json_rpc_addr: Option<SocketAddr>,
+    /// If set, the read-only query API is also served as REST with JSON
+    /// responses at this address, eg. `GET /v1/chain/tip`. The REST
+    /// gateway does not support TLS or authentication, so it should only
+    /// be bound to a trusted interface.
+    #[arg(env = "ENFORCER_SERVE_REST_ADDR", long)]
+    pub serve_rest_addr: Option<SocketAddr>,
+    /// If set, a read-only GraphQL endpoint is served at this address, at
+    /// `/graphql`. Requires the `graphql` feature. The endpoint does not
+    /// support TLS or authentication, so it should only be bound to a
+    /// trusted interface.
+    #[cfg(feature = "graphql")]
+    #[arg(env = "ENFORCER_SERVE_GRAPHQL_ADDR", long)]
+    pub serve_graphql_addr: Option<SocketAddr>,
+    #[command(flatten)]
+    pub wallet_opts: WalletConfig,
+}
+
+#[derive(Clone, Subcommand)]
+pub enum FixturesCommand {
+    /// Connect each fixture's block to its recorded state, in scratch DBs,
+    /// and report any differences from the recorded results
+    Replay {
+        /// Fixture files to replay
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+pub enum SnapshotCommand {
+    /// Write a compacted copy of the validator DBs to a file
+    Export {
+        /// File to write the snapshot to. Must not already exist.
+        path: PathBuf,
+    },
+    /// Restore the validator DBs from a snapshot file.
+    /// Fails if validator DBs already exist; run `reindex` first to replace
+    /// them.
+    Import {
+        /// Snapshot file to restore from
+        path: PathBuf,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+pub enum DbCommand {
+    /// Check consistency of the validator DBs
+    Check,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    /// CSV, with a header row
+    #[default]
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+#[derive(Args, Clone)]
+pub struct ClientConfig {
+    /// gRPC endpoint of a running enforcer
+    #[arg(
+        default_value = "http://127.0.0.1:50051",
+        env = "ENFORCER_CLIENT_RPC_URL",
+        long
+    )]
+    pub rpc_url: String,
+    #[arg(default_value_t, env = "ENFORCER_CLIENT_OUTPUT", long, value_enum)]
+    pub output: OutputFormat,
+}
+
+#[derive(Clone, Subcommand)]
+pub enum ClientCommand {
+    /// Get the chain tip that the enforcer is synced to
+    GetChainTip,
+    /// Get the CTIP for a sidechain slot
+    GetCtip { sidechain_number: u8 },
+    /// Get the state of the enforcer's sync task, and the most recent errors
+    /// that it encountered
+    GetEnforcerStatus,
+    /// Get sync progress of the enforcer, relative to the node
+    GetSyncInfo,
+    /// Get the enforcer's version, supported proto packages, and enabled
+    /// features
+    GetVersion,
+    /// List pending withdrawal bundles, with their votes and the number of
+    /// blocks remaining before they expire
+    ListPendingWithdrawalBundles {
+        /// Only list bundles for this sidechain slot
+        #[arg(long)]
+        sidechain_number: Option<u8>,
+    },
+    /// List active sidechains
+    ListSidechains,
+    /// List the history of a sidechain's treasury UTXOs, in sequence order
+    ListTreasuryUtxos {
+        sidechain_number: u8,
+        /// List treasury UTXOs beginning at this sequence number
+        #[arg(long, default_value_t = 0)]
+        start_sequence: u64,
+    },
+    /// Print the CTIP for a sidechain slot, and then each new CTIP as the
+    /// treasury UTXO changes.
+    /// With JSON output, each CTIP is printed on a single line.
+    SubscribeCtip { sidechain_number: u8 },
+    /// Print events for a sidechain slot as they occur.
+    /// With JSON output, each event is printed on a single line.
+    SubscribeEvents {
+        sidechain_number: u8,
+        /// Replay events from the event journal, starting at this sequence
+        /// number
+        #[arg(long, conflicts_with = "start_block_hash")]
+        start_sequence: Option<u64>,
+        /// Replay `ConnectBlock` events for blocks in the best chain after
+        /// this block, before printing new events
+        #[arg(long)]
+        start_block_hash: Option<bitcoin::BlockHash>,
+    },
+    /// Print mainchain headers as blocks are connected and disconnected.
+    /// With JSON output, each header is printed on a single line.
+    SubscribeHeaders {
+        /// Replay headers from the event journal, starting at this sequence
+        /// number
+        #[arg(long)]
+        start_sequence: Option<u64>,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+pub enum Command {
+    /// Sync the validator and serve gRPC
+    Serve(ServeConfig),
+    /// Delete the validator DBs, so that they are rebuilt from the node on
+    /// the next `serve`. Wallet data is not affected.
+    Reindex,
+    /// Export or import validator DB snapshots
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+    /// Validator DB maintenance
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Print validator state as JSON, for debugging and for diffing state
+    /// between enforcer instances
+    Dump {
+        /// Number of recent blocks in the best chain to include block info
+        /// for
+        #[arg(default_value_t = 10, long)]
+        recent_blocks: u32,
+    },
+    /// Export per-block sidechain activity (deposits, withdrawals and BMM)
+    /// for best chain blocks in a height range, to stdout
+    ExportActivity {
+        #[arg(default_value_t = 0, long)]
+        start_height: u32,
+        /// Defaults to the current tip
+        #[arg(long)]
+        end_height: Option<u32>,
+        #[arg(default_value_t, long, value_enum)]
+        format: ExportFormat,
+    },
+    /// Replay block fixtures, recorded with `--record-fixtures-dir`
+    #[command(subcommand)]
+    Fixtures(FixturesCommand),
+    /// Query a running enforcer via gRPC
+    Client {
+        #[command(flatten)]
+        config: ClientConfig,
+        #[command(subcommand)]
+        command: ClientCommand,
+    },
+}
+
+/// All options can also be set via environment variables, prefixed with
+/// `ENFORCER_`. For example, `--node-zmq-addr-sequence` can be set via
+/// `ENFORCER_NODE_ZMQ_ADDR_SEQUENCE`. CLI args take precedence over
+/// environment variables.
+///
+/// Maintenance commands (`reindex`, `snapshot`, `db`, `dump`,
+/// `export-activity`) operate on the validator DBs directly, and MUST NOT be
+/// run while the enforcer is serving.
 #[derive(Clone, Parser)]
 pub struct Config {
     /// Directory to store wallet + drivechain + validator data.
-    #[arg(default_value_os_t = get_data_dir().unwrap_or_else(|_| PathBuf::from("./datadir")), long)]
+    #[arg(
+        default_value_os_t = get_data_dir().unwrap_or_else(|_| PathBuf::from("./datadir")),
+        env = "ENFORCER_DATA_DIR",
+        global = true,
+        long
+    )]
     pub data_dir: PathBuf,
-    #[arg(long)]
-    pub enable_wallet: bool,
     /// Log level.
     /// Logs from most dependencies are filtered one level below the specified
     /// log level, if a lower level exists.
     /// For example, at the default log level `DEBUG`, logs from most
     /// dependencies are only emitted if their level is `INFO` or lower.
-    #[arg(default_value_t = tracing::Level::DEBUG, long)]
+    #[arg(
+        default_value_t = tracing::Level::DEBUG,
+        env = "ENFORCER_LOG_LEVEL",
+        global = true,
+        long
+    )]
     pub log_level: tracing::Level,
-    #[command(flatten)]
-    pub node_rpc_opts: NodeRpcConfig,
-    /// Bitcoin node ZMQ endpoint for `sequence`
-    #[arg(long)]
-    pub node_zmq_addr_sequence: String,
-    #[arg(default_value_t = DEFAULT_SERVE_RPC_ADDR, long)]
-    pub serve_rpc_addr: SocketAddr,
-    #[command(flatten)]
-    pub wallet_opts: WalletConfig,
+    #[arg(
+        default_value_t,
+        env = "ENFORCER_LOG_FORMAT",
+        global = true,
+        long,
+        value_enum
+    )]
+    pub log_format: LogFormat,
+    /// Network that the enforcer runs on. The enforcer refuses to start if
+    /// the node is on a different network.
+    /// Data is stored separately for each network.
+    #[arg(
+        default_value_t,
+        env = "ENFORCER_NETWORK",
+        global = true,
+        long,
+        value_enum
+    )]
+    pub network: Network,
+    #[command(subcommand)]
+    pub command: Command,
 }
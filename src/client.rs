@@ -0,0 +1,774 @@
+//! gRPC client for inspecting a running enforcer
+
+use std::io::Write as _;
+
+use bitcoin::BlockHash;
+use futures::StreamExt as _;
+use miette::{miette, IntoDiagnostic as _, Result};
+use serde_json::json;
+use tonic::transport::Channel;
+
+use crate::{
+    cli::{ClientCommand, ClientConfig, OutputFormat},
+    proto::{
+        common::{ConsensusHex, ReverseHex},
+        mainchain::{
+            get_ctip_response::Ctip, get_enforcer_status_response,
+            get_sidechains_response::SidechainInfo, sidechain_declaration,
+            subscribe_events_response, subscribe_headers_response,
+            validator_service_client::ValidatorServiceClient, BlockHeaderInfo, BlockInfo,
+            GetChainTipRequest, GetCtipRequest, GetEnforcerStatusRequest, GetSidechainsRequest,
+            GetSyncInfoRequest, GetVersionRequest, ListPendingWithdrawalBundlesRequest,
+            ListTreasuryUtxosRequest, SubscribeCtipRequest, SubscribeEventsRequest,
+            SubscribeHeadersRequest, WithdrawalBundleEventType,
+        },
+    },
+};
+
+fn reverse_hex(value: Option<ReverseHex>) -> Option<String> {
+    value.and_then(|value| value.hex)
+}
+
+fn consensus_hex(value: Option<ConsensusHex>) -> Option<String> {
+    value.and_then(|value| value.hex)
+}
+
+/// Write a line to stdout
+fn print_line(line: impl std::fmt::Display) -> Result<()> {
+    writeln!(std::io::stdout(), "{line}").into_diagnostic()
+}
+
+fn print_json(value: &serde_json::Value) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    serde_json::to_writer_pretty(&mut stdout, value).into_diagnostic()?;
+    writeln!(stdout).into_diagnostic()
+}
+
+fn header_info_json(header_info: BlockHeaderInfo) -> serde_json::Value {
+    json!({
+        "block_hash": reverse_hex(header_info.block_hash),
+        "prev_block_hash": reverse_hex(header_info.prev_block_hash),
+        "height": header_info.height,
+        "work": consensus_hex(header_info.work),
+    })
+}
+
+fn format_ctip(ctip: Ctip) -> String {
+    format!(
+        "{}:{} ({} sats, sequence number {})",
+        reverse_hex(ctip.txid).unwrap_or_default(),
+        ctip.vout,
+        ctip.value,
+        ctip.sequence_number
+    )
+}
+
+fn ctip_json(ctip: Ctip) -> serde_json::Value {
+    json!({
+        "txid": reverse_hex(ctip.txid),
+        "vout": ctip.vout,
+        "value": ctip.value,
+        "sequence_number": ctip.sequence_number,
+    })
+}
+
+fn block_info_json(block_info: BlockInfo) -> serde_json::Value {
+    let deposits: Vec<_> = block_info
+        .deposits
+        .into_iter()
+        .map(|deposit| {
+            let outpoint = deposit.outpoint.map(|outpoint| {
+                json!({
+                    "txid": reverse_hex(outpoint.txid),
+                    "vout": outpoint.vout,
+                })
+            });
+            let output = deposit.output.map(|output| {
+                json!({
+                    "address": output.address.and_then(|address| address.hex),
+                    "value_sats": output.value_sats,
+                })
+            });
+            json!({
+                "sequence_number": deposit.sequence_number,
+                "outpoint": outpoint,
+                "output": output,
+            })
+        })
+        .collect();
+    let withdrawal_bundle_events: Vec<_> = block_info
+        .withdrawal_bundle_events
+        .into_iter()
+        .map(|event| {
+            let kind = WithdrawalBundleEventType::try_from(event.withdrawal_bundle_event_type)
+                .map(|kind| kind.as_str_name().to_owned())
+                .unwrap_or_else(|_| event.withdrawal_bundle_event_type.to_string());
+            json!({
+                "m6id": consensus_hex(event.m6id),
+                "kind": kind,
+            })
+        })
+        .collect();
+    json!({
+        "deposits": deposits,
+        "withdrawal_bundle_events": withdrawal_bundle_events,
+        "bmm_commitment": consensus_hex(block_info.bmm_commitment),
+    })
+}
+
+fn sidechain_json(sidechain: SidechainInfo) -> serde_json::Value {
+    let declaration = sidechain
+        .declaration
+        .and_then(|declaration| declaration.sidechain_declaration)
+        .map(|declaration| match declaration {
+            sidechain_declaration::SidechainDeclaration::V0(v0) => json!({
+                "title": v0.title,
+                "description": v0.description,
+                "hash_id_1": consensus_hex(v0.hash_id_1),
+                "hash_id_2": v0.hash_id_2.and_then(|hash_id_2| hash_id_2.hex),
+            }),
+        });
+    json!({
+        "sidechain_number": sidechain.sidechain_number,
+        "description": consensus_hex(sidechain.description),
+        "declaration": declaration,
+        "description_sha256d_hash": reverse_hex(sidechain.description_sha256d_hash),
+        "vote_count": sidechain.vote_count,
+        "proposal_height": sidechain.proposal_height,
+        "activation_height": sidechain.activation_height,
+        "replacement_pending": sidechain.replacement_pending,
+    })
+}
+
+async fn get_chain_tip(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+) -> Result<()> {
+    let resp = client
+        .get_chain_tip(GetChainTipRequest {})
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    let header_info = resp
+        .block_header_info
+        .ok_or_else(|| miette!("missing block header info in response"))?;
+    match output {
+        OutputFormat::Human => print_line(format_args!(
+            "{} (height {})",
+            reverse_hex(header_info.block_hash).unwrap_or_default(),
+            header_info.height
+        )),
+        OutputFormat::Json => print_json(&header_info_json(header_info)),
+    }
+}
+
+async fn get_enforcer_status(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+) -> Result<()> {
+    let resp = client
+        .get_enforcer_status(GetEnforcerStatusRequest {})
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    let task_state = match resp.task_state() {
+        get_enforcer_status_response::TaskState::Unspecified => "unspecified",
+        get_enforcer_status_response::TaskState::Running => "running",
+        get_enforcer_status_response::TaskState::Degraded => "degraded",
+        get_enforcer_status_response::TaskState::Stopped => "stopped",
+    };
+    let error_kind = |error: &get_enforcer_status_response::Error| match error.kind() {
+        get_enforcer_status_response::ErrorKind::Unspecified => "unspecified",
+        get_enforcer_status_response::ErrorKind::NonFatal => "non-fatal",
+        get_enforcer_status_response::ErrorKind::Recoverable => "recoverable",
+        get_enforcer_status_response::ErrorKind::Fatal => "fatal",
+    };
+    match output {
+        OutputFormat::Human => {
+            print_line(format_args!("sync task: {task_state}"))?;
+            print_line(format_args!(
+                "sync task restarts: {}",
+                resp.sync_task_restarts
+            ))?;
+            for error in &resp.recent_errors {
+                print_line(format_args!(
+                    "[{}] {} error: {}",
+                    error.timestamp,
+                    error_kind(error),
+                    error.message
+                ))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            let recent_errors: Vec<_> = resp
+                .recent_errors
+                .iter()
+                .map(|error| {
+                    json!({
+                        "timestamp": error.timestamp,
+                        "kind": error_kind(error),
+                        "message": error.message,
+                    })
+                })
+                .collect();
+            print_json(&json!({
+                "task_state": task_state,
+                "sync_task_restarts": resp.sync_task_restarts,
+                "recent_errors": recent_errors,
+            }))
+        }
+    }
+}
+
+async fn get_sync_info(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+) -> Result<()> {
+    let resp = client
+        .get_sync_info(GetSyncInfoRequest {})
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    match output {
+        OutputFormat::Human => {
+            let height = |height: Option<u32>| {
+                height.map_or_else(|| "none".to_owned(), |height| height.to_string())
+            };
+            print_line(format_args!(
+                "blocks: {}/{}",
+                height(resp.block_height),
+                resp.node_block_height
+            ))?;
+            print_line(format_args!(
+                "headers: {}/{}",
+                height(resp.header_height),
+                resp.node_header_height
+            ))?;
+            if let Some(seconds) = resp.estimated_seconds_remaining {
+                print_line(format_args!("estimated time remaining: {seconds}s"))?;
+            }
+            print_line(format_args!("ZMQ connected: {}", resp.zmq_connected))?;
+            print_line(format_args!(
+                "sync task restarts: {}",
+                resp.sync_task_restarts
+            ))
+        }
+        OutputFormat::Json => print_json(&json!({
+            "block_height": resp.block_height,
+            "header_height": resp.header_height,
+            "node_block_height": resp.node_block_height,
+            "node_header_height": resp.node_header_height,
+            "estimated_seconds_remaining": resp.estimated_seconds_remaining,
+            "zmq_connected": resp.zmq_connected,
+            "sync_task_restarts": resp.sync_task_restarts,
+        })),
+    }
+}
+
+async fn get_version(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+) -> Result<()> {
+    let resp = client
+        .get_version(GetVersionRequest {})
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    match output {
+        OutputFormat::Human => {
+            print_line(format_args!("version: {}", resp.version))?;
+            print_line(format_args!(
+                "proto packages: {}",
+                resp.proto_packages.join(", ")
+            ))?;
+            print_line(format_args!("features: {}", resp.features.join(", ")))
+        }
+        OutputFormat::Json => print_json(&json!({
+            "version": resp.version,
+            "proto_packages": resp.proto_packages,
+            "features": resp.features,
+        })),
+    }
+}
+
+async fn get_ctip(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+    sidechain_number: u8,
+) -> Result<()> {
+    let request = GetCtipRequest {
+        sidechain_number: Some(sidechain_number.into()),
+    };
+    let resp = client
+        .get_ctip(request)
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    match (output, resp.ctip) {
+        (OutputFormat::Human, None) => {
+            print_line(format_args!("No CTIP for sidechain {sidechain_number}"))
+        }
+        (OutputFormat::Human, Some(ctip)) => print_line(format_ctip(ctip)),
+        (OutputFormat::Json, ctip) => print_json(&json!({ "ctip": ctip.map(ctip_json) })),
+    }
+}
+
+async fn list_sidechains(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+) -> Result<()> {
+    let resp = client
+        .get_sidechains(GetSidechainsRequest {})
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    match output {
+        OutputFormat::Human => {
+            if resp.sidechains.is_empty() {
+                return print_line("No active sidechains");
+            }
+            for sidechain in resp.sidechains {
+                let title = sidechain
+                    .declaration
+                    .and_then(|declaration| declaration.sidechain_declaration)
+                    .and_then(|declaration| match declaration {
+                        sidechain_declaration::SidechainDeclaration::V0(v0) => v0.title,
+                    });
+                // Fall back to the raw description if it is not a valid
+                // declaration
+                let description = title
+                    .or_else(|| {
+                        sidechain
+                            .description
+                            .and_then(|description| description.hex)
+                            .and_then(|hex| {
+                                bitcoin::consensus::encode::deserialize_hex::<Vec<u8>>(&hex).ok()
+                            })
+                            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                    })
+                    .unwrap_or_default();
+                print_line(format_args!(
+                    "{}: {description:?} (activated at height {})",
+                    sidechain.sidechain_number.unwrap_or_default(),
+                    sidechain
+                        .activation_height
+                        .map(|height| height.to_string())
+                        .unwrap_or_else(|| "?".to_owned())
+                ))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            let sidechains: Vec<_> = resp.sidechains.into_iter().map(sidechain_json).collect();
+            print_json(&json!({ "sidechains": sidechains }))
+        }
+    }
+}
+
+async fn list_pending_withdrawal_bundles(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+    sidechain_number: Option<u8>,
+) -> Result<()> {
+    let resp = client
+        .list_pending_withdrawal_bundles(ListPendingWithdrawalBundlesRequest {
+            sidechain_number: sidechain_number.map(u32::from),
+        })
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    match output {
+        OutputFormat::Human => {
+            if resp.bundles.is_empty() {
+                return print_line("No pending withdrawal bundles");
+            }
+            for bundle in resp.bundles {
+                print_line(format_args!(
+                    "{} #{}: {} ({} votes, age {}, {} blocks remaining)",
+                    bundle.sidechain_number,
+                    bundle.vote_index,
+                    consensus_hex(bundle.m6id).unwrap_or_default(),
+                    bundle.vote_count,
+                    bundle.age,
+                    bundle.blocks_remaining,
+                ))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            let bundles: Vec<_> = resp
+                .bundles
+                .into_iter()
+                .map(|bundle| {
+                    json!({
+                        "sidechain_number": bundle.sidechain_number,
+                        "vote_index": bundle.vote_index,
+                        "m6id": consensus_hex(bundle.m6id),
+                        "vote_count": bundle.vote_count,
+                        "proposal_height": bundle.proposal_height,
+                        "age": bundle.age,
+                        "blocks_remaining": bundle.blocks_remaining,
+                    })
+                })
+                .collect();
+            print_json(&json!({
+                "tip_height": resp.tip_height,
+                "bundles": bundles,
+            }))
+        }
+    }
+}
+
+/// Print all treasury UTXOs from `start_sequence` onwards, requesting
+/// them one page at a time
+async fn list_treasury_utxos(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+    sidechain_number: u8,
+    start_sequence: u64,
+) -> Result<()> {
+    let mut next_sequence = Some(start_sequence);
+    let mut treasury_utxos = Vec::new();
+    while let Some(start_sequence) = next_sequence {
+        let resp = client
+            .list_treasury_utxos(ListTreasuryUtxosRequest {
+                sidechain_number: Some(sidechain_number.into()),
+                start_sequence: Some(start_sequence),
+                limit: None,
+            })
+            .await
+            .into_diagnostic()?
+            .into_inner();
+        treasury_utxos.extend(resp.treasury_utxos);
+        next_sequence = resp.next_sequence;
+    }
+    match output {
+        OutputFormat::Human => {
+            if treasury_utxos.is_empty() {
+                return print_line("No treasury UTXOs");
+            }
+            for treasury_utxo in treasury_utxos {
+                let total_value = bitcoin::Amount::from_sat(treasury_utxo.total_value);
+                let previous_total_value =
+                    bitcoin::Amount::from_sat(treasury_utxo.previous_total_value);
+                print_line(format_args!(
+                    "#{}: {}:{} {} -> {}",
+                    treasury_utxo.sequence_number,
+                    reverse_hex(treasury_utxo.txid).unwrap_or_default(),
+                    treasury_utxo.vout,
+                    previous_total_value,
+                    total_value,
+                ))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            let treasury_utxos: Vec<_> = treasury_utxos
+                .into_iter()
+                .map(|treasury_utxo| {
+                    json!({
+                        "sequence_number": treasury_utxo.sequence_number,
+                        "txid": reverse_hex(treasury_utxo.txid),
+                        "vout": treasury_utxo.vout,
+                        "address": treasury_utxo.address.and_then(|address| address.hex),
+                        "total_value": treasury_utxo.total_value,
+                        "previous_total_value": treasury_utxo.previous_total_value,
+                    })
+                })
+                .collect();
+            print_json(&json!({ "treasury_utxos": treasury_utxos }))
+        }
+    }
+}
+
+async fn subscribe_events(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+    sidechain_number: u8,
+    start_sequence: Option<u64>,
+    start_block_hash: Option<BlockHash>,
+) -> Result<()> {
+    let request = SubscribeEventsRequest {
+        sidechain_id: Some(sidechain_number.into()),
+        start_sequence,
+        start_block_hash: start_block_hash.map(|block_hash| ReverseHex::encode(&block_hash)),
+    };
+    let mut stream = client
+        .subscribe_events(request)
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    while let Some(resp) = stream.next().await {
+        let resp = resp.into_diagnostic()?;
+        // Replayed events do not have sequence numbers
+        let sequence = resp.sequence;
+        let label = sequence.map_or_else(|| "replay".to_owned(), |sequence| sequence.to_string());
+        let event = resp
+            .event
+            .and_then(|event| event.event)
+            .ok_or_else(|| miette!("missing event in response"))?;
+        match (output, event) {
+            (
+                OutputFormat::Human,
+                subscribe_events_response::event::Event::ConnectBlock(connect_block),
+            ) => {
+                let header_info = connect_block.header_info.unwrap_or_default();
+                let block_info = connect_block.block_info.unwrap_or_default();
+                print_line(format_args!(
+                    "[{label}] connect block {} (height {}): {} deposit(s), {} withdrawal bundle event(s), BMM commitment: {}",
+                    reverse_hex(header_info.block_hash).unwrap_or_default(),
+                    header_info.height,
+                    block_info.deposits.len(),
+                    block_info.withdrawal_bundle_events.len(),
+                    consensus_hex(block_info.bmm_commitment).unwrap_or_else(|| "none".to_owned()),
+                ))?;
+            }
+            (
+                OutputFormat::Human,
+                subscribe_events_response::event::Event::DisconnectBlock(disconnect_block),
+            ) => {
+                print_line(format_args!(
+                    "[{label}] disconnect block {}",
+                    reverse_hex(disconnect_block.block_hash).unwrap_or_default()
+                ))?;
+            }
+            (
+                OutputFormat::Json,
+                subscribe_events_response::event::Event::ConnectBlock(connect_block),
+            ) => {
+                let event = json!({
+                    "sequence": sequence,
+                    "connect_block": {
+                        "header_info": connect_block.header_info.map(header_info_json),
+                        "block_info": connect_block.block_info.map(block_info_json),
+                    }
+                });
+                // One event per line, so that output can be streamed
+                print_line(event)?;
+            }
+            (
+                OutputFormat::Json,
+                subscribe_events_response::event::Event::DisconnectBlock(disconnect_block),
+            ) => {
+                let event = json!({
+                    "sequence": sequence,
+                    "disconnect_block": {
+                        "block_hash": reverse_hex(disconnect_block.block_hash),
+                    }
+                });
+                print_line(event)?;
+            }
+            (
+                OutputFormat::Human,
+                subscribe_events_response::event::Event::WithdrawalBundleAlert(alert),
+            ) => {
+                print_line(format_args!(
+                    "[{label}] ALERT: alarm vote for bundle {}{} in block {} (votes: {} -> {})",
+                    consensus_hex(alert.m6id).unwrap_or_default(),
+                    if alert.leading.unwrap_or_default() {
+                        " (leading)"
+                    } else {
+                        ""
+                    },
+                    reverse_hex(alert.block_hash).unwrap_or_default(),
+                    alert.previous_vote_count.unwrap_or_default(),
+                    alert.vote_count.unwrap_or_default(),
+                ))?;
+            }
+            (
+                OutputFormat::Json,
+                subscribe_events_response::event::Event::WithdrawalBundleAlert(alert),
+            ) => {
+                let event = json!({
+                    "sequence": sequence,
+                    "withdrawal_bundle_alert": {
+                        "block_hash": reverse_hex(alert.block_hash),
+                        "m6id": consensus_hex(alert.m6id),
+                        "previous_vote_count": alert.previous_vote_count,
+                        "vote_count": alert.vote_count,
+                        "leading": alert.leading,
+                    }
+                });
+                print_line(event)?;
+            }
+            (
+                OutputFormat::Human,
+                subscribe_events_response::event::Event::DepositConfirmed(confirmed),
+            ) => {
+                print_line(format_args!(
+                    "[{label}] deposit {} in block {} (height {}) confirmed with {} confirmation(s) in block {}",
+                    confirmed.deposit.and_then(|deposit| deposit.sequence_number).unwrap_or_default(),
+                    reverse_hex(confirmed.deposit_block_hash).unwrap_or_default(),
+                    confirmed.deposit_block_height,
+                    confirmed.confirmations,
+                    reverse_hex(confirmed.block_hash).unwrap_or_default(),
+                ))?;
+            }
+            (
+                OutputFormat::Json,
+                subscribe_events_response::event::Event::DepositConfirmed(confirmed),
+            ) => {
+                let event = json!({
+                    "sequence": sequence,
+                    "deposit_confirmed": {
+                        "block_hash": reverse_hex(confirmed.block_hash),
+                        "deposit_sequence_number": confirmed.deposit.and_then(|deposit| deposit.sequence_number),
+                        "deposit_block_hash": reverse_hex(confirmed.deposit_block_hash),
+                        "deposit_block_height": confirmed.deposit_block_height,
+                        "confirmations": confirmed.confirmations,
+                    }
+                });
+                print_line(event)?;
+            }
+            (
+                OutputFormat::Human,
+                subscribe_events_response::event::Event::DepositUnconfirmed(unconfirmed),
+            ) => {
+                print_line(format_args!(
+                    "[{label}] deposit {} in block {} (height {}) unconfirmed by disconnecting block {}, {} confirmation(s) left",
+                    unconfirmed.deposit.and_then(|deposit| deposit.sequence_number).unwrap_or_default(),
+                    reverse_hex(unconfirmed.deposit_block_hash).unwrap_or_default(),
+                    unconfirmed.deposit_block_height,
+                    reverse_hex(unconfirmed.block_hash).unwrap_or_default(),
+                    unconfirmed.confirmations,
+                ))?;
+            }
+            (
+                OutputFormat::Json,
+                subscribe_events_response::event::Event::DepositUnconfirmed(unconfirmed),
+            ) => {
+                let event = json!({
+                    "sequence": sequence,
+                    "deposit_unconfirmed": {
+                        "block_hash": reverse_hex(unconfirmed.block_hash),
+                        "deposit_sequence_number": unconfirmed.deposit.and_then(|deposit| deposit.sequence_number),
+                        "deposit_block_hash": reverse_hex(unconfirmed.deposit_block_hash),
+                        "deposit_block_height": unconfirmed.deposit_block_height,
+                        "confirmations": unconfirmed.confirmations,
+                    }
+                });
+                print_line(event)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn subscribe_headers(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+    start_sequence: Option<u64>,
+) -> Result<()> {
+    let request = SubscribeHeadersRequest { start_sequence };
+    let mut stream = client
+        .subscribe_headers(request)
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    while let Some(resp) = stream.next().await {
+        let resp = resp.into_diagnostic()?;
+        let sequence = resp.sequence.unwrap_or_default();
+        let (kind, header_info) = match resp
+            .event
+            .and_then(|event| event.event)
+            .ok_or_else(|| miette!("missing event in response"))?
+        {
+            subscribe_headers_response::event::Event::ConnectBlock(header_info) => {
+                ("connect_block", header_info)
+            }
+            subscribe_headers_response::event::Event::DisconnectBlock(header_info) => {
+                ("disconnect_block", header_info)
+            }
+        };
+        match output {
+            OutputFormat::Human => print_line(format_args!(
+                "[{sequence}] {kind} {} (height {})",
+                reverse_hex(header_info.block_hash).unwrap_or_default(),
+                header_info.height,
+            ))?,
+            OutputFormat::Json => print_line(json!({
+                "sequence": sequence,
+                kind: header_info_json(header_info),
+            }))?,
+        }
+    }
+    Ok(())
+}
+
+async fn subscribe_ctip(
+    client: &mut ValidatorServiceClient<Channel>,
+    output: OutputFormat,
+    sidechain_number: u8,
+) -> Result<()> {
+    let request = SubscribeCtipRequest {
+        sidechain_number: Some(sidechain_number.into()),
+    };
+    let mut stream = client
+        .subscribe_ctip(request)
+        .await
+        .into_diagnostic()?
+        .into_inner();
+    while let Some(resp) = stream.next().await {
+        let resp = resp.into_diagnostic()?;
+        match (output, resp.ctip) {
+            (OutputFormat::Human, None) => {
+                print_line(format_args!("No CTIP for sidechain {sidechain_number}"))?
+            }
+            (OutputFormat::Human, Some(ctip)) => print_line(format_ctip(ctip))?,
+            (OutputFormat::Json, ctip) => print_line(json!({ "ctip": ctip.map(ctip_json) }))?,
+        }
+    }
+    Ok(())
+}
+
+/// Run a client command against the configured enforcer
+pub async fn run(config: ClientConfig, command: ClientCommand) -> Result<()> {
+    let mut client = ValidatorServiceClient::connect(config.rpc_url.clone())
+        .await
+        .map_err(|err| {
+            miette!(
+                "failed to connect to enforcer at {}: {err:#}",
+                config.rpc_url
+            )
+        })?;
+    match command {
+        ClientCommand::GetChainTip => get_chain_tip(&mut client, config.output).await,
+        ClientCommand::GetCtip { sidechain_number } => {
+            get_ctip(&mut client, config.output, sidechain_number).await
+        }
+        ClientCommand::GetEnforcerStatus => get_enforcer_status(&mut client, config.output).await,
+        ClientCommand::GetSyncInfo => get_sync_info(&mut client, config.output).await,
+        ClientCommand::GetVersion => get_version(&mut client, config.output).await,
+        ClientCommand::ListPendingWithdrawalBundles { sidechain_number } => {
+            list_pending_withdrawal_bundles(&mut client, config.output, sidechain_number).await
+        }
+        ClientCommand::ListSidechains => list_sidechains(&mut client, config.output).await,
+        ClientCommand::ListTreasuryUtxos {
+            sidechain_number,
+            start_sequence,
+        } => {
+            list_treasury_utxos(&mut client, config.output, sidechain_number, start_sequence).await
+        }
+        ClientCommand::SubscribeCtip { sidechain_number } => {
+            subscribe_ctip(&mut client, config.output, sidechain_number).await
+        }
+        ClientCommand::SubscribeEvents {
+            sidechain_number,
+            start_sequence,
+            start_block_hash,
+        } => {
+            subscribe_events(
+                &mut client,
+                config.output,
+                sidechain_number,
+                start_sequence,
+                start_block_hash,
+            )
+            .await
+        }
+        ClientCommand::SubscribeHeaders { start_sequence } => {
+            subscribe_headers(&mut client, config.output, start_sequence).await
+        }
+    }
+}
@@ -10,6 +10,18 @@ pub fn bdk_block_hash_to_bitcoin_block_hash(
     bitcoin::BlockHash::from_raw_hash(hash)
 }
 
+pub fn bitcoin_block_hash_to_bdk_block_hash(
+    hash: bitcoin::BlockHash,
+) -> bdk_wallet::bitcoin::BlockHash {
+    use bitcoin::hashes::Hash as _;
+    let bytes = hash.to_byte_array();
+
+    use bdk_wallet::bitcoin::hashes::sha256d::Hash;
+    let hash: bdk_wallet::bitcoin::hashes::sha256d::Hash = Hash::from_byte_array(bytes);
+
+    bdk_wallet::bitcoin::BlockHash::from_raw_hash(hash)
+}
+
 pub fn bitcoin_tx_to_bdk_tx(
     tx: bitcoin::Transaction,
 ) -> Result<bdk_wallet::bitcoin::Transaction, bdk_wallet::bitcoin::consensus::encode::Error> {
@@ -0,0 +1,153 @@
+//! Encoding and decoding of sidechain deposit destinations.
+//!
+//! An M5 deposit tx has an OP_DRIVECHAIN output for the sidechain slot at
+//! output 0, directly followed by an OP_RETURN output containing the
+//! sidechain-specific deposit address as a single push at output 1.
+
+use bitcoin::{
+    opcodes::all::OP_RETURN,
+    script::{Instruction, PushBytesBuf},
+    Amount, Script, ScriptBuf, TxOut,
+};
+use thiserror::Error;
+
+use crate::{messages, types::SidechainNumber};
+
+/// Maximum address length, so that the OP_RETURN output is standard
+/// (at most 83 bytes)
+pub const MAX_ADDRESS_LEN: usize = 80;
+
+#[derive(Debug, Error)]
+pub enum DepositAddressError {
+    #[error("deposit address must be non-empty")]
+    Empty,
+    #[error("deposit address is too long ({len} bytes, maximum is {MAX_ADDRESS_LEN} bytes)")]
+    TooLong { len: usize },
+}
+
+/// A sidechain-specific deposit address, that is valid for use in a deposit
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DepositAddress(PushBytesBuf);
+
+impl DepositAddress {
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    /// OP_RETURN script for the deposit address
+    pub fn to_script(&self) -> ScriptBuf {
+        ScriptBuf::new_op_return(&self.0)
+    }
+}
+
+impl TryFrom<Vec<u8>> for DepositAddress {
+    type Error = DepositAddressError;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        if bytes.is_empty() {
+            return Err(DepositAddressError::Empty);
+        }
+        let len = bytes.len();
+        if len > MAX_ADDRESS_LEN {
+            return Err(DepositAddressError::TooLong { len });
+        }
+        let bytes =
+            PushBytesBuf::try_from(bytes).map_err(|_| DepositAddressError::TooLong { len })?;
+        Ok(Self(bytes))
+    }
+}
+
+/// Destination for a deposit
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DepositDestination {
+    pub sidechain_number: SidechainNumber,
+    pub address: DepositAddress,
+}
+
+impl DepositDestination {
+    /// M5 outputs, in order, for a deposit of `value` to a sidechain with
+    /// CTIP value `ctip_value`
+    pub fn outputs(&self, ctip_value: Amount, value: Amount) -> [TxOut; 2] {
+        let op_drivechain_output =
+            messages::create_m5_deposit_output(self.sidechain_number, ctip_value, value);
+        let address_output = TxOut {
+            script_pubkey: self.address.to_script(),
+            value: Amount::ZERO,
+        };
+        [op_drivechain_output, address_output]
+    }
+}
+
+/// Parse the deposit address from an OP_RETURN script.
+/// Returns `None` if the script is not an OP_RETURN followed by a single
+/// push.
+///
+/// Length limits are NOT enforced, since they are not consensus rules.
+pub fn parse_address_script(script: &Script) -> Option<Vec<u8>> {
+    let mut instructions = script.instructions();
+    let Some(Ok(Instruction::Op(OP_RETURN))) = instructions.next() else {
+        return None;
+    };
+    let Some(Ok(Instruction::PushBytes(address))) = instructions.next() else {
+        return None;
+    };
+    let None = instructions.next() else {
+        return None;
+    };
+    Some(address.as_bytes().to_owned())
+}
+
+/// Parse the deposit address from the outputs of an M5 tx.
+/// Returns `None` if the tx has no deposit address output.
+pub fn parse_address(outputs: &[TxOut]) -> Option<Vec<u8>> {
+    outputs
+        .get(1)
+        .and_then(|output| parse_address_script(&output.script_pubkey))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let destination = DepositDestination {
+            sidechain_number: SidechainNumber(3),
+            address: vec![7u8; 32].try_into().unwrap(),
+        };
+        let outputs = destination.outputs(Amount::from_sat(1000), Amount::from_sat(500));
+        assert_eq!(outputs[0].value, Amount::from_sat(1500));
+        let (_, sidechain_number) =
+            messages::parse_op_drivechain(outputs[0].script_pubkey.as_bytes()).unwrap();
+        assert_eq!(sidechain_number, destination.sidechain_number);
+        assert_eq!(
+            parse_address(&outputs).as_deref(),
+            Some(destination.address.as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_length_limits() {
+        assert!(matches!(
+            DepositAddress::try_from(Vec::new()),
+            Err(DepositAddressError::Empty)
+        ));
+        let address = DepositAddress::try_from(vec![0u8; MAX_ADDRESS_LEN]).unwrap();
+        assert_eq!(address.to_script().len(), 83);
+        assert!(address.to_script().is_op_return());
+        assert!(matches!(
+            DepositAddress::try_from(vec![0u8; MAX_ADDRESS_LEN + 1]),
+            Err(DepositAddressError::TooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_missing_address_output() {
+        let outputs = [messages::create_m5_deposit_output(
+            SidechainNumber(0),
+            Amount::ZERO,
+            Amount::from_sat(1),
+        )];
+        assert_eq!(parse_address(&outputs), None);
+    }
+}
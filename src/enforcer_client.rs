@@ -0,0 +1,665 @@
+//! Typed client for the enforcer's gRPC API.
+//!
+//! Wraps the generated [`ValidatorServiceClient`], and converts responses
+//! from the hex-encoded proto messages into [`bitcoin`] types, so that
+//! sidechain implementations do not need to decode the proto messages
+//! themselves. Events and two-way peg data are scoped to a single sidechain,
+//! as with the underlying API.
+
+use std::time::Duration;
+
+use bitcoin::{Amount, BlockHash, OutPoint};
+use futures::{stream::BoxStream, StreamExt as _, TryStreamExt as _};
+use thiserror::Error;
+use tonic::transport::{Channel, Endpoint};
+
+use crate::{
+    proto::{
+        self,
+        common::ReverseHex,
+        mainchain::{
+            get_sidechains_response::SidechainInfo, get_two_way_peg_data_response,
+            subscribe_events_response, validator_service_client::ValidatorServiceClient,
+            GetBlockHeaderInfoRequest, GetChainTipRequest, GetCtipRequest, GetSidechainsRequest,
+            GetSyncInfoRequest, GetTwoWayPegDataRequest, StreamTwoWayPegDataRequest,
+            StreamTwoWayPegDataResponse, SubscribeEventsRequest, WithdrawalBundleEventType,
+        },
+    },
+    types::{
+        Hash256, HeaderInfo, SidechainDescription, SidechainNumber, WithdrawalBundleEventKind,
+    },
+    validator::SyncInfo,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to connect to enforcer")]
+    Connect(#[from] tonic::transport::Error),
+    #[error("Failed to decode response from enforcer")]
+    Decode(#[from] proto::Error),
+    #[error("Enforcer returned an error")]
+    Status(#[from] tonic::Status),
+}
+
+/// Sidechain treasury UTXO
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ctip {
+    pub outpoint: OutPoint,
+    pub value: Amount,
+    pub sequence_number: u64,
+}
+
+impl TryFrom<proto::mainchain::Ctip> for Ctip {
+    type Error = proto::Error;
+
+    fn try_from(ctip: proto::mainchain::Ctip) -> Result<Self, Self::Error> {
+        use proto::mainchain::Ctip;
+        let Ctip {
+            txid,
+            vout,
+            value,
+            sequence_number,
+        } = ctip;
+        let txid = txid
+            .ok_or_else(|| Self::Error::missing_field::<Ctip>("txid"))?
+            .decode::<Ctip, _>("txid")?;
+        Ok(Self {
+            outpoint: OutPoint { txid, vout },
+            value: Amount::from_sat(value),
+            sequence_number,
+        })
+    }
+}
+
+/// Active sidechain
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sidechain {
+    pub sidechain_number: SidechainNumber,
+    pub description: SidechainDescription,
+    pub vote_count: u16,
+    pub proposal_height: u32,
+    pub activation_height: Option<u32>,
+}
+
+impl TryFrom<SidechainInfo> for Sidechain {
+    type Error = proto::Error;
+
+    fn try_from(sidechain_info: SidechainInfo) -> Result<Self, Self::Error> {
+        let SidechainInfo {
+            sidechain_number,
+            description,
+            declaration: _,
+            description_sha256d_hash: _,
+            vote_count,
+            proposal_height,
+            activation_height,
+            replacement_pending: _,
+        } = sidechain_info;
+        let sidechain_number = {
+            let sidechain_number = sidechain_number
+                .ok_or_else(|| Self::Error::missing_field::<SidechainInfo>("sidechain_number"))?;
+            SidechainNumber::try_from(sidechain_number).map_err(|err| {
+                Self::Error::invalid_field_value::<SidechainInfo, _>(
+                    "sidechain_number",
+                    &sidechain_number.to_string(),
+                    err,
+                )
+            })?
+        };
+        let description: Vec<u8> = description
+            .ok_or_else(|| Self::Error::missing_field::<SidechainInfo>("description"))?
+            .decode::<SidechainInfo, _>("description")?;
+        let vote_count = {
+            let vote_count = vote_count
+                .ok_or_else(|| Self::Error::missing_field::<SidechainInfo>("vote_count"))?;
+            u16::try_from(vote_count).map_err(|err| {
+                Self::Error::invalid_field_value::<SidechainInfo, _>(
+                    "vote_count",
+                    &vote_count.to_string(),
+                    err,
+                )
+            })?
+        };
+        let proposal_height = proposal_height
+            .ok_or_else(|| Self::Error::missing_field::<SidechainInfo>("proposal_height"))?;
+        Ok(Self {
+            sidechain_number,
+            description: description.into(),
+            vote_count,
+            proposal_height,
+            activation_height,
+        })
+    }
+}
+
+/// Deposit to a sidechain
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Deposit {
+    pub sequence_number: u64,
+    pub outpoint: OutPoint,
+    /// Sidechain address
+    pub address: Vec<u8>,
+    pub value: Amount,
+}
+
+impl TryFrom<proto::mainchain::Deposit> for Deposit {
+    type Error = proto::Error;
+
+    fn try_from(deposit: proto::mainchain::Deposit) -> Result<Self, Self::Error> {
+        use proto::mainchain::{deposit::Output, Deposit};
+        let Deposit {
+            sequence_number,
+            outpoint,
+            output,
+        } = deposit;
+        let sequence_number = sequence_number
+            .ok_or_else(|| Self::Error::missing_field::<Deposit>("sequence_number"))?;
+        let outpoint = outpoint
+            .ok_or_else(|| Self::Error::missing_field::<Deposit>("outpoint"))?
+            .try_into()?;
+        let Output {
+            address,
+            value_sats,
+        } = output.ok_or_else(|| Self::Error::missing_field::<Deposit>("output"))?;
+        let address = address
+            .ok_or_else(|| Self::Error::missing_field::<Output>("address"))?
+            .decode::<Output, _>("address")?;
+        let value_sats =
+            value_sats.ok_or_else(|| Self::Error::missing_field::<Output>("value_sats"))?;
+        Ok(Self {
+            sequence_number,
+            outpoint,
+            address,
+            value: Amount::from_sat(value_sats),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WithdrawalBundleEvent {
+    pub m6id: Hash256,
+    pub kind: WithdrawalBundleEventKind,
+}
+
+impl TryFrom<proto::mainchain::WithdrawalBundleEvent> for WithdrawalBundleEvent {
+    type Error = proto::Error;
+
+    fn try_from(event: proto::mainchain::WithdrawalBundleEvent) -> Result<Self, Self::Error> {
+        use proto::mainchain::WithdrawalBundleEvent;
+        let WithdrawalBundleEvent {
+            m6id,
+            withdrawal_bundle_event_type,
+        } = event;
+        let m6id = m6id
+            .ok_or_else(|| Self::Error::missing_field::<WithdrawalBundleEvent>("m6id"))?
+            .decode::<WithdrawalBundleEvent, _>("m6id")?;
+        let kind = WithdrawalBundleEventType::try_from(withdrawal_bundle_event_type)
+            .map_err(|_| {
+                Self::Error::unknown_enum_tag::<WithdrawalBundleEvent>(
+                    "withdrawal_bundle_event_type",
+                    withdrawal_bundle_event_type,
+                )
+            })?
+            .try_into()?;
+        Ok(Self { m6id, kind })
+    }
+}
+
+/// Two-way peg data for a single block, for a single sidechain
+#[derive(Clone, Debug, Default)]
+pub struct BlockInfo {
+    pub deposits: Vec<Deposit>,
+    pub withdrawal_bundle_events: Vec<WithdrawalBundleEvent>,
+    pub bmm_commitment: Option<Hash256>,
+}
+
+impl TryFrom<proto::mainchain::BlockInfo> for BlockInfo {
+    type Error = proto::Error;
+
+    fn try_from(block_info: proto::mainchain::BlockInfo) -> Result<Self, Self::Error> {
+        use proto::mainchain::BlockInfo;
+        let BlockInfo {
+            deposits,
+            withdrawal_bundle_events,
+            bmm_commitment,
+        } = block_info;
+        let deposits = deposits
+            .into_iter()
+            .map(Deposit::try_from)
+            .collect::<Result<_, _>>()?;
+        let withdrawal_bundle_events = withdrawal_bundle_events
+            .into_iter()
+            .map(WithdrawalBundleEvent::try_from)
+            .collect::<Result<_, _>>()?;
+        let bmm_commitment = bmm_commitment
+            .map(|bmm_commitment| bmm_commitment.decode::<BlockInfo, _>("bmm_commitment"))
+            .transpose()?;
+        Ok(Self {
+            deposits,
+            withdrawal_bundle_events,
+            bmm_commitment,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TwoWayPegData {
+    pub header_info: HeaderInfo,
+    pub block_info: BlockInfo,
+}
+
+impl TryFrom<get_two_way_peg_data_response::ResponseItem> for TwoWayPegData {
+    type Error = proto::Error;
+
+    fn try_from(item: get_two_way_peg_data_response::ResponseItem) -> Result<Self, Self::Error> {
+        use get_two_way_peg_data_response::ResponseItem;
+        let ResponseItem {
+            block_header_info,
+            block_info,
+        } = item;
+        let header_info = block_header_info
+            .ok_or_else(|| Self::Error::missing_field::<ResponseItem>("block_header_info"))?
+            .try_into()?;
+        let block_info = block_info
+            .ok_or_else(|| Self::Error::missing_field::<ResponseItem>("block_info"))?
+            .try_into()?;
+        Ok(Self {
+            header_info,
+            block_info,
+        })
+    }
+}
+
+/// Event for a single sidechain
+#[derive(Clone, Debug)]
+pub enum Event {
+    ConnectBlock {
+        header_info: HeaderInfo,
+        block_info: BlockInfo,
+    },
+    DisconnectBlock {
+        block_hash: BlockHash,
+    },
+    /// Alarm vote for a pending withdrawal bundle
+    WithdrawalBundleAlert {
+        block_hash: BlockHash,
+        m6id: Hash256,
+        previous_vote_count: u16,
+        vote_count: u16,
+        leading: bool,
+    },
+    /// Deposit reached the configured number of confirmations for its
+    /// sidechain in `block_hash`
+    DepositConfirmed {
+        block_hash: BlockHash,
+        deposit: Deposit,
+        deposit_block_hash: BlockHash,
+        deposit_block_height: u32,
+        confirmations: u32,
+    },
+    /// Disconnecting `block_hash` reduced the confirmations of a confirmed
+    /// deposit to `confirmations`
+    DepositUnconfirmed {
+        block_hash: BlockHash,
+        deposit: Deposit,
+        deposit_block_hash: BlockHash,
+        deposit_block_height: u32,
+        confirmations: u32,
+    },
+}
+
+impl TryFrom<subscribe_events_response::event::Event> for Event {
+    type Error = proto::Error;
+
+    fn try_from(event: subscribe_events_response::event::Event) -> Result<Self, Self::Error> {
+        use subscribe_events_response::event::{
+            ConnectBlock, DepositConfirmed, DepositUnconfirmed, DisconnectBlock, Event,
+            WithdrawalBundleAlert,
+        };
+        match event {
+            Event::ConnectBlock(ConnectBlock {
+                header_info,
+                block_info,
+            }) => {
+                let header_info = header_info
+                    .ok_or_else(|| Self::Error::missing_field::<ConnectBlock>("header_info"))?
+                    .try_into()?;
+                let block_info = block_info
+                    .ok_or_else(|| Self::Error::missing_field::<ConnectBlock>("block_info"))?
+                    .try_into()?;
+                Ok(Self::ConnectBlock {
+                    header_info,
+                    block_info,
+                })
+            }
+            Event::DisconnectBlock(DisconnectBlock { block_hash }) => {
+                let block_hash = block_hash
+                    .ok_or_else(|| Self::Error::missing_field::<DisconnectBlock>("block_hash"))?
+                    .decode::<DisconnectBlock, _>("block_hash")?;
+                Ok(Self::DisconnectBlock { block_hash })
+            }
+            Event::WithdrawalBundleAlert(WithdrawalBundleAlert {
+                block_hash,
+                m6id,
+                previous_vote_count,
+                vote_count,
+                leading,
+            }) => {
+                let vote_count_field = |field_name: &str, vote_count: Option<u32>| {
+                    let vote_count = vote_count.ok_or_else(|| {
+                        Self::Error::missing_field::<WithdrawalBundleAlert>(field_name)
+                    })?;
+                    u16::try_from(vote_count).map_err(|err| {
+                        Self::Error::invalid_field_value::<WithdrawalBundleAlert, _>(
+                            field_name,
+                            &vote_count.to_string(),
+                            err,
+                        )
+                    })
+                };
+                let block_hash = block_hash
+                    .ok_or_else(|| {
+                        Self::Error::missing_field::<WithdrawalBundleAlert>("block_hash")
+                    })?
+                    .decode::<WithdrawalBundleAlert, _>("block_hash")?;
+                let m6id = m6id
+                    .ok_or_else(|| Self::Error::missing_field::<WithdrawalBundleAlert>("m6id"))?
+                    .decode::<WithdrawalBundleAlert, _>("m6id")?;
+                let leading = leading.ok_or_else(|| {
+                    Self::Error::missing_field::<WithdrawalBundleAlert>("leading")
+                })?;
+                Ok(Self::WithdrawalBundleAlert {
+                    block_hash,
+                    m6id,
+                    previous_vote_count: vote_count_field(
+                        "previous_vote_count",
+                        previous_vote_count,
+                    )?,
+                    vote_count: vote_count_field("vote_count", vote_count)?,
+                    leading,
+                })
+            }
+            Event::DepositConfirmed(DepositConfirmed {
+                block_hash,
+                deposit,
+                deposit_block_hash,
+                deposit_block_height,
+                confirmations,
+            }) => {
+                let block_hash = block_hash
+                    .ok_or_else(|| Self::Error::missing_field::<DepositConfirmed>("block_hash"))?
+                    .decode::<DepositConfirmed, _>("block_hash")?;
+                let deposit = deposit
+                    .ok_or_else(|| Self::Error::missing_field::<DepositConfirmed>("deposit"))?
+                    .try_into()?;
+                let deposit_block_hash = deposit_block_hash
+                    .ok_or_else(|| {
+                        Self::Error::missing_field::<DepositConfirmed>("deposit_block_hash")
+                    })?
+                    .decode::<DepositConfirmed, _>("deposit_block_hash")?;
+                Ok(Self::DepositConfirmed {
+                    block_hash,
+                    deposit,
+                    deposit_block_hash,
+                    deposit_block_height,
+                    confirmations,
+                })
+            }
+            Event::DepositUnconfirmed(DepositUnconfirmed {
+                block_hash,
+                deposit,
+                deposit_block_hash,
+                deposit_block_height,
+                confirmations,
+            }) => {
+                let block_hash = block_hash
+                    .ok_or_else(|| Self::Error::missing_field::<DepositUnconfirmed>("block_hash"))?
+                    .decode::<DepositUnconfirmed, _>("block_hash")?;
+                let deposit = deposit
+                    .ok_or_else(|| Self::Error::missing_field::<DepositUnconfirmed>("deposit"))?
+                    .try_into()?;
+                let deposit_block_hash = deposit_block_hash
+                    .ok_or_else(|| {
+                        Self::Error::missing_field::<DepositUnconfirmed>("deposit_block_hash")
+                    })?
+                    .decode::<DepositUnconfirmed, _>("deposit_block_hash")?;
+                Ok(Self::DepositUnconfirmed {
+                    block_hash,
+                    deposit,
+                    deposit_block_hash,
+                    deposit_block_height,
+                    confirmations,
+                })
+            }
+        }
+    }
+}
+
+impl TryFrom<subscribe_events_response::Event> for Event {
+    type Error = proto::Error;
+
+    fn try_from(event: subscribe_events_response::Event) -> Result<Self, Self::Error> {
+        let subscribe_events_response::Event { event } = event;
+        event
+            .ok_or_else(|| Self::Error::missing_field::<subscribe_events_response::Event>("event"))?
+            .try_into()
+    }
+}
+
+fn decode_event(
+    resp: proto::mainchain::SubscribeEventsResponse,
+) -> Result<(u64, Event), proto::Error> {
+    use proto::mainchain::SubscribeEventsResponse;
+    let SubscribeEventsResponse { sequence, event } = resp;
+    let sequence = sequence
+        .ok_or_else(|| proto::Error::missing_field::<SubscribeEventsResponse>("sequence"))?;
+    let event = event
+        .ok_or_else(|| proto::Error::missing_field::<SubscribeEventsResponse>("event"))?
+        .try_into()?;
+    Ok((sequence, event))
+}
+
+/// Typed client for the enforcer's validator service
+#[derive(Clone, Debug)]
+pub struct EnforcerClient {
+    inner: ValidatorServiceClient<Channel>,
+}
+
+impl EnforcerClient {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            inner: ValidatorServiceClient::new(channel),
+        }
+    }
+
+    /// Connect to the enforcer's gRPC server, eg. `http://127.0.0.1:50051`
+    pub async fn connect<D>(endpoint: D) -> Result<Self, Error>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<tonic::codegen::StdError>,
+    {
+        let inner = ValidatorServiceClient::connect(endpoint).await?;
+        Ok(Self { inner })
+    }
+
+    /// Access the underlying generated client, for requests that do not
+    /// have a typed wrapper
+    pub fn inner_mut(&mut self) -> &mut ValidatorServiceClient<Channel> {
+        &mut self.inner
+    }
+
+    pub async fn get_chain_tip(&mut self) -> Result<HeaderInfo, Error> {
+        use proto::mainchain::GetChainTipResponse;
+        let GetChainTipResponse { block_header_info } = self
+            .inner
+            .get_chain_tip(GetChainTipRequest {})
+            .await?
+            .into_inner();
+        let header_info = block_header_info
+            .ok_or_else(|| proto::Error::missing_field::<GetChainTipResponse>("block_header_info"))?
+            .try_into()?;
+        Ok(header_info)
+    }
+
+    pub async fn get_block_header_info(
+        &mut self,
+        block_hash: BlockHash,
+    ) -> Result<HeaderInfo, Error> {
+        use proto::mainchain::GetBlockHeaderInfoResponse;
+        let request = GetBlockHeaderInfoRequest {
+            block_hash: Some(ReverseHex::encode(&block_hash)),
+            height: None,
+        };
+        let GetBlockHeaderInfoResponse { header_info } = self
+            .inner
+            .get_block_header_info(request)
+            .await?
+            .into_inner();
+        let header_info = header_info
+            .ok_or_else(|| {
+                proto::Error::missing_field::<GetBlockHeaderInfoResponse>("header_info")
+            })?
+            .try_into()?;
+        Ok(header_info)
+    }
+
+    pub async fn get_sync_info(&mut self) -> Result<SyncInfo, Error> {
+        use proto::mainchain::GetSyncInfoResponse;
+        let GetSyncInfoResponse {
+            block_height,
+            header_height,
+            node_block_height,
+            node_header_height,
+            estimated_seconds_remaining,
+            zmq_connected,
+            sync_task_restarts,
+        } = self
+            .inner
+            .get_sync_info(GetSyncInfoRequest {})
+            .await?
+            .into_inner();
+        Ok(SyncInfo {
+            block_height,
+            header_height,
+            node_block_height,
+            node_header_height,
+            estimated_time_remaining: estimated_seconds_remaining.map(Duration::from_secs),
+            zmq_connected,
+            sync_task_restarts,
+        })
+    }
+
+    /// Returns `None` if the sidechain has no treasury UTXO
+    pub async fn get_ctip(
+        &mut self,
+        sidechain_number: SidechainNumber,
+    ) -> Result<Option<Ctip>, Error> {
+        let request = GetCtipRequest {
+            sidechain_number: Some(sidechain_number.0.into()),
+        };
+        let ctip = self.inner.get_ctip(request).await?.into_inner().ctip;
+        Ok(ctip.map(Ctip::try_from).transpose()?)
+    }
+
+    /// Active sidechains
+    pub async fn get_sidechains(&mut self) -> Result<Vec<Sidechain>, Error> {
+        let sidechains = self
+            .inner
+            .get_sidechains(GetSidechainsRequest {})
+            .await?
+            .into_inner()
+            .sidechains;
+        let sidechains = sidechains
+            .into_iter()
+            .map(Sidechain::try_from)
+            .collect::<Result<_, _>>()?;
+        Ok(sidechains)
+    }
+
+    /// Two-way peg data for blocks after `start_block_hash`, up to and
+    /// including `end_block_hash`. If `start_block_hash` is `None`, starts
+    /// from genesis. Blocks without any data for the sidechain are omitted.
+    pub async fn get_two_way_peg_data(
+        &mut self,
+        sidechain_number: SidechainNumber,
+        start_block_hash: Option<BlockHash>,
+        end_block_hash: BlockHash,
+    ) -> Result<Vec<TwoWayPegData>, Error> {
+        let request = GetTwoWayPegDataRequest {
+            sidechain_id: Some(sidechain_number.0.into()),
+            start_block_hash: start_block_hash
+                .map(|start_block_hash| ReverseHex::encode(&start_block_hash)),
+            end_block_hash: Some(ReverseHex::encode(&end_block_hash)),
+            min_confirmations: None,
+        };
+        let blocks = self
+            .inner
+            .get_two_way_peg_data(request)
+            .await?
+            .into_inner()
+            .blocks;
+        let blocks = blocks
+            .into_iter()
+            .map(TwoWayPegData::try_from)
+            .collect::<Result<_, _>>()?;
+        Ok(blocks)
+    }
+
+    /// Like [`Self::get_two_way_peg_data`], but blocks are streamed one at a
+    /// time, so that the range is not limited by the max message size
+    pub async fn stream_two_way_peg_data(
+        &mut self,
+        sidechain_number: SidechainNumber,
+        start_block_hash: Option<BlockHash>,
+        end_block_hash: BlockHash,
+    ) -> Result<BoxStream<'static, Result<TwoWayPegData, Error>>, Error> {
+        let request = StreamTwoWayPegDataRequest {
+            sidechain_id: Some(sidechain_number.0.into()),
+            start_block_hash: start_block_hash
+                .map(|start_block_hash| ReverseHex::encode(&start_block_hash)),
+            end_block_hash: Some(ReverseHex::encode(&end_block_hash)),
+        };
+        let stream = self
+            .inner
+            .stream_two_way_peg_data(request)
+            .await?
+            .into_inner()
+            .map_err(Error::from)
+            .and_then(|resp| {
+                let res = resp
+                    .block
+                    .ok_or_else(|| {
+                        proto::Error::missing_field::<StreamTwoWayPegDataResponse>("block")
+                    })
+                    .and_then(TwoWayPegData::try_from)
+                    .map_err(Error::from);
+                futures::future::ready(res)
+            })
+            .boxed();
+        Ok(stream)
+    }
+
+    /// Subscribe to events for a sidechain. Each event is paired with its
+    /// sequence number, which can be used as `start_sequence` to resume the
+    /// subscription after a reconnect.
+    pub async fn subscribe_events(
+        &mut self,
+        sidechain_number: SidechainNumber,
+        start_sequence: Option<u64>,
+    ) -> Result<BoxStream<'static, Result<(u64, Event), Error>>, Error> {
+        let request = SubscribeEventsRequest {
+            sidechain_id: Some(sidechain_number.0.into()),
+            start_sequence,
+            start_block_hash: None,
+        };
+        let stream = self
+            .inner
+            .subscribe_events(request)
+            .await?
+            .into_inner()
+            .map_err(Error::from)
+            .and_then(|resp| futures::future::ready(decode_event(resp).map_err(Error::from)))
+            .boxed();
+        Ok(stream)
+    }
+}
@@ -0,0 +1,518 @@
+//! C API, for sidechain implementations that are not written in Rust.
+//!
+//! Enabled with the `cdylib` feature. The API wraps
+//! [`crate::enforcer_client::EnforcerClient`], so callers need a running
+//! enforcer, but do not need to speak gRPC or decode proto messages. See
+//! `include/bip300301_enforcer.h` for the C declarations.
+//!
+//! Functions that can fail return a negative value (or null), and set an
+//! error message that can be retrieved with [`enforcer_last_error`].
+//! Hashes are passed as 32-byte arrays, in consensus (internal) byte order.
+//! Variable-length results are returned as JSON strings, which must be freed
+//! with [`enforcer_string_free`].
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    ptr,
+    time::Duration,
+};
+
+use bitcoin::{hashes::Hash as _, BlockHash};
+use futures::{stream::BoxStream, StreamExt as _};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::{
+    enforcer_client::{self, BlockInfo, Deposit, EnforcerClient, Event, TwoWayPegData},
+    types::{HeaderInfo, SidechainNumber, WithdrawalBundleEventKind},
+};
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error(transparent)]
+    Client(#[from] enforcer_client::Error),
+    #[error("Failed to start async runtime")]
+    Runtime(#[source] std::io::Error),
+    #[error("Event stream ended")]
+    EventStreamEnded,
+    #[error("Argument `{0}` must be valid UTF-8")]
+    InvalidUtf8(&'static str),
+    #[error("Not subscribed to events")]
+    NotSubscribed,
+    #[error("Argument `{0}` must not be null")]
+    NullPointer(&'static str),
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(err: &Error) {
+    let mut msg = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        msg = format!("{msg}: {err}");
+        source = err.source();
+    }
+    // Messages cannot contain interior nul bytes
+    let msg = CString::new(msg.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(msg));
+}
+
+/// Map a result to a C return code, setting the last error on failure
+fn return_code(res: Result<c_int, Error>) -> c_int {
+    match res {
+        Ok(code) => code,
+        Err(err) => {
+            set_last_error(&err);
+            -1
+        }
+    }
+}
+
+fn into_c_string(value: serde_json::Value) -> *mut c_char {
+    // JSON-encoded strings escape nul bytes, so this cannot fail
+    CString::new(value.to_string())
+        .unwrap_or_default()
+        .into_raw()
+}
+
+/// Opaque client handle
+pub struct Handle {
+    runtime: tokio::runtime::Runtime,
+    client: EnforcerClient,
+    events: Option<BoxStream<'static, Result<(u64, Event), enforcer_client::Error>>>,
+}
+
+#[repr(C)]
+pub struct EnforcerHeaderInfo {
+    pub block_hash: [u8; 32],
+    pub prev_block_hash: [u8; 32],
+    pub height: u32,
+    /// Cumulative chain work, little-endian
+    pub work: [u8; 32],
+}
+
+impl From<HeaderInfo> for EnforcerHeaderInfo {
+    fn from(header_info: HeaderInfo) -> Self {
+        Self {
+            block_hash: header_info.block_hash.to_byte_array(),
+            prev_block_hash: header_info.prev_block_hash.to_byte_array(),
+            height: header_info.height,
+            work: header_info.work.to_le_bytes(),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct EnforcerCtip {
+    pub txid: [u8; 32],
+    pub vout: u32,
+    pub value_sats: u64,
+    pub sequence_number: u64,
+}
+
+fn header_info_json(header_info: &HeaderInfo) -> serde_json::Value {
+    json!({
+        "block_hash": header_info.block_hash,
+        "prev_block_hash": header_info.prev_block_hash,
+        "height": header_info.height,
+        "work": hex::encode(header_info.work.to_le_bytes()),
+    })
+}
+
+fn deposit_json(deposit: &Deposit) -> serde_json::Value {
+    json!({
+        "sequence_number": deposit.sequence_number,
+        "outpoint": {
+            "txid": deposit.outpoint.txid,
+            "vout": deposit.outpoint.vout,
+        },
+        "output": {
+            "address": hex::encode(&deposit.address),
+            "value_sats": deposit.value.to_sat(),
+        },
+    })
+}
+
+fn block_info_json(block_info: &BlockInfo) -> serde_json::Value {
+    let deposits: Vec<_> = block_info.deposits.iter().map(deposit_json).collect();
+    let withdrawal_bundle_events: Vec<_> = block_info
+        .withdrawal_bundle_events
+        .iter()
+        .map(|event| {
+            let kind = match event.kind {
+                WithdrawalBundleEventKind::Failed => "failed",
+                WithdrawalBundleEventKind::Submitted => "submitted",
+                WithdrawalBundleEventKind::Succeeded => "succeeded",
+            };
+            json!({
+                "m6id": hex::encode(event.m6id),
+                "kind": kind,
+            })
+        })
+        .collect();
+    json!({
+        "deposits": deposits,
+        "withdrawal_bundle_events": withdrawal_bundle_events,
+        "bmm_commitment": block_info.bmm_commitment.map(hex::encode),
+    })
+}
+
+fn two_way_peg_data_json(two_way_peg_data: &[TwoWayPegData]) -> serde_json::Value {
+    let blocks: Vec<_> = two_way_peg_data
+        .iter()
+        .map(|block| {
+            json!({
+                "header_info": header_info_json(&block.header_info),
+                "block_info": block_info_json(&block.block_info),
+            })
+        })
+        .collect();
+    json!({ "blocks": blocks })
+}
+
+fn event_json(event: &Event) -> serde_json::Value {
+    match event {
+        Event::ConnectBlock {
+            header_info,
+            block_info,
+        } => json!({
+            "connect_block": {
+                "header_info": header_info_json(header_info),
+                "block_info": block_info_json(block_info),
+            }
+        }),
+        Event::DisconnectBlock { block_hash } => json!({
+            "disconnect_block": {
+                "block_hash": block_hash,
+            }
+        }),
+        Event::WithdrawalBundleAlert {
+            block_hash,
+            m6id,
+            previous_vote_count,
+            vote_count,
+            leading,
+        } => json!({
+            "withdrawal_bundle_alert": {
+                "block_hash": block_hash,
+                "m6id": hex::encode(m6id),
+                "previous_vote_count": previous_vote_count,
+                "vote_count": vote_count,
+                "leading": leading,
+            }
+        }),
+        Event::DepositConfirmed {
+            block_hash,
+            deposit,
+            deposit_block_hash,
+            deposit_block_height,
+            confirmations,
+        } => json!({
+            "deposit_confirmed": {
+                "block_hash": block_hash,
+                "deposit": deposit_json(deposit),
+                "deposit_block_hash": deposit_block_hash,
+                "deposit_block_height": deposit_block_height,
+                "confirmations": confirmations,
+            }
+        }),
+        Event::DepositUnconfirmed {
+            block_hash,
+            deposit,
+            deposit_block_hash,
+            deposit_block_height,
+            confirmations,
+        } => json!({
+            "deposit_unconfirmed": {
+                "block_hash": block_hash,
+                "deposit": deposit_json(deposit),
+                "deposit_block_hash": deposit_block_hash,
+                "deposit_block_height": deposit_block_height,
+                "confirmations": confirmations,
+            }
+        }),
+    }
+}
+
+/// # Safety
+/// `handle` must be null, or a valid pointer returned by
+/// [`enforcer_client_connect`] that has not been freed.
+unsafe fn handle_mut<'a>(handle: *mut Handle) -> Result<&'a mut Handle, Error> {
+    handle.as_mut().ok_or(Error::NullPointer("handle"))
+}
+
+/// # Safety
+/// `block_hash` must be null, or point to 32 readable bytes.
+unsafe fn read_block_hash(block_hash: *const u8) -> Option<BlockHash> {
+    if block_hash.is_null() {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    ptr::copy_nonoverlapping(block_hash, bytes.as_mut_ptr(), bytes.len());
+    Some(BlockHash::from_byte_array(bytes))
+}
+
+/// Returns the last error that occurred on the current thread, or null if
+/// there was no error. The string is valid until the next call to an
+/// `enforcer_` function on the same thread.
+#[no_mangle]
+pub extern "C" fn enforcer_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |msg| msg.as_ptr())
+    })
+}
+
+/// Free a string returned by this API.
+///
+/// # Safety
+/// `s` must be null, or a string returned by this API that has not already
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+unsafe fn connect(url: *const c_char) -> Result<Handle, Error> {
+    if url.is_null() {
+        return Err(Error::NullPointer("url"));
+    }
+    let url = CStr::from_ptr(url)
+        .to_str()
+        .map_err(|_| Error::InvalidUtf8("url"))?
+        .to_owned();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::Runtime)?;
+    let client = runtime.block_on(EnforcerClient::connect(url))?;
+    Ok(Handle {
+        runtime,
+        client,
+        events: None,
+    })
+}
+
+/// Connect to the enforcer's gRPC server at `url`, eg.
+/// `http://127.0.0.1:50051`. Returns null on failure.
+///
+/// # Safety
+/// `url` must be null, or a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_client_connect(url: *const c_char) -> *mut Handle {
+    match connect(url) {
+        Ok(handle) => Box::into_raw(Box::new(handle)),
+        Err(err) => {
+            set_last_error(&err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Close the connection and free the handle.
+///
+/// # Safety
+/// `handle` must be null, or a valid pointer returned by
+/// [`enforcer_client_connect`] that has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_client_free(handle: *mut Handle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+unsafe fn get_chain_tip(handle: *mut Handle, out: *mut EnforcerHeaderInfo) -> Result<c_int, Error> {
+    let handle = handle_mut(handle)?;
+    let out = out.as_mut().ok_or(Error::NullPointer("out"))?;
+    let header_info = handle.runtime.block_on(handle.client.get_chain_tip())?;
+    *out = header_info.into();
+    Ok(0)
+}
+
+/// Write the enforcer's chain tip to `out`. Returns 0 on success.
+///
+/// # Safety
+/// `handle` must be a valid client handle, and `out` must be null or valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_get_chain_tip(
+    handle: *mut Handle,
+    out: *mut EnforcerHeaderInfo,
+) -> c_int {
+    return_code(get_chain_tip(handle, out))
+}
+
+unsafe fn get_ctip(
+    handle: *mut Handle,
+    sidechain_number: u8,
+    out: *mut EnforcerCtip,
+) -> Result<c_int, Error> {
+    let handle = handle_mut(handle)?;
+    let out = out.as_mut().ok_or(Error::NullPointer("out"))?;
+    let ctip = handle.runtime.block_on(
+        handle
+            .client
+            .get_ctip(SidechainNumber::from(sidechain_number)),
+    )?;
+    let Some(ctip) = ctip else { return Ok(0) };
+    *out = EnforcerCtip {
+        txid: ctip.outpoint.txid.to_byte_array(),
+        vout: ctip.outpoint.vout,
+        value_sats: ctip.value.to_sat(),
+        sequence_number: ctip.sequence_number,
+    };
+    Ok(1)
+}
+
+/// Write the sidechain's CTIP to `out`. Returns 1 if the sidechain has a
+/// CTIP, and 0 if it does not, in which case `out` is not modified.
+///
+/// # Safety
+/// `handle` must be a valid client handle, and `out` must be null or valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_get_ctip(
+    handle: *mut Handle,
+    sidechain_number: u8,
+    out: *mut EnforcerCtip,
+) -> c_int {
+    return_code(get_ctip(handle, sidechain_number, out))
+}
+
+unsafe fn get_two_way_peg_data(
+    handle: *mut Handle,
+    sidechain_number: u8,
+    start_block_hash: *const u8,
+    end_block_hash: *const u8,
+    out_json: *mut *mut c_char,
+) -> Result<c_int, Error> {
+    let handle = handle_mut(handle)?;
+    let out_json = out_json.as_mut().ok_or(Error::NullPointer("out_json"))?;
+    let start_block_hash = read_block_hash(start_block_hash);
+    let end_block_hash =
+        read_block_hash(end_block_hash).ok_or(Error::NullPointer("end_block_hash"))?;
+    let two_way_peg_data = handle.runtime.block_on(handle.client.get_two_way_peg_data(
+        SidechainNumber::from(sidechain_number),
+        start_block_hash,
+        end_block_hash,
+    ))?;
+    *out_json = into_c_string(two_way_peg_data_json(&two_way_peg_data));
+    Ok(0)
+}
+
+/// Fetch two-way peg data for the sidechain, for blocks after
+/// `start_block_hash` (or from genesis, if null), up to and including
+/// `end_block_hash`. On success, returns 0 and writes a JSON string to
+/// `out_json`, which must be freed with [`enforcer_string_free`].
+///
+/// # Safety
+/// `handle` must be a valid client handle. `start_block_hash` must be null or
+/// point to 32 readable bytes, `end_block_hash` must point to 32 readable
+/// bytes, and `out_json` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_get_two_way_peg_data(
+    handle: *mut Handle,
+    sidechain_number: u8,
+    start_block_hash: *const u8,
+    end_block_hash: *const u8,
+    out_json: *mut *mut c_char,
+) -> c_int {
+    return_code(get_two_way_peg_data(
+        handle,
+        sidechain_number,
+        start_block_hash,
+        end_block_hash,
+        out_json,
+    ))
+}
+
+unsafe fn subscribe_events(
+    handle: *mut Handle,
+    sidechain_number: u8,
+    start_sequence: i64,
+) -> Result<c_int, Error> {
+    let handle = handle_mut(handle)?;
+    let start_sequence = u64::try_from(start_sequence).ok();
+    let events = handle.runtime.block_on(
+        handle
+            .client
+            .subscribe_events(SidechainNumber::from(sidechain_number), start_sequence),
+    )?;
+    handle.events = Some(events);
+    Ok(0)
+}
+
+/// Subscribe to events for the sidechain, replacing any existing
+/// subscription on this handle. If `start_sequence` is non-negative, events
+/// are replayed from that sequence number. Returns 0 on success.
+///
+/// # Safety
+/// `handle` must be a valid client handle.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_subscribe_events(
+    handle: *mut Handle,
+    sidechain_number: u8,
+    start_sequence: i64,
+) -> c_int {
+    return_code(subscribe_events(handle, sidechain_number, start_sequence))
+}
+
+unsafe fn poll_event(
+    handle: *mut Handle,
+    timeout_ms: u64,
+    out_sequence: *mut u64,
+    out_json: *mut *mut c_char,
+) -> Result<c_int, Error> {
+    let handle = handle_mut(handle)?;
+    let out_sequence = out_sequence
+        .as_mut()
+        .ok_or(Error::NullPointer("out_sequence"))?;
+    let out_json = out_json.as_mut().ok_or(Error::NullPointer("out_json"))?;
+    let events = handle.events.as_mut().ok_or(Error::NotSubscribed)?;
+    let timeout = Duration::from_millis(timeout_ms);
+    let next = handle
+        .runtime
+        .block_on(async { tokio::time::timeout(timeout, events.next()).await });
+    let res = match next {
+        Err(_elapsed) => return Ok(0),
+        Ok(None) => Err(Error::EventStreamEnded),
+        Ok(Some(Err(err))) => Err(err.into()),
+        Ok(Some(Ok((sequence, event)))) => {
+            *out_sequence = sequence;
+            *out_json = into_c_string(event_json(&event));
+            Ok(1)
+        }
+    };
+    if res.is_err() {
+        handle.events = None;
+    }
+    res
+}
+
+/// Wait up to `timeout_ms` milliseconds for the next event. Returns 1 and
+/// writes the event's sequence number and a JSON string to `out_sequence`
+/// and `out_json` if an event was received, or 0 if the timeout elapsed.
+/// The JSON string must be freed with [`enforcer_string_free`].
+///
+/// If the event stream fails or ends, the subscription is dropped, and
+/// callers should resubscribe from the last sequence number they processed.
+///
+/// # Safety
+/// `handle` must be a valid client handle, and `out_sequence` and `out_json`
+/// must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn enforcer_poll_event(
+    handle: *mut Handle,
+    timeout_ms: u64,
+    out_sequence: *mut u64,
+    out_json: *mut *mut c_char,
+) -> c_int {
+    return_code(poll_event(handle, timeout_ms, out_sequence, out_json))
+}
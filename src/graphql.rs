@@ -0,0 +1,604 @@
+//! Read-only GraphQL endpoint over the enforcer's state.
+//!
+//! Objects expose their relations as fields, so that nested data (eg. a
+//! block with its deposits, and the sidechains that they were made to) can
+//! be fetched in a single request. Encodings follow the REST gateway:
+//! hashes are hex, in the same byte order as Bitcoin Core's RPC interface,
+//! and amounts are in sats.
+//!
+//! Queries are served at `/graphql`, via `GET` or `POST`.
+//!
+//! Queries are rejected if their estimated cost is too high. Fields that
+//! read from the DBs cost more than other fields, and list fields multiply
+//! the cost of their items by the requested count, or by an estimated
+//! length if the count is not set by the query.
+
+use std::{future::Future, net::SocketAddr};
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Object, SchemaBuilder, SimpleObject,
+};
+use async_graphql_axum::GraphQL;
+use axum::Router;
+use bitcoin::{hashes::sha256d, BlockHash};
+
+use crate::{
+    json,
+    types::{self, BlockInfo, HeaderInfo, PendingM6id, SidechainDeclaration, SidechainNumber},
+    validator::{dbs::UnitKey, SidechainProposalVotingStatus, Validator},
+};
+
+/// Max number of blocks that can be requested with `blocks`
+const MAX_BLOCKS_PER_QUERY: u32 = 100;
+
+/// Max nesting depth of a query
+const MAX_QUERY_DEPTH: usize = 10;
+
+/// Max estimated cost of a query
+const MAX_QUERY_COMPLEXITY: usize = 5_000;
+
+/// Cost of a field that reads from the DBs, excluding its children
+const DB_READ_COMPLEXITY: usize = 5;
+
+/// Assumed length of lists whose length is not set by the query, for
+/// estimating their cost
+const ESTIMATED_LIST_LEN: usize = 10;
+
+type Result<T, E = async_graphql::Error> = std::result::Result<T, E>;
+
+fn validator<'ctx>(ctx: &Context<'ctx>) -> &'ctx Validator {
+    ctx.data_unchecked::<Validator>()
+}
+
+/// Returns `None` if the header is unknown
+fn try_get_block(validator: &Validator, block_hash: &BlockHash) -> Result<Option<Block>> {
+    let dbs = validator.dbs();
+    let rotxn = dbs.read_txn()?;
+    let Some(header_info) = dbs.block_hashes.try_get_header_info(&rotxn, block_hash)? else {
+        return Ok(None);
+    };
+    let block_info = dbs.block_hashes.try_get_block_info(&rotxn, block_hash)?;
+    Ok(Some(Block {
+        header_info,
+        block_info,
+    }))
+}
+
+/// Returns `None` if the sidechain slot is not active
+fn try_get_active_sidechain(
+    validator: &Validator,
+    sidechain_number: SidechainNumber,
+) -> Result<Option<Sidechain>> {
+    let dbs = validator.dbs();
+    let rotxn = dbs.read_txn()?;
+    let sidechain = dbs
+        .active_sidechains
+        .sidechain
+        .try_get(&rotxn, &sidechain_number)?;
+    Ok(sidechain.map(Sidechain))
+}
+
+pub struct Block {
+    header_info: HeaderInfo,
+    /// `None` if block info has been pruned
+    block_info: Option<BlockInfo>,
+}
+
+#[Object]
+impl Block {
+    async fn hash(&self) -> String {
+        self.header_info.block_hash.to_string()
+    }
+
+    async fn prev_block_hash(&self) -> String {
+        self.header_info.prev_block_hash.to_string()
+    }
+
+    async fn height(&self) -> u32 {
+        self.header_info.height
+    }
+
+    /// `true` if the block is in the current best chain
+    #[graphql(complexity = "DB_READ_COMPLEXITY")]
+    async fn in_best_chain(&self, ctx: &Context<'_>) -> Result<bool> {
+        let best_chain_block_hash =
+            validator(ctx).try_get_best_chain_block_hash(self.header_info.height)?;
+        Ok(best_chain_block_hash == Some(self.header_info.block_hash))
+    }
+
+    /// `null` if block info has been pruned
+    #[graphql(complexity = "ESTIMATED_LIST_LEN * child_complexity")]
+    async fn deposits(&self) -> Option<Vec<Deposit>> {
+        let block_info = self.block_info.as_ref()?;
+        let deposits = block_info
+            .deposits
+            .iter()
+            .map(|deposit| Deposit {
+                deposit: deposit.clone(),
+                block_hash: self.header_info.block_hash,
+            })
+            .collect();
+        Some(deposits)
+    }
+
+    /// `null` if block info has been pruned
+    #[graphql(complexity = "ESTIMATED_LIST_LEN * child_complexity")]
+    async fn withdrawal_bundle_events(&self) -> Option<Vec<WithdrawalBundleEvent>> {
+        let block_info = self.block_info.as_ref()?;
+        let events = block_info
+            .withdrawal_bundle_events
+            .iter()
+            .map(|event| WithdrawalBundleEvent {
+                event: event.clone(),
+                block_hash: self.header_info.block_hash,
+            })
+            .collect();
+        Some(events)
+    }
+
+    /// `null` if block info has been pruned
+    #[graphql(complexity = "ESTIMATED_LIST_LEN * child_complexity")]
+    async fn bmm_commitments(&self) -> Option<Vec<BmmCommitment>> {
+        let block_info = self.block_info.as_ref()?;
+        let commitments = block_info
+            .bmm_commitments
+            .iter()
+            .map(|(sidechain_number, commitment)| BmmCommitment {
+                sidechain_number: sidechain_number.0,
+                commitment: hex::encode(commitment),
+            })
+            .collect();
+        Some(commitments)
+    }
+}
+
+pub struct Deposit {
+    deposit: types::Deposit,
+    block_hash: BlockHash,
+}
+
+#[Object]
+impl Deposit {
+    async fn sidechain_number(&self) -> u8 {
+        self.deposit.sidechain_id.0
+    }
+
+    async fn sequence_number(&self) -> u64 {
+        self.deposit.sequence_number
+    }
+
+    async fn outpoint(&self) -> String {
+        self.deposit.outpoint.to_string()
+    }
+
+    /// Hex-encoded sidechain address
+    async fn address(&self) -> String {
+        hex::encode(&self.deposit.address)
+    }
+
+    async fn value_sats(&self) -> u64 {
+        self.deposit.value.to_sat()
+    }
+
+    /// Block containing the deposit
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        try_get_block(validator(ctx), &self.block_hash)
+    }
+
+    /// The sidechain currently active in the deposit's slot, if any
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn sidechain(&self, ctx: &Context<'_>) -> Result<Option<Sidechain>> {
+        try_get_active_sidechain(validator(ctx), self.deposit.sidechain_id)
+    }
+}
+
+pub struct WithdrawalBundleEvent {
+    event: types::WithdrawalBundleEvent,
+    block_hash: BlockHash,
+}
+
+#[Object]
+impl WithdrawalBundleEvent {
+    async fn sidechain_number(&self) -> u8 {
+        self.event.sidechain_id.0
+    }
+
+    async fn m6id(&self) -> String {
+        hex::encode(self.event.m6id)
+    }
+
+    /// One of `submitted`, `failed` or `succeeded`
+    async fn kind(&self) -> &'static str {
+        json::withdrawal_bundle_event_kind(self.event.kind)
+    }
+
+    /// Block containing the event
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        try_get_block(validator(ctx), &self.block_hash)
+    }
+
+    /// The sidechain currently active in the event's slot, if any
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn sidechain(&self, ctx: &Context<'_>) -> Result<Option<Sidechain>> {
+        try_get_active_sidechain(validator(ctx), self.event.sidechain_id)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct BmmCommitment {
+    sidechain_number: u8,
+    commitment: String,
+}
+
+#[derive(SimpleObject)]
+pub struct Ctip {
+    txid: String,
+    vout: u32,
+    value_sats: u64,
+    sequence_number: u64,
+}
+
+#[derive(SimpleObject)]
+pub struct SidechainStats {
+    total_deposited_sats: u64,
+    total_withdrawn_sats: u64,
+    deposit_count: u64,
+    succeeded_withdrawal_bundle_count: u64,
+    failed_withdrawal_bundle_count: u64,
+    treasury_balance_sats: u64,
+}
+
+#[derive(SimpleObject)]
+pub struct PendingWithdrawalBundle {
+    m6id: String,
+    vote_count: u16,
+    proposal_height: u32,
+    age: u32,
+}
+
+impl PendingWithdrawalBundle {
+    fn new(pending_m6id: &PendingM6id, age: u32) -> Self {
+        Self {
+            m6id: hex::encode(pending_m6id.m6id),
+            vote_count: pending_m6id.vote_count,
+            proposal_height: pending_m6id.proposal_height,
+            age,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Declaration {
+    title: String,
+    description: String,
+    hash_id_1: String,
+    hash_id_2: String,
+}
+
+impl From<SidechainDeclaration> for Declaration {
+    fn from(declaration: SidechainDeclaration) -> Self {
+        Self {
+            title: declaration.title,
+            description: declaration.description,
+            hash_id_1: hex::encode(declaration.hash_id_1),
+            hash_id_2: hex::encode(declaration.hash_id_2),
+        }
+    }
+}
+
+/// An active sidechain
+pub struct Sidechain(types::Sidechain);
+
+#[Object]
+impl Sidechain {
+    async fn sidechain_number(&self) -> u8 {
+        self.0.proposal.sidechain_number.0
+    }
+
+    /// Hex-encoded sidechain description
+    async fn description(&self) -> String {
+        hex::encode(&self.0.proposal.description.0)
+    }
+
+    /// `null` if the description is not a valid declaration
+    async fn declaration(&self) -> Option<Declaration> {
+        SidechainDeclaration::try_from(&self.0.proposal.description)
+            .ok()
+            .map(Declaration::from)
+    }
+
+    async fn vote_count(&self) -> u16 {
+        self.0.status.vote_count
+    }
+
+    async fn proposal_height(&self) -> u32 {
+        self.0.status.proposal_height
+    }
+
+    async fn activation_height(&self) -> Option<u32> {
+        self.0.status.activation_height
+    }
+
+    /// `null` if there have been no deposits to the sidechain
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn ctip(&self, ctx: &Context<'_>) -> Result<Option<Ctip>> {
+        let validator = validator(ctx);
+        let sidechain_number = self.0.proposal.sidechain_number;
+        let Some(ctip) = validator.try_get_ctip(sidechain_number)? else {
+            return Ok(None);
+        };
+        let sequence_number = validator
+            .get_ctip_sequence_number(sidechain_number)?
+            .unwrap_or(0);
+        Ok(Some(Ctip {
+            txid: ctip.outpoint.txid.to_string(),
+            vout: ctip.outpoint.vout,
+            value_sats: ctip.value.to_sat(),
+            sequence_number,
+        }))
+    }
+
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn stats(&self, ctx: &Context<'_>) -> Result<Option<SidechainStats>> {
+        let Some(info) = validator(ctx).get_sidechain_stats(self.0.proposal.sidechain_number)?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(SidechainStats {
+            total_deposited_sats: info.stats.total_deposited.to_sat(),
+            total_withdrawn_sats: info.stats.total_withdrawn.to_sat(),
+            deposit_count: info.stats.deposit_count,
+            succeeded_withdrawal_bundle_count: info.stats.succeeded_withdrawal_bundle_count,
+            failed_withdrawal_bundle_count: info.stats.failed_withdrawal_bundle_count,
+            treasury_balance_sats: info.treasury_balance.to_sat(),
+        }))
+    }
+
+    #[graphql(complexity = "DB_READ_COMPLEXITY + ESTIMATED_LIST_LEN * child_complexity")]
+    async fn pending_withdrawal_bundles(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<PendingWithdrawalBundle>> {
+        let queue = validator(ctx).get_withdrawal_bundle_queue(self.0.proposal.sidechain_number)?;
+        Ok(queue
+            .iter()
+            .map(|(pending_m6id, age)| PendingWithdrawalBundle::new(pending_m6id, *age))
+            .collect())
+    }
+}
+
+/// A pending sidechain proposal
+pub struct SidechainProposal {
+    description_hash: sha256d::Hash,
+    sidechain: types::Sidechain,
+    status: SidechainProposalVotingStatus,
+}
+
+#[Object]
+impl SidechainProposal {
+    async fn description_hash(&self) -> String {
+        self.description_hash.to_string()
+    }
+
+    async fn sidechain_number(&self) -> u8 {
+        self.sidechain.proposal.sidechain_number.0
+    }
+
+    /// Hex-encoded sidechain description
+    async fn description(&self) -> String {
+        hex::encode(&self.sidechain.proposal.description.0)
+    }
+
+    /// `null` if the description is not a valid declaration
+    async fn declaration(&self) -> Option<Declaration> {
+        SidechainDeclaration::try_from(&self.sidechain.proposal.description)
+            .ok()
+            .map(Declaration::from)
+    }
+
+    async fn vote_count(&self) -> u16 {
+        self.status.vote_count
+    }
+
+    async fn proposal_height(&self) -> u32 {
+        self.status.proposal_height
+    }
+
+    async fn proposal_age(&self) -> u32 {
+        self.status.age
+    }
+
+    async fn max_age(&self) -> u16 {
+        self.status.rules.max_age
+    }
+
+    /// `true` if the proposal would replace an active sidechain
+    async fn sidechain_slot_is_used(&self) -> bool {
+        self.status.sidechain_slot_is_used
+    }
+
+    /// The sidechain that the proposal would replace, if any
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn active_sidechain(&self, ctx: &Context<'_>) -> Result<Option<Sidechain>> {
+        try_get_active_sidechain(validator(ctx), self.sidechain.proposal.sidechain_number)
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Tip of the current best chain. `null` if no blocks have been synced.
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn chain_tip(&self, ctx: &Context<'_>) -> Result<Option<Block>> {
+        let validator = validator(ctx);
+        let tip = {
+            let dbs = validator.dbs();
+            let rotxn = dbs.read_txn()?;
+            dbs.current_chain_tip.try_get(&rotxn, &UnitKey)?
+        };
+        match tip {
+            Some(tip) => try_get_block(validator, &tip),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a block by hash, or by height in the current best chain.
+    /// Exactly one of `hash` or `height` must be set.
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn block(
+        &self,
+        ctx: &Context<'_>,
+        hash: Option<String>,
+        height: Option<u32>,
+    ) -> Result<Option<Block>> {
+        let validator = validator(ctx);
+        let block_hash = match (hash, height) {
+            (Some(hash), None) => hash
+                .parse::<BlockHash>()
+                .map_err(|err| format!("invalid block hash `{hash}`: {err}"))?,
+            (None, Some(height)) => match validator.try_get_best_chain_block_hash(height)? {
+                Some(block_hash) => block_hash,
+                None => return Ok(None),
+            },
+            _ => return Err("exactly one of `hash` or `height` must be set".into()),
+        };
+        try_get_block(validator, &block_hash)
+    }
+
+    /// Up to `count` consecutive blocks in the current best chain, starting
+    /// at `startHeight`. Fewer blocks are returned if the tip is reached.
+    #[graphql(complexity = "DB_READ_COMPLEXITY + count as usize * child_complexity")]
+    async fn blocks(&self, ctx: &Context<'_>, start_height: u32, count: u32) -> Result<Vec<Block>> {
+        if count > MAX_BLOCKS_PER_QUERY {
+            return Err(format!("`count` must not exceed {MAX_BLOCKS_PER_QUERY}").into());
+        }
+        let validator = validator(ctx);
+        let mut res = Vec::new();
+        for height in start_height..start_height.saturating_add(count) {
+            let Some(block_hash) = validator.try_get_best_chain_block_hash(height)? else {
+                break;
+            };
+            if let Some(block) = try_get_block(validator, &block_hash)? {
+                res.push(block);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Active sidechains
+    #[graphql(complexity = "DB_READ_COMPLEXITY + ESTIMATED_LIST_LEN * child_complexity")]
+    async fn sidechains(&self, ctx: &Context<'_>) -> Result<Vec<Sidechain>> {
+        let sidechains = validator(ctx).get_active_sidechains()?;
+        Ok(sidechains.into_iter().map(Sidechain).collect())
+    }
+
+    /// The sidechain active in the specified slot, if any
+    #[graphql(complexity = "DB_READ_COMPLEXITY + child_complexity")]
+    async fn sidechain(
+        &self,
+        ctx: &Context<'_>,
+        sidechain_number: u8,
+    ) -> Result<Option<Sidechain>> {
+        try_get_active_sidechain(validator(ctx), SidechainNumber(sidechain_number))
+    }
+
+    /// Pending sidechain proposals, with their voting status at the current
+    /// tip
+    #[graphql(complexity = "DB_READ_COMPLEXITY + ESTIMATED_LIST_LEN * child_complexity")]
+    async fn sidechain_proposals(&self, ctx: &Context<'_>) -> Result<Vec<SidechainProposal>> {
+        let proposals = validator(ctx).get_sidechain_proposals()?;
+        Ok(proposals
+            .into_iter()
+            .map(|(description_hash, sidechain, status)| SidechainProposal {
+                description_hash,
+                sidechain,
+                status,
+            })
+            .collect())
+    }
+}
+
+pub type Schema = async_graphql::Schema<Query, EmptyMutation, EmptySubscription>;
+
+fn schema_builder() -> SchemaBuilder<Query, EmptyMutation, EmptySubscription> {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+}
+
+pub fn schema(validator: Validator) -> Schema {
+    schema_builder().data(validator).finish()
+}
+
+pub fn router(validator: Validator) -> Router {
+    Router::new().route_service("/graphql", GraphQL::new(schema(validator)))
+}
+
+/// Serve the GraphQL endpoint on the specified address, until `shutdown`
+/// completes and in-flight requests have finished
+pub async fn serve<F>(
+    validator: Validator,
+    addr: SocketAddr,
+    shutdown: F,
+) -> Result<(), std::io::Error>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Serving GraphQL endpoint at {}", listener.local_addr()?);
+    axum::serve(listener, router(validator))
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::schema_builder;
+
+    /// Introspection queries do not read from the DBs, so the schema can be
+    /// queried without a validator
+    #[tokio::test]
+    async fn test_schema_query() {
+        let schema = schema_builder().finish();
+        let resp = schema
+            .execute(r#"{ __type(name: "Query") { fields { name } } }"#)
+            .await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        let fields: Vec<String> = resp.data.into_json().unwrap()["__type"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "chainTip",
+                "block",
+                "blocks",
+                "sidechains",
+                "sidechain",
+                "sidechainProposals"
+            ]
+        );
+    }
+
+    /// Queries over the complexity limit are rejected before any fields are
+    /// resolved
+    #[tokio::test]
+    async fn test_complexity_limit() {
+        let schema = schema_builder().finish();
+        let resp = schema
+            .execute(
+                "{ blocks(startHeight: 0, count: 100) { \
+                    deposits { block { inBestChain } sidechain { stats { depositCount } } } \
+                } }",
+            )
+            .await;
+        let messages: Vec<&str> = resp.errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(messages, ["Query is too complex."]);
+    }
+}
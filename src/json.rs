@@ -0,0 +1,259 @@
+//! JSON representations of enforcer data, for the HTTP APIs.
+//! Hashes are encoded as hex, in the same byte order as Bitcoin Core's RPC
+//! interface, and amounts are in sats.
+
+use std::collections::BTreeSet;
+
+use bitcoin::hashes::sha256d;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    types::{
+        BlockInfo, Ctip, DepositConfirmation, Event, HeaderInfo, PendingM6id, Sidechain,
+        SidechainDeclaration, SidechainNumber, TwoWayPegData, WithdrawalBundleEventKind,
+    },
+    validator::SidechainProposalVotingStatus,
+};
+
+pub(crate) fn header_info(header_info: &HeaderInfo) -> Value {
+    json!({
+        "block_hash": header_info.block_hash.to_string(),
+        "prev_block_hash": header_info.prev_block_hash.to_string(),
+        "height": header_info.height,
+    })
+}
+
+pub(crate) fn ctip(ctip: &Ctip, sequence_number: u64) -> Value {
+    json!({
+        "txid": ctip.outpoint.txid.to_string(),
+        "vout": ctip.outpoint.vout,
+        "value_sats": ctip.value.to_sat(),
+        "sequence_number": sequence_number,
+    })
+}
+
+pub(crate) fn sidechain(sidechain: &Sidechain) -> Value {
+    let description = &sidechain.proposal.description;
+    let declaration = SidechainDeclaration::try_from(description)
+        .ok()
+        .map(|declaration| {
+            json!({
+                "title": declaration.title,
+                "description": declaration.description,
+                "hash_id_1": hex::encode(declaration.hash_id_1),
+                "hash_id_2": hex::encode(declaration.hash_id_2),
+            })
+        });
+    json!({
+        "sidechain_number": sidechain.proposal.sidechain_number.0,
+        "description": hex::encode(&description.0),
+        "declaration": declaration,
+        "vote_count": sidechain.status.vote_count,
+        "proposal_height": sidechain.status.proposal_height,
+        "activation_height": sidechain.status.activation_height,
+    })
+}
+
+/// `tip_height` is used to compute the age of the proposal
+pub(crate) fn sidechain_proposal(
+    description_hash: &sha256d::Hash,
+    sidechain: &Sidechain,
+    status: &SidechainProposalVotingStatus,
+) -> Value {
+    let mut res = self::sidechain(sidechain);
+    res["description_hash"] = json!(description_hash.to_string());
+    res["proposal_age"] = json!(status.age);
+    res["max_age"] = json!(status.rules.max_age);
+    res
+}
+
+/// `age` is the number of blocks since the bundle was proposed
+pub(crate) fn pending_withdrawal_bundle(pending_m6id: &PendingM6id, age: u32) -> Value {
+    json!({
+        "m6id": hex::encode(pending_m6id.m6id),
+        "vote_count": pending_m6id.vote_count,
+        "proposal_height": pending_m6id.proposal_height,
+        "age": age,
+    })
+}
+
+pub(crate) fn withdrawal_bundle_event_kind(kind: WithdrawalBundleEventKind) -> &'static str {
+    match kind {
+        WithdrawalBundleEventKind::Submitted => "submitted",
+        WithdrawalBundleEventKind::Failed => "failed",
+        WithdrawalBundleEventKind::Succeeded => "succeeded",
+    }
+}
+
+/// Block info for a single sidechain. Returns `None` if the block does not
+/// contain any deposits, withdrawal bundle events or BMM commitments for
+/// the sidechain.
+pub(crate) fn block_info(
+    block_info: &BlockInfo,
+    sidechain_number: SidechainNumber,
+) -> Option<Value> {
+    let deposits: Vec<_> = block_info
+        .deposits
+        .iter()
+        .filter(|deposit| deposit.sidechain_id == sidechain_number)
+        .map(|deposit| {
+            json!({
+                "sequence_number": deposit.sequence_number,
+                "outpoint": deposit.outpoint.to_string(),
+                "address": hex::encode(&deposit.address),
+                "value_sats": deposit.value.to_sat(),
+            })
+        })
+        .collect();
+    let withdrawal_bundle_events: Vec<_> = block_info
+        .withdrawal_bundle_events
+        .iter()
+        .filter(|event| event.sidechain_id == sidechain_number)
+        .map(|event| {
+            json!({
+                "m6id": hex::encode(event.m6id),
+                "kind": withdrawal_bundle_event_kind(event.kind),
+            })
+        })
+        .collect();
+    let bmm_commitment = block_info.bmm_commitments.get(&sidechain_number);
+    if deposits.is_empty() && withdrawal_bundle_events.is_empty() && bmm_commitment.is_none() {
+        return None;
+    }
+    Some(json!({
+        "deposits": deposits,
+        "withdrawal_bundle_events": withdrawal_bundle_events,
+        "bmm_commitment": bmm_commitment.map(hex::encode),
+    }))
+}
+
+/// `block_hash` is the block that changed the number of confirmations
+pub(crate) fn deposit_confirmation(
+    block_hash: &bitcoin::BlockHash,
+    confirmation: &DepositConfirmation,
+) -> Value {
+    let deposit = &confirmation.deposit;
+    json!({
+        "block_hash": block_hash.to_string(),
+        "sidechain_number": deposit.sidechain_id.0,
+        "sequence_number": deposit.sequence_number,
+        "outpoint": deposit.outpoint.to_string(),
+        "address": hex::encode(&deposit.address),
+        "value_sats": deposit.value.to_sat(),
+        "deposit_block_hash": confirmation.deposit_block_hash.to_string(),
+        "deposit_block_height": confirmation.deposit_block_height,
+        "confirmations": confirmation.confirmations,
+    })
+}
+
+/// Returns `None` if the block does not contain any two-way peg data for the
+/// sidechain
+pub(crate) fn two_way_peg_data(
+    two_way_peg_data: &TwoWayPegData,
+    sidechain_number: SidechainNumber,
+) -> Option<Value> {
+    let block_info = block_info(&two_way_peg_data.block_info, sidechain_number)?;
+    Some(json!({
+        "header_info": header_info(&two_way_peg_data.header_info),
+        "block_info": block_info,
+    }))
+}
+
+/// Sidechains with deposits, withdrawal bundle events or BMM commitments in
+/// the block
+fn block_info_sidechains(block_info: &BlockInfo) -> BTreeSet<SidechainNumber> {
+    let deposits = block_info
+        .deposits
+        .iter()
+        .map(|deposit| deposit.sidechain_id);
+    let withdrawal_bundle_events = block_info
+        .withdrawal_bundle_events
+        .iter()
+        .map(|event| event.sidechain_id);
+    let bmm_commitments = block_info.bmm_commitments.keys().copied();
+    deposits
+        .chain(withdrawal_bundle_events)
+        .chain(bmm_commitments)
+        .collect()
+}
+
+/// If `sidechains` is `Some`, only data for the specified sidechains is
+/// included. Returns `None` for events that only concern other sidechains.
+/// Block info for connected blocks is keyed by sidechain number, and only
+/// includes sidechains with data in the block.
+pub(crate) fn event(
+    sequence: u64,
+    event: &Event,
+    sidechains: Option<&BTreeSet<SidechainNumber>>,
+) -> Option<Value> {
+    let includes = |sidechain_number: &SidechainNumber| match sidechains {
+        Some(sidechains) => sidechains.contains(sidechain_number),
+        None => true,
+    };
+    let res = match event {
+        Event::ConnectBlock {
+            header_info,
+            block_info,
+        } => {
+            let block_infos: Map<String, Value> = block_info_sidechains(block_info)
+                .into_iter()
+                .filter(includes)
+                .filter_map(|sidechain_number| {
+                    let sidechain_block_info = self::block_info(block_info, sidechain_number)?;
+                    Some((sidechain_number.0.to_string(), sidechain_block_info))
+                })
+                .collect();
+            json!({
+                "sequence": sequence,
+                "event": "connect_block",
+                "header_info": self::header_info(header_info),
+                "block_info": block_infos,
+            })
+        }
+        Event::DisconnectBlock { block_hash } => json!({
+            "sequence": sequence,
+            "event": "disconnect_block",
+            "block_hash": block_hash.to_string(),
+        }),
+        Event::WithdrawalBundleAlert { block_hash, alert } => {
+            if !includes(&alert.sidechain_id) {
+                return None;
+            }
+            json!({
+                "sequence": sequence,
+                "event": "withdrawal_bundle_alert",
+                "block_hash": block_hash.to_string(),
+                "sidechain_number": alert.sidechain_id.0,
+                "m6id": hex::encode(alert.m6id),
+                "previous_vote_count": alert.previous_vote_count,
+                "vote_count": alert.vote_count,
+                "leading": alert.leading,
+            })
+        }
+        Event::DepositConfirmed {
+            block_hash,
+            confirmation,
+        } => {
+            if !includes(&confirmation.deposit.sidechain_id) {
+                return None;
+            }
+            let mut res = deposit_confirmation(block_hash, confirmation);
+            res["sequence"] = json!(sequence);
+            res["event"] = json!("deposit_confirmed");
+            res
+        }
+        Event::DepositUnconfirmed {
+            block_hash,
+            confirmation,
+        } => {
+            if !includes(&confirmation.deposit.sidechain_id) {
+                return None;
+            }
+            let mut res = deposit_confirmation(block_hash, confirmation);
+            res["sequence"] = json!(sequence);
+            res["event"] = json!("deposit_unconfirmed");
+            res
+        }
+    };
+    Some(res)
+}
@@ -0,0 +1,147 @@
+//! JSON-RPC server for the read-only query API, for clients that cannot
+//! easily use gRPC.
+//!
+//! Methods are named in the style of Bitcoin Core's RPC interface, eg.
+//! `getchaintip`. Hashes are encoded in the same byte order as Bitcoin
+//! Core, and amounts are in sats.
+
+use std::{net::SocketAddr, num::NonZeroU32};
+
+use bitcoin::BlockHash;
+use jsonrpsee::{
+    core::RpcResult,
+    proc_macros::rpc,
+    server::{Server, ServerHandle},
+    types::{error::INTERNAL_ERROR_CODE, ErrorObject, ErrorObjectOwned},
+};
+use miette::IntoDiagnostic as _;
+use serde_json::Value;
+
+use crate::{json, types::SidechainNumber, validator::Validator};
+
+fn internal_error(err: miette::Report) -> ErrorObjectOwned {
+    ErrorObject::owned(INTERNAL_ERROR_CODE, format!("{err:#}"), None::<()>)
+}
+
+#[rpc(server)]
+pub trait Rpc {
+    /// Header info for the current mainchain tip
+    #[method(name = "getchaintip")]
+    fn get_chain_tip(&self) -> RpcResult<Value>;
+
+    /// CTIP for an active sidechain, or `null` if the sidechain has no CTIP
+    #[method(name = "getctip")]
+    fn get_ctip(&self, sidechain_number: u8) -> RpcResult<Option<Value>>;
+
+    /// Active sidechains
+    #[method(name = "getsidechains")]
+    fn get_sidechains(&self) -> RpcResult<Vec<Value>>;
+
+    /// Sidechain proposals, including activated sidechains
+    #[method(name = "getsidechainproposals")]
+    fn get_sidechain_proposals(&self) -> RpcResult<Vec<Value>>;
+
+    /// Two-way peg data for a sidechain, for blocks after
+    /// `start_block_hash` up to and including `end_block_hash`. Blocks
+    /// without two-way peg data for the sidechain are omitted.
+    /// If `min_confirmations` is set, blocks with fewer confirmations are
+    /// omitted.
+    #[method(name = "gettwowaypegdata")]
+    fn get_two_way_peg_data(
+        &self,
+        sidechain_number: u8,
+        end_block_hash: BlockHash,
+        start_block_hash: Option<BlockHash>,
+        min_confirmations: Option<NonZeroU32>,
+    ) -> RpcResult<Vec<Value>>;
+}
+
+#[derive(Clone)]
+pub struct JsonRpcServer {
+    validator: Validator,
+}
+
+impl JsonRpcServer {
+    pub fn new(validator: Validator) -> Self {
+        Self { validator }
+    }
+
+    /// Start serving on the specified address. The server runs until the
+    /// returned handle is stopped or dropped.
+    pub async fn serve(self, addr: SocketAddr) -> Result<ServerHandle, std::io::Error> {
+        let server = Server::builder().build(addr).await?;
+        tracing::info!("Serving JSON-RPC at {}", server.local_addr()?);
+        Ok(server.start(self.into_rpc()))
+    }
+}
+
+impl RpcServer for JsonRpcServer {
+    fn get_chain_tip(&self) -> RpcResult<Value> {
+        let tip = self.validator.get_mainchain_tip().map_err(internal_error)?;
+        let header_info = self
+            .validator
+            .get_header_info(&tip)
+            .into_diagnostic()
+            .map_err(internal_error)?;
+        Ok(json::header_info(&header_info))
+    }
+
+    fn get_ctip(&self, sidechain_number: u8) -> RpcResult<Option<Value>> {
+        let sidechain_number = SidechainNumber::from(sidechain_number);
+        let Some(ctip) = self
+            .validator
+            .try_get_ctip(sidechain_number)
+            .map_err(internal_error)?
+        else {
+            return Ok(None);
+        };
+        let sequence_number = self
+            .validator
+            .get_ctip_sequence_number(sidechain_number)
+            .map_err(internal_error)?
+            .ok_or_else(|| internal_error(miette::miette!("missing CTIP sequence number")))?;
+        Ok(Some(json::ctip(&ctip, sequence_number)))
+    }
+
+    fn get_sidechains(&self) -> RpcResult<Vec<Value>> {
+        let sidechains = self
+            .validator
+            .get_active_sidechains()
+            .map_err(internal_error)?;
+        Ok(sidechains.iter().map(json::sidechain).collect())
+    }
+
+    fn get_sidechain_proposals(&self) -> RpcResult<Vec<Value>> {
+        let proposals = self
+            .validator
+            .get_sidechain_proposals()
+            .map_err(internal_error)?;
+        Ok(proposals
+            .iter()
+            .map(|(description_hash, sidechain, status)| {
+                json::sidechain_proposal(description_hash, sidechain, status)
+            })
+            .collect())
+    }
+
+    fn get_two_way_peg_data(
+        &self,
+        sidechain_number: u8,
+        end_block_hash: BlockHash,
+        start_block_hash: Option<BlockHash>,
+        min_confirmations: Option<NonZeroU32>,
+    ) -> RpcResult<Vec<Value>> {
+        let sidechain_number = SidechainNumber::from(sidechain_number);
+        let two_way_peg_data = self
+            .validator
+            .get_two_way_peg_data(start_block_hash, end_block_hash, min_confirmations)
+            .into_diagnostic()
+            .map_err(internal_error)?;
+        Ok(two_way_peg_data
+            .iter()
+            .filter_map(|two_way_peg_data| {
+                json::two_way_peg_data(two_way_peg_data, sidechain_number)
+            })
+            .collect())
+    }
+}
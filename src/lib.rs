@@ -0,0 +1,35 @@
+//! BIP300/301 enforcer.
+//!
+//! The enforcer can be run as a standalone daemon, or embedded in-process,
+//! eg. by sidechain node implementations. To embed the enforcer, create a
+//! node client with [`rpc_client::create_client`], and then a
+//! [`Validator`], which syncs with the node in the background. The
+//! validator can be queried directly, or served via gRPC with the services
+//! in [`server`].
+
+pub mod auth;
+pub mod cli;
+pub mod client;
+mod convert;
+pub mod deposit_address;
+pub mod enforcer_client;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+mod json;
+pub mod json_rpc;
+pub mod messages;
+pub mod p2p;
+pub mod proto;
+pub mod rate_limit;
+pub mod rest;
+pub mod rpc_client;
+pub mod server;
+pub mod types;
+pub mod validator;
+pub mod wallet;
+pub mod webhook;
+pub mod zmq;
+
+pub use validator::Validator;
@@ -1,32 +1,46 @@
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    io::Write as _,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use bip300301::MainClient;
 use clap::Parser;
 use futures::{future::TryFutureExt, FutureExt, StreamExt};
 use miette::{miette, IntoDiagnostic, Result};
 use tokio::{spawn, task::JoinHandle, time::interval};
-use tonic::{server::NamedService, transport::Server};
+use tonic::{
+    server::NamedService,
+    transport::{Certificate, Identity, Server, ServerTlsConfig},
+};
 use tower::ServiceBuilder;
 use tower_http::trace::{DefaultOnFailure, DefaultOnResponse, TraceLayer};
 use tracing_subscriber::{filter as tracing_filter, layer::SubscriberExt};
 
-mod cli;
-mod convert;
-mod messages;
-mod proto;
-mod rpc_client;
-mod server;
-mod types;
-mod validator;
-mod wallet;
-mod zmq;
-
-use proto::{
-    crypto::crypto_service_server::CryptoServiceServer,
-    mainchain::{wallet_service_server::WalletServiceServer, Server as ValidatorServiceServer},
+#[cfg(feature = "graphql")]
+use bip300301_enforcer::graphql;
+use bip300301_enforcer::{
+    auth::{self, Authenticator},
+    cli, client,
+    json_rpc::JsonRpcServer,
+    proto::{
+        self,
+        crypto::crypto_service_server::CryptoServiceServer,
+        mainchain::{wallet_service_server::WalletServiceServer, Server as ValidatorServiceServer},
+    },
+    rate_limit::{RateLimitConfig, RateLimitLayer},
+    rest, rpc_client, server,
+    validator::{fixtures, maintenance, DepositConfirmationDepths, Validator},
+    wallet::{self, Wallet},
+    webhook, zmq,
 };
-use validator::Validator;
-use wallet::Wallet;
+
+/// Max time to wait for other handles to the validator DBs to be dropped
+/// on shutdown
+const DB_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Saturating predecessor of a log level
 fn saturating_pred_level(log_level: tracing::Level) -> tracing::Level {
@@ -59,7 +73,10 @@ where
 }
 
 // Configure logger.
-fn set_tracing_subscriber(log_level: tracing::Level) -> miette::Result<()> {
+fn set_tracing_subscriber(
+    log_level: tracing::Level,
+    log_format: cli::LogFormat,
+) -> miette::Result<()> {
     let targets_filter = {
         let default_directives_str = targets_directive_str([
             ("", saturating_pred_level(log_level)),
@@ -76,13 +93,29 @@ fn set_tracing_subscriber(log_level: tracing::Level) -> miette::Result<()> {
             .parse(directives_str)
             .into_diagnostic()?
     };
-    let stdout_layer = tracing_subscriber::fmt::layer()
-        .compact()
-        .with_file(true)
-        .with_line_number(true);
+    let (compact_layer, json_layer) = match log_format {
+        cli::LogFormat::Compact => {
+            let compact_layer = tracing_subscriber::fmt::layer()
+                .compact()
+                .with_file(true)
+                .with_line_number(true);
+            (Some(compact_layer), None)
+        }
+        cli::LogFormat::Json => {
+            let json_layer = tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_file(true)
+                .with_line_number(true);
+            (None, Some(json_layer))
+        }
+    };
     let tracing_subscriber = tracing_subscriber::registry()
         .with(targets_filter)
-        .with(stdout_layer);
+        .with(compact_layer)
+        .with(json_layer);
     tracing::subscriber::set_global_default(tracing_subscriber)
         .into_diagnostic()
         .map_err(|err| miette::miette!("setting default subscriber failed: {err:#}"))
@@ -93,10 +126,22 @@ async fn wallet_task(wallet: Arc<wallet::Wallet>) -> Result<(), miette::Report>
     const SYNC_INTERVAL: Duration = Duration::from_secs(15);
     let mut interval_stream = tokio_stream::wrappers::IntervalStream::new(interval(SYNC_INTERVAL));
     while let Some(_tick) = interval_stream.next().await {
-        match wallet.sync() {
+        match wallet.sync().await {
             Ok(_) => (),
             Err(err) => tracing::error!("wallet sync error: {err:#}"),
         }
+        match wallet.bump_bmm_requests().await {
+            Ok(()) => (),
+            Err(err) => tracing::error!("BMM request fee bump error: {err:#}"),
+        }
+        match wallet.rebid_bmm_requests().await {
+            Ok(()) => (),
+            Err(err) => tracing::error!("BMM request re-bid error: {err:#}"),
+        }
+        match wallet.update_bmm_request_history().await {
+            Ok(()) => (),
+            Err(err) => tracing::error!("BMM request history update error: {err:#}"),
+        }
     }
     Ok(())
 }
@@ -104,7 +149,13 @@ async fn wallet_task(wallet: Arc<wallet::Wallet>) -> Result<(), miette::Report>
 async fn run_server(
     validator: Validator,
     wallet: Option<Arc<Wallet>>,
+    capabilities: server::Capabilities,
     addr: SocketAddr,
+    tls_config: Option<ServerTlsConfig>,
+    auth: Authenticator,
+    allow_unauthenticated_reads: bool,
+    rate_limit: RateLimitConfig,
+    shutdown: impl Future<Output = ()>,
 ) -> Result<()> {
     let tracer = ServiceBuilder::new()
         .layer(
@@ -116,97 +167,289 @@ async fn run_server(
         )
         .into_inner();
 
-    let crypto_service = CryptoServiceServer::new(server::CryptoServiceServer);
-    let validator_service = ValidatorServiceServer::new(validator);
+    let read_auth = if allow_unauthenticated_reads {
+        auth.optional()
+    } else {
+        auth.clone()
+    };
+    let crypto_service =
+        CryptoServiceServer::with_interceptor(server::CryptoServiceServer, read_auth.clone());
+    let validator_service = ValidatorServiceServer::with_interceptor(
+        server::ValidatorServer::new(validator, capabilities, auth.clone()),
+        read_auth,
+    );
 
-    let mut builder = Server::builder()
+    let mut builder = Server::builder();
+    let tls_enabled = tls_config.is_some();
+    if let Some(tls_config) = tls_config {
+        builder = builder.tls_config(tls_config).into_diagnostic()?;
+    }
+    let mut builder = builder
         .layer(tracer)
+        .layer(RateLimitLayer::new(rate_limit))
         .add_service(crypto_service)
         .add_service(validator_service);
 
     let mut reflection_service_builder = tonic_reflection::server::Builder::configure()
         .with_service_name(CryptoServiceServer::<server::CryptoServiceServer>::NAME)
-        .with_service_name(ValidatorServiceServer::<Validator>::NAME)
+        .with_service_name(ValidatorServiceServer::<server::ValidatorServer>::NAME)
         .register_encoded_file_descriptor_set(proto::ENCODED_FILE_DESCRIPTOR_SET);
 
+    let mut sync_wallet: Option<JoinHandle<()>> = None;
     if let Some(wallet) = wallet {
         tracing::info!("gRPC: enabling wallet service");
 
-        let wallet_service = WalletServiceServer::new(Arc::clone(&wallet));
+        let wallet_service = WalletServiceServer::with_interceptor(Arc::clone(&wallet), auth);
         builder = builder.add_service(wallet_service);
         reflection_service_builder =
             reflection_service_builder.with_service_name(WalletServiceServer::<Wallet>::NAME);
 
-        let _sync_wallet: JoinHandle<()> = {
+        sync_wallet = {
             let wallet = Arc::clone(&wallet);
-            spawn(wallet_task(wallet).unwrap_or_else(|err| tracing::error!("{err:#}")))
+            Some(spawn(
+                wallet_task(wallet).unwrap_or_else(|err| tracing::error!("{err:#}")),
+            ))
         };
     }
 
-    tracing::info!("Listening for gRPC on {addr} with reflection");
+    if tls_enabled {
+        tracing::info!("Listening for gRPC over TLS on {addr} with reflection");
+    } else {
+        tracing::info!("Listening for gRPC on {addr} with reflection");
+    }
 
-    builder
+    let () = builder
         .add_service(reflection_service_builder.build_v1().into_diagnostic()?)
-        .serve(addr)
+        .serve_with_shutdown(addr, shutdown)
         .map_err(|err| miette!("error in validator server: {err:#}"))
-        .await
+        .await?;
+    tracing::info!("Stopped gRPC server");
+    // The wallet task runs until aborted, and holds a handle to the
+    // validator DBs
+    if let Some(sync_wallet) = sync_wallet {
+        sync_wallet.abort();
+        let _: Result<(), tokio::task::JoinError> = sync_wallet.await;
+    }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = cli::Config::parse();
-    set_tracing_subscriber(cli.log_level)?;
-
-    tracing::info!(
-        "starting up bip300301_enforcer with data directory {}",
-        cli.data_dir.display()
-    );
+/// Completes on SIGINT (ctrl-c), or on SIGTERM on unix, or when the
+/// console is closed or the system shuts down on Windows
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for SIGINT: {err:#}");
+            std::future::pending::<()>().await
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                let _: Option<()> = sigterm.recv().await;
+            }
+            Err(err) => {
+                tracing::warn!("Failed to listen for SIGTERM: {err:#}");
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(windows)]
+    let terminate = async {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        match (ctrl_close(), ctrl_shutdown()) {
+            (Ok(mut ctrl_close), Ok(mut ctrl_shutdown)) => {
+                let _: Option<()> = tokio::select! {
+                    res = ctrl_close.recv() => res,
+                    res = ctrl_shutdown.recv() => res,
+                };
+            }
+            (Err(err), _) | (_, Err(err)) => {
+                tracing::warn!("Failed to listen for console close events: {err:#}");
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(not(any(unix, windows)))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => tracing::info!("Received SIGINT, shutting down"),
+        () = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
 
-    let mainchain_client = rpc_client::create_client(&cli.node_rpc_opts)?;
+async fn serve(
+    data_dir: &Path,
+    network: bitcoin::Network,
+    mut serve_config: cli::ServeConfig,
+) -> Result<()> {
+    // Set if the sync task stops due to an unrecoverable error. The enforcer
+    // then shuts down gracefully, and exits with an error.
+    let (sync_err_tx, sync_err_rx) = futures::channel::oneshot::channel::<String>();
+    let sync_err = sync_err_rx.map(Result::ok).shared();
+    let shutdown = {
+        let sync_err = sync_err.clone();
+        async move {
+            tokio::select! {
+                () = shutdown_signal() => (),
+                Some(err) = sync_err => {
+                    tracing::error!("Sync task stopped, shutting down: {err}");
+                }
+            }
+        }
+    }
+    .shared();
+    // Poll the shutdown signal immediately, so that signals received during
+    // startup also trigger a graceful shutdown
+    let _listen_for_shutdown: JoinHandle<()> = spawn(shutdown.clone());
+    let tls_config = rpc_tls_config(&serve_config)?;
+    let capabilities = server::Capabilities {
+        wallet: serve_config.enable_wallet,
+        json_rpc: serve_config.serve_json_rpc_addr.is_some(),
+        rest_gateway: serve_config.serve_rest_addr.is_some(),
+        #[cfg(feature = "graphql")]
+        graphql: serve_config.serve_graphql_addr.is_some(),
+        #[cfg(not(feature = "graphql"))]
+        graphql: false,
+        zmq_events: serve_config.serve_zmq_addr.is_some(),
+    };
+    let rpc_auth = rpc_authenticator(&serve_config)?;
+    let backup_rpc_addrs: Vec<_> = serve_config
+        .backup_nodes
+        .iter()
+        .map(|backup_node| backup_node.rpc_addr)
+        .collect();
+    let mainchain_client =
+        rpc_client::create_client(&serve_config.node_rpc_opts, &backup_rpc_addrs, network)?;
 
     tracing::info!(
         "Created mainchain client from options: {}:{}@{}",
-        cli.node_rpc_opts.user.as_deref().unwrap_or("cookie"),
-        cli.node_rpc_opts
+        serve_config
+            .node_rpc_opts
+            .user
+            .as_deref()
+            .unwrap_or("cookie"),
+        serve_config
+            .node_rpc_opts
             .pass
             .as_deref()
             .map(|_| "*****")
             .unwrap_or("cookie"),
-        cli.node_rpc_opts.addr,
+        serve_config.node_rpc_opts.rpc_addr(network),
     );
 
     let info = mainchain_client
         .get_blockchain_info()
         .await
         .into_diagnostic()?;
+    if info.chain != network {
+        return Err(miette!(
+            "Network mismatch: enforcer is configured for `{network}`, but node is on `{}`",
+            info.chain
+        ));
+    }
+    let node_info = mainchain_client.get_node_info().await.into_diagnostic()?;
+    tracing::info!("Connected to node: {node_info}");
+    let node_zmq_addr_sequence = check_node_info(&node_info, network, &mut serve_config)?;
 
     // Both wallet data and validator data are stored under the same root
     // directory. Add a subdirectories to clearly indicate which
     // is which.
-    let validator_data_dir = cli.data_dir.join("validator").join(info.chain.to_string());
-    let wallet_data_dir = cli.data_dir.join("wallet").join(info.chain.to_string());
+    let validator_data_dir = validator_data_dir(data_dir, network);
+    let wallet_data_dir = data_dir.join("wallet").join(network.to_string());
 
     // Ensure that the data directories exists
     for data_dir in [validator_data_dir.clone(), wallet_data_dir.clone()] {
         std::fs::create_dir_all(data_dir).into_diagnostic()?;
     }
 
-    let (err_tx, err_rx) = futures::channel::oneshot::channel();
+    let _health_checks: Option<JoinHandle<()>> = if backup_rpc_addrs.is_empty() {
+        None
+    } else {
+        Some(spawn(mainchain_client.clone().run_health_checks()))
+    };
+
+    let _mempool_sync: Option<JoinHandle<()>> = serve_config
+        .node_zmq_addr_rawtx
+        .map(|zmq_addr_rawtx| spawn(mainchain_client.clone().run_mempool_sync(zmq_addr_rawtx)));
+
     let validator = Validator::new(
         mainchain_client.clone(),
-        cli.node_zmq_addr_sequence,
+        std::iter::once(node_zmq_addr_sequence)
+            .chain(
+                serve_config
+                    .backup_nodes
+                    .into_iter()
+                    .map(|backup_node| backup_node.zmq_addr_sequence),
+            )
+            .collect(),
         &validator_data_dir,
-        |err| async {
-            let _send_err: Result<(), _> = err_tx.send(err);
+        serve_config.prune_block_info_depth,
+        DepositConfirmationDepths::new(serve_config.deposit_confirmations),
+        serve_config.record_fixtures_dir,
+        |err| async move {
+            let _send_err: Result<(), _> = sync_err_tx.send(format!("{err:#}"));
         },
     )
     .await
     .into_diagnostic()?;
 
-    let wallet: Option<Arc<wallet::Wallet>> = if cli.enable_wallet {
+    let publish_events: Option<JoinHandle<()>> = match serve_config.serve_zmq_addr {
+        Some(zmq_addr) => {
+            let publisher = zmq::EventPublisher::bind(&zmq_addr)
+                .await
+                .into_diagnostic()?;
+            Some(spawn(publisher.run(validator.subscribe_events(None))))
+        }
+        None => None,
+    };
+
+    let deposit_webhook: Option<JoinHandle<()>> = match serve_config.deposit_webhook_url {
+        Some(url) => {
+            let webhook = webhook::DepositWebhook::new(url).into_diagnostic()?;
+            Some(spawn(webhook.run(validator.subscribe_events(None))))
+        }
+        None => None,
+    };
+
+    let json_rpc_server: Option<jsonrpsee::server::ServerHandle> =
+        match serve_config.serve_json_rpc_addr {
+            Some(json_rpc_addr) => Some(
+                JsonRpcServer::new(validator.clone())
+                    .serve(json_rpc_addr)
+                    .await
+                    .into_diagnostic()?,
+            ),
+            None => None,
+        };
+
+    let rest_gateway: Option<JoinHandle<()>> = serve_config.serve_rest_addr.map(|rest_addr| {
+        spawn(
+            rest::serve(validator.clone(), rest_addr, shutdown.clone()).unwrap_or_else(|err| {
+                tracing::error!("error in REST gateway: {err:#}");
+            }),
+        )
+    });
+
+    #[cfg(feature = "graphql")]
+    let graphql_endpoint: Option<JoinHandle<()>> =
+        serve_config.serve_graphql_addr.map(|graphql_addr| {
+            spawn(
+                graphql::serve(validator.clone(), graphql_addr, shutdown.clone()).unwrap_or_else(
+                    |err| {
+                        tracing::error!("error in GraphQL endpoint: {err:#}");
+                    },
+                ),
+            )
+        });
+    #[cfg(not(feature = "graphql"))]
+    let graphql_endpoint: Option<JoinHandle<()>> = None;
+
+    let wallet: Option<Arc<wallet::Wallet>> = if serve_config.enable_wallet {
         let wallet = Wallet::new(
             &wallet_data_dir,
-            &cli.wallet_opts,
+            &serve_config.wallet_opts,
             mainchain_client,
             validator.clone(),
         )
@@ -216,13 +459,284 @@ async fn main() -> Result<()> {
         None
     };
 
-    let _handle_validator_errors: JoinHandle<()> = spawn({
-        err_rx.map(|err| {
-            if let Ok(err) = err {
-                tracing::error!("{err:#}");
-            }
-        })
+    // Stopping the sync task ends event streams, so that the servers can
+    // finish in-flight requests and stop
+    let shutdown_validator: JoinHandle<Result<()>> = spawn({
+        let validator = validator.clone();
+        shutdown
+            .clone()
+            .then(|()| async move { validator.shutdown().await.into_diagnostic() })
     });
 
-    run_server(validator, wallet, cli.serve_rpc_addr).await
+    let () = run_server(
+        validator.clone(),
+        wallet,
+        capabilities,
+        serve_config.serve_rpc_addr,
+        tls_config,
+        rpc_auth,
+        serve_config.rpc_allow_unauthenticated_reads,
+        RateLimitConfig {
+            max_requests_per_sec: serve_config.rpc_max_requests_per_sec,
+            max_concurrent_requests: serve_config.rpc_max_concurrent_requests,
+        },
+        shutdown,
+    )
+    .await?;
+
+    let () = shutdown_validator.await.into_diagnostic()??;
+    if let Some(json_rpc_server) = json_rpc_server {
+        let _: Result<(), _> = json_rpc_server.stop();
+        let () = json_rpc_server.stopped().await;
+    }
+    for task in [
+        rest_gateway,
+        graphql_endpoint,
+        publish_events,
+        deposit_webhook,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        let () = task.await.into_diagnostic()?;
+    }
+    if validator.close(DB_CLOSE_TIMEOUT).await.into_diagnostic()? {
+        tracing::info!("Closed validator DBs");
+    } else {
+        tracing::warn!("Timed out waiting to close validator DBs");
+    }
+    // The sync task has stopped, so this completes immediately
+    match sync_err.await {
+        Some(err) => Err(miette!("Sync task failed: {err}")),
+        None => Ok(()),
+    }
+}
+
+/// Read the TLS certificate and key for the gRPC server, if configured
+fn rpc_tls_config(serve_config: &cli::ServeConfig) -> Result<Option<ServerTlsConfig>> {
+    let (Some(cert_path), Some(key_path)) = (&serve_config.rpc_tls_cert, &serve_config.rpc_tls_key)
+    else {
+        return Ok(None);
+    };
+    let cert = std::fs::read(cert_path)
+        .map_err(|err| miette!("failed to read TLS cert `{}`: {err}", cert_path.display()))?;
+    let key = std::fs::read(key_path)
+        .map_err(|err| miette!("failed to read TLS key `{}`: {err}", key_path.display()))?;
+    let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+    if let Some(client_ca_path) = &serve_config.rpc_tls_client_ca {
+        let client_ca = std::fs::read(client_ca_path).map_err(|err| {
+            miette!(
+                "failed to read TLS client CA `{}`: {err}",
+                client_ca_path.display()
+            )
+        })?;
+        // Clients without certs are authenticated with bearer tokens, or
+        // rejected by the auth interceptor
+        tls_config = tls_config
+            .client_ca_root(Certificate::from_pem(client_ca))
+            .client_auth_optional(true);
+    }
+    Ok(Some(tls_config))
+}
+
+/// Authenticator for the gRPC server. Auth is disabled if neither client
+/// certificates nor bearer tokens are configured.
+fn rpc_authenticator(serve_config: &cli::ServeConfig) -> Result<Authenticator> {
+    let client_certs = serve_config.rpc_tls_client_ca.is_some();
+    let tokens = match &serve_config.rpc_auth_tokens_file {
+        Some(path) => auth::load_tokens(path).into_diagnostic()?,
+        None => Vec::new(),
+    };
+    if !client_certs && tokens.is_empty() {
+        return Ok(Authenticator::disabled());
+    }
+    if !tokens.is_empty() && serve_config.rpc_tls_cert.is_none() {
+        tracing::warn!("gRPC bearer tokens are sent in plaintext, since TLS is not enabled");
+    }
+    Ok(Authenticator::new(tokens, client_certs))
+}
+
+/// Port of a ZMQ TCP endpoint, eg. `29000` for `tcp://0.0.0.0:29000`
+fn zmq_port(addr: &str) -> Option<&str> {
+    addr.rsplit_once(':').map(|(_, port)| port)
+}
+
+/// Refuse to start if the node lacks features that the enforcer requires,
+/// and disable optional features that the node does not support.
+/// Returns the ZMQ `sequence` endpoint to connect to.
+fn check_node_info(
+    node_info: &rpc_client::NodeInfo,
+    network: bitcoin::Network,
+    serve_config: &mut cli::ServeConfig,
+) -> Result<String> {
+    if node_info.version < rpc_client::NODE_MIN_VERSION {
+        return Err(miette!(
+            "Node version {} is not supported, Bitcoin Core v0.21 or later is required",
+            node_info.version,
+        ));
+    }
+    let zmq_addr_sequence = match &serve_config.node_zmq_addr_sequence {
+        Some(zmq_addr_sequence) => zmq_addr_sequence.clone(),
+        None => {
+            if node_info.zmq_notifications.is_empty() {
+                return Err(miette!(
+                    "Node has no ZMQ notifications configured, set `zmqpubsequence` in the node config, eg. `zmqpubsequence=tcp://0.0.0.0:29000`"
+                ));
+            }
+            let zmq_addr_sequence = node_info
+                .zmq_connect_address(
+                    rpc_client::ZMQ_PUB_SEQUENCE,
+                    serve_config.node_rpc_opts.rpc_addr(network).ip(),
+                )
+                .ok_or_else(|| {
+                    miette!(
+                        "Node does not publish ZMQ `sequence` notifications, set `zmqpubsequence` in the node config"
+                    )
+                })?;
+            tracing::info!("Using ZMQ `sequence` endpoint from node: `{zmq_addr_sequence}`");
+            zmq_addr_sequence
+        }
+    };
+    let Some(node_zmq_addr_sequence) = node_info.zmq_address(rpc_client::ZMQ_PUB_SEQUENCE) else {
+        return Err(miette!(
+            "Node does not publish ZMQ `sequence` notifications, set `zmqpubsequence={zmq_addr_sequence}` in the node config"
+        ));
+    };
+    // The node may bind to a different host than the enforcer connects to,
+    // so only the ports are compared
+    if zmq_port(node_zmq_addr_sequence) != zmq_port(&zmq_addr_sequence) {
+        tracing::warn!(
+            "Node publishes ZMQ `sequence` notifications on `{node_zmq_addr_sequence}`, but the enforcer is configured to connect to `{zmq_addr_sequence}`"
+        );
+    }
+    if serve_config.node_zmq_addr_rawtx.is_some()
+        && node_info.zmq_address(rpc_client::ZMQ_PUB_RAWTX).is_none()
+    {
+        tracing::warn!(
+            "Node does not publish ZMQ `rawtx` notifications, mempool transactions will not be tracked. Set `zmqpubrawtx` in the node config to enable."
+        );
+        serve_config.node_zmq_addr_rawtx = None;
+    }
+    if !node_info.txindex {
+        tracing::warn!(
+            "Node is running without `txindex`, confirmed transactions can only be looked up by block hash"
+        );
+    }
+    if !node_info.block_filter_index {
+        tracing::info!(
+            "Node is running without `blockfilterindex`, the initial sync will download every block"
+        );
+    }
+    Ok(zmq_addr_sequence)
+}
+
+/// Validator data is stored in a subdirectory of the data directory
+fn validator_data_dir(data_dir: &Path, network: bitcoin::Network) -> PathBuf {
+    data_dir.join("validator").join(network.to_string())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = cli::Config::parse();
+    set_tracing_subscriber(cli.log_level, cli.log_format)?;
+
+    tracing::info!(
+        "starting up bip300301_enforcer with data directory {}",
+        cli.data_dir.display()
+    );
+
+    let network = bitcoin::Network::from(cli.network);
+    let validator_data_dir = validator_data_dir(&cli.data_dir, network);
+    match cli.command {
+        cli::Command::Serve(serve_config) => serve(&cli.data_dir, network, serve_config).await,
+        cli::Command::Reindex => {
+            if maintenance::reindex(&validator_data_dir, network).into_diagnostic()? {
+                tracing::info!("Validator DBs will be rebuilt on next startup");
+            } else {
+                tracing::info!("No validator DBs to reindex");
+            }
+            Ok(())
+        }
+        cli::Command::Snapshot(cli::SnapshotCommand::Export { path }) => {
+            maintenance::export_snapshot(&validator_data_dir, network, &path).into_diagnostic()
+        }
+        cli::Command::Snapshot(cli::SnapshotCommand::Import { path }) => {
+            maintenance::import_snapshot(&validator_data_dir, network, &path).into_diagnostic()
+        }
+        cli::Command::Dump { recent_blocks } => {
+            let dump =
+                maintenance::dump(&validator_data_dir, network, recent_blocks).into_diagnostic()?;
+            let mut stdout = std::io::stdout().lock();
+            serde_json::to_writer_pretty(&mut stdout, &dump).into_diagnostic()?;
+            writeln!(stdout).into_diagnostic()
+        }
+        cli::Command::ExportActivity {
+            start_height,
+            end_height,
+            format,
+        } => {
+            let activity = maintenance::export_activity(
+                &validator_data_dir,
+                network,
+                start_height,
+                end_height,
+            )
+            .into_diagnostic()?;
+            let mut stdout = std::io::stdout().lock();
+            match format {
+                cli::ExportFormat::Csv => {
+                    writeln!(stdout, "{}", maintenance::SidechainActivity::CSV_HEADER)
+                        .into_diagnostic()?;
+                    for row in &activity {
+                        writeln!(stdout, "{}", row.to_csv_row()).into_diagnostic()?;
+                    }
+                }
+                cli::ExportFormat::JsonLines => {
+                    for row in &activity {
+                        serde_json::to_writer(&mut stdout, row).into_diagnostic()?;
+                        writeln!(stdout).into_diagnostic()?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        cli::Command::Fixtures(cli::FixturesCommand::Replay { paths }) => {
+            let mut failed = 0;
+            let mut stdout = std::io::stdout().lock();
+            for path in &paths {
+                let fixture = fixtures::load(path).into_diagnostic()?;
+                let problems = fixtures::replay(&fixture).into_diagnostic()?;
+                for problem in &problems {
+                    writeln!(stdout, "{}: {problem}", path.display()).into_diagnostic()?;
+                }
+                if !problems.is_empty() {
+                    failed += 1;
+                }
+            }
+            if failed == 0 {
+                tracing::info!("Replayed {} fixture(s)", paths.len());
+                Ok(())
+            } else {
+                Err(miette!("{failed} of {} fixture(s) failed", paths.len()))
+            }
+        }
+        cli::Command::Client { config, command } => client::run(config, command).await,
+        cli::Command::Db(cli::DbCommand::Check) => {
+            let problems =
+                maintenance::check_dbs(&validator_data_dir, network).into_diagnostic()?;
+            let mut stdout = std::io::stdout().lock();
+            for problem in &problems {
+                writeln!(stdout, "{problem}").into_diagnostic()?;
+            }
+            if problems.is_empty() {
+                tracing::info!("No inconsistencies found in validator DBs");
+                Ok(())
+            } else {
+                Err(miette!(
+                    "Found {} inconsistencies in validator DBs",
+                    problems.len()
+                ))
+            }
+        }
+    }
 }
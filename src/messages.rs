@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use bitcoin::script::{Instruction, Instructions};
 use bitcoin::{
     hashes::{sha256d, Hash},
@@ -5,8 +7,9 @@ use bitcoin::{
         all::{OP_NOP5, OP_PUSHBYTES_1, OP_RETURN},
         OP_TRUE,
     },
+    psbt::Psbt,
     script::PushBytesBuf,
-    Amount, Opcode, Script, ScriptBuf, Transaction, TxOut,
+    Amount, Opcode, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
 };
 use byteorder::{ByteOrder, LittleEndian};
 use nom::{
@@ -16,13 +19,15 @@ use nom::{
     multi::many0,
     IResult,
 };
+use thiserror::Error;
 
 use crate::types::{
-    SidechainDeclaration, SidechainDescription, SidechainNumber, SidechainProposal,
+    PendingM6id, SidechainDeclaration, SidechainDescription, SidechainNumber, SidechainProposal,
 };
 
 pub const OP_DRIVECHAIN: Opcode = OP_NOP5;
 
+#[derive(Default)]
 pub struct CoinbaseBuilder {
     messages: Vec<CoinbaseMessage>,
 }
@@ -117,7 +122,7 @@ pub enum CoinbaseMessage {
     },
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct M8BmmRequest {
     pub sidechain_number: SidechainNumber,
     // Also called H* or critical hash, critical data hash, hash critical
@@ -138,7 +143,7 @@ pub const ABSTAIN_TWO_BYTES: u16 = 0xFFFF;
 pub const ALARM_ONE_BYTE: u8 = 0xFE;
 pub const ALARM_TWO_BYTES: u16 = 0xFFFE;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum M4AckBundles {
     RepeatPrevious,
     OneByte { upvotes: Vec<u8> },
@@ -168,6 +173,90 @@ impl M4AckBundles {
             Self::LeadingBy50 { .. } => LEADING_BY_50_TAG,
         }
     }
+
+    /// Most compact encoding of two byte upvotes, one per sidechain slot.
+    /// `previous_upvotes` are the upvotes of the previous M4 message, and
+    /// `leading_by_50_upvotes` the upvotes implied by
+    /// [`M4AckBundles::LeadingBy50`]. Slots without a vote abstain.
+    /// Returns `None` if all slots abstain, since no M4 message is needed.
+    pub fn compact(
+        upvotes: &[u16],
+        previous_upvotes: &[u16],
+        leading_by_50_upvotes: &[u16],
+    ) -> Option<Self> {
+        let upvotes = trim_abstains(upvotes);
+        if upvotes.is_empty() {
+            return None;
+        }
+        if upvotes == trim_abstains(previous_upvotes) {
+            return Some(Self::RepeatPrevious);
+        }
+        if upvotes == trim_abstains(leading_by_50_upvotes) {
+            return Some(Self::LeadingBy50);
+        }
+        let one_byte_upvotes: Option<Vec<u8>> = upvotes
+            .iter()
+            .map(|upvote| match *upvote {
+                ABSTAIN_TWO_BYTES => Some(ABSTAIN_ONE_BYTE),
+                ALARM_TWO_BYTES => Some(ALARM_ONE_BYTE),
+                upvote => u8::try_from(upvote)
+                    .ok()
+                    .filter(|upvote| *upvote < ALARM_ONE_BYTE),
+            })
+            .collect();
+        match one_byte_upvotes {
+            Some(upvotes) => Some(Self::OneByte { upvotes }),
+            None => Some(Self::TwoBytes {
+                upvotes: upvotes.to_vec(),
+            }),
+        }
+    }
+}
+
+/// Strip trailing abstain votes
+fn trim_abstains(upvotes: &[u16]) -> &[u16] {
+    let len = upvotes
+        .iter()
+        .rposition(|upvote| *upvote != ABSTAIN_TWO_BYTES)
+        .map_or(0, |index| index + 1);
+    &upvotes[..len]
+}
+
+/// Minimum lead that a pending bundle must have over all other pending bundles
+/// of the same sidechain, to be upvoted by [`M4AckBundles::LeadingBy50`]
+pub const LEADING_BY_50_THRESHOLD: u16 = 50;
+
+/// Two byte upvotes implied by [`M4AckBundles::LeadingBy50`], one per sidechain
+/// slot. For each sidechain, the pending bundle that leads all other pending
+/// bundles by at least [`LEADING_BY_50_THRESHOLD`] votes is upvoted.
+/// Sidechains without such a bundle abstain.
+pub fn leading_by_50_upvotes(
+    pending_m6ids: &BTreeMap<SidechainNumber, Vec<PendingM6id>>,
+) -> Vec<u16> {
+    let Some(max_sidechain_number) = pending_m6ids.keys().next_back() else {
+        return Vec::new();
+    };
+    let mut upvotes = vec![ABSTAIN_TWO_BYTES; max_sidechain_number.0 as usize + 1];
+    for (sidechain_number, pending_m6ids) in pending_m6ids {
+        let Some((leader_index, leader)) = pending_m6ids
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, pending_m6id)| pending_m6id.vote_count)
+        else {
+            continue;
+        };
+        let runner_up_vote_count = pending_m6ids
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != leader_index)
+            .map(|(_, pending_m6id)| pending_m6id.vote_count)
+            .max()
+            .unwrap_or(0);
+        if leader.vote_count >= runner_up_vote_count.saturating_add(LEADING_BY_50_THRESHOLD) {
+            upvotes[sidechain_number.0 as usize] = leader_index as u16;
+        }
+    }
+    upvotes
 }
 
 pub fn parse_coinbase_script(script: &Script) -> IResult<&[u8], CoinbaseMessage> {
@@ -230,20 +319,6 @@ pub fn parse_op_drivechain(input: &[u8]) -> IResult<&[u8], SidechainNumber> {
     Ok((input, SidechainNumber::from(sidechain_number)))
 }
 
-pub fn try_parse_op_return_address(script: &Script) -> Option<Vec<u8>> {
-    let mut instructions = script.instructions();
-    let Some(Ok(Instruction::Op(OP_RETURN))) = instructions.next() else {
-        return None;
-    };
-    let Some(Ok(Instruction::PushBytes(address))) = instructions.next() else {
-        return None;
-    };
-    let None = instructions.next() else {
-        return None;
-    };
-    Some(address.as_bytes().to_owned())
-}
-
 pub fn create_m5_deposit_output(
     sidechain_number: SidechainNumber,
     old_ctip_amount: Amount,
@@ -441,7 +516,63 @@ impl TryFrom<CoinbaseMessage> for ScriptBuf {
     }
 }
 
-pub fn m6_to_id(m6: &Transaction, previous_treasury_utxo_total: u64) -> [u8; 32] {
+#[derive(Debug, Error)]
+pub enum M6LayoutError {
+    #[error("M6 must spend exactly one input, the previous treasury UTXO, but spends {0}")]
+    InputCount(usize),
+    #[error("M6 has no outputs")]
+    NoOutputs,
+    #[error("M6 output 0 is not an OP_DRIVECHAIN output")]
+    MissingTreasuryOutput,
+    #[error("M6 output {vout} is an OP_DRIVECHAIN output; only output 0 may be a treasury output")]
+    ExtraTreasuryOutput { vout: usize },
+    #[error("M6 output {vout} is a fee output; the fee output is only part of the blinded M6")]
+    FeeOutput { vout: usize },
+    #[error("M6 outputs total more than the previous treasury value of {previous_total}")]
+    Overspend { previous_total: Amount },
+}
+
+/// Returns `true` if the script is `OP_RETURN <8 byte fee encoding>`, as
+/// appended to a blinded M6
+fn is_m6_fee_output(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    bytes.len() == 9 && bytes[0] == OP_RETURN.to_u8()
+}
+
+/// Check that an M6 follows the input and output ordering in the spec.
+/// An M6 spends the previous treasury UTXO as its only input. The new
+/// treasury output is at index 0, and is followed by the withdrawal
+/// outputs. The fee is not encoded in the M6 itself, so none of the
+/// withdrawal outputs may be treasury or fee outputs.
+pub fn check_m6_layout(m6: &Transaction) -> Result<(), M6LayoutError> {
+    if m6.input.len() != 1 {
+        return Err(M6LayoutError::InputCount(m6.input.len()));
+    }
+    let Some((treasury_output, payouts)) = m6.output.split_first() else {
+        return Err(M6LayoutError::NoOutputs);
+    };
+    if parse_op_drivechain(treasury_output.script_pubkey.as_bytes()).is_err() {
+        return Err(M6LayoutError::MissingTreasuryOutput);
+    }
+    for (index, payout) in payouts.iter().enumerate() {
+        let vout = index + 1;
+        if parse_op_drivechain(payout.script_pubkey.as_bytes()).is_ok() {
+            return Err(M6LayoutError::ExtraTreasuryOutput { vout });
+        }
+        if is_m6_fee_output(&payout.script_pubkey) {
+            return Err(M6LayoutError::FeeOutput { vout });
+        }
+    }
+    Ok(())
+}
+
+/// Compute the M6ID of an M6, after checking its layout with
+/// [`check_m6_layout`]
+pub fn m6_to_id(
+    m6: &Transaction,
+    previous_treasury_utxo_total: u64,
+) -> Result<[u8; 32], M6LayoutError> {
+    let () = check_m6_layout(m6)?;
     let mut m6 = m6.clone();
     /*
     1. Remove the single input spending the previous treasury UTXO from the `vin`
@@ -453,7 +584,13 @@ pub fn m6_to_id(m6: &Transaction, previous_treasury_utxo_total: u64) -> [u8; 32]
        `M6`, so `P_total` = sum of `nValue`s of all outputs of this `M6` except for
        the new treasury UTXO at index 0.
             */
-    let p_total: Amount = m6.output[1..].iter().map(|o| o.value).sum();
+    let overspend = || M6LayoutError::Overspend {
+        previous_total: Amount::from_sat(previous_treasury_utxo_total),
+    };
+    let p_total = m6.output[1..]
+        .iter()
+        .try_fold(0u64, |total, o| total.checked_add(o.value.to_sat()))
+        .ok_or_else(overspend)?;
     /*
     3. Set `T_n` equal to the `nValue` of the treasury UTXO created in this `M6`.
         */
@@ -464,7 +601,10 @@ pub fn m6_to_id(m6: &Transaction, previous_treasury_utxo_total: u64) -> [u8; 32]
        `P_total` were computed in previous steps..
         */
     let t_n_minus_1 = previous_treasury_utxo_total;
-    let f_total = t_n_minus_1 - t_n - p_total.to_sat();
+    let f_total = t_n_minus_1
+        .checked_sub(t_n)
+        .and_then(|rest| rest.checked_sub(p_total))
+        .ok_or_else(overspend)?;
     /*
     5. Encode `F_total` as `F_total_be_bytes`, an array of 8 bytes encoding the 64
        bit unsigned integer in big endian order.
@@ -485,7 +625,7 @@ pub fn m6_to_id(m6: &Transaction, previous_treasury_utxo_total: u64) -> [u8; 32]
     At this point we have constructed `M6_blinded`.
         */
     let m6_blinded = m6;
-    m6_blinded.compute_txid().as_raw_hash().to_byte_array()
+    Ok(m6_blinded.compute_txid().as_raw_hash().to_byte_array())
 }
 
 // Move all non-consensus components out of Bitcoin Core.
@@ -526,6 +666,53 @@ pub fn create_sidechain_proposal(
     Ok((tx_out, description))
 }
 
+#[derive(Debug, Error)]
+pub enum CoinbasePsbtError {
+    #[error("coinbase template is not a coinbase transaction")]
+    NotCoinbase,
+    #[error(transparent)]
+    Psbt(#[from] bitcoin::psbt::Error),
+}
+
+/// Create a BIP174 PSBT for a coinbase tx that includes the specified
+/// outputs.
+/// If a coinbase template is provided, the outputs are appended to the
+/// template's outputs, and the template's script sig and witness are set as
+/// the finalized script sig and witness of the coinbase input.
+/// Otherwise, the PSBT contains a single coinbase input with an empty script
+/// sig, which must be completed by the miner.
+pub fn coinbase_psbt(
+    template: Option<Transaction>,
+    outputs: Vec<TxOut>,
+) -> Result<Psbt, CoinbasePsbtError> {
+    let mut tx = template.unwrap_or_else(|| Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: Vec::new(),
+    });
+    if !tx.is_coinbase() {
+        return Err(CoinbasePsbtError::NotCoinbase);
+    }
+    tx.output.extend(outputs);
+    // Unsigned txs in PSBTs must have empty script sigs and witnesses
+    let script_sig = std::mem::take(&mut tx.input[0].script_sig);
+    let witness = std::mem::take(&mut tx.input[0].witness);
+    let mut psbt = Psbt::from_unsigned_tx(tx)?;
+    if !script_sig.is_empty() {
+        psbt.inputs[0].final_script_sig = Some(script_sig);
+    }
+    if !witness.is_empty() {
+        psbt.inputs[0].final_script_witness = Some(witness);
+    }
+    Ok(psbt)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -602,4 +789,164 @@ mod tests {
 
         assert_eq!(parsed, declaration);
     }
+
+    #[test]
+    fn test_coinbase_psbt() {
+        let outputs = CoinbaseBuilder::new()
+            .ack_sidechain(SidechainNumber(1), sha256d::Hash::all_zeros())
+            .build()
+            .unwrap();
+
+        let psbt = coinbase_psbt(None, outputs.clone()).unwrap();
+        assert!(psbt.unsigned_tx.is_coinbase());
+        assert_eq!(psbt.unsigned_tx.output, outputs);
+        let psbt = Psbt::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(psbt.unsigned_tx.output, outputs);
+
+        let script_sig = ScriptBuf::from_bytes(vec![0x03, 0x01, 0x02, 0x03]);
+        let reward = TxOut {
+            value: Amount::from_sat(50),
+            script_pubkey: ScriptBuf::new(),
+        };
+        let template = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: script_sig.clone(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![reward.clone()],
+        };
+        let psbt = coinbase_psbt(Some(template.clone()), outputs.clone()).unwrap();
+        assert_eq!(psbt.unsigned_tx.output[0], reward);
+        assert_eq!(psbt.unsigned_tx.output[1..], outputs[..]);
+        assert_eq!(psbt.inputs[0].final_script_sig, Some(script_sig));
+        let tx = psbt.extract_tx_unchecked_fee_rate();
+        assert_eq!(tx.input, template.input);
+
+        let mut not_coinbase = template;
+        not_coinbase.input[0].previous_output.vout = 0;
+        assert!(matches!(
+            coinbase_psbt(Some(not_coinbase), outputs),
+            Err(CoinbasePsbtError::NotCoinbase)
+        ));
+    }
+
+    #[test]
+    fn test_m4_ack_bundles_compact() {
+        const ABSTAIN: u16 = ABSTAIN_TWO_BYTES;
+        assert_eq!(M4AckBundles::compact(&[ABSTAIN, ABSTAIN], &[], &[]), None);
+        assert_eq!(
+            M4AckBundles::compact(&[1, ABSTAIN], &[1], &[]),
+            Some(M4AckBundles::RepeatPrevious)
+        );
+        assert_eq!(
+            M4AckBundles::compact(&[ABSTAIN, 0], &[], &[ABSTAIN, 0, ABSTAIN]),
+            Some(M4AckBundles::LeadingBy50)
+        );
+        assert_eq!(
+            M4AckBundles::compact(&[2, ALARM_TWO_BYTES, ABSTAIN], &[], &[]),
+            Some(M4AckBundles::OneByte {
+                upvotes: vec![2, ALARM_ONE_BYTE]
+            })
+        );
+        assert_eq!(
+            M4AckBundles::compact(&[ABSTAIN, 0xFE], &[], &[]),
+            Some(M4AckBundles::TwoBytes {
+                upvotes: vec![ABSTAIN, 0xFE]
+            })
+        );
+    }
+
+    #[test]
+    fn test_leading_by_50_upvotes() {
+        let pending_m6id = |vote_count| PendingM6id {
+            m6id: [0; 32],
+            vote_count,
+            proposal_height: 0,
+        };
+        let pending_m6ids = BTreeMap::from([
+            (SidechainNumber(0), vec![pending_m6id(10), pending_m6id(60)]),
+            (SidechainNumber(1), vec![pending_m6id(10), pending_m6id(59)]),
+            (SidechainNumber(3), vec![pending_m6id(50)]),
+        ]);
+        assert_eq!(
+            leading_by_50_upvotes(&pending_m6ids),
+            vec![1, ABSTAIN_TWO_BYTES, ABSTAIN_TWO_BYTES, 0]
+        );
+        assert!(leading_by_50_upvotes(&BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_m6_layout() {
+        const PREVIOUS_TOTAL: Amount = Amount::from_sat(100_000);
+        let payout = |value| TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::new(),
+        };
+        let m6 = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                create_m5_deposit_output(
+                    SidechainNumber(1),
+                    Amount::ZERO,
+                    Amount::from_sat(60_000),
+                ),
+                payout(30_000),
+            ],
+        };
+        let m6id = m6_to_id(&m6, PREVIOUS_TOTAL.to_sat()).unwrap();
+
+        // The fee is committed to by the M6ID
+        assert_ne!(m6_to_id(&m6, PREVIOUS_TOTAL.to_sat() + 1).unwrap(), m6id);
+
+        let mut two_inputs = m6.clone();
+        two_inputs.input.push(TxIn::default());
+        assert!(matches!(
+            m6_to_id(&two_inputs, PREVIOUS_TOTAL.to_sat()),
+            Err(M6LayoutError::InputCount(2))
+        ));
+
+        let mut treasury_last = m6.clone();
+        treasury_last.output.reverse();
+        assert!(matches!(
+            m6_to_id(&treasury_last, PREVIOUS_TOTAL.to_sat()),
+            Err(M6LayoutError::MissingTreasuryOutput)
+        ));
+
+        let mut extra_treasury = m6.clone();
+        extra_treasury.output.push(m6.output[0].clone());
+        assert!(matches!(
+            m6_to_id(&extra_treasury, PREVIOUS_TOTAL.to_sat()),
+            Err(M6LayoutError::ExtraTreasuryOutput { vout: 2 })
+        ));
+
+        let mut fee_output = m6.clone();
+        fee_output.output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::from_bytes(
+                [vec![OP_RETURN.to_u8()], 10_000u64.to_be_bytes().to_vec()].concat(),
+            ),
+        });
+        assert!(matches!(
+            m6_to_id(&fee_output, PREVIOUS_TOTAL.to_sat()),
+            Err(M6LayoutError::FeeOutput { vout: 2 })
+        ));
+
+        let mut overspend = m6.clone();
+        overspend.output.push(payout(10_001));
+        assert!(matches!(
+            m6_to_id(&overspend, PREVIOUS_TOTAL.to_sat()),
+            Err(M6LayoutError::Overspend { .. })
+        ));
+        overspend.output[2] = payout(u64::MAX);
+        assert!(matches!(
+            m6_to_id(&overspend, PREVIOUS_TOTAL.to_sat()),
+            Err(M6LayoutError::Overspend { .. })
+        ));
+    }
 }
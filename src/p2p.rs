@@ -0,0 +1,413 @@
+//! Minimal Bitcoin P2P client, for fetching headers and blocks directly from
+//! a node
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bitcoin::{
+    bip152::{BlockTransactionsRequest, HeaderAndShortIds, ShortId},
+    block::Header,
+    consensus::encode,
+    p2p::{
+        message::{NetworkMessage, RawNetworkMessage},
+        message_blockdata::{GetHeadersMessage, Inventory},
+        message_compact_blocks::{BlockTxn, CmpctBlock, GetBlockTxn, SendCmpct},
+        message_network::VersionMessage,
+        Address, Magic, ServiceFlags,
+    },
+    Block, BlockHash, Transaction, Txid, Wtxid,
+};
+use hashlink::LinkedHashMap;
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+};
+
+/// Size of a P2P message header
+const MESSAGE_HEADER_LEN: usize = 24;
+
+/// Max payload size of messages received from the peer
+const MAX_PAYLOAD_LEN: u32 = 32 * 1024 * 1024;
+
+/// Max number of headers in a `headers` message
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+
+const USER_AGENT: &str = concat!("/bip300301_enforcer:", env!("CARGO_PKG_VERSION"), "/");
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to connect to peer at `{addr}`")]
+    Connect {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+    #[error("Failed to decode message from peer")]
+    Decode(#[from] encode::Error),
+    #[error("Invalid compact block `{0}`")]
+    InvalidCompactBlock(BlockHash),
+    #[error("I/O error communicating with peer")]
+    Io(#[from] std::io::Error),
+    #[error("Received message with unexpected network magic `{0}`")]
+    Magic(Magic),
+    #[error("Peer does not have block `{0}`")]
+    NotFound(BlockHash),
+    #[error("Message payload too large ({0} bytes)")]
+    PayloadTooLarge(u32),
+    #[error("Request to peer timed out")]
+    Timeout,
+}
+
+/// Recently seen mempool transactions, for reconstructing compact blocks
+/// and for looking up transactions that have since left the mempool
+#[derive(Debug, Default)]
+pub struct Mempool {
+    txs: LinkedHashMap<Wtxid, Transaction>,
+    wtxids: HashMap<Txid, Wtxid>,
+}
+
+impl Mempool {
+    /// Max number of transactions to keep. The oldest transactions are
+    /// evicted first.
+    const MAX_TXS: usize = 100_000;
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    pub fn insert(&mut self, tx: Transaction) {
+        let wtxid = tx.compute_wtxid();
+        let _: Option<Wtxid> = self.wtxids.insert(tx.compute_txid(), wtxid);
+        let _: Option<Transaction> = self.txs.replace(wtxid, tx);
+        while self.txs.len() > Self::MAX_TXS {
+            let Some((wtxid, tx)) = self.txs.pop_front() else {
+                break;
+            };
+            let txid = tx.compute_txid();
+            if self.wtxids.get(&txid) == Some(&wtxid) {
+                let _: Option<Wtxid> = self.wtxids.remove(&txid);
+            }
+        }
+    }
+
+    /// Look up a recently seen transaction by txid
+    pub fn get(&self, txid: &Txid) -> Option<&Transaction> {
+        let wtxid = self.wtxids.get(txid)?;
+        self.txs.get(wtxid)
+    }
+
+    /// Fill in the transactions of a compact block, from prefilled
+    /// transactions and mempool transactions.
+    /// Transactions that are not in the mempool are `None`.
+    fn reconstruct(
+        &self,
+        compact_block: &HeaderAndShortIds,
+    ) -> Result<Vec<Option<Transaction>>, Error> {
+        let block_hash = compact_block.header.block_hash();
+        let mut txs = vec![None; compact_block.short_ids.len() + compact_block.prefilled_txs.len()];
+        // Prefilled transaction indexes are differentially encoded
+        let mut next_idx = 0;
+        for prefilled_tx in &compact_block.prefilled_txs {
+            let idx = next_idx + usize::from(prefilled_tx.idx);
+            let slot = txs
+                .get_mut(idx)
+                .ok_or(Error::InvalidCompactBlock(block_hash))?;
+            *slot = Some(prefilled_tx.tx.clone());
+            next_idx = idx + 1;
+        }
+        let siphash_keys =
+            ShortId::calculate_siphash_keys(&compact_block.header, compact_block.nonce);
+        let mempool_txs: HashMap<ShortId, &Transaction> = self
+            .txs
+            .iter()
+            .map(|(wtxid, tx)| (ShortId::with_siphash_keys(wtxid, siphash_keys), tx))
+            .collect();
+        let mut short_ids = compact_block.short_ids.iter();
+        for slot in txs.iter_mut().filter(|slot| slot.is_none()) {
+            let short_id = short_ids
+                .next()
+                .ok_or(Error::InvalidCompactBlock(block_hash))?;
+            *slot = mempool_txs.get(short_id).map(|tx| (*tx).clone());
+        }
+        Ok(txs)
+    }
+}
+
+/// Connection to a single peer, after the version handshake
+#[derive(Debug)]
+pub struct Peer {
+    magic: Magic,
+    stream: TcpStream,
+}
+
+impl Peer {
+    /// Connect to the peer at `addr`, and perform the version handshake
+    pub async fn connect(addr: SocketAddr, magic: Magic) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|source| Error::Connect { addr, source })?;
+        let mut peer = Self { magic, stream };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let version = VersionMessage {
+            relay: false,
+            ..VersionMessage::new(
+                ServiceFlags::NONE,
+                timestamp as i64,
+                Address::new(&addr, ServiceFlags::NONE),
+                Address::new(
+                    &SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    ServiceFlags::NONE,
+                ),
+                rand::random(),
+                USER_AGENT.to_owned(),
+                0,
+            )
+        };
+        let () = peer.send(NetworkMessage::Version(version)).await?;
+        let (mut version_received, mut verack_received) = (false, false);
+        while !(version_received && verack_received) {
+            match peer.recv().await? {
+                NetworkMessage::Version(version) => {
+                    tracing::debug!(
+                        "Connected to peer at {addr} ({}, version {})",
+                        version.user_agent,
+                        version.version
+                    );
+                    version_received = true;
+                    let () = peer.send(NetworkMessage::Verack).await?;
+                }
+                NetworkMessage::Verack => {
+                    verack_received = true;
+                }
+                _ => (),
+            }
+        }
+        // Compact blocks use wtxids for short IDs from version 2
+        let send_cmpct = SendCmpct {
+            send_compact: false,
+            version: 2,
+        };
+        let () = peer.send(NetworkMessage::SendCmpct(send_cmpct)).await?;
+        Ok(peer)
+    }
+
+    async fn send(&mut self, msg: NetworkMessage) -> Result<(), Error> {
+        let msg = RawNetworkMessage::new(self.magic, msg);
+        let () = self.stream.write_all(&encode::serialize(&msg)).await?;
+        Ok(())
+    }
+
+    /// Receive the next message. Pings are answered, and not returned.
+    async fn recv(&mut self) -> Result<NetworkMessage, Error> {
+        loop {
+            let mut msg = vec![0; MESSAGE_HEADER_LEN];
+            let _: usize = self.stream.read_exact(&mut msg).await?;
+            let magic: Magic = encode::deserialize(&msg[..4])?;
+            if magic != self.magic {
+                return Err(Error::Magic(magic));
+            }
+            let payload_len: u32 = encode::deserialize(&msg[16..20])?;
+            if payload_len > MAX_PAYLOAD_LEN {
+                return Err(Error::PayloadTooLarge(payload_len));
+            }
+            msg.resize(MESSAGE_HEADER_LEN + payload_len as usize, 0);
+            let _: usize = self
+                .stream
+                .read_exact(&mut msg[MESSAGE_HEADER_LEN..])
+                .await?;
+            let msg: RawNetworkMessage = encode::deserialize(&msg)?;
+            match msg.into_payload() {
+                NetworkMessage::Ping(nonce) => {
+                    let () = self.send(NetworkMessage::Pong(nonce)).await?;
+                }
+                payload => return Ok(payload),
+            }
+        }
+    }
+
+    /// Request up to [`MAX_HEADERS_RESULTS`] headers following the first
+    /// locator hash that is in the peer's active chain, up to and including
+    /// `stop_hash`.
+    pub async fn get_headers(
+        &mut self,
+        locator_hashes: Vec<BlockHash>,
+        stop_hash: BlockHash,
+    ) -> Result<Vec<Header>, Error> {
+        let msg = GetHeadersMessage::new(locator_hashes, stop_hash);
+        let () = self.send(NetworkMessage::GetHeaders(msg)).await?;
+        loop {
+            if let NetworkMessage::Headers(headers) = self.recv().await? {
+                return Ok(headers);
+            }
+        }
+    }
+
+    /// Request blocks, returned in the same order as `block_hashes`
+    pub async fn get_blocks(&mut self, block_hashes: &[BlockHash]) -> Result<Vec<Block>, Error> {
+        let inv = block_hashes
+            .iter()
+            .map(|block_hash| Inventory::WitnessBlock(*block_hash))
+            .collect();
+        let () = self.send(NetworkMessage::GetData(inv)).await?;
+        let mut blocks = HashMap::with_capacity(block_hashes.len());
+        while blocks.len() < block_hashes.len() {
+            match self.recv().await? {
+                NetworkMessage::Block(block) => {
+                    let _: Option<Block> = blocks.insert(block.block_hash(), block);
+                }
+                NetworkMessage::NotFound(inv) => {
+                    let block_hash =
+                        inv.into_iter()
+                            .find_map(|inv| match inv {
+                                Inventory::Block(block_hash)
+                                | Inventory::WitnessBlock(block_hash) => Some(block_hash),
+                                _ => None,
+                            })
+                            .unwrap_or(block_hashes[0]);
+                    return Err(Error::NotFound(block_hash));
+                }
+                _ => (),
+            }
+        }
+        block_hashes
+            .iter()
+            .map(|block_hash| {
+                blocks
+                    .remove(block_hash)
+                    .ok_or(Error::NotFound(*block_hash))
+            })
+            .collect()
+    }
+
+    /// Request a block as a compact block (BIP152), and reconstruct it using
+    /// transactions from `mempool`. Transactions that are not in the mempool
+    /// are requested from the peer.
+    /// If the reconstructed block is invalid, eg. due to short ID
+    /// collisions, the full block is requested instead.
+    pub async fn get_block_compact(
+        &mut self,
+        block_hash: BlockHash,
+        mempool: &parking_lot::Mutex<Mempool>,
+    ) -> Result<Block, Error> {
+        let inv = vec![Inventory::CompactBlock(block_hash)];
+        let () = self.send(NetworkMessage::GetData(inv)).await?;
+        let compact_block = loop {
+            match self.recv().await? {
+                NetworkMessage::CmpctBlock(CmpctBlock { compact_block })
+                    if compact_block.header.block_hash() == block_hash =>
+                {
+                    break compact_block
+                }
+                // Peers send the full block if it is not recent
+                NetworkMessage::Block(block) if block.block_hash() == block_hash => {
+                    return Ok(block)
+                }
+                NetworkMessage::NotFound(_) => return Err(Error::NotFound(block_hash)),
+                _ => (),
+            }
+        };
+        let mut txs = mempool.lock().reconstruct(&compact_block)?;
+        let missing_indexes: Vec<usize> = txs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, tx)| tx.is_none().then_some(idx))
+            .collect();
+        if !missing_indexes.is_empty() {
+            tracing::debug!(
+                "Requesting {}/{} transactions missing from compact block `{block_hash}`",
+                missing_indexes.len(),
+                txs.len()
+            );
+            let txs_request = BlockTransactionsRequest {
+                block_hash,
+                indexes: missing_indexes.iter().map(|idx| *idx as u64).collect(),
+            };
+            let () = self
+                .send(NetworkMessage::GetBlockTxn(GetBlockTxn { txs_request }))
+                .await?;
+            let missing_txs = loop {
+                if let NetworkMessage::BlockTxn(BlockTxn { transactions }) = self.recv().await? {
+                    if transactions.block_hash == block_hash {
+                        break transactions.transactions;
+                    }
+                }
+            };
+            if missing_txs.len() != missing_indexes.len() {
+                return Err(Error::InvalidCompactBlock(block_hash));
+            }
+            for (idx, tx) in missing_indexes.into_iter().zip(missing_txs) {
+                txs[idx] = Some(tx);
+            }
+        }
+        let block = Block {
+            header: compact_block.header,
+            txdata: txs.into_iter().flatten().collect(),
+        };
+        if block.check_merkle_root() && block.check_witness_commitment() {
+            Ok(block)
+        } else {
+            tracing::debug!(
+                "Failed to reconstruct compact block `{block_hash}`, requesting full block"
+            );
+            let mut blocks = self.get_blocks(&[block_hash]).await?;
+            blocks.pop().ok_or(Error::NotFound(block_hash))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{absolute::LockTime, transaction::Version, Transaction, TxIn, Witness};
+
+    use super::Mempool;
+
+    fn tx(lock_time: u32, witness: &[&[u8]]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_consensus(lock_time),
+            input: vec![TxIn {
+                witness: Witness::from_slice(witness),
+                ..TxIn::default()
+            }],
+            output: Vec::new(),
+        }
+    }
+
+    /// The oldest txs are evicted first, and a txid is only removed from the
+    /// index if it refers to the evicted tx
+    #[test]
+    fn test_eviction() {
+        let mut mempool = Mempool::default();
+        let original = tx(0, &[]);
+        // Same txid, different wtxid
+        let malleated = tx(0, &[&[1]]);
+        let txid = original.compute_txid();
+        assert_eq!(malleated.compute_txid(), txid);
+        assert_ne!(malleated.compute_wtxid(), original.compute_wtxid());
+        mempool.insert(original);
+        mempool.insert(malleated.clone());
+        assert_eq!(mempool.get(&txid), Some(&malleated));
+        for lock_time in 1..Mempool::MAX_TXS as u32 {
+            mempool.insert(tx(lock_time, &[]));
+        }
+        // The original tx was evicted, but the txid still refers to the
+        // malleated tx
+        assert_eq!(mempool.txs.len(), Mempool::MAX_TXS);
+        assert_eq!(mempool.wtxids.len(), Mempool::MAX_TXS);
+        assert_eq!(mempool.get(&txid), Some(&malleated));
+        mempool.insert(tx(Mempool::MAX_TXS as u32, &[]));
+        assert_eq!(mempool.txs.len(), Mempool::MAX_TXS);
+        assert_eq!(mempool.wtxids.len(), Mempool::MAX_TXS);
+        assert_eq!(mempool.get(&txid), None);
+        let newest = tx(Mempool::MAX_TXS as u32, &[]);
+        assert_eq!(mempool.get(&newest.compute_txid()), Some(&newest));
+        let oldest = tx(1, &[]);
+        assert_eq!(mempool.get(&oldest.compute_txid()), Some(&oldest));
+    }
+}
@@ -85,6 +85,17 @@ impl Error {
             message_name: Message::full_name(),
         }
     }
+
+    pub fn unknown_enum_tag<Message>(field_name: &str, tag: i32) -> Self
+    where
+        Message: prost::Name,
+    {
+        Self::UnknownEnumTag {
+            field_name: field_name.to_owned(),
+            message_name: Message::full_name(),
+            tag,
+        }
+    }
 }
 
 pub mod common {
@@ -226,7 +237,9 @@ pub mod mainchain {
 
     tonic::include_proto!("cusf.mainchain.v1");
 
-    use subscribe_events_response::event::{ConnectBlock, DisconnectBlock};
+    use subscribe_events_response::event::{
+        ConnectBlock, DepositConfirmed, DepositUnconfirmed, DisconnectBlock, WithdrawalBundleAlert,
+    };
     #[allow(unused_imports)]
     pub use validator_service_server::{
         self as server, ValidatorService as Service, ValidatorServiceServer as Server,
@@ -538,6 +551,65 @@ pub mod mainchain {
         }
     }
 
+    impl TryFrom<BlockHeaderInfo> for crate::types::HeaderInfo {
+        type Error = super::Error;
+
+        fn try_from(header_info: BlockHeaderInfo) -> Result<Self, Self::Error> {
+            let BlockHeaderInfo {
+                block_hash,
+                prev_block_hash,
+                height,
+                work,
+            } = header_info;
+            let block_hash = block_hash
+                .ok_or_else(|| Self::Error::missing_field::<BlockHeaderInfo>("block_hash"))?
+                .decode::<BlockHeaderInfo, _>("block_hash")?;
+            let prev_block_hash = prev_block_hash
+                .ok_or_else(|| Self::Error::missing_field::<BlockHeaderInfo>("prev_block_hash"))?
+                .decode::<BlockHeaderInfo, _>("prev_block_hash")?;
+            let work: [u8; 32] = work
+                .ok_or_else(|| Self::Error::missing_field::<BlockHeaderInfo>("work"))?
+                .decode::<BlockHeaderInfo, _>("work")?;
+            Ok(Self {
+                block_hash,
+                prev_block_hash,
+                height,
+                work: bitcoin::Work::from_le_bytes(work),
+            })
+        }
+    }
+
+    impl TryFrom<OutPoint> for bitcoin::OutPoint {
+        type Error = super::Error;
+
+        fn try_from(outpoint: OutPoint) -> Result<Self, Self::Error> {
+            let OutPoint { txid, vout } = outpoint;
+            let txid = txid
+                .ok_or_else(|| Self::Error::missing_field::<OutPoint>("txid"))?
+                .decode::<OutPoint, _>("txid")?;
+            let vout = vout.ok_or_else(|| Self::Error::missing_field::<OutPoint>("vout"))?;
+            Ok(Self { txid, vout })
+        }
+    }
+
+    impl TryFrom<WithdrawalBundleEventType> for crate::types::WithdrawalBundleEventKind {
+        type Error = super::Error;
+
+        fn try_from(kind: WithdrawalBundleEventType) -> Result<Self, Self::Error> {
+            match kind {
+                WithdrawalBundleEventType::Unspecified => {
+                    Err(Self::Error::invalid_enum_variant::<WithdrawalBundleEvent>(
+                        "withdrawal_bundle_event_type",
+                        kind.as_str_name(),
+                    ))
+                }
+                WithdrawalBundleEventType::Failed => Ok(Self::Failed),
+                WithdrawalBundleEventType::Submitted => Ok(Self::Submitted),
+                WithdrawalBundleEventType::Succeded => Ok(Self::Succeeded),
+            }
+        }
+    }
+
     impl From<crate::types::Deposit> for (SidechainNumber, Deposit) {
         fn from(deposit: crate::types::Deposit) -> Self {
             let crate::types::Deposit {
@@ -648,12 +720,43 @@ pub mod mainchain {
         }
     }
 
+    impl From<(bitcoin::BlockHash, crate::types::DepositConfirmation)> for DepositConfirmed {
+        fn from(
+            (block_hash, confirmation): (bitcoin::BlockHash, crate::types::DepositConfirmation),
+        ) -> Self {
+            let (_, deposit) = confirmation.deposit.into();
+            Self {
+                block_hash: Some(ReverseHex::encode(&block_hash)),
+                deposit: Some(deposit),
+                deposit_block_hash: Some(ReverseHex::encode(&confirmation.deposit_block_hash)),
+                deposit_block_height: confirmation.deposit_block_height,
+                confirmations: confirmation.confirmations,
+            }
+        }
+    }
+
+    impl From<(bitcoin::BlockHash, crate::types::DepositConfirmation)> for DepositUnconfirmed {
+        fn from(
+            (block_hash, confirmation): (bitcoin::BlockHash, crate::types::DepositConfirmation),
+        ) -> Self {
+            let (_, deposit) = confirmation.deposit.into();
+            Self {
+                block_hash: Some(ReverseHex::encode(&block_hash)),
+                deposit: Some(deposit),
+                deposit_block_hash: Some(ReverseHex::encode(&confirmation.deposit_block_hash)),
+                deposit_block_height: confirmation.deposit_block_height,
+                confirmations: confirmation.confirmations,
+            }
+        }
+    }
+
     impl crate::types::Event {
+        /// Returns `None` for events that only concern other sidechains
         pub fn into_proto(
             self,
             sidechain_number: SidechainNumber,
-        ) -> subscribe_events_response::event::Event {
-            match self {
+        ) -> Option<subscribe_events_response::event::Event> {
+            let event = match self {
                 Self::ConnectBlock {
                     header_info,
                     block_info,
@@ -670,7 +773,43 @@ pub mod mainchain {
                     };
                     subscribe_events_response::event::Event::DisconnectBlock(event)
                 }
-            }
+                Self::WithdrawalBundleAlert { block_hash, alert } => {
+                    if alert.sidechain_id != sidechain_number {
+                        return None;
+                    }
+                    let event = WithdrawalBundleAlert {
+                        block_hash: Some(ReverseHex::encode(&block_hash)),
+                        m6id: Some(ConsensusHex::encode(&alert.m6id)),
+                        previous_vote_count: Some(alert.previous_vote_count.into()),
+                        vote_count: Some(alert.vote_count.into()),
+                        leading: Some(alert.leading),
+                    };
+                    subscribe_events_response::event::Event::WithdrawalBundleAlert(event)
+                }
+                Self::DepositConfirmed {
+                    block_hash,
+                    confirmation,
+                } => {
+                    if confirmation.deposit.sidechain_id != sidechain_number {
+                        return None;
+                    }
+                    subscribe_events_response::event::Event::DepositConfirmed(
+                        (block_hash, confirmation).into(),
+                    )
+                }
+                Self::DepositUnconfirmed {
+                    block_hash,
+                    confirmation,
+                } => {
+                    if confirmation.deposit.sidechain_id != sidechain_number {
+                        return None;
+                    }
+                    subscribe_events_response::event::Event::DepositUnconfirmed(
+                        (block_hash, confirmation).into(),
+                    )
+                }
+            };
+            Some(event)
         }
     }
 
@@ -680,14 +819,380 @@ pub mod mainchain {
         }
     }
 
-    impl From<crate::types::Sidechain> for get_sidechains_response::SidechainInfo {
-        fn from(sidechain: crate::types::Sidechain) -> Self {
+    impl From<subscribe_headers_response::event::Event> for subscribe_headers_response::Event {
+        fn from(event: subscribe_headers_response::event::Event) -> Self {
+            Self { event: Some(event) }
+        }
+    }
+
+    impl From<crate::types::WithdrawalBundleFailureReason> for WithdrawalBundleFailureReason {
+        fn from(reason: crate::types::WithdrawalBundleFailureReason) -> Self {
+            match reason {
+                crate::types::WithdrawalBundleFailureReason::Expired => Self::Expired,
+            }
+        }
+    }
+
+    impl From<crate::types::WithdrawalBundleStatus> for get_withdrawal_bundle_status_response::Status {
+        fn from(status: crate::types::WithdrawalBundleStatus) -> Self {
+            use get_withdrawal_bundle_status_response::{Failed, Pending, Succeeded, Unknown};
+            match status {
+                crate::types::WithdrawalBundleStatus::Unknown => Self::Unknown(Unknown {}),
+                crate::types::WithdrawalBundleStatus::Pending {
+                    vote_count,
+                    proposal_height,
+                    age,
+                } => Self::Pending(Pending {
+                    vote_count: vote_count as u32,
+                    proposal_height,
+                    age,
+                }),
+                crate::types::WithdrawalBundleStatus::Resolved(
+                    crate::types::WithdrawalBundleOutcome::Succeeded {
+                        txid,
+                        block_hash,
+                        height,
+                    },
+                ) => Self::Succeeded(Succeeded {
+                    txid: Some(ReverseHex::encode(&txid)),
+                    block_hash: Some(ReverseHex::encode(&block_hash)),
+                    height,
+                }),
+                crate::types::WithdrawalBundleStatus::Resolved(
+                    crate::types::WithdrawalBundleOutcome::Failed {
+                        block_hash,
+                        height,
+                        reason,
+                    },
+                ) => Self::Failed(Failed {
+                    block_hash: Some(ReverseHex::encode(&block_hash)),
+                    height,
+                    reason: WithdrawalBundleFailureReason::from(reason) as i32,
+                }),
+            }
+        }
+    }
+
+    impl From<crate::validator::CtipInfo> for get_ctip_response::Ctip {
+        fn from(ctip_info: crate::validator::CtipInfo) -> Self {
+            let crate::validator::CtipInfo {
+                ctip,
+                sequence_number,
+            } = ctip_info;
+            Self {
+                txid: Some(ReverseHex::encode(&ctip.outpoint.txid)),
+                vout: ctip.outpoint.vout,
+                value: ctip.value.to_sat(),
+                sequence_number,
+            }
+        }
+    }
+
+    impl From<crate::types::SidechainAck> for voting_policy::SidechainAck {
+        fn from(ack: crate::types::SidechainAck) -> Self {
+            use bitcoin::hashes::Hash as _;
+            Self {
+                sidechain_number: Some(ack.sidechain_number.0 as u32),
+                description_hash: Some(ConsensusHex::encode(&ack.description_hash.to_byte_array())),
+            }
+        }
+    }
+
+    impl TryFrom<voting_policy::SidechainAck> for crate::types::SidechainAck {
+        type Error = super::Error;
+
+        fn try_from(ack: voting_policy::SidechainAck) -> Result<Self, Self::Error> {
+            use bitcoin::hashes::{sha256d, Hash as _};
+            use voting_policy::SidechainAck;
+            let SidechainAck {
+                sidechain_number,
+                description_hash,
+            } = ack;
+            let sidechain_number: SidechainNumber = {
+                let sidechain_number: u32 = sidechain_number.ok_or_else(|| {
+                    Self::Error::missing_field::<SidechainAck>("sidechain_number")
+                })?;
+                sidechain_number.try_into().map_err(|err| {
+                    Self::Error::invalid_field_value::<SidechainAck, _>(
+                        "sidechain_number",
+                        &sidechain_number.to_string(),
+                        err,
+                    )
+                })?
+            };
+            let description_hash: [u8; 32] = description_hash
+                .ok_or_else(|| Self::Error::missing_field::<SidechainAck>("description_hash"))?
+                .decode::<SidechainAck, _>("description_hash")?;
+            Ok(Self {
+                sidechain_number,
+                description_hash: sha256d::Hash::from_byte_array(description_hash),
+            })
+        }
+    }
+
+    impl From<(SidechainNumber, crate::wallet::voting_policy::BundleVote)>
+        for voting_policy::BundleVote
+    {
+        fn from(
+            (sidechain_number, vote): (SidechainNumber, crate::wallet::voting_policy::BundleVote),
+        ) -> Self {
+            use crate::wallet::voting_policy::BundleVote;
+            let (kind, m6id) = match vote {
+                BundleVote::Upvote { m6id } => {
+                    (voting_policy::bundle_vote::Kind::Upvote, Some(m6id))
+                }
+                BundleVote::Abstain => (voting_policy::bundle_vote::Kind::Abstain, None),
+                BundleVote::Alarm => (voting_policy::bundle_vote::Kind::Alarm, None),
+            };
+            Self {
+                sidechain_number: Some(sidechain_number.0 as u32),
+                kind: kind as i32,
+                m6id: m6id.map(|m6id| ConsensusHex::encode(&m6id)),
+            }
+        }
+    }
+
+    impl TryFrom<voting_policy::BundleVote>
+        for (SidechainNumber, crate::wallet::voting_policy::BundleVote)
+    {
+        type Error = super::Error;
+
+        fn try_from(vote: voting_policy::BundleVote) -> Result<Self, Self::Error> {
+            use crate::wallet::voting_policy::BundleVote as Vote;
+            use voting_policy::{bundle_vote::Kind, BundleVote};
+            let BundleVote {
+                sidechain_number,
+                kind,
+                m6id,
+            } = vote;
+            let sidechain_number: SidechainNumber = {
+                let sidechain_number: u32 = sidechain_number
+                    .ok_or_else(|| Self::Error::missing_field::<BundleVote>("sidechain_number"))?;
+                sidechain_number.try_into().map_err(|err| {
+                    Self::Error::invalid_field_value::<BundleVote, _>(
+                        "sidechain_number",
+                        &sidechain_number.to_string(),
+                        err,
+                    )
+                })?
+            };
+            let kind = Kind::try_from(kind)
+                .map_err(|_| Self::Error::unknown_enum_tag::<BundleVote>("kind", kind))?;
+            let vote = match kind {
+                Kind::Unspecified => {
+                    return Err(Self::Error::invalid_enum_variant::<BundleVote>(
+                        "kind",
+                        kind.as_str_name(),
+                    ))
+                }
+                Kind::Upvote => {
+                    let m6id = m6id
+                        .ok_or_else(|| Self::Error::missing_field::<BundleVote>("m6id"))?
+                        .decode::<BundleVote, _>("m6id")?;
+                    Vote::Upvote { m6id }
+                }
+                Kind::Abstain => Vote::Abstain,
+                Kind::Alarm => Vote::Alarm,
+            };
+            Ok((sidechain_number, vote))
+        }
+    }
+
+    impl From<crate::wallet::voting_policy::VotingPolicy> for VotingPolicy {
+        fn from(voting_policy: crate::wallet::voting_policy::VotingPolicy) -> Self {
+            let crate::wallet::voting_policy::VotingPolicy {
+                sidechain_acks,
+                bundle_votes,
+            } = voting_policy;
+            Self {
+                sidechain_acks: sidechain_acks.into_iter().map(Into::into).collect(),
+                bundle_votes: bundle_votes.into_iter().map(Into::into).collect(),
+            }
+        }
+    }
+
+    impl From<crate::wallet::bmm_request_history::BmmRequestOrigin>
+        for list_bmm_requests_response::Origin
+    {
+        fn from(origin: crate::wallet::bmm_request_history::BmmRequestOrigin) -> Self {
+            use crate::wallet::bmm_request_history::BmmRequestOrigin;
+            match origin {
+                BmmRequestOrigin::Created => Self::Created,
+                BmmRequestOrigin::Observed => Self::Observed,
+            }
+        }
+    }
+
+    impl From<crate::wallet::bmm_request_history::BmmRequestState>
+        for list_bmm_requests_response::State
+    {
+        fn from(state: crate::wallet::bmm_request_history::BmmRequestState) -> Self {
+            use crate::wallet::bmm_request_history::BmmRequestState;
+            match state {
+                BmmRequestState::Created => Self::Created,
+                BmmRequestState::Broadcast => Self::Broadcast,
+                BmmRequestState::Accepted => Self::Accepted,
+                BmmRequestState::NotAccepted => Self::NotAccepted,
+                BmmRequestState::Expired => Self::Expired,
+                BmmRequestState::Replaced => Self::Replaced,
+            }
+        }
+    }
+
+    impl From<crate::wallet::bmm_request_history::BmmRequestRecord>
+        for list_bmm_requests_response::BmmRequest
+    {
+        fn from(record: crate::wallet::bmm_request_history::BmmRequestRecord) -> Self {
+            Self {
+                txid: Some(ReverseHex::encode(&record.txid)),
+                sidechain_number: Some(record.sidechain_number.0 as u32),
+                prev_block_hash: Some(ReverseHex::encode(&record.prev_block_hash)),
+                sidechain_block_hash: Some(ConsensusHex::encode(&record.sidechain_block_hash)),
+                bid_sats: Some(record.bid.to_sat()),
+                fee_sats: Some(record.fee.to_sat()),
+                origin: list_bmm_requests_response::Origin::from(record.origin) as i32,
+                state: list_bmm_requests_response::State::from(record.state) as i32,
+                created_at: Some(record.created_at),
+                broadcast_at: record.broadcast_at,
+                resolved_at: record.resolved_at,
+                block_hash: record
+                    .block_hash
+                    .map(|block_hash| ReverseHex::encode(&block_hash)),
+                replaced_by: record.replaced_by.map(|txid| ReverseHex::encode(&txid)),
+            }
+        }
+    }
+
+    impl From<crate::wallet::bmm_request_history::BmmRequestSummary>
+        for list_bmm_requests_response::Summary
+    {
+        fn from(summary: crate::wallet::bmm_request_history::BmmRequestSummary) -> Self {
+            let crate::wallet::bmm_request_history::BmmRequestSummary {
+                accepted,
+                not_accepted,
+                expired,
+                replaced,
+                unresolved,
+                spent,
+            } = summary;
+            Self {
+                accepted,
+                not_accepted,
+                expired,
+                replaced,
+                unresolved,
+                spent_sats: spent.to_sat(),
+            }
+        }
+    }
+
+    impl From<crate::validator::TaskState> for get_enforcer_status_response::TaskState {
+        fn from(task_state: crate::validator::TaskState) -> Self {
+            use crate::validator::TaskState;
+            match task_state {
+                TaskState::Running => Self::Running,
+                TaskState::Degraded => Self::Degraded,
+                TaskState::Stopped => Self::Stopped,
+            }
+        }
+    }
+
+    impl From<crate::validator::TaskErrorKind> for get_enforcer_status_response::ErrorKind {
+        fn from(kind: crate::validator::TaskErrorKind) -> Self {
+            use crate::validator::TaskErrorKind;
+            match kind {
+                TaskErrorKind::NonFatal => Self::NonFatal,
+                TaskErrorKind::Recoverable => Self::Recoverable,
+                TaskErrorKind::Fatal => Self::Fatal,
+            }
+        }
+    }
+
+    impl From<crate::validator::TaskError> for get_enforcer_status_response::Error {
+        fn from(error: crate::validator::TaskError) -> Self {
+            let crate::validator::TaskError {
+                time,
+                kind,
+                message,
+            } = error;
+            let timestamp = time
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+            Self {
+                timestamp,
+                kind: get_enforcer_status_response::ErrorKind::from(kind) as i32,
+                message,
+            }
+        }
+    }
+
+    impl From<crate::validator::EnforcerStatus> for GetEnforcerStatusResponse {
+        fn from(status: crate::validator::EnforcerStatus) -> Self {
+            let crate::validator::EnforcerStatus {
+                task_state,
+                recent_errors,
+                sync_task_restarts,
+            } = status;
+            Self {
+                task_state: get_enforcer_status_response::TaskState::from(task_state) as i32,
+                recent_errors: recent_errors.into_iter().map(Into::into).collect(),
+                sync_task_restarts,
+            }
+        }
+    }
+
+    impl From<crate::types::Bip300Transaction> for get_bip300_transaction_response::Transaction {
+        fn from(transaction: crate::types::Bip300Transaction) -> Self {
+            let crate::types::Bip300Transaction { block_hash, kind } = transaction;
+            let (kind, sidechain_number, accepted) = match kind {
+                crate::types::Bip300TransactionKind::Coinbase => {
+                    (Bip300TransactionKind::Coinbase, None, None)
+                }
+                crate::types::Bip300TransactionKind::M5Deposit { sidechain_number } => (
+                    Bip300TransactionKind::M5Deposit,
+                    Some(sidechain_number),
+                    None,
+                ),
+                crate::types::Bip300TransactionKind::M6Withdrawal { sidechain_number } => (
+                    Bip300TransactionKind::M6Withdrawal,
+                    Some(sidechain_number),
+                    None,
+                ),
+                crate::types::Bip300TransactionKind::M8BmmRequest { accepted } => {
+                    (Bip300TransactionKind::M8BmmRequest, None, Some(accepted))
+                }
+            };
+            Self {
+                block_hash: Some(ReverseHex::encode(&block_hash)),
+                kind: kind as i32,
+                sidechain_number: sidechain_number
+                    .map(|sidechain_number| sidechain_number.0 as u32),
+                accepted,
+            }
+        }
+    }
+
+    /// Active sidechain, with a flag that is `true` if there is a pending
+    /// proposal to replace it
+    impl From<(crate::types::Sidechain, bool)> for get_sidechains_response::SidechainInfo {
+        fn from((sidechain, replacement_pending): (crate::types::Sidechain, bool)) -> Self {
+            let description_hash = sidechain.proposal.description.sha256d_hash();
+            // Descriptions that are not valid declarations are returned
+            // without a parsed declaration
+            let declaration =
+                crate::types::SidechainDeclaration::try_from(&sidechain.proposal.description)
+                    .map(SidechainDeclaration::from)
+                    .ok();
             Self {
                 sidechain_number: Some(sidechain.proposal.sidechain_number.0 as u32),
                 description: Some(ConsensusHex::encode(&sidechain.proposal.description.0)),
+                declaration,
+                description_sha256d_hash: Some(ReverseHex::encode(&description_hash)),
                 vote_count: Some(sidechain.status.vote_count as u32),
                 proposal_height: Some(sidechain.status.proposal_height),
                 activation_height: sidechain.status.activation_height,
+                replacement_pending: Some(replacement_pending),
             }
         }
     }
@@ -0,0 +1,323 @@
+//! Per-peer rate limiting and concurrency caps for the gRPC server.
+//!
+//! Peers are identified by IP address. Requests over the limits are
+//! rejected with `RESOURCE_EXHAUSTED`, rather than queued, so that a
+//! misbehaving client cannot hold open DB read transactions or event
+//! subscriptions at the expense of other clients.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures::future::{self, BoxFuture, Either, FutureExt as _};
+use http_body::{Body, Frame, SizeHint};
+use parking_lot::Mutex;
+use tonic::{
+    body::BoxBody,
+    transport::server::{TcpConnectInfo, TlsConnectInfo},
+    Status,
+};
+use tower::{Layer, Service};
+
+/// Once more than this many peers are tracked, peers with no in-flight
+/// requests and a full token bucket are forgotten
+const MAX_TRACKED_PEERS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimitConfig {
+    /// Max requests per second, per peer. Bursts of up to this many
+    /// requests are allowed.
+    pub max_requests_per_sec: Option<NonZeroU32>,
+    /// Max in-flight requests per peer, including open streams
+    pub max_concurrent_requests: Option<NonZeroU32>,
+}
+
+#[derive(Debug)]
+struct PeerState {
+    /// Token bucket for rate limiting
+    tokens: f64,
+    last_refill: Instant,
+    in_flight: u32,
+}
+
+impl PeerState {
+    fn new(config: &RateLimitConfig, now: Instant) -> Self {
+        Self {
+            tokens: config
+                .max_requests_per_sec
+                .map_or(0., |rate| rate.get() as f64),
+            last_refill: now,
+            in_flight: 0,
+        }
+    }
+
+    fn refill(&mut self, config: &RateLimitConfig, now: Instant) {
+        if let Some(rate) = config.max_requests_per_sec {
+            let rate = rate.get() as f64;
+            let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+            self.tokens = (self.tokens + (elapsed * rate)).min(rate);
+        }
+        self.last_refill = now;
+    }
+
+    fn is_idle(&mut self, config: &RateLimitConfig, now: Instant) -> bool {
+        self.refill(config, now);
+        let bucket_full = match config.max_requests_per_sec {
+            Some(rate) => self.tokens >= rate.get() as f64,
+            None => true,
+        };
+        self.in_flight == 0 && bucket_full
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Rejection {
+    RateLimited,
+    TooManyConcurrentRequests,
+}
+
+impl From<Rejection> for Status {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::RateLimited => Status::resource_exhausted("request rate limit exceeded"),
+            Rejection::TooManyConcurrentRequests => {
+                Status::resource_exhausted("too many concurrent requests")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Limiter {
+    config: RateLimitConfig,
+    peers: Mutex<HashMap<IpAddr, PeerState>>,
+}
+
+impl Limiter {
+    fn acquire(self: &Arc<Self>, peer: IpAddr) -> Result<InFlightGuard, Rejection> {
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        if peers.len() > MAX_TRACKED_PEERS {
+            peers.retain(|_, state| !state.is_idle(&self.config, now));
+        }
+        let state = peers
+            .entry(peer)
+            .or_insert_with(|| PeerState::new(&self.config, now));
+        if let Some(max) = self.config.max_concurrent_requests {
+            if state.in_flight >= max.get() {
+                return Err(Rejection::TooManyConcurrentRequests);
+            }
+        }
+        if self.config.max_requests_per_sec.is_some() {
+            state.refill(&self.config, now);
+            if state.tokens < 1. {
+                return Err(Rejection::RateLimited);
+            }
+            state.tokens -= 1.;
+        }
+        state.in_flight += 1;
+        drop(peers);
+        Ok(InFlightGuard {
+            limiter: Arc::clone(self),
+            peer,
+        })
+    }
+}
+
+/// Counts as an in-flight request for the peer until dropped
+#[derive(Debug)]
+struct InFlightGuard {
+    limiter: Arc<Limiter>,
+    peer: IpAddr,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut peers = self.limiter.peers.lock();
+        if let Some(state) = peers.get_mut(&self.peer) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// Response body that holds an in-flight guard, so that streaming
+/// responses count towards the concurrency cap until they end
+struct GuardedBody {
+    inner: BoxBody,
+    _guard: InFlightGuard,
+}
+
+impl Body for GuardedBody {
+    type Data = <BoxBody as Body>::Data;
+    type Error = <BoxBody as Body>::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn peer_ip<B>(request: &http::Request<B>) -> Option<IpAddr> {
+    let extensions = request.extensions();
+    let remote_addr = match extensions.get::<TcpConnectInfo>() {
+        Some(connect_info) => connect_info.remote_addr(),
+        None => extensions
+            .get::<TlsConnectInfo<TcpConnectInfo>>()?
+            .get_ref()
+            .remote_addr(),
+    };
+    remote_addr.map(|addr| addr.ip())
+}
+
+/// Layer that applies [`RateLimitConfig`] to each peer
+#[derive(Clone, Debug)]
+pub struct RateLimitLayer {
+    limiter: Option<Arc<Limiter>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let enabled =
+            config.max_requests_per_sec.is_some() || config.max_concurrent_requests.is_some();
+        let limiter = enabled.then(|| {
+            Arc::new(Limiter {
+                config,
+                peers: Mutex::new(HashMap::new()),
+            })
+        });
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Option<Arc<Limiter>>,
+}
+
+impl<S, B> Service<http::Request<B>> for RateLimit<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Error: 'static,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Either<
+        future::Ready<Result<Self::Response, Self::Error>>,
+        BoxFuture<'static, Result<Self::Response, Self::Error>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let guard = match (&self.limiter, peer_ip(&request)) {
+            (Some(limiter), Some(peer)) => match limiter.acquire(peer) {
+                Ok(guard) => Some(guard),
+                Err(rejection) => {
+                    tracing::debug!("Rejecting gRPC request from {peer}: {rejection:?}");
+                    let response = Status::from(rejection).into_http();
+                    return Either::Left(future::ready(Ok(response)));
+                }
+            },
+            _ => None,
+        };
+        let response = self.inner.call(request);
+        let response = response.map(move |response| {
+            let response = response?;
+            Ok(match guard {
+                Some(guard) => response.map(|body| {
+                    tonic::body::boxed(GuardedBody {
+                        inner: body,
+                        _guard: guard,
+                    })
+                }),
+                None => response,
+            })
+        });
+        Either::Right(response.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr},
+        num::NonZeroU32,
+        sync::Arc,
+    };
+
+    use parking_lot::Mutex;
+
+    use super::{Limiter, RateLimitConfig, Rejection};
+
+    fn limiter(config: RateLimitConfig) -> Arc<Limiter> {
+        Arc::new(Limiter {
+            config,
+            peers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const OTHER_PEER: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = limiter(RateLimitConfig {
+            max_requests_per_sec: NonZeroU32::new(3),
+            max_concurrent_requests: None,
+        });
+        for _ in 0..3 {
+            let _guard = limiter.acquire(PEER).unwrap();
+        }
+        assert_eq!(limiter.acquire(PEER).unwrap_err(), Rejection::RateLimited);
+        assert!(limiter.acquire(OTHER_PEER).is_ok());
+    }
+
+    #[test]
+    fn test_concurrency_cap() {
+        let limiter = limiter(RateLimitConfig {
+            max_requests_per_sec: None,
+            max_concurrent_requests: NonZeroU32::new(2),
+        });
+        let first = limiter.acquire(PEER).unwrap();
+        let _second = limiter.acquire(PEER).unwrap();
+        assert_eq!(
+            limiter.acquire(PEER).unwrap_err(),
+            Rejection::TooManyConcurrentRequests
+        );
+        assert!(limiter.acquire(OTHER_PEER).is_ok());
+        drop(first);
+        assert!(limiter.acquire(PEER).is_ok());
+    }
+}
@@ -0,0 +1,280 @@
+//! HTTP gateway for the read-only query API, with JSON responses.
+//!
+//! Routes mirror the validator service's gRPC methods, eg.
+//! `GET /v1/chain/tip` for `GetChainTip`. Errors are returned with an
+//! appropriate status code and a body of the form `{"error": "<message>"}`.
+//!
+//! Events are pushed over a WebSocket at `/v1/events`, as JSON text
+//! messages.
+
+use std::{collections::BTreeSet, future::Future, net::SocketAddr, num::NonZeroU32};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use bitcoin::BlockHash;
+use futures::StreamExt as _;
+use miette::IntoDiagnostic as _;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{json, types::SidechainNumber, validator::Validator};
+
+#[derive(Debug)]
+enum Error {
+    BadRequest(String),
+    NotFound(String),
+    Internal(miette::Report),
+}
+
+impl From<miette::Report> for Error {
+    fn from(err: miette::Report) -> Self {
+        Self::Internal(err)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Self::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            Self::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            Self::Internal(err) => {
+                tracing::error!("REST gateway error: {err:#}");
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"))
+            }
+        };
+        (status, Json(json!({ "error": message }))).into_response()
+    }
+}
+
+fn parse_block_hash(s: &str) -> Result<BlockHash, Error> {
+    s.parse()
+        .map_err(|err| Error::BadRequest(format!("invalid block hash `{s}`: {err}")))
+}
+
+async fn get_chain_info(State(validator): State<Validator>) -> Json<Value> {
+    Json(json!({ "network": validator.network().to_string() }))
+}
+
+async fn get_chain_tip(State(validator): State<Validator>) -> Result<Json<Value>, Error> {
+    let tip = validator.get_mainchain_tip()?;
+    let header_info = validator.get_header_info(&tip).into_diagnostic()?;
+    Ok(Json(json::header_info(&header_info)))
+}
+
+async fn get_block_header_info(
+    State(validator): State<Validator>,
+    Path(block_hash): Path<String>,
+) -> Result<Json<Value>, Error> {
+    let block_hash = parse_block_hash(&block_hash)?;
+    let header_info = validator.get_header_info(&block_hash).into_diagnostic()?;
+    Ok(Json(json::header_info(&header_info)))
+}
+
+async fn get_sidechains(State(validator): State<Validator>) -> Result<Json<Value>, Error> {
+    let sidechains = validator.get_active_sidechains()?;
+    let sidechains: Vec<_> = sidechains.iter().map(json::sidechain).collect();
+    Ok(Json(json!({ "sidechains": sidechains })))
+}
+
+async fn get_sidechain_proposals(State(validator): State<Validator>) -> Result<Json<Value>, Error> {
+    let proposals = validator.get_sidechain_proposals()?;
+    let proposals: Vec<_> = proposals
+        .iter()
+        .map(|(description_hash, sidechain, status)| {
+            json::sidechain_proposal(description_hash, sidechain, status)
+        })
+        .collect();
+    Ok(Json(json!({ "sidechain_proposals": proposals })))
+}
+
+async fn get_ctip(
+    State(validator): State<Validator>,
+    Path(sidechain_number): Path<u8>,
+) -> Result<Json<Value>, Error> {
+    let sidechain_number = SidechainNumber::from(sidechain_number);
+    let Some(ctip) = validator.try_get_ctip(sidechain_number)? else {
+        return Err(Error::NotFound(format!(
+            "no CTIP for sidechain {}",
+            sidechain_number.0
+        )));
+    };
+    let sequence_number = validator
+        .get_ctip_sequence_number(sidechain_number)?
+        .ok_or_else(|| miette::miette!("missing CTIP sequence number"))?;
+    Ok(Json(json::ctip(&ctip, sequence_number)))
+}
+
+#[derive(Debug, Deserialize)]
+struct TwoWayPegDataQuery {
+    end_block_hash: String,
+    start_block_hash: Option<String>,
+    min_confirmations: Option<NonZeroU32>,
+}
+
+async fn get_two_way_peg_data(
+    State(validator): State<Validator>,
+    Path(sidechain_number): Path<u8>,
+    Query(query): Query<TwoWayPegDataQuery>,
+) -> Result<Json<Value>, Error> {
+    let sidechain_number = SidechainNumber::from(sidechain_number);
+    let end_block_hash = parse_block_hash(&query.end_block_hash)?;
+    let start_block_hash = query
+        .start_block_hash
+        .as_deref()
+        .map(parse_block_hash)
+        .transpose()?;
+    let two_way_peg_data = validator
+        .get_two_way_peg_data(start_block_hash, end_block_hash, query.min_confirmations)
+        .into_diagnostic()?;
+    let blocks: Vec<_> = two_way_peg_data
+        .iter()
+        .filter_map(|two_way_peg_data| json::two_way_peg_data(two_way_peg_data, sidechain_number))
+        .collect();
+    Ok(Json(json!({ "blocks": blocks })))
+}
+
+async fn get_withdrawal_bundle_queue(
+    State(validator): State<Validator>,
+    Path(sidechain_number): Path<u8>,
+) -> Result<Json<Value>, Error> {
+    let sidechain_number = SidechainNumber::from(sidechain_number);
+    let queue = validator.get_withdrawal_bundle_queue(sidechain_number)?;
+    let bundles: Vec<_> = queue
+        .iter()
+        .map(|(pending_m6id, age)| json::pending_withdrawal_bundle(pending_m6id, *age))
+        .collect();
+    Ok(Json(json!({ "bundles": bundles })))
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// Comma-separated sidechain numbers. If not set, events for all
+    /// sidechains are pushed.
+    sidechains: Option<String>,
+    start_sequence: Option<u64>,
+}
+
+fn parse_sidechains(s: &str) -> Result<BTreeSet<SidechainNumber>, Error> {
+    s.split(',')
+        .map(|sidechain_number| {
+            sidechain_number
+                .trim()
+                .parse::<u8>()
+                .map(SidechainNumber::from)
+                .map_err(|err| {
+                    Error::BadRequest(format!(
+                        "invalid sidechain number `{sidechain_number}`: {err}"
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// Push events to the client until the client disconnects or the events
+/// stream ends
+async fn push_events(
+    mut socket: WebSocket,
+    validator: Validator,
+    start_sequence: Option<u64>,
+    sidechains: Option<BTreeSet<SidechainNumber>>,
+) {
+    let mut events = std::pin::pin!(validator.subscribe_events(start_sequence));
+    loop {
+        tokio::select! {
+            res = events.next() => {
+                let (sequence, event) = match res {
+                    Some(Ok(event)) => event,
+                    Some(Err(err)) => {
+                        tracing::error!("Error in events stream, closing WebSocket: {err:#}");
+                        return;
+                    }
+                    None => {
+                        // Event streams only end once the sync task has
+                        // stopped
+                        let reason = if validator.is_shutting_down() {
+                            "the enforcer is shutting down"
+                        } else {
+                            "the enforcer has stopped syncing"
+                        };
+                        let close = CloseFrame {
+                            code: close_code::AWAY,
+                            reason: reason.into(),
+                        };
+                        let _: Result<(), _> = socket.send(Message::Close(Some(close))).await;
+                        return;
+                    }
+                };
+                let Some(body) = json::event(sequence, &event, sidechains.as_ref()) else {
+                    continue;
+                };
+                if socket.send(Message::Text(body.to_string())).await.is_err() {
+                    return;
+                }
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                // Pings are answered automatically
+                Some(Ok(_)) => (),
+            }
+        }
+    }
+}
+
+async fn subscribe_events(
+    State(validator): State<Validator>,
+    Query(query): Query<EventsQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let sidechains = query
+        .sidechains
+        .as_deref()
+        .map(parse_sidechains)
+        .transpose()?;
+    Ok(ws
+        .on_upgrade(move |socket| push_events(socket, validator, query.start_sequence, sidechains)))
+}
+
+pub fn router(validator: Validator) -> Router {
+    Router::new()
+        .route("/v1/chain/info", get(get_chain_info))
+        .route("/v1/chain/tip", get(get_chain_tip))
+        .route("/v1/blocks/:block_hash/header", get(get_block_header_info))
+        .route("/v1/sidechains", get(get_sidechains))
+        .route("/v1/sidechains/proposals", get(get_sidechain_proposals))
+        .route("/v1/sidechains/:slot/ctip", get(get_ctip))
+        .route(
+            "/v1/sidechains/:slot/two_way_peg_data",
+            get(get_two_way_peg_data),
+        )
+        .route(
+            "/v1/sidechains/:slot/withdrawal_bundles",
+            get(get_withdrawal_bundle_queue),
+        )
+        .route("/v1/events", get(subscribe_events))
+        .with_state(validator)
+}
+
+/// Serve the REST gateway on the specified address, until `shutdown`
+/// completes and in-flight requests have finished
+pub async fn serve<F>(
+    validator: Validator,
+    addr: SocketAddr,
+    shutdown: F,
+) -> Result<(), std::io::Error>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Serving REST gateway at {}", listener.local_addr()?);
+    axum::serve(listener, router(validator))
+        .with_graceful_shutdown(shutdown)
+        .await
+}
@@ -1,9 +1,1003 @@
-use bip300301::jsonrpsee::http_client::HttpClient;
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::Engine as _;
+use bip300301::jsonrpsee::{
+    core::{
+        async_trait,
+        client::{BatchResponse, ClientT},
+        params::BatchRequestBuilder,
+        rpc_params,
+        traits::ToRpcParams,
+        ClientError,
+    },
+    http_client::{transport, HeaderMap, HeaderValue, HttpClient, HttpClientBuilder},
+};
+use bitcoin::{
+    block::{Header, Version},
+    consensus::encode::FromHexError,
+    error::UnprefixedHexError,
+    hashes::Hash as _,
+    p2p::Magic,
+    Amount, Block, BlockHash, CompactTarget, FeeRate, OutPoint, ScriptBuf, Transaction,
+    TxMerkleNode, TxOut, Txid,
+};
+use futures::{future::try_join_all, TryStreamExt as _};
 use miette::{miette, IntoDiagnostic};
+use parking_lot::RwLock;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::value::RawValue;
+use thiserror::Error;
+use tokio::sync::{watch, Mutex, MutexGuard};
+
+use crate::{cli::NodeRpcConfig, p2p};
+
+/// Convert a fee rate in BTC/kvB, as returned by the node, to a [`FeeRate`].
+/// Returns `None` for invalid fee rates.
+pub fn fee_rate_from_btc_per_kvb(btc_per_kvb: f64) -> Option<FeeRate> {
+    let sat_per_kvb = Amount::from_btc(btc_per_kvb).ok()?.to_sat();
+    // 1 kvB = 4 kwu
+    Some(FeeRate::from_sat_per_kwu(sat_per_kvb / 4))
+}
+
+/// Fee rate estimates and mempool info, as reported by the node
+#[derive(Clone, Copy, Debug)]
+pub struct FeeInfo {
+    /// Estimated fee rate for confirmation within
+    /// [`Self::SHORT_TERM_CONF_TARGET`] blocks, if the node can estimate it
+    pub short_term: Option<FeeRate>,
+    /// Estimated fee rate for confirmation within
+    /// [`Self::MEDIUM_TERM_CONF_TARGET`] blocks, if the node can estimate it
+    pub medium_term: Option<FeeRate>,
+    /// Estimated fee rate for confirmation within
+    /// [`Self::LONG_TERM_CONF_TARGET`] blocks, if the node can estimate it
+    pub long_term: Option<FeeRate>,
+    pub min_relay_fee: FeeRate,
+    /// Minimum fee rate for transactions to be accepted into the mempool
+    pub mempool_min_fee: FeeRate,
+    pub mempool_tx_count: u64,
+    /// Total vsize of transactions in the mempool
+    pub mempool_vsize: u64,
+}
+
+impl FeeInfo {
+    pub const SHORT_TERM_CONF_TARGET: u16 = 2;
+    pub const MEDIUM_TERM_CONF_TARGET: u16 = 6;
+    pub const LONG_TERM_CONF_TARGET: u16 = 144;
+
+    const CONF_TARGETS: [u16; 3] = [
+        Self::SHORT_TERM_CONF_TARGET,
+        Self::MEDIUM_TERM_CONF_TARGET,
+        Self::LONG_TERM_CONF_TARGET,
+    ];
+}
+
+/// Retry policy for node RPC requests
+#[derive(Clone, Copy, Debug)]
+pub struct RetryConfig {
+    /// Max number of retries for a single request
+    pub max_retries: u32,
+    /// Delay before the first retry. Doubled for each subsequent retry.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+/// Outputs spent by the transactions in a block
+pub type Prevouts = HashMap<OutPoint, TxOut>;
+
+/// Minimum Bitcoin Core version that supports `getblock` with verbosity 3,
+/// which includes prevouts
+const GETBLOCK_PREVOUTS_MIN_VERSION: u32 = 230000;
+
+/// Name of the BIP158 basic block filter index, in `getindexinfo`
+const BASIC_BLOCK_FILTER_INDEX: &str = "basic block filter";
+
+/// Name of the transaction index, in `getindexinfo`
+const TX_INDEX: &str = "txindex";
+
+/// Minimum supported Bitcoin Core version. Earlier versions do not support
+/// `getindexinfo` or signet.
+pub const NODE_MIN_VERSION: u32 = 210000;
+
+/// ZMQ notification type for `sequence`, in `getzmqnotifications`
+pub const ZMQ_PUB_SEQUENCE: &str = "pubsequence";
+
+/// ZMQ notification type for `rawtx`, in `getzmqnotifications`
+pub const ZMQ_PUB_RAWTX: &str = "pubrawtx";
+
+/// ZMQ notification published by a node, from `getzmqnotifications`
+#[derive(Clone, Debug, Deserialize)]
+pub struct ZmqNotification {
+    /// Notification type, eg. `pubsequence`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub address: String,
+}
+
+/// Version and optional features of a node, checked on startup
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    pub version: u32,
+    pub subversion: String,
+    /// Height of the first block that the node has block data for, if the
+    /// node is pruned
+    pub prune_height: Option<u32>,
+    pub txindex: bool,
+    pub block_filter_index: bool,
+    pub zmq_notifications: Vec<ZmqNotification>,
+}
+
+impl NodeInfo {
+    /// Address that the node publishes the specified ZMQ notification on,
+    /// if any
+    pub fn zmq_address(&self, kind: &str) -> Option<&str> {
+        self.zmq_notifications
+            .iter()
+            .find(|notification| notification.kind == kind)
+            .map(|notification| notification.address.as_str())
+    }
+
+    /// Address to connect to for the specified ZMQ notification, if the node
+    /// publishes it. Wildcard binds (eg. `tcp://0.0.0.0:29000`) are rewritten
+    /// to connect to `node_host`.
+    pub fn zmq_connect_address(&self, kind: &str, node_host: IpAddr) -> Option<String> {
+        let address = self.zmq_address(kind)?;
+        let Some((host, port)) = address
+            .strip_prefix("tcp://")
+            .and_then(|host_port| host_port.rsplit_once(':'))
+        else {
+            return Some(address.to_owned());
+        };
+        match host {
+            "0.0.0.0" | "*" | "[::]" => Some(match node_host {
+                IpAddr::V4(node_host) => format!("tcp://{node_host}:{port}"),
+                IpAddr::V6(node_host) => format!("tcp://[{node_host}]:{port}"),
+            }),
+            _ => Some(address.to_owned()),
+        }
+    }
+}
+
+impl std::fmt::Display for NodeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (version {}), txindex: {}, block filter index: {}, ",
+            self.subversion, self.version, self.txindex, self.block_filter_index
+        )?;
+        match self.prune_height {
+            Some(prune_height) => write!(f, "pruned to height {prune_height}, ")?,
+            None => write!(f, "not pruned, ")?,
+        }
+        write!(f, "ZMQ notifications: ")?;
+        if self.zmq_notifications.is_empty() {
+            return write!(f, "none");
+        }
+        for (idx, notification) in self.zmq_notifications.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} on {}", notification.kind, notification.address)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum GetBlocksWithPrevoutsError {
+    #[error("Invalid `bits` in block `{block_hash}`")]
+    Bits {
+        block_hash: BlockHash,
+        source: UnprefixedHexError,
+    },
+    #[error("Block `{block_hash}` does not match reconstructed block `{reconstructed}`")]
+    BlockHashMismatch {
+        block_hash: BlockHash,
+        reconstructed: BlockHash,
+    },
+    #[error("Failed to decode transaction in block `{block_hash}`")]
+    DecodeTx {
+        block_hash: BlockHash,
+        source: FromHexError,
+    },
+    #[error(transparent)]
+    JsonRpc(#[from] ClientError),
+}
+
+#[derive(Debug, Deserialize)]
+struct VerboseScriptPubKey {
+    hex: ScriptBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerbosePrevout {
+    #[serde(with = "bitcoin::amount::serde::as_btc")]
+    value: Amount,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: VerboseScriptPubKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerboseTxIn {
+    /// Absent for coinbase inputs
+    prevout: Option<VerbosePrevout>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerboseTx {
+    hex: String,
+    vin: Vec<VerboseTxIn>,
+}
+
+/// Response of `getblock` with verbosity 3
+#[derive(Debug, Deserialize)]
+struct VerboseBlock {
+    hash: BlockHash,
+    version: i32,
+    merkleroot: TxMerkleNode,
+    time: u32,
+    bits: String,
+    nonce: u32,
+    /// Absent for the genesis block
+    previousblockhash: Option<BlockHash>,
+    tx: Vec<VerboseTx>,
+}
+
+impl VerboseBlock {
+    fn into_block(self) -> Result<(Block, Prevouts), GetBlocksWithPrevoutsError> {
+        let block_hash = self.hash;
+        let bits = CompactTarget::from_unprefixed_hex(&self.bits)
+            .map_err(|source| GetBlocksWithPrevoutsError::Bits { block_hash, source })?;
+        let header = Header {
+            version: Version::from_consensus(self.version),
+            prev_blockhash: self.previousblockhash.unwrap_or_else(BlockHash::all_zeros),
+            merkle_root: self.merkleroot,
+            time: self.time,
+            bits,
+            nonce: self.nonce,
+        };
+        let mut txdata = Vec::with_capacity(self.tx.len());
+        let mut prevouts = Prevouts::new();
+        for verbose_tx in self.tx {
+            let tx: Transaction = bitcoin::consensus::encode::deserialize_hex(&verbose_tx.hex)
+                .map_err(|source| GetBlocksWithPrevoutsError::DecodeTx { block_hash, source })?;
+            prevouts.extend(tx.input.iter().zip(verbose_tx.vin).filter_map(
+                |(input, verbose_input)| {
+                    let prevout = verbose_input.prevout?;
+                    let txout = TxOut {
+                        value: prevout.value,
+                        script_pubkey: prevout.script_pubkey.hex,
+                    };
+                    Some((input.previous_output, txout))
+                },
+            ));
+            txdata.push(tx);
+        }
+        let block = Block { header, txdata };
+        let reconstructed = block.block_hash();
+        if reconstructed != block_hash || !block.check_merkle_root() {
+            return Err(GetBlocksWithPrevoutsError::BlockHashMismatch {
+                block_hash,
+                reconstructed,
+            });
+        }
+        Ok((block, prevouts))
+    }
+}
+
+/// Params that have already been serialized, so that requests can be retried
+struct RawParams(Option<Box<RawValue>>);
+
+impl ToRpcParams for RawParams {
+    fn to_rpc_params(self) -> Result<Option<Box<RawValue>>, serde_json::Error> {
+        Ok(self.0)
+    }
+}
+
+/// Returns `true` if the node rejected the request due to invalid
+/// credentials
+fn is_auth_failure(err: &ClientError) -> bool {
+    let ClientError::Transport(err) = err else {
+        return false;
+    };
+    matches!(
+        err.downcast_ref::<transport::Error>(),
+        Some(transport::Error::Rejected { status_code: 401 })
+    )
+}
+
+/// Returns `true` if a request that failed with this error may succeed if
+/// retried. JSON-RPC errors returned by the node, and authentication
+/// failures, are not retried.
+fn is_transient(err: &ClientError) -> bool {
+    match err {
+        ClientError::Transport(_) => !is_auth_failure(err),
+        ClientError::RestartNeeded(_) | ClientError::RequestTimeout => true,
+        _ => false,
+    }
+}
+
+/// Returns `true` if `method` only reads node state, so that it can be
+/// retried safely. Other requests, eg. `submitblock` and
+/// `sendrawtransaction`, may have been processed by the node before failing
+/// with a transport error or timeout, and are not retried.
+fn is_read_method(method: &str) -> bool {
+    match method {
+        "getnewaddress" | "getrawchangeaddress" => false,
+        "estimatesmartfee" | "finalizepsbt" | "testmempoolaccept" | "uptime" => true,
+        _ => ["decode", "get", "list"]
+            .iter()
+            .any(|prefix| method.starts_with(prefix)),
+    }
+}
+
+/// Bitcoin Core's RPC error code for unknown txs, blocks and addresses
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+/// Returns `true` if the node does not know the requested tx, block or
+/// address
+pub fn is_not_found(err: &ClientError) -> bool {
+    matches!(err, ClientError::Call(err) if err.code() == RPC_INVALID_ADDRESS_OR_KEY)
+}
+
+/// Returns `true` if the node failed to return a block because it has been
+/// pruned
+pub fn is_block_pruned(err: &ClientError) -> bool {
+    matches!(err, ClientError::Call(err) if err.message() == "Block not available (pruned data)")
+}
+
+/// Read RPC user and password from a Bitcoin Core cookie file
+fn read_cookie(cookie_path: &str) -> Result<(String, String), miette::Report> {
+    let auth = std::fs::read_to_string(cookie_path)
+        .map_err(|err| miette!("unable to read bitcoind cookie at {}: {}", cookie_path, err))?;
+    let (user, pass) = auth
+        .trim_end()
+        .split_once(':')
+        .ok_or(miette!("failed to get rpcuser and rpcpassword from cookie"))?;
+    Ok((user.to_owned(), pass.to_owned()))
+}
+
+#[derive(Debug, Error)]
+pub enum RestError {
+    #[error("Failed to decode block `{block_hash}` from REST response")]
+    Decode {
+        block_hash: BlockHash,
+        source: bitcoin::consensus::encode::Error,
+    },
+    #[error("REST request for block `{block_hash}` failed")]
+    Request {
+        block_hash: BlockHash,
+        source: reqwest::Error,
+    },
+}
+
+/// Client for Bitcoin Core's REST interface
+#[derive(Clone, Debug)]
+struct RestClient {
+    base_url: Arc<str>,
+    client: reqwest::Client,
+}
+
+impl RestClient {
+    fn new(base_url: &reqwest::Url, request_timeout: Duration) -> Result<Self, miette::Report> {
+        let client = reqwest::Client::builder()
+            .timeout(request_timeout)
+            .build()
+            .into_diagnostic()?;
+        Ok(Self {
+            base_url: Arc::from(base_url.as_str().trim_end_matches('/')),
+            client,
+        })
+    }
+
+    async fn get_block(&self, block_hash: BlockHash) -> Result<Block, RestError> {
+        let url = format!("{}/rest/block/{block_hash}.bin", self.base_url);
+        let bytes = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|source| RestError::Request { block_hash, source })?
+            .bytes()
+            .await
+            .map_err(|source| RestError::Request { block_hash, source })?;
+        bitcoin::consensus::deserialize(&bytes)
+            .map_err(|source| RestError::Decode { block_hash, source })
+    }
+}
+
+/// P2P connection to the primary node. The connection is established
+/// lazily, and re-established after errors.
+#[derive(Debug)]
+struct P2pNode {
+    addr: SocketAddr,
+    magic: Magic,
+    peer: Mutex<Option<p2p::Peer>>,
+    /// Transactions published via ZMQ `rawtx`, for reconstructing compact
+    /// blocks
+    mempool: parking_lot::Mutex<p2p::Mempool>,
+}
+
+impl P2pNode {
+    /// Connect to the node, if not already connected
+    async fn connected_peer<'a>(
+        &self,
+        slot: &'a mut Option<p2p::Peer>,
+    ) -> Result<&'a mut p2p::Peer, p2p::Error> {
+        let peer = match slot.take() {
+            Some(peer) => peer,
+            None => p2p::Peer::connect(self.addr, self.magic).await?,
+        };
+        Ok(slot.insert(peer))
+    }
+
+    /// Drop the connection if a request failed, since it may be in an
+    /// inconsistent state, eg. if a response was only partially read
+    fn reset_on_err<T>(peer: &mut Option<p2p::Peer>, res: &Result<T, p2p::Error>) {
+        if res.is_err() {
+            *peer = None;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    rpc_addr: SocketAddr,
+    /// Replaced when the cookie is re-read
+    client: RwLock<HttpClient>,
+}
+
+/// Max size of a JSON-RPC response, in bytes.
+/// Batched `getblock` responses with verbosity 3 can be several hundred MB
+/// for full blocks, which exceeds jsonrpsee's default limit of 10 MB.
+const MAX_RESPONSE_SIZE: u32 = 1024 * 1024 * 1024;
+
+/// HTTP client for a node's JSON-RPC interface, with basic auth
+fn http_client(rpc_addr: SocketAddr, user: &str, pass: &str) -> Result<HttpClient, miette::Report> {
+    let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{user}:{pass}"));
+    let mut headers = HeaderMap::new();
+    let _: Option<HeaderValue> = headers.insert(
+        "authorization",
+        HeaderValue::from_str(&format!("Basic {credentials}")).into_diagnostic()?,
+    );
+    HttpClientBuilder::default()
+        .set_headers(headers)
+        .max_response_size(MAX_RESPONSE_SIZE)
+        .build(format!("http://{rpc_addr}"))
+        .into_diagnostic()
+}
+
+impl Node {
+    fn new(rpc_addr: SocketAddr, user: &str, pass: &str) -> Result<Self, miette::Report> {
+        let client = http_client(rpc_addr, user, pass)?;
+        Ok(Self {
+            rpc_addr,
+            client: RwLock::new(client),
+        })
+    }
+
+    fn client(&self) -> HttpClient {
+        self.client.read().clone()
+    }
+}
+
+/// JSON-RPC client for the mainchain node(s).
+/// Requests time out after the configured timeout. Read-only requests that
+/// fail due to transport errors or timeouts are retried with exponential
+/// backoff.
+/// If retries are exhausted and backup nodes are configured, the client
+/// fails over to the first healthy node after the active node.
+#[derive(Clone, Debug)]
+pub struct Client {
+    /// Primary node, followed by backup nodes
+    nodes: Arc<[Node]>,
+    /// Index of the node that requests are sent to
+    active_node: Arc<watch::Sender<usize>>,
+    /// Path to the Bitcoin Core cookie, if cookie authentication is used
+    cookie_path: Option<Arc<str>>,
+    /// REST interface of the primary node, if configured
+    rest: Option<RestClient>,
+    /// P2P connection to the primary node, if configured
+    p2p: Option<Arc<P2pNode>>,
+    request_timeout: Duration,
+    retry: RetryConfig,
+}
+
+impl Client {
+    /// Interval between health checks of the primary node, while failed
+    /// over to a backup node
+    const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// Index of the node that requests are sent to. `0` is the primary node.
+    pub fn active_node(&self) -> usize {
+        *self.active_node.borrow()
+    }
+
+    /// Subscribe to changes of the active node
+    pub fn subscribe_active_node(&self) -> watch::Receiver<usize> {
+        self.active_node.subscribe()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns `true` if the node responds to `getblockcount`
+    pub async fn is_healthy(&self, node: usize) -> bool {
+        let client = self.nodes[node].client();
+        let request = client.request::<u64, _>("getblockcount", rpc_params![]);
+        matches!(
+            tokio::time::timeout(self.request_timeout, request).await,
+            Ok(Ok(_))
+        )
+    }
+
+    /// Re-read the cookie, which changes whenever Bitcoin Core restarts, and
+    /// recreate the node clients with the new credentials.
+    /// Returns `false` if cookie authentication is not used, or if the
+    /// cookie could not be read.
+    fn reload_cookie(&self) -> bool {
+        let Some(cookie_path) = &self.cookie_path else {
+            return false;
+        };
+        let res = read_cookie(cookie_path).and_then(|(user, pass)| {
+            self.nodes.iter().try_for_each(|node| {
+                let client = http_client(node.rpc_addr, &user, &pass)?;
+                *node.client.write() = client;
+                Ok(())
+            })
+        });
+        match res {
+            Ok(()) => {
+                tracing::info!("Re-read RPC cookie at {cookie_path}");
+                true
+            }
+            Err(err) => {
+                tracing::error!("Failed to re-read RPC cookie: {err:#}");
+                false
+            }
+        }
+    }
+
+    /// Fail over from `failed_node` to the first healthy node after it.
+    /// Returns the new active node, or `None` if no other node is healthy.
+    pub async fn fail_over(&self, failed_node: usize) -> Option<usize> {
+        for offset in 1..self.nodes.len() {
+            let node = (failed_node + offset) % self.nodes.len();
+            if !self.is_healthy(node).await {
+                continue;
+            }
+            // Another request may have failed over already
+            let failed_over = self.active_node.send_if_modified(|active_node| {
+                if *active_node == failed_node {
+                    *active_node = node;
+                    true
+                } else {
+                    false
+                }
+            });
+            if failed_over {
+                tracing::warn!(
+                    "Failed over from node at {} to node at {}",
+                    self.nodes[failed_node].rpc_addr,
+                    self.nodes[node].rpc_addr
+                );
+            }
+            return Some(self.active_node());
+        }
+        None
+    }
+
+    /// While failed over to a backup node, periodically check if the
+    /// primary node is healthy, and fail back to it if so
+    pub async fn run_health_checks(self) {
+        let mut interval = tokio::time::interval(Self::HEALTH_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if self.active_node() == 0 || !self.is_healthy(0).await {
+                continue;
+            }
+            let _previous_active_node: usize = self.active_node.send_replace(0);
+            tracing::info!(
+                "Primary node at {} is reachable again, failed back",
+                self.nodes[0].rpc_addr
+            );
+        }
+    }
+
+    /// Download blocks via the REST interface of the primary node.
+    /// Returns `None` if REST is not configured, or if failed over to a
+    /// backup node, in which case blocks should be requested with
+    /// `getblock`.
+    pub async fn get_blocks_rest(
+        &self,
+        block_hashes: &[BlockHash],
+    ) -> Option<Result<Vec<Block>, RestError>> {
+        let rest = self.rest.as_ref()?;
+        if self.active_node() != 0 {
+            return None;
+        }
+        let blocks = try_join_all(
+            block_hashes
+                .iter()
+                .map(|block_hash| rest.get_block(*block_hash)),
+        )
+        .await;
+        Some(blocks)
+    }
+
+    /// Lock the P2P connection to the primary node.
+    /// Returns `None` if P2P is not configured, or if failed over to a
+    /// backup node.
+    async fn lock_p2p(&self) -> Option<(&P2pNode, MutexGuard<'_, Option<p2p::Peer>>)> {
+        let p2p_node = self.p2p.as_deref()?;
+        if self.active_node() != 0 {
+            return None;
+        }
+        Some((p2p_node, p2p_node.peer.lock().await))
+    }
 
-use crate::cli::NodeRpcConfig;
+    /// Fetch up to [`p2p::MAX_HEADERS_RESULTS`] headers from the primary
+    /// node over P2P. See [`p2p::Peer::get_headers`].
+    /// Returns `None` if P2P is not configured, or if failed over to a
+    /// backup node.
+    pub async fn get_headers_p2p(
+        &self,
+        locator_hashes: Vec<BlockHash>,
+        stop_hash: BlockHash,
+    ) -> Option<Result<Vec<Header>, p2p::Error>> {
+        let (p2p_node, mut peer) = self.lock_p2p().await?;
+        let res = tokio::time::timeout(self.request_timeout, async {
+            let peer = p2p_node.connected_peer(&mut peer).await?;
+            peer.get_headers(locator_hashes, stop_hash).await
+        })
+        .await
+        .unwrap_or(Err(p2p::Error::Timeout));
+        P2pNode::reset_on_err(&mut peer, &res);
+        drop(peer);
+        Some(res)
+    }
+
+    /// Download blocks from the primary node over P2P.
+    /// Returns `None` if P2P is not configured, or if failed over to a
+    /// backup node.
+    pub async fn get_blocks_p2p(
+        &self,
+        block_hashes: &[BlockHash],
+    ) -> Option<Result<Vec<Block>, p2p::Error>> {
+        let (p2p_node, mut peer) = self.lock_p2p().await?;
+        let res = tokio::time::timeout(self.request_timeout, async {
+            let peer = p2p_node.connected_peer(&mut peer).await?;
+            peer.get_blocks(block_hashes).await
+        })
+        .await
+        .unwrap_or(Err(p2p::Error::Timeout));
+        P2pNode::reset_on_err(&mut peer, &res);
+        drop(peer);
+        Some(res)
+    }
+
+    /// Download a block from the primary node over P2P, as a compact block.
+    /// Returns `None` if P2P is not configured, if failed over to a backup
+    /// node, or if no mempool transactions have been seen, in which case
+    /// compact blocks offer no benefit.
+    pub async fn get_block_compact_p2p(
+        &self,
+        block_hash: BlockHash,
+    ) -> Option<Result<Block, p2p::Error>> {
+        if self.p2p.as_ref()?.mempool.lock().is_empty() {
+            return None;
+        }
+        let (p2p_node, mut peer) = self.lock_p2p().await?;
+        let res = tokio::time::timeout(self.request_timeout, async {
+            let peer = p2p_node.connected_peer(&mut peer).await?;
+            peer.get_block_compact(block_hash, &p2p_node.mempool).await
+        })
+        .await
+        .unwrap_or(Err(p2p::Error::Timeout));
+        P2pNode::reset_on_err(&mut peer, &res);
+        drop(peer);
+        Some(res)
+    }
+
+    /// Look up a transaction that was recently published by the primary node
+    /// via ZMQ `rawtx`, including transactions that have since left the
+    /// mempool. Returns `None` if P2P is not configured.
+    pub fn try_get_recent_mempool_tx(&self, txid: &Txid) -> Option<Transaction> {
+        self.p2p.as_ref()?.mempool.lock().get(txid).cloned()
+    }
+
+    /// Track transactions published by the primary node via ZMQ `rawtx`, for
+    /// reconstructing compact blocks. Resubscribes after errors.
+    /// Does nothing if P2P is not configured.
+    pub async fn run_mempool_sync(self, zmq_addr_rawtx: String) {
+        const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+        let Some(p2p_node) = self.p2p else {
+            return;
+        };
+        loop {
+            let res = async {
+                let mut txs = crate::zmq::subscribe_rawtx(&zmq_addr_rawtx).await?;
+                while let Some(tx) = txs.try_next().await? {
+                    p2p_node.mempool.lock().insert(tx);
+                }
+                Ok::<_, crate::zmq::RawTxStreamError>(())
+            }
+            .await;
+            if let Err(err) = res {
+                tracing::warn!(
+                    "Error in `rawtx` subscription, resubscribing in {RESUBSCRIBE_DELAY:?}: {:#}",
+                    anyhow::Error::from(err)
+                );
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    /// Returns `true` if the active node supports `getblock` with prevouts
+    /// (verbosity 3), ie. if it is running Bitcoin Core v23 or later
+    pub async fn supports_getblock_prevouts(&self) -> Result<bool, ClientError> {
+        #[derive(Deserialize)]
+        struct NetworkInfo {
+            version: u32,
+        }
+        let network_info: NetworkInfo = self.request("getnetworkinfo", rpc_params![]).await?;
+        Ok(network_info.version >= GETBLOCK_PREVOUTS_MIN_VERSION)
+    }
+
+    /// Height of the first block that the active node has block data for,
+    /// if the node is pruned
+    pub async fn prune_height(&self) -> Result<Option<u32>, ClientError> {
+        #[derive(Deserialize)]
+        struct BlockchainInfo {
+            pruned: bool,
+            pruneheight: Option<u32>,
+        }
+        let blockchain_info: BlockchainInfo =
+            self.request("getblockchaininfo", rpc_params![]).await?;
+        if blockchain_info.pruned {
+            Ok(Some(blockchain_info.pruneheight.unwrap_or(0)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Fee rate estimates and mempool info from the active node
+    pub async fn get_fee_info(&self) -> Result<FeeInfo, ClientError> {
+        #[derive(Debug, Deserialize)]
+        struct EstimateSmartFee {
+            /// Fee rate in BTC/kvB
+            #[serde(default)]
+            feerate: Option<f64>,
+        }
+        #[derive(Deserialize)]
+        struct MempoolInfo {
+            size: u64,
+            bytes: u64,
+            /// Fee rate in BTC/kvB
+            mempoolminfee: f64,
+            /// Fee rate in BTC/kvB
+            minrelaytxfee: f64,
+        }
+        let estimates: Vec<EstimateSmartFee> = self
+            .batch_call(
+                "estimatesmartfee",
+                FeeInfo::CONF_TARGETS.map(|conf_target| rpc_params![conf_target]),
+            )
+            .await?;
+        let [short_term, medium_term, long_term] =
+            [0, 1, 2].map(|idx| estimates[idx].feerate.and_then(fee_rate_from_btc_per_kvb));
+        let mempool_info: MempoolInfo = self.request("getmempoolinfo", rpc_params![]).await?;
+        Ok(FeeInfo {
+            short_term,
+            medium_term,
+            long_term,
+            min_relay_fee: fee_rate_from_btc_per_kvb(mempool_info.minrelaytxfee)
+                .unwrap_or(FeeRate::BROADCAST_MIN),
+            mempool_min_fee: fee_rate_from_btc_per_kvb(mempool_info.mempoolminfee)
+                .unwrap_or(FeeRate::BROADCAST_MIN),
+            mempool_tx_count: mempool_info.size,
+            mempool_vsize: mempool_info.bytes,
+        })
+    }
+
+    /// Fetch blocks along with the outputs spent by their transactions,
+    /// using batched `getblock` requests with verbosity 3.
+    /// Requires Bitcoin Core v23 or later, see
+    /// [`Self::supports_getblock_prevouts`].
+    pub async fn get_blocks_with_prevouts(
+        &self,
+        block_hashes: &[BlockHash],
+    ) -> Result<Vec<(Block, Prevouts)>, GetBlocksWithPrevoutsError> {
+        let blocks: Vec<VerboseBlock> = self
+            .batch_call(
+                "getblock",
+                block_hashes
+                    .iter()
+                    .map(|block_hash| rpc_params![block_hash, 3]),
+            )
+            .await?;
+        blocks.into_iter().map(VerboseBlock::into_block).collect()
+    }
+
+    /// Version, indexes, pruning and ZMQ configuration of the active node
+    pub async fn get_node_info(&self) -> Result<NodeInfo, ClientError> {
+        #[derive(Deserialize)]
+        struct NetworkInfo {
+            version: u32,
+            subversion: String,
+        }
+        let network_info: NetworkInfo = self.request("getnetworkinfo", rpc_params![]).await?;
+        let prune_height = self.prune_height().await?;
+        let index_info: HashMap<String, serde_json::Value> =
+            self.request("getindexinfo", rpc_params![]).await?;
+        let zmq_notifications: Vec<ZmqNotification> =
+            self.request("getzmqnotifications", rpc_params![]).await?;
+        Ok(NodeInfo {
+            version: network_info.version,
+            subversion: network_info.subversion,
+            prune_height,
+            txindex: index_info.contains_key(TX_INDEX),
+            block_filter_index: index_info.contains_key(BASIC_BLOCK_FILTER_INDEX),
+            zmq_notifications,
+        })
+    }
 
-pub fn create_client(conf: &NodeRpcConfig) -> Result<HttpClient, miette::Report> {
+    /// Call `method` once for each item of `params`, in a single batch
+    /// request. Returns the results in order, or the first error.
+    pub async fn batch_call<R, P>(
+        &self,
+        method: &str,
+        params: impl IntoIterator<Item = P>,
+    ) -> Result<Vec<R>, ClientError>
+    where
+        R: DeserializeOwned + std::fmt::Debug,
+        P: ToRpcParams,
+    {
+        let mut batch = BatchRequestBuilder::new();
+        for params in params {
+            let () = batch.insert(method, params)?;
+        }
+        if batch.iter().next().is_none() {
+            return Ok(Vec::new());
+        }
+        self.batch_request::<R>(batch)
+            .await?
+            .into_iter()
+            .map(|res| res.map_err(|err| ClientError::Call(err.into_owned())))
+            .collect()
+    }
+
+    /// Requests that are not read-only are only retried if the node rejected
+    /// the credentials, see [`is_read_method`].
+    async fn with_retries<T, F, Fut>(
+        &self,
+        method: &str,
+        read_only: bool,
+        node: usize,
+        f: &mut F,
+    ) -> Result<T, ClientError>
+    where
+        F: FnMut(HttpClient) -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let mut backoff = self.retry.initial_backoff;
+        let mut retries = 0;
+        let mut cookie_reloaded = false;
+        loop {
+            let request = f(self.nodes[node].client());
+            let err = match tokio::time::timeout(self.request_timeout, request).await {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(err)) => err,
+                Err(_elapsed) => ClientError::RequestTimeout,
+            };
+            if is_auth_failure(&err) && !cookie_reloaded {
+                tracing::warn!("Node RPC request `{method}` was rejected, re-reading RPC cookie");
+                cookie_reloaded = true;
+                if self.reload_cookie() {
+                    continue;
+                }
+            }
+            if !read_only || !is_transient(&err) || retries >= self.retry.max_retries {
+                return Err(err);
+            }
+            retries += 1;
+            tracing::warn!(
+                "Node RPC request `{method}` failed (retry {retries}/{}), retrying in {backoff:?}: {err:#}",
+                self.retry.max_retries
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.retry.max_backoff);
+        }
+    }
+
+    /// Requests that are not read-only are not re-sent to the node that is
+    /// failed over to.
+    async fn with_failover<T, F, Fut>(
+        &self,
+        method: &str,
+        read_only: bool,
+        mut f: F,
+    ) -> Result<T, ClientError>
+    where
+        F: FnMut(HttpClient) -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let node = self.active_node();
+        let err = match self.with_retries(method, read_only, node, &mut f).await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        if !is_transient(&err) {
+            return Err(err);
+        }
+        let Some(node) = self.fail_over(node).await else {
+            return Err(err);
+        };
+        if !read_only {
+            return Err(err);
+        }
+        self.with_retries(method, read_only, node, &mut f).await
+    }
+}
+
+#[async_trait]
+impl ClientT for Client {
+    async fn notification<Params>(&self, method: &str, params: Params) -> Result<(), ClientError>
+    where
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        self.with_failover(method, is_read_method(method), |client| {
+            let params = RawParams(params.clone());
+            async move { client.notification(method, params).await }
+        })
+        .await
+    }
+
+    async fn request<R, Params>(&self, method: &str, params: Params) -> Result<R, ClientError>
+    where
+        R: DeserializeOwned,
+        Params: ToRpcParams + Send,
+    {
+        let params = params.to_rpc_params()?;
+        self.with_failover(method, is_read_method(method), |client| {
+            let params = RawParams(params.clone());
+            async move { client.request(method, params).await }
+        })
+        .await
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: BatchRequestBuilder<'a>,
+    ) -> Result<BatchResponse<'a, R>, ClientError>
+    where
+        R: DeserializeOwned + std::fmt::Debug + 'a,
+    {
+        let read_only = batch.iter().all(|(method, _)| is_read_method(method));
+        self.with_failover("batch", read_only, |client| {
+            let batch = batch.clone();
+            async move { client.batch_request(batch).await }
+        })
+        .await
+    }
+}
+
+/// Create a client for the primary node, and backup nodes at
+/// `backup_rpc_addrs`. Backup nodes use the same credentials as the primary
+/// node.
+pub fn create_client(
+    conf: &NodeRpcConfig,
+    backup_rpc_addrs: &[SocketAddr],
+    network: bitcoin::Network,
+) -> Result<Client, miette::Report> {
     if conf.user.is_none() != conf.pass.is_none() {
         return Err(miette!("RPC user and password must be set together"));
     }
@@ -12,28 +1006,115 @@ pub fn create_client(conf: &NodeRpcConfig) -> Result<HttpClient, miette::Report>
         return Err(miette!("precisely one of RPC user and cookie must be set"));
     }
 
-    let mut conf_user = conf.user.clone().unwrap_or_default();
-    let mut conf_pass = conf.pass.clone().unwrap_or_default();
+    let (user, pass) = match &conf.cookie_path {
+        Some(cookie_path) => read_cookie(cookie_path)?,
+        None => (
+            conf.user.clone().unwrap_or_default(),
+            conf.pass.clone().unwrap_or_default(),
+        ),
+    };
+    let nodes = std::iter::once(conf.rpc_addr(network))
+        .chain(backup_rpc_addrs.iter().copied())
+        .map(|rpc_addr| Node::new(rpc_addr, &user, &pass))
+        .collect::<Result<_, _>>()?;
+    let request_timeout = Duration::from_secs(conf.timeout_secs);
+    let rest = conf
+        .rest_url
+        .as_ref()
+        .map(|rest_url| RestClient::new(rest_url, request_timeout))
+        .transpose()?;
+    let p2p = conf.p2p_addr.map(|addr| {
+        Arc::new(P2pNode {
+            addr,
+            magic: conf.p2p_magic.unwrap_or_else(|| network.magic()),
+            peer: Mutex::new(None),
+            mempool: parking_lot::Mutex::new(p2p::Mempool::default()),
+        })
+    });
+    Ok(Client {
+        nodes,
+        active_node: Arc::new(watch::channel(0).0),
+        cookie_path: conf.cookie_path.as_deref().map(Arc::from),
+        rest,
+        p2p,
+        request_timeout,
+        retry: RetryConfig {
+            max_retries: conf.max_retries,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-    if conf.cookie_path.is_some() {
-        let cookie_path = conf.cookie_path.clone().unwrap();
-        let auth = std::fs::read_to_string(cookie_path.clone())
-            .map_err(|err| miette!("unable to read bitcoind cookie at {}: {}", cookie_path, err))?;
+    use super::{is_read_method, NodeInfo, ZmqNotification, ZMQ_PUB_RAWTX, ZMQ_PUB_SEQUENCE};
 
-        let mut auth = auth.split(':');
+    fn node_info(zmq_notifications: &[(&str, &str)]) -> NodeInfo {
+        NodeInfo {
+            version: 280000,
+            subversion: "/Satoshi:28.0.0/".to_owned(),
+            prune_height: None,
+            txindex: true,
+            block_filter_index: false,
+            zmq_notifications: zmq_notifications
+                .iter()
+                .map(|(kind, address)| ZmqNotification {
+                    kind: (*kind).to_owned(),
+                    address: (*address).to_owned(),
+                })
+                .collect(),
+        }
+    }
 
-        conf_user = auth
-            .next()
-            .ok_or(miette!("failed to get rpcuser"))?
-            .to_string()
-            .clone();
+    #[test]
+    fn zmq_connect_address_rewrites_wildcard_binds() {
+        let node_host = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let info = node_info(&[
+            (ZMQ_PUB_SEQUENCE, "tcp://0.0.0.0:29000"),
+            (ZMQ_PUB_RAWTX, "tcp://127.0.0.1:29001"),
+        ]);
+        assert_eq!(
+            info.zmq_connect_address(ZMQ_PUB_SEQUENCE, node_host)
+                .as_deref(),
+            Some("tcp://10.0.0.2:29000")
+        );
+        assert_eq!(
+            info.zmq_connect_address(ZMQ_PUB_RAWTX, node_host)
+                .as_deref(),
+            Some("tcp://127.0.0.1:29001")
+        );
+        let info = node_info(&[(ZMQ_PUB_SEQUENCE, "tcp://[::]:29000")]);
+        assert_eq!(
+            info.zmq_connect_address(ZMQ_PUB_SEQUENCE, IpAddr::V6(Ipv6Addr::LOCALHOST))
+                .as_deref(),
+            Some("tcp://[::1]:29000")
+        );
+        assert_eq!(
+            node_info(&[]).zmq_connect_address(ZMQ_PUB_SEQUENCE, node_host),
+            None
+        );
+    }
 
-        conf_pass = auth
-            .next()
-            .ok_or(miette!("failed to get rpcpassword"))?
-            .to_string()
-            .to_string()
-            .clone();
+    #[test]
+    fn only_read_methods_are_retried() {
+        for method in [
+            "getblock",
+            "getblockheader",
+            "estimatesmartfee",
+            "listunspent",
+        ] {
+            assert!(is_read_method(method), "{method}");
+        }
+        for method in [
+            "submitblock",
+            "sendrawtransaction",
+            "getnewaddress",
+            "importdescriptors",
+        ] {
+            assert!(!is_read_method(method), "{method}");
+        }
     }
-    bip300301::client(conf.addr, None, &conf_pass, &conf_user).into_diagnostic()
 }
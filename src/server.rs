@@ -1,21 +1,27 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroU32,
+    sync::Arc,
+    time::Duration,
+};
 
 use bitcoin::{
-    absolute::Height,
     hashes::{hmac, ripemd160, sha256, sha512, Hash, HashEngine},
     key::Secp256k1,
-    Amount, BlockHash, Transaction, TxOut,
+    Amount, BlockHash, Transaction, TxOut, Txid,
 };
 use futures::{
     stream::{BoxStream, FusedStream},
-    StreamExt as _,
+    Stream, StreamExt as _,
 };
 use miette::IntoDiagnostic as _;
 use thiserror::Error;
 use tonic::{Request, Response, Status};
 
 use crate::{
+    auth::Authenticator,
     convert,
+    deposit_address::{DepositAddress, DepositDestination},
     messages::CoinbaseMessage,
     proto::{
         common::{ConsensusHex, Hex, ReverseHex},
@@ -26,26 +32,70 @@ use crate::{
             Secp256k1VerifyRequest, Secp256k1VerifyResponse,
         },
         mainchain::{
-            create_sidechain_proposal_response, get_bmm_h_star_commitment_response,
-            get_ctip_response::Ctip, get_sidechain_proposals_response::SidechainProposal,
-            get_sidechains_response::SidechainInfo, server::ValidatorService,
-            wallet_service_server::WalletService, BroadcastWithdrawalBundleRequest,
-            BroadcastWithdrawalBundleResponse, CreateBmmCriticalDataTransactionRequest,
-            CreateBmmCriticalDataTransactionResponse, CreateDepositTransactionRequest,
+            broadcast_transaction_response, create_sidechain_proposal_response,
+            generate_blocks_response, get_best_bmm_requests_response, get_block_infos_response,
+            get_bmm_h_star_commitment_response, get_bmm_inclusion_status_response,
+            get_bmm_request_status_response, get_ctip_response::Ctip, get_fee_info_response,
+            get_raw_transaction_response, get_sidechain_proposals_response::SidechainProposal,
+            get_sidechains_response::SidechainInfo, get_withdrawal_bundle_queue_response,
+            import_descriptors_request, list_blocks_with_bmm_commitments_response,
+            list_pending_withdrawal_bundles_response, list_treasury_utxos_response,
+            request_bmm_request, server::ValidatorService, subscribe_headers_response,
+            wallet_service_server::WalletService, BroadcastTransactionRequest,
+            BroadcastTransactionResponse, BroadcastWithdrawalBundleRequest,
+            BroadcastWithdrawalBundleResponse, BumpBmmRequestRequest, BumpBmmRequestResponse,
+            BumpDepositFeeRequest, BumpDepositFeeResponse, CancelBmmRebidRequest,
+            CancelBmmRebidResponse, CompactDatabaseRequest, CompactDatabaseResponse,
+            CreateBmmCriticalDataTransactionRequest, CreateBmmCriticalDataTransactionResponse,
+            CreateDepositPsbtRequest, CreateDepositPsbtResponse, CreateDepositTransactionRequest,
             CreateDepositTransactionResponse, CreateNewAddressRequest, CreateNewAddressResponse,
-            CreateSidechainProposalRequest, CreateSidechainProposalResponse, GenerateBlocksRequest,
-            GenerateBlocksResponse, GetBlockHeaderInfoRequest, GetBlockHeaderInfoResponse,
-            GetBlockInfoRequest, GetBlockInfoResponse, GetBmmHStarCommitmentRequest,
-            GetBmmHStarCommitmentResponse, GetChainInfoRequest, GetChainInfoResponse,
-            GetChainTipRequest, GetChainTipResponse, GetCoinbasePsbtRequest,
-            GetCoinbasePsbtResponse, GetCtipRequest, GetCtipResponse, GetSidechainProposalsRequest,
-            GetSidechainProposalsResponse, GetSidechainsRequest, GetSidechainsResponse,
-            GetTwoWayPegDataRequest, GetTwoWayPegDataResponse, Network, SubscribeEventsRequest,
-            SubscribeEventsResponse,
+            CreateSidechainProposalRequest, CreateSidechainProposalResponse, FinalizePsbtRequest,
+            FinalizePsbtResponse, GenerateBlocksRequest, GenerateBlocksResponse,
+            GetAncestorHeadersRequest, GetAncestorHeadersResponse, GetBestBmmRequestsRequest,
+            GetBestBmmRequestsResponse, GetBip300TransactionRequest, GetBip300TransactionResponse,
+            GetBlockHeaderInfoRequest, GetBlockHeaderInfoResponse, GetBlockInfoRequest,
+            GetBlockInfoResponse, GetBlockInfosRequest, GetBlockInfosResponse,
+            GetBlockTemplateRequest, GetBlockTemplateResponse, GetBmmHStarCommitmentRequest,
+            GetBmmHStarCommitmentResponse, GetBmmInclusionStatusRequest,
+            GetBmmInclusionStatusResponse, GetBmmRequestStatusRequest, GetBmmRequestStatusResponse,
+            GetCapabilitiesRequest, GetCapabilitiesResponse, GetChainInfoRequest,
+            GetChainInfoResponse, GetChainTipRequest, GetChainTipResponse, GetCoinbasePsbtRequest,
+            GetCoinbasePsbtResponse, GetCommonAncestorRequest, GetCommonAncestorResponse,
+            GetCtipRequest, GetCtipResponse, GetEnforcerStatusRequest, GetEnforcerStatusResponse,
+            GetFeeInfoRequest, GetFeeInfoResponse, GetNextM4Request, GetNextM4Response,
+            GetRawTransactionRequest, GetRawTransactionResponse, GetSidechainProposalStatusRequest,
+            GetSidechainProposalStatusResponse, GetSidechainProposalsRequest,
+            GetSidechainProposalsResponse, GetSidechainStatsRequest, GetSidechainStatsResponse,
+            GetSidechainsRequest, GetSidechainsResponse, GetSyncInfoRequest, GetSyncInfoResponse,
+            GetTwoWayPegDataRequest, GetTwoWayPegDataResponse, GetVersionRequest,
+            GetVersionResponse, GetVotingPolicyRequest, GetVotingPolicyResponse,
+            GetWithdrawalBundleQueueRequest, GetWithdrawalBundleQueueResponse,
+            GetWithdrawalBundleStatusRequest, GetWithdrawalBundleStatusResponse,
+            ImportDescriptorsRequest, ImportDescriptorsResponse,
+            ListBlocksWithBmmCommitmentsRequest, ListBlocksWithBmmCommitmentsResponse,
+            ListBmmRequestsRequest, ListBmmRequestsResponse, ListPendingWithdrawalBundlesRequest,
+            ListPendingWithdrawalBundlesResponse, ListTreasuryUtxosRequest,
+            ListTreasuryUtxosResponse, Network, RequestBmmRequest, RequestBmmResponse,
+            SetVotingPolicyRequest, SetVotingPolicyResponse, StreamHeaderChainRequest,
+            StreamHeaderChainResponse, StreamTwoWayPegDataRequest, StreamTwoWayPegDataResponse,
+            SubscribeCtipRequest, SubscribeCtipResponse, SubscribeEventsRequest,
+            SubscribeEventsResponse, SubscribeHeadersRequest, SubscribeHeadersResponse,
+            WaitForBlockRequest, WaitForBlockResponse, WaitForHeightRequest, WaitForHeightResponse,
         },
     },
-    types::{Event, SidechainNumber},
-    validator::Validator,
+    rpc_client::FeeInfo,
+    types::{Event, HeaderInfo, SidechainNumber},
+    validator::{
+        BmmInclusionStatus, BroadcastTransactionError, CheckTransactionError, EventsStreamError,
+        GetBestChainBlockInfosError, GetBmmInclusionStatusError, ListBlocksWithBmmCommitmentsError,
+        SidechainStatsInfo, SubscribeEventsError, TransactionRejection, Validator,
+        WaitForBlockError, WaitForBlockTarget, MAX_PENDING_WITHDRAWAL_BUNDLES,
+        WITHDRAWAL_BUNDLE_MAX_AGE,
+    },
+    wallet::{
+        backend::DescriptorImport, bmm_rebid::BmmRebid, bmm_request_history::BmmRequestSummary,
+        BmmRequestStatus,
+    },
 };
 
 fn invalid_field_value<Message, Error>(
@@ -97,17 +147,312 @@ impl IntoStatus for miette::Report {
     }
 }
 
+/// Resolve a block hash from request fields that accept either a block hash
+/// or a height in the current best chain
+fn block_hash_or_height<Message>(
+    validator: &Validator,
+    block_hash: Option<ReverseHex>,
+    height: Option<u32>,
+) -> Result<BlockHash, tonic::Status>
+where
+    Message: prost::Name,
+{
+    match (block_hash, height) {
+        (Some(block_hash), None) => block_hash.decode_tonic::<Message, _>("block_hash"),
+        (None, Some(height)) => validator
+            .try_get_best_chain_block_hash(height)
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?
+            .ok_or_else(|| {
+                tonic::Status::not_found(format!("no block at height {height} in best chain"))
+            }),
+        (None, None) => Err(missing_field::<Message>("block_hash")),
+        (Some(_), Some(height)) => Err(invalid_field_value::<Message, _>(
+            "height",
+            &height.to_string(),
+            Error::BlockHashAndHeight,
+        )),
+    }
+}
+
+/// Status for streams and waits that end because the event stream closed
+fn events_closed_status(validator: &Validator) -> tonic::Status {
+    if validator.is_shutting_down() {
+        tonic::Status::unavailable("the enforcer is shutting down")
+    } else {
+        tonic::Status::unavailable("the enforcer has stopped syncing")
+    }
+}
+
+/// Status for event streams that fail. Streams whose next event has been
+/// pruned from the event journal end with `OUT_OF_RANGE`, so that
+/// subscribers can tell that they must resync.
+fn events_stream_status(err: EventsStreamError) -> tonic::Status {
+    match err {
+        EventsStreamError::EventsPruned { .. } => tonic::Status::out_of_range(err.to_string()),
+        err => miette::Report::from(err).into_status(),
+    }
+}
+
+/// Max number of blocks returned by `GetBlockInfos`
+pub const MAX_BLOCK_INFOS: u32 = 1000;
+
+/// Max number of headers returned by `GetAncestorHeaders`
+pub const MAX_ANCESTOR_HEADERS: u32 = 2000;
+
+/// Max number of blocks in the range of a `GetTwoWayPegData` request
+pub const MAX_TWO_WAY_PEG_DATA_RANGE: u32 = 1000;
+
+/// Max number of blocks in the range of a `ListBlocksWithBmmCommitments`
+/// request
+pub const MAX_BMM_COMMITMENTS_RANGE: u32 = 10_000;
+
+/// Max number of treasury UTXOs returned by `ListTreasuryUtxos`
+pub const MAX_TREASURY_UTXOS: u32 = 1000;
+
+/// Default timeout for `WaitForHeight` and `WaitForBlock`, in milliseconds
+pub const DEFAULT_WAIT_FOR_BLOCK_TIMEOUT_MS: u32 = 60_000;
+
+/// Max timeout for `WaitForHeight` and `WaitForBlock`, in milliseconds
+pub const MAX_WAIT_FOR_BLOCK_TIMEOUT_MS: u32 = 600_000;
+
+/// Proto packages implemented by this enforcer, reported by `GetVersion`
+pub const PROTO_PACKAGES: &[&str] = &["cusf.common.v1", "cusf.crypto.v1", "cusf.mainchain.v1"];
+
+/// Optional subsystems that are enabled in this enforcer instance
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+    /// If `true`, the wallet service is available
+    pub wallet: bool,
+    /// If `true`, the query API is also served as JSON-RPC
+    pub json_rpc: bool,
+    /// If `true`, the query API is also served as REST
+    pub rest_gateway: bool,
+    /// If `true`, state is also served via GraphQL
+    pub graphql: bool,
+    /// If `true`, events are published via ZMQ
+    pub zmq_events: bool,
+}
+
+impl Capabilities {
+    /// Names of enabled features, reported by `GetVersion`
+    pub fn features(&self) -> Vec<&'static str> {
+        let Self {
+            wallet,
+            json_rpc,
+            rest_gateway,
+            graphql,
+            zmq_events,
+        } = *self;
+        [
+            (wallet, "wallet"),
+            (json_rpc, "json_rpc"),
+            (rest_gateway, "rest_gateway"),
+            (graphql, "graphql"),
+            (zmq_events, "zmq_events"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, feature)| enabled.then_some(feature))
+        .collect()
+    }
+}
+
+/// Implements the validator service
+pub struct ValidatorServer {
+    validator: Validator,
+    capabilities: Capabilities,
+    /// Authenticates requests to RPCs that modify state
+    /// (`CompactDatabase`, `BroadcastTransaction`), which require
+    /// credentials even if unauthenticated reads are allowed
+    write_auth: Authenticator,
+}
+
+impl ValidatorServer {
+    pub fn new(
+        validator: Validator,
+        capabilities: Capabilities,
+        write_auth: Authenticator,
+    ) -> Self {
+        Self {
+            validator,
+            capabilities,
+            write_auth,
+        }
+    }
+
+    /// Final item for event-derived streams. Event streams only end once the
+    /// sync task has stopped.
+    fn events_closed<T>(&self) -> impl Stream<Item = Result<T, tonic::Status>> {
+        let validator = self.validator.clone();
+        futures::stream::once(async move { Err(events_closed_status(&validator)) })
+    }
+
+    /// Wait for the target block to be synced, with a timeout in
+    /// milliseconds. Returns `DEADLINE_EXCEEDED` if the timeout elapses
+    /// first.
+    async fn wait_for_target<Message>(
+        &self,
+        target: WaitForBlockTarget,
+        timeout_ms: Option<u32>,
+    ) -> Result<HeaderInfo, tonic::Status>
+    where
+        Message: prost::Name,
+    {
+        let timeout_ms = timeout_ms.unwrap_or(DEFAULT_WAIT_FOR_BLOCK_TIMEOUT_MS);
+        if timeout_ms > MAX_WAIT_FOR_BLOCK_TIMEOUT_MS {
+            return Err(invalid_field_value::<Message, _>(
+                "timeout_ms",
+                &timeout_ms.to_string(),
+                Error::ValueMustNotExceed(MAX_WAIT_FOR_BLOCK_TIMEOUT_MS),
+            ));
+        }
+        let timeout = Duration::from_millis(timeout_ms.into());
+        match tokio::time::timeout(timeout, self.validator.wait_for_block(target)).await {
+            Ok(Ok(header_info)) => Ok(header_info),
+            Ok(Err(WaitForBlockError::EventsClosed)) => Err(events_closed_status(&self.validator)),
+            Ok(Err(err)) => Err(tonic::Status::from_error(Box::new(err))),
+            Err(_elapsed) => Err(tonic::Status::deadline_exceeded(format!(
+                "timed out after {timeout_ms}ms waiting for {target:?}"
+            ))),
+        }
+    }
+}
+
 #[tonic::async_trait]
-impl ValidatorService for Validator {
+impl ValidatorService for ValidatorServer {
+    /// Schedules compaction of the validator DBs on next startup, rather
+    /// than compacting them immediately. `path` is the marker file that
+    /// requests compaction, and `size_after` is not set.
+    async fn compact_database(
+        &self,
+        request: tonic::Request<CompactDatabaseRequest>,
+    ) -> Result<tonic::Response<CompactDatabaseResponse>, tonic::Status> {
+        let () = self.write_auth.check(&request)?;
+        let CompactDatabaseRequest {} = request.into_inner();
+        let scheduled = self
+            .validator
+            .schedule_db_compaction()
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
+        let resp = CompactDatabaseResponse {
+            path: Some(scheduled.marker_path.display().to_string()),
+            size_before: Some(scheduled.size),
+            size_after: None,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_bip300_transaction(
+        &self,
+        request: tonic::Request<GetBip300TransactionRequest>,
+    ) -> Result<tonic::Response<GetBip300TransactionResponse>, tonic::Status> {
+        let GetBip300TransactionRequest { txid } = request.into_inner();
+        let txid: Txid = txid
+            .ok_or_else(|| missing_field::<GetBip300TransactionRequest>("txid"))?
+            .decode_tonic::<GetBip300TransactionRequest, _>("txid")?;
+        let transaction = self
+            .validator
+            .try_get_bip300_transaction(&txid)
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
+        let resp = GetBip300TransactionResponse {
+            transaction: transaction.map(Into::into),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_raw_transaction(
+        &self,
+        request: tonic::Request<GetRawTransactionRequest>,
+    ) -> Result<tonic::Response<GetRawTransactionResponse>, tonic::Status> {
+        let GetRawTransactionRequest { txid } = request.into_inner();
+        let txid: Txid = txid
+            .ok_or_else(|| missing_field::<GetRawTransactionRequest>("txid"))?
+            .decode_tonic::<GetRawTransactionRequest, _>("txid")?;
+        let raw_transaction = self
+            .validator
+            .get_raw_bip300_transaction(&txid)
+            .await
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
+        let resp = GetRawTransactionResponse {
+            transaction: raw_transaction.map(|raw_transaction| {
+                get_raw_transaction_response::RawTransaction {
+                    transaction: Some(ConsensusHex::encode(&raw_transaction.transaction)),
+                    bip300_transaction: Some(raw_transaction.bip300_transaction.into()),
+                    block_height: raw_transaction.block_height,
+                    confirmations: raw_transaction.confirmations,
+                }
+            }),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_bmm_inclusion_status(
+        &self,
+        request: tonic::Request<GetBmmInclusionStatusRequest>,
+    ) -> Result<tonic::Response<GetBmmInclusionStatusResponse>, tonic::Status> {
+        let GetBmmInclusionStatusRequest { txid } = request.into_inner();
+        let txid: Txid = txid
+            .ok_or_else(|| missing_field::<GetBmmInclusionStatusRequest>("txid"))?
+            .decode_tonic::<GetBmmInclusionStatusRequest, _>("txid")?;
+        let info = match self.validator.get_bmm_inclusion_status(&txid).await {
+            Ok(Some(info)) => info,
+            Ok(None) => {
+                return Err(tonic::Status::not_found(format!(
+                    "BMM request `{txid}` not found"
+                )))
+            }
+            Err(err @ GetBmmInclusionStatusError::NotBmmRequest(_)) => {
+                return Err(tonic::Status::invalid_argument(err.to_string()))
+            }
+            Err(err) => return Err(tonic::Status::from_error(Box::new(err))),
+        };
+        let status = match info.status {
+            BmmInclusionStatus::InMempool => get_bmm_inclusion_status_response::Status::InMempool(
+                get_bmm_inclusion_status_response::InMempool {},
+            ),
+            BmmInclusionStatus::Included { block_hash } => {
+                get_bmm_inclusion_status_response::Status::Included(
+                    get_bmm_inclusion_status_response::Included {
+                        block_hash: Some(ReverseHex::encode(&block_hash)),
+                    },
+                )
+            }
+            BmmInclusionStatus::NotAccepted { block_hash } => {
+                get_bmm_inclusion_status_response::Status::NotAccepted(
+                    get_bmm_inclusion_status_response::NotAccepted {
+                        block_hash: Some(ReverseHex::encode(&block_hash)),
+                    },
+                )
+            }
+            BmmInclusionStatus::Expired => get_bmm_inclusion_status_response::Status::Expired(
+                get_bmm_inclusion_status_response::Expired {},
+            ),
+            BmmInclusionStatus::Replaced => get_bmm_inclusion_status_response::Status::Replaced(
+                get_bmm_inclusion_status_response::Replaced {},
+            ),
+            BmmInclusionStatus::Dropped => get_bmm_inclusion_status_response::Status::Dropped(
+                get_bmm_inclusion_status_response::Dropped {},
+            ),
+        };
+        let bmm_request = info.bmm_request;
+        let resp = GetBmmInclusionStatusResponse {
+            sidechain_number: Some(bmm_request.sidechain_number.0 as u32),
+            sidechain_block_hash: Some(ConsensusHex::encode(&bmm_request.sidechain_block_hash)),
+            prev_block_hash: Some(ReverseHex::encode(&BlockHash::from_byte_array(
+                bmm_request.prev_mainchain_block_hash,
+            ))),
+            status: Some(status),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
     async fn get_block_header_info(
         &self,
         request: tonic::Request<GetBlockHeaderInfoRequest>,
     ) -> Result<tonic::Response<GetBlockHeaderInfoResponse>, tonic::Status> {
-        let GetBlockHeaderInfoRequest { block_hash } = request.into_inner();
-        let block_hash = block_hash
-            .ok_or_else(|| missing_field::<GetBlockHeaderInfoRequest>("block_hash"))?
-            .decode_tonic::<GetBlockHeaderInfoRequest, _>("block_hash")?;
+        let GetBlockHeaderInfoRequest { block_hash, height } = request.into_inner();
+        let block_hash =
+            block_hash_or_height::<GetBlockHeaderInfoRequest>(&self.validator, block_hash, height)?;
         let header_info = self
+            .validator
             .get_header_info(&block_hash)
             .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
         let resp = GetBlockHeaderInfoResponse {
@@ -116,17 +461,75 @@ impl ValidatorService for Validator {
         Ok(tonic::Response::new(resp))
     }
 
+    async fn get_ancestor_headers(
+        &self,
+        request: tonic::Request<GetAncestorHeadersRequest>,
+    ) -> Result<tonic::Response<GetAncestorHeadersResponse>, tonic::Status> {
+        let GetAncestorHeadersRequest { block_hash, count } = request.into_inner();
+        let block_hash: BlockHash = block_hash
+            .ok_or_else(|| missing_field::<GetAncestorHeadersRequest>("block_hash"))?
+            .decode_tonic::<GetAncestorHeadersRequest, _>("block_hash")?;
+        if count == 0 {
+            return Err(invalid_field_value::<GetAncestorHeadersRequest, _>(
+                "count",
+                &count.to_string(),
+                Error::ValueMustBeGreaterThanZero,
+            ));
+        }
+        if count > MAX_ANCESTOR_HEADERS {
+            return Err(invalid_field_value::<GetAncestorHeadersRequest, _>(
+                "count",
+                &count.to_string(),
+                Error::ValueMustNotExceed(MAX_ANCESTOR_HEADERS),
+            ));
+        }
+        let header_infos = self
+            .validator
+            .get_ancestor_headers(&block_hash, count as usize)
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
+        let resp = GetAncestorHeadersResponse {
+            header_infos: header_infos.into_iter().map(Into::into).collect(),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_common_ancestor(
+        &self,
+        request: tonic::Request<GetCommonAncestorRequest>,
+    ) -> Result<tonic::Response<GetCommonAncestorResponse>, tonic::Status> {
+        let GetCommonAncestorRequest {
+            block_hash_a,
+            block_hash_b,
+        } = request.into_inner();
+        let block_hash_a: BlockHash = block_hash_a
+            .ok_or_else(|| missing_field::<GetCommonAncestorRequest>("block_hash_a"))?
+            .decode_tonic::<GetCommonAncestorRequest, _>("block_hash_a")?;
+        let block_hash_b: BlockHash = block_hash_b
+            .ok_or_else(|| missing_field::<GetCommonAncestorRequest>("block_hash_b"))?
+            .decode_tonic::<GetCommonAncestorRequest, _>("block_hash_b")?;
+        let common_ancestor = self
+            .validator
+            .get_common_ancestor(&block_hash_a, &block_hash_b)
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
+        let resp = GetCommonAncestorResponse {
+            header_info: Some(common_ancestor.header_info.into()),
+            depth_a: common_ancestor.depth_a,
+            depth_b: common_ancestor.depth_b,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
     async fn get_block_info(
         &self,
         request: tonic::Request<GetBlockInfoRequest>,
     ) -> Result<tonic::Response<GetBlockInfoResponse>, tonic::Status> {
         let GetBlockInfoRequest {
             block_hash,
+            height,
             sidechain_id,
         } = request.into_inner();
-        let block_hash = block_hash
-            .ok_or_else(|| missing_field::<GetBlockInfoRequest>("block_hash"))?
-            .decode_tonic::<GetBlockInfoRequest, _>("block_hash")?;
+        let block_hash =
+            block_hash_or_height::<GetBlockInfoRequest>(&self.validator, block_hash, height)?;
         let sidechain_id = {
             let raw_id =
                 sidechain_id.ok_or_else(|| missing_field::<GetBlockInfoRequest>("sidechain_id"))?;
@@ -141,9 +544,11 @@ impl ValidatorService for Validator {
         };
 
         let header_info = self
+            .validator
             .get_header_info(&block_hash)
             .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
         let block_info = self
+            .validator
             .get_block_info(&block_hash)
             .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
         let resp = GetBlockInfoResponse {
@@ -153,6 +558,86 @@ impl ValidatorService for Validator {
         Ok(tonic::Response::new(resp))
     }
 
+    async fn get_block_infos(
+        &self,
+        request: tonic::Request<GetBlockInfosRequest>,
+    ) -> Result<tonic::Response<GetBlockInfosResponse>, tonic::Status> {
+        let GetBlockInfosRequest {
+            block_hash,
+            height,
+            count,
+            sidechain_id,
+        } = request.into_inner();
+        let sidechain_id = {
+            let raw_id = sidechain_id
+                .ok_or_else(|| missing_field::<GetBlockInfosRequest>("sidechain_id"))?;
+
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<GetBlockInfosRequest, _>(
+                    "sidechain_id",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
+        if count == 0 {
+            return Err(invalid_field_value::<GetBlockInfosRequest, _>(
+                "count",
+                &count.to_string(),
+                Error::ValueMustBeGreaterThanZero,
+            ));
+        }
+        if count > MAX_BLOCK_INFOS {
+            return Err(invalid_field_value::<GetBlockInfosRequest, _>(
+                "count",
+                &count.to_string(),
+                Error::ValueMustNotExceed(MAX_BLOCK_INFOS),
+            ));
+        }
+        let start_height = match (block_hash, height) {
+            (None, Some(height)) => height,
+            (block_hash, height) => {
+                let block_hash = block_hash_or_height::<GetBlockInfosRequest>(
+                    &self.validator,
+                    block_hash,
+                    height,
+                )?;
+                let height = self
+                    .validator
+                    .get_header_info(&block_hash)
+                    .map_err(|err| tonic::Status::from_error(Box::new(err)))?
+                    .height;
+                let best_chain_block_hash = self
+                    .validator
+                    .try_get_best_chain_block_hash(height)
+                    .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
+                if best_chain_block_hash != Some(block_hash) {
+                    return Err(tonic::Status::failed_precondition(format!(
+                        "block `{block_hash}` is not in the best chain"
+                    )));
+                }
+                height
+            }
+        };
+        let blocks = self
+            .validator
+            .get_best_chain_block_infos(start_height, count)
+            .map_err(|err| match err {
+                GetBestChainBlockInfosError::BlockInfoPruned(_) => {
+                    tonic::Status::failed_precondition(err.to_string())
+                }
+                err => tonic::Status::from_error(Box::new(err)),
+            })?
+            .into_iter()
+            .map(|two_way_peg_data| get_block_infos_response::ResponseItem {
+                header_info: Some(two_way_peg_data.header_info.into()),
+                block_info: Some(two_way_peg_data.block_info.into_proto(sidechain_id)),
+            })
+            .collect();
+        let resp = GetBlockInfosResponse { blocks };
+        Ok(tonic::Response::new(resp))
+    }
+
     async fn get_bmm_h_star_commitment(
         &self,
         request: tonic::Request<GetBmmHStarCommitmentRequest>,
@@ -179,6 +664,7 @@ impl ValidatorService for Validator {
         };
 
         let bmm_commitments = self
+            .validator
             .try_get_bmm_commitments(&block_hash)
             .map_err(|err| tonic::Status::from_error(Box::new(err)))?;
         let res = match bmm_commitments {
@@ -200,12 +686,79 @@ impl ValidatorService for Validator {
         Ok(tonic::Response::new(resp))
     }
 
+    async fn list_blocks_with_bmm_commitments(
+        &self,
+        request: tonic::Request<ListBlocksWithBmmCommitmentsRequest>,
+    ) -> Result<tonic::Response<ListBlocksWithBmmCommitmentsResponse>, tonic::Status> {
+        let ListBlocksWithBmmCommitmentsRequest {
+            sidechain_id,
+            start_height,
+            end_height,
+        } = request.into_inner();
+        let sidechain_id = {
+            let raw_id = sidechain_id.ok_or_else(|| {
+                missing_field::<ListBlocksWithBmmCommitmentsRequest>("sidechain_id")
+            })?;
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<ListBlocksWithBmmCommitmentsRequest, _>(
+                    "sidechain_id",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
+        if end_height < start_height {
+            return Err(tonic::Status::invalid_argument(
+                Error::EndHeightBeforeStartHeight.to_string(),
+            ));
+        }
+        let range_blocks = (end_height - start_height).saturating_add(1);
+        if range_blocks > MAX_BMM_COMMITMENTS_RANGE {
+            return Err(
+                invalid_field_value::<ListBlocksWithBmmCommitmentsRequest, _>(
+                    "end_height",
+                    &end_height.to_string(),
+                    Error::ValueMustNotExceed(start_height + (MAX_BMM_COMMITMENTS_RANGE - 1)),
+                ),
+            );
+        }
+        let blocks = self
+            .validator
+            .list_blocks_with_bmm_commitments(sidechain_id, start_height, end_height)
+            .map_err(|err| match err {
+                ListBlocksWithBmmCommitmentsError::BmmCommitmentsPruned(_) => {
+                    tonic::Status::failed_precondition(err.to_string())
+                }
+                err => tonic::Status::from_error(Box::new(err)),
+            })?
+            .into_iter()
+            .map(
+                |(header_info, commitment)| list_blocks_with_bmm_commitments_response::Block {
+                    header_info: Some(header_info.into()),
+                    commitment: Some(ConsensusHex::encode(&commitment)),
+                },
+            )
+            .collect();
+        let resp = ListBlocksWithBmmCommitmentsResponse { blocks };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_capabilities(
+        &self,
+        request: tonic::Request<GetCapabilitiesRequest>,
+    ) -> Result<tonic::Response<GetCapabilitiesResponse>, tonic::Status> {
+        let GetCapabilitiesRequest {} = request.into_inner();
+        let Capabilities { wallet, .. } = self.capabilities;
+        let resp = GetCapabilitiesResponse { wallet };
+        Ok(tonic::Response::new(resp))
+    }
+
     async fn get_chain_info(
         &self,
         request: tonic::Request<GetChainInfoRequest>,
     ) -> Result<tonic::Response<GetChainInfoResponse>, tonic::Status> {
         let GetChainInfoRequest {} = request.into_inner();
-        let network: Network = self.network().into();
+        let network: Network = self.validator.network().into();
         let resp = GetChainInfoResponse {
             network: network as i32,
         };
@@ -217,9 +770,13 @@ impl ValidatorService for Validator {
         request: tonic::Request<GetChainTipRequest>,
     ) -> Result<tonic::Response<GetChainTipResponse>, tonic::Status> {
         let GetChainTipRequest {} = request.into_inner();
-        let tip_hash = self.get_mainchain_tip().map_err(|err| err.into_status())?;
+        let tip_hash = self
+            .validator
+            .get_mainchain_tip()
+            .map_err(|err| err.into_status())?;
 
         let header_info = self
+            .validator
             .get_header_info(&tip_hash)
             .map_err(|err| tonic::Status::from_error(err.into()))?;
         let resp = GetChainTipResponse {
@@ -228,6 +785,173 @@ impl ValidatorService for Validator {
         Ok(tonic::Response::new(resp))
     }
 
+    async fn wait_for_height(
+        &self,
+        request: tonic::Request<WaitForHeightRequest>,
+    ) -> Result<tonic::Response<WaitForHeightResponse>, tonic::Status> {
+        let WaitForHeightRequest { height, timeout_ms } = request.into_inner();
+        let header_info = self
+            .wait_for_target::<WaitForHeightRequest>(WaitForBlockTarget::Height(height), timeout_ms)
+            .await?;
+        let resp = WaitForHeightResponse {
+            header_info: Some(header_info.into()),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn wait_for_block(
+        &self,
+        request: tonic::Request<WaitForBlockRequest>,
+    ) -> Result<tonic::Response<WaitForBlockResponse>, tonic::Status> {
+        let WaitForBlockRequest {
+            block_hash,
+            timeout_ms,
+        } = request.into_inner();
+        let block_hash: BlockHash = block_hash
+            .ok_or_else(|| missing_field::<WaitForBlockRequest>("block_hash"))?
+            .decode_tonic::<WaitForBlockRequest, _>("block_hash")?;
+        let header_info = self
+            .wait_for_target::<WaitForBlockRequest>(
+                WaitForBlockTarget::BlockHash(block_hash),
+                timeout_ms,
+            )
+            .await?;
+        let resp = WaitForBlockResponse {
+            header_info: Some(header_info.into()),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_sync_info(
+        &self,
+        request: tonic::Request<GetSyncInfoRequest>,
+    ) -> Result<tonic::Response<GetSyncInfoResponse>, tonic::Status> {
+        let GetSyncInfoRequest {} = request.into_inner();
+        let sync_info = self
+            .validator
+            .get_sync_info()
+            .await
+            .map_err(|err| err.into_status())?;
+        let resp = GetSyncInfoResponse {
+            block_height: sync_info.block_height,
+            header_height: sync_info.header_height,
+            node_block_height: sync_info.node_block_height,
+            node_header_height: sync_info.node_header_height,
+            estimated_seconds_remaining: sync_info
+                .estimated_time_remaining
+                .map(|remaining| remaining.as_secs()),
+            zmq_connected: sync_info.zmq_connected,
+            sync_task_restarts: sync_info.sync_task_restarts,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_enforcer_status(
+        &self,
+        request: tonic::Request<GetEnforcerStatusRequest>,
+    ) -> Result<tonic::Response<GetEnforcerStatusResponse>, tonic::Status> {
+        let GetEnforcerStatusRequest {} = request.into_inner();
+        let resp = self.validator.get_enforcer_status().into();
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_fee_info(
+        &self,
+        request: tonic::Request<GetFeeInfoRequest>,
+    ) -> Result<tonic::Response<GetFeeInfoResponse>, tonic::Status> {
+        let GetFeeInfoRequest {} = request.into_inner();
+        let fee_info = self
+            .validator
+            .get_fee_info()
+            .await
+            .map_err(|err| err.into_status())?;
+        // 1 kvB = 4 kwu
+        let sat_per_kvb = |fee_rate: bitcoin::FeeRate| fee_rate.to_sat_per_kwu() * 4;
+        let fee_estimate = |conf_target: u16, fee_rate: Option<bitcoin::FeeRate>| {
+            get_fee_info_response::FeeEstimate {
+                conf_target: conf_target as u32,
+                sat_per_kvb: fee_rate.map(sat_per_kvb),
+            }
+        };
+        let resp = GetFeeInfoResponse {
+            short_term: Some(fee_estimate(
+                FeeInfo::SHORT_TERM_CONF_TARGET,
+                fee_info.short_term,
+            )),
+            medium_term: Some(fee_estimate(
+                FeeInfo::MEDIUM_TERM_CONF_TARGET,
+                fee_info.medium_term,
+            )),
+            long_term: Some(fee_estimate(
+                FeeInfo::LONG_TERM_CONF_TARGET,
+                fee_info.long_term,
+            )),
+            min_relay_fee_sat_per_kvb: sat_per_kvb(fee_info.min_relay_fee),
+            mempool_min_fee_sat_per_kvb: sat_per_kvb(fee_info.mempool_min_fee),
+            mempool_tx_count: fee_info.mempool_tx_count,
+            mempool_vsize: fee_info.mempool_vsize,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn broadcast_transaction(
+        &self,
+        request: tonic::Request<BroadcastTransactionRequest>,
+    ) -> Result<tonic::Response<BroadcastTransactionResponse>, tonic::Status> {
+        use broadcast_transaction_response::{RejectReason, Rejection};
+        let () = self.write_auth.check(&request)?;
+        let BroadcastTransactionRequest { transaction } = request.into_inner();
+        let transaction = transaction
+            .ok_or_else(|| missing_field::<BroadcastTransactionRequest>("transaction"))?;
+        let transaction: Transaction =
+            bitcoin::consensus::deserialize(&transaction).map_err(|err| {
+                invalid_field_value::<BroadcastTransactionRequest, _>(
+                    "transaction",
+                    &hex::encode(&transaction),
+                    err,
+                )
+            })?;
+        let rejection = |reason: RejectReason, message: String| BroadcastTransactionResponse {
+            txid: None,
+            rejection: Some(Rejection {
+                reason: reason as i32,
+                message,
+            }),
+        };
+        let resp = match self.validator.broadcast_transaction(&transaction).await {
+            Ok((txid, _checked)) => BroadcastTransactionResponse {
+                txid: Some(ReverseHex::encode(&txid)),
+                rejection: None,
+            },
+            Err(BroadcastTransactionError::CheckTransaction(CheckTransactionError::Rejected(
+                err,
+            ))) => {
+                let reason = match err {
+                    TransactionRejection::BmmRequestExpired { .. } => {
+                        RejectReason::BmmRequestExpired
+                    }
+                    TransactionRejection::CtipSpentWithoutTreasuryOutput { .. } => {
+                        RejectReason::CtipSpentWithoutTreasuryOutput
+                    }
+                    TransactionRejection::CtipUnspent { .. } => RejectReason::CtipUnspent,
+                    TransactionRejection::EmptyDeposit { .. } => RejectReason::EmptyDeposit,
+                    TransactionRejection::InactiveSidechain(_) => RejectReason::InactiveSidechain,
+                    TransactionRejection::InvalidM6Layout(_) => RejectReason::InvalidM6Layout,
+                    TransactionRejection::MissingDepositAddress { .. } => {
+                        RejectReason::MissingDepositAddress
+                    }
+                    TransactionRejection::M6NotApproved { .. } => RejectReason::M6NotApproved,
+                };
+                rejection(reason, format!("{:#}", anyhow::Error::from(err)))
+            }
+            Err(BroadcastTransactionError::SendRawTransaction(
+                bip300301::jsonrpsee::core::ClientError::Call(err),
+            )) => rejection(RejectReason::NodeRejected, err.message().to_owned()),
+            Err(err) => return Err(tonic::Status::from_error(Box::new(err))),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
     async fn get_coinbase_psbt(
         &self,
         request: Request<GetCoinbasePsbtRequest>,
@@ -271,14 +995,24 @@ impl ValidatorService for Validator {
             })
             .collect::<miette::Result<Vec<_>>>()
             .map_err(|err| tonic::Status::internal(err.to_string()))?;
-        let transaction = Transaction {
-            output,
-            input: vec![],
-            lock_time: bitcoin::absolute::LockTime::Blocks(Height::ZERO),
-            version: bitcoin::transaction::Version::TWO,
-        };
+        let coinbase_template = request
+            .coinbase_template
+            .map(|template| {
+                template.decode_tonic::<GetCoinbasePsbtRequest, Transaction>("coinbase_template")
+            })
+            .transpose()?;
+        let psbt = crate::messages::coinbase_psbt(coinbase_template, output).map_err(|err| {
+            invalid_field_value::<GetCoinbasePsbtRequest, _>(
+                "coinbase_template",
+                "<transaction>",
+                err,
+            )
+        })?;
+        // BIP174 serialization, not consensus serialization
         let response = GetCoinbasePsbtResponse {
-            psbt: Some(ConsensusHex::encode(&transaction)),
+            psbt: Some(ConsensusHex {
+                hex: Some(hex::encode(psbt.serialize())),
+            }),
         };
         Ok(Response::new(response))
     }
@@ -302,10 +1036,12 @@ impl ValidatorService for Validator {
         };
 
         let ctip = self
+            .validator
             .try_get_ctip(sidechain_number)
             .map_err(|err| err.into_status())?;
         if let Some(ctip) = ctip {
             let sequence_number = self
+                .validator
                 .get_ctip_sequence_number(sidechain_number)
                 .map_err(|err| err.into_status())?;
             // get_ctip returned Some(ctip) above, so we know that the sequence_number will also
@@ -325,6 +1061,71 @@ impl ValidatorService for Validator {
         }
     }
 
+    async fn list_treasury_utxos(
+        &self,
+        request: tonic::Request<ListTreasuryUtxosRequest>,
+    ) -> Result<tonic::Response<ListTreasuryUtxosResponse>, tonic::Status> {
+        let ListTreasuryUtxosRequest {
+            sidechain_number,
+            start_sequence,
+            limit,
+        } = request.into_inner();
+        let sidechain_number = {
+            let raw_id = sidechain_number
+                .ok_or_else(|| missing_field::<ListTreasuryUtxosRequest>("sidechain_number"))?;
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<ListTreasuryUtxosRequest, _>(
+                    "sidechain_number",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
+        let limit = limit.unwrap_or(MAX_TREASURY_UTXOS);
+        if limit == 0 {
+            return Err(invalid_field_value::<ListTreasuryUtxosRequest, _>(
+                "limit",
+                &limit.to_string(),
+                Error::ValueMustBeGreaterThanZero,
+            ));
+        }
+        if limit > MAX_TREASURY_UTXOS {
+            return Err(invalid_field_value::<ListTreasuryUtxosRequest, _>(
+                "limit",
+                &limit.to_string(),
+                Error::ValueMustNotExceed(MAX_TREASURY_UTXOS),
+            ));
+        }
+        let start_sequence = start_sequence.unwrap_or(0);
+        let (treasury_utxos, treasury_utxo_count) = self
+            .validator
+            .list_treasury_utxos(sidechain_number, start_sequence, limit as u64)
+            .map_err(|err| err.into_status())?;
+        let next_sequence = treasury_utxos
+            .last()
+            .map(|(sequence_number, _)| sequence_number + 1)
+            .filter(|next_sequence| *next_sequence < treasury_utxo_count);
+        let treasury_utxos = treasury_utxos
+            .into_iter()
+            .map(
+                |(sequence_number, treasury_utxo)| list_treasury_utxos_response::TreasuryUtxo {
+                    sequence_number,
+                    txid: Some(ReverseHex::encode(&treasury_utxo.outpoint.txid)),
+                    vout: treasury_utxo.outpoint.vout,
+                    address: treasury_utxo.address.as_ref().map(Hex::encode),
+                    total_value: treasury_utxo.total_value.to_sat(),
+                    previous_total_value: treasury_utxo.previous_total_value.to_sat(),
+                },
+            )
+            .collect();
+        let resp = ListTreasuryUtxosResponse {
+            treasury_utxos,
+            treasury_utxo_count,
+            next_sequence,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
     /*
     async fn get_deposits(
         &self,
@@ -332,7 +1133,7 @@ impl ValidatorService for Validator {
     ) -> Result<Response<GetDepositsResponse>, Status> {
         let request = request.into_inner();
         let sidechain_number = request.sidechain_number as u8;
-        let deposits = self.get_deposits(sidechain_number).unwrap();
+        let deposits = self.validator.get_deposits(sidechain_number).unwrap();
         let mut response = GetDepositsResponse { deposits: vec![] };
         for deposit in deposits {
             let deposit = Deposit {
@@ -351,16 +1152,13 @@ impl ValidatorService for Validator {
         request: tonic::Request<GetSidechainProposalsRequest>,
     ) -> Result<tonic::Response<GetSidechainProposalsResponse>, tonic::Status> {
         let GetSidechainProposalsRequest {} = request.into_inner();
-        let mainchain_tip = self.get_mainchain_tip().map_err(|err| err.into_status())?;
-        let mainchain_tip_height = self
-            .get_header_info(&mainchain_tip)
-            .into_diagnostic()
-            .map_err(|err| err.into_status())?
-            .height;
-        let sidechain_proposals = self.get_sidechains().map_err(|err| err.into_status())?;
+        let sidechain_proposals = self
+            .validator
+            .get_sidechain_proposals()
+            .map_err(|err| err.into_status())?;
         let sidechain_proposals = sidechain_proposals
             .into_iter()
-            .map(|(description_sha256d_hash, sidechain)| {
+            .map(|(description_sha256d_hash, sidechain, status)| {
                 let description = ConsensusHex::encode(&sidechain.proposal.description.0);
                 let declaration =
                     crate::types::SidechainDeclaration::try_from(&sidechain.proposal.description)
@@ -373,7 +1171,8 @@ impl ValidatorService for Validator {
                     description_sha256d_hash: Some(ReverseHex::encode(&description_sha256d_hash)),
                     vote_count: Some(sidechain.status.vote_count as u32),
                     proposal_height: Some(sidechain.status.proposal_height),
-                    proposal_age: Some(mainchain_tip_height - sidechain.status.proposal_height),
+                    proposal_age: Some(status.age),
+                    max_age: Some(status.rules.max_age as u32),
                 }
             })
             .collect();
@@ -383,14 +1182,90 @@ impl ValidatorService for Validator {
         Ok(Response::new(response))
     }
 
-    async fn get_sidechains(
+    async fn get_sidechain_proposal_status(
         &self,
-        request: tonic::Request<GetSidechainsRequest>,
-    ) -> Result<tonic::Response<GetSidechainsResponse>, tonic::Status> {
-        let GetSidechainsRequest {} = request.into_inner();
-        let sidechains = self
-            .get_active_sidechains()
-            .map_err(|err| err.into_status())?;
+        request: tonic::Request<GetSidechainProposalStatusRequest>,
+    ) -> Result<tonic::Response<GetSidechainProposalStatusResponse>, tonic::Status> {
+        let GetSidechainProposalStatusRequest { description_hash } = request.into_inner();
+        let description_hash = description_hash
+            .ok_or_else(|| missing_field::<GetSidechainProposalStatusRequest>("description_hash"))?
+            .decode_tonic::<GetSidechainProposalStatusRequest, _>("description_hash")?;
+        let Some(status) = self
+            .validator
+            .get_sidechain_proposal_status(&description_hash)
+            .map_err(|err| err.into_status())?
+        else {
+            return Err(tonic::Status::not_found(format!(
+                "no pending sidechain proposal with description hash {description_hash}"
+            )));
+        };
+        let resp = GetSidechainProposalStatusResponse {
+            sidechain_number: Some(status.sidechain_number.0 as u32),
+            vote_count: Some(status.vote_count as u32),
+            proposal_height: Some(status.proposal_height),
+            proposal_age: Some(status.age),
+            sidechain_slot_is_used: Some(status.sidechain_slot_is_used),
+            max_age: Some(status.rules.max_age as u32),
+            activation_threshold: Some(status.rules.activation_threshold as u32),
+            blocks_remaining: Some(status.blocks_remaining),
+            votes_required: Some(status.votes_required),
+            activation_possible: Some(status.activation_possible),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_sidechain_stats(
+        &self,
+        request: tonic::Request<GetSidechainStatsRequest>,
+    ) -> Result<tonic::Response<GetSidechainStatsResponse>, tonic::Status> {
+        let GetSidechainStatsRequest { sidechain_number } = request.into_inner();
+        let sidechain_number = {
+            let raw_id = sidechain_number
+                .ok_or_else(|| missing_field::<GetSidechainStatsRequest>("sidechain_number"))?;
+
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<GetSidechainStatsRequest, _>(
+                    "sidechain_number",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
+        let Some(stats_info) = self
+            .validator
+            .get_sidechain_stats(sidechain_number)
+            .map_err(|err| err.into_status())?
+        else {
+            return Err(tonic::Status::not_found(format!(
+                "sidechain {sidechain_number} is not active"
+            )));
+        };
+        let SidechainStatsInfo {
+            stats,
+            treasury_balance,
+            activation_height,
+        } = stats_info;
+        let resp = GetSidechainStatsResponse {
+            total_deposited_sats: Some(stats.total_deposited.to_sat()),
+            total_withdrawn_sats: Some(stats.total_withdrawn.to_sat()),
+            deposit_count: Some(stats.deposit_count),
+            succeeded_withdrawal_bundle_count: Some(stats.succeeded_withdrawal_bundle_count),
+            failed_withdrawal_bundle_count: Some(stats.failed_withdrawal_bundle_count),
+            treasury_balance_sats: Some(treasury_balance.to_sat()),
+            activation_height,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_sidechains(
+        &self,
+        request: tonic::Request<GetSidechainsRequest>,
+    ) -> Result<tonic::Response<GetSidechainsResponse>, tonic::Status> {
+        let GetSidechainsRequest {} = request.into_inner();
+        let sidechains = self
+            .validator
+            .get_active_sidechains_with_pending_replacements()
+            .map_err(|err| err.into_status())?;
         let sidechains = sidechains.into_iter().map(SidechainInfo::from).collect();
         let response = GetSidechainsResponse { sidechains };
         Ok(Response::new(response))
@@ -404,6 +1279,7 @@ impl ValidatorService for Validator {
             sidechain_id,
             start_block_hash,
             end_block_hash,
+            min_confirmations,
         } = request.into_inner();
 
         let sidechain_id = {
@@ -436,7 +1312,48 @@ impl ValidatorService for Validator {
             .map(bdk_wallet::bitcoin::BlockHash::from_byte_array)
             .map(convert::bdk_block_hash_to_bitcoin_block_hash)?;
 
-        match self.get_two_way_peg_data(start_block_hash, end_block_hash) {
+        let min_confirmations = min_confirmations
+            .map(|min_confirmations| {
+                NonZeroU32::new(min_confirmations).ok_or_else(|| {
+                    invalid_field_value::<GetTwoWayPegDataRequest, _>(
+                        "min_confirmations",
+                        &min_confirmations.to_string(),
+                        Error::ValueMustBeGreaterThanZero,
+                    )
+                })
+            })
+            .transpose()?;
+
+        let end_height = self
+            .validator
+            .get_header_info(&end_block_hash)
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?
+            .height;
+        let range_blocks = match start_block_hash {
+            Some(start_block_hash) if start_block_hash == end_block_hash => 1,
+            Some(start_block_hash) => {
+                let start_height = self
+                    .validator
+                    .get_header_info(&start_block_hash)
+                    .map_err(|err| tonic::Status::from_error(Box::new(err)))?
+                    .height;
+                end_height.saturating_sub(start_height)
+            }
+            None => end_height.saturating_add(1),
+        };
+        if range_blocks > MAX_TWO_WAY_PEG_DATA_RANGE {
+            let err = Error::RangeTooLarge {
+                blocks: range_blocks,
+                max: MAX_TWO_WAY_PEG_DATA_RANGE,
+            };
+            return Err(tonic::Status::invalid_argument(err.to_string()));
+        }
+
+        match self.validator.get_two_way_peg_data(
+            start_block_hash,
+            end_block_hash,
+            min_confirmations,
+        ) {
             Err(err) => Err(tonic::Status::from_error(Box::new(err))),
             Ok(two_way_peg_data) => {
                 let two_way_peg_data = two_way_peg_data
@@ -451,13 +1368,258 @@ impl ValidatorService for Validator {
         }
     }
 
+    type StreamTwoWayPegDataStream =
+        BoxStream<'static, Result<StreamTwoWayPegDataResponse, tonic::Status>>;
+
+    async fn stream_two_way_peg_data(
+        &self,
+        request: tonic::Request<StreamTwoWayPegDataRequest>,
+    ) -> Result<tonic::Response<Self::StreamTwoWayPegDataStream>, tonic::Status> {
+        let StreamTwoWayPegDataRequest {
+            sidechain_id,
+            start_block_hash,
+            end_block_hash,
+        } = request.into_inner();
+
+        let sidechain_id = {
+            let raw_id = sidechain_id
+                .ok_or_else(|| missing_field::<StreamTwoWayPegDataRequest>("sidechain_id"))?;
+
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<StreamTwoWayPegDataRequest, _>(
+                    "sidechain_id",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
+        let start_block_hash: Option<BlockHash> = start_block_hash
+            .map(|start_block_hash| {
+                start_block_hash.decode_tonic::<StreamTwoWayPegDataRequest, _>("start_block_hash")
+            })
+            .transpose()?;
+        let end_block_hash: BlockHash = end_block_hash
+            .ok_or_else(|| missing_field::<StreamTwoWayPegDataRequest>("end_block_hash"))?
+            .decode_tonic::<StreamTwoWayPegDataRequest, _>("end_block_hash")?;
+
+        // Blocks are read as the client consumes the stream, so the
+        // response is subject to HTTP/2 flow control
+        let stream = self
+            .validator
+            .stream_two_way_peg_data(start_block_hash, end_block_hash)
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?
+            .filter_map(move |res| {
+                let resp = match res {
+                    Ok(two_way_peg_data) => two_way_peg_data
+                        .into_proto(sidechain_id)
+                        .map(|block| Ok(StreamTwoWayPegDataResponse { block: Some(block) })),
+                    Err(err) => Some(Err(tonic::Status::from_error(Box::new(err)))),
+                };
+                futures::future::ready(resp)
+            })
+            .boxed();
+        Ok(tonic::Response::new(stream))
+    }
+
+    type StreamHeaderChainStream =
+        BoxStream<'static, Result<StreamHeaderChainResponse, tonic::Status>>;
+
+    async fn stream_header_chain(
+        &self,
+        request: tonic::Request<StreamHeaderChainRequest>,
+    ) -> Result<tonic::Response<Self::StreamHeaderChainStream>, tonic::Status> {
+        let StreamHeaderChainRequest {
+            start_block_hash,
+            end_block_hash,
+        } = request.into_inner();
+        let start_block_hash: Option<BlockHash> = start_block_hash
+            .map(|start_block_hash| {
+                start_block_hash.decode_tonic::<StreamHeaderChainRequest, _>("start_block_hash")
+            })
+            .transpose()?;
+        let end_block_hash: BlockHash = match end_block_hash {
+            Some(end_block_hash) => {
+                end_block_hash.decode_tonic::<StreamHeaderChainRequest, _>("end_block_hash")?
+            }
+            None => self
+                .validator
+                .get_mainchain_tip()
+                .map_err(|err| err.into_status())?,
+        };
+        let stream = self
+            .validator
+            .stream_header_chain(start_block_hash, end_block_hash)
+            .map_err(|err| tonic::Status::from_error(Box::new(err)))?
+            .map(|res| match res {
+                Ok(chain_header) => Ok(StreamHeaderChainResponse {
+                    block_hash: Some(ReverseHex::encode(&chain_header.header.block_hash())),
+                    header: Some(ConsensusHex::encode(&chain_header.header)),
+                    height: chain_header.height,
+                    cumulative_work: Some(ConsensusHex::encode(
+                        &chain_header.cumulative_work.to_le_bytes(),
+                    )),
+                }),
+                Err(err) => Err(tonic::Status::from_error(Box::new(err))),
+            })
+            .boxed();
+        Ok(tonic::Response::new(stream))
+    }
+
+    async fn get_version(
+        &self,
+        request: tonic::Request<GetVersionRequest>,
+    ) -> Result<tonic::Response<GetVersionResponse>, tonic::Status> {
+        let GetVersionRequest {} = request.into_inner();
+        let resp = GetVersionResponse {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            proto_packages: PROTO_PACKAGES
+                .iter()
+                .map(|package| (*package).to_owned())
+                .collect(),
+            features: self
+                .capabilities
+                .features()
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_withdrawal_bundle_queue(
+        &self,
+        request: tonic::Request<GetWithdrawalBundleQueueRequest>,
+    ) -> Result<tonic::Response<GetWithdrawalBundleQueueResponse>, tonic::Status> {
+        let GetWithdrawalBundleQueueRequest { sidechain_number } = request.into_inner();
+        let sidechain_number = {
+            let raw_id = sidechain_number.ok_or_else(|| {
+                missing_field::<GetWithdrawalBundleQueueRequest>("sidechain_number")
+            })?;
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<GetWithdrawalBundleQueueRequest, _>(
+                    "sidechain_number",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
+        let bundles = self
+            .validator
+            .get_withdrawal_bundle_queue(sidechain_number)
+            .map_err(|err| err.into_status())?
+            .into_iter()
+            .map(
+                |(pending_m6id, age)| get_withdrawal_bundle_queue_response::Bundle {
+                    m6id: Some(ConsensusHex::encode(&pending_m6id.m6id)),
+                    vote_count: pending_m6id.vote_count as u32,
+                    proposal_height: pending_m6id.proposal_height,
+                    age,
+                },
+            )
+            .collect();
+        let resp = GetWithdrawalBundleQueueResponse {
+            bundles,
+            max_age: WITHDRAWAL_BUNDLE_MAX_AGE as u32,
+            max_pending_bundles: MAX_PENDING_WITHDRAWAL_BUNDLES as u32,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn list_pending_withdrawal_bundles(
+        &self,
+        request: tonic::Request<ListPendingWithdrawalBundlesRequest>,
+    ) -> Result<tonic::Response<ListPendingWithdrawalBundlesResponse>, tonic::Status> {
+        let ListPendingWithdrawalBundlesRequest { sidechain_number } = request.into_inner();
+        let sidechain_number = sidechain_number
+            .map(|raw_id| {
+                SidechainNumber::try_from(raw_id).map_err(|err| {
+                    invalid_field_value::<ListPendingWithdrawalBundlesRequest, _>(
+                        "sidechain_number",
+                        &raw_id.to_string(),
+                        err,
+                    )
+                })
+            })
+            .transpose()?;
+        let (pending_m6ids, tip_height) = self
+            .validator
+            .list_pending_withdrawal_bundles(sidechain_number)
+            .map_err(|err| err.into_status())?;
+        let tip_height = tip_height.unwrap_or_default();
+        let bundles = pending_m6ids
+            .into_iter()
+            .flat_map(|(sidechain_number, pending_m6ids)| {
+                pending_m6ids
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(vote_index, pending_m6id)| {
+                        let age = tip_height.saturating_sub(pending_m6id.proposal_height);
+                        list_pending_withdrawal_bundles_response::Bundle {
+                            sidechain_number: sidechain_number.0 as u32,
+                            m6id: Some(ConsensusHex::encode(&pending_m6id.m6id)),
+                            vote_count: pending_m6id.vote_count as u32,
+                            proposal_height: pending_m6id.proposal_height,
+                            age,
+                            // Bundles can be paid out in blocks in which
+                            // their age does not exceed the max age
+                            blocks_remaining: (WITHDRAWAL_BUNDLE_MAX_AGE as u32)
+                                .saturating_sub(age),
+                            vote_index: vote_index as u32,
+                        }
+                    })
+            })
+            .collect();
+        let resp = ListPendingWithdrawalBundlesResponse {
+            bundles,
+            tip_height,
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
+    async fn get_withdrawal_bundle_status(
+        &self,
+        request: tonic::Request<GetWithdrawalBundleStatusRequest>,
+    ) -> Result<tonic::Response<GetWithdrawalBundleStatusResponse>, tonic::Status> {
+        let GetWithdrawalBundleStatusRequest {
+            sidechain_number,
+            m6id,
+        } = request.into_inner();
+        let sidechain_number = {
+            let raw_id = sidechain_number.ok_or_else(|| {
+                missing_field::<GetWithdrawalBundleStatusRequest>("sidechain_number")
+            })?;
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<GetWithdrawalBundleStatusRequest, _>(
+                    "sidechain_number",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
+        let m6id = m6id
+            .ok_or_else(|| missing_field::<GetWithdrawalBundleStatusRequest>("m6id"))?
+            .decode_tonic::<GetWithdrawalBundleStatusRequest, _>("m6id")?;
+        let status = self
+            .validator
+            .get_withdrawal_bundle_status(sidechain_number, m6id)
+            .map_err(|err| err.into_status())?;
+        let resp = GetWithdrawalBundleStatusResponse {
+            status: Some(status.into()),
+        };
+        Ok(tonic::Response::new(resp))
+    }
+
     type SubscribeEventsStream = BoxStream<'static, Result<SubscribeEventsResponse, tonic::Status>>;
 
     async fn subscribe_events(
         &self,
         request: tonic::Request<SubscribeEventsRequest>,
     ) -> Result<tonic::Response<Self::SubscribeEventsStream>, tonic::Status> {
-        let SubscribeEventsRequest { sidechain_id } = request.into_inner();
+        let SubscribeEventsRequest {
+            sidechain_id,
+            start_sequence,
+            start_block_hash,
+        } = request.into_inner();
 
         let sidechain_id = {
             let raw_id = sidechain_id
@@ -472,14 +1634,137 @@ impl ValidatorService for Validator {
             })?
         };
 
+        let events = match (start_sequence, start_block_hash) {
+            (start_sequence, None) => self
+                .validator
+                .subscribe_events(start_sequence)
+                .map(|res| res.map(|(sequence, event)| (Some(sequence), event)))
+                .boxed(),
+            (None, Some(start_block_hash)) => {
+                let start_block_hash = start_block_hash
+                    .decode_tonic::<SubscribeEventsRequest, _>("start_block_hash")?;
+                self.validator
+                    .subscribe_events_after_block(start_block_hash)
+                    .map_err(|err| match err {
+                        SubscribeEventsError::BlockNotInBestChain(_) => {
+                            tonic::Status::not_found(err.to_string())
+                        }
+                        SubscribeEventsError::BlockInfoPruned(_) => {
+                            tonic::Status::failed_precondition(err.to_string())
+                        }
+                        err => miette::Report::from(err).into_status(),
+                    })?
+                    .boxed()
+            }
+            (Some(start_sequence), Some(_)) => {
+                return Err(invalid_field_value::<SubscribeEventsRequest, _>(
+                    "start_sequence",
+                    &start_sequence.to_string(),
+                    Error::StartSequenceAndStartBlockHash,
+                ))
+            }
+        };
+        let stream = events
+            .filter_map(move |res| {
+                let resp = match res {
+                    Ok((sequence, event)) => event.into_proto(sidechain_id).map(|event| {
+                        Ok(SubscribeEventsResponse {
+                            sequence,
+                            event: Some(event.into()),
+                        })
+                    }),
+                    Err(err) => Some(Err(events_stream_status(err))),
+                };
+                futures::future::ready(resp)
+            })
+            .chain(self.events_closed())
+            .boxed();
+        Ok(tonic::Response::new(stream))
+    }
+
+    type SubscribeHeadersStream =
+        BoxStream<'static, Result<SubscribeHeadersResponse, tonic::Status>>;
+
+    async fn subscribe_headers(
+        &self,
+        request: tonic::Request<SubscribeHeadersRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeHeadersStream>, tonic::Status> {
+        let SubscribeHeadersRequest { start_sequence } = request.into_inner();
+        let validator = self.validator.clone();
+        let stream = self
+            .validator
+            .subscribe_events(start_sequence)
+            .filter_map(move |res| {
+                let event = res
+                    .map_err(events_stream_status)
+                    .and_then(|(sequence, event)| {
+                        let event = match event {
+                            Event::ConnectBlock { header_info, .. } => {
+                                subscribe_headers_response::event::Event::ConnectBlock(
+                                    header_info.into(),
+                                )
+                            }
+                            // Headers for disconnected blocks are retained
+                            Event::DisconnectBlock { block_hash } => {
+                                let header_info = validator
+                                    .get_header_info(&block_hash)
+                                    .into_diagnostic()
+                                    .map_err(IntoStatus::into_status)?;
+                                subscribe_headers_response::event::Event::DisconnectBlock(
+                                    header_info.into(),
+                                )
+                            }
+                            Event::WithdrawalBundleAlert { .. }
+                            | Event::DepositConfirmed { .. }
+                            | Event::DepositUnconfirmed { .. } => return Ok(None),
+                        };
+                        Ok(Some((sequence, event)))
+                    });
+                let resp = match event {
+                    Ok(Some((sequence, event))) => Some(Ok(SubscribeHeadersResponse {
+                        sequence: Some(sequence),
+                        event: Some(event.into()),
+                    })),
+                    Ok(None) => None,
+                    Err(err) => Some(Err(err)),
+                };
+                futures::future::ready(resp)
+            })
+            .chain(self.events_closed())
+            .boxed();
+        Ok(tonic::Response::new(stream))
+    }
+
+    type SubscribeCtipStream = BoxStream<'static, Result<SubscribeCtipResponse, tonic::Status>>;
+
+    async fn subscribe_ctip(
+        &self,
+        request: tonic::Request<SubscribeCtipRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeCtipStream>, tonic::Status> {
+        let SubscribeCtipRequest { sidechain_number } = request.into_inner();
+        let sidechain_number = {
+            let raw_id = sidechain_number
+                .ok_or_else(|| missing_field::<SubscribeCtipRequest>("sidechain_number"))?;
+
+            SidechainNumber::try_from(raw_id).map_err(|err| {
+                invalid_field_value::<SubscribeCtipRequest, _>(
+                    "sidechain_number",
+                    &raw_id.to_string(),
+                    err,
+                )
+            })?
+        };
         let stream = self
-            .subscribe_events()
-            .map(move |res| match res.into_diagnostic() {
-                Ok(event) => Ok(SubscribeEventsResponse {
-                    event: Some(event.into_proto(sidechain_id).into()),
+            .validator
+            .subscribe_ctip(sidechain_number)
+            .map_err(|err| miette::Report::from(err).into_status())?
+            .map(|res| match res {
+                Ok(ctip_info) => Ok(SubscribeCtipResponse {
+                    ctip: ctip_info.map(Ctip::from),
                 }),
-                Err(err) => Err(err.into_status()),
+                Err(err) => Err(miette::Report::from(err).into_status()),
             })
+            .chain(self.events_closed())
             .boxed();
         Ok(tonic::Response::new(stream))
     }
@@ -541,7 +1826,7 @@ fn stream_proposal_confirmations(
     fn connect_block_event(
         sidechain_proposal: &crate::types::SidechainProposal,
         confirmations: &mut HashMap<BlockHash, (u32, Arc<bitcoin::OutPoint>)>,
-        header_info: crate::types::HeaderInfo,
+        header_info: HeaderInfo,
         block_info: crate::types::BlockInfo,
     ) -> CreateSidechainProposalResponse {
         let (confirms, outpoint) = {
@@ -589,9 +1874,9 @@ fn stream_proposal_confirmations(
     }
 
     let mut confirmations = HashMap::<BlockHash, (u32, Arc<bitcoin::OutPoint>)>::new();
-    validator.subscribe_events().filter_map(move |res| {
+    validator.subscribe_events(None).filter_map(move |res| {
         let resp = match res.into_diagnostic() {
-            Ok(event) => match event {
+            Ok((_, event)) => match event {
                 Event::ConnectBlock {
                     header_info,
                     block_info,
@@ -604,7 +1889,10 @@ fn stream_proposal_confirmations(
                     );
                     Some(Ok(resp))
                 }
-                Event::DisconnectBlock { .. } => None,
+                Event::DisconnectBlock { .. }
+                | Event::WithdrawalBundleAlert { .. }
+                | Event::DepositConfirmed { .. }
+                | Event::DepositUnconfirmed { .. } => None,
             },
             Err(err) => Some(Err(err.into_status())),
         };
@@ -612,6 +1900,13 @@ fn stream_proposal_confirmations(
     })
 }
 
+/// Wallet methods that share a name with an RPC are shadowed by the
+/// `WalletService` method when called on the service, so call them on the
+/// wallet returned by this instead
+fn wallet(wallet: &Arc<crate::wallet::Wallet>) -> &crate::wallet::Wallet {
+    wallet
+}
+
 #[tonic::async_trait]
 impl WalletService for Arc<crate::wallet::Wallet> {
     type CreateSidechainProposalStream =
@@ -662,7 +1957,11 @@ impl WalletService for Arc<crate::wallet::Wallet> {
             tonic::Status::internal(err.to_string())
         })?;
 
-        tracing::info!("Persisted sidechain proposal into DB",);
+        tracing::info!(
+            sidechain_slot = sidechain_id.0,
+            "Persisted sidechain proposal `{}` into DB",
+            sidechain_proposal.description.sha256d_hash()
+        );
 
         let stream = stream_proposal_confirmations(self.validator(), sidechain_proposal).boxed();
 
@@ -675,7 +1974,10 @@ impl WalletService for Arc<crate::wallet::Wallet> {
     ) -> std::result::Result<tonic::Response<CreateNewAddressResponse>, tonic::Status> {
         let wallet = self as &Arc<crate::wallet::Wallet>;
 
-        let address = wallet.get_new_address().map_err(|err| err.into_status())?;
+        let address = wallet
+            .get_new_address()
+            .await
+            .map_err(|err| err.into_status())?;
 
         let response = CreateNewAddressResponse {
             address: address.to_string(),
@@ -683,6 +1985,41 @@ impl WalletService for Arc<crate::wallet::Wallet> {
         Ok(tonic::Response::new(response))
     }
 
+    async fn import_descriptors(
+        &self,
+        request: tonic::Request<ImportDescriptorsRequest>,
+    ) -> std::result::Result<tonic::Response<ImportDescriptorsResponse>, tonic::Status> {
+        let ImportDescriptorsRequest {
+            descriptors,
+            rescan_from_height,
+        } = request.into_inner();
+        if descriptors.is_empty() {
+            return Err(missing_field::<ImportDescriptorsRequest>("descriptors"));
+        }
+        let descriptors = descriptors
+            .into_iter()
+            .map(
+                |import_descriptors_request::Descriptor {
+                     descriptor,
+                     internal,
+                     active,
+                 }| DescriptorImport {
+                    descriptor,
+                    internal,
+                    active,
+                },
+            )
+            .collect();
+
+        // Disambiguate from `WalletService::import_descriptors`
+        crate::wallet::Wallet::import_descriptors(self, descriptors, rescan_from_height)
+            .await
+            .map_err(|err| err.into_status())?;
+
+        let response = ImportDescriptorsResponse {};
+        Ok(tonic::Response::new(response))
+    }
+
     async fn generate_blocks(
         &self,
         request: tonic::Request<GenerateBlocksRequest>,
@@ -701,22 +2038,230 @@ impl WalletService for Arc<crate::wallet::Wallet> {
         } = request.into_inner();
         let count = blocks.unwrap_or(1);
 
-        self.generate(count, ack_all_proposals)
+        let generated_blocks = self
+            .generate(count, ack_all_proposals)
             .await
             .map_err(|err| err.into_status())?;
-        let response = GenerateBlocksResponse {};
+        let blocks = generated_blocks
+            .into_iter()
+            .map(|block| generate_blocks_response::Block {
+                block_hash: Some(ReverseHex::encode(&block.block_hash)),
+                coinbase_outputs: block
+                    .coinbase_outputs
+                    .iter()
+                    .map(ConsensusHex::encode)
+                    .collect(),
+            })
+            .collect();
+        let response = GenerateBlocksResponse { blocks };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_block_template(
+        &self,
+        request: tonic::Request<GetBlockTemplateRequest>,
+    ) -> std::result::Result<tonic::Response<GetBlockTemplateResponse>, tonic::Status> {
+        let GetBlockTemplateRequest { template_json } = request.into_inner();
+        // If no template is specified, a template is fetched from the node
+        let template = template_json
+            .map(|template_json| {
+                serde_json::from_str(&template_json).map_err(|err| {
+                    invalid_field_value::<GetBlockTemplateRequest, _>(
+                        "template_json",
+                        &template_json,
+                        err,
+                    )
+                })
+            })
+            .transpose()?;
+
+        let (template, coinbase_outputs) = self
+            .augment_block_template(template)
+            .await
+            .map_err(|err| err.into_status())?;
+
+        let response = GetBlockTemplateResponse {
+            template_json: template.to_string(),
+            coinbase_outputs: coinbase_outputs.iter().map(ConsensusHex::encode).collect(),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_best_bmm_requests(
+        &self,
+        request: tonic::Request<GetBestBmmRequestsRequest>,
+    ) -> std::result::Result<tonic::Response<GetBestBmmRequestsResponse>, tonic::Status> {
+        let GetBestBmmRequestsRequest {} = request.into_inner();
+        let (prev_blockhash, bmm_requests) = self
+            .best_bmm_requests()
+            .await
+            .map_err(|err| err.into_status())?;
+        let bmm_requests = bmm_requests
+            .into_iter()
+            .map(|bmm_request| get_best_bmm_requests_response::BmmRequest {
+                txid: Some(ReverseHex::encode(&bmm_request.txid)),
+                sidechain_number: Some(bmm_request.sidechain_number.0 as u32),
+                sidechain_block_hash: Some(ConsensusHex::encode(&bmm_request.sidechain_block_hash)),
+                bid_sats: Some(bmm_request.bid.to_sat()),
+                fee_sats: Some(bmm_request.fee.to_sat()),
+            })
+            .collect();
+        let response = GetBestBmmRequestsResponse {
+            prev_block_hash: Some(ReverseHex::encode(&prev_blockhash)),
+            bmm_requests,
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_next_m4(
+        &self,
+        request: tonic::Request<GetNextM4Request>,
+    ) -> std::result::Result<tonic::Response<GetNextM4Response>, tonic::Status> {
+        let GetNextM4Request {} = request.into_inner();
+        let coinbase_output = self.next_m4_output().map_err(|err| err.into_status())?;
+        let response = GetNextM4Response {
+            coinbase_output: coinbase_output.as_ref().map(ConsensusHex::encode),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn list_bmm_requests(
+        &self,
+        request: tonic::Request<ListBmmRequestsRequest>,
+    ) -> std::result::Result<tonic::Response<ListBmmRequestsResponse>, tonic::Status> {
+        let ListBmmRequestsRequest {
+            sidechain_id,
+            limit,
+        } = request.into_inner();
+        let sidechain_number = sidechain_id
+            .map(|raw_id| {
+                SidechainNumber::try_from(raw_id).map_err(|err| {
+                    invalid_field_value::<ListBmmRequestsRequest, _>(
+                        "sidechain_id",
+                        &raw_id.to_string(),
+                        err,
+                    )
+                })
+            })
+            .transpose()?;
+        let records = self
+            .list_bmm_request_history(sidechain_number)
+            .map_err(|err| err.into_status())?;
+        let summary = BmmRequestSummary::from_records(&records);
+        let limit = limit.map_or(records.len(), |limit| limit as usize);
+        let response = ListBmmRequestsResponse {
+            bmm_requests: records.into_iter().take(limit).map(Into::into).collect(),
+            summary: Some(summary.into()),
+        };
         Ok(tonic::Response::new(response))
     }
 
+    async fn get_voting_policy(
+        &self,
+        request: tonic::Request<GetVotingPolicyRequest>,
+    ) -> std::result::Result<tonic::Response<GetVotingPolicyResponse>, tonic::Status> {
+        let GetVotingPolicyRequest {} = request.into_inner();
+        let voting_policy = wallet(self)
+            .get_voting_policy()
+            .map_err(|err| err.into_status())?;
+        let response = GetVotingPolicyResponse {
+            voting_policy: Some(voting_policy.into()),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn set_voting_policy(
+        &self,
+        request: tonic::Request<SetVotingPolicyRequest>,
+    ) -> std::result::Result<tonic::Response<SetVotingPolicyResponse>, tonic::Status> {
+        let SetVotingPolicyRequest {
+            ack_sidechains,
+            nack_sidechains,
+            bundle_votes,
+        } = request.into_inner();
+        let ack_sidechains: Vec<crate::types::SidechainAck> = ack_sidechains
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, crate::proto::Error>>()
+            .map_err(|err| err.into_status())?;
+        let nack_sidechains: Vec<crate::types::SidechainAck> = nack_sidechains
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, crate::proto::Error>>()
+            .map_err(|err| err.into_status())?;
+        let mut votes = BTreeMap::new();
+        for bundle_vote in bundle_votes {
+            let (sidechain_number, vote) = bundle_vote
+                .try_into()
+                .map_err(|err: crate::proto::Error| err.into_status())?;
+            if votes.insert(sidechain_number, vote).is_some() {
+                return Err(tonic::Status::invalid_argument(format!(
+                    "multiple bundle votes for sidechain {sidechain_number}"
+                )));
+            }
+        }
+        let wallet = wallet(self);
+        let () = wallet
+            .update_voting_policy(&ack_sidechains, &nack_sidechains, &votes)
+            .map_err(|err| err.into_status())?;
+        let voting_policy = wallet
+            .get_voting_policy()
+            .map_err(|err| err.into_status())?;
+        let response = SetVotingPolicyResponse {
+            voting_policy: Some(voting_policy.into()),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    /// Despite the RPC name, the bundle is not broadcast. An M3 proposal for
+    /// the bundle is queued, to be included in the next generated block.
     async fn broadcast_withdrawal_bundle(
         &self,
-        _request: tonic::Request<BroadcastWithdrawalBundleRequest>,
+        request: tonic::Request<BroadcastWithdrawalBundleRequest>,
     ) -> std::result::Result<tonic::Response<BroadcastWithdrawalBundleResponse>, tonic::Status>
     {
-        Err(tonic::Status::new(
-            tonic::Code::Unimplemented,
-            "not implemented",
-        ))
+        let BroadcastWithdrawalBundleRequest {
+            sidechain_id,
+            transaction,
+        } = request.into_inner();
+        let sidechain_number = sidechain_id
+            .ok_or_else(|| missing_field::<BroadcastWithdrawalBundleRequest>("sidechain_id"))
+            .map(SidechainNumber::try_from)?
+            .map_err(|err| {
+                invalid_field_value::<BroadcastWithdrawalBundleRequest, _>(
+                    "sidechain_id",
+                    &sidechain_id.unwrap_or_default().to_string(),
+                    err,
+                )
+            })?;
+        let transaction = transaction
+            .ok_or_else(|| missing_field::<BroadcastWithdrawalBundleRequest>("transaction"))?;
+        let transaction: Transaction =
+            bitcoin::consensus::deserialize(&transaction).map_err(|err| {
+                invalid_field_value::<BroadcastWithdrawalBundleRequest, _>(
+                    "transaction",
+                    &hex::encode(&transaction),
+                    err,
+                )
+            })?;
+
+        if !self
+            .is_sidechain_active(sidechain_number)
+            .map_err(|err| err.into_status())?
+        {
+            return Err(tonic::Status::failed_precondition(
+                "sidechain is not active",
+            ));
+        }
+
+        let m6id = self.propose_withdrawal_bundle(sidechain_number, &transaction)?;
+        tracing::info!(
+            sidechain_slot = sidechain_number.0,
+            "queued bundle proposal with M6ID {}",
+            hex::encode(m6id)
+        );
+        let response = BroadcastWithdrawalBundleResponse {};
+        Ok(tonic::Response::new(response))
     }
 
     // Legacy Bitcoin Core-based implementation
@@ -805,7 +2350,7 @@ impl WalletService for Arc<crate::wallet::Wallet> {
             return Err(tonic::Status::invalid_argument(message));
         }
 
-        let tx = self
+        let (tx, fee_rate) = self
             .create_bmm_request(
                 sidechain_number,
                 prev_bytes,
@@ -813,6 +2358,7 @@ impl WalletService for Arc<crate::wallet::Wallet> {
                 amount,
                 locktime,
             )
+            .await
             .map_err(|err| err.into_status())
             .and_then(|tx| {
                 tx.ok_or_else(|| {
@@ -837,73 +2383,418 @@ impl WalletService for Arc<crate::wallet::Wallet> {
 
         let response = CreateBmmCriticalDataTransactionResponse {
             txid: Some(ReverseHex::encode(&txid)),
+            fee_rate_sat_per_vbyte: Some(fee_rate.to_sat_per_vb_ceil()),
         };
         Ok(tonic::Response::new(response))
     }
 
-    async fn create_deposit_transaction(
+    async fn bump_bmm_request(
         &self,
-        request: tonic::Request<CreateDepositTransactionRequest>,
-    ) -> std::result::Result<tonic::Response<CreateDepositTransactionResponse>, tonic::Status> {
-        let CreateDepositTransactionRequest {
+        request: tonic::Request<BumpBmmRequestRequest>,
+    ) -> std::result::Result<tonic::Response<BumpBmmRequestResponse>, tonic::Status> {
+        let BumpBmmRequestRequest {
             sidechain_id,
-            address,
+            prev_bytes,
+            fee_rate_sat_per_vbyte,
+        } = request.into_inner();
+        let sidechain_number = sidechain_id
+            .ok_or_else(|| missing_field::<BumpBmmRequestRequest>("sidechain_id"))
+            .map(SidechainNumber::try_from)?
+            .map_err(|err| {
+                invalid_field_value::<BumpBmmRequestRequest, _>(
+                    "sidechain_id",
+                    &sidechain_id.unwrap_or_default().to_string(),
+                    err,
+                )
+            })?;
+        let prev_bytes = prev_bytes
+            .ok_or_else(|| missing_field::<BumpBmmRequestRequest>("prev_bytes"))?
+            .decode_tonic::<BumpBmmRequestRequest, _>("prev_bytes")
+            .map(bdk_wallet::bitcoin::BlockHash::from_byte_array)?;
+        // If no fee rate is specified, the fee rate is estimated
+        let fee_rate = fee_rate_sat_per_vbyte
+            .map(|fee_rate| {
+                bdk_wallet::bitcoin::FeeRate::from_sat_per_vb(fee_rate).ok_or_else(|| {
+                    tonic::Status::invalid_argument(format!(
+                        "invalid fee_rate_sat_per_vbyte: {fee_rate}"
+                    ))
+                })
+            })
+            .transpose()?;
+
+        // Disambiguate from `WalletService::bump_bmm_request`
+        let (tx, fee_rate) =
+            crate::wallet::Wallet::bump_bmm_request(self, sidechain_number, prev_bytes, fee_rate)
+                .await
+                .map_err(|err| err.into_status())?;
+
+        let txid = convert::bdk_txid_to_bitcoin_txid(tx.compute_txid());
+        let response = BumpBmmRequestResponse {
+            txid: Some(ReverseHex::encode(&txid)),
+            fee_rate_sat_per_vbyte: fee_rate.to_sat_per_vb_ceil(),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn bump_deposit_fee(
+        &self,
+        request: tonic::Request<BumpDepositFeeRequest>,
+    ) -> std::result::Result<tonic::Response<BumpDepositFeeResponse>, tonic::Status> {
+        let BumpDepositFeeRequest {
+            txid,
+            fee_rate_sat_per_vbyte,
+        } = request.into_inner();
+        let txid: Txid = txid
+            .ok_or_else(|| missing_field::<BumpDepositFeeRequest>("txid"))?
+            .decode_tonic::<BumpDepositFeeRequest, _>("txid")?;
+        // If no fee rate is specified, the fee rate is estimated
+        let fee_rate = fee_rate_sat_per_vbyte
+            .map(|fee_rate| {
+                bdk_wallet::bitcoin::FeeRate::from_sat_per_vb(fee_rate).ok_or_else(|| {
+                    tonic::Status::invalid_argument(format!(
+                        "invalid fee_rate_sat_per_vbyte: {fee_rate}"
+                    ))
+                })
+            })
+            .transpose()?;
+
+        let (child_tx, fee_rate) = crate::wallet::Wallet::bump_deposit_fee(self, txid, fee_rate)
+            .await
+            .map_err(|err| err.into_status())?;
+
+        let child_txid = convert::bdk_txid_to_bitcoin_txid(child_tx.compute_txid());
+        let response = BumpDepositFeeResponse {
+            child_txid: Some(ReverseHex::encode(&child_txid)),
+            fee_rate_sat_per_vbyte: fee_rate.to_sat_per_vb_ceil(),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn request_bmm(
+        &self,
+        request: tonic::Request<RequestBmmRequest>,
+    ) -> std::result::Result<tonic::Response<RequestBmmResponse>, tonic::Status> {
+        let RequestBmmRequest {
+            sidechain_id,
+            critical_hash,
             value_sats,
-            fee_sats,
+            auto_rebid,
         } = request.into_inner();
         let sidechain_number = sidechain_id
-            .ok_or_else(|| missing_field::<CreateDepositTransactionRequest>("sidechain_id"))
+            .ok_or_else(|| missing_field::<RequestBmmRequest>("sidechain_id"))
             .map(SidechainNumber::try_from)?
             .map_err(|err| {
-                invalid_field_value::<CreateDepositTransactionRequest, _>(
+                invalid_field_value::<RequestBmmRequest, _>(
                     "sidechain_id",
                     &sidechain_id.unwrap_or_default().to_string(),
                     err,
                 )
             })?;
-        let address: Vec<u8> = address
-            .ok_or_else(|| missing_field::<CreateDepositTransactionRequest>("address"))?
-            .decode_tonic::<CreateDepositTransactionRequest, _>("address")?;
-        if address.is_empty() {
-            return Err(invalid_field_value::<CreateDepositTransactionRequest, _>(
-                "address",
-                &hex::encode(address),
-                Error::AddressMustBeNonEmpty,
-            ));
-        }
-        let value = value_sats
-            .ok_or_else(|| missing_field::<CreateDepositTransactionRequest>("value_sats"))
-            .map(Amount::from_sat)?;
-        if value == Amount::ZERO {
-            return Err(invalid_field_value::<CreateDepositTransactionRequest, _>(
-                "value_sats",
-                &value.to_string(),
-                Error::ValueMustBeGreaterThanZero,
-            ));
-        }
-        let fee = fee_sats
-            .ok_or_else(|| missing_field::<CreateDepositTransactionRequest>("fee_sats"))
-            .map(Amount::from_sat)?;
+        // This is also called H*
+        let critical_hash = critical_hash
+            .ok_or_else(|| missing_field::<RequestBmmRequest>("critical_hash"))?
+            .decode_tonic::<RequestBmmRequest, _>("critical_hash")?;
+        let amount = value_sats
+            .ok_or_else(|| missing_field::<RequestBmmRequest>("value_sats"))
+            .map(bdk_wallet::bitcoin::Amount::from_sat)?;
+        let auto_rebid = auto_rebid
+            .map(|auto_rebid| {
+                let max_attempts = auto_rebid.max_attempts.ok_or_else(|| {
+                    missing_field::<request_bmm_request::AutoRebid>("max_attempts")
+                })?;
+                if max_attempts == 0 {
+                    return Err(invalid_field_value::<request_bmm_request::AutoRebid, _>(
+                        "max_attempts",
+                        &max_attempts.to_string(),
+                        Error::ValueMustBeGreaterThanZero,
+                    ));
+                }
+                let max_total_fee = auto_rebid
+                    .max_total_fee_sats
+                    .ok_or_else(|| {
+                        missing_field::<request_bmm_request::AutoRebid>("max_total_fee_sats")
+                    })
+                    .map(Amount::from_sat)?;
+                Ok((max_attempts, max_total_fee))
+            })
+            .transpose()?;
 
         if !self
             .is_sidechain_active(sidechain_number)
             .map_err(|err| err.into_status())?
         {
-            return Err(tonic::Status::new(
-                tonic::Code::FailedPrecondition,
-                format!("sidechain {sidechain_number} is not active"),
+            return Err(tonic::Status::failed_precondition(
+                "sidechain is not active",
             ));
         }
 
-        let txid = self
-            .create_deposit(sidechain_number, address, value, Some(fee))
+        // Disambiguate from `WalletService::request_bmm`
+        let (tx, prev_blockhash, fee_rate) =
+            crate::wallet::Wallet::request_bmm(self, sidechain_number, critical_hash, amount)
+                .await
+                .map_err(|err| err.into_status())?
+                .ok_or_else(|| {
+                    tonic::Status::already_exists(
+                        "BMM request with same `sidechain_number` and `prev_bytes` already exists",
+                    )
+                })?;
+
+        if let Some((max_attempts, max_total_fee)) = auto_rebid {
+            let rebid = BmmRebid {
+                sidechain_number,
+                sidechain_block_hash: critical_hash,
+                bid: Amount::from_sat(amount.to_sat()),
+                max_attempts,
+                max_total_fee,
+                attempts: 1,
+                total_fee: crate::wallet::Wallet::bmm_request_fee(&tx, fee_rate),
+                last_prev_block_hash: prev_blockhash,
+            };
+            self.put_bmm_rebid(&rebid)
+                .map_err(|err| err.into_status())?;
+        }
+
+        let txid = convert::bdk_txid_to_bitcoin_txid(tx.compute_txid());
+        let response = RequestBmmResponse {
+            txid: Some(ReverseHex::encode(&txid)),
+            prev_bytes: Some(ConsensusHex::encode(&prev_blockhash)),
+            fee_rate_sat_per_vbyte: fee_rate.to_sat_per_vb_ceil(),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn get_bmm_request_status(
+        &self,
+        request: tonic::Request<GetBmmRequestStatusRequest>,
+    ) -> std::result::Result<tonic::Response<GetBmmRequestStatusResponse>, tonic::Status> {
+        let GetBmmRequestStatusRequest {
+            sidechain_id,
+            prev_bytes,
+            critical_hash,
+        } = request.into_inner();
+        let sidechain_number = sidechain_id
+            .ok_or_else(|| missing_field::<GetBmmRequestStatusRequest>("sidechain_id"))
+            .map(SidechainNumber::try_from)?
+            .map_err(|err| {
+                invalid_field_value::<GetBmmRequestStatusRequest, _>(
+                    "sidechain_id",
+                    &sidechain_id.unwrap_or_default().to_string(),
+                    err,
+                )
+            })?;
+        let prev_blockhash = prev_bytes
+            .ok_or_else(|| missing_field::<GetBmmRequestStatusRequest>("prev_bytes"))?
+            .decode_tonic::<GetBmmRequestStatusRequest, _>("prev_bytes")
+            .map(BlockHash::from_byte_array)?;
+        let critical_hash = critical_hash
+            .ok_or_else(|| missing_field::<GetBmmRequestStatusRequest>("critical_hash"))?
+            .decode_tonic::<GetBmmRequestStatusRequest, _>("critical_hash")?;
+
+        let status = crate::wallet::Wallet::get_bmm_request_status(
+            self,
+            sidechain_number,
+            prev_blockhash,
+            critical_hash,
+        )
+        .map_err(|err| err.into_status())?;
+        let status = match status {
+            BmmRequestStatus::Pending => get_bmm_request_status_response::Status::Pending(
+                get_bmm_request_status_response::Pending {},
+            ),
+            BmmRequestStatus::Accepted { block_hash } => {
+                get_bmm_request_status_response::Status::Accepted(
+                    get_bmm_request_status_response::Accepted {
+                        block_hash: Some(ReverseHex::encode(&block_hash)),
+                    },
+                )
+            }
+            BmmRequestStatus::NotAccepted { block_hash } => {
+                get_bmm_request_status_response::Status::NotAccepted(
+                    get_bmm_request_status_response::NotAccepted {
+                        block_hash: Some(ReverseHex::encode(&block_hash)),
+                    },
+                )
+            }
+            BmmRequestStatus::Expired => get_bmm_request_status_response::Status::Expired(
+                get_bmm_request_status_response::Expired {},
+            ),
+        };
+        let response = GetBmmRequestStatusResponse {
+            status: Some(status),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn cancel_bmm_rebid(
+        &self,
+        request: tonic::Request<CancelBmmRebidRequest>,
+    ) -> std::result::Result<tonic::Response<CancelBmmRebidResponse>, tonic::Status> {
+        let CancelBmmRebidRequest { sidechain_id } = request.into_inner();
+        let sidechain_number = sidechain_id
+            .ok_or_else(|| missing_field::<CancelBmmRebidRequest>("sidechain_id"))
+            .map(SidechainNumber::try_from)?
+            .map_err(|err| {
+                invalid_field_value::<CancelBmmRebidRequest, _>(
+                    "sidechain_id",
+                    &sidechain_id.unwrap_or_default().to_string(),
+                    err,
+                )
+            })?;
+        let cancelled = wallet(self)
+            .cancel_bmm_rebid(sidechain_number)
+            .map_err(|err| err.into_status())?;
+        let response = CancelBmmRebidResponse { cancelled };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn create_deposit_transaction(
+        &self,
+        request: tonic::Request<CreateDepositTransactionRequest>,
+    ) -> std::result::Result<tonic::Response<CreateDepositTransactionResponse>, tonic::Status> {
+        let CreateDepositTransactionRequest {
+            sidechain_id,
+            address,
+            value_sats,
+            fee_sats,
+        } = request.into_inner();
+        let (destination, value) = parse_deposit_request::<CreateDepositTransactionRequest>(
+            self,
+            sidechain_id,
+            address,
+            value_sats,
+        )?;
+        // If no fee is specified, the fee rate is estimated
+        let fee = fee_sats.map(Amount::from_sat);
+
+        let (txid, fee_rate) = self
+            .create_deposit(destination, value, fee)
             .await
             .map_err(|err| err.into_status())?;
 
         let txid = ReverseHex::encode(&txid);
-        let response = CreateDepositTransactionResponse { txid: Some(txid) };
+        let response = CreateDepositTransactionResponse {
+            txid: Some(txid),
+            fee_rate_sat_per_vbyte: fee_rate.map(|fee_rate| fee_rate.to_sat_per_vb_ceil()),
+        };
         Ok(tonic::Response::new(response))
     }
+
+    async fn create_deposit_psbt(
+        &self,
+        request: tonic::Request<CreateDepositPsbtRequest>,
+    ) -> std::result::Result<tonic::Response<CreateDepositPsbtResponse>, tonic::Status> {
+        let CreateDepositPsbtRequest {
+            sidechain_id,
+            address,
+            value_sats,
+            fee_sats,
+        } = request.into_inner();
+        let (destination, value) = parse_deposit_request::<CreateDepositPsbtRequest>(
+            self,
+            sidechain_id,
+            address,
+            value_sats,
+        )?;
+        // If no fee is specified, the fee rate is estimated
+        let fee = fee_sats.map(Amount::from_sat);
+
+        // Disambiguate from `WalletService::create_deposit_psbt`
+        let (psbt, fee_rate) =
+            crate::wallet::Wallet::create_deposit_psbt(self, destination, value, fee)
+                .await
+                .map_err(|err| err.into_status())?;
+
+        let response = CreateDepositPsbtResponse {
+            psbt: Some(ConsensusHex {
+                hex: Some(hex::encode(psbt.serialize())),
+            }),
+            fee_rate_sat_per_vbyte: fee_rate.map(|fee_rate| fee_rate.to_sat_per_vb_ceil()),
+        };
+        Ok(tonic::Response::new(response))
+    }
+
+    async fn finalize_psbt(
+        &self,
+        request: tonic::Request<FinalizePsbtRequest>,
+    ) -> std::result::Result<tonic::Response<FinalizePsbtResponse>, tonic::Status> {
+        let FinalizePsbtRequest { psbt } = request.into_inner();
+        let psbt_hex = psbt
+            .and_then(|psbt| psbt.hex)
+            .ok_or_else(|| missing_field::<FinalizePsbtRequest>("psbt"))?;
+        let psbt = hex::decode(&psbt_hex)
+            .map_err(|err| invalid_field_value::<FinalizePsbtRequest, _>("psbt", &psbt_hex, err))
+            .and_then(|bytes| {
+                bdk_wallet::bitcoin::psbt::Psbt::deserialize(&bytes).map_err(|err| {
+                    invalid_field_value::<FinalizePsbtRequest, _>("psbt", &psbt_hex, err)
+                })
+            })?;
+
+        let tx = self
+            .finalize_and_broadcast_psbt(psbt)
+            .await
+            .map_err(|err| err.into_status())?;
+
+        let txid = convert::bdk_txid_to_bitcoin_txid(tx.compute_txid());
+        let response = FinalizePsbtResponse {
+            txid: Some(ReverseHex::encode(&txid)),
+        };
+        Ok(tonic::Response::new(response))
+    }
+}
+
+/// Validate the deposit destination and value of a deposit request, and
+/// check that the sidechain is active
+fn parse_deposit_request<Message>(
+    wallet: &crate::wallet::Wallet,
+    sidechain_id: Option<u32>,
+    address: Option<Hex>,
+    value_sats: Option<u64>,
+) -> Result<(DepositDestination, Amount), tonic::Status>
+where
+    Message: prost::Name,
+{
+    let sidechain_number = sidechain_id
+        .ok_or_else(|| missing_field::<Message>("sidechain_id"))
+        .map(SidechainNumber::try_from)?
+        .map_err(|err| {
+            invalid_field_value::<Message, _>(
+                "sidechain_id",
+                &sidechain_id.unwrap_or_default().to_string(),
+                err,
+            )
+        })?;
+    let address: Vec<u8> = address
+        .ok_or_else(|| missing_field::<Message>("address"))?
+        .decode_tonic::<Message, _>("address")?;
+    let address_hex = hex::encode(&address);
+    let address = DepositAddress::try_from(address)
+        .map_err(|err| invalid_field_value::<Message, _>("address", &address_hex, err))?;
+    let value = value_sats
+        .ok_or_else(|| missing_field::<Message>("value_sats"))
+        .map(Amount::from_sat)?;
+    if value == Amount::ZERO {
+        return Err(invalid_field_value::<Message, _>(
+            "value_sats",
+            &value.to_string(),
+            Error::ValueMustBeGreaterThanZero,
+        ));
+    }
+
+    if !wallet
+        .is_sidechain_active(sidechain_number)
+        .map_err(|err| err.into_status())?
+    {
+        return Err(tonic::Status::new(
+            tonic::Code::FailedPrecondition,
+            format!("sidechain {sidechain_number} is not active"),
+        ));
+    }
+
+    let destination = DepositDestination {
+        sidechain_number,
+        address,
+    };
+    Ok((destination, value))
 }
 
 #[derive(Debug, Error)]
@@ -911,8 +2802,22 @@ enum Error {
     #[error("value must be greater than zero")]
     ValueMustBeGreaterThanZero,
 
-    #[error("address must be non-empty")]
-    AddressMustBeNonEmpty,
+    #[error("only one of `block_hash` and `height` may be set")]
+    BlockHashAndHeight,
+
+    #[error("only one of `start_sequence` and `start_block_hash` may be set")]
+    StartSequenceAndStartBlockHash,
+
+    #[error("value must not exceed {0}")]
+    ValueMustNotExceed(u32),
+
+    #[error("`end_height` must not be less than `start_height`")]
+    EndHeightBeforeStartHeight,
+
+    #[error(
+        "range of {blocks} blocks exceeds the max of {max} blocks, use `StreamTwoWayPegData` for larger ranges"
+    )]
+    RangeTooLarge { blocks: u32, max: u32 },
 }
 
 #[derive(Debug, Default)]
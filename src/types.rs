@@ -45,7 +45,7 @@ impl From<SidechainNumber> for u8 {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Ctip {
     pub outpoint: OutPoint,
     pub value: Amount,
@@ -172,10 +172,7 @@ impl SidechainDeclaration {
     /// with nom, but the author doesn't know how. It's fine to do this in the outer function,
     /// anyways.
     fn try_parse(input: &[u8]) -> nom::IResult<&[u8], Self, ParseSidechainDeclarationError> {
-        use nom::{
-            bytes::complete::{tag, take},
-            number::complete::be_u8,
-        };
+        use nom::{bytes::complete::take, number::complete::be_u8};
         fn failed_to_deserialize(
             err: nom::Err<nom::error::Error<&[u8]>>,
         ) -> nom::Err<ParseSidechainDeclarationError> {
@@ -183,10 +180,12 @@ impl SidechainDeclaration {
                 .map(ParseSidechainDeclarationError::FailedToDeserialize)
         }
         const VERSION_0: u8 = 0;
-        let (input, _) =
-            tag(&[VERSION_0])(input).map_err(|_: nom::Err<nom::error::Error<&[u8]>>| {
-                nom::Err::Error(ParseSidechainDeclarationError::UnknownVersion(input[0]))
-            })?;
+        let (input, version) = be_u8(input).map_err(failed_to_deserialize)?;
+        if version != VERSION_0 {
+            return Err(nom::Err::Error(
+                ParseSidechainDeclarationError::UnknownVersion(version),
+            ));
+        }
 
         let (input, title_length) = be_u8(input).map_err(failed_to_deserialize)?;
         let (input, title_bytes) = take(title_length)(input).map_err(failed_to_deserialize)?;
@@ -197,7 +196,15 @@ impl SidechainDeclaration {
         const HASH_ID_1_LENGTH: usize = 32;
         const HASH_ID_2_LENGTH: usize = 20;
 
-        let description_length = input.len() - HASH_ID_1_LENGTH - HASH_ID_2_LENGTH;
+        let description_length = input
+            .len()
+            .checked_sub(HASH_ID_1_LENGTH + HASH_ID_2_LENGTH)
+            .ok_or_else(|| {
+                failed_to_deserialize(nom::Err::Error(nom::error::Error::new(
+                    input,
+                    nom::error::ErrorKind::Eof,
+                )))
+            })?;
         let (input, description_bytes) =
             take(description_length)(input).map_err(failed_to_deserialize)?;
         let description = std::str::from_utf8(description_bytes).map_err(|err| {
@@ -247,6 +254,64 @@ pub struct SidechainAck {
 pub struct PendingM6id {
     pub m6id: Hash256,
     pub vote_count: u16,
+    /// Height of the block containing the M3 that proposed the bundle
+    pub proposal_height: u32,
+}
+
+/// Running totals for a sidechain slot, updated as blocks are connected.
+/// Totals are kept across sidechain replacements, along with the slot's
+/// treasury.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SidechainStats {
+    pub total_deposited: Amount,
+    /// Total value paid out of the treasury by M6s, including fees
+    pub total_withdrawn: Amount,
+    pub deposit_count: u64,
+    pub succeeded_withdrawal_bundle_count: u64,
+    pub failed_withdrawal_bundle_count: u64,
+}
+
+impl SidechainStats {
+    /// Add the deposit or M6 that created a treasury UTXO.
+    /// Treasury UTXOs are only recorded for deposits and M6s, and M6s are the
+    /// only treasury UTXOs with a lower total value than their predecessor.
+    pub fn add_treasury_utxo(&mut self, treasury_utxo: &TreasuryUtxo) {
+        if treasury_utxo.total_value < treasury_utxo.previous_total_value {
+            self.total_withdrawn += treasury_utxo.previous_total_value - treasury_utxo.total_value;
+        } else {
+            self.total_deposited += treasury_utxo.total_value - treasury_utxo.previous_total_value;
+            self.deposit_count += 1;
+        }
+    }
+
+    /// Remove a treasury UTXO that was added with
+    /// [`Self::add_treasury_utxo`]
+    pub fn remove_treasury_utxo(&mut self, treasury_utxo: &TreasuryUtxo) {
+        if treasury_utxo.total_value < treasury_utxo.previous_total_value {
+            self.total_withdrawn -= treasury_utxo.previous_total_value - treasury_utxo.total_value;
+        } else {
+            self.total_deposited -= treasury_utxo.total_value - treasury_utxo.previous_total_value;
+            self.deposit_count -= 1;
+        }
+    }
+
+    pub fn add_withdrawal_bundle_outcome(&mut self, outcome: &WithdrawalBundleOutcome) {
+        match outcome {
+            WithdrawalBundleOutcome::Succeeded { .. } => {
+                self.succeeded_withdrawal_bundle_count += 1
+            }
+            WithdrawalBundleOutcome::Failed { .. } => self.failed_withdrawal_bundle_count += 1,
+        }
+    }
+
+    pub fn remove_withdrawal_bundle_outcome(&mut self, outcome: &WithdrawalBundleOutcome) {
+        match outcome {
+            WithdrawalBundleOutcome::Succeeded { .. } => {
+                self.succeeded_withdrawal_bundle_count -= 1
+            }
+            WithdrawalBundleOutcome::Failed { .. } => self.failed_withdrawal_bundle_count -= 1,
+        }
+    }
 }
 
 #[derive(derive_more::Debug, Deserialize, Serialize)]
@@ -267,7 +332,18 @@ pub struct Deposit {
     pub value: Amount,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// A deposit that reached, or fell below, the configured confirmation depth
+/// for its sidechain
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DepositConfirmation {
+    pub deposit: Deposit,
+    /// Block that includes the deposit
+    pub deposit_block_hash: BlockHash,
+    pub deposit_block_height: u32,
+    pub confirmations: u32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub struct HeaderInfo {
     pub block_hash: BlockHash,
     pub prev_block_hash: BlockHash,
@@ -289,10 +365,96 @@ pub struct WithdrawalBundleEvent {
     pub kind: WithdrawalBundleEventKind,
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum WithdrawalBundleFailureReason {
+    /// The bundle was not paid out before its age exceeded
+    /// [`crate::validator::WITHDRAWAL_BUNDLE_MAX_AGE`]
+    Expired,
+}
+
+/// Outcome of a withdrawal bundle that is no longer pending
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum WithdrawalBundleOutcome {
+    Succeeded {
+        /// Txid of the M6 that paid out the bundle
+        txid: Txid,
+        block_hash: BlockHash,
+        height: u32,
+    },
+    Failed {
+        block_hash: BlockHash,
+        height: u32,
+        reason: WithdrawalBundleFailureReason,
+    },
+}
+
+impl WithdrawalBundleOutcome {
+    /// Block in which the bundle succeeded or failed
+    pub fn block_hash(&self) -> BlockHash {
+        match self {
+            Self::Succeeded { block_hash, .. } | Self::Failed { block_hash, .. } => *block_hash,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum WithdrawalBundleStatus {
+    /// The bundle has not been proposed, or was resolved before bundle
+    /// outcomes were indexed
+    Unknown,
+    Pending {
+        vote_count: u16,
+        proposal_height: u32,
+        /// Number of blocks since the bundle was proposed
+        age: u32,
+    },
+    Resolved(WithdrawalBundleOutcome),
+}
+
+/// Raised when miners broadcast an alarm vote for a sidechain, downvoting
+/// all of its pending withdrawal bundles. Repeated alarm votes are the early
+/// warning sign of a contested withdrawal.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WithdrawalBundleAlert {
+    pub sidechain_id: SidechainNumber,
+    pub m6id: Hash256,
+    /// Vote count before the alarm vote
+    pub previous_vote_count: u16,
+    pub vote_count: u16,
+    /// `true` if the bundle had the most votes of the sidechain's pending
+    /// bundles before the alarm vote
+    pub leading: bool,
+}
+
+/// Kinds of BIP300/301 transactions processed by the enforcer
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum Bip300TransactionKind {
+    /// Coinbase tx containing at least one M1, M2, M3, M4 or M7 message
+    Coinbase,
+    M5Deposit {
+        sidechain_number: SidechainNumber,
+    },
+    M6Withdrawal {
+        sidechain_number: SidechainNumber,
+    },
+    /// BMM request. `accepted` is `false` if the request was rejected,
+    /// ie. if it was not accepted by miners or had expired.
+    M8BmmRequest {
+        accepted: bool,
+    },
+}
+
+/// A BIP300/301 transaction, and the block that it was processed in
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Bip300Transaction {
+    pub block_hash: BlockHash,
+    pub kind: Bip300TransactionKind,
+}
+
 /// BMM commitments for a single block
 pub type BmmCommitments = LinkedHashMap<SidechainNumber, Hash256>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct BlockInfo {
     /// Sequential map of sidechain IDs to BMM commitments
     pub bmm_commitments: BmmCommitments,
@@ -310,7 +472,7 @@ pub struct TwoWayPegData {
     pub block_info: BlockInfo,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Event {
     ConnectBlock {
         header_info: HeaderInfo,
@@ -319,6 +481,30 @@ pub enum Event {
     DisconnectBlock {
         block_hash: BlockHash,
     },
+    /// Sent after the `ConnectBlock` event for the block that raised the
+    /// alert
+    WithdrawalBundleAlert {
+        block_hash: BlockHash,
+        alert: WithdrawalBundleAlert,
+    },
+    /// Sent after the `ConnectBlock` or `DisconnectBlock` event for the
+    /// block that changed the best chain tip, if the deposit has the
+    /// configured number of confirmations for its sidechain in the new best
+    /// chain, but not in the previous best chain
+    DepositConfirmed {
+        block_hash: BlockHash,
+        confirmation: DepositConfirmation,
+    },
+    /// Sent after the `ConnectBlock` or `DisconnectBlock` event for the
+    /// block that changed the best chain tip, if the confirmations of a
+    /// confirmed deposit fell below the configured number of confirmations
+    /// for its sidechain. Deposits that are no longer in the best chain have
+    /// zero confirmations. The deposit is confirmed again if it regains
+    /// enough confirmations.
+    DepositUnconfirmed {
+        block_hash: BlockHash,
+        confirmation: DepositConfirmation,
+    },
 }
 
 #[cfg(test)]
@@ -433,4 +619,25 @@ mod tests {
             "sidechain_proposal::unknown_version"
         );
     }
+
+    #[test]
+    fn test_try_deserialize_truncated() {
+        for description in [
+            vec![],
+            vec![0],
+            vec![
+                0, // version
+                5, // title length
+                b'H', b'e', b'l', b'l', b'o', // title
+            ],
+        ] {
+            let sidechain_proposal = proposal(description);
+            let result: Result<SidechainDeclaration, _> =
+                (&sidechain_proposal.description).try_into();
+            assert_eq!(
+                format!("{}", result.unwrap_err().code().unwrap()),
+                "sidechain_proposal::failed_to_deserialize"
+            );
+        }
+    }
 }
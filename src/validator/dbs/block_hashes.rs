@@ -1,6 +1,9 @@
 use bitcoin::{block::Header, hashes::Hash as _, BlockHash, Txid, Work};
 use fallible_iterator::FallibleIterator;
-use heed::{types::SerdeBincode, RoTxn};
+use heed::{
+    types::{Bytes, SerdeBincode},
+    RoTxn,
+};
 
 use crate::{
     types::{
@@ -20,19 +23,19 @@ pub mod error {
 
     #[derive(Debug, Error)]
     #[error("Missing header info for block hash `{block_hash}`")]
-    pub(crate) struct MissingHeader {
+    pub struct MissingHeader {
         pub(super) block_hash: BlockHash,
     }
 
     #[derive(Debug, Error)]
     #[error("Missing parent for block hash `{block_hash}`: `{prev_block_hash}`")]
-    pub(crate) struct MissingParent {
+    pub struct MissingParent {
         pub(super) block_hash: BlockHash,
         pub(super) prev_block_hash: BlockHash,
     }
 
     #[derive(Debug, Error)]
-    pub(crate) enum PutBlockInfo {
+    pub enum PutBlockInfo {
         #[error(transparent)]
         DbPut(#[from] db_error::Put),
         #[error(transparent)]
@@ -44,7 +47,7 @@ pub mod error {
     }
 
     #[derive(Debug, Error)]
-    pub(crate) enum TryGetHeaderInfo {
+    pub enum TryGetHeaderInfo {
         #[error(transparent)]
         DbTryGet(#[from] db_error::TryGet),
         #[error(transparent)]
@@ -52,7 +55,7 @@ pub mod error {
     }
 
     #[derive(Debug, Error)]
-    pub(crate) enum GetHeaderInfo {
+    pub enum GetHeaderInfo {
         #[error(transparent)]
         MissingHeader(#[from] MissingHeader),
         #[error(transparent)]
@@ -60,7 +63,7 @@ pub mod error {
     }
 
     #[derive(Debug, Error)]
-    pub(crate) enum TryGetBlockInfo {
+    pub enum TryGetBlockInfo {
         #[error(transparent)]
         DbTryGet(#[from] db_error::TryGet),
         #[error(transparent)]
@@ -68,7 +71,7 @@ pub mod error {
     }
 
     #[derive(Debug, Error)]
-    pub(crate) enum GetBlockInfo {
+    pub enum GetBlockInfo {
         #[error("Missing block info for block hash `{block_hash}`")]
         MissingValue { block_hash: BlockHash },
         #[error(transparent)]
@@ -76,7 +79,7 @@ pub mod error {
     }
 
     #[derive(Debug, Error)]
-    pub(crate) enum TryGetTwoWayPegData {
+    pub enum TryGetTwoWayPegData {
         #[error(transparent)]
         TryGetBlockInfo(#[from] TryGetBlockInfo),
         #[error(transparent)]
@@ -106,18 +109,22 @@ pub mod error {
     }
 }
 
+/// Block info DBs (`bmm_commitments`, `coinbase_txid`, `deposits`,
+/// `sidechain_proposals`, `withdrawal_bundle_events`) may be pruned for
+/// blocks deep below the tip, so ancestors are only guaranteed to exist in
+/// `cumulative_work`, `header`, and `height`.
 #[derive(Clone)]
 pub struct BlockHashDbs {
-    // All ancestors for each block MUST exist in this DB.
+    // All ancestors for each block MUST exist in this DB, unless pruned.
     // All keys in this DB MUST also exist in ALL other DBs.
     bmm_commitments: Database<SerdeBincode<BlockHash>, SerdeBincode<BmmCommitments>>,
-    // All ancestors for each block MUST exist in this DB.
+    // All ancestors for each block MUST exist in this DB, unless pruned.
     // All keys in this DB MUST also exist in ALL other DBs.
     coinbase_txid: Database<SerdeBincode<BlockHash>, SerdeBincode<Txid>>,
     // All ancestors for each block MUST exist in this DB.
-    // All keys in this DB MUST also exist in ALL other DBs.
+    // All keys in this DB MUST also exist in `header` and `height`.
     cumulative_work: Database<SerdeBincode<BlockHash>, SerdeBincode<Work>>,
-    // All ancestors for each block MUST exist in this DB.
+    // All ancestors for each block MUST exist in this DB, unless pruned.
     // All keys in this DB MUST also exist in ALL other DBs.
     deposits: Database<SerdeBincode<BlockHash>, SerdeBincode<Vec<Deposit>>>,
     // All keys in this DB MUST also exist in `height`
@@ -126,11 +133,11 @@ pub struct BlockHashDbs {
     // `prev_blockhash` in a value
     height: Database<SerdeBincode<BlockHash>, SerdeBincode<u32>>,
     /// Sidechain proposals in each block sorted by coinbase vout
-    // All ancestors for each block MUST exist in this DB.
+    // All ancestors for each block MUST exist in this DB, unless pruned.
     // All keys in this DB MUST also exist in ALL other DBs.
     sidechain_proposals:
         Database<SerdeBincode<BlockHash>, SerdeBincode<Vec<(u32, SidechainProposal)>>>,
-    // All ancestors for each block MUST exist in this DB.
+    // All ancestors for each block MUST exist in this DB, unless pruned.
     // All keys in this DB MUST also exist in ALL other DBs.
     withdrawal_bundle_events:
         Database<SerdeBincode<BlockHash>, SerdeBincode<Vec<WithdrawalBundleEvent>>>,
@@ -171,6 +178,10 @@ impl BlockHashDbs {
         (*self.cumulative_work).clone()
     }
 
+    pub fn header(&self) -> RoDatabase<SerdeBincode<BlockHash>, SerdeBincode<Header>> {
+        (*self.header).clone()
+    }
+
     pub fn height(&self) -> RoDatabase<SerdeBincode<BlockHash>, SerdeBincode<u32>> {
         (*self.height).clone()
     }
@@ -184,13 +195,14 @@ impl BlockHashDbs {
         self.header.contains_key(rotxn, block_hash)
     }
 
-    /// Check if the database contains the provided block
+    /// Check if the database contains the provided block.
+    /// Returns `true` for blocks with pruned block info.
     pub fn contains_block(
         &self,
         rotxn: &RoTxn,
         block_hash: &BlockHash,
     ) -> Result<bool, db_error::TryGet> {
-        self.bmm_commitments.contains_key(rotxn, block_hash)
+        self.cumulative_work.contains_key(rotxn, block_hash)
     }
 
     /// Store info for a single header
@@ -211,6 +223,24 @@ impl BlockHashDbs {
         Ok(())
     }
 
+    /// Store a header with known cumulative work, without its ancestors.
+    /// Used to replay block fixtures, where ancestors are not available.
+    pub fn put_header_with_cumulative_work(
+        &self,
+        rwtxn: &mut RwTxn,
+        header: &Header,
+        height: u32,
+        cumulative_work: &Work,
+    ) -> Result<(), db_error::Put> {
+        let block_hash = header.block_hash();
+        let () = self.header.put(rwtxn, &block_hash, header)?;
+        let () = self.height.put(rwtxn, &block_hash, &height)?;
+        let () = self
+            .cumulative_work
+            .put(rwtxn, &block_hash, cumulative_work)?;
+        Ok(())
+    }
+
     /// Store info for a single block
     pub fn put_block_info(
         &self,
@@ -259,6 +289,54 @@ impl BlockHashDbs {
         Ok(())
     }
 
+    /// Delete block info for a single block, retaining the header and
+    /// cumulative work.
+    /// Returns `false` if the block info did not exist, or was already
+    /// pruned.
+    pub fn prune_block_info(
+        &self,
+        rwtxn: &mut RwTxn,
+        block_hash: &BlockHash,
+    ) -> Result<bool, db_error::Delete> {
+        if !self.bmm_commitments.delete(rwtxn, block_hash)? {
+            return Ok(false);
+        }
+        let _: bool = self.coinbase_txid.delete(rwtxn, block_hash)?;
+        let _: bool = self.deposits.delete(rwtxn, block_hash)?;
+        let _: bool = self.sidechain_proposals.delete(rwtxn, block_hash)?;
+        let _: bool = self.withdrawal_bundle_events.delete(rwtxn, block_hash)?;
+        Ok(true)
+    }
+
+    /// Delete block info and cumulative work for a single block, retaining
+    /// the header.
+    /// Returns `false` if the block did not exist.
+    pub fn delete_block_info(
+        &self,
+        rwtxn: &mut RwTxn,
+        block_hash: &BlockHash,
+    ) -> Result<bool, db_error::Delete> {
+        if !self.cumulative_work.delete(rwtxn, block_hash)? {
+            return Ok(false);
+        }
+        let _: bool = self.prune_block_info(rwtxn, block_hash)?;
+        Ok(true)
+    }
+
+    /// All DBs, with raw keys and values
+    pub fn raw_dbs(&self) -> [RoDatabase<Bytes, Bytes>; Self::NUM_DBS as usize] {
+        [
+            (*self.bmm_commitments).as_bytes(),
+            (*self.coinbase_txid).as_bytes(),
+            (*self.cumulative_work).as_bytes(),
+            (*self.deposits).as_bytes(),
+            (*self.header).as_bytes(),
+            (*self.height).as_bytes(),
+            (*self.sidechain_proposals).as_bytes(),
+            (*self.withdrawal_bundle_events).as_bytes(),
+        ]
+    }
+
     /// Iterate over existing ancestor headers, including the provided block
     /// hash, if it exists in the DB.
     /// Note that ancestor headers may not exist in the DB.
@@ -299,6 +377,30 @@ impl BlockHashDbs {
         }
     }
 
+    /// Find the most recent common ancestor of two blocks, which may be one
+    /// of the blocks. Headers for both blocks and their ancestors, back to
+    /// the common ancestor, must exist in the DB.
+    pub fn common_ancestor(
+        &self,
+        rotxn: &RoTxn,
+        block_hash_a: &BlockHash,
+        block_hash_b: &BlockHash,
+    ) -> Result<HeaderInfo, error::GetHeaderInfo> {
+        let mut header_info_a = self.get_header_info(rotxn, block_hash_a)?;
+        let mut header_info_b = self.get_header_info(rotxn, block_hash_b)?;
+        while header_info_a.height > header_info_b.height {
+            header_info_a = self.get_header_info(rotxn, &header_info_a.prev_block_hash)?;
+        }
+        while header_info_b.height > header_info_a.height {
+            header_info_b = self.get_header_info(rotxn, &header_info_b.prev_block_hash)?;
+        }
+        while header_info_a.block_hash != header_info_b.block_hash {
+            header_info_a = self.get_header_info(rotxn, &header_info_a.prev_block_hash)?;
+            header_info_b = self.get_header_info(rotxn, &header_info_b.prev_block_hash)?;
+        }
+        Ok(header_info_a)
+    }
+
     pub fn try_get_header_info(
         &self,
         rotxn: &RoTxn,
@@ -1,32 +1,65 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use bitcoin::hashes::sha256d;
-use heed::{types::SerdeBincode, EnvOpenOptions, RoTxn};
+use bitcoin::{hashes::sha256d, BlockHash, OutPoint, Txid};
+use fallible_iterator::FallibleIterator as _;
+use heed::{
+    byteorder::BigEndian,
+    types::{Bytes, SerdeBincode, U64},
+    EnvOpenOptions, RoTxn,
+};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::types::{Ctip, Hash256, PendingM6id, Sidechain, SidechainNumber, TreasuryUtxo};
+use crate::types::{
+    Bip300Transaction, Ctip, Event, Hash256, PendingM6id, Sidechain, SidechainNumber,
+    SidechainStats, TreasuryUtxo, WithdrawalBundleOutcome,
+};
 
 mod block_hashes;
 mod util;
 
 pub use block_hashes::{error as block_hash_dbs_error, BlockHashDbs};
 pub use util::{
-    db_error, CommitWriteTxnError, Database, Env, ReadTxnError, RwTxn, UnitKey, WriteTxnError,
+    db_error, CommitWriteTxnError, CopyEnvError, Database, Env, ReadTxnError, RoDatabase, RwTxn,
+    SyncEnvError, UnitKey, WriteTxnError,
 };
 
+/// Entries overwritten when connecting a block, as raw key/value pairs.
+/// Values are `None` for entries that did not exist.
+pub type RawEntries = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// Data required to disconnect a block, recorded when the block is
+/// connected
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BlockUndo {
+    /// Previous entries for each of [`Dbs::sidechain_state_dbs`]
+    pub sidechain_state: Vec<RawEntries>,
+    /// Keys for treasury UTXOs created by the block
+    pub treasury_utxos: Vec<(SidechainNumber, u64)>,
+    /// Previous entries for BIP300/301 transactions in the block
+    pub bip300_transactions: Vec<(Txid, Option<Bip300Transaction>)>,
+    /// Chain tip before the block was connected
+    pub previous_chain_tip: Option<BlockHash>,
+}
+
 /// These DBs should all contain exacty the same keys.
 #[derive(Clone)]
-pub(super) struct ActiveSidechainDbs {
+pub struct ActiveSidechainDbs {
     pub ctip: Database<SerdeBincode<SidechainNumber>, SerdeBincode<Ctip>>,
     pub pending_m6ids: Database<SerdeBincode<SidechainNumber>, SerdeBincode<Vec<PendingM6id>>>,
     pub sidechain: Database<SerdeBincode<SidechainNumber>, SerdeBincode<Sidechain>>,
     pub slot_sequence_to_treasury_utxo:
         Database<SerdeBincode<(SidechainNumber, u64)>, SerdeBincode<TreasuryUtxo>>,
+    pub stats: Database<SerdeBincode<SidechainNumber>, SerdeBincode<SidechainStats>>,
     pub treasury_utxo_count: Database<SerdeBincode<SidechainNumber>, SerdeBincode<u64>>,
 }
 
 impl ActiveSidechainDbs {
-    const NUM_DBS: u32 = 5;
+    const NUM_DBS: u32 = 6;
 
     fn new(env: &Env, rwtxn: &mut RwTxn) -> Result<Self, util::CreateDbError> {
         let ctip = env.create_db(rwtxn, "active_sidechain_number_to_ctip")?;
@@ -34,6 +67,7 @@ impl ActiveSidechainDbs {
         let sidechain = env.create_db(rwtxn, "active_sidechain_number_to_sidechain")?;
         let slot_sequence_to_treasury_utxo =
             env.create_db(rwtxn, "active_sidechain_slot_sequence_to_treasury_utxo")?;
+        let stats = env.create_db(rwtxn, "active_sidechain_number_to_stats")?;
         let treasury_utxo_count =
             env.create_db(rwtxn, "active_sidechain_number_to_treasury_utxo_count")?;
         Ok(Self {
@@ -41,6 +75,7 @@ impl ActiveSidechainDbs {
             pending_m6ids,
             sidechain,
             slot_sequence_to_treasury_utxo,
+            stats,
             treasury_utxo_count,
         })
     }
@@ -52,6 +87,16 @@ pub enum CreateDbsError {
     CommitWriteTxn(#[from] util::CommitWriteTxnError),
     #[error(transparent)]
     CreateDb(#[from] util::CreateDbError),
+    #[error(transparent)]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    DbGet(#[from] db_error::Get),
+    #[error(transparent)]
+    DbLen(#[from] db_error::Len),
+    #[error(transparent)]
+    DbPut(#[from] db_error::Put),
+    #[error(transparent)]
+    DbTryGet(#[from] db_error::TryGet),
     #[error("Error creating directory (`{path}`)")]
     CreateDirectory {
         path: PathBuf,
@@ -59,28 +104,290 @@ pub enum CreateDbsError {
     },
     #[error(transparent)]
     OpenEnv(#[from] util::OpenEnvError),
+    #[error("Timed out closing database env at `{path}` before swapping in compacted copy")]
+    CloseEnv { path: PathBuf },
+    #[error(transparent)]
+    CopyEnv(#[from] util::CopyEnvError),
+    #[error(transparent)]
+    DiskSize(#[from] util::DiskSizeError),
+    #[error("Error removing compacted database file (`{path}`)")]
+    RemoveCompacted {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Error removing compaction marker file (`{path}`)")]
+    RemoveCompactionMarker {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Error swapping in compacted database file (`{path}`)")]
+    SwapCompacted {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Validator DBs in `{path}` have schema version {found}, but this enforcer only supports schema versions up to {expected}. Run `reindex` to resync the DBs from the node, or `snapshot import` a snapshot exported by a compatible enforcer")]
+    UnsupportedSchemaVersion {
+        path: PathBuf,
+        found: u32,
+        expected: u32,
+    },
     #[error(transparent)]
     WriteTxn(#[from] util::WriteTxnError),
 }
 
+#[derive(Debug, Error)]
+pub enum ScheduleCompactionError {
+    #[error(transparent)]
+    DiskSize(#[from] util::DiskSizeError),
+    #[error("Error writing compaction marker file (`{path}`)")]
+    WriteMarker {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum CheckError {
+    #[error(transparent)]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    DbLen(#[from] db_error::Len),
+    #[error(transparent)]
+    DbTryGet(#[from] db_error::TryGet),
+    #[error(transparent)]
+    ReadTxn(#[from] util::ReadTxnError),
+}
+
+#[derive(Debug, Error)]
+pub enum AppendEventError {
+    #[error(transparent)]
+    DbDelete(#[from] db_error::Delete),
+    #[error(transparent)]
+    DbFirst(#[from] db_error::First),
+    #[error(transparent)]
+    DbLast(#[from] db_error::Last),
+    #[error(transparent)]
+    DbPut(#[from] db_error::Put),
+}
+
+#[derive(Debug, Error)]
+pub enum DeleteDbsError {
+    #[error("Error removing database directory (`{path}`)")]
+    RemoveDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ImportSnapshotError {
+    #[error("Error copying snapshot `{src}` to `{dst}`")]
+    Copy {
+        src: PathBuf,
+        dst: PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    CreateDbs(#[from] Box<CreateDbsError>),
+    #[error("Error creating directory (`{path}`)")]
+    CreateDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Database already exists at `{path}`")]
+    DbExists { path: PathBuf },
+}
+
+/// Compaction that was scheduled for the next startup
+#[derive(Clone, Debug)]
+pub struct ScheduledCompaction {
+    /// Path to the marker file. Its presence requests that the live data
+    /// file is compacted on next startup.
+    pub marker_path: PathBuf,
+    /// Current size of the live data file
+    pub size: u64,
+}
+
+/// Validator DBs.
+/// Writes are made by the validator task, and the DBs should be treated as
+/// read-only elsewhere.
 #[derive(Clone)]
-pub(super) struct Dbs {
+pub struct Dbs {
     env: Env,
     pub active_sidechains: ActiveSidechainDbs,
+    /// Block hashes for each height in the current best chain
+    pub best_chain_height_to_block_hash: Database<SerdeBincode<u32>, SerdeBincode<BlockHash>>,
     pub block_hashes: BlockHashDbs,
+    /// Undo data for connected blocks. Pruned along with block info.
+    pub block_undo: Database<SerdeBincode<BlockHash>, SerdeBincode<BlockUndo>>,
+    /// Reverse index of active sidechain CTIPs, so that CTIP spends can be
+    /// classified without checking the CTIP for every sidechain
+    pub ctip_outpoint_to_sidechain: Database<SerdeBincode<OutPoint>, SerdeBincode<SidechainNumber>>,
     /// Tip that the enforcer is synced to
     pub current_chain_tip: Database<SerdeBincode<UnitKey>, SerdeBincode<bitcoin::BlockHash>>,
     pub description_hash_to_sidechain:
         Database<SerdeBincode<sha256d::Hash>, SerdeBincode<Sidechain>>,
+    /// Append-only journal of events, keyed by sequence number.
+    /// Keys are big-endian, so that events are iterated in sequence order.
+    pub events: Database<U64<BigEndian>, SerdeBincode<Event>>,
     pub _leading_by_50: Database<SerdeBincode<UnitKey>, SerdeBincode<Vec<Hash256>>>,
-    pub _previous_votes: Database<SerdeBincode<UnitKey>, SerdeBincode<Vec<Hash256>>>,
+    /// Two byte upvotes of the most recent M4 message, which are repeated by
+    /// [`crate::messages::M4AckBundles::RepeatPrevious`]. Empty if the most
+    /// recent block did not include an M4 message.
+    pub previous_votes: Database<SerdeBincode<UnitKey>, SerdeBincode<Vec<u16>>>,
+    /// Layout version of the DBs, see [`Self::SCHEMA_VERSION`]
+    schema_version: Database<SerdeBincode<UnitKey>, SerdeBincode<u32>>,
+    /// Unspent treasury outputs created in connected blocks. Used to detect
+    /// spends of treasury outputs other than CTIPs without prevouts,
+    /// regardless of how blocks are fetched. Entries are removed when the
+    /// output is spent, or when the block that created them is disconnected.
+    pub treasury_outpoint_to_sidechain:
+        Database<SerdeBincode<OutPoint>, SerdeBincode<SidechainNumber>>,
+    /// BIP300/301 transactions processed by the enforcer
+    pub txid_to_bip300_transaction: Database<SerdeBincode<Txid>, SerdeBincode<Bip300Transaction>>,
+    /// Outcomes of withdrawal bundles that succeeded or failed in the current
+    /// best chain, oldest first. A bundle that failed can be proposed and
+    /// resolved again. Outcomes are removed when the block that resolved the
+    /// bundle is disconnected.
+    pub withdrawal_bundle_outcomes: Database<
+        SerdeBincode<(SidechainNumber, Hash256)>,
+        SerdeBincode<Vec<WithdrawalBundleOutcome>>,
+    >,
 }
 
 impl Dbs {
-    const NUM_DBS: u32 = ActiveSidechainDbs::NUM_DBS + BlockHashDbs::NUM_DBS + 4;
+    const NUM_DBS: u32 = ActiveSidechainDbs::NUM_DBS + BlockHashDbs::NUM_DBS + 12;
+
+    /// Layout version of the DBs. Must be incremented when the encoding of
+    /// existing DB entries changes, eg. when a field is added to a value,
+    /// and DBs with older versions migrated in [`Self::new`].
+    /// DBs created before schema versions were recorded are version 0.
+    ///
+    /// Version 1: pending M6IDs record their proposal height.
+    const SCHEMA_VERSION: u32 = 1;
+
+    /// Max number of events retained in the event journal
+    pub const MAX_JOURNAL_EVENTS: u64 = 1_000_000;
+
+    /// LMDB data file name
+    const DATA_FILE_NAME: &'static str = "data.mdb";
+
+    /// Empty marker file. If present, the live data file is compacted on
+    /// next startup.
+    const COMPACT_ON_STARTUP_FILE_NAME: &'static str = "compact-on-startup";
+
+    /// File name that compacted copies are written to, before they are
+    /// renamed. A crash while copying never leaves a partial copy at
+    /// [`Self::DATA_FILE_NAME`].
+    const COMPACTING_DATA_FILE_NAME: &'static str = "data.mdb.compacting";
+
+    /// Max time to wait for the env to close before swapping in a compacted
+    /// data file on startup
+    const COMPACT_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn db_dir(data_dir: &Path, network: bitcoin::Network) -> PathBuf {
+        data_dir.join(format!("{network}.mdb"))
+    }
+
+    fn open_env(db_dir: &Path) -> Result<Env, util::OpenEnvError> {
+        // 1 GB
+        const GB: usize = 1024 * 1024 * 1024;
+        // 10 GB
+        const DB_MAP_SIZE: usize = 10 * GB;
+        let mut env_opts = EnvOpenOptions::new();
+        let _: &mut EnvOpenOptions = env_opts.max_dbs(Self::NUM_DBS).map_size(DB_MAP_SIZE);
+        unsafe { Env::open(&env_opts, db_dir.to_owned()) }
+    }
+
+    /// If compaction was scheduled via [`Self::schedule_compaction`],
+    /// replace the live data file with a compacted copy, before the sync
+    /// task starts writing.
+    fn compact_on_startup(db_dir: &Path) -> Result<(), CreateDbsError> {
+        let marker_file = db_dir.join(Self::COMPACT_ON_STARTUP_FILE_NAME);
+        if !marker_file.exists() {
+            return Ok(());
+        }
+        let compacting_data_file = db_dir.join(Self::COMPACTING_DATA_FILE_NAME);
+        if compacting_data_file.exists() {
+            if let Err(err) = std::fs::remove_file(&compacting_data_file) {
+                return Err(CreateDbsError::RemoveCompacted {
+                    path: compacting_data_file,
+                    source: err,
+                });
+            }
+        }
+        let env = Self::open_env(db_dir)?;
+        let size_before = env.disk_size()?;
+        let () = env.copy_compacted(&compacting_data_file)?;
+        if !env.close(Self::COMPACT_CLOSE_TIMEOUT) {
+            return Err(CreateDbsError::CloseEnv {
+                path: db_dir.to_owned(),
+            });
+        }
+        // `rename` atomically replaces the existing data file
+        let data_file = db_dir.join(Self::DATA_FILE_NAME);
+        if let Err(err) = std::fs::rename(&compacting_data_file, &data_file) {
+            return Err(CreateDbsError::SwapCompacted {
+                path: compacting_data_file,
+                source: err,
+            });
+        }
+        if let Err(err) = std::fs::remove_file(&marker_file) {
+            return Err(CreateDbsError::RemoveCompactionMarker {
+                path: marker_file,
+                source: err,
+            });
+        }
+        let size_after = std::fs::metadata(&data_file).map_or(0, |metadata| metadata.len());
+        tracing::info!(
+            "Compacted validator DBs in {} ({size_before} bytes -> {size_after} bytes)",
+            data_file.display()
+        );
+        Ok(())
+    }
+
+    /// Re-encode pending M6IDs from schema version 0, which did not record
+    /// proposal heights. Proposal heights are set to the height of the
+    /// current tip, so that pending bundles are not expired early. Bundles
+    /// may be expired up to [`crate::validator::WITHDRAWAL_BUNDLE_MAX_AGE`]
+    /// blocks later than they would otherwise have been.
+    fn migrate_pending_m6ids_v0(
+        env: &Env,
+        rwtxn: &mut RwTxn,
+        pending_m6ids: &Database<SerdeBincode<SidechainNumber>, SerdeBincode<Vec<PendingM6id>>>,
+        tip_height: u32,
+    ) -> Result<(), CreateDbsError> {
+        /// [`PendingM6id`] in schema version 0
+        #[derive(Deserialize)]
+        struct PendingM6idV0 {
+            m6id: Hash256,
+            vote_count: u16,
+        }
+        let pending_m6ids_v0: Database<
+            SerdeBincode<SidechainNumber>,
+            SerdeBincode<Vec<PendingM6idV0>>,
+        > = env.create_db(rwtxn, pending_m6ids.name())?;
+        let entries: Vec<_> = pending_m6ids_v0
+            .iter(rwtxn)
+            .map_err(db_error::Iter::from)?
+            .map_err(db_error::Iter::from)
+            .collect()?;
+        for (sidechain_number, pending_m6ids_v0) in entries {
+            let migrated: Vec<_> = pending_m6ids_v0
+                .into_iter()
+                .map(|pending_m6id| PendingM6id {
+                    m6id: pending_m6id.m6id,
+                    vote_count: pending_m6id.vote_count,
+                    proposal_height: tip_height,
+                })
+                .collect();
+            let () = pending_m6ids.put(rwtxn, &sidechain_number, &migrated)?;
+        }
+        Ok(())
+    }
 
     pub fn new(data_dir: &Path, network: bitcoin::Network) -> Result<Self, CreateDbsError> {
-        let db_dir = data_dir.join(format!("{network}.mdb"));
+        let db_dir = Self::db_dir(data_dir, network);
         if let Err(err) = std::fs::create_dir_all(&db_dir) {
             let err = CreateDbsError::CreateDirectory {
                 path: db_dir,
@@ -88,37 +395,481 @@ impl Dbs {
             };
             return Err(err);
         }
-        let env = {
-            // 1 GB
-            const GB: usize = 1024 * 1024 * 1024;
-            // 10 GB
-            const DB_MAP_SIZE: usize = 10 * GB;
-            let mut env_opts = EnvOpenOptions::new();
-            let _: &mut EnvOpenOptions = env_opts.max_dbs(Self::NUM_DBS).map_size(DB_MAP_SIZE);
-            unsafe { Env::open(&env_opts, db_dir.clone()) }?
-        };
+        let () = Self::compact_on_startup(&db_dir)?;
+        let env = Self::open_env(&db_dir)?;
         let mut rwtxn = env.write_txn()?;
+        // Refuse to open DBs with a newer layout, rather than failing to
+        // decode entries later. DBs with an older layout are migrated, and
+        // the current version is recorded once the backfills below have run.
+        let schema_version = env.create_db(&mut rwtxn, "schema_version")?;
+        let current_chain_tip = env.create_db(&mut rwtxn, "current_chain_tip")?;
+        let found_schema_version = schema_version.try_get(&rwtxn, &UnitKey)?;
+        let tip = current_chain_tip.try_get(&rwtxn, &UnitKey)?;
+        let migrate_from = match found_schema_version {
+            Some(Self::SCHEMA_VERSION) => None,
+            Some(found) if found > Self::SCHEMA_VERSION => {
+                return Err(CreateDbsError::UnsupportedSchemaVersion {
+                    path: db_dir,
+                    found,
+                    expected: Self::SCHEMA_VERSION,
+                });
+            }
+            Some(found) => Some(found),
+            // New DBs are created with the current version
+            None if tip.is_none() => None,
+            None => Some(0),
+        };
         let active_sidechains = ActiveSidechainDbs::new(&env, &mut rwtxn)?;
+        let best_chain_height_to_block_hash =
+            env.create_db(&mut rwtxn, "best_chain_height_to_block_hash")?;
         let block_hashes = BlockHashDbs::new(&env, &mut rwtxn)?;
-        let current_chain_tip = env.create_db(&mut rwtxn, "current_chain_tip")?;
+        if let (Some(0), Some(tip)) = (migrate_from, tip) {
+            let tip_height = block_hashes.height().get(&rwtxn, &tip)?;
+            let () = Self::migrate_pending_m6ids_v0(
+                &env,
+                &mut rwtxn,
+                &active_sidechains.pending_m6ids,
+                tip_height,
+            )?;
+        }
+        let block_undo = env.create_db(&mut rwtxn, "block_hash_to_undo")?;
+        let ctip_outpoint_to_sidechain = env.create_db(&mut rwtxn, "ctip_outpoint_to_sidechain")?;
+        // Populate the index for DBs created before it existed
+        let ctips: Vec<_> = active_sidechains
+            .ctip
+            .iter(&rwtxn)
+            .map_err(db_error::Iter::from)?
+            .map_err(db_error::Iter::from)
+            .collect()?;
+        for (sidechain_number, ctip) in ctips {
+            let () =
+                ctip_outpoint_to_sidechain.put(&mut rwtxn, &ctip.outpoint, &sidechain_number)?;
+        }
         let description_hash_to_sidechain =
             env.create_db(&mut rwtxn, "description_hash_to_sidechain")?;
+        let events = env.create_db(&mut rwtxn, "events")?;
         let leading_by_50 = env.create_db(&mut rwtxn, "leading_by_50")?;
         let previous_votes = env.create_db(&mut rwtxn, "previous_votes")?;
+        let treasury_outpoint_to_sidechain =
+            env.create_db(&mut rwtxn, "treasury_outpoint_to_sidechain")?;
+        // Populate the index for DBs created before it existed. Each
+        // recorded treasury UTXO spends the previous CTIP, so only the
+        // current CTIPs are unspent.
+        if treasury_outpoint_to_sidechain.len(&rwtxn)? == 0 {
+            let treasury_outpoints: Vec<_> = active_sidechains
+                .ctip
+                .iter(&rwtxn)
+                .map_err(db_error::Iter::from)?
+                .map_err(db_error::Iter::from)
+                .map(|(sidechain_number, ctip)| Ok((ctip.outpoint, sidechain_number)))
+                .collect()?;
+            for (outpoint, sidechain_number) in treasury_outpoints {
+                let () =
+                    treasury_outpoint_to_sidechain.put(&mut rwtxn, &outpoint, &sidechain_number)?;
+            }
+        }
+        let txid_to_bip300_transaction = env.create_db(&mut rwtxn, "txid_to_bip300_transaction")?;
+        let withdrawal_bundle_outcomes = env.create_db(&mut rwtxn, "withdrawal_bundle_outcomes")?;
+        // Populate stats for DBs created before they were tracked. Treasury
+        // UTXOs are only recorded for deposits and M6s.
+        if active_sidechains.stats.len(&rwtxn)? == 0 {
+            let mut stats = BTreeMap::<SidechainNumber, SidechainStats>::new();
+            let () = active_sidechains
+                .slot_sequence_to_treasury_utxo
+                .iter(&rwtxn)
+                .map_err(db_error::Iter::from)?
+                .map_err(db_error::Iter::from)
+                .for_each(|((sidechain_number, _), treasury_utxo)| {
+                    stats
+                        .entry(sidechain_number)
+                        .or_default()
+                        .add_treasury_utxo(&treasury_utxo);
+                    Ok(())
+                })?;
+            let () = withdrawal_bundle_outcomes
+                .iter(&rwtxn)
+                .map_err(db_error::Iter::from)?
+                .map_err(db_error::Iter::from)
+                .for_each(|((sidechain_number, _), outcomes)| {
+                    let stats = stats.entry(sidechain_number).or_default();
+                    for outcome in &outcomes {
+                        stats.add_withdrawal_bundle_outcome(outcome);
+                    }
+                    Ok(())
+                })?;
+            for (sidechain_number, stats) in stats {
+                let () = active_sidechains
+                    .stats
+                    .put(&mut rwtxn, &sidechain_number, &stats)?;
+            }
+        }
+        if found_schema_version != Some(Self::SCHEMA_VERSION) {
+            let () = schema_version.put(&mut rwtxn, &UnitKey, &Self::SCHEMA_VERSION)?;
+        }
         let () = rwtxn.commit()?;
+        if let Some(from_version) = migrate_from {
+            tracing::info!(
+                "Migrated validator DBs from schema version {from_version} to {}",
+                Self::SCHEMA_VERSION
+            );
+        }
 
         tracing::info!("Created validator DBs in {}", db_dir.display());
         Ok(Self {
             env,
             active_sidechains,
+            best_chain_height_to_block_hash,
             block_hashes,
+            block_undo,
+            ctip_outpoint_to_sidechain,
             current_chain_tip,
             description_hash_to_sidechain,
+            events,
             _leading_by_50: leading_by_50,
-            _previous_votes: previous_votes,
+            previous_votes,
+            schema_version,
+            treasury_outpoint_to_sidechain,
+            txid_to_bip300_transaction,
+            withdrawal_bundle_outcomes,
         })
     }
 
+    /// Sidechain state DBs. When connecting a block, the previous value of
+    /// each entry is recorded in [`BlockUndo`] before the entry is written.
+    /// Undo data and fixtures depend on the number and order of these DBs,
+    /// so DBs that can be reverted from other undo data, such as
+    /// [`ActiveSidechainDbs::stats`], are not included.
+    pub fn sidechain_state_dbs(&self) -> [Database<Bytes, Bytes>; 8] {
+        [
+            self.active_sidechains.ctip.as_bytes(),
+            self.active_sidechains.pending_m6ids.as_bytes(),
+            self.active_sidechains.sidechain.as_bytes(),
+            self.active_sidechains.treasury_utxo_count.as_bytes(),
+            self.ctip_outpoint_to_sidechain.as_bytes(),
+            self.description_hash_to_sidechain.as_bytes(),
+            self.previous_votes.as_bytes(),
+            self.treasury_outpoint_to_sidechain.as_bytes(),
+        ]
+    }
+
+    /// All DBs, with raw keys and values
+    pub fn raw_dbs(&self) -> Vec<RoDatabase<Bytes, Bytes>> {
+        let mut res = vec![
+            (*self.active_sidechains.ctip).as_bytes(),
+            (*self.active_sidechains.pending_m6ids).as_bytes(),
+            (*self.active_sidechains.sidechain).as_bytes(),
+            (*self.active_sidechains.slot_sequence_to_treasury_utxo).as_bytes(),
+            (*self.active_sidechains.stats).as_bytes(),
+            (*self.active_sidechains.treasury_utxo_count).as_bytes(),
+            (*self.best_chain_height_to_block_hash).as_bytes(),
+            (*self.block_undo).as_bytes(),
+            (*self.ctip_outpoint_to_sidechain).as_bytes(),
+            (*self.current_chain_tip).as_bytes(),
+            (*self.description_hash_to_sidechain).as_bytes(),
+            (*self.events).as_bytes(),
+            (*self._leading_by_50).as_bytes(),
+            (*self.previous_votes).as_bytes(),
+            (*self.schema_version).as_bytes(),
+            (*self.treasury_outpoint_to_sidechain).as_bytes(),
+            (*self.txid_to_bip300_transaction).as_bytes(),
+            (*self.withdrawal_bundle_outcomes).as_bytes(),
+        ];
+        res.extend(self.block_hashes.raw_dbs());
+        res
+    }
+
+    /// Sequence number of the next event to be appended to the journal
+    pub fn next_event_sequence(&self, rotxn: &RoTxn) -> Result<u64, db_error::Last> {
+        let next_sequence = self
+            .events
+            .lazy_decode()
+            .last(rotxn)?
+            .map_or(0, |(sequence, _)| sequence + 1);
+        Ok(next_sequence)
+    }
+
+    /// Append an event to the journal, returning its sequence number.
+    /// The oldest events are removed, so that at most
+    /// [`Self::MAX_JOURNAL_EVENTS`] events are retained.
+    pub fn append_event(&self, rwtxn: &mut RwTxn, event: &Event) -> Result<u64, AppendEventError> {
+        let sequence = self.next_event_sequence(rwtxn)?;
+        let () = self.events.put(rwtxn, &sequence, event)?;
+        let retain_from = (sequence + 1).saturating_sub(Self::MAX_JOURNAL_EVENTS);
+        while let Some((oldest, _)) = self.events.lazy_decode().first(rwtxn)? {
+            if oldest >= retain_from {
+                break;
+            }
+            let _: bool = self.events.delete(rwtxn, &oldest)?;
+        }
+        Ok(sequence)
+    }
+
+    /// Sequence number of the oldest event in the journal, if any
+    pub fn first_event_sequence(&self, rotxn: &RoTxn) -> Result<Option<u64>, db_error::First> {
+        let first = self.events.lazy_decode().first(rotxn)?;
+        Ok(first.map(|(sequence, _)| sequence))
+    }
+
+    /// Read up to `limit` events from the journal, starting at sequence
+    /// number `start_sequence`
+    pub fn read_events(
+        &self,
+        rotxn: &RoTxn,
+        start_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<(u64, Event)>, db_error::Iter> {
+        let events = self
+            .events
+            .range(rotxn, &(start_sequence..))?
+            .take(limit)
+            .collect()?;
+        Ok(events)
+    }
+
+    /// Sequence number of the most recent `ConnectBlock` event for the
+    /// specified block, if it is in the journal
+    pub fn find_connect_block_event(
+        &self,
+        rotxn: &RoTxn,
+        block_hash: &BlockHash,
+    ) -> Result<Option<u64>, db_error::Iter> {
+        let sequence = self
+            .events
+            .rev_iter(rotxn)?
+            .find(|(_, event)| {
+                Ok(matches!(
+                    event,
+                    Event::ConnectBlock { header_info, .. }
+                        if header_info.block_hash == *block_hash
+                ))
+            })?
+            .map(|(sequence, _)| sequence);
+        Ok(sequence)
+    }
+
+    /// Schedule compaction of the live data file on next startup, by
+    /// writing an empty marker file alongside it.
+    /// LMDB envs cannot be re-opened in place while the DBs are in use, so
+    /// the live data file is only compacted when the enforcer restarts,
+    /// before any writes are made.
+    pub fn schedule_compaction(&self) -> Result<ScheduledCompaction, ScheduleCompactionError> {
+        let size = self.env.disk_size()?;
+        let marker_path = self.env.path().join(Self::COMPACT_ON_STARTUP_FILE_NAME);
+        if let Err(err) = std::fs::write(&marker_path, b"") {
+            return Err(ScheduleCompactionError::WriteMarker {
+                path: marker_path,
+                source: err,
+            });
+        }
+        tracing::info!(
+            "Scheduled compaction of validator DBs ({size} bytes) on next startup, marker file: {}",
+            marker_path.display()
+        );
+        Ok(ScheduledCompaction { marker_path, size })
+    }
+
+    /// Check if DBs exist for the specified network
+    pub fn exist(data_dir: &Path, network: bitcoin::Network) -> bool {
+        Self::db_dir(data_dir, network)
+            .join(Self::DATA_FILE_NAME)
+            .exists()
+    }
+
+    /// Delete the DBs for the specified network, if they exist.
+    /// Returns `true` if the DBs existed.
+    pub fn delete(data_dir: &Path, network: bitcoin::Network) -> Result<bool, DeleteDbsError> {
+        let db_dir = Self::db_dir(data_dir, network);
+        if !db_dir.exists() {
+            return Ok(false);
+        }
+        match std::fs::remove_dir_all(&db_dir) {
+            Ok(()) => {
+                tracing::info!("Deleted validator DBs in {}", db_dir.display());
+                Ok(true)
+            }
+            Err(err) => Err(DeleteDbsError::RemoveDir {
+                path: db_dir,
+                source: err,
+            }),
+        }
+    }
+
+    /// Write a compacted snapshot of the DBs to a new file at `path`
+    pub fn export_snapshot(&self, path: &Path) -> Result<(), util::CopyEnvError> {
+        let () = self.env.copy_compacted(path)?;
+        tracing::info!("Exported validator DB snapshot to {}", path.display());
+        Ok(())
+    }
+
+    /// Restore the DBs for the specified network from a snapshot file.
+    /// Fails if the DBs already exist. The snapshot is opened after copying,
+    /// and removed again if it cannot be opened.
+    pub fn import_snapshot(
+        data_dir: &Path,
+        network: bitcoin::Network,
+        path: &Path,
+    ) -> Result<Self, ImportSnapshotError> {
+        let db_dir = Self::db_dir(data_dir, network);
+        let data_file = db_dir.join(Self::DATA_FILE_NAME);
+        if data_file.exists() {
+            return Err(ImportSnapshotError::DbExists { path: data_file });
+        }
+        if let Err(err) = std::fs::create_dir_all(&db_dir) {
+            return Err(ImportSnapshotError::CreateDirectory {
+                path: db_dir,
+                source: err,
+            });
+        }
+        if let Err(err) = std::fs::copy(path, &data_file) {
+            return Err(ImportSnapshotError::Copy {
+                src: path.to_owned(),
+                dst: data_file,
+                source: err,
+            });
+        }
+        match Self::new(data_dir, network) {
+            Ok(dbs) => {
+                tracing::info!("Imported validator DB snapshot from {}", path.display());
+                Ok(dbs)
+            }
+            Err(err) => {
+                if let Err(remove_err) = std::fs::remove_dir_all(&db_dir) {
+                    tracing::error!(
+                        "Failed to remove invalid snapshot at {}: {remove_err:#}",
+                        db_dir.display()
+                    );
+                }
+                Err(ImportSnapshotError::CreateDbs(Box::new(err)))
+            }
+        }
+    }
+
+    /// Check consistency between DBs.
+    /// Returns a description of each inconsistency found.
+    pub fn check(&self) -> Result<Vec<String>, CheckError> {
+        let rotxn = self.read_txn()?;
+        let mut problems = Vec::new();
+        let sidechain_db = &self.active_sidechains.sidechain;
+        // Every active sidechain DB must only contain active sidechains
+        let ctip_sidechains: Vec<_> = self
+            .active_sidechains
+            .ctip
+            .iter(&rotxn)
+            .map_err(db_error::Iter::from)?
+            .map_err(db_error::Iter::from)
+            .collect()?;
+        for (sidechain_number, _) in &ctip_sidechains {
+            if !sidechain_db.contains_key(&rotxn, sidechain_number)? {
+                problems.push(format!(
+                    "CTIP exists for inactive sidechain {sidechain_number}"
+                ));
+            }
+        }
+        let pending_m6id_sidechains: Vec<_> = self
+            .active_sidechains
+            .pending_m6ids
+            .lazy_decode()
+            .iter(&rotxn)
+            .map_err(db_error::Iter::from)?
+            .map(|(sidechain_number, _)| Ok(sidechain_number))
+            .map_err(db_error::Iter::from)
+            .collect()?;
+        for sidechain_number in pending_m6id_sidechains {
+            if !sidechain_db.contains_key(&rotxn, &sidechain_number)? {
+                problems.push(format!(
+                    "Pending M6IDs exist for inactive sidechain {sidechain_number}"
+                ));
+            }
+        }
+        let treasury_utxo_counts: Vec<_> = self
+            .active_sidechains
+            .treasury_utxo_count
+            .iter(&rotxn)
+            .map_err(db_error::Iter::from)?
+            .map_err(db_error::Iter::from)
+            .collect()?;
+        for (sidechain_number, count) in treasury_utxo_counts {
+            if !sidechain_db.contains_key(&rotxn, &sidechain_number)? {
+                problems.push(format!(
+                    "Treasury UTXO count exists for inactive sidechain {sidechain_number}"
+                ));
+            }
+            if let Some(last_sequence) = count.checked_sub(1) {
+                if !self
+                    .active_sidechains
+                    .slot_sequence_to_treasury_utxo
+                    .contains_key(&rotxn, &(sidechain_number, last_sequence))?
+                {
+                    problems.push(format!(
+                        "Missing treasury UTXO {last_sequence} for sidechain {sidechain_number}"
+                    ));
+                }
+            }
+        }
+        // The CTIP index must match the CTIPs exactly
+        for (sidechain_number, ctip) in &ctip_sidechains {
+            match self
+                .ctip_outpoint_to_sidechain
+                .try_get(&rotxn, &ctip.outpoint)?
+            {
+                Some(indexed) if indexed == *sidechain_number => (),
+                Some(indexed) => problems.push(format!(
+                    "CTIP {} for sidechain {sidechain_number} is indexed to sidechain {indexed}",
+                    ctip.outpoint
+                )),
+                None => problems.push(format!(
+                    "CTIP {} for sidechain {sidechain_number} is not indexed",
+                    ctip.outpoint
+                )),
+            }
+        }
+        let ctip_index_len = self.ctip_outpoint_to_sidechain.len(&rotxn)?;
+        if ctip_index_len != ctip_sidechains.len() as u64 {
+            problems.push(format!(
+                "CTIP index contains {ctip_index_len} entries, but {} CTIPs exist",
+                ctip_sidechains.len()
+            ));
+        }
+        // The tip must be a known block in the best chain index
+        if let Some(tip) = self.current_chain_tip.try_get(&rotxn, &UnitKey)? {
+            match self.block_hashes.height().try_get(&rotxn, &tip)? {
+                None => problems.push(format!("Missing header for chain tip {tip}")),
+                Some(height) => {
+                    match self
+                        .best_chain_height_to_block_hash
+                        .try_get(&rotxn, &height)?
+                    {
+                        Some(block_hash) if block_hash == tip => (),
+                        Some(block_hash) => problems.push(format!(
+                            "Best chain block at height {height} is {block_hash}, but tip is {tip}"
+                        )),
+                        None => problems.push(format!(
+                            "Chain tip {tip} is missing from best chain index at height {height}"
+                        )),
+                    }
+                    if !self.block_hashes.contains_block(&rotxn, &tip)? {
+                        problems.push(format!("Missing block info for chain tip {tip}"));
+                    }
+                }
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Flush the DBs to disk
+    pub fn force_sync(&self) -> Result<(), SyncEnvError> {
+        self.env.force_sync()
+    }
+
+    /// Flush the DBs to disk and close the env, once all other handles to
+    /// the DBs have been dropped.
+    /// Waits at most `timeout` for other handles to be dropped, and returns
+    /// `false` if the env is still open.
+    pub fn close(self, timeout: Duration) -> Result<bool, SyncEnvError> {
+        let () = self.env.force_sync()?;
+        Ok(self.env.close(timeout))
+    }
+
     pub fn read_txn(&self) -> Result<RoTxn<'_>, ReadTxnError> {
         self.env.read_txn()
     }
@@ -1,12 +1,16 @@
 use std::{
-    ops::{Deref, DerefMut},
+    ops::{Deref, DerefMut, RangeBounds},
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use educe::Educe;
 use fallible_iterator::{FallibleIterator, IteratorExt};
-use heed::{types::LazyDecode, BytesDecode, BytesEncode, EnvOpenOptions, RoTxn};
+use heed::{
+    types::{Bytes, LazyDecode},
+    BytesDecode, BytesEncode, EnvOpenOptions, RoTxn,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -139,6 +143,14 @@ pub mod db_error {
         Item(#[from] IterItem),
     }
 
+    #[derive(Debug, Error)]
+    #[error("Failed to read last item from db `{db_name}` at `{db_path}`")]
+    pub struct Last {
+        pub(super) db_name: &'static str,
+        pub(super) db_path: PathBuf,
+        pub(super) source: heed::Error,
+    }
+
     #[derive(Debug, Error)]
     #[error("Failed to read length for db `{db_name}` at `{db_path}`")]
     pub struct Len {
@@ -208,7 +220,7 @@ pub mod db_error {
         .exists_in,
         .not_in
     )]
-    pub(crate) struct InconsistentDbs {
+    pub struct InconsistentDbs {
         key: Vec<u8>,
         exists_in: &'static str,
         not_in: &'static str,
@@ -319,6 +331,97 @@ impl<KC, DC> RoDatabase<KC, DC> {
         }
     }
 
+    /// Iterate over all items, in reverse key order
+    pub fn rev_iter<'txn>(
+        &self,
+        rotxn: &'txn RoTxn<'_>,
+    ) -> Result<
+        fallible_iterator::MapErr<
+            fallible_iterator::Convert<heed::RoRevIter<'txn, KC, DC>>,
+            impl FnMut(heed::Error) -> db_error::IterItem + '_,
+        >,
+        db_error::IterInit,
+    >
+    where
+        KC: BytesDecode<'txn>,
+        DC: BytesDecode<'txn>,
+    {
+        match self.inner.rev_iter(rotxn) {
+            Ok(it) => Ok(it.transpose_into_fallible().map_err({
+                let db_path = self.path.clone();
+                move |err| db_error::IterItem {
+                    db_name: self.name,
+                    db_path: (*db_path).clone(),
+                    source: err,
+                }
+            })),
+            Err(err) => Err(db_error::IterInit {
+                db_name: self.name,
+                db_path: (*self.path).clone(),
+                source: err,
+            }),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn last<'txn>(
+        &self,
+        rotxn: &'txn RoTxn<'_>,
+    ) -> Result<Option<(KC::DItem, DC::DItem)>, db_error::Last>
+    where
+        KC: BytesDecode<'txn>,
+        DC: BytesDecode<'txn>,
+    {
+        self.inner.last(rotxn).map_err(|err| db_error::Last {
+            db_name: self.name,
+            db_path: (*self.path).clone(),
+            source: err,
+        })
+    }
+
+    /// Iterate over the items with keys in the specified range, in key order
+    pub fn range<'a, 'txn, R>(
+        &self,
+        rotxn: &'txn RoTxn<'_>,
+        range: &'a R,
+    ) -> Result<
+        fallible_iterator::MapErr<
+            fallible_iterator::Convert<heed::RoRange<'txn, KC, DC>>,
+            impl FnMut(heed::Error) -> db_error::IterItem + '_,
+        >,
+        db_error::IterInit,
+    >
+    where
+        KC: BytesEncode<'a> + BytesDecode<'txn>,
+        DC: BytesDecode<'txn>,
+        R: RangeBounds<KC::EItem>,
+    {
+        match self.inner.range(rotxn, range) {
+            Ok(it) => Ok(it.transpose_into_fallible().map_err({
+                let db_path = self.path.clone();
+                move |err| db_error::IterItem {
+                    db_name: self.name,
+                    db_path: (*db_path).clone(),
+                    source: err,
+                }
+            })),
+            Err(err) => Err(db_error::IterInit {
+                db_name: self.name,
+                db_path: (*self.path).clone(),
+                source: err,
+            }),
+        }
+    }
+
+    /// View of the DB with raw keys and values
+    pub fn as_bytes(&self) -> RoDatabase<Bytes, Bytes> {
+        RoDatabase {
+            inner: self.inner.remap_types(),
+            name: self.name,
+            path: self.path.clone(),
+        }
+    }
+
     pub fn lazy_decode(&self) -> RoDatabase<KC, LazyDecode<DC>> {
         let inner = self.inner.lazily_decode_data();
         RoDatabase {
@@ -382,6 +485,36 @@ impl<KC, DC> RoDatabase<KC, DC> {
             }
         })
     }
+
+    /// Encoded key, and the raw value for the key if it exists
+    pub fn try_get_raw<'a, 'txn>(
+        &self,
+        rotxn: &'txn RoTxn<'_>,
+        key: &'a KC::EItem,
+    ) -> Result<(Vec<u8>, Option<&'txn [u8]>), db_error::TryGet>
+    where
+        KC: BytesEncode<'a>,
+    {
+        let value = self
+            .inner
+            .remap_data_type::<Bytes>()
+            .get(rotxn, key)
+            .map_err(|err| {
+                let key_bytes =
+                    <KC as BytesEncode>::bytes_encode(key).map(|key_bytes| key_bytes.to_vec());
+                db_error::TryGet {
+                    db_name: self.name,
+                    db_path: (*self.path).clone(),
+                    key_bytes,
+                    source: err,
+                }
+            })?;
+        let key_bytes = <KC as BytesEncode>::bytes_encode(key)
+            // Safety: key must encode successfully, as the lookup succeeded
+            .unwrap()
+            .to_vec();
+        Ok((key_bytes, value))
+    }
 }
 
 /// Wrapper for heed's `Database`
@@ -412,6 +545,13 @@ impl<KC, DC> Database<KC, DC> {
         })
     }
 
+    /// View of the DB with raw keys and values
+    pub fn as_bytes(&self) -> Database<Bytes, Bytes> {
+        Database {
+            inner: self.inner.as_bytes(),
+        }
+    }
+
     pub fn lazy_decode(&self) -> Database<KC, LazyDecode<DC>> {
         Database {
             inner: self.inner.lazy_decode(),
@@ -467,6 +607,28 @@ pub struct CreateDbError {
     source: heed::Error,
 }
 
+#[derive(Debug, Error)]
+#[error("Error copying database env at `{path}` to `{dst}`")]
+pub struct CopyEnvError {
+    path: PathBuf,
+    dst: PathBuf,
+    source: heed::Error,
+}
+
+#[derive(Debug, Error)]
+#[error("Error reading disk size for database env at `{path}`")]
+pub struct DiskSizeError {
+    path: PathBuf,
+    source: heed::Error,
+}
+
+#[derive(Debug, Error)]
+#[error("Error syncing database env at `{path}` to disk")]
+pub struct SyncEnvError {
+    path: PathBuf,
+    source: heed::Error,
+}
+
 #[derive(Debug, Error)]
 #[error("Error creating read txn for database dir `{db_dir}`")]
 pub struct ReadTxnError {
@@ -526,6 +688,52 @@ impl Env {
         })
     }
 
+    /// Copy the env to a new file at `dst`, omitting free pages and
+    /// renumbering all pages sequentially.
+    /// The copy is taken from a read txn, so it does not block writers.
+    /// The copy is flushed to disk before returning.
+    pub fn copy_compacted(&self, dst: &Path) -> Result<(), CopyEnvError> {
+        match self
+            .inner
+            .copy_to_file(dst, heed::CompactionOption::Enabled)
+            .and_then(|file| file.sync_all().map_err(heed::Error::Io))
+        {
+            Ok(()) => Ok(()),
+            Err(err) => Err(CopyEnvError {
+                path: (*self.path).clone(),
+                dst: dst.to_owned(),
+                source: err,
+            }),
+        }
+    }
+
+    /// Size of the data file on disk
+    pub fn disk_size(&self) -> Result<u64, DiskSizeError> {
+        self.inner.real_disk_size().map_err(|err| DiskSizeError {
+            path: (*self.path).clone(),
+            source: err,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flush the env's buffers to disk
+    pub fn force_sync(&self) -> Result<(), SyncEnvError> {
+        self.inner.force_sync().map_err(|err| SyncEnvError {
+            path: (*self.path).clone(),
+            source: err,
+        })
+    }
+
+    /// Close the env, once all other handles to it have been dropped.
+    /// Waits at most `timeout` for other handles to be dropped, and returns
+    /// `false` if the env is still open.
+    pub fn close(self, timeout: Duration) -> bool {
+        self.inner.prepare_for_closing().wait_timeout(timeout)
+    }
+
     pub fn read_txn(&self) -> Result<RoTxn<'_>, ReadTxnError> {
         self.inner.read_txn().map_err(|err| ReadTxnError {
             db_dir: (*self.path).clone(),
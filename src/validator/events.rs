@@ -0,0 +1,310 @@
+//! Typed event subscriptions, for consumers that embed the validator
+
+use std::pin::pin;
+
+use bitcoin::BlockHash;
+use futures::{
+    stream::{BoxStream, FusedStream},
+    Stream, StreamExt as _,
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tokio::{
+    sync::mpsc,
+    task::{spawn, JoinHandle},
+};
+use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
+
+use super::{dbs, EventsStreamError, Validator};
+use crate::types::{Event, TwoWayPegData};
+
+/// Default capacity for [`Backpressure::Bounded`]
+const DEFAULT_CAPACITY: usize = 256;
+
+/// How events are buffered for subscribers that consume events more slowly
+/// than they are produced
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backpressure {
+    /// Buffer up to the specified number of events. Subscribers that fall
+    /// further behind catch up from the event journal. Subscribers that fall
+    /// so far behind that their next event has been pruned from the journal
+    /// fail with [`EventsStreamError::EventsPruned`], see
+    /// [`dbs::Dbs::MAX_JOURNAL_EVENTS`].
+    Bounded(usize),
+    /// Only the most recent event is delivered. Events that are superseded
+    /// before the subscriber receives them are skipped.
+    LatestOnly,
+    /// Buffer all events in memory, without limit
+    Unbounded,
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Self::Bounded(DEFAULT_CAPACITY)
+    }
+}
+
+/// Where to start streaming events from
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum EventsStart {
+    /// Only stream new events
+    #[default]
+    New,
+    /// Replay events from the journal, starting at the specified sequence
+    /// number
+    Sequence(u64),
+    /// Replay events from the journal, starting after the most recent
+    /// `ConnectBlock` event for the specified block.
+    /// Useful for consumers that persist the last block they processed,
+    /// rather than a sequence number.
+    AfterBlock(BlockHash),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SubscribeEventsOptions {
+    pub start: EventsStart,
+    pub backpressure: Backpressure,
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum SubscribeEventsError {
+    #[error("Block info for `{0}` has been pruned")]
+    BlockInfoPruned(BlockHash),
+    #[error("Block `{0}` is not in the best chain")]
+    BlockNotInBestChain(BlockHash),
+    #[error("Block `{0}` is not in the event journal")]
+    BlockNotInJournal(BlockHash),
+    #[error(transparent)]
+    DbIter(#[from] dbs::db_error::Iter),
+    #[error(transparent)]
+    DbLast(#[from] dbs::db_error::Last),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    TryGetBlockInfo(#[from] dbs::block_hash_dbs_error::TryGetBlockInfo),
+}
+
+/// Stream of events, along with their sequence numbers in the event journal
+pub type EventStream = BoxStream<'static, Result<(u64, Event), EventsStreamError>>;
+
+/// Forward events into a bounded channel, until either the stream ends or
+/// the subscriber is dropped
+async fn forward_bounded<S>(stream: S, tx: mpsc::Sender<S::Item>)
+where
+    S: Stream,
+{
+    let mut stream = pin!(stream);
+    loop {
+        let item = tokio::select! {
+            item = stream.next() => item,
+            () = tx.closed() => break,
+        };
+        let Some(item) = item else { break };
+        if tx.send(item).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Forward events into an unbounded channel, until either the stream ends
+/// or the subscriber is dropped
+async fn forward_unbounded<S>(stream: S, tx: mpsc::UnboundedSender<S::Item>)
+where
+    S: Stream,
+{
+    let mut stream = pin!(stream);
+    loop {
+        let item = tokio::select! {
+            item = stream.next() => item,
+            () = tx.closed() => break,
+        };
+        let Some(item) = item else { break };
+        if tx.send(item).is_err() {
+            break;
+        }
+    }
+}
+
+impl Validator {
+    /// Subscribe to events, skipping to the most recent event whenever the
+    /// subscriber falls behind
+    fn subscribe_latest_event(
+        &self,
+        start_sequence: u64,
+    ) -> impl FusedStream<Item = Result<(u64, Event), EventsStreamError>> {
+        let receiver = self.events_rx.activate_cloned();
+        let dbs = self.dbs.clone();
+        futures::stream::try_unfold(
+            (receiver, start_sequence),
+            move |(mut receiver, next_sequence)| {
+                let dbs = dbs.clone();
+                async move {
+                    loop {
+                        let latest = {
+                            let rotxn = dbs.read_txn()?;
+                            dbs.events.last(&rotxn)?
+                        };
+                        if let Some((sequence, event)) = latest {
+                            if sequence >= next_sequence {
+                                let state = (receiver, sequence + 1);
+                                return Ok(Some(((sequence, event), state)));
+                            }
+                        }
+                        let mut latest = match receiver.recv_direct().await {
+                            Ok(item) => Some(item),
+                            Err(async_broadcast::RecvError::Overflowed(_)) => None,
+                            Err(async_broadcast::RecvError::Closed) => return Ok(None),
+                        };
+                        // Skip events that have already been superseded
+                        loop {
+                            match receiver.try_recv() {
+                                Ok(item) => latest = Some(item),
+                                Err(async_broadcast::TryRecvError::Overflowed(_)) => latest = None,
+                                Err(
+                                    async_broadcast::TryRecvError::Empty
+                                    | async_broadcast::TryRecvError::Closed,
+                                ) => break,
+                            }
+                        }
+                        if let Some((sequence, event)) = latest {
+                            if sequence >= next_sequence {
+                                let state = (receiver, sequence + 1);
+                                return Ok(Some(((sequence, event), state)));
+                            }
+                        }
+                    }
+                }
+            },
+        )
+        .fuse()
+    }
+
+    /// Subscribe to events, with the specified start point and
+    /// backpressure.
+    /// Unlike the gRPC `SubscribeEvents` stream, events are not filtered by
+    /// sidechain.
+    /// Must be called from within a tokio runtime, since buffered
+    /// subscriptions spawn a task to fill the buffer.
+    pub fn subscribe_events_with_options(
+        &self,
+        options: SubscribeEventsOptions,
+    ) -> Result<EventStream, SubscribeEventsError> {
+        let SubscribeEventsOptions {
+            start,
+            backpressure,
+        } = options;
+        let start_sequence = {
+            let rotxn = self.dbs.read_txn()?;
+            match start {
+                EventsStart::New => self.dbs.next_event_sequence(&rotxn)?,
+                EventsStart::Sequence(sequence) => sequence,
+                EventsStart::AfterBlock(block_hash) => {
+                    self.dbs
+                        .find_connect_block_event(&rotxn, &block_hash)?
+                        .ok_or(SubscribeEventsError::BlockNotInJournal(block_hash))?
+                        + 1
+                }
+            }
+        };
+        let stream = match backpressure {
+            Backpressure::Bounded(capacity) => {
+                let (tx, rx) = mpsc::channel(capacity.max(1));
+                let events = self.subscribe_events(Some(start_sequence));
+                let _forward_task: JoinHandle<()> = spawn(forward_bounded(events, tx));
+                ReceiverStream::new(rx).boxed()
+            }
+            Backpressure::LatestOnly => self.subscribe_latest_event(start_sequence).boxed(),
+            Backpressure::Unbounded => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                let events = self.subscribe_events(Some(start_sequence));
+                let _forward_task: JoinHandle<()> = spawn(forward_unbounded(events, tx));
+                UnboundedReceiverStream::new(rx).boxed()
+            }
+        };
+        Ok(stream)
+    }
+
+    /// Replay `ConnectBlock` events for the blocks in the best chain after
+    /// `start_block`, and then stream new events.
+    /// Replayed events are derived from stored header and block info,
+    /// rather than the event journal, and do not have sequence numbers.
+    /// `start_block` must be in the best chain, and block info must not
+    /// have been pruned for the replayed blocks.
+    /// Blocks that are disconnected during the replay are followed by
+    /// `DisconnectBlock` events, as with new events.
+    pub fn subscribe_events_after_block(
+        &self,
+        start_block: BlockHash,
+    ) -> Result<
+        impl FusedStream<Item = Result<(Option<u64>, Event), EventsStreamError>>,
+        SubscribeEventsError,
+    > {
+        let (next_sequence, replay_blocks) = {
+            let rotxn = self.dbs.read_txn()?;
+            let next_sequence = self.dbs.next_event_sequence(&rotxn)?;
+            let start_height = self
+                .dbs
+                .block_hashes
+                .height()
+                .try_get(&rotxn, &start_block)?
+                .ok_or(SubscribeEventsError::BlockNotInBestChain(start_block))?;
+            if self
+                .dbs
+                .best_chain_height_to_block_hash
+                .try_get(&rotxn, &start_height)?
+                != Some(start_block)
+            {
+                return Err(SubscribeEventsError::BlockNotInBestChain(start_block));
+            }
+            // Collect the blocks to replay up front, so that the replay is
+            // consistent with the journal at `next_sequence`
+            let mut replay_blocks = Vec::new();
+            for height in (start_height + 1).. {
+                let Some(block_hash) = self
+                    .dbs
+                    .best_chain_height_to_block_hash
+                    .try_get(&rotxn, &height)?
+                else {
+                    break;
+                };
+                replay_blocks.push(block_hash);
+            }
+            // Block info is pruned from the oldest blocks first
+            if let Some(first_block) = replay_blocks.first() {
+                if self
+                    .dbs
+                    .block_hashes
+                    .try_get_block_info(&rotxn, first_block)?
+                    .is_none()
+                {
+                    return Err(SubscribeEventsError::BlockInfoPruned(*first_block));
+                }
+            }
+            (next_sequence, replay_blocks)
+        };
+        let dbs = self.dbs.clone();
+        let replay = futures::stream::iter(replay_blocks).map(move |block_hash| {
+            let rotxn = dbs.read_txn()?;
+            let Some(TwoWayPegData {
+                header_info,
+                block_info,
+            }) = dbs
+                .block_hashes
+                .try_get_two_way_peg_data(&rotxn, &block_hash)?
+            else {
+                return Err(EventsStreamError::BlockInfoPruned(block_hash));
+            };
+            let event = Event::ConnectBlock {
+                header_info,
+                block_info,
+            };
+            Ok((None, event))
+        });
+        let new_events = self
+            .subscribe_events(Some(next_sequence))
+            .map(|res| res.map(|(sequence, event)| (Some(sequence), event)));
+        Ok(replay.chain(new_events).fuse())
+    }
+}
@@ -0,0 +1,435 @@
+//! Maintenance operations on the validator DBs.
+//! These operate on the DBs directly, and must not be run while a
+//! [`super::Validator`] is using the same data directory.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use bitcoin::{BlockHash, Txid};
+use fallible_iterator::FallibleIterator as _;
+use serde::Serialize;
+use serde_json::json;
+use thiserror::Error;
+
+use super::dbs::{self, db_error, CheckError, CreateDbsError, Dbs, UnitKey};
+pub use super::dbs::{DeleteDbsError, ImportSnapshotError};
+use crate::types::{Bip300TransactionKind, SidechainNumber, WithdrawalBundleEventKind};
+
+#[derive(Debug, Error)]
+pub enum OpenDbsError {
+    #[error(transparent)]
+    CreateDbs(#[from] CreateDbsError),
+    #[error("No validator DBs for network `{network}` in `{data_dir}`")]
+    NotFound {
+        data_dir: PathBuf,
+        network: bitcoin::Network,
+    },
+}
+
+/// Open existing DBs, without creating them if they do not exist
+fn open_dbs(data_dir: &Path, network: bitcoin::Network) -> Result<Dbs, OpenDbsError> {
+    if !Dbs::exist(data_dir, network) {
+        return Err(OpenDbsError::NotFound {
+            data_dir: data_dir.to_owned(),
+            network,
+        });
+    }
+    Ok(Dbs::new(data_dir, network)?)
+}
+
+/// Delete the validator DBs, so that they are rebuilt from the node on next
+/// startup. Returns `true` if the DBs existed.
+pub fn reindex(data_dir: &Path, network: bitcoin::Network) -> Result<bool, DeleteDbsError> {
+    Dbs::delete(data_dir, network)
+}
+
+#[derive(Debug, Error)]
+pub enum ExportSnapshotError {
+    #[error(transparent)]
+    CopyEnv(#[from] dbs::CopyEnvError),
+    #[error(transparent)]
+    OpenDbs(#[from] OpenDbsError),
+}
+
+/// Write a compacted snapshot of the validator DBs to a new file at `path`
+pub fn export_snapshot(
+    data_dir: &Path,
+    network: bitcoin::Network,
+    path: &Path,
+) -> Result<(), ExportSnapshotError> {
+    let dbs = open_dbs(data_dir, network)?;
+    let () = dbs.export_snapshot(path)?;
+    Ok(())
+}
+
+/// Restore the validator DBs from a snapshot file.
+/// Fails if the validator DBs already exist.
+pub fn import_snapshot(
+    data_dir: &Path,
+    network: bitcoin::Network,
+    path: &Path,
+) -> Result<(), ImportSnapshotError> {
+    let _dbs: Dbs = Dbs::import_snapshot(data_dir, network, path)?;
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum CheckDbsError {
+    #[error(transparent)]
+    Check(#[from] CheckError),
+    #[error(transparent)]
+    OpenDbs(#[from] OpenDbsError),
+}
+
+/// Check consistency of the validator DBs.
+/// Returns a description of each inconsistency found.
+pub fn check_dbs(data_dir: &Path, network: bitcoin::Network) -> Result<Vec<String>, CheckDbsError> {
+    let dbs = open_dbs(data_dir, network)?;
+    Ok(dbs.check()?)
+}
+
+#[derive(Debug, Error)]
+pub enum DumpError {
+    #[error(transparent)]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    DbTryGet(#[from] db_error::TryGet),
+    #[error(transparent)]
+    OpenDbs(#[from] OpenDbsError),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    TryGetBlockInfo(#[from] dbs::block_hash_dbs_error::TryGetBlockInfo),
+}
+
+/// Dump validator state as JSON, for debugging and for diffing state
+/// between enforcer instances.
+/// Includes all sidechain proposals, active sidechains with their CTIPs and
+/// pending M6IDs, and block info for up to `recent_blocks` blocks in the
+/// best chain, most recent first.
+pub fn dump(
+    data_dir: &Path,
+    network: bitcoin::Network,
+    recent_blocks: u32,
+) -> Result<serde_json::Value, DumpError> {
+    let dbs = open_dbs(data_dir, network)?;
+    let rotxn = dbs.read_txn()?;
+    let sidechain_proposals: Vec<_> = dbs
+        .description_hash_to_sidechain
+        .iter(&rotxn)
+        .map_err(db_error::Iter::from)?
+        .map(|(description_hash, sidechain)| {
+            Ok(json!({
+                "description_hash": description_hash.to_string(),
+                "sidechain_number": sidechain.proposal.sidechain_number.0,
+                "description": hex::encode(&sidechain.proposal.description.0),
+                "vote_count": sidechain.status.vote_count,
+                "proposal_height": sidechain.status.proposal_height,
+                "activation_height": sidechain.status.activation_height,
+            }))
+        })
+        .map_err(db_error::Iter::from)
+        .collect()?;
+    let active_sidechain_numbers: Vec<_> = dbs
+        .active_sidechains
+        .sidechain
+        .iter(&rotxn)
+        .map_err(db_error::Iter::from)?
+        .map(|(sidechain_number, _)| Ok(sidechain_number))
+        .map_err(db_error::Iter::from)
+        .collect()?;
+    let mut active_sidechains = Vec::new();
+    for sidechain_number in active_sidechain_numbers {
+        let ctip = dbs
+            .active_sidechains
+            .ctip
+            .try_get(&rotxn, &sidechain_number)?
+            .map(|ctip| {
+                json!({
+                    "outpoint": ctip.outpoint.to_string(),
+                    "value_sats": ctip.value.to_sat(),
+                })
+            });
+        let pending_m6ids: Vec<_> = dbs
+            .active_sidechains
+            .pending_m6ids
+            .try_get(&rotxn, &sidechain_number)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|pending_m6id| {
+                json!({
+                    "m6id": hex::encode(pending_m6id.m6id),
+                    "vote_count": pending_m6id.vote_count,
+                    "proposal_height": pending_m6id.proposal_height,
+                })
+            })
+            .collect();
+        let treasury_utxo_count = dbs
+            .active_sidechains
+            .treasury_utxo_count
+            .try_get(&rotxn, &sidechain_number)?
+            .unwrap_or(0);
+        active_sidechains.push(json!({
+            "sidechain_number": sidechain_number.0,
+            "ctip": ctip,
+            "pending_m6ids": pending_m6ids,
+            "treasury_utxo_count": treasury_utxo_count,
+        }));
+    }
+    let chain_tip = dbs.current_chain_tip.try_get(&rotxn, &UnitKey)?;
+    let chain_tip_height = match chain_tip {
+        Some(chain_tip) => dbs.block_hashes.height().try_get(&rotxn, &chain_tip)?,
+        None => None,
+    };
+    let mut blocks = Vec::new();
+    if let Some(tip_height) = chain_tip_height.filter(|_| recent_blocks > 0) {
+        let min_height = tip_height.saturating_sub(recent_blocks - 1);
+        for height in (min_height..=tip_height).rev() {
+            let Some(block_hash) = dbs
+                .best_chain_height_to_block_hash
+                .try_get(&rotxn, &height)?
+            else {
+                break;
+            };
+            // Block info may have been pruned
+            let Some(block_info) = dbs.block_hashes.try_get_block_info(&rotxn, &block_hash)? else {
+                break;
+            };
+            let bmm_commitments: serde_json::Map<String, serde_json::Value> = block_info
+                .bmm_commitments
+                .iter()
+                .map(|(sidechain_number, commitment)| {
+                    let commitment = serde_json::Value::from(hex::encode(commitment));
+                    (sidechain_number.to_string(), commitment)
+                })
+                .collect();
+            let deposits: Vec<_> = block_info
+                .deposits
+                .iter()
+                .map(|deposit| {
+                    json!({
+                        "sidechain_number": deposit.sidechain_id.0,
+                        "sequence_number": deposit.sequence_number,
+                        "outpoint": deposit.outpoint.to_string(),
+                        "address": hex::encode(&deposit.address),
+                        "value_sats": deposit.value.to_sat(),
+                    })
+                })
+                .collect();
+            let sidechain_proposals: Vec<_> = block_info
+                .sidechain_proposals
+                .iter()
+                .map(|(vout, proposal)| {
+                    json!({
+                        "vout": vout,
+                        "sidechain_number": proposal.sidechain_number.0,
+                        "description": hex::encode(&proposal.description.0),
+                    })
+                })
+                .collect();
+            let withdrawal_bundle_events: Vec<_> = block_info
+                .withdrawal_bundle_events
+                .iter()
+                .map(|event| {
+                    json!({
+                        "sidechain_number": event.sidechain_id.0,
+                        "m6id": hex::encode(event.m6id),
+                        "kind": format!("{:?}", event.kind),
+                    })
+                })
+                .collect();
+            blocks.push(json!({
+                "block_hash": block_hash.to_string(),
+                "height": height,
+                "coinbase_txid": block_info.coinbase_txid.to_string(),
+                "bmm_commitments": bmm_commitments,
+                "deposits": deposits,
+                "sidechain_proposals": sidechain_proposals,
+                "withdrawal_bundle_events": withdrawal_bundle_events,
+            }));
+        }
+    }
+    Ok(json!({
+        "network": network.to_string(),
+        "chain_tip": chain_tip.map(|chain_tip| chain_tip.to_string()),
+        "chain_tip_height": chain_tip_height,
+        "sidechain_proposals": sidechain_proposals,
+        "active_sidechains": active_sidechains,
+        "recent_blocks": blocks,
+    }))
+}
+
+#[derive(Debug, Error)]
+pub enum ExportActivityError {
+    #[error("Block info for height {height} (`{block_hash}`) has been pruned")]
+    BlockInfoPruned { height: u32, block_hash: BlockHash },
+    #[error(transparent)]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    DbTryGet(#[from] db_error::TryGet),
+    #[error("Start height {start_height} is above end height {end_height}")]
+    InvalidRange { start_height: u32, end_height: u32 },
+    #[error(transparent)]
+    OpenDbs(#[from] OpenDbsError),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    TryGetBlockInfo(#[from] dbs::block_hash_dbs_error::TryGetBlockInfo),
+}
+
+/// Activity for a sidechain slot in a single best chain block, as exported
+/// by [`export_activity`]
+#[derive(Clone, Debug, Serialize)]
+pub struct SidechainActivity {
+    pub height: u32,
+    pub block_hash: BlockHash,
+    pub sidechain_number: u8,
+    pub deposit_count: u64,
+    pub deposit_sats: u64,
+    /// Number of withdrawal bundles paid out by M6s in the block
+    pub withdrawal_count: u64,
+    /// Value paid out of the treasury by M6s in the block, including fees
+    pub withdrawal_sats: u64,
+    /// `true` if the block includes a BMM commitment for the sidechain
+    pub bmm: bool,
+}
+
+impl SidechainActivity {
+    pub const CSV_HEADER: &'static str = "height,block_hash,sidechain_number,deposit_count,\
+        deposit_sats,withdrawal_count,withdrawal_sats,bmm";
+
+    /// Format as a CSV row, with columns as in [`Self::CSV_HEADER`]
+    pub fn to_csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.height,
+            self.block_hash,
+            self.sidechain_number,
+            self.deposit_count,
+            self.deposit_sats,
+            self.withdrawal_count,
+            self.withdrawal_sats,
+            self.bmm
+        )
+    }
+
+    fn new(height: u32, block_hash: BlockHash, sidechain_number: SidechainNumber) -> Self {
+        Self {
+            height,
+            block_hash,
+            sidechain_number: sidechain_number.0,
+            deposit_count: 0,
+            deposit_sats: 0,
+            withdrawal_count: 0,
+            withdrawal_sats: 0,
+            bmm: false,
+        }
+    }
+}
+
+/// Export per-block sidechain activity for best chain blocks between
+/// `start_height` and `end_height` (inclusive), in height order.
+/// `end_height` defaults to the current tip. Blocks are included once for
+/// each sidechain slot with deposits, withdrawals or a BMM commitment in
+/// the block, so blocks without any sidechain activity are omitted.
+/// Fails if block info for any block in the range has been pruned.
+pub fn export_activity(
+    data_dir: &Path,
+    network: bitcoin::Network,
+    start_height: u32,
+    end_height: Option<u32>,
+) -> Result<Vec<SidechainActivity>, ExportActivityError> {
+    let dbs = open_dbs(data_dir, network)?;
+    let rotxn = dbs.read_txn()?;
+    let Some(tip) = dbs.current_chain_tip.try_get(&rotxn, &UnitKey)? else {
+        return Ok(Vec::new());
+    };
+    let Some(tip_height) = dbs.block_hashes.height().try_get(&rotxn, &tip)? else {
+        return Ok(Vec::new());
+    };
+    let end_height = end_height.map_or(tip_height, |end_height| end_height.min(tip_height));
+    if start_height > end_height {
+        return Err(ExportActivityError::InvalidRange {
+            start_height,
+            end_height,
+        });
+    }
+    // Block info does not include withdrawal values, so these are derived
+    // from the treasury UTXOs created by M6s
+    let withdrawals: Vec<(Txid, u64)> = dbs
+        .active_sidechains
+        .slot_sequence_to_treasury_utxo
+        .iter(&rotxn)
+        .map_err(db_error::Iter::from)?
+        .filter_map(|(_, treasury_utxo)| {
+            let withdrawal = treasury_utxo
+                .previous_total_value
+                .checked_sub(treasury_utxo.total_value)
+                .map(|value| (treasury_utxo.outpoint.txid, value.to_sat()));
+            Ok(withdrawal)
+        })
+        .map_err(db_error::Iter::from)
+        .collect()?;
+    let mut block_withdrawals = HashMap::<BlockHash, Vec<(SidechainNumber, u64)>>::new();
+    for (txid, withdrawal_sats) in withdrawals {
+        let Some(bip300_transaction) = dbs.txid_to_bip300_transaction.try_get(&rotxn, &txid)?
+        else {
+            continue;
+        };
+        if let Bip300TransactionKind::M6Withdrawal { sidechain_number } = bip300_transaction.kind {
+            block_withdrawals
+                .entry(bip300_transaction.block_hash)
+                .or_default()
+                .push((sidechain_number, withdrawal_sats));
+        }
+    }
+    let mut res = Vec::new();
+    for height in start_height..=end_height {
+        let Some(block_hash) = dbs
+            .best_chain_height_to_block_hash
+            .try_get(&rotxn, &height)?
+        else {
+            break;
+        };
+        let Some(block_info) = dbs.block_hashes.try_get_block_info(&rotxn, &block_hash)? else {
+            return Err(ExportActivityError::BlockInfoPruned { height, block_hash });
+        };
+        let mut activity = BTreeMap::<SidechainNumber, SidechainActivity>::new();
+        let new_activity = |sidechain_number: &SidechainNumber| {
+            SidechainActivity::new(height, block_hash, *sidechain_number)
+        };
+        for deposit in &block_info.deposits {
+            let entry = activity
+                .entry(deposit.sidechain_id)
+                .or_insert_with_key(new_activity);
+            entry.deposit_count += 1;
+            entry.deposit_sats += deposit.value.to_sat();
+        }
+        for event in &block_info.withdrawal_bundle_events {
+            if matches!(event.kind, WithdrawalBundleEventKind::Succeeded) {
+                let entry = activity
+                    .entry(event.sidechain_id)
+                    .or_insert_with_key(new_activity);
+                entry.withdrawal_count += 1;
+            }
+        }
+        for (sidechain_number, withdrawal_sats) in
+            block_withdrawals.remove(&block_hash).unwrap_or_default()
+        {
+            let entry = activity
+                .entry(sidechain_number)
+                .or_insert_with_key(new_activity);
+            entry.withdrawal_sats += withdrawal_sats;
+        }
+        for sidechain_number in block_info.bmm_commitments.keys() {
+            let entry = activity
+                .entry(*sidechain_number)
+                .or_insert_with_key(new_activity);
+            entry.bmm = true;
+        }
+        res.extend(activity.into_values());
+    }
+    Ok(res)
+}
@@ -1,22 +1,69 @@
-use std::{future::Future, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    future::Future,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_broadcast::{broadcast, InactiveReceiver};
-use bip300301::{jsonrpsee, MainClient};
-use bitcoin::{self, hashes::sha256d, BlockHash};
+use bip300301::{
+    jsonrpsee::{
+        self,
+        core::{client::ClientT as _, rpc_params},
+    },
+    MainClient,
+};
+use bitcoin::{
+    self,
+    hashes::{sha256d, Hash as _},
+    Amount, BlockHash, Txid,
+};
 use fallible_iterator::FallibleIterator;
-use futures::{stream::FusedStream, FutureExt as _, StreamExt, TryFutureExt as _};
+use futures::{stream::FusedStream, StreamExt, TryFutureExt as _, TryStreamExt as _};
+use heed::RoTxn;
 use miette::{Diagnostic, IntoDiagnostic};
+use parking_lot::Mutex;
 use thiserror::Error;
-use tokio::task::{spawn, JoinHandle};
+use tokio::{
+    sync::watch,
+    task::{spawn, JoinHandle},
+};
 
-use crate::types::{
-    BlockInfo, BmmCommitments, Ctip, Event, HeaderInfo, Sidechain, SidechainNumber, TwoWayPegData,
+use crate::{
+    messages::M8BmmRequest,
+    rpc_client::FeeInfo,
+    types::{
+        Bip300Transaction, BlockInfo, BmmCommitments, Ctip, Event, Hash256, HeaderInfo,
+        PendingM6id, Sidechain, SidechainNumber, SidechainStats, TreasuryUtxo, TwoWayPegData,
+        WithdrawalBundleStatus,
+    },
 };
 
-mod dbs;
+pub mod dbs;
+mod events;
+pub mod maintenance;
+mod sync_progress;
 mod task;
 
-use dbs::{CreateDbsError, Dbs};
+use dbs::{CreateDbsError, Dbs, ScheduleCompactionError, ScheduledCompaction, SyncEnvError};
+pub use events::{
+    Backpressure, EventStream, EventsStart, SubscribeEventsError, SubscribeEventsOptions,
+};
+use sync_progress::SyncProgress;
+pub use sync_progress::{
+    EnforcerStatus, SyncInfo, TaskError, TaskErrorKind, TaskState, MAX_RECENT_ERRORS,
+};
+pub use task::{
+    fixtures,
+    policy::{CheckTransactionError, CheckedTransaction, TransactionRejection},
+    DepositConfirmationDepth, DepositConfirmationDepths, SidechainProposalRules,
+    MAX_PENDING_WITHDRAWAL_BUNDLES, WITHDRAWAL_BUNDLE_MAX_AGE,
+};
+
+/// Max age of cached fee info returned by [`Validator::get_fee_info`]
+pub const FEE_INFO_CACHE_TTL: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Error)]
 pub enum InitError {
@@ -37,6 +84,185 @@ pub enum GetBlockInfoError {
     GetBlockInfo(#[from] dbs::block_hash_dbs_error::GetBlockInfo),
 }
 
+/// Common ancestor of two blocks, as reported by
+/// [`Validator::get_common_ancestor`]
+#[derive(Clone, Copy, Debug)]
+pub struct CommonAncestor {
+    pub header_info: HeaderInfo,
+    /// Number of blocks from the common ancestor to the first block
+    pub depth_a: u32,
+    /// Number of blocks from the common ancestor to the second block
+    pub depth_b: u32,
+}
+
+/// Indexed BIP300/301 transaction, as returned by
+/// [`Validator::get_raw_bip300_transaction`]
+#[derive(Clone, Debug)]
+pub struct RawBip300Transaction {
+    pub transaction: bitcoin::Transaction,
+    pub bip300_transaction: Bip300Transaction,
+    pub block_height: u32,
+    /// Number of blocks in the enforcer's chain, from the block that
+    /// includes the transaction up to and including the tip
+    pub confirmations: u32,
+}
+
+/// Inclusion status of an M8 BMM request tx, as reported by
+/// [`Validator::get_bmm_inclusion_status`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BmmInclusionStatus {
+    /// In the node's mempool, or in a block that has not been synced yet,
+    /// and the previous mainchain block that it commits to is the tip
+    InMempool,
+    /// Included in a block whose coinbase accepted the request with a
+    /// matching M7
+    Included { block_hash: BlockHash },
+    /// Included in a block without a matching M7
+    NotAccepted { block_hash: BlockHash },
+    /// Not included, and the previous mainchain block that it commits to is
+    /// no longer the tip, so it can no longer be included
+    Expired,
+    /// No longer in the mempool, and an input was spent by a conflicting tx
+    Replaced,
+    /// No longer in the mempool, and all inputs are unspent, eg. because it
+    /// was evicted
+    Dropped,
+}
+
+/// Inclusion status of a BMM request tx that is not in a synced block.
+/// `known_to_node` is `true` if the node has the tx in its mempool, or in a
+/// block that has not been synced yet. `is_spent` checks whether an outpoint
+/// is spent, and is only called for txs that are no longer known to the
+/// node.
+async fn unsynced_bmm_inclusion_status<F, Fut, E>(
+    transaction: &bitcoin::Transaction,
+    bmm_request: &M8BmmRequest,
+    tip: Option<BlockHash>,
+    known_to_node: bool,
+    mut is_spent: F,
+) -> Result<BmmInclusionStatus, E>
+where
+    F: FnMut(bitcoin::OutPoint) -> Fut,
+    Fut: Future<Output = Result<bool, E>>,
+{
+    let prev_mainchain_block_hash =
+        BlockHash::from_byte_array(bmm_request.prev_mainchain_block_hash);
+    if tip != Some(prev_mainchain_block_hash) {
+        return Ok(BmmInclusionStatus::Expired);
+    }
+    if known_to_node {
+        return Ok(BmmInclusionStatus::InMempool);
+    }
+    for input in &transaction.input {
+        if is_spent(input.previous_output).await? {
+            return Ok(BmmInclusionStatus::Replaced);
+        }
+    }
+    Ok(BmmInclusionStatus::Dropped)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BmmInclusionInfo {
+    pub bmm_request: M8BmmRequest,
+    pub status: BmmInclusionStatus,
+}
+
+/// CTIP for a sidechain, along with its sequence number in the sidechain's
+/// treasury UTXO history
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CtipInfo {
+    pub ctip: Ctip,
+    pub sequence_number: u64,
+}
+
+/// Statistics for an active sidechain, as reported by
+/// [`Validator::get_sidechain_stats`]
+#[derive(Clone, Copy, Debug)]
+pub struct SidechainStatsInfo {
+    pub stats: SidechainStats,
+    /// Value of the CTIP, or zero if there is no CTIP
+    pub treasury_balance: Amount,
+    pub activation_height: Option<u32>,
+}
+
+/// Block to wait for with [`Validator::wait_for_block`]
+#[derive(Clone, Copy, Debug)]
+pub enum WaitForBlockTarget {
+    /// Any block at or above the height in the best chain
+    Height(u32),
+    /// A specific block in the best chain
+    BlockHash(BlockHash),
+}
+
+/// Header in a chain streamed by [`Validator::stream_header_chain`]
+#[derive(Clone, Copy, Debug)]
+pub struct ChainHeader {
+    pub header: bitcoin::block::Header,
+    pub height: u32,
+    /// Total work of the chain up to and including this header
+    pub cumulative_work: bitcoin::Work,
+}
+
+/// Progress of a pending sidechain proposal towards activation, as reported
+/// by [`Validator::get_sidechain_proposal_status`]
+#[derive(Clone, Copy, Debug)]
+pub struct SidechainProposalVotingStatus {
+    pub sidechain_number: SidechainNumber,
+    pub vote_count: u16,
+    pub proposal_height: u32,
+    /// Number of blocks since the proposal was made, at the current tip
+    pub age: u32,
+    /// `true` if the proposal would replace an active sidechain
+    pub sidechain_slot_is_used: bool,
+    pub rules: SidechainProposalRules,
+    /// Number of blocks remaining in which acks for the proposal are counted
+    pub blocks_remaining: u32,
+    /// Number of additional acks required for activation
+    pub votes_required: u32,
+    /// `false` if the proposal cannot be activated, even if it is acked in
+    /// every remaining block. Assumes that each block acks a proposal at
+    /// most once.
+    pub activation_possible: bool,
+}
+
+impl SidechainProposalVotingStatus {
+    /// Voting status of a pending proposal, at a tip with the specified
+    /// height
+    pub fn new(sidechain: &Sidechain, sidechain_slot_is_used: bool, tip_height: u32) -> Self {
+        let rules = SidechainProposalRules::new(sidechain_slot_is_used);
+        let vote_count = sidechain.status.vote_count;
+        let age = tip_height.saturating_sub(sidechain.status.proposal_height);
+        // Acks are counted in blocks in which the proposal's age does not
+        // exceed the max age
+        let blocks_remaining = (rules.max_age as u32).saturating_sub(age);
+        let votes_required =
+            (rules.activation_threshold as u32 + 1).saturating_sub(vote_count as u32);
+        Self {
+            sidechain_number: sidechain.proposal.sidechain_number,
+            vote_count,
+            proposal_height: sidechain.status.proposal_height,
+            age,
+            sidechain_slot_is_used,
+            rules,
+            blocks_remaining,
+            votes_required,
+            activation_possible: votes_required <= blocks_remaining,
+        }
+    }
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum GetBestChainBlockInfosError {
+    #[error("Block info for `{0}` has been pruned")]
+    BlockInfoPruned(BlockHash),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    TryGetTwoWayPegData(#[from] dbs::block_hash_dbs_error::TryGetTwoWayPegData),
+}
+
 #[derive(Debug, Diagnostic, Error)]
 pub enum GetHeaderInfoError {
     #[error(transparent)]
@@ -45,14 +271,136 @@ pub enum GetHeaderInfoError {
     GetHeaderInfo(#[from] dbs::block_hash_dbs_error::GetHeaderInfo),
 }
 
+#[derive(Debug, Diagnostic, Error)]
+pub enum GetAncestorHeadersError {
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error(transparent)]
+    GetHeaderInfo(#[from] dbs::block_hash_dbs_error::GetHeaderInfo),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+}
+
 #[derive(Debug, Error)]
 pub enum GetTwoWayPegDataRangeError {
+    #[error(transparent)]
+    DbGet(#[from] dbs::db_error::Get),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
     #[error(transparent)]
     ReadTxn(#[from] dbs::ReadTxnError),
     #[error(transparent)]
     GetTwoWayPegDataRange(#[from] dbs::block_hash_dbs_error::GetTwoWayPegDataRange),
 }
 
+#[derive(Debug, Diagnostic, Error)]
+pub enum BlockHashRangeError {
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error("End block `{0}` not found")]
+    EndBlockNotFound(BlockHash),
+    #[error("Header for `{0}` not found")]
+    MissingHeader(BlockHash),
+    #[error("Start block `{start_block}` is not an ancestor of end block `{end_block}`")]
+    StartBlockNotAncestor {
+        start_block: BlockHash,
+        end_block: BlockHash,
+    },
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum StreamTwoWayPegDataError {
+    #[error("Block info for `{0}` not found")]
+    BlockInfoNotFound(BlockHash),
+    #[error(transparent)]
+    BlockHashRange(#[from] BlockHashRangeError),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    TryGetTwoWayPegData(#[from] dbs::block_hash_dbs_error::TryGetTwoWayPegData),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum StreamHeaderChainError {
+    #[error(transparent)]
+    BlockHashRange(#[from] BlockHashRangeError),
+    #[error("Cumulative work for start block `{0}` not found")]
+    CumulativeWorkNotFound(BlockHash),
+    #[error(transparent)]
+    DbGet(#[from] dbs::db_error::Get),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum BroadcastTransactionError {
+    #[error(transparent)]
+    CheckTransaction(#[from] CheckTransactionError),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error("Node rejected transaction")]
+    SendRawTransaction(#[source] jsonrpsee::core::ClientError),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum GetBmmInclusionStatusError {
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error("Failed to decode transaction `{txid}`")]
+    DecodeTransaction {
+        txid: Txid,
+        source: bitcoin::consensus::encode::FromHexError,
+    },
+    #[error("Failed to fetch transaction `{txid}` from the node")]
+    GetRawTransaction {
+        txid: Txid,
+        source: jsonrpsee::core::ClientError,
+    },
+    #[error("Failed to check whether output `{outpoint}` is spent")]
+    GetTxOut {
+        outpoint: bitcoin::OutPoint,
+        source: jsonrpsee::core::ClientError,
+    },
+    #[error("Transaction `{0}` is not a BMM request")]
+    NotBmmRequest(Txid),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum GetRawBip300TransactionError {
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error("Failed to decode transaction `{txid}`")]
+    DecodeTransaction {
+        txid: Txid,
+        source: bitcoin::consensus::encode::FromHexError,
+    },
+    #[error(transparent)]
+    GetHeaderInfo(#[from] dbs::block_hash_dbs_error::GetHeaderInfo),
+    #[error("Failed to fetch transaction `{txid}` from the node")]
+    GetRawTransaction {
+        txid: Txid,
+        source: jsonrpsee::core::ClientError,
+    },
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum ListBlocksWithBmmCommitmentsError {
+    #[error("BMM commitments for `{0}` have been pruned")]
+    BmmCommitmentsPruned(BlockHash),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error(transparent)]
+    GetHeaderInfo(#[from] dbs::block_hash_dbs_error::GetHeaderInfo),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+}
+
 #[derive(Debug, Error)]
 pub enum TryGetBmmCommitmentsError {
     #[error(transparent)]
@@ -61,25 +409,106 @@ pub enum TryGetBmmCommitmentsError {
     DbTryGet(#[from] dbs::db_error::TryGet),
 }
 
+#[derive(Debug, Error)]
+pub enum TryGetBestChainBlockHashError {
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum WaitForBlockError {
+    #[error(transparent)]
+    DbLast(#[from] dbs::db_error::Last),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error(transparent)]
+    EventsStream(#[from] EventsStreamError),
+    #[error("Event stream closed")]
+    EventsClosed,
+    #[error(transparent)]
+    GetHeaderInfo(#[from] dbs::block_hash_dbs_error::GetHeaderInfo),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    TryGetHeaderInfo(#[from] dbs::block_hash_dbs_error::TryGetHeaderInfo),
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum SubscribeCtipError {
+    #[error(transparent)]
+    DbLast(#[from] dbs::db_error::Last),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+    #[error(transparent)]
+    EventsStream(#[from] EventsStreamError),
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+}
+
+#[derive(Debug, Error)]
+pub enum TryGetBip300TransactionError {
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    DbTryGet(#[from] dbs::db_error::TryGet),
+}
+
 #[derive(Debug, Diagnostic, Error)]
 pub enum EventsStreamError {
-    #[error("Events stream closed due to overflow")]
-    Overflow,
+    #[error("Block info for `{0}` has been pruned")]
+    BlockInfoPruned(BlockHash),
+    #[error(transparent)]
+    DbIter(#[from] dbs::db_error::Iter),
+    #[error(transparent)]
+    DbLast(#[from] dbs::db_error::Last),
+    #[error(
+        "Event {sequence} has been removed from the event journal, the oldest retained event is {oldest}"
+    )]
+    EventsPruned { sequence: u64, oldest: u64 },
+    #[error(transparent)]
+    ReadTxn(#[from] dbs::ReadTxnError),
+    #[error(transparent)]
+    TryGetTwoWayPegData(#[from] dbs::block_hash_dbs_error::TryGetTwoWayPegData),
+}
+
+/// Handle to the sync task. The task is aborted when the last handle is
+/// dropped.
+struct TaskHandle {
+    join_handle: Mutex<Option<JoinHandle<()>>>,
+    shutdown_tx: watch::Sender<bool>,
+}
+
+impl Drop for TaskHandle {
+    fn drop(&mut self) {
+        if let Some(join_handle) = self.join_handle.get_mut().take() {
+            join_handle.abort()
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Validator {
     dbs: Dbs,
+    /// Most recent fee info from the node, and the time at which it was
+    /// fetched
+    fee_info_cache: Arc<Mutex<Option<(Instant, FeeInfo)>>>,
+    mainchain_client: crate::rpc_client::Client,
     network: bitcoin::Network,
-    events_rx: InactiveReceiver<Event>,
-    task: Arc<JoinHandle<()>>,
+    events_rx: InactiveReceiver<(u64, Event)>,
+    sync_progress: Arc<SyncProgress>,
+    task: Arc<TaskHandle>,
 }
 
 impl Validator {
     pub async fn new<F, Fut>(
-        mainchain_client: jsonrpsee::http_client::HttpClient,
-        zmq_addr_sequence: String,
+        mainchain_client: crate::rpc_client::Client,
+        zmq_addrs_sequence: Vec<String>,
         data_dir: &Path,
+        prune_block_info_depth: Option<u32>,
+        deposit_confirmation_depths: DepositConfirmationDepths,
+        record_fixtures_dir: Option<PathBuf>,
         err_handler: F,
     ) -> Result<Self, InitError>
     where
@@ -98,42 +527,268 @@ impl Validator {
             })
             .await?;
         let dbs = Dbs::new(data_dir, blockchain_info.chain)?;
+        let sync_progress = Arc::new(SyncProgress::default());
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
         let task = spawn({
             let dbs = dbs.clone();
+            let mainchain_client = mainchain_client.clone();
+            let sync_progress = sync_progress.clone();
             async move {
-                task::task(&mainchain_client, &zmq_addr_sequence, &dbs, &events_tx)
-                    .then(|res| async {
-                        if let Err(err) = res {
-                            let err = anyhow::Error::from(err);
-                            err_handler(err).await
-                        }
-                    })
-                    .await
+                // The sync task only awaits between write txns, so stopping
+                // it at an await point never leaves a write txn open.
+                // Event streams end once `events_tx` is dropped.
+                let res = tokio::select! {
+                    res = task::supervise(
+                        &mainchain_client,
+                        &zmq_addrs_sequence,
+                        &dbs,
+                        &sync_progress,
+                        &events_tx,
+                        prune_block_info_depth,
+                        &deposit_confirmation_depths,
+                        record_fixtures_dir.as_deref(),
+                    ) => res,
+                    _ = shutdown_rx.wait_for(|shutdown| *shutdown) => {
+                        tracing::info!("Stopped sync task");
+                        Ok(())
+                    }
+                };
+                drop(events_tx);
+                sync_progress.set_task_state(TaskState::Stopped);
+                if let Err(err) = res {
+                    let err = anyhow::Error::from(err);
+                    sync_progress.record_error(TaskErrorKind::Fatal, &err);
+                    err_handler(err).await
+                }
             }
         });
         Ok(Self {
             dbs,
+            fee_info_cache: Arc::new(Mutex::new(None)),
+            mainchain_client,
             events_rx: events_rx.deactivate(),
             network: blockchain_info.chain,
-            task: Arc::new(task),
+            sync_progress,
+            task: Arc::new(TaskHandle {
+                join_handle: Mutex::new(Some(task)),
+                shutdown_tx,
+            }),
         })
     }
 
+    /// Stop the sync task, and flush the DBs to disk.
+    /// The sync task is stopped between DB write txns, so any block that is
+    /// being connected is either committed or discarded. Event streams end
+    /// once the sync task has stopped. The validator can still be queried
+    /// after shutdown.
+    pub async fn shutdown(&self) -> Result<(), SyncEnvError> {
+        let _: bool = self.task.shutdown_tx.send_replace(true);
+        let join_handle = self.task.join_handle.lock().take();
+        if let Some(join_handle) = join_handle {
+            if let Err(err) = join_handle.await {
+                tracing::error!("Sync task failed: {err:#}");
+            }
+        }
+        self.dbs.force_sync()
+    }
+
+    /// `true` if [`Self::shutdown`] has been called
+    pub fn is_shutting_down(&self) -> bool {
+        *self.task.shutdown_tx.borrow()
+    }
+
+    /// Shut down, and close the DBs once all other clones of the validator
+    /// have been dropped.
+    /// Waits at most `timeout` for other clones to be dropped, and returns
+    /// `false` if the DBs are still open.
+    pub async fn close(self, timeout: Duration) -> Result<bool, SyncEnvError> {
+        let () = self.shutdown().await?;
+        let dbs = self.dbs.clone();
+        drop(self);
+        tokio::task::block_in_place(|| dbs.close(timeout))
+    }
+
+    /// Validator DBs, for queries that are not covered by other methods.
+    /// The DBs must not be written to.
+    pub fn dbs(&self) -> &Dbs {
+        &self.dbs
+    }
+
+    /// Schedule compaction of the validator DBs on next startup.
+    /// Compaction does not happen until the enforcer is restarted.
+    pub fn schedule_db_compaction(&self) -> Result<ScheduledCompaction, ScheduleCompactionError> {
+        self.dbs.schedule_compaction()
+    }
+
     pub fn network(&self) -> bitcoin::Network {
         self.network
     }
 
-    pub fn subscribe_events(&self) -> impl FusedStream<Item = Result<Event, EventsStreamError>> {
-        futures::stream::try_unfold(self.events_rx.activate_cloned(), |mut receiver| async {
-            match receiver.recv_direct().await {
-                Ok(event) => Ok(Some((event, receiver))),
-                Err(async_broadcast::RecvError::Closed) => Ok(None),
-                Err(async_broadcast::RecvError::Overflowed(_)) => Err(EventsStreamError::Overflow),
-            }
-        })
+    /// Subscribe to events, along with their sequence numbers in the event
+    /// journal.
+    /// If `start_sequence` is specified, events are replayed from the journal
+    /// starting at that sequence number, before streaming new events.
+    /// Otherwise, only new events are streamed.
+    /// Subscribers that fall behind catch up from the journal, so that no
+    /// events are skipped.
+    /// Fails with [`EventsStreamError::EventsPruned`] if the next event to
+    /// stream is no longer in the journal, either because `start_sequence`
+    /// is too old, or because the subscriber fell more than
+    /// [`Dbs::MAX_JOURNAL_EVENTS`] events behind.
+    pub fn subscribe_events(
+        &self,
+        start_sequence: Option<u64>,
+    ) -> impl FusedStream<Item = Result<(u64, Event), EventsStreamError>> {
+        /// Max number of events to read from the journal at once
+        const JOURNAL_BATCH_SIZE: usize = 256;
+        // Activate the receiver before reading the journal, so that events
+        // appended in between are not missed
+        let receiver = self.events_rx.activate_cloned();
+        // The start sequence is read when subscribing rather than on first
+        // poll, so that events appended in between are not missed
+        let start_sequence = match start_sequence {
+            Some(start_sequence) => Ok(start_sequence),
+            None => self
+                .dbs
+                .read_txn()
+                .map_err(EventsStreamError::from)
+                .and_then(|rotxn| Ok(self.dbs.next_event_sequence(&rotxn)?)),
+        };
+        let dbs = self.dbs.clone();
+        futures::stream::try_unfold(
+            (receiver, start_sequence, VecDeque::new()),
+            move |(mut receiver, next_sequence, mut journal_events)| {
+                let dbs = dbs.clone();
+                async move {
+                    let next_sequence = next_sequence?;
+                    loop {
+                        if let Some((sequence, event)) = journal_events.pop_front() {
+                            let state = (receiver, Ok(sequence + 1), journal_events);
+                            return Ok(Some(((sequence, event), state)));
+                        }
+                        journal_events = {
+                            let rotxn = dbs.read_txn()?;
+                            dbs.read_events(&rotxn, next_sequence, JOURNAL_BATCH_SIZE)?
+                        }
+                        .into();
+                        if let Some((oldest, _)) = journal_events.front() {
+                            if *oldest != next_sequence {
+                                return Err(EventsStreamError::EventsPruned {
+                                    sequence: next_sequence,
+                                    oldest: *oldest,
+                                });
+                            }
+                            continue;
+                        }
+                        match receiver.recv_direct().await {
+                            Ok((sequence, event)) if sequence == next_sequence => {
+                                let state = (receiver, Ok(sequence + 1), journal_events);
+                                return Ok(Some(((sequence, event), state)));
+                            }
+                            // Either already streamed from the journal, or
+                            // events were missed, and are read from the
+                            // journal instead
+                            Ok(_) | Err(async_broadcast::RecvError::Overflowed(_)) => (),
+                            Err(async_broadcast::RecvError::Closed) => return Ok(None),
+                        }
+                    }
+                }
+            },
+        )
         .fuse()
     }
 
+    /// Voting status of a pending sidechain proposal.
+    /// Returns `None` if there is no pending proposal with the description
+    /// hash, eg. because it was activated or has failed.
+    pub fn get_sidechain_proposal_status(
+        &self,
+        description_hash: &sha256d::Hash,
+    ) -> Result<Option<SidechainProposalVotingStatus>, miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let Some(sidechain) = self
+            .dbs
+            .description_hash_to_sidechain
+            .try_get(&rotxn, description_hash)
+            .into_diagnostic()?
+        else {
+            return Ok(None);
+        };
+        let tip = self
+            .dbs
+            .current_chain_tip
+            .get(&rotxn, &dbs::UnitKey)
+            .into_diagnostic()?;
+        let tip_height = self
+            .dbs
+            .block_hashes
+            .height()
+            .get(&rotxn, &tip)
+            .into_diagnostic()?;
+        let sidechain_slot_is_used = self
+            .dbs
+            .active_sidechains
+            .sidechain
+            .try_get(&rotxn, &sidechain.proposal.sidechain_number)
+            .into_diagnostic()?
+            .is_some();
+        Ok(Some(SidechainProposalVotingStatus::new(
+            &sidechain,
+            sidechain_slot_is_used,
+            tip_height,
+        )))
+    }
+
+    /// Get pending sidechain proposals, with their voting status at the
+    /// current tip
+    pub fn get_sidechain_proposals(
+        &self,
+    ) -> Result<Vec<(sha256d::Hash, Sidechain, SidechainProposalVotingStatus)>, miette::Report>
+    {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let Some(tip) = self
+            .dbs
+            .current_chain_tip
+            .try_get(&rotxn, &dbs::UnitKey)
+            .into_diagnostic()?
+        else {
+            return Ok(Vec::new());
+        };
+        let tip_height = self
+            .dbs
+            .block_hashes
+            .height()
+            .get(&rotxn, &tip)
+            .into_diagnostic()?;
+        let active_sidechains: BTreeSet<SidechainNumber> = self
+            .dbs
+            .active_sidechains
+            .sidechain
+            .iter(&rotxn)
+            .into_diagnostic()?
+            .map(|(sidechain_number, _sidechain)| Ok(sidechain_number))
+            .collect()
+            .into_diagnostic()?;
+        let res = self
+            .dbs
+            .description_hash_to_sidechain
+            .iter(&rotxn)
+            .into_diagnostic()?
+            .map(|(description_hash, sidechain)| {
+                let sidechain_slot_is_used =
+                    active_sidechains.contains(&sidechain.proposal.sidechain_number);
+                let status = SidechainProposalVotingStatus::new(
+                    &sidechain,
+                    sidechain_slot_is_used,
+                    tip_height,
+                );
+                Ok((description_hash, sidechain, status))
+            })
+            .collect()
+            .into_diagnostic()?;
+        Ok(res)
+    }
+
     /// Get (possibly unactivated) sidechains
     pub fn get_sidechains(&self) -> Result<Vec<(sha256d::Hash, Sidechain)>, miette::Report> {
         let rotxn = self.dbs.read_txn().into_diagnostic()?;
@@ -147,6 +802,35 @@ impl Validator {
         Ok(res)
     }
 
+    /// Get active sidechains. Each sidechain is returned with a flag that is
+    /// `true` if there is a pending proposal to replace it.
+    pub fn get_active_sidechains_with_pending_replacements(
+        &self,
+    ) -> Result<Vec<(Sidechain, bool)>, miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let proposed_slots: BTreeSet<SidechainNumber> = self
+            .dbs
+            .description_hash_to_sidechain
+            .iter(&rotxn)
+            .into_diagnostic()?
+            .map(|(_description_hash, sidechain)| Ok(sidechain.proposal.sidechain_number))
+            .collect()
+            .into_diagnostic()?;
+        let res = self
+            .dbs
+            .active_sidechains
+            .sidechain
+            .iter(&rotxn)
+            .into_diagnostic()?
+            .map(|(sidechain_number, sidechain)| {
+                let replacement_pending = proposed_slots.contains(&sidechain_number);
+                Ok((sidechain, replacement_pending))
+            })
+            .collect()
+            .into_diagnostic()?;
+        Ok(res)
+    }
+
     pub fn get_active_sidechains(&self) -> Result<Vec<Sidechain>, miette::Report> {
         let rotxn = self.dbs.read_txn().into_diagnostic()?;
         let res = self
@@ -183,6 +867,42 @@ impl Validator {
         Ok(sequence_number)
     }
 
+    /// Returns up to `limit` treasury UTXOs for the given sidechain number,
+    /// in sequence order, beginning at `start_sequence`.
+    /// Also returns the total number of treasury UTXOs for the sidechain.
+    pub fn list_treasury_utxos(
+        &self,
+        sidechain_number: SidechainNumber,
+        start_sequence: u64,
+        limit: u64,
+    ) -> Result<(Vec<(u64, TreasuryUtxo)>, u64), miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let treasury_utxo_count = self
+            .dbs
+            .active_sidechains
+            .treasury_utxo_count
+            .try_get(&rotxn, &sidechain_number)
+            .into_diagnostic()?
+            .unwrap_or(0);
+        let end_sequence = start_sequence
+            .saturating_add(limit)
+            .min(treasury_utxo_count);
+        // Keys are not ordered by sequence number in the db, so each
+        // treasury UTXO is looked up individually
+        let treasury_utxos = (start_sequence..end_sequence)
+            .map(|sequence_number| {
+                let treasury_utxo = self
+                    .dbs
+                    .active_sidechains
+                    .slot_sequence_to_treasury_utxo
+                    .get(&rotxn, &(sidechain_number, sequence_number))
+                    .into_diagnostic()?;
+                Ok((sequence_number, treasury_utxo))
+            })
+            .collect::<Result<_, miette::Report>>()?;
+        Ok((treasury_utxos, treasury_utxo_count))
+    }
+
     /// Returns `Some` with the Ctip for the given sidechain number. `None`
     /// if there's no Ctip for the given sidechain number.
     pub fn try_get_ctip(
@@ -199,12 +919,395 @@ impl Validator {
         Ok(ctip)
     }
 
+    /// Returns `None` if the sidechain slot is not active.
+    pub fn get_sidechain_stats(
+        &self,
+        sidechain_number: SidechainNumber,
+    ) -> Result<Option<SidechainStatsInfo>, miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let Some(sidechain) = self
+            .dbs
+            .active_sidechains
+            .sidechain
+            .try_get(&rotxn, &sidechain_number)
+            .into_diagnostic()?
+        else {
+            return Ok(None);
+        };
+        let stats = self
+            .dbs
+            .active_sidechains
+            .stats
+            .try_get(&rotxn, &sidechain_number)
+            .into_diagnostic()?
+            .unwrap_or_default();
+        let treasury_balance = self
+            .dbs
+            .active_sidechains
+            .ctip
+            .try_get(&rotxn, &sidechain_number)
+            .into_diagnostic()?
+            .map_or(Amount::ZERO, |ctip| ctip.value);
+        Ok(Some(SidechainStatsInfo {
+            stats,
+            treasury_balance,
+            activation_height: sidechain.status.activation_height,
+        }))
+    }
+
+    fn try_get_ctip_info(
+        &self,
+        rotxn: &RoTxn,
+        sidechain_number: SidechainNumber,
+    ) -> Result<Option<CtipInfo>, dbs::db_error::TryGet> {
+        let ctip = self
+            .dbs
+            .active_sidechains
+            .ctip
+            .try_get(rotxn, &sidechain_number)?;
+        let treasury_utxo_count = self
+            .dbs
+            .active_sidechains
+            .treasury_utxo_count
+            .try_get(rotxn, &sidechain_number)?;
+        match (ctip, treasury_utxo_count) {
+            (Some(ctip), Some(treasury_utxo_count)) => Ok(Some(CtipInfo {
+                ctip,
+                // The CTIP is the last treasury UTXO
+                sequence_number: treasury_utxo_count - 1,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Stream the CTIP for a sidechain, beginning with the current CTIP.
+    /// A new item is emitted each time the CTIP changes, including when a
+    /// block that changed the CTIP is disconnected. Items are `None` if the
+    /// sidechain has no CTIP.
+    pub fn subscribe_ctip(
+        &self,
+        sidechain_number: SidechainNumber,
+    ) -> Result<
+        impl FusedStream<Item = Result<Option<CtipInfo>, SubscribeCtipError>>,
+        SubscribeCtipError,
+    > {
+        // Read the CTIP alongside the next sequence number, so that changes
+        // in between are not missed
+        let (ctip_info, next_sequence) = {
+            let rotxn = self.dbs.read_txn()?;
+            let ctip_info = self.try_get_ctip_info(&rotxn, sidechain_number)?;
+            (ctip_info, self.dbs.next_event_sequence(&rotxn)?)
+        };
+        let events = self.subscribe_events(Some(next_sequence)).boxed();
+        let validator = self.clone();
+        let updates = futures::stream::try_unfold(
+            (events, ctip_info),
+            move |(mut events, last_ctip_info)| {
+                let validator = validator.clone();
+                async move {
+                    while let Some((_sequence, event)) = events.try_next().await? {
+                        if let Event::WithdrawalBundleAlert { .. }
+                        | Event::DepositConfirmed { .. }
+                        | Event::DepositUnconfirmed { .. } = event
+                        {
+                            continue;
+                        }
+                        let ctip_info = {
+                            let rotxn = validator.dbs.read_txn()?;
+                            validator.try_get_ctip_info(&rotxn, sidechain_number)?
+                        };
+                        if ctip_info != last_ctip_info {
+                            return Ok(Some((ctip_info, (events, ctip_info))));
+                        }
+                    }
+                    Ok::<_, SubscribeCtipError>(None)
+                }
+            },
+        );
+        Ok(futures::stream::once(futures::future::ready(Ok(ctip_info)))
+            .chain(updates)
+            .fuse())
+    }
+
+    /// Returns pending M6IDs for the given sidechain number, in order.
+    pub fn get_pending_m6ids(
+        &self,
+        sidechain_number: SidechainNumber,
+    ) -> Result<Vec<PendingM6id>, miette::Report> {
+        let txn = self.dbs.read_txn().into_diagnostic()?;
+        let pending_m6ids = self
+            .dbs
+            .active_sidechains
+            .pending_m6ids
+            .try_get(&txn, &sidechain_number)
+            .into_diagnostic()?;
+        Ok(pending_m6ids.unwrap_or_default())
+    }
+
+    /// Returns pending M6IDs for the given sidechain number, in order, with
+    /// their ages at the current tip
+    pub fn get_withdrawal_bundle_queue(
+        &self,
+        sidechain_number: SidechainNumber,
+    ) -> Result<Vec<(PendingM6id, u32)>, miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let Some(tip) = self
+            .dbs
+            .current_chain_tip
+            .try_get(&rotxn, &dbs::UnitKey)
+            .into_diagnostic()?
+        else {
+            return Ok(Vec::new());
+        };
+        let tip_height = self
+            .dbs
+            .block_hashes
+            .height()
+            .get(&rotxn, &tip)
+            .into_diagnostic()?;
+        let pending_m6ids = self
+            .dbs
+            .active_sidechains
+            .pending_m6ids
+            .try_get(&rotxn, &sidechain_number)
+            .into_diagnostic()?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|pending_m6id| {
+                let age = tip_height.saturating_sub(pending_m6id.proposal_height);
+                (pending_m6id, age)
+            })
+            .collect();
+        Ok(pending_m6ids)
+    }
+
+    /// Returns pending M6IDs for all active sidechains, or for a single
+    /// sidechain if `sidechain_number` is specified.
+    /// Pending M6IDs for each sidechain are in order, so that the index of
+    /// each M6ID is the index used to upvote it in M4 messages.
+    /// Also returns the height of the current tip.
+    pub fn list_pending_withdrawal_bundles(
+        &self,
+        sidechain_number: Option<SidechainNumber>,
+    ) -> Result<(Vec<(SidechainNumber, Vec<PendingM6id>)>, Option<u32>), miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let tip_height = match self
+            .dbs
+            .current_chain_tip
+            .try_get(&rotxn, &dbs::UnitKey)
+            .into_diagnostic()?
+        {
+            Some(tip) => Some(
+                self.dbs
+                    .block_hashes
+                    .height()
+                    .get(&rotxn, &tip)
+                    .into_diagnostic()?,
+            ),
+            None => None,
+        };
+        let pending_m6ids = match sidechain_number {
+            Some(sidechain_number) => {
+                let pending_m6ids = self
+                    .dbs
+                    .active_sidechains
+                    .pending_m6ids
+                    .try_get(&rotxn, &sidechain_number)
+                    .into_diagnostic()?;
+                pending_m6ids
+                    .map(|pending_m6ids| (sidechain_number, pending_m6ids))
+                    .into_iter()
+                    .collect()
+            }
+            None => self
+                .dbs
+                .active_sidechains
+                .pending_m6ids
+                .iter(&rotxn)
+                .into_diagnostic()?
+                .collect()
+                .into_diagnostic()?,
+        };
+        Ok((pending_m6ids, tip_height))
+    }
+
+    /// Status of a withdrawal bundle, from the pending M6IDs for the
+    /// sidechain, or the outcomes of bundles that are no longer pending
+    pub fn get_withdrawal_bundle_status(
+        &self,
+        sidechain_number: SidechainNumber,
+        m6id: Hash256,
+    ) -> Result<WithdrawalBundleStatus, miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let pending_m6id = self
+            .dbs
+            .active_sidechains
+            .pending_m6ids
+            .try_get(&rotxn, &sidechain_number)
+            .into_diagnostic()?
+            .unwrap_or_default()
+            .into_iter()
+            .find(|pending_m6id| pending_m6id.m6id == m6id);
+        // A bundle that failed can be proposed again, so pending bundles
+        // take precedence over outcomes
+        if let Some(pending_m6id) = pending_m6id {
+            let tip = self
+                .dbs
+                .current_chain_tip
+                .get(&rotxn, &dbs::UnitKey)
+                .into_diagnostic()?;
+            let tip_height = self
+                .dbs
+                .block_hashes
+                .height()
+                .get(&rotxn, &tip)
+                .into_diagnostic()?;
+            return Ok(WithdrawalBundleStatus::Pending {
+                vote_count: pending_m6id.vote_count,
+                proposal_height: pending_m6id.proposal_height,
+                age: tip_height.saturating_sub(pending_m6id.proposal_height),
+            });
+        }
+        let outcome = self
+            .dbs
+            .withdrawal_bundle_outcomes
+            .try_get(&rotxn, &(sidechain_number, m6id))
+            .into_diagnostic()?
+            .and_then(|outcomes| outcomes.last().copied());
+        Ok(outcome.map_or(
+            WithdrawalBundleStatus::Unknown,
+            WithdrawalBundleStatus::Resolved,
+        ))
+    }
+
+    /// Returns the two byte upvotes of the M4 message in the current tip,
+    /// which would be repeated by an M4 message in the next block with
+    /// [`crate::messages::M4AckBundles::RepeatPrevious`].
+    pub fn get_previous_votes(&self) -> Result<Vec<u16>, miette::Report> {
+        let rotxn = self.dbs.read_txn().into_diagnostic()?;
+        let previous_votes = self
+            .dbs
+            .previous_votes
+            .try_get(&rotxn, &dbs::UnitKey)
+            .into_diagnostic()?;
+        Ok(previous_votes.unwrap_or_default())
+    }
+
+    /// Returns `Some` with the block hash at the specified height in the
+    /// current best chain, if it exists
+    pub fn try_get_best_chain_block_hash(
+        &self,
+        height: u32,
+    ) -> Result<Option<BlockHash>, TryGetBestChainBlockHashError> {
+        let rotxn = self.dbs.read_txn()?;
+        let res = self
+            .dbs
+            .best_chain_height_to_block_hash
+            .try_get(&rotxn, &height)?;
+        Ok(res)
+    }
+
+    /// Returns `Some` with the header info for the target block, if it has
+    /// been synced. For a height target, this is the block at that height in
+    /// the best chain.
+    fn try_get_synced_block(
+        &self,
+        rotxn: &RoTxn,
+        target: WaitForBlockTarget,
+    ) -> Result<Option<HeaderInfo>, WaitForBlockError> {
+        match target {
+            WaitForBlockTarget::Height(height) => {
+                let Some(block_hash) = self
+                    .dbs
+                    .best_chain_height_to_block_hash
+                    .try_get(rotxn, &height)?
+                else {
+                    return Ok(None);
+                };
+                let header_info = self.dbs.block_hashes.get_header_info(rotxn, &block_hash)?;
+                Ok(Some(header_info))
+            }
+            WaitForBlockTarget::BlockHash(block_hash) => {
+                let Some(header_info) = self
+                    .dbs
+                    .block_hashes
+                    .try_get_header_info(rotxn, &block_hash)?
+                else {
+                    return Ok(None);
+                };
+                let best_chain_block_hash = self
+                    .dbs
+                    .best_chain_height_to_block_hash
+                    .try_get(rotxn, &header_info.height)?;
+                if best_chain_block_hash == Some(block_hash) {
+                    Ok(Some(header_info))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Wait until the target block has been synced, returning its header
+    /// info. Returns immediately if it has already been synced.
+    /// Does not time out; callers should apply their own deadline.
+    pub async fn wait_for_block(
+        &self,
+        target: WaitForBlockTarget,
+    ) -> Result<HeaderInfo, WaitForBlockError> {
+        // Subscribe from the sequence number read alongside the check, so
+        // that blocks connected in between are not missed
+        let start_sequence = {
+            let rotxn = self.dbs.read_txn()?;
+            if let Some(header_info) = self.try_get_synced_block(&rotxn, target)? {
+                return Ok(header_info);
+            }
+            self.dbs.next_event_sequence(&rotxn)?
+        };
+        let mut events = std::pin::pin!(self.subscribe_events(Some(start_sequence)));
+        while let Some((_sequence, _event)) = events.next().await.transpose()? {
+            let rotxn = self.dbs.read_txn()?;
+            if let Some(header_info) = self.try_get_synced_block(&rotxn, target)? {
+                return Ok(header_info);
+            }
+        }
+        Err(WaitForBlockError::EventsClosed)
+    }
+
     pub fn get_block_info(&self, block_hash: &BlockHash) -> Result<BlockInfo, GetBlockInfoError> {
         let rotxn = self.dbs.read_txn()?;
         let res = self.dbs.block_hashes.get_block_info(&rotxn, block_hash)?;
         Ok(res)
     }
 
+    /// Header and block info for up to `count` consecutive blocks in the
+    /// current best chain, starting at `start_height`.
+    /// Fewer blocks are returned if the tip is reached.
+    pub fn get_best_chain_block_infos(
+        &self,
+        start_height: u32,
+        count: u32,
+    ) -> Result<Vec<TwoWayPegData>, GetBestChainBlockInfosError> {
+        let rotxn = self.dbs.read_txn()?;
+        let mut res = Vec::new();
+        for height in start_height..start_height.saturating_add(count) {
+            let Some(block_hash) = self
+                .dbs
+                .best_chain_height_to_block_hash
+                .try_get(&rotxn, &height)?
+            else {
+                break;
+            };
+            let two_way_peg_data = self
+                .dbs
+                .block_hashes
+                .try_get_two_way_peg_data(&rotxn, &block_hash)?
+                .ok_or(GetBestChainBlockInfosError::BlockInfoPruned(block_hash))?;
+            res.push(two_way_peg_data);
+        }
+        Ok(res)
+    }
+
     pub fn get_header_info(
         &self,
         block_hash: &BlockHash,
@@ -214,6 +1317,68 @@ impl Validator {
         Ok(res)
     }
 
+    /// Returns header info for up to `count` blocks, beginning with the
+    /// specified block and followed by its ancestors, in descending order.
+    /// Fewer headers are returned if genesis is reached or ancestor headers
+    /// are missing.
+    pub fn get_ancestor_headers(
+        &self,
+        block_hash: &BlockHash,
+        count: usize,
+    ) -> Result<Vec<HeaderInfo>, GetAncestorHeadersError> {
+        let rotxn = self.dbs.read_txn()?;
+        let height = self
+            .dbs
+            .block_hashes
+            .get_header_info(&rotxn, block_hash)?
+            .height;
+        let res = self
+            .dbs
+            .block_hashes
+            .ancestor_headers(&rotxn, *block_hash)
+            .take(count)
+            .enumerate()
+            .map(|(depth, (block_hash, header))| {
+                Ok(HeaderInfo {
+                    block_hash,
+                    prev_block_hash: header.prev_blockhash,
+                    height: height - depth as u32,
+                    work: header.work(),
+                })
+            })
+            .collect()?;
+        Ok(res)
+    }
+
+    /// Find the most recent common ancestor of two blocks, and the number of
+    /// blocks by which each block is ahead of it
+    pub fn get_common_ancestor(
+        &self,
+        block_hash_a: &BlockHash,
+        block_hash_b: &BlockHash,
+    ) -> Result<CommonAncestor, GetHeaderInfoError> {
+        let rotxn = self.dbs.read_txn()?;
+        let height_a = self
+            .dbs
+            .block_hashes
+            .get_header_info(&rotxn, block_hash_a)?
+            .height;
+        let height_b = self
+            .dbs
+            .block_hashes
+            .get_header_info(&rotxn, block_hash_b)?
+            .height;
+        let header_info =
+            self.dbs
+                .block_hashes
+                .common_ancestor(&rotxn, block_hash_a, block_hash_b)?;
+        Ok(CommonAncestor {
+            depth_a: height_a - header_info.height,
+            depth_b: height_b - header_info.height,
+            header_info,
+        })
+    }
+
     pub fn get_mainchain_tip(&self) -> Result<BlockHash, miette::Report> {
         let txn = self.dbs.read_txn().into_diagnostic()?;
         self.dbs
@@ -222,12 +1387,179 @@ impl Validator {
             .into_diagnostic()
     }
 
+    /// Check a transaction against the state at the current tip, and
+    /// broadcast it via the node if it passes.
+    /// BIP300/301 transactions that would not be accepted at the current tip
+    /// are rejected with [`TransactionRejection`]. Other transactions are
+    /// broadcast without further checks.
+    /// Transactions are broadcast with the node's default `maxburnamount`,
+    /// so the node rejects transactions with OP_RETURN outputs that carry
+    /// value. Deposit address outputs do not carry value.
+    pub async fn broadcast_transaction(
+        &self,
+        transaction: &bitcoin::Transaction,
+    ) -> Result<(Txid, CheckedTransaction), BroadcastTransactionError> {
+        let checked = {
+            let rotxn = self.dbs.read_txn()?;
+            task::policy::check_transaction(&rotxn, &self.dbs, transaction)?
+        };
+        let tx_hex = bitcoin::consensus::encode::serialize_hex(transaction);
+        let txid: Txid = self
+            .mainchain_client
+            .send_raw_transaction(tx_hex, None, None)
+            .await
+            .map_err(BroadcastTransactionError::SendRawTransaction)?;
+        Ok((txid, checked))
+    }
+
+    /// Fee rate estimates and mempool info from the node. Cached for
+    /// [`FEE_INFO_CACHE_TTL`], so that frequent requests do not each query
+    /// the node.
+    pub async fn get_fee_info(&self) -> Result<FeeInfo, miette::Report> {
+        let cached = *self.fee_info_cache.lock();
+        if let Some((fetched_at, fee_info)) = cached {
+            if fetched_at.elapsed() < FEE_INFO_CACHE_TTL {
+                return Ok(fee_info);
+            }
+        }
+        let fee_info = self
+            .mainchain_client
+            .get_fee_info()
+            .await
+            .into_diagnostic()?;
+        *self.fee_info_cache.lock() = Some((Instant::now(), fee_info));
+        Ok(fee_info)
+    }
+
+    /// Sync progress of the enforcer, relative to the node
+    pub async fn get_sync_info(&self) -> Result<SyncInfo, miette::Report> {
+        #[derive(serde::Deserialize)]
+        struct NodeBlockchainInfo {
+            blocks: u32,
+            headers: u32,
+        }
+        let node_info: NodeBlockchainInfo = self
+            .mainchain_client
+            .request("getblockchaininfo", rpc_params![])
+            .await
+            .into_diagnostic()?;
+        let block_height = {
+            let rotxn = self.dbs.read_txn().into_diagnostic()?;
+            match self
+                .dbs
+                .current_chain_tip
+                .try_get(&rotxn, &dbs::UnitKey)
+                .into_diagnostic()?
+            {
+                Some(tip) => Some(
+                    self.dbs
+                        .block_hashes
+                        .height()
+                        .get(&rotxn, &tip)
+                        .into_diagnostic()?,
+                ),
+                None => None,
+            }
+        };
+        let remaining_blocks = node_info
+            .blocks
+            .saturating_sub(block_height.unwrap_or_default());
+        let estimated_time_remaining = if remaining_blocks == 0 {
+            Some(std::time::Duration::ZERO)
+        } else {
+            self.sync_progress.estimate_time_remaining(remaining_blocks)
+        };
+        Ok(SyncInfo {
+            block_height,
+            header_height: self.sync_progress.header_height(),
+            node_block_height: node_info.blocks,
+            node_header_height: node_info.headers,
+            estimated_time_remaining,
+            zmq_connected: self.sync_progress.zmq_connected(),
+            sync_task_restarts: self.sync_progress.sync_task_restarts(),
+        })
+    }
+
+    /// State of the sync task, and the most recent errors that it
+    /// encountered
+    pub fn get_enforcer_status(&self) -> EnforcerStatus {
+        self.sync_progress.status()
+    }
+
+    /// Last block in the range after `start_block`, up to and including
+    /// `end_block`, that is in the best chain with at least
+    /// `min_confirmations` confirmations.
+    /// Returns `None` if no block in the range has enough confirmations.
+    fn last_confirmed_block(
+        &self,
+        rotxn: &RoTxn,
+        start_block: Option<BlockHash>,
+        end_block: BlockHash,
+        min_confirmations: NonZeroU32,
+    ) -> Result<Option<BlockHash>, GetTwoWayPegDataRangeError> {
+        let Some(tip) = self.dbs.current_chain_tip.try_get(rotxn, &dbs::UnitKey)? else {
+            return Ok(None);
+        };
+        let tip_height = self.dbs.block_hashes.height().get(rotxn, &tip)?;
+        let Some(max_height) = (tip_height + 1).checked_sub(min_confirmations.get()) else {
+            return Ok(None);
+        };
+        // Missing end blocks are reported when fetching the range
+        let Some(mut height) = self.dbs.block_hashes.height().try_get(rotxn, &end_block)? else {
+            return Ok(Some(end_block));
+        };
+        let mut ancestor_headers = self.dbs.block_hashes.ancestor_headers(rotxn, end_block);
+        while let Some((block_hash, _header)) = ancestor_headers.next()? {
+            let is_start_block = Some(block_hash) == start_block;
+            // The start block is excluded, unless it is also the end block
+            if is_start_block && block_hash != end_block {
+                return Ok(None);
+            }
+            if height <= max_height
+                && self
+                    .dbs
+                    .best_chain_height_to_block_hash
+                    .try_get(rotxn, &height)?
+                    == Some(block_hash)
+            {
+                return Ok(Some(block_hash));
+            }
+            if is_start_block {
+                return Ok(None);
+            }
+            let Some(prev_height) = height.checked_sub(1) else {
+                break;
+            };
+            height = prev_height;
+        }
+        Ok(None)
+    }
+
+    /// Two-way peg data for blocks after `start_block`, up to and including
+    /// `end_block`. If `min_confirmations` is set, the range ends at the last
+    /// block in the best chain with at least that many confirmations instead,
+    /// so that only data that is unlikely to be reorged is returned.
     pub fn get_two_way_peg_data(
         &self,
         start_block: Option<BlockHash>,
         end_block: BlockHash,
+        min_confirmations: Option<NonZeroU32>,
     ) -> Result<Vec<TwoWayPegData>, GetTwoWayPegDataRangeError> {
         let rotxn = self.dbs.read_txn()?;
+        let end_block = match min_confirmations {
+            Some(min_confirmations) => {
+                match self.last_confirmed_block(
+                    &rotxn,
+                    start_block,
+                    end_block,
+                    min_confirmations,
+                )? {
+                    Some(end_block) => end_block,
+                    None => return Ok(Vec::new()),
+                }
+            }
+            None => end_block,
+        };
         let res =
             self.dbs
                 .block_hashes
@@ -235,6 +1567,116 @@ impl Validator {
         Ok(res)
     }
 
+    /// Block hashes after `start_block`, up to and including `end_block`, in
+    /// ascending order. If `start_block` is `None`, the range begins at
+    /// genesis.
+    fn block_hash_range(
+        &self,
+        rotxn: &RoTxn,
+        start_block: Option<BlockHash>,
+        end_block: BlockHash,
+    ) -> Result<Vec<BlockHash>, BlockHashRangeError> {
+        if !self.dbs.block_hashes.contains_header(rotxn, &end_block)? {
+            return Err(BlockHashRangeError::EndBlockNotFound(end_block));
+        }
+        let mut block_hashes = vec![end_block];
+        let mut prev_block = end_block;
+        if Some(end_block) != start_block {
+            let mut ancestor_headers = self.dbs.block_hashes.ancestor_headers(rotxn, end_block);
+            while let Some((block_hash, header)) = ancestor_headers.next()? {
+                if block_hash != end_block {
+                    if Some(block_hash) == start_block {
+                        break;
+                    }
+                    block_hashes.push(block_hash);
+                }
+                prev_block = header.prev_blockhash;
+            }
+            if let Some(start_block) = start_block {
+                if prev_block != start_block {
+                    return Err(BlockHashRangeError::StartBlockNotAncestor {
+                        start_block,
+                        end_block,
+                    });
+                }
+            } else if prev_block != BlockHash::all_zeros() {
+                return Err(BlockHashRangeError::MissingHeader(prev_block));
+            }
+        }
+        block_hashes.reverse();
+        Ok(block_hashes)
+    }
+
+    /// Stream two way peg data for the blocks after `start_block`, up to and
+    /// including `end_block`, in ascending order. The range is the same as
+    /// for [`Self::get_two_way_peg_data`].
+    /// Block info is read as the stream is polled, so that large ranges are
+    /// not loaded into memory at once.
+    pub fn stream_two_way_peg_data(
+        &self,
+        start_block: Option<BlockHash>,
+        end_block: BlockHash,
+    ) -> Result<
+        impl FusedStream<Item = Result<TwoWayPegData, StreamTwoWayPegDataError>>,
+        StreamTwoWayPegDataError,
+    > {
+        let block_hashes = {
+            let rotxn = self.dbs.read_txn()?;
+            self.block_hash_range(&rotxn, start_block, end_block)?
+        };
+        let dbs = self.dbs.clone();
+        let stream = futures::stream::iter(block_hashes).map(move |block_hash| {
+            let rotxn = dbs.read_txn()?;
+            dbs.block_hashes
+                .try_get_two_way_peg_data(&rotxn, &block_hash)?
+                .ok_or(StreamTwoWayPegDataError::BlockInfoNotFound(block_hash))
+        });
+        Ok(stream.fuse())
+    }
+
+    /// Stream the headers after `start_block`, up to and including
+    /// `end_block`, in ascending order, with their cumulative work.
+    /// If `start_block` is `None`, the chain begins at genesis. Otherwise,
+    /// the cumulative work of `start_block` must be known, ie. it must have
+    /// been synced.
+    /// Headers are read as the stream is polled.
+    pub fn stream_header_chain(
+        &self,
+        start_block: Option<BlockHash>,
+        end_block: BlockHash,
+    ) -> Result<
+        impl FusedStream<Item = Result<ChainHeader, StreamHeaderChainError>>,
+        StreamHeaderChainError,
+    > {
+        let (block_hashes, mut cumulative_work) = {
+            let rotxn = self.dbs.read_txn()?;
+            let block_hashes = self.block_hash_range(&rotxn, start_block, end_block)?;
+            let cumulative_work = match start_block {
+                Some(start_block) => self
+                    .dbs
+                    .block_hashes
+                    .cumulative_work()
+                    .try_get(&rotxn, &start_block)?
+                    .ok_or(StreamHeaderChainError::CumulativeWorkNotFound(start_block))?,
+                None => bitcoin::Work::from_le_bytes([0; 32]),
+            };
+            (block_hashes, cumulative_work)
+        };
+        let dbs = self.dbs.clone();
+        let stream = futures::stream::iter(block_hashes).map(move |block_hash| {
+            let rotxn = dbs.read_txn()?;
+            let header = dbs.block_hashes.header().get(&rotxn, &block_hash)?;
+            let height = dbs.block_hashes.height().get(&rotxn, &block_hash)?;
+            cumulative_work = cumulative_work + header.work();
+            Ok(ChainHeader {
+                header,
+                height,
+                cumulative_work,
+            })
+        });
+        Ok(stream.fuse())
+    }
+
     pub fn try_get_bmm_commitments(
         &self,
         block_hash: &BlockHash,
@@ -248,6 +1690,214 @@ impl Validator {
         Ok(res)
     }
 
+    /// Blocks in the current best chain with heights in
+    /// `start_height..=end_height` that contain a BMM commitment (M7) for the
+    /// sidechain, with the committed h*, in ascending order.
+    /// Heights above the tip are ignored.
+    pub fn list_blocks_with_bmm_commitments(
+        &self,
+        sidechain_number: SidechainNumber,
+        start_height: u32,
+        end_height: u32,
+    ) -> Result<Vec<(HeaderInfo, Hash256)>, ListBlocksWithBmmCommitmentsError> {
+        let rotxn = self.dbs.read_txn()?;
+        let mut res = Vec::new();
+        for height in start_height..=end_height {
+            let Some(block_hash) = self
+                .dbs
+                .best_chain_height_to_block_hash
+                .try_get(&rotxn, &height)?
+            else {
+                break;
+            };
+            let bmm_commitments = self
+                .dbs
+                .block_hashes
+                .bmm_commitments()
+                .try_get(&rotxn, &block_hash)?
+                .ok_or(ListBlocksWithBmmCommitmentsError::BmmCommitmentsPruned(
+                    block_hash,
+                ))?;
+            let Some(commitment) = bmm_commitments.get(&sidechain_number) else {
+                continue;
+            };
+            let header_info = self.dbs.block_hashes.get_header_info(&rotxn, &block_hash)?;
+            res.push((header_info, *commitment));
+        }
+        Ok(res)
+    }
+
+    /// Returns `Some` if the txid is a BIP300/301 transaction that was
+    /// processed by the enforcer
+    pub fn try_get_bip300_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<Bip300Transaction>, TryGetBip300TransactionError> {
+        let rotxn = self.dbs.read_txn()?;
+        let res = self.dbs.txid_to_bip300_transaction.try_get(&rotxn, txid)?;
+        Ok(res)
+    }
+
+    /// Returns `Some` if the txid is a BIP300/301 transaction that was
+    /// processed by the enforcer, with the raw transaction fetched from the
+    /// node. The transaction is requested from the block that includes it,
+    /// so the node does not need a transaction index.
+    pub async fn get_raw_bip300_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<RawBip300Transaction>, GetRawBip300TransactionError> {
+        let (bip300_transaction, block_height, tip_height) = {
+            let rotxn = self.dbs.read_txn()?;
+            let Some(bip300_transaction) =
+                self.dbs.txid_to_bip300_transaction.try_get(&rotxn, txid)?
+            else {
+                return Ok(None);
+            };
+            let block_height = self
+                .dbs
+                .block_hashes
+                .get_header_info(&rotxn, &bip300_transaction.block_hash)?
+                .height;
+            let tip_height = match self.dbs.current_chain_tip.try_get(&rotxn, &dbs::UnitKey)? {
+                Some(tip) => self.dbs.block_hashes.get_header_info(&rotxn, &tip)?.height,
+                None => block_height,
+            };
+            (bip300_transaction, block_height, tip_height)
+        };
+        let tx_hex: String = self
+            .mainchain_client
+            .request(
+                "getrawtransaction",
+                rpc_params![txid, false, bip300_transaction.block_hash],
+            )
+            .await
+            .map_err(|source| GetRawBip300TransactionError::GetRawTransaction {
+                txid: *txid,
+                source,
+            })?;
+        let transaction =
+            bitcoin::consensus::encode::deserialize_hex(&tx_hex).map_err(|source| {
+                GetRawBip300TransactionError::DecodeTransaction {
+                    txid: *txid,
+                    source,
+                }
+            })?;
+        Ok(Some(RawBip300Transaction {
+            transaction,
+            bip300_transaction,
+            block_height,
+            confirmations: tip_height.saturating_sub(block_height) + 1,
+        }))
+    }
+
+    /// Inclusion status of an M8 BMM request tx. Txs that have left the
+    /// node's mempool without being included can only be found if they were
+    /// seen by the mempool watcher.
+    /// Returns `None` if the tx is unknown.
+    pub async fn get_bmm_inclusion_status(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<BmmInclusionInfo>, GetBmmInclusionStatusError> {
+        #[derive(serde::Deserialize)]
+        struct VerboseTransaction {
+            hex: String,
+        }
+
+        fn bmm_request(
+            txid: &Txid,
+            transaction: &bitcoin::Transaction,
+        ) -> Result<M8BmmRequest, GetBmmInclusionStatusError> {
+            transaction
+                .output
+                .iter()
+                .find_map(|txout| {
+                    crate::messages::parse_m8_bmm_request(txout.script_pubkey.as_bytes())
+                        .ok()
+                        .map(|(_, bmm_request)| bmm_request)
+                })
+                .ok_or(GetBmmInclusionStatusError::NotBmmRequest(*txid))
+        }
+
+        let decode = |tx_hex: &str| {
+            bitcoin::consensus::encode::deserialize_hex::<bitcoin::Transaction>(tx_hex).map_err(
+                |source| GetBmmInclusionStatusError::DecodeTransaction {
+                    txid: *txid,
+                    source,
+                },
+            )
+        };
+
+        let (bip300_transaction, tip) = {
+            let rotxn = self.dbs.read_txn()?;
+            let bip300_transaction = self.dbs.txid_to_bip300_transaction.try_get(&rotxn, txid)?;
+            let tip = self.dbs.current_chain_tip.try_get(&rotxn, &dbs::UnitKey)?;
+            (bip300_transaction, tip)
+        };
+        // Txs in synced blocks are indexed with the result of M7 matching
+        if let Some(Bip300Transaction { block_hash, kind }) = bip300_transaction {
+            let crate::types::Bip300TransactionKind::M8BmmRequest { accepted } = kind else {
+                return Err(GetBmmInclusionStatusError::NotBmmRequest(*txid));
+            };
+            let tx_hex: String = self
+                .mainchain_client
+                .request("getrawtransaction", rpc_params![txid, false, block_hash])
+                .await
+                .map_err(|source| GetBmmInclusionStatusError::GetRawTransaction {
+                    txid: *txid,
+                    source,
+                })?;
+            let status = if accepted {
+                BmmInclusionStatus::Included { block_hash }
+            } else {
+                BmmInclusionStatus::NotAccepted { block_hash }
+            };
+            return Ok(Some(BmmInclusionInfo {
+                bmm_request: bmm_request(txid, &decode(&tx_hex)?)?,
+                status,
+            }));
+        }
+        let res: Result<VerboseTransaction, _> = self
+            .mainchain_client
+            .request("getrawtransaction", rpc_params![txid, true])
+            .await;
+        let (transaction, known_to_node) = match res {
+            Ok(verbose_tx) => (decode(&verbose_tx.hex)?, true),
+            Err(err) if crate::rpc_client::is_not_found(&err) => {
+                let Some(transaction) = self.mainchain_client.try_get_recent_mempool_tx(txid)
+                else {
+                    return Ok(None);
+                };
+                (transaction, false)
+            }
+            Err(source) => {
+                return Err(GetBmmInclusionStatusError::GetRawTransaction {
+                    txid: *txid,
+                    source,
+                })
+            }
+        };
+        let bmm_request = bmm_request(txid, &transaction)?;
+        let status = unsynced_bmm_inclusion_status(
+            &transaction,
+            &bmm_request,
+            tip,
+            known_to_node,
+            |outpoint| async move {
+                let txout: Option<serde_json::Value> = self
+                    .mainchain_client
+                    .request("gettxout", rpc_params![outpoint.txid, outpoint.vout, true])
+                    .await
+                    .map_err(|source| GetBmmInclusionStatusError::GetTxOut { outpoint, source })?;
+                Ok(txout.is_none())
+            },
+        )
+        .await?;
+        Ok(Some(BmmInclusionInfo {
+            bmm_request,
+            status,
+        }))
+    }
+
     /*
     pub fn get_main_block_height(&self) -> Result<u32> {
         let txn = self.env.read_txn().into_diagnostic()?;
@@ -300,8 +1950,76 @@ impl Validator {
     */
 }
 
-impl Drop for Validator {
-    fn drop(&mut self) {
-        self.task.abort()
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, convert::Infallible};
+
+    use bitcoin::{hashes::Hash as _, BlockHash, OutPoint, Transaction, TxIn, Txid};
+
+    use super::{unsynced_bmm_inclusion_status, BmmInclusionStatus};
+    use crate::{messages::M8BmmRequest, types::SidechainNumber};
+
+    #[tokio::test]
+    async fn test_unsynced_bmm_inclusion_status() {
+        let prev_mainchain_block_hash = BlockHash::from_byte_array([1; 32]);
+        let bmm_request = M8BmmRequest {
+            sidechain_number: SidechainNumber(1),
+            sidechain_block_hash: [2; 32],
+            prev_mainchain_block_hash: prev_mainchain_block_hash.to_byte_array(),
+        };
+        let outpoint = |vout| OutPoint {
+            txid: Txid::from_byte_array([3; 32]),
+            vout,
+        };
+        let transaction = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: outpoint(vout),
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: Vec::new(),
+        };
+        let status = |tip, known_to_node, spent: &[OutPoint]| {
+            let spent: HashSet<OutPoint> = spent.iter().copied().collect();
+            let transaction = &transaction;
+            let bmm_request = &bmm_request;
+            async move {
+                unsynced_bmm_inclusion_status(
+                    transaction,
+                    bmm_request,
+                    tip,
+                    known_to_node,
+                    |outpoint| std::future::ready(Ok::<_, Infallible>(spent.contains(&outpoint))),
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let tip = Some(prev_mainchain_block_hash);
+        let other_tip = Some(BlockHash::from_byte_array([4; 32]));
+        assert_eq!(status(tip, true, &[]).await, BmmInclusionStatus::InMempool);
+        assert_eq!(
+            status(other_tip, true, &[]).await,
+            BmmInclusionStatus::Expired
+        );
+        assert_eq!(status(None, false, &[]).await, BmmInclusionStatus::Expired);
+        // Expiry takes precedence over replacement
+        assert_eq!(
+            status(other_tip, false, &[outpoint(1)]).await,
+            BmmInclusionStatus::Expired
+        );
+        assert_eq!(
+            status(tip, false, &[outpoint(1)]).await,
+            BmmInclusionStatus::Replaced
+        );
+        assert_eq!(status(tip, false, &[]).await, BmmInclusionStatus::Dropped);
+        // Inputs are not checked while the tx is known to the node
+        assert_eq!(
+            status(tip, true, &[outpoint(0)]).await,
+            BmmInclusionStatus::InMempool
+        );
     }
 }
@@ -0,0 +1,186 @@
+//! Sync progress of the validator task
+
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+use parking_lot::Mutex;
+
+/// Sync progress, as reported by [`super::Validator::get_sync_info`]
+#[derive(Clone, Copy, Debug)]
+pub struct SyncInfo {
+    /// Height of the enforcer's tip, if any blocks have been synced
+    pub block_height: Option<u32>,
+    /// Height of the best header synced by the enforcer, if header sync has
+    /// completed at least once
+    pub header_height: Option<u32>,
+    /// Height of the node's tip
+    pub node_block_height: u32,
+    /// Height of the best header known to the node
+    pub node_header_height: u32,
+    /// Estimated time until the enforcer is synced to the node's tip, based
+    /// on the rate at which blocks are currently being connected
+    pub estimated_time_remaining: Option<Duration>,
+    /// `true` if the ZMQ sequence stream is connected
+    pub zmq_connected: bool,
+    /// Number of times the sync task has been restarted after a recoverable
+    /// error, since the validator was started
+    pub sync_task_restarts: u32,
+}
+
+/// Max number of errors retained for [`super::Validator::get_enforcer_status`]
+pub const MAX_RECENT_ERRORS: usize = 32;
+
+/// Life state of the sync task
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TaskState {
+    /// Syncing, or following the node's tip
+    #[default]
+    Running,
+    /// Waiting to restart after a recoverable error
+    Degraded,
+    /// Stopped after an unrecoverable error, or on shutdown
+    Stopped,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TaskErrorKind {
+    /// Logged, and syncing continued
+    NonFatal,
+    /// The sync task was restarted
+    Recoverable,
+    /// The sync task was stopped
+    Fatal,
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskError {
+    pub time: SystemTime,
+    pub kind: TaskErrorKind,
+    /// Error message, including sources
+    pub message: String,
+}
+
+/// State of the sync task, as reported by
+/// [`super::Validator::get_enforcer_status`]
+#[derive(Clone, Debug)]
+pub struct EnforcerStatus {
+    pub task_state: TaskState,
+    /// Most recent errors, newest first. At most [`MAX_RECENT_ERRORS`] errors
+    /// are retained.
+    pub recent_errors: Vec<TaskError>,
+    /// Number of times the sync task has been restarted after a recoverable
+    /// error, since the validator was started
+    pub sync_task_restarts: u32,
+}
+
+#[derive(Debug)]
+struct BlockSync {
+    started: Instant,
+    blocks_connected: u32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    header_height: Option<u32>,
+    /// Set while blocks are being synced
+    block_sync: Option<BlockSync>,
+    task_state: TaskState,
+    /// Newest errors at the front
+    recent_errors: VecDeque<TaskError>,
+}
+
+/// Sync progress, shared between the validator task and the validator
+#[derive(Debug, Default)]
+pub(super) struct SyncProgress {
+    zmq_connected: AtomicBool,
+    sync_task_restarts: AtomicU32,
+    state: Mutex<State>,
+}
+
+impl SyncProgress {
+    pub fn set_zmq_connected(&self, connected: bool) {
+        self.zmq_connected.store(connected, Ordering::SeqCst);
+    }
+
+    pub fn zmq_connected(&self) -> bool {
+        self.zmq_connected.load(Ordering::SeqCst)
+    }
+
+    pub fn sync_task_restarted(&self) {
+        self.sync_task_restarts.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn sync_task_restarts(&self) -> u32 {
+        self.sync_task_restarts.load(Ordering::SeqCst)
+    }
+
+    pub fn set_task_state(&self, task_state: TaskState) {
+        self.state.lock().task_state = task_state;
+    }
+
+    pub fn record_error(&self, kind: TaskErrorKind, err: &anyhow::Error) {
+        let error = TaskError {
+            time: SystemTime::now(),
+            kind,
+            message: format!("{err:#}"),
+        };
+        let mut state = self.state.lock();
+        state.recent_errors.push_front(error);
+        state.recent_errors.truncate(MAX_RECENT_ERRORS);
+    }
+
+    pub fn status(&self) -> EnforcerStatus {
+        let state = self.state.lock();
+        EnforcerStatus {
+            task_state: state.task_state,
+            recent_errors: state.recent_errors.iter().cloned().collect(),
+            sync_task_restarts: self.sync_task_restarts(),
+        }
+    }
+
+    pub fn headers_synced(&self, header_height: u32) {
+        self.state.lock().header_height = Some(header_height);
+    }
+
+    pub fn header_height(&self) -> Option<u32> {
+        self.state.lock().header_height
+    }
+
+    pub fn start_block_sync(&self) {
+        self.state.lock().block_sync = Some(BlockSync {
+            started: Instant::now(),
+            blocks_connected: 0,
+        });
+    }
+
+    pub fn block_connected(&self) {
+        self.state
+            .lock()
+            .block_sync
+            .iter_mut()
+            .for_each(|block_sync| block_sync.blocks_connected += 1);
+    }
+
+    pub fn finish_block_sync(&self) {
+        self.state.lock().block_sync = None;
+    }
+
+    /// Estimated time to connect the remaining blocks, at the rate that
+    /// blocks have been connected since block sync started.
+    /// Returns `None` if blocks are not currently being synced, or if no
+    /// blocks have been connected yet.
+    pub fn estimate_time_remaining(&self, remaining_blocks: u32) -> Option<Duration> {
+        let (elapsed, blocks_connected) = {
+            let state = self.state.lock();
+            let block_sync = state.block_sync.as_ref()?;
+            (block_sync.started.elapsed(), block_sync.blocks_connected)
+        };
+        if blocks_connected == 0 {
+            return None;
+        }
+        Some(elapsed.mul_f64(f64::from(remaining_blocks) / f64::from(blocks_connected)))
+    }
+}
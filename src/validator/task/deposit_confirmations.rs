@@ -0,0 +1,423 @@
+//! Confirmation depth tracking for deposits, so that sidechains can credit
+//! deposits once they are buried deep enough.
+//!
+//! Deposits are confirmed when the best chain tip changes to a block that
+//! gives them the configured number of confirmations for their sidechain.
+//! If the tip changes to a block that gives them fewer confirmations, or to
+//! a branch that does not include them, the deposits are unconfirmed, and
+//! are confirmed again once they regain enough confirmations.
+//! Confirmations are compared between the old and new best chain on every
+//! tip change, so that reorgs to a branch with more work are handled in the
+//! same way as disconnected blocks.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    num::NonZeroU32,
+    str::FromStr,
+};
+
+use bitcoin::BlockHash;
+use heed::RoTxn;
+use thiserror::Error;
+
+use crate::{
+    types::{DepositConfirmation, Event, SidechainNumber},
+    validator::{
+        dbs::{Dbs, RwTxn, UnitKey},
+        task::error,
+    },
+};
+
+/// Number of confirmations at which deposits are confirmed, as
+/// `<depth>` for all sidechains, or `<sidechain slot>:<depth>` for a single
+/// sidechain
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DepositConfirmationDepth {
+    pub sidechain_number: Option<SidechainNumber>,
+    pub depth: NonZeroU32,
+}
+
+#[derive(Debug, Error)]
+pub enum ParseDepositConfirmationDepthError {
+    #[error("invalid confirmation depth `{0}`, expected a number greater than zero")]
+    Depth(String),
+    #[error("invalid sidechain slot `{0}`")]
+    SidechainNumber(String),
+}
+
+impl FromStr for DepositConfirmationDepth {
+    type Err = ParseDepositConfirmationDepthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sidechain_number, depth) = match s.split_once(':') {
+            Some((sidechain_number, depth)) => {
+                let sidechain_number = sidechain_number.parse::<u8>().map_err(|_| {
+                    ParseDepositConfirmationDepthError::SidechainNumber(sidechain_number.to_owned())
+                })?;
+                (Some(SidechainNumber(sidechain_number)), depth)
+            }
+            None => (None, s),
+        };
+        let depth = depth
+            .parse()
+            .map_err(|_| ParseDepositConfirmationDepthError::Depth(depth.to_owned()))?;
+        Ok(Self {
+            sidechain_number,
+            depth,
+        })
+    }
+}
+
+/// Configured confirmation depths. Deposits to sidechains without a
+/// configured depth are not tracked.
+#[derive(Clone, Debug, Default)]
+pub struct DepositConfirmationDepths {
+    /// Depth for sidechains without a sidechain-specific depth
+    default: Option<NonZeroU32>,
+    sidechains: HashMap<SidechainNumber, NonZeroU32>,
+}
+
+impl DepositConfirmationDepths {
+    /// Sidechain-specific depths take precedence over a default depth.
+    /// If a depth is specified more than once, the last one is used.
+    pub fn new<I>(depths: I) -> Self
+    where
+        I: IntoIterator<Item = DepositConfirmationDepth>,
+    {
+        let mut res = Self::default();
+        for DepositConfirmationDepth {
+            sidechain_number,
+            depth,
+        } in depths
+        {
+            match sidechain_number {
+                Some(sidechain_number) => {
+                    res.sidechains.insert(sidechain_number, depth);
+                }
+                None => res.default = Some(depth),
+            }
+        }
+        res
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.sidechains.is_empty()
+    }
+
+    pub fn get(&self, sidechain_number: SidechainNumber) -> Option<u32> {
+        self.sidechains
+            .get(&sidechain_number)
+            .or(self.default.as_ref())
+            .map(|depth| depth.get())
+    }
+
+    fn depths(&self) -> BTreeSet<u32> {
+        self.default
+            .iter()
+            .chain(self.sidechains.values())
+            .map(|depth| depth.get())
+            .collect()
+    }
+}
+
+/// Whether a deposit in a block at `height` has at least `depth`
+/// confirmations, if the best chain tip is at `tip_height`
+fn is_confirmed(height: u32, depth: u32, tip_height: Option<u32>) -> bool {
+    tip_height
+        .is_some_and(|tip_height| u64::from(height) + u64::from(depth) <= u64::from(tip_height) + 1)
+}
+
+/// Blocks in the old and new best chains after their fork point
+struct Branches {
+    /// Height of the fork point, if the best chains have a common ancestor
+    fork_height: Option<u32>,
+    /// Blocks that are only in the old best chain, with their heights,
+    /// starting from the old tip
+    old: Vec<(u32, BlockHash)>,
+    /// Blocks that are only in the new best chain, with their heights,
+    /// starting from the new tip
+    new: Vec<(u32, BlockHash)>,
+}
+
+impl Branches {
+    /// Follow headers back from each tip to their fork point
+    fn new(
+        rotxn: &RoTxn,
+        dbs: &Dbs,
+        old_tip: Option<BlockHash>,
+        new_tip: Option<BlockHash>,
+    ) -> Result<Self, error::DepositConfirmations> {
+        let get_header_info =
+            |block_hash: BlockHash| dbs.block_hashes.get_header_info(rotxn, &block_hash);
+        let mut old = old_tip.map(get_header_info).transpose()?;
+        let mut new = new_tip.map(get_header_info).transpose()?;
+        let mut res = Self {
+            fork_height: None,
+            old: Vec::new(),
+            new: Vec::new(),
+        };
+        loop {
+            match (&old, &new) {
+                (Some(old), Some(new)) if old.block_hash == new.block_hash => break,
+                (None, None) => break,
+                _ => (),
+            }
+            let old_height = old.as_ref().map(|header_info| header_info.height);
+            let new_height = new.as_ref().map(|header_info| header_info.height);
+            // Step back from the higher tip, or from both tips if they are
+            // at the same height
+            for (current, branch, height, other_height) in [
+                (&mut old, &mut res.old, old_height, new_height),
+                (&mut new, &mut res.new, new_height, old_height),
+            ] {
+                if height < other_height {
+                    continue;
+                }
+                let Some(header_info) = current.take() else {
+                    continue;
+                };
+                branch.push((header_info.height, header_info.block_hash));
+                if header_info.height != 0 {
+                    *current = Some(get_header_info(header_info.prev_block_hash)?);
+                }
+            }
+        }
+        res.fork_height = old.map(|header_info| header_info.height);
+        Ok(res)
+    }
+
+    fn old_tip_height(&self) -> Option<u32> {
+        self.old
+            .first()
+            .map(|(height, _)| *height)
+            .or(self.fork_height)
+    }
+
+    fn new_tip_height(&self) -> Option<u32> {
+        self.new
+            .first()
+            .map(|(height, _)| *height)
+            .or(self.fork_height)
+    }
+}
+
+/// Deposits that gain or lose the configured number of confirmations for
+/// their sidechain when the best chain tip changes
+#[derive(Debug, Default)]
+pub(super) struct ConfirmationChanges {
+    /// Deposits that are no longer confirmed. Deposits in blocks that left
+    /// the best chain have zero confirmations left.
+    unconfirmed: Vec<DepositConfirmation>,
+    confirmed: Vec<DepositConfirmation>,
+}
+
+/// Deposits that gain or lose the configured number of confirmations for
+/// their sidechain if the best chain tip changes from `old_tip` to
+/// `new_tip`.
+/// Blocks up to the fork point are read from the best chain index, and
+/// blocks after the fork point are read by following headers back from
+/// each tip, so this works both before and after the best chain index is
+/// updated.
+/// Deposits in blocks for which block info has been pruned are skipped.
+fn confirmation_changes(
+    rotxn: &RoTxn,
+    dbs: &Dbs,
+    depths: &DepositConfirmationDepths,
+    old_tip: Option<BlockHash>,
+    new_tip: Option<BlockHash>,
+) -> Result<ConfirmationChanges, error::DepositConfirmations> {
+    let mut res = ConfirmationChanges::default();
+    let branches = Branches::new(rotxn, dbs, old_tip, new_tip)?;
+    let old_tip_height = branches.old_tip_height();
+    let new_tip_height = branches.new_tip_height();
+    let mut push_changes = |block_hash: BlockHash,
+                            height: u32,
+                            in_old_chain: bool,
+                            in_new_chain: bool|
+     -> Result<(), error::DepositConfirmations> {
+        let Some(block_info) = dbs.block_hashes.try_get_block_info(rotxn, &block_hash)? else {
+            return Ok(());
+        };
+        for deposit in block_info.deposits {
+            let Some(depth) = depths.get(deposit.sidechain_id) else {
+                continue;
+            };
+            let was_confirmed = in_old_chain && is_confirmed(height, depth, old_tip_height);
+            let now_confirmed = in_new_chain && is_confirmed(height, depth, new_tip_height);
+            let confirmations = match new_tip_height {
+                Some(new_tip_height) if in_new_chain => new_tip_height + 1 - height,
+                _ => 0,
+            };
+            let confirmation = DepositConfirmation {
+                deposit,
+                deposit_block_hash: block_hash,
+                deposit_block_height: height,
+                confirmations,
+            };
+            match (was_confirmed, now_confirmed) {
+                (true, false) => res.unconfirmed.push(confirmation),
+                (false, true) => res.confirmed.push(confirmation),
+                (true, true) | (false, false) => (),
+            }
+        }
+        Ok(())
+    };
+    for (height, block_hash) in &branches.old {
+        let () = push_changes(*block_hash, *height, true, false)?;
+    }
+    // Blocks up to the fork point are in both best chains, and only change
+    // for the configured depths between the old and new tip heights
+    if let (Some(fork_height), Some(old_tip_height), Some(new_tip_height)) =
+        (branches.fork_height, old_tip_height, new_tip_height)
+    {
+        let min_tip_height = old_tip_height.min(new_tip_height);
+        let max_tip_height = old_tip_height.max(new_tip_height);
+        let mut heights = BTreeSet::new();
+        for depth in depths.depths() {
+            let Some(end) = (max_tip_height + 1).checked_sub(depth) else {
+                continue;
+            };
+            let start = (min_tip_height + 2).saturating_sub(depth);
+            heights.extend(start..=end.min(fork_height));
+        }
+        for height in heights {
+            let Some(block_hash) = dbs
+                .best_chain_height_to_block_hash
+                .try_get(rotxn, &height)?
+            else {
+                continue;
+            };
+            let () = push_changes(block_hash, height, true, true)?;
+        }
+    }
+    for (height, block_hash) in branches.new.iter().rev() {
+        let () = push_changes(*block_hash, *height, false, true)?;
+    }
+    Ok(res)
+}
+
+/// Append `DepositUnconfirmed` and `DepositConfirmed` events for a change of
+/// best chain tip, caused by connecting or disconnecting `block_hash`
+fn append_events(
+    rwtxn: &mut RwTxn,
+    dbs: &Dbs,
+    events: &mut Vec<(u64, Event)>,
+    block_hash: BlockHash,
+    changes: ConfirmationChanges,
+) -> Result<(), error::DepositConfirmations> {
+    for confirmation in changes.unconfirmed {
+        tracing::warn!(
+            sidechain_slot = confirmation.deposit.sidechain_id.0,
+            "Deposit `{}` unconfirmed by tip change at `{block_hash}`, {} confirmations left",
+            confirmation.deposit.outpoint,
+            confirmation.confirmations
+        );
+        let event = Event::DepositUnconfirmed {
+            block_hash,
+            confirmation,
+        };
+        let sequence = dbs.append_event(rwtxn, &event)?;
+        events.push((sequence, event));
+    }
+    for confirmation in changes.confirmed {
+        tracing::debug!(
+            sidechain_slot = confirmation.deposit.sidechain_id.0,
+            "Deposit `{}` confirmed with {} confirmations",
+            confirmation.deposit.outpoint,
+            confirmation.confirmations
+        );
+        let event = Event::DepositConfirmed {
+            block_hash,
+            confirmation,
+        };
+        let sequence = dbs.append_event(rwtxn, &event)?;
+        events.push((sequence, event));
+    }
+    Ok(())
+}
+
+/// Append events for deposits that were confirmed or unconfirmed by
+/// connecting a block, including by a reorg to a branch with more work.
+/// `previous_tip` is the best chain tip from before the block was
+/// connected. Does nothing if the block did not become the tip.
+pub(super) fn append_connect_events(
+    rwtxn: &mut RwTxn,
+    dbs: &Dbs,
+    depths: &DepositConfirmationDepths,
+    events: &mut Vec<(u64, Event)>,
+    block_hash: BlockHash,
+    previous_tip: Option<BlockHash>,
+) -> Result<(), error::DepositConfirmations> {
+    if depths.is_empty() || dbs.current_chain_tip.try_get(rwtxn, &UnitKey)? != Some(block_hash) {
+        return Ok(());
+    }
+    let changes = confirmation_changes(rwtxn, dbs, depths, previous_tip, Some(block_hash))?;
+    append_events(rwtxn, dbs, events, block_hash, changes)
+}
+
+/// Deposits that are confirmed or unconfirmed if the tip is disconnected,
+/// and the tip from before it was connected is restored.
+/// MUST be called before disconnecting the tip.
+pub(super) fn disconnect_changes(
+    rotxn: &RoTxn,
+    dbs: &Dbs,
+    depths: &DepositConfirmationDepths,
+    block_hash: BlockHash,
+) -> Result<ConfirmationChanges, error::DepositConfirmations> {
+    if depths.is_empty() || dbs.current_chain_tip.try_get(rotxn, &UnitKey)? != Some(block_hash) {
+        return Ok(ConfirmationChanges::default());
+    }
+    let previous_tip = dbs
+        .block_undo
+        .try_get(rotxn, &block_hash)?
+        .and_then(|undo| undo.previous_chain_tip);
+    confirmation_changes(rotxn, dbs, depths, Some(block_hash), previous_tip)
+}
+
+/// Append events for changes returned by [`disconnect_changes`], once the
+/// block has been disconnected
+pub(super) fn append_disconnect_events(
+    rwtxn: &mut RwTxn,
+    dbs: &Dbs,
+    events: &mut Vec<(u64, Event)>,
+    block_hash: BlockHash,
+    changes: ConfirmationChanges,
+) -> Result<(), error::DepositConfirmations> {
+    append_events(rwtxn, dbs, events, block_hash, changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::{DepositConfirmationDepth, DepositConfirmationDepths};
+    use crate::types::SidechainNumber;
+
+    #[test]
+    fn parse_depths() {
+        let depth: DepositConfirmationDepth = "6".parse().unwrap();
+        assert_eq!(depth.sidechain_number, None);
+        assert_eq!(depth.depth, NonZeroU32::new(6).unwrap());
+        let depth: DepositConfirmationDepth = "2:100".parse().unwrap();
+        assert_eq!(depth.sidechain_number, Some(SidechainNumber(2)));
+        assert_eq!(depth.depth, NonZeroU32::new(100).unwrap());
+        assert!("0".parse::<DepositConfirmationDepth>().is_err());
+        assert!("256:6".parse::<DepositConfirmationDepth>().is_err());
+        assert!("1:".parse::<DepositConfirmationDepth>().is_err());
+    }
+
+    #[test]
+    fn sidechain_depth_overrides_default() {
+        let depths = DepositConfirmationDepths::new(
+            ["3:10", "6"]
+                .into_iter()
+                .map(|depth| depth.parse().unwrap()),
+        );
+        assert_eq!(depths.get(SidechainNumber(3)), Some(10));
+        assert_eq!(depths.get(SidechainNumber(1)), Some(6));
+        assert_eq!(depths.depths().into_iter().collect::<Vec<_>>(), vec![6, 10]);
+        let depths = DepositConfirmationDepths::new(["3:10".parse().unwrap()]);
+        assert_eq!(depths.get(SidechainNumber(1)), None);
+        assert!(DepositConfirmationDepths::default().is_empty());
+    }
+}
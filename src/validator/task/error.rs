@@ -1,8 +1,11 @@
 use bip300301::jsonrpsee;
+use bitcoin::BlockHash;
 use fatality::fatality;
 use thiserror::Error;
 
 use crate::{
+    messages::M6LayoutError,
+    rpc_client::GetBlocksWithPrevoutsError,
     types::SidechainNumber,
     validator::dbs::{self, db_error},
 };
@@ -53,11 +56,25 @@ pub(in crate::validator::task) enum HandleM3ProposeBundle {
     #[error(transparent)]
     #[fatal]
     DbTryGet(#[from] db_error::TryGet),
+    #[error(
+        "Cannot propose bundle `{}`; already pending for sidechain slot {}",
+        hex::encode(.m6id),
+        .sidechain_number.0
+    )]
+    DuplicateM6id {
+        sidechain_number: SidechainNumber,
+        m6id: [u8; 32],
+    },
     #[error(
         "Cannot propose bundle; sidechain slot {} is inactive",
         .sidechain_number.0
     )]
     InactiveSidechain { sidechain_number: SidechainNumber },
+    #[error(
+        "Cannot propose bundle; sidechain slot {} has too many pending bundles",
+        .sidechain_number.0
+    )]
+    TooManyPendingBundles { sidechain_number: SidechainNumber },
 }
 
 #[fatality(splitable)]
@@ -72,6 +89,12 @@ pub(in crate::validator::task) enum HandleM4Votes {
 
 #[fatality(splitable)]
 pub(in crate::validator::task) enum HandleM4AckBundles {
+    #[error(transparent)]
+    #[fatal]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    #[fatal]
+    DbTryGet(#[from] db_error::TryGet),
     #[error("Error handling M4 Votes")]
     #[fatal(forward)]
     Votes(#[from] HandleM4Votes),
@@ -85,10 +108,26 @@ pub(in crate::validator::task) enum HandleFailedM6Ids {
     #[error(transparent)]
     #[fatal]
     DbPut(#[from] db_error::Put),
+    #[error(transparent)]
+    #[fatal]
+    DbTryGet(#[from] db_error::TryGet),
+}
+
+#[fatality(splitable)]
+pub(in crate::validator::task) enum PushWithdrawalBundleOutcome {
+    #[error(transparent)]
+    #[fatal]
+    DbPut(#[from] db_error::Put),
+    #[error(transparent)]
+    #[fatal]
+    DbTryGet(#[from] db_error::TryGet),
 }
 
 #[fatality(splitable)]
 pub(in crate::validator::task) enum HandleM5M6 {
+    #[error(transparent)]
+    #[fatal]
+    DbDelete(#[from] db_error::Delete),
     #[error(transparent)]
     #[fatal]
     DbPut(#[from] db_error::Put),
@@ -97,6 +136,8 @@ pub(in crate::validator::task) enum HandleM5M6 {
     DbTryGet(#[from] db_error::TryGet),
     #[error("Invalid M6")]
     InvalidM6,
+    #[error("Invalid M6 layout")]
+    InvalidM6Layout(#[from] M6LayoutError),
     #[error("Old Ctip for sidechain {} is unspent", .sidechain_number.0)]
     OldCtipUnspent { sidechain_number: SidechainNumber },
 }
@@ -109,8 +150,27 @@ pub(in crate::validator::task) enum HandleM8 {
     NotAcceptedByMiners,
 }
 
+#[fatality(splitable)]
+pub(in crate::validator::task) enum UpdateBestChain {
+    #[error(transparent)]
+    #[fatal]
+    DbDelete(#[from] db_error::Delete),
+    #[error(transparent)]
+    #[fatal]
+    DbPut(#[from] db_error::Put),
+    #[error(transparent)]
+    #[fatal]
+    DbTryGet(#[from] db_error::TryGet),
+    #[error(transparent)]
+    #[fatal]
+    GetHeaderInfo(#[from] dbs::block_hash_dbs_error::GetHeaderInfo),
+}
+
 #[fatality(splitable)]
 pub(in crate::validator::task) enum ConnectBlock {
+    #[error(transparent)]
+    #[fatal]
+    AppendEvent(#[from] dbs::AppendEventError),
     #[error(transparent)]
     #[fatal]
     PutBlockInfo(#[from] dbs::block_hash_dbs_error::PutBlockInfo),
@@ -125,6 +185,9 @@ pub(in crate::validator::task) enum ConnectBlock {
     DbGet(#[from] db_error::Get),
     #[error(transparent)]
     #[fatal]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    #[fatal]
     DbLen(#[from] db_error::Len),
     #[error(transparent)]
     #[fatal]
@@ -156,18 +219,80 @@ pub(in crate::validator::task) enum ConnectBlock {
     #[error("Error handling M8")]
     #[fatal(forward)]
     M8(#[from] HandleM8),
+    #[error("Error recording withdrawal bundle outcome")]
+    #[fatal(forward)]
+    PushWithdrawalBundleOutcome(#[from] PushWithdrawalBundleOutcome),
     #[error("Multiple blocks BMM'd in sidechain slot {}", .sidechain_number.0)]
     MultipleBmmBlocks { sidechain_number: SidechainNumber },
+    #[error("Error updating best chain")]
+    #[fatal(forward)]
+    UpdateBestChain(#[from] UpdateBestChain),
 }
 
 #[derive(Debug, Error)]
-pub(in crate::validator::task) enum DisconnectBlock {}
+pub(in crate::validator::task) enum DisconnectBlock {
+    #[error(transparent)]
+    AppendEvent(#[from] dbs::AppendEventError),
+    #[error(transparent)]
+    DbDelete(#[from] db_error::Delete),
+    #[error(transparent)]
+    DbGet(#[from] db_error::Get),
+    #[error(transparent)]
+    DbPut(#[from] db_error::Put),
+    #[error(transparent)]
+    DbTryGet(#[from] db_error::TryGet),
+    #[error("Missing undo data for block `{block_hash}`")]
+    MissingUndo { block_hash: BlockHash },
+    #[error("Cannot disconnect block `{block_hash}`, which is not the chain tip")]
+    NotChainTip { block_hash: BlockHash },
+    #[error(transparent)]
+    TryGetBlockInfo(#[from] dbs::block_hash_dbs_error::TryGetBlockInfo),
+    #[error("Error updating best chain")]
+    UpdateBestChain(#[from] UpdateBestChain),
+}
 
 #[derive(Debug, Error)]
 pub(in crate::validator::task) enum TxValidation {}
 
+#[fatality(splitable)]
+pub(in crate::validator::task) enum DepositConfirmations {
+    #[error(transparent)]
+    #[fatal]
+    AppendEvent(#[from] dbs::AppendEventError),
+    #[error(transparent)]
+    #[fatal]
+    DbTryGet(#[from] db_error::TryGet),
+    #[error(transparent)]
+    #[fatal]
+    GetHeaderInfo(#[from] dbs::block_hash_dbs_error::GetHeaderInfo),
+    #[error(transparent)]
+    #[fatal]
+    TryGetBlockInfo(#[from] dbs::block_hash_dbs_error::TryGetBlockInfo),
+}
+
+#[fatality(splitable)]
+pub(in crate::validator::task) enum PruneBlockInfo {
+    #[error(transparent)]
+    #[fatal]
+    DbDelete(#[from] db_error::Delete),
+    #[error(transparent)]
+    #[fatal]
+    DbGet(#[from] db_error::Get),
+    #[error(transparent)]
+    #[fatal]
+    DbTryGet(#[from] db_error::TryGet),
+}
+
 #[fatality(splitable)]
 pub(in crate::validator::task) enum Sync {
+    #[error(
+        "Failed to fetch blocks from `{first_block_hash}`, block data has been pruned by the node"
+    )]
+    #[fatal]
+    BlockDataPruned { first_block_hash: BlockHash },
+    #[error("Blocks from height {height} are required, but the node has pruned blocks below height {prune_height}. Restore the validator DBs from a snapshot synced past height {prune_height} with `snapshot import`, or resync the node without pruning")]
+    #[fatal]
+    BlocksPrunedBeforeSync { height: u32, prune_height: u32 },
     #[error(transparent)]
     #[fatal]
     CommitWriteTxn(#[from] dbs::CommitWriteTxnError),
@@ -183,24 +308,72 @@ pub(in crate::validator::task) enum Sync {
     #[error(transparent)]
     #[fatal]
     DbTryGet(#[from] db_error::TryGet),
+    #[error("Failed to decode hex response (`{method}`)")]
+    #[fatal]
+    DecodeHex {
+        method: String,
+        source: bitcoin::consensus::encode::FromHexError,
+    },
+    #[error("Error tracking deposit confirmations")]
+    #[fatal(forward)]
+    DepositConfirmations(#[from] DepositConfirmations),
+    #[error("Failed to fetch blocks with prevouts")]
+    #[fatal]
+    GetBlocksWithPrevouts(#[from] GetBlocksWithPrevoutsError),
     #[error("JSON RPC error (`{method}`)")]
     #[fatal]
     JsonRpc {
         method: String,
         source: jsonrpsee::core::ClientError,
     },
+    #[error("Error pruning block info")]
+    #[fatal(forward)]
+    PruneBlockInfo(#[from] PruneBlockInfo),
     #[error(transparent)]
     #[fatal]
     ReadTxn(#[from] dbs::ReadTxnError),
+    #[error("Error updating best chain")]
+    #[fatal(forward)]
+    UpdateBestChain(#[from] UpdateBestChain),
     #[error(transparent)]
     #[fatal]
     WriteTxn(#[from] dbs::WriteTxnError),
 }
 
+impl Sync {
+    /// Returns `true` for errors caused by the node being unreachable or
+    /// returning an unexpected response, which may resolve by retrying
+    pub(in crate::validator::task) fn is_recoverable(&self) -> bool {
+        match self {
+            Self::DecodeHex { .. } | Self::GetBlocksWithPrevouts(_) | Self::JsonRpc { .. } => true,
+            Self::BlockDataPruned { .. }
+            | Self::BlocksPrunedBeforeSync { .. }
+            | Self::CommitWriteTxn(_)
+            | Self::ConnectBlock(_)
+            | Self::DbGet(_)
+            | Self::DbPut(_)
+            | Self::DbTryGet(_)
+            | Self::DepositConfirmations(_)
+            | Self::PruneBlockInfo(_)
+            | Self::ReadTxn(_)
+            | Self::UpdateBestChain(_)
+            | Self::WriteTxn(_) => false,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub(in crate::validator::task) enum FatalInner {
+    #[error(transparent)]
+    CommitWriteTxn(#[from] dbs::CommitWriteTxnError),
+    #[error("Error tracking deposit confirmations")]
+    DepositConfirmations(#[from] DepositConfirmations),
     #[error(transparent)]
     DisconnectBlock(#[from] DisconnectBlock),
+    #[error("No reachable node to fail over to")]
+    NoHealthyNodes,
+    #[error("Sync failed")]
+    RecoverableSync(#[source] Sync),
     #[error(transparent)]
     Sync(#[from] <Sync as fatality::Split>::Fatal),
     #[error(transparent)]
@@ -215,6 +388,25 @@ pub(in crate::validator::task) enum FatalInner {
 #[error(transparent)]
 pub struct Fatal(FatalInner);
 
+impl Fatal {
+    /// Returns `true` if the sync task can be restarted after this error,
+    /// eg. if the node was unreachable, or ZMQ notifications were dropped.
+    /// DB errors are not recoverable.
+    pub(in crate::validator::task) fn is_recoverable(&self) -> bool {
+        match &self.0 {
+            FatalInner::NoHealthyNodes
+            | FatalInner::RecoverableSync(_)
+            | FatalInner::Zmq(_)
+            | FatalInner::ZmqSequenceStream(_) => true,
+            FatalInner::CommitWriteTxn(_)
+            | FatalInner::DepositConfirmations(_)
+            | FatalInner::DisconnectBlock(_)
+            | FatalInner::Sync(_)
+            | FatalInner::WriteTxn(_) => false,
+        }
+    }
+}
+
 impl<E> From<E> for Fatal
 where
     FatalInner: From<E>,
@@ -0,0 +1,382 @@
+//! Block fixtures, for regression tests against real blocks without a node.
+//!
+//! Fixtures are recorded while syncing, for blocks that change sidechain
+//! state or contain BIP300/301 transactions other than the coinbase. Blocks
+//! that only contain coinbase messages that change nothing, such as BMM
+//! commitments, are skipped. Each fixture contains the block, the sidechain
+//! state before the block was connected, and the changes made by connecting
+//! it. Replaying a fixture connects the block to the recorded state in
+//! scratch DBs, and compares the results with the recorded results.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::atomic::{self, AtomicU64},
+};
+
+use bitcoin::{
+    block::Header, consensus::encode, hashes::Hash as _, Block, BlockHash, OutPoint, TxOut, Work,
+};
+use fallible_iterator::FallibleIterator as _;
+use heed::RoTxn;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::connect_block;
+use crate::{
+    rpc_client::Prevouts,
+    types::BlockInfo,
+    validator::dbs::{self, db_error, Dbs, UnitKey},
+};
+
+/// A block, and the enforcer state before and after connecting it
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BlockFixture {
+    pub block_hash: BlockHash,
+    pub height: u32,
+    /// Consensus-encoded block, as hex
+    pub block: String,
+    /// Outputs spent by the block's transactions, if they were available
+    /// when the block was connected
+    pub prevouts: Option<Vec<(OutPoint, TxOut)>>,
+    /// Parent header and cumulative work. `None` for the genesis block.
+    pub parent: Option<(Header, Work)>,
+    /// Entries in each of [`Dbs::sidechain_state_dbs`] before the block was
+    /// connected, as hex
+    pub state_before: Vec<BTreeMap<String, String>>,
+    /// Entries in each of [`Dbs::sidechain_state_dbs`] that were changed by
+    /// connecting the block, with their new values as hex.
+    /// Values are `None` for deleted entries.
+    pub state_diff: Vec<BTreeMap<String, Option<String>>>,
+    pub block_info: BlockInfo,
+}
+
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error("Error creating fixtures directory `{path}`")]
+    CreateDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    DbGet(#[from] db_error::Get),
+    #[error(transparent)]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    GetBlockInfo(#[from] dbs::block_hash_dbs_error::GetBlockInfo),
+    #[error("Error writing fixture to `{path}`")]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Error serializing fixture to `{path}`")]
+    Serialize {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// Raw entries in each of [`Dbs::sidechain_state_dbs`]
+fn snapshot_sidechain_state(
+    rotxn: &RoTxn,
+    dbs: &Dbs,
+) -> Result<Vec<BTreeMap<Vec<u8>, Vec<u8>>>, db_error::Iter> {
+    let mut snapshot = Vec::new();
+    for db in dbs.sidechain_state_dbs() {
+        let entries: BTreeMap<_, _> = db
+            .iter(rotxn)?
+            .map(|(key, value)| Ok((key.to_vec(), value.to_vec())))
+            .collect()?;
+        snapshot.push(entries);
+    }
+    Ok(snapshot)
+}
+
+/// Record a fixture for the current chain tip, if it changed sidechain state
+/// or contains BIP300/301 transactions other than the coinbase.
+/// Must be called with committed state, after the block is connected and
+/// before any other block is connected.
+/// Fixtures are written to `<dir>/<height>-<block hash>.json`.
+/// Returns the path to the fixture, if one was written.
+pub(super) fn record(
+    rotxn: &RoTxn,
+    dbs: &Dbs,
+    dir: &Path,
+    block: &Block,
+    prevouts: Option<&Prevouts>,
+    height: u32,
+) -> Result<Option<PathBuf>, RecordError> {
+    let block_hash = block.block_hash();
+    let undo = dbs.block_undo.get(rotxn, &block_hash)?;
+    let coinbase_txid = block.txdata[0].compute_txid();
+    let has_transactions = undo
+        .bip300_transactions
+        .iter()
+        .any(|(txid, _)| *txid != coinbase_txid);
+    let changed_state = undo
+        .sidechain_state
+        .iter()
+        .any(|changes| !changes.is_empty());
+    if !has_transactions && !changed_state {
+        return Ok(None);
+    }
+    let state_after = snapshot_sidechain_state(rotxn, dbs)?;
+    let mut state_before = state_after.clone();
+    let mut state_diff = Vec::new();
+    for ((before, after), changes) in state_before
+        .iter_mut()
+        .zip(&state_after)
+        .zip(&undo.sidechain_state)
+    {
+        let mut diff = BTreeMap::new();
+        for (key, previous_value) in changes {
+            let _: Option<Vec<u8>> = match previous_value {
+                Some(previous_value) => before.insert(key.clone(), previous_value.clone()),
+                None => before.remove(key),
+            };
+            diff.insert(hex::encode(key), after.get(key).map(hex::encode));
+        }
+        state_diff.push(diff);
+    }
+    let parent = if block.header.prev_blockhash == BlockHash::all_zeros() {
+        None
+    } else {
+        let prev_block_hash = block.header.prev_blockhash;
+        let header = dbs.block_hashes.header().get(rotxn, &prev_block_hash)?;
+        let cumulative_work = dbs
+            .block_hashes
+            .cumulative_work()
+            .get(rotxn, &prev_block_hash)?;
+        Some((header, cumulative_work))
+    };
+    let fixture = BlockFixture {
+        block_hash,
+        height,
+        block: encode::serialize_hex(block),
+        prevouts: prevouts.map(|prevouts| {
+            prevouts
+                .iter()
+                .map(|(outpoint, txout)| (*outpoint, txout.clone()))
+                .collect()
+        }),
+        parent,
+        state_before: state_before
+            .into_iter()
+            .map(|entries| {
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (hex::encode(key), hex::encode(value)))
+                    .collect()
+            })
+            .collect(),
+        state_diff,
+        block_info: dbs.block_hashes.get_block_info(rotxn, &block_hash)?,
+    };
+    if let Err(err) = std::fs::create_dir_all(dir) {
+        return Err(RecordError::CreateDir {
+            path: dir.to_owned(),
+            source: err,
+        });
+    }
+    let path = dir.join(format!("{height}-{block_hash}.json"));
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(err) => return Err(RecordError::Write { path, source: err }),
+    };
+    if let Err(err) = serde_json::to_writer_pretty(BufWriter::new(file), &fixture) {
+        return Err(RecordError::Serialize { path, source: err });
+    }
+    Ok(Some(path))
+}
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("Error reading fixture `{path}`")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Error parsing fixture `{path}`")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// Load a fixture from a JSON file
+pub fn load(path: &Path) -> Result<BlockFixture, LoadError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(LoadError::Read {
+                path: path.to_owned(),
+                source: err,
+            })
+        }
+    };
+    serde_json::from_reader(BufReader::new(file)).map_err(|err| LoadError::Parse {
+        path: path.to_owned(),
+        source: err,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error(transparent)]
+    CreateDbs(#[from] dbs::CreateDbsError),
+    #[error("Error creating scratch directory")]
+    CreateScratchDir(#[source] std::io::Error),
+    #[error(transparent)]
+    DbGet(#[from] db_error::Get),
+    #[error(transparent)]
+    DbIter(#[from] db_error::Iter),
+    #[error(transparent)]
+    DbPut(#[from] db_error::Put),
+    #[error("Error decoding block")]
+    DecodeBlock(#[from] encode::FromHexError),
+    #[error("Error decoding state entry")]
+    DecodeState(#[from] hex::FromHexError),
+    #[error(transparent)]
+    GetBlockInfo(#[from] dbs::block_hash_dbs_error::GetBlockInfo),
+    #[error("Expected state for {expected} DBs, but fixture contains state for {found} DBs")]
+    StateDbCount { expected: usize, found: usize },
+    #[error("Error serializing block info")]
+    SerializeBlockInfo(#[from] serde_json::Error),
+    #[error(transparent)]
+    WriteTxn(#[from] dbs::WriteTxnError),
+}
+
+/// Directory for scratch DBs, which is removed when dropped
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new() -> std::io::Result<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "bip300301_enforcer-fixture-{}-{id}",
+            std::process::id()
+        ));
+        let () = std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.0) {
+            tracing::warn!(
+                "Failed to remove scratch directory {}: {err:#}",
+                self.0.display()
+            );
+        }
+    }
+}
+
+/// Connect the fixture's block to the recorded state, in scratch DBs.
+/// Returns a description of each difference from the recorded results.
+pub fn replay(fixture: &BlockFixture) -> Result<Vec<String>, ReplayError> {
+    let block: Block = encode::deserialize_hex(&fixture.block)?;
+    let scratch_dir = ScratchDir::new().map_err(ReplayError::CreateScratchDir)?;
+    let dbs = Dbs::new(&scratch_dir.0, bitcoin::Network::Regtest)?;
+    let mut rwtxn = dbs.write_txn()?;
+    let state_dbs = dbs.sidechain_state_dbs();
+    for found in [fixture.state_before.len(), fixture.state_diff.len()] {
+        if found != state_dbs.len() {
+            return Err(ReplayError::StateDbCount {
+                expected: state_dbs.len(),
+                found,
+            });
+        }
+    }
+    for (db, entries) in state_dbs.iter().zip(&fixture.state_before) {
+        for (key, value) in entries {
+            let () = db.put(&mut rwtxn, &hex::decode(key)?, &hex::decode(value)?)?;
+        }
+    }
+    if let Some((parent_header, parent_cumulative_work)) = &fixture.parent {
+        let parent_hash = parent_header.block_hash();
+        let parent_height = fixture.height.saturating_sub(1);
+        let () = dbs.block_hashes.put_header_with_cumulative_work(
+            &mut rwtxn,
+            parent_header,
+            parent_height,
+            parent_cumulative_work,
+        )?;
+        let () = dbs
+            .current_chain_tip
+            .put(&mut rwtxn, &UnitKey, &parent_hash)?;
+        let () =
+            dbs.best_chain_height_to_block_hash
+                .put(&mut rwtxn, &parent_height, &parent_hash)?;
+    }
+    let () = dbs
+        .block_hashes
+        .put_header(&mut rwtxn, &block.header, fixture.height)?;
+    let prevouts: Option<Prevouts> = fixture
+        .prevouts
+        .as_ref()
+        .map(|prevouts| prevouts.iter().cloned().collect());
+    let mut problems = Vec::new();
+    if let Err(err) = connect_block(
+        &mut rwtxn,
+        &dbs,
+        &mut Vec::new(),
+        &block,
+        prevouts.as_ref(),
+        fixture.height,
+    ) {
+        problems.push(format!(
+            "Failed to connect block: {:#}",
+            anyhow::Error::from(err)
+        ));
+        return Ok(problems);
+    }
+    let block_info = dbs
+        .block_hashes
+        .get_block_info(&rwtxn, &fixture.block_hash)?;
+    let block_info = serde_json::to_value(block_info)?;
+    let expected_block_info = serde_json::to_value(&fixture.block_info)?;
+    if block_info != expected_block_info {
+        problems.push(format!(
+            "Block info differs: expected `{expected_block_info}`, got `{block_info}`"
+        ));
+    }
+    let state_after = snapshot_sidechain_state(&rwtxn, &dbs)?;
+    for (((db, before), diff), after) in state_dbs
+        .iter()
+        .zip(&fixture.state_before)
+        .zip(&fixture.state_diff)
+        .zip(state_after)
+    {
+        let mut expected = before.clone();
+        for (key, value) in diff {
+            let _: Option<String> = match value {
+                Some(value) => expected.insert(key.clone(), value.clone()),
+                None => expected.remove(key),
+            };
+        }
+        let mut after: BTreeMap<String, String> = after
+            .into_iter()
+            .map(|(key, value)| (hex::encode(key), hex::encode(value)))
+            .collect();
+        for (key, expected_value) in expected {
+            match after.remove(&key) {
+                Some(value) if value == expected_value => (),
+                value => problems.push(format!(
+                    "`{}` entry `{key}` differs: expected `{expected_value}`, got {value:?}",
+                    db.name()
+                )),
+            }
+        }
+        for (key, value) in after {
+            problems.push(format!(
+                "`{}` entry `{key}` differs: expected none, got `{value}`",
+                db.name()
+            ));
+        }
+    }
+    Ok(problems)
+}
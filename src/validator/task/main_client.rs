@@ -0,0 +1,267 @@
+//! Requests to the mainchain node that are used to sync the validator.
+//!
+//! Sync is generic over [`MainchainClient`], so that tests can sync from a
+//! canned chain rather than a live node.
+
+use std::future::Future;
+
+use bip300301::{jsonrpsee::core::rpc_params, MainClient as _};
+use bitcoin::{block::Header, Block, BlockHash};
+use futures::TryFutureExt as _;
+
+use super::error;
+use crate::rpc_client::{is_block_pruned, GetBlocksWithPrevoutsError, Prevouts};
+
+/// Max number of headers to request from the node in a single batch
+const HEADERS_BATCH_SIZE: u32 = 200;
+
+/// Optional node features that are used to sync, if available
+#[derive(Clone, Copy, Debug, Default)]
+pub(in crate::validator::task) struct NodeCapabilities {
+    /// `getblock` with verbosity 3, which includes prevouts
+    pub getblock_prevouts: bool,
+}
+
+pub(in crate::validator::task) trait MainchainClient: Sync {
+    fn get_best_block_hash(&self) -> impl Future<Output = Result<BlockHash, error::Sync>> + Send;
+
+    /// Height of the first block that is available from the node, if it is
+    /// pruned
+    fn get_prune_height(&self) -> impl Future<Output = Result<Option<u32>, error::Sync>> + Send;
+
+    /// Header and height
+    fn get_header(
+        &self,
+        block_hash: BlockHash,
+    ) -> impl Future<Output = Result<(Header, u32), error::Sync>> + Send;
+
+    /// Ancestors of `header` at `height`, starting with the parent, by
+    /// height in the node's active chain.
+    /// May return any number of ancestors, including none. Ancestors that
+    /// are not returned are fetched one at a time with
+    /// [`Self::get_header`].
+    fn get_ancestor_headers(
+        &self,
+        header: &Header,
+        height: u32,
+    ) -> impl Future<Output = Result<Vec<(Header, u32)>, error::Sync>> + Send;
+
+    /// Headers following `locator_hashes`, up to `stop_hash`, over P2P.
+    /// Returns `None` if P2P is not configured, in which case headers are
+    /// synced with [`Self::get_header`].
+    fn get_headers_p2p(
+        &self,
+        _locator_hashes: Vec<BlockHash>,
+        _stop_hash: BlockHash,
+    ) -> impl Future<Output = Option<Result<Vec<Header>, anyhow::Error>>> + Send {
+        async { None }
+    }
+
+    /// If `capabilities.getblock_prevouts` is `true`, blocks should include
+    /// the outputs spent by their transactions, if possible.
+    fn get_blocks(
+        &self,
+        block_hashes: &[BlockHash],
+        capabilities: NodeCapabilities,
+    ) -> impl Future<Output = Result<Vec<(Block, Option<Prevouts>)>, error::Sync>> + Send;
+}
+
+impl MainchainClient for crate::rpc_client::Client {
+    async fn get_best_block_hash(&self) -> Result<BlockHash, error::Sync> {
+        self.getbestblockhash()
+            .map_err(|err| error::Sync::JsonRpc {
+                method: "getbestblockhash".to_owned(),
+                source: err,
+            })
+            .await
+    }
+
+    async fn get_prune_height(&self) -> Result<Option<u32>, error::Sync> {
+        self.prune_height()
+            .map_err(|err| error::Sync::JsonRpc {
+                method: "getblockchaininfo".to_owned(),
+                source: err,
+            })
+            .await
+    }
+
+    async fn get_header(&self, block_hash: BlockHash) -> Result<(Header, u32), error::Sync> {
+        let header = self
+            .getblockheader(block_hash)
+            .map_err(|err| error::Sync::JsonRpc {
+                method: "getblockheader".to_owned(),
+                source: err,
+            })
+            .await?;
+        let height = header.height;
+        Ok((header.into(), height))
+    }
+
+    /// Fetch up to [`HEADERS_BATCH_SIZE`] ancestors, using batch requests.
+    /// Stops at the first header that is not the parent of the previous
+    /// header, eg. if `header` is not in the node's active chain.
+    async fn get_ancestor_headers(
+        &self,
+        header: &Header,
+        height: u32,
+    ) -> Result<Vec<(Header, u32)>, error::Sync> {
+        let heights: Vec<u32> = (height.saturating_sub(HEADERS_BATCH_SIZE)..height)
+            .rev()
+            .collect();
+        let block_hashes: Vec<BlockHash> = self
+            .batch_call(
+                "getblockhash",
+                heights.iter().map(|height| rpc_params![height]),
+            )
+            .map_err(|err| error::Sync::JsonRpc {
+                method: "getblockhash".to_owned(),
+                source: err,
+            })
+            .await?;
+        let header_hexes: Vec<String> = self
+            .batch_call(
+                "getblockheader",
+                block_hashes
+                    .iter()
+                    .map(|block_hash| rpc_params![block_hash, false]),
+            )
+            .map_err(|err| error::Sync::JsonRpc {
+                method: "getblockheader".to_owned(),
+                source: err,
+            })
+            .await?;
+        let mut res = Vec::with_capacity(header_hexes.len());
+        let mut prev_blockhash = header.prev_blockhash;
+        for (height, header_hex) in heights.into_iter().zip(header_hexes) {
+            let header: Header =
+                bitcoin::consensus::encode::deserialize_hex(&header_hex).map_err(|err| {
+                    error::Sync::DecodeHex {
+                        method: "getblockheader".to_owned(),
+                        source: err,
+                    }
+                })?;
+            if header.block_hash() != prev_blockhash {
+                break;
+            }
+            prev_blockhash = header.prev_blockhash;
+            res.push((header, height));
+        }
+        Ok(res)
+    }
+
+    async fn get_headers_p2p(
+        &self,
+        locator_hashes: Vec<BlockHash>,
+        stop_hash: BlockHash,
+    ) -> Option<Result<Vec<Header>, anyhow::Error>> {
+        let res =
+            crate::rpc_client::Client::get_headers_p2p(self, locator_hashes, stop_hash).await?;
+        Some(res.map_err(anyhow::Error::from))
+    }
+
+    /// Blocks are always fetched in full. BIP158 block filters cannot be
+    /// used to skip blocks, since filters only match exact scripts, while
+    /// any output script that begins with `OP_DRIVECHAIN <slot> OP_TRUE` is
+    /// a treasury output.
+    async fn get_blocks(
+        &self,
+        block_hashes: &[BlockHash],
+        capabilities: NodeCapabilities,
+    ) -> Result<Vec<(Block, Option<Prevouts>)>, error::Sync> {
+        get_full_blocks(self, block_hashes, capabilities.getblock_prevouts).await
+    }
+}
+
+/// Fetch blocks over P2P or via REST if configured, otherwise with
+/// batched `getblock` requests. Falls back to `getblock` if P2P or REST
+/// requests fail.
+/// Single blocks are fetched as compact blocks over P2P, if a ZMQ
+/// `rawtx` subscription is configured.
+async fn get_full_blocks(
+    client: &crate::rpc_client::Client,
+    block_hashes: &[BlockHash],
+    getblock_prevouts: bool,
+) -> Result<Vec<(Block, Option<Prevouts>)>, error::Sync> {
+    fn without_prevouts(blocks: Vec<Block>) -> Vec<(Block, Option<Prevouts>)> {
+        blocks.into_iter().map(|block| (block, None)).collect()
+    }
+    // New tips are fetched as compact blocks, if possible
+    if let [block_hash] = block_hashes {
+        match client.get_block_compact_p2p(*block_hash).await {
+            Some(Ok(block)) => return Ok(vec![(block, None)]),
+            Some(Err(err)) => {
+                tracing::warn!(
+                    "Failed to fetch compact block `{block_hash}`: {:#}",
+                    anyhow::Error::from(err)
+                );
+            }
+            None => (),
+        }
+    }
+    match client.get_blocks_p2p(block_hashes).await {
+        Some(Ok(blocks)) => return Ok(without_prevouts(blocks)),
+        Some(Err(err)) => {
+            tracing::warn!(
+                "Failed to fetch blocks over P2P, falling back to `getblock`: {:#}",
+                anyhow::Error::from(err)
+            );
+        }
+        None => (),
+    }
+    match client.get_blocks_rest(block_hashes).await {
+        Some(Ok(blocks)) => return Ok(without_prevouts(blocks)),
+        Some(Err(err)) => {
+            tracing::warn!(
+                "Failed to fetch blocks via REST, falling back to `getblock`: {:#}",
+                anyhow::Error::from(err)
+            );
+        }
+        None => (),
+    }
+    let pruned_err = || error::Sync::BlockDataPruned {
+        first_block_hash: block_hashes[0],
+    };
+    if getblock_prevouts {
+        let blocks = client
+            .get_blocks_with_prevouts(block_hashes)
+            .await
+            .map_err(|err| match err {
+                GetBlocksWithPrevoutsError::JsonRpc(err) if is_block_pruned(&err) => pruned_err(),
+                err => err.into(),
+            })?
+            .into_iter()
+            .map(|(block, prevouts)| (block, Some(prevouts)))
+            .collect();
+        return Ok(blocks);
+    }
+    let block_hexes: Vec<String> = client
+        .batch_call(
+            "getblock",
+            block_hashes
+                .iter()
+                .map(|block_hash| rpc_params![block_hash, 0]),
+        )
+        .map_err(|err| {
+            if is_block_pruned(&err) {
+                pruned_err()
+            } else {
+                error::Sync::JsonRpc {
+                    method: "getblock".to_owned(),
+                    source: err,
+                }
+            }
+        })
+        .await?;
+    block_hexes
+        .iter()
+        .map(|block_hex| {
+            let block = bitcoin::consensus::encode::deserialize_hex(block_hex).map_err(|err| {
+                error::Sync::DecodeHex {
+                    method: "getblock".to_owned(),
+                    source: err,
+                }
+            })?;
+            Ok((block, None))
+        })
+        .collect()
+}
@@ -1,17 +1,18 @@
-use std::collections::HashSet;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use crate::{
     messages::{
-        m6_to_id, parse_coinbase_script, parse_m8_bmm_request, parse_op_drivechain,
-        CoinbaseMessage, M4AckBundles, ABSTAIN_TWO_BYTES, ALARM_TWO_BYTES,
+        leading_by_50_upvotes, m6_to_id, parse_coinbase_script, CoinbaseMessage, M4AckBundles,
+        ABSTAIN_ONE_BYTE, ABSTAIN_TWO_BYTES, ALARM_ONE_BYTE, ALARM_TWO_BYTES,
     },
     types::SidechainProposalStatus,
 };
 use async_broadcast::{Sender, TrySendError};
-use bip300301::{
-    client::{GetBlockClient, U8Witness},
-    jsonrpsee, MainClient,
-};
 use bitcoin::{
     self,
     hashes::{sha256d, Hash as _},
@@ -20,25 +21,46 @@ use bitcoin::{
 use either::Either;
 use fallible_iterator::FallibleIterator;
 use fatality::Split as _;
-use futures::{TryFutureExt as _, TryStreamExt as _};
+use futures::TryStreamExt as _;
 use hashlink::{LinkedHashMap, LinkedHashSet};
-use heed::RoTxn;
+use heed::{BytesEncode, RoTxn};
 
 use crate::{
+    rpc_client::Prevouts,
     types::{
-        BlockInfo, BmmCommitments, Ctip, Deposit, Event, HeaderInfo, PendingM6id, Sidechain,
-        SidechainNumber, SidechainProposal, TreasuryUtxo, WithdrawalBundleEvent,
-        WithdrawalBundleEventKind,
+        Bip300Transaction, Bip300TransactionKind, BlockInfo, BmmCommitments, Ctip, Deposit, Event,
+        HeaderInfo, PendingM6id, Sidechain, SidechainNumber, SidechainProposal, TreasuryUtxo,
+        WithdrawalBundleAlert, WithdrawalBundleEvent, WithdrawalBundleEventKind,
+        WithdrawalBundleFailureReason, WithdrawalBundleOutcome,
+    },
+    validator::{
+        dbs::{db_error, BlockUndo, Dbs, RawEntries, RoDatabase, RwTxn, UnitKey},
+        sync_progress::{SyncProgress, TaskErrorKind, TaskState},
     },
-    validator::dbs::{db_error, Dbs, RwTxn, UnitKey},
     zmq::SequenceMessage,
 };
 
+mod deposit_confirmations;
 mod error;
+pub mod fixtures;
+mod main_client;
+pub mod policy;
+mod scan;
+
+pub use deposit_confirmations::{DepositConfirmationDepth, DepositConfirmationDepths};
+use main_client::{MainchainClient, NodeCapabilities};
+use scan::ScannedTx;
 
-const WITHDRAWAL_BUNDLE_MAX_AGE: u16 = 10;
+/// Number of blocks after which a pending withdrawal bundle fails, if it
+/// has not been paid out
+pub const WITHDRAWAL_BUNDLE_MAX_AGE: u16 = 10;
 const WITHDRAWAL_BUNDLE_INCLUSION_THRESHOLD: u16 = WITHDRAWAL_BUNDLE_MAX_AGE / 2; // 5
 
+/// Max number of pending withdrawal bundles per sidechain slot.
+/// M4 votes refer to pending bundles by index, and the largest index that
+/// can be voted for with a two byte vote is one below [`ALARM_TWO_BYTES`].
+pub const MAX_PENDING_WITHDRAWAL_BUNDLES: usize = ALARM_TWO_BYTES as usize;
+
 const USED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE: u16 = WITHDRAWAL_BUNDLE_MAX_AGE; // 5
 const USED_SIDECHAIN_SLOT_ACTIVATION_THRESHOLD: u16 = USED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE / 2;
 
@@ -47,11 +69,38 @@ const UNUSED_SIDECHAIN_SLOT_ACTIVATION_MAX_FAILS: u16 = 5;
 const UNUSED_SIDECHAIN_SLOT_ACTIVATION_THRESHOLD: u16 =
     UNUSED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE - UNUSED_SIDECHAIN_SLOT_ACTIVATION_MAX_FAILS;
 
+/// Voting rules for a sidechain proposal, which depend on whether the
+/// proposal's slot is used by an active sidechain
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SidechainProposalRules {
+    /// Proposals fail once their age exceeds this number of blocks
+    pub max_age: u16,
+    /// Proposals are activated once their vote count exceeds this
+    pub activation_threshold: u16,
+}
+
+impl SidechainProposalRules {
+    pub const fn new(sidechain_slot_is_used: bool) -> Self {
+        if sidechain_slot_is_used {
+            Self {
+                max_age: USED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE,
+                activation_threshold: USED_SIDECHAIN_SLOT_ACTIVATION_THRESHOLD,
+            }
+        } else {
+            Self {
+                max_age: UNUSED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE,
+                activation_threshold: UNUSED_SIDECHAIN_SLOT_ACTIVATION_THRESHOLD,
+            }
+        }
+    }
+}
+
 /// Returns `Some` if the sidechain proposal does not already exist
 // See https://github.com/LayerTwo-Labs/bip300_bip301_specifications/blob/master/bip300.md#m1-1
 fn handle_m1_propose_sidechain(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
     proposal: SidechainProposal,
     proposal_height: u32,
 ) -> Result<Option<Sidechain>, error::HandleM1ProposeSidechain> {
@@ -80,6 +129,11 @@ fn handle_m1_propose_sidechain(
         },
     };
 
+    let () = sidechain_state_undo.record(
+        rwtxn,
+        &dbs.description_hash_to_sidechain,
+        &description_hash,
+    )?;
     let () = dbs
         .description_hash_to_sidechain
         .put(rwtxn, &description_hash, &sidechain)?;
@@ -92,6 +146,7 @@ fn handle_m1_propose_sidechain(
 fn handle_m2_ack_sidechain(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
     height: u32,
     sidechain_number: SidechainNumber,
     description_hash: &sha256d::Hash,
@@ -106,6 +161,8 @@ fn handle_m2_ack_sidechain(
         return Ok(());
     }
     sidechain.status.vote_count += 1;
+    let () =
+        sidechain_state_undo.record(rwtxn, &dbs.description_hash_to_sidechain, description_hash)?;
     dbs.description_hash_to_sidechain
         .put(rwtxn, description_hash, &sidechain)?;
 
@@ -117,23 +174,23 @@ fn handle_m2_ack_sidechain(
         .try_get(rwtxn, &sidechain_number)?
         .is_some();
 
-    let new_sidechain_activated = {
-        sidechain_slot_is_used
-            && sidechain.status.vote_count > USED_SIDECHAIN_SLOT_ACTIVATION_THRESHOLD
-            && sidechain_proposal_age <= USED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE as u32
-    } || {
-        !sidechain_slot_is_used
-            && sidechain.status.vote_count > UNUSED_SIDECHAIN_SLOT_ACTIVATION_THRESHOLD
-            && sidechain_proposal_age <= UNUSED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE as u32
-    };
+    let rules = SidechainProposalRules::new(sidechain_slot_is_used);
+    let new_sidechain_activated = sidechain.status.vote_count > rules.activation_threshold
+        && sidechain_proposal_age <= rules.max_age as u32;
 
     if new_sidechain_activated {
         tracing::info!(
+            sidechain_slot = sidechain_number.0,
             "sidechain {} in slot {} was activated",
             String::from_utf8_lossy(&sidechain.proposal.description.0),
             sidechain_number.0
         );
         sidechain.status.activation_height = Some(height);
+        let () = sidechain_state_undo.record(
+            rwtxn,
+            &dbs.active_sidechains.sidechain,
+            &sidechain_number,
+        )?;
         dbs.active_sidechains
             .sidechain
             .put(rwtxn, &sidechain_number, &sidechain)?;
@@ -146,6 +203,7 @@ fn handle_m2_ack_sidechain(
 fn handle_failed_sidechain_proposals(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
     height: u32,
 ) -> Result<(), error::HandleFailedSidechainProposals> {
     let failed_proposals: Vec<_> = dbs
@@ -162,10 +220,8 @@ fn handle_failed_sidechain_proposals(
                 .is_some();
             // FIXME: Do we need to check that the vote_count is below the threshold, or is it
             // enough to check that the max age was exceeded?
-            let failed = sidechain_slot_is_used
-                && sidechain_proposal_age > USED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE as u32
-                || !sidechain_slot_is_used
-                    && sidechain_proposal_age > UNUSED_SIDECHAIN_SLOT_PROPOSAL_MAX_AGE as u32;
+            let failed = sidechain_proposal_age
+                > SidechainProposalRules::new(sidechain_slot_is_used).max_age as u32;
             if failed {
                 Ok(Some(description_hash))
             } else {
@@ -174,6 +230,11 @@ fn handle_failed_sidechain_proposals(
         })
         .collect()?;
     for failed_description_hash in &failed_proposals {
+        let () = sidechain_state_undo.record(
+            rwtxn,
+            &dbs.description_hash_to_sidechain,
+            failed_description_hash,
+        )?;
         dbs.description_hash_to_sidechain
             .delete(rwtxn, failed_description_hash)?;
     }
@@ -183,8 +244,10 @@ fn handle_failed_sidechain_proposals(
 fn handle_m3_propose_bundle(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
     sidechain_number: SidechainNumber,
     m6id: [u8; 32],
+    height: u32,
 ) -> Result<(), error::HandleM3ProposeBundle> {
     if !dbs
         .active_sidechains
@@ -198,11 +261,31 @@ fn handle_m3_propose_bundle(
         .pending_m6ids
         .try_get(rwtxn, &sidechain_number)?;
     let mut pending_m6ids = pending_m6ids.unwrap_or_default();
+    // Votes in M4s refer to pending M6IDs by index, so a duplicate would
+    // shift the indices of subsequent proposals
+    if pending_m6ids
+        .iter()
+        .any(|pending_m6id| pending_m6id.m6id == m6id)
+    {
+        return Err(error::HandleM3ProposeBundle::DuplicateM6id {
+            sidechain_number,
+            m6id,
+        });
+    }
+    if pending_m6ids.len() >= MAX_PENDING_WITHDRAWAL_BUNDLES {
+        return Err(error::HandleM3ProposeBundle::TooManyPendingBundles { sidechain_number });
+    }
     let pending_m6id = PendingM6id {
         m6id,
         vote_count: 0,
+        proposal_height: height,
     };
     pending_m6ids.push(pending_m6id);
+    let () = sidechain_state_undo.record(
+        rwtxn,
+        &dbs.active_sidechains.pending_m6ids,
+        &sidechain_number,
+    )?;
     let () = dbs
         .active_sidechains
         .pending_m6ids
@@ -210,11 +293,14 @@ fn handle_m3_propose_bundle(
     Ok(())
 }
 
+/// Returns alerts for bundles that were downvoted by alarm votes
 fn handle_m4_votes(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
     upvotes: &[u16],
-) -> Result<(), error::HandleM4Votes> {
+) -> Result<Vec<WithdrawalBundleAlert>, error::HandleM4Votes> {
+    let mut alerts = Vec::new();
     for (sidechain_number, vote) in upvotes.iter().enumerate() {
         let sidechain_number = (sidechain_number as u8).into();
         let vote = *vote;
@@ -229,48 +315,103 @@ fn handle_m4_votes(
             continue;
         };
         if vote == ALARM_TWO_BYTES {
-            for pending_m6id in &mut pending_m6ids {
+            let leading_index = pending_m6ids
+                .iter()
+                .enumerate()
+                .filter(|(_, pending_m6id)| pending_m6id.vote_count > 0)
+                .max_by_key(|(index, pending_m6id)| (pending_m6id.vote_count, Reverse(*index)))
+                .map(|(index, _)| index);
+            for (index, pending_m6id) in pending_m6ids.iter_mut().enumerate() {
+                let previous_vote_count = pending_m6id.vote_count;
                 if pending_m6id.vote_count > 0 {
                     pending_m6id.vote_count -= 1;
                 }
+                tracing::warn!(
+                    sidechain_slot = sidechain_number.0,
+                    "Alarm vote for bundle {} (votes: {previous_vote_count} -> {})",
+                    hex::encode(pending_m6id.m6id),
+                    pending_m6id.vote_count
+                );
+                alerts.push(WithdrawalBundleAlert {
+                    sidechain_id: sidechain_number,
+                    m6id: pending_m6id.m6id,
+                    previous_vote_count,
+                    vote_count: pending_m6id.vote_count,
+                    leading: leading_index == Some(index),
+                });
             }
         } else if let Some(pending_m6id) = pending_m6ids.get_mut(vote as usize) {
             pending_m6id.vote_count += 1;
         }
+        let () = sidechain_state_undo.record(
+            rwtxn,
+            &dbs.active_sidechains.pending_m6ids,
+            &sidechain_number,
+        )?;
         let () =
             dbs.active_sidechains
                 .pending_m6ids
                 .put(rwtxn, &sidechain_number, &pending_m6ids)?;
     }
-    Ok(())
+    let () = sidechain_state_undo.record(rwtxn, &dbs.previous_votes, &UnitKey)?;
+    let () = dbs.previous_votes.put(rwtxn, &UnitKey, &upvotes.to_vec())?;
+    Ok(alerts)
 }
 
+/// Returns alerts for bundles that were downvoted by alarm votes
 fn handle_m4_ack_bundles(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
     m4: &M4AckBundles,
-) -> Result<(), error::HandleM4AckBundles> {
+) -> Result<Vec<WithdrawalBundleAlert>, error::HandleM4AckBundles> {
     match m4 {
         M4AckBundles::LeadingBy50 => {
-            todo!();
+            let pending_m6ids: BTreeMap<_, _> = dbs
+                .active_sidechains
+                .pending_m6ids
+                .iter(rwtxn)
+                .map_err(db_error::Iter::from)?
+                .map_err(db_error::Iter::from)
+                .collect()?;
+            let upvotes = leading_by_50_upvotes(&pending_m6ids);
+            handle_m4_votes(rwtxn, dbs, sidechain_state_undo, &upvotes)
+                .map_err(error::HandleM4AckBundles::from)
         }
         M4AckBundles::RepeatPrevious => {
-            todo!();
+            let upvotes = dbs
+                .previous_votes
+                .try_get(rwtxn, &UnitKey)?
+                .unwrap_or_default();
+            handle_m4_votes(rwtxn, dbs, sidechain_state_undo, &upvotes)
+                .map_err(error::HandleM4AckBundles::from)
         }
         M4AckBundles::OneByte { upvotes } => {
-            let upvotes: Vec<u16> = upvotes.iter().map(|vote| *vote as u16).collect();
-            handle_m4_votes(rwtxn, dbs, &upvotes).map_err(error::HandleM4AckBundles::from)
+            let upvotes: Vec<u16> = upvotes
+                .iter()
+                .map(|vote| match *vote {
+                    ABSTAIN_ONE_BYTE => ABSTAIN_TWO_BYTES,
+                    ALARM_ONE_BYTE => ALARM_TWO_BYTES,
+                    vote => vote as u16,
+                })
+                .collect();
+            handle_m4_votes(rwtxn, dbs, sidechain_state_undo, &upvotes)
+                .map_err(error::HandleM4AckBundles::from)
         }
         M4AckBundles::TwoBytes { upvotes } => {
-            handle_m4_votes(rwtxn, dbs, upvotes).map_err(error::HandleM4AckBundles::from)
+            handle_m4_votes(rwtxn, dbs, sidechain_state_undo, upvotes)
+                .map_err(error::HandleM4AckBundles::from)
         }
     }
 }
 
-/// Returns failed M6IDs with sidechain numbers
+/// Returns failed M6IDs with sidechain numbers.
+/// A bundle fails once its age exceeds [`WITHDRAWAL_BUNDLE_MAX_AGE`].
 fn handle_failed_m6ids(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
+    height: u32,
 ) -> Result<LinkedHashSet<(SidechainNumber, [u8; 32])>, error::HandleFailedM6Ids> {
     let mut failed_m6ids = LinkedHashSet::new();
     let mut updated_slots = LinkedHashMap::new();
@@ -282,7 +423,8 @@ fn handle_failed_m6ids(
         .map_err(db_error::Iter::from)
         .for_each(|(sidechain_number, pending_m6ids)| {
             for pending_m6id in &pending_m6ids {
-                if pending_m6id.vote_count > WITHDRAWAL_BUNDLE_MAX_AGE {
+                let age = height.saturating_sub(pending_m6id.proposal_height);
+                if age > WITHDRAWAL_BUNDLE_MAX_AGE as u32 {
                     failed_m6ids.insert((sidechain_number, pending_m6id.m6id));
                 }
             }
@@ -296,6 +438,11 @@ fn handle_failed_m6ids(
             Ok(())
         })?;
     for (sidechain_number, pending_m6ids) in updated_slots {
+        let () = sidechain_state_undo.record(
+            rwtxn,
+            &dbs.active_sidechains.pending_m6ids,
+            &sidechain_number,
+        )?;
         let () =
             dbs.active_sidechains
                 .pending_m6ids
@@ -304,6 +451,34 @@ fn handle_failed_m6ids(
     Ok(failed_m6ids)
 }
 
+/// Record the outcome of a withdrawal bundle that is no longer pending
+fn push_withdrawal_bundle_outcome(
+    rwtxn: &mut RwTxn,
+    dbs: &Dbs,
+    sidechain_number: SidechainNumber,
+    m6id: [u8; 32],
+    outcome: WithdrawalBundleOutcome,
+) -> Result<(), error::PushWithdrawalBundleOutcome> {
+    let key = (sidechain_number, m6id);
+    let mut outcomes = dbs
+        .withdrawal_bundle_outcomes
+        .try_get(rwtxn, &key)?
+        .unwrap_or_default();
+    let mut stats = dbs
+        .active_sidechains
+        .stats
+        .try_get(rwtxn, &sidechain_number)?
+        .unwrap_or_default();
+    stats.add_withdrawal_bundle_outcome(&outcome);
+    let () = dbs
+        .active_sidechains
+        .stats
+        .put(rwtxn, &sidechain_number, &stats)?;
+    outcomes.push(outcome);
+    let () = dbs.withdrawal_bundle_outcomes.put(rwtxn, &key, &outcomes)?;
+    Ok(())
+}
+
 /// Deposit or (sidechain_id, m6id)
 type DepositOrSuccessfulWithdrawal = Either<Deposit, (SidechainNumber, [u8; 32])>;
 
@@ -311,12 +486,13 @@ type DepositOrSuccessfulWithdrawal = Either<Deposit, (SidechainNumber, [u8; 32])
 fn handle_m6(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
+    sidechain_state_undo: &mut SidechainStateUndo,
     transaction: &Transaction,
     sidechain_number: SidechainNumber,
     old_total_value: Amount,
 ) -> Result<Option<[u8; 32]>, error::HandleM5M6> {
     let mut m6_valid = false;
-    let m6id = m6_to_id(transaction, old_total_value.to_sat());
+    let m6id = m6_to_id(transaction, old_total_value.to_sat())?;
     if let Some(pending_m6ids) = dbs
         .active_sidechains
         .pending_m6ids
@@ -334,6 +510,11 @@ fn handle_m6(
                 .into_iter()
                 .filter(|pending_m6id| pending_m6id.m6id != m6id)
                 .collect();
+            let () = sidechain_state_undo.record(
+                rwtxn,
+                &dbs.active_sidechains.pending_m6ids,
+                &sidechain_number,
+            )?;
             dbs.active_sidechains
                 .pending_m6ids
                 .put(rwtxn, &sidechain_number, &pending_m6ids)?;
@@ -346,44 +527,91 @@ fn handle_m6(
     }
 }
 
+/// Treasury outputs spent by the transaction are detected from
+/// `scanned.treasury_spends`, if prevouts were available when scanning, and
+/// from the treasury output index, which spent outputs are removed from.
+/// These are used to detect spends of treasury outputs that are not CTIPs.
 fn handle_m5_m6(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
-    transaction: &Transaction,
+    sidechain_state_undo: &mut SidechainStateUndo,
+    scanned: &ScannedTx,
 ) -> Result<Option<DepositOrSuccessfulWithdrawal>, error::HandleM5M6> {
-    let txid = transaction.compute_txid();
+    let transaction = scanned.transaction;
+    let txid = scanned.txid;
+    let mut spent_ctips = LinkedHashMap::new();
+    // Treasury outputs are detected from the treasury output index, so that
+    // detection does not depend on whether prevouts are available.
+    // Prevouts, if available, also detect spends of OP_DRIVECHAIN outputs
+    // that were never indexed.
+    let mut treasury_spends: LinkedHashMap<OutPoint, SidechainNumber> =
+        scanned.treasury_spends.iter().copied().collect();
+    for input in &transaction.input {
+        if let Some(sidechain_number) = dbs
+            .ctip_outpoint_to_sidechain
+            .try_get(rwtxn, &input.previous_output)?
+        {
+            spent_ctips.insert(sidechain_number, input.previous_output);
+        }
+        if let Some(sidechain_number) = dbs
+            .treasury_outpoint_to_sidechain
+            .try_get(rwtxn, &input.previous_output)?
+        {
+            treasury_spends.insert(input.previous_output, sidechain_number);
+            let () = sidechain_state_undo.record(
+                rwtxn,
+                &dbs.treasury_outpoint_to_sidechain,
+                &input.previous_output,
+            )?;
+            let _: bool = dbs
+                .treasury_outpoint_to_sidechain
+                .delete(rwtxn, &input.previous_output)?;
+        }
+    }
+    if let Some(sidechain_number) = scanned.drivechain_output {
+        let treasury_outpoint = OutPoint { txid, vout: 0 };
+        let () = sidechain_state_undo.record(
+            rwtxn,
+            &dbs.treasury_outpoint_to_sidechain,
+            &treasury_outpoint,
+        )?;
+        let () =
+            dbs.treasury_outpoint_to_sidechain
+                .put(rwtxn, &treasury_outpoint, &sidechain_number)?;
+    }
+    for (outpoint, sidechain_number) in &treasury_spends {
+        if spent_ctips.get(sidechain_number) != Some(outpoint) {
+            tracing::warn!(
+                sidechain_slot = sidechain_number.0,
+                "Tx `{txid}` spends treasury output `{outpoint}` for sidechain {sidechain_number}, which is not the CTIP"
+            );
+        }
+    }
     // TODO: Check that there is only one OP_DRIVECHAIN per sidechain slot.
     let (sidechain_number, new_ctip, new_total_value) = {
-        let output = &transaction.output[0];
-        // If OP_DRIVECHAIN script is invalid,
-        // for example if it is missing OP_TRUE at the end,
-        // it will just be ignored.
-        if let Ok((_input, sidechain_number)) =
-            parse_op_drivechain(&output.script_pubkey.to_bytes())
-        {
+        if let Some(sidechain_number) = scanned.drivechain_output {
             let new_ctip = OutPoint { txid, vout: 0 };
-            let new_total_value = output.value;
+            let new_total_value = transaction.output[0].value;
 
             (sidechain_number, new_ctip, new_total_value)
         } else {
+            for (sidechain_number, ctip_outpoint) in spent_ctips {
+                tracing::warn!(
+                    sidechain_slot = sidechain_number.0,
+                    "CTIP `{ctip_outpoint}` for sidechain {sidechain_number} spent by tx `{txid}` without an OP_DRIVECHAIN output"
+                );
+            }
             return Ok(None);
         }
     };
-    let address = {
-        let spk = &transaction.output[1].script_pubkey;
-        crate::messages::try_parse_op_return_address(spk)
-    };
+    let address = crate::deposit_address::parse_address(&transaction.output);
+    let old_ctip = dbs
+        .active_sidechains
+        .ctip
+        .try_get(rwtxn, &sidechain_number)?;
     let old_total_value = {
-        if let Some(old_ctip) = dbs
-            .active_sidechains
-            .ctip
-            .try_get(rwtxn, &sidechain_number)?
-        {
-            let old_ctip_found = transaction
-                .input
-                .iter()
-                .any(|input| input.previous_output == old_ctip.outpoint);
-            if !old_ctip_found {
+        if let Some(old_ctip) = &old_ctip {
+            if !spent_ctips.contains_key(&sidechain_number) {
                 return Err(error::HandleM5M6::OldCtipUnspent { sidechain_number });
             }
             old_ctip.value
@@ -408,7 +636,14 @@ fn handle_m5_m6(
     let sequence_number = treasury_utxo_count;
     // M6
     let res = if new_total_value < old_total_value {
-        if let Some(m6id) = handle_m6(rwtxn, dbs, transaction, sidechain_number, old_total_value)? {
+        if let Some(m6id) = handle_m6(
+            rwtxn,
+            dbs,
+            sidechain_state_undo,
+            transaction,
+            sidechain_number,
+            old_total_value,
+        )? {
             Either::Right((sidechain_number, m6id))
         } else {
             return Ok(None);
@@ -425,21 +660,48 @@ fn handle_m5_m6(
     } else {
         return Ok(None);
     };
+    let mut stats = dbs
+        .active_sidechains
+        .stats
+        .try_get(rwtxn, &sidechain_number)?
+        .unwrap_or_default();
+    stats.add_treasury_utxo(&treasury_utxo);
+    dbs.active_sidechains
+        .stats
+        .put(rwtxn, &sidechain_number, &stats)?;
     dbs.active_sidechains.slot_sequence_to_treasury_utxo.put(
         rwtxn,
         &(sidechain_number, sequence_number),
         &treasury_utxo,
     )?;
     let new_treasury_utxo_count = treasury_utxo_count + 1;
+    let () = sidechain_state_undo.record(
+        rwtxn,
+        &dbs.active_sidechains.treasury_utxo_count,
+        &sidechain_number,
+    )?;
     dbs.active_sidechains.treasury_utxo_count.put(
         rwtxn,
         &sidechain_number,
         &new_treasury_utxo_count,
     )?;
+    if let Some(old_ctip) = old_ctip {
+        let () = sidechain_state_undo.record(
+            rwtxn,
+            &dbs.ctip_outpoint_to_sidechain,
+            &old_ctip.outpoint,
+        )?;
+        dbs.ctip_outpoint_to_sidechain
+            .delete(rwtxn, &old_ctip.outpoint)?;
+    }
+    let () = sidechain_state_undo.record(rwtxn, &dbs.ctip_outpoint_to_sidechain, &new_ctip)?;
+    dbs.ctip_outpoint_to_sidechain
+        .put(rwtxn, &new_ctip, &sidechain_number)?;
     let new_ctip = Ctip {
         outpoint: new_ctip,
         value: new_total_value,
     };
+    let () = sidechain_state_undo.record(rwtxn, &dbs.active_sidechains.ctip, &sidechain_number)?;
     dbs.active_sidechains
         .ctip
         .put(rwtxn, &sidechain_number, &new_ctip)?;
@@ -450,14 +712,11 @@ fn handle_m5_m6(
 /// Returns `true` if this is a valid BMM request, `HandleM8Error::Jfyi` if
 /// this is an invalid BMM request, and `false` if this is not a BMM request.
 fn handle_m8(
-    transaction: &Transaction,
+    scanned: &ScannedTx,
     accepted_bmm_requests: &BmmCommitments,
     prev_mainchain_block_hash: &BlockHash,
 ) -> Result<bool, error::HandleM8> {
-    let output = &transaction.output[0];
-    let script = output.script_pubkey.to_bytes();
-
-    if let Ok((_input, bmm_request)) = parse_m8_bmm_request(&script) {
+    if let Some(bmm_request) = &scanned.bmm_request {
         if !accepted_bmm_requests
             .get(&bmm_request.sidechain_number)
             .is_some_and(|commitment| *commitment == bmm_request.sidechain_block_hash)
@@ -474,19 +733,120 @@ fn handle_m8(
     }
 }
 
+/// Update the best chain index for a new chain tip, rewriting entries back
+/// to the fork point with the previous best chain
+fn update_best_chain(
+    rwtxn: &mut RwTxn,
+    dbs: &Dbs,
+    tip: BlockHash,
+    tip_height: u32,
+) -> Result<(), error::UpdateBestChain> {
+    // Remove entries above the new tip
+    let mut height = tip_height + 1;
+    while dbs.best_chain_height_to_block_hash.delete(rwtxn, &height)? {
+        height += 1;
+    }
+    let mut block_hash = tip;
+    let mut height = tip_height;
+    while dbs
+        .best_chain_height_to_block_hash
+        .try_get(rwtxn, &height)?
+        != Some(block_hash)
+    {
+        let () = dbs
+            .best_chain_height_to_block_hash
+            .put(rwtxn, &height, &block_hash)?;
+        if height == 0 {
+            break;
+        }
+        block_hash = dbs
+            .block_hashes
+            .get_header_info(rwtxn, &block_hash)?
+            .prev_block_hash;
+        height -= 1;
+    }
+    Ok(())
+}
+
+/// Previous entries in [`Dbs::sidechain_state_dbs`], recorded before each
+/// write while connecting a block
+#[derive(Default)]
+struct SidechainStateUndo(HashMap<&'static str, BTreeMap<Vec<u8>, Option<Vec<u8>>>>);
+
+impl SidechainStateUndo {
+    /// Record the value for a key before it is written, unless a previous
+    /// value was already recorded for the key
+    fn record<'a, KC, DC>(
+        &mut self,
+        rotxn: &RoTxn,
+        db: &RoDatabase<KC, DC>,
+        key: &'a KC::EItem,
+    ) -> Result<(), db_error::TryGet>
+    where
+        KC: BytesEncode<'a>,
+    {
+        let (key_bytes, value) = db.try_get_raw(rotxn, key)?;
+        let _: &mut Option<Vec<u8>> = self
+            .0
+            .entry(db.name())
+            .or_default()
+            .entry(key_bytes)
+            .or_insert_with(|| value.map(<[u8]>::to_vec));
+        Ok(())
+    }
+
+    /// Recorded entries for each of [`Dbs::sidechain_state_dbs`], excluding
+    /// entries that were written back to their previous values
+    fn into_entries(
+        mut self,
+        rotxn: &RoTxn,
+        dbs: &Dbs,
+    ) -> Result<Vec<RawEntries>, db_error::TryGet> {
+        let mut res = Vec::new();
+        for db in dbs.sidechain_state_dbs() {
+            let mut entries = RawEntries::new();
+            for (key, previous_value) in self.0.remove(db.name()).unwrap_or_default() {
+                if db.try_get(rotxn, &key)? != previous_value.as_deref() {
+                    entries.push((key, previous_value));
+                }
+            }
+            res.push(entries);
+        }
+        debug_assert!(
+            self.0.is_empty(),
+            "recorded writes to DBs that are not sidechain state DBs: {:?}",
+            self.0.keys()
+        );
+        Ok(res)
+    }
+}
+
+#[tracing::instrument(skip_all, fields(block_hash = %block.block_hash(), height))]
 fn connect_block(
     rwtxn: &mut RwTxn,
     dbs: &Dbs,
-    event_tx: &Sender<Event>,
+    events: &mut Vec<(u64, Event)>,
     block: &Block,
+    prevouts: Option<&Prevouts>,
     height: u32,
 ) -> Result<(), error::ConnectBlock> {
     // TODO: Check that there are no duplicate M2s.
+    let mut sidechain_state_undo = SidechainStateUndo::default();
+    let treasury_utxo_counts: BTreeMap<_, _> = dbs
+        .active_sidechains
+        .treasury_utxo_count
+        .iter(rwtxn)
+        .map_err(db_error::Iter::from)?
+        .map_err(db_error::Iter::from)
+        .collect()?;
     let coinbase = &block.txdata[0];
     let mut bmmed_sidechain_slots = HashSet::new();
     let mut accepted_bmm_requests = BmmCommitments::new();
     let mut sidechain_proposals = Vec::new();
     let mut withdrawal_bundle_events = Vec::new();
+    let mut withdrawal_bundle_alerts = Vec::new();
+    let mut bip300_transactions = Vec::new();
+    let mut m4_included = false;
     for (vout, output) in coinbase.output.iter().enumerate() {
         let message = match parse_coinbase_script(&output.script_pubkey) {
             Ok((rest, message)) => {
@@ -504,12 +864,16 @@ fn connect_block(
             }
         };
 
+        if bip300_transactions.is_empty() {
+            bip300_transactions.push((coinbase.compute_txid(), Bip300TransactionKind::Coinbase));
+        }
         match message {
             CoinbaseMessage::M1ProposeSidechain {
                 sidechain_number,
                 data,
             } => {
                 tracing::info!(
+                    sidechain_slot = sidechain_number.0,
                     "Propose sidechain number {sidechain_number} with data \"{}\"",
                     String::from_utf8_lossy(&data)
                 );
@@ -517,9 +881,13 @@ fn connect_block(
                     sidechain_number,
                     description: data.into(),
                 };
-                if let Some(sidechain) =
-                    handle_m1_propose_sidechain(rwtxn, dbs, sidechain_proposal, height)?
-                {
+                if let Some(sidechain) = handle_m1_propose_sidechain(
+                    rwtxn,
+                    dbs,
+                    &mut sidechain_state_undo,
+                    sidechain_proposal,
+                    height,
+                )? {
                     // sidechain proposal is new
                     sidechain_proposals.push((vout as u32, sidechain.proposal));
                 }
@@ -529,12 +897,14 @@ fn connect_block(
                 data_hash: description_hash,
             } => {
                 tracing::info!(
+                    sidechain_slot = sidechain_number.0,
                     "Ack sidechain number {sidechain_number} with proposal description hash {}",
                     hex::encode(description_hash)
                 );
                 handle_m2_ack_sidechain(
                     rwtxn,
                     dbs,
+                    &mut sidechain_state_undo,
                     height,
                     sidechain_number,
                     &sha256d::Hash::from_byte_array(description_hash),
@@ -544,7 +914,14 @@ fn connect_block(
                 sidechain_number,
                 bundle_txid,
             } => {
-                let () = handle_m3_propose_bundle(rwtxn, dbs, sidechain_number, bundle_txid)?;
+                let () = handle_m3_propose_bundle(
+                    rwtxn,
+                    dbs,
+                    &mut sidechain_state_undo,
+                    sidechain_number,
+                    bundle_txid,
+                    height,
+                )?;
                 let event = WithdrawalBundleEvent {
                     sidechain_id: sidechain_number,
                     m6id: bundle_txid,
@@ -553,7 +930,13 @@ fn connect_block(
                 withdrawal_bundle_events.push(event);
             }
             CoinbaseMessage::M4AckBundles(m4) => {
-                handle_m4_ack_bundles(rwtxn, dbs, &m4)?;
+                withdrawal_bundle_alerts.extend(handle_m4_ack_bundles(
+                    rwtxn,
+                    dbs,
+                    &mut sidechain_state_undo,
+                    &m4,
+                )?);
+                m4_included = true;
             }
             CoinbaseMessage::M7BmmAccept {
                 sidechain_number,
@@ -568,13 +951,26 @@ fn connect_block(
         }
     }
 
-    let () = handle_failed_sidechain_proposals(rwtxn, dbs, height)?;
-    let failed_m6ids = handle_failed_m6ids(rwtxn, dbs)?;
+    if !m4_included {
+        // Votes are only repeated from the immediately preceding block
+        let () = sidechain_state_undo.record(rwtxn, &dbs.previous_votes, &UnitKey)?;
+        let () = dbs.previous_votes.put(rwtxn, &UnitKey, &Vec::new())?;
+    }
+    let () = handle_failed_sidechain_proposals(rwtxn, dbs, &mut sidechain_state_undo, height)?;
+    let failed_m6ids = handle_failed_m6ids(rwtxn, dbs, &mut sidechain_state_undo, height)?;
 
     let block_hash = block.header.block_hash();
     let prev_mainchain_block_hash = block.header.prev_blockhash;
 
     let mut deposits = Vec::new();
+    for (sidechain_number, m6id) in &failed_m6ids {
+        let outcome = WithdrawalBundleOutcome::Failed {
+            block_hash,
+            height,
+            reason: WithdrawalBundleFailureReason::Expired,
+        };
+        let () = push_withdrawal_bundle_outcome(rwtxn, dbs, *sidechain_number, *m6id, outcome)?;
+    }
     withdrawal_bundle_events.extend(failed_m6ids.into_iter().map(|(sidechain_id, m6id)| {
         WithdrawalBundleEvent {
             m6id,
@@ -582,10 +978,27 @@ fn connect_block(
             kind: WithdrawalBundleEventKind::Failed,
         }
     }));
-    for transaction in &block.txdata[1..] {
-        match handle_m5_m6(rwtxn, dbs, transaction)? {
-            Some(Either::Left(deposit)) => deposits.push(deposit),
+    for scanned in scan::scan_block(block, prevouts) {
+        let txid = scanned.txid;
+        match handle_m5_m6(rwtxn, dbs, &mut sidechain_state_undo, &scanned)? {
+            Some(Either::Left(deposit)) => {
+                let kind = Bip300TransactionKind::M5Deposit {
+                    sidechain_number: deposit.sidechain_id,
+                };
+                bip300_transactions.push((txid, kind));
+                deposits.push(deposit);
+            }
             Some(Either::Right((sidechain_id, m6id))) => {
+                let kind = Bip300TransactionKind::M6Withdrawal {
+                    sidechain_number: sidechain_id,
+                };
+                bip300_transactions.push((txid, kind));
+                let outcome = WithdrawalBundleOutcome::Succeeded {
+                    txid,
+                    block_hash,
+                    height,
+                };
+                let () = push_withdrawal_bundle_outcome(rwtxn, dbs, sidechain_id, m6id, outcome)?;
                 let withdrawal_bundle_event = WithdrawalBundleEvent {
                     m6id,
                     sidechain_id,
@@ -595,27 +1008,37 @@ fn connect_block(
             }
             None => (),
         };
-        if handle_m8(
-            transaction,
-            &accepted_bmm_requests,
-            &prev_mainchain_block_hash,
-        )
-        // We need to differentiate fatal and non-fatal errors. Non-fatal
-        // errors should not cause the initial sync to exit! We therefore must take
-        // care to not use the ? operator to exit from connect_block with an error
-        .or_else(|err| match err.split() {
-            Ok(just_for_info) => {
-                tracing::warn!("Non-fatal error handling M8: {just_for_info:#}");
-                Ok(false)
-            }
-            Err(err) => Err(error::ConnectBlock::M8(err.into())),
-        })? {
-            tracing::trace!(
-                "Handled valid M8 BMM request in tx `{}`",
-                transaction.compute_txid()
-            );
+        let m8_accepted =
+            match handle_m8(&scanned, &accepted_bmm_requests, &prev_mainchain_block_hash) {
+                Ok(true) => Some(true),
+                Ok(false) => None,
+                // We need to differentiate fatal and non-fatal errors. Non-fatal
+                // errors should not cause the initial sync to exit! We therefore must take
+                // care to not use the ? operator to exit from connect_block with an error
+                Err(err) => match err.split() {
+                    Ok(just_for_info) => {
+                        tracing::warn!("Non-fatal error handling M8: {just_for_info:#}");
+                        Some(false)
+                    }
+                    Err(err) => return Err(error::ConnectBlock::M8(err.into())),
+                },
+            };
+        if let Some(accepted) = m8_accepted {
+            if accepted {
+                tracing::trace!("Handled valid M8 BMM request in tx `{txid}`");
+            }
+            bip300_transactions.push((txid, Bip300TransactionKind::M8BmmRequest { accepted }));
         }
     }
+    let mut undo_bip300_transactions = Vec::new();
+    for (txid, kind) in bip300_transactions {
+        let previous = dbs.txid_to_bip300_transaction.try_get(rwtxn, &txid)?;
+        undo_bip300_transactions.push((txid, previous));
+        let bip300_transaction = Bip300Transaction { block_hash, kind };
+        let () = dbs
+            .txid_to_bip300_transaction
+            .put(rwtxn, &txid, &bip300_transaction)?;
+    }
 
     let block_info = BlockInfo {
         bmm_commitments: accepted_bmm_requests.into_iter().collect(),
@@ -629,21 +1052,44 @@ fn connect_block(
         .put_block_info(rwtxn, &block_hash, &block_info)
         .map_err(error::ConnectBlock::PutBlockInfo)?;
     // TODO: invalidate block
-    let current_tip_cumulative_work: Option<Work> = 'work: {
-        let Some(current_tip) = dbs.current_chain_tip.try_get(rwtxn, &UnitKey)? else {
-            break 'work None;
-        };
-        Some(
+    let current_tip = dbs.current_chain_tip.try_get(rwtxn, &UnitKey)?;
+    let current_tip_cumulative_work: Option<Work> = match current_tip {
+        Some(current_tip) => Some(
             dbs.block_hashes
                 .cumulative_work()
                 .get(rwtxn, &current_tip)?,
-        )
+        ),
+        None => None,
     };
     let cumulative_work = dbs.block_hashes.cumulative_work().get(rwtxn, &block_hash)?;
     if Some(cumulative_work) > current_tip_cumulative_work {
         dbs.current_chain_tip.put(rwtxn, &UnitKey, &block_hash)?;
+        let () = update_best_chain(rwtxn, dbs, block_hash, height)?;
         tracing::debug!("updated current chain tip to {block_hash}");
     }
+    let mut undo_treasury_utxos = Vec::new();
+    let () = dbs
+        .active_sidechains
+        .treasury_utxo_count
+        .iter(rwtxn)
+        .map_err(db_error::Iter::from)?
+        .map_err(db_error::Iter::from)
+        .for_each(|(sidechain_number, count)| {
+            let previous_count = treasury_utxo_counts
+                .get(&sidechain_number)
+                .copied()
+                .unwrap_or(0);
+            undo_treasury_utxos
+                .extend((previous_count..count).map(|sequence| (sidechain_number, sequence)));
+            Ok(())
+        })?;
+    let undo = BlockUndo {
+        sidechain_state: sidechain_state_undo.into_entries(rwtxn, dbs)?,
+        treasury_utxos: undo_treasury_utxos,
+        bip300_transactions: undo_bip300_transactions,
+        previous_chain_tip: current_tip,
+    };
+    let () = dbs.block_undo.put(rwtxn, &block_hash, &undo)?;
     let event = {
         let header_info = HeaderInfo {
             block_hash,
@@ -656,26 +1102,168 @@ fn connect_block(
             block_info,
         }
     };
-    let _send_err: Result<Option<_>, TrySendError<_>> = event_tx.try_broadcast(event);
+    let alert_events = withdrawal_bundle_alerts
+        .into_iter()
+        .map(|alert| Event::WithdrawalBundleAlert { block_hash, alert });
+    for event in std::iter::once(event).chain(alert_events) {
+        let sequence = dbs.append_event(rwtxn, &event)?;
+        events.push((sequence, event));
+    }
+    Ok(())
+}
+
+/// Prune block info for best chain blocks more than `depth` blocks below the
+/// current tip.
+/// Stops at the first block that has already been pruned, so this only does
+/// significant work the first time that pruning is enabled.
+fn prune_block_info(rwtxn: &mut RwTxn, dbs: &Dbs, depth: u32) -> Result<(), error::PruneBlockInfo> {
+    let Some(tip) = dbs.current_chain_tip.try_get(rwtxn, &UnitKey)? else {
+        return Ok(());
+    };
+    let tip_height = dbs.block_hashes.height().get(rwtxn, &tip)?;
+    let Some(mut height) = tip_height
+        .checked_sub(depth)
+        .and_then(|height| height.checked_sub(1))
+    else {
+        return Ok(());
+    };
+    while let Some(block_hash) = dbs
+        .best_chain_height_to_block_hash
+        .try_get(rwtxn, &height)?
+    {
+        if !dbs.block_hashes.prune_block_info(rwtxn, &block_hash)? {
+            break;
+        }
+        let _: bool = dbs.block_undo.delete(rwtxn, &block_hash)?;
+        tracing::trace!("pruned block info at height {height}: {block_hash}");
+        if height == 0 {
+            break;
+        }
+        height -= 1;
+    }
     Ok(())
 }
 
-// TODO: Add unit tests ensuring that `connect_block` and `disconnect_block` are inverse
-// operations.
-#[allow(unreachable_code, unused_variables)]
+/// Disconnect the current chain tip, restoring the state from before it
+/// was connected.
+/// Blocks can only be disconnected if their block info has not been pruned.
+#[tracing::instrument(skip_all, fields(%block_hash))]
 fn disconnect_block(
-    _rwtxn: &mut RwTxn,
-    _dbs: &Dbs,
-    event_tx: &Sender<Event>,
+    rwtxn: &mut RwTxn,
+    dbs: &Dbs,
+    events: &mut Vec<(u64, Event)>,
     block_hash: BlockHash,
 ) -> Result<(), error::DisconnectBlock> {
-    // FIXME: implement
-    todo!();
+    if dbs.current_chain_tip.try_get(rwtxn, &UnitKey)? != Some(block_hash) {
+        return Err(error::DisconnectBlock::NotChainTip { block_hash });
+    }
+    let Some(undo) = dbs.block_undo.try_get(rwtxn, &block_hash)? else {
+        return Err(error::DisconnectBlock::MissingUndo { block_hash });
+    };
+    for (db, entries) in dbs
+        .sidechain_state_dbs()
+        .into_iter()
+        .zip(undo.sidechain_state)
+    {
+        for (key, value) in entries {
+            if let Some(value) = value {
+                let () = db.put(rwtxn, &key, &value)?;
+            } else {
+                let _: bool = db.delete(rwtxn, &key)?;
+            }
+        }
+    }
+    // Stats are not sidechain state DBs, and are reverted from the treasury
+    // UTXOs and bundle outcomes that the block created
+    for slot_sequence @ (sidechain_number, _) in &undo.treasury_utxos {
+        if let Some(treasury_utxo) = dbs
+            .active_sidechains
+            .slot_sequence_to_treasury_utxo
+            .try_get(rwtxn, slot_sequence)?
+        {
+            let mut stats = dbs.active_sidechains.stats.get(rwtxn, sidechain_number)?;
+            stats.remove_treasury_utxo(&treasury_utxo);
+            let () = dbs
+                .active_sidechains
+                .stats
+                .put(rwtxn, sidechain_number, &stats)?;
+        }
+        let _: bool = dbs
+            .active_sidechains
+            .slot_sequence_to_treasury_utxo
+            .delete(rwtxn, slot_sequence)?;
+    }
+    for (txid, bip300_transaction) in &undo.bip300_transactions {
+        if let Some(bip300_transaction) = bip300_transaction {
+            let () = dbs
+                .txid_to_bip300_transaction
+                .put(rwtxn, txid, bip300_transaction)?;
+        } else {
+            let _: bool = dbs.txid_to_bip300_transaction.delete(rwtxn, txid)?;
+        }
+    }
+    if let Some(previous_tip) = undo.previous_chain_tip {
+        dbs.current_chain_tip.put(rwtxn, &UnitKey, &previous_tip)?;
+        let previous_tip_height = dbs.block_hashes.height().get(rwtxn, &previous_tip)?;
+        let () = update_best_chain(rwtxn, dbs, previous_tip, previous_tip_height)?;
+    } else {
+        let _: bool = dbs.current_chain_tip.delete(rwtxn, &UnitKey)?;
+        let mut height = 0;
+        while dbs.best_chain_height_to_block_hash.delete(rwtxn, &height)? {
+            height += 1;
+        }
+    }
+    // Bundles resolved in this block are pending again
+    if let Some(block_info) = dbs.block_hashes.try_get_block_info(rwtxn, &block_hash)? {
+        for event in &block_info.withdrawal_bundle_events {
+            if matches!(event.kind, WithdrawalBundleEventKind::Submitted) {
+                continue;
+            }
+            let key = (event.sidechain_id, event.m6id);
+            let mut outcomes = dbs
+                .withdrawal_bundle_outcomes
+                .try_get(rwtxn, &key)?
+                .unwrap_or_default();
+            let mut stats = dbs
+                .active_sidechains
+                .stats
+                .get(rwtxn, &event.sidechain_id)?;
+            outcomes.retain(|outcome| {
+                if outcome.block_hash() == block_hash {
+                    stats.remove_withdrawal_bundle_outcome(outcome);
+                    false
+                } else {
+                    true
+                }
+            });
+            let () = dbs
+                .active_sidechains
+                .stats
+                .put(rwtxn, &event.sidechain_id, &stats)?;
+            if outcomes.is_empty() {
+                let _: bool = dbs.withdrawal_bundle_outcomes.delete(rwtxn, &key)?;
+            } else {
+                let () = dbs.withdrawal_bundle_outcomes.put(rwtxn, &key, &outcomes)?;
+            }
+        }
+    }
+    let _: bool = dbs.block_hashes.delete_block_info(rwtxn, &block_hash)?;
+    let _: bool = dbs.block_undo.delete(rwtxn, &block_hash)?;
+    tracing::debug!("disconnected block");
     let event = Event::DisconnectBlock { block_hash };
-    let _send_err: Result<Option<_>, TrySendError<_>> = event_tx.try_broadcast(event);
+    let sequence = dbs.append_event(rwtxn, &event)?;
+    events.push((sequence, event));
     Ok(())
 }
 
+/// Broadcast events that were appended to the event journal, once the
+/// write txn that appended them has been committed
+fn broadcast_events(event_tx: &Sender<(u64, Event)>, events: Vec<(u64, Event)>) {
+    for event in events {
+        let _send_err: Result<Option<_>, TrySendError<_>> = event_tx.try_broadcast(event);
+    }
+}
+
 fn _is_transaction_valid(
     _rotxn: &mut RoTxn,
     _dbs: &Dbs,
@@ -684,11 +1272,104 @@ fn _is_transaction_valid(
     todo!();
 }
 
-async fn sync_headers(
+/// Block locator for the current chain tip, for `getheaders` requests.
+/// Contains the 10 latest blocks, and then exponentially fewer blocks,
+/// ending at the genesis block.
+/// Empty if no blocks have been connected yet.
+fn block_locator(rotxn: &RoTxn, dbs: &Dbs) -> Result<Vec<BlockHash>, error::Sync> {
+    let Some(tip) = dbs.current_chain_tip.try_get(rotxn, &UnitKey)? else {
+        return Ok(Vec::new());
+    };
+    let mut height = dbs.block_hashes.height().get(rotxn, &tip)?;
+    let mut res = vec![tip];
+    let mut step = 1;
+    while height > 0 {
+        height = height.saturating_sub(step);
+        res.push(dbs.best_chain_height_to_block_hash.get(rotxn, &height)?);
+        if res.len() >= 10 {
+            step *= 2;
+        }
+    }
+    Ok(res)
+}
+
+/// Sync headers following the current chain tip from the primary node over
+/// P2P, if configured.
+/// Any headers that are not synced, eg. if P2P requests fail, are synced via
+/// RPC afterwards.
+async fn sync_headers_p2p<C>(
     dbs: &Dbs,
-    main_client: &jsonrpsee::http_client::HttpClient,
+    main_client: &C,
     main_tip: BlockHash,
-) -> Result<(), error::Sync> {
+) -> Result<(), error::Sync>
+where
+    C: MainchainClient,
+{
+    let mut locator = tokio::task::block_in_place(|| {
+        let rotxn = dbs.read_txn()?;
+        block_locator(&rotxn, dbs)
+    })?;
+    if locator.is_empty() {
+        return Ok(());
+    }
+    loop {
+        let headers = match main_client.get_headers_p2p(locator, main_tip).await {
+            None => return Ok(()),
+            Some(Ok(headers)) => headers,
+            Some(Err(err)) => {
+                tracing::warn!(
+                    "Failed to sync headers over P2P, falling back to RPC: {:#}",
+                    anyhow::Error::from(err)
+                );
+                return Ok(());
+            }
+        };
+        let Some(last_header) = headers.last() else {
+            return Ok(());
+        };
+        let last_block_hash = last_header.block_hash();
+        tracing::debug!(
+            "Syncing {} header(s) -> `{last_block_hash}` over P2P",
+            headers.len()
+        );
+        let () = tokio::task::block_in_place(|| {
+            let mut rwtxn = dbs.write_txn()?;
+            for header in &headers {
+                if !dbs
+                    .block_hashes
+                    .contains_header(&rwtxn, &header.prev_blockhash)?
+                {
+                    // Not connected to known headers. Remaining headers are
+                    // synced via RPC.
+                    break;
+                }
+                let prev_height = dbs
+                    .block_hashes
+                    .height()
+                    .get(&rwtxn, &header.prev_blockhash)?;
+                let () = dbs
+                    .block_hashes
+                    .put_header(&mut rwtxn, header, prev_height + 1)?;
+            }
+            rwtxn.commit().map_err(error::Sync::from)
+        })?;
+        if last_block_hash == main_tip || headers.len() < crate::p2p::MAX_HEADERS_RESULTS {
+            return Ok(());
+        }
+        locator = vec![last_block_hash];
+    }
+}
+
+async fn sync_headers<C>(
+    dbs: &Dbs,
+    sync_progress: &SyncProgress,
+    main_client: &C,
+    main_tip: BlockHash,
+) -> Result<(), error::Sync>
+where
+    C: MainchainClient,
+{
+    let () = sync_headers_p2p(dbs, main_client, main_tip).await?;
     let mut block_hash = main_tip;
     while let Some((latest_missing_header, latest_missing_header_height)) =
         tokio::task::block_in_place(|| {
@@ -714,31 +1395,165 @@ async fn sync_headers(
         } else {
             tracing::debug!("Syncing header `{latest_missing_header}` -> `{main_tip}`");
         }
-        let header = main_client
-            .getblockheader(latest_missing_header)
-            .map_err(|err| error::Sync::JsonRpc {
-                method: "getblockheader".to_owned(),
-                source: err,
-            })
-            .await?;
-        latest_missing_header_height.inspect(|height| assert_eq!(*height, header.height));
-        let height = header.height;
+        let (header, height) = main_client.get_header(latest_missing_header).await?;
+        latest_missing_header_height
+            .inspect(|expected_height| assert_eq!(*expected_height, height));
+        let prev_header_missing = height > 0
+            && !tokio::task::block_in_place(|| {
+                let rotxn = dbs.read_txn()?;
+                dbs.block_hashes
+                    .contains_header(&rotxn, &header.prev_blockhash)
+                    .map_err(error::Sync::DbTryGet)
+            })?;
+        let ancestor_headers = if prev_header_missing {
+            main_client.get_ancestor_headers(&header, height).await?
+        } else {
+            Vec::new()
+        };
         let mut rwtxn = dbs.write_txn()?;
-        dbs.block_hashes
-            .put_header(&mut rwtxn, &header.into(), height)?;
+        dbs.block_hashes.put_header(&mut rwtxn, &header, height)?;
+        for (ancestor_header, ancestor_height) in ancestor_headers {
+            dbs.block_hashes
+                .put_header(&mut rwtxn, &ancestor_header, ancestor_height)?;
+        }
         let () = rwtxn.commit()?;
         block_hash = latest_missing_header;
     }
+    let main_tip_height = tokio::task::block_in_place(|| {
+        let rotxn = dbs.read_txn()?;
+        dbs.block_hashes
+            .height()
+            .get(&rotxn, &main_tip)
+            .map_err(error::Sync::from)
+    })?;
+    sync_progress.headers_synced(main_tip_height);
+    Ok(())
+}
+
+/// Max number of blocks to request from the node in a single batch
+const BLOCKS_BATCH_SIZE: usize = 16;
+
+/// Max number of blocks to connect in a single write txn
+const CONNECT_BATCH_MAX_BLOCKS: usize = 256;
+
+/// Max total size of blocks to connect in a single write txn, in bytes
+const CONNECT_BATCH_MAX_BYTES: usize = 32 * 1024 * 1024;
+
+/// Connect blocks in a single write txn, broadcasting their events once the
+/// txn is committed.
+/// If a block fails to connect, the blocks before it are committed before
+/// returning the error.
+fn connect_batch(
+    dbs: &Dbs,
+    sync_progress: &SyncProgress,
+    event_tx: &Sender<(u64, Event)>,
+    blocks: &[(Block, Option<Prevouts>)],
+    prune_block_info_depth: Option<u32>,
+    deposit_confirmation_depths: &DepositConfirmationDepths,
+    record_fixtures_dir: Option<&Path>,
+) -> Result<(), error::Sync> {
+    if blocks.is_empty() {
+        return Ok(());
+    }
+    // Fixtures are recorded from the committed state after each block, so
+    // blocks are committed one at a time while recording fixtures
+    if record_fixtures_dir.is_some() && blocks.len() > 1 {
+        for block in blocks {
+            let () = connect_batch(
+                dbs,
+                sync_progress,
+                event_tx,
+                std::slice::from_ref(block),
+                prune_block_info_depth,
+                deposit_confirmation_depths,
+                record_fixtures_dir,
+            )?;
+        }
+        return Ok(());
+    }
+    let mut rwtxn = dbs.write_txn()?;
+    let mut events = Vec::new();
+    for (index, (block, prevouts)) in blocks.iter().enumerate() {
+        let block_hash = block.block_hash();
+        let height = dbs.block_hashes.height().get(&rwtxn, &block_hash)?;
+        let previous_tip = dbs.current_chain_tip.try_get(&rwtxn, &UnitKey)?;
+        if let Err(err) = connect_block(
+            &mut rwtxn,
+            dbs,
+            &mut events,
+            block,
+            prevouts.as_ref(),
+            height,
+        ) {
+            drop(rwtxn);
+            let () = connect_batch(
+                dbs,
+                sync_progress,
+                event_tx,
+                &blocks[..index],
+                prune_block_info_depth,
+                deposit_confirmation_depths,
+                record_fixtures_dir,
+            )?;
+            return Err(err.into());
+        }
+        tracing::debug!("connected block at height {height}: {block_hash}");
+        let () = deposit_confirmations::append_connect_events(
+            &mut rwtxn,
+            dbs,
+            deposit_confirmation_depths,
+            &mut events,
+            block_hash,
+            previous_tip,
+        )?;
+    }
+    if let Some(depth) = prune_block_info_depth {
+        let () = prune_block_info(&mut rwtxn, dbs, depth)?;
+    }
+    let () = rwtxn.commit()?;
+    if let Some(dir) = record_fixtures_dir {
+        for (block, prevouts) in blocks {
+            let block_hash = block.block_hash();
+            let res = dbs
+                .read_txn()
+                .map_err(anyhow::Error::from)
+                .and_then(|rotxn| {
+                    let height = dbs.block_hashes.height().get(&rotxn, &block_hash)?;
+                    let path =
+                        fixtures::record(&rotxn, dbs, dir, block, prevouts.as_ref(), height)?;
+                    Ok(path)
+                });
+            match res {
+                Ok(Some(path)) => tracing::debug!("recorded fixture: {}", path.display()),
+                Ok(None) => (),
+                Err(err) => {
+                    tracing::warn!("Failed to record fixture for block `{block_hash}`: {err:#}")
+                }
+            }
+        }
+    }
+    broadcast_events(event_tx, events);
+    for _ in blocks {
+        sync_progress.block_connected();
+    }
     Ok(())
 }
 
 // MUST be called after `initial_sync_headers`.
-async fn sync_blocks(
+async fn sync_blocks<C>(
     dbs: &Dbs,
-    event_tx: &Sender<Event>,
-    main_client: &jsonrpsee::http_client::HttpClient,
+    sync_progress: &SyncProgress,
+    event_tx: &Sender<(u64, Event)>,
+    main_client: &C,
     main_tip: BlockHash,
-) -> Result<(), error::Sync> {
+    prune_block_info_depth: Option<u32>,
+    deposit_confirmation_depths: &DepositConfirmationDepths,
+    record_fixtures_dir: Option<&Path>,
+    capabilities: NodeCapabilities,
+) -> Result<(), error::Sync>
+where
+    C: MainchainClient,
+{
     let missing_blocks: Vec<BlockHash> = tokio::task::block_in_place(|| {
         let rotxn = dbs.read_txn()?;
         dbs.block_hashes
@@ -751,92 +1566,240 @@ async fn sync_blocks(
     if missing_blocks.is_empty() {
         return Ok(());
     }
-    for missing_block in missing_blocks.into_iter().rev() {
-        tracing::debug!("Syncing block `{missing_block}` -> `{main_tip}`");
-        let block = main_client
-            .get_block(missing_block, U8Witness::<0>)
-            .map_err(|err| error::Sync::JsonRpc {
-                method: "getblock".to_owned(),
-                source: err,
-            })
-            .await?
-            .0;
-        let mut rwtxn = dbs.write_txn()?;
-        let height = dbs.block_hashes.height().get(&rwtxn, &missing_block)?;
-        let () = connect_block(&mut rwtxn, dbs, event_tx, &block, height)?;
-        tracing::debug!("connected block at height {height}: {missing_block}");
-        let () = rwtxn.commit()?;
+    sync_progress.start_block_sync();
+    let missing_blocks: Vec<_> = missing_blocks.into_iter().rev().collect();
+    let missing_blocks_count = missing_blocks.len();
+    // Blocks are connected in batches, to reduce the number of commits
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+    for (chunk_index, missing_blocks) in missing_blocks.chunks(BLOCKS_BATCH_SIZE).enumerate() {
+        tracing::debug!(
+            "Syncing {} block(s) `{}` -> `{main_tip}`",
+            missing_blocks.len(),
+            missing_blocks[0]
+        );
+        let blocks = main_client.get_blocks(missing_blocks, capabilities).await?;
+        let is_last_chunk = (chunk_index + 1) * BLOCKS_BATCH_SIZE >= missing_blocks_count;
+        for (block, prevouts) in blocks {
+            batch_bytes += block.total_size();
+            batch.push((block, prevouts));
+        }
+        if is_last_chunk
+            || batch.len() >= CONNECT_BATCH_MAX_BLOCKS
+            || batch_bytes >= CONNECT_BATCH_MAX_BYTES
+        {
+            let () = tokio::task::block_in_place(|| {
+                connect_batch(
+                    dbs,
+                    sync_progress,
+                    event_tx,
+                    &batch,
+                    prune_block_info_depth,
+                    deposit_confirmation_depths,
+                    record_fixtures_dir,
+                )
+            })?;
+            batch.clear();
+            batch_bytes = 0;
+        }
     }
     Ok(())
 }
 
-async fn sync_to_tip(
+async fn sync_to_tip<C>(
     dbs: &Dbs,
-    event_tx: &Sender<Event>,
-    main_client: &jsonrpsee::http_client::HttpClient,
+    sync_progress: &SyncProgress,
+    event_tx: &Sender<(u64, Event)>,
+    main_client: &C,
     main_tip: BlockHash,
-) -> Result<(), error::Sync> {
-    let () = sync_headers(dbs, main_client, main_tip).await?;
-    let () = sync_blocks(dbs, event_tx, main_client, main_tip).await?;
-    Ok(())
+    prune_block_info_depth: Option<u32>,
+    deposit_confirmation_depths: &DepositConfirmationDepths,
+    record_fixtures_dir: Option<&Path>,
+    capabilities: NodeCapabilities,
+) -> Result<(), error::Sync>
+where
+    C: MainchainClient,
+{
+    let () = sync_headers(dbs, sync_progress, main_client, main_tip).await?;
+    let res = sync_blocks(
+        dbs,
+        sync_progress,
+        event_tx,
+        main_client,
+        main_tip,
+        prune_block_info_depth,
+        deposit_confirmation_depths,
+        record_fixtures_dir,
+        capabilities,
+    )
+    .await;
+    sync_progress.finish_block_sync();
+    res
 }
 
-async fn initial_sync(
+async fn initial_sync<C>(
     dbs: &Dbs,
-    event_tx: &Sender<Event>,
-    main_client: &jsonrpsee::http_client::HttpClient,
-) -> Result<(), error::Sync> {
-    let main_tip: BlockHash = main_client
-        .getbestblockhash()
-        .map_err(|err| error::Sync::JsonRpc {
-            method: "getbestblockhash".to_owned(),
-            source: err,
-        })
-        .await?;
+    sync_progress: &SyncProgress,
+    event_tx: &Sender<(u64, Event)>,
+    main_client: &C,
+    prune_block_info_depth: Option<u32>,
+    deposit_confirmation_depths: &DepositConfirmationDepths,
+    record_fixtures_dir: Option<&Path>,
+    capabilities: NodeCapabilities,
+) -> Result<(), error::Sync>
+where
+    C: MainchainClient,
+{
+    let main_tip = main_client.get_best_block_hash().await?;
     tracing::debug!("mainchain tip: `{main_tip}`");
-    let () = sync_to_tip(dbs, event_tx, main_client, main_tip).await?;
+    // Populate the best chain index for DBs created before it existed
+    let tip_height = tokio::task::block_in_place(|| {
+        let mut rwtxn = dbs.write_txn()?;
+        let tip_height = if let Some(tip) = dbs.current_chain_tip.try_get(&rwtxn, &UnitKey)? {
+            let tip_height = dbs.block_hashes.height().get(&rwtxn, &tip)?;
+            let () = update_best_chain(&mut rwtxn, dbs, tip, tip_height)?;
+            Some(tip_height)
+        } else {
+            None
+        };
+        let () = rwtxn.commit()?;
+        Ok::<_, error::Sync>(tip_height)
+    })?;
+    // Blocks must be connected in order, so a pruned node can only be used
+    // if the enforcer has already synced past the prune height
+    let prune_height = main_client.get_prune_height().await?;
+    if let Some(prune_height) = prune_height {
+        let next_height = tip_height.map_or(0, |tip_height| tip_height + 1);
+        if next_height < prune_height {
+            return Err(error::Sync::BlocksPrunedBeforeSync {
+                height: next_height,
+                prune_height,
+            });
+        }
+        tracing::info!(
+            "Node is pruned, with blocks available from height {prune_height}. Blocks are synced as they arrive, and the enforcer must not fall behind the prune height"
+        );
+    }
+    let () = sync_to_tip(
+        dbs,
+        sync_progress,
+        event_tx,
+        main_client,
+        main_tip,
+        prune_block_info_depth,
+        deposit_confirmation_depths,
+        record_fixtures_dir,
+        capabilities,
+    )
+    .await?;
     Ok(())
 }
 
-pub(super) async fn task(
-    main_client: &jsonrpsee::http_client::HttpClient,
+/// Split a sync error into non-fatal and fatal errors. Fatal errors that may
+/// resolve by retrying are returned as recoverable, so that the sync task is
+/// restarted rather than stopped.
+fn split_sync_error(
+    err: error::Sync,
+) -> Result<<error::Sync as fatality::Split>::Jfyi, error::Fatal> {
+    if err.is_recoverable() {
+        return Err(error::FatalInner::RecoverableSync(err).into());
+    }
+    err.split().map_err(error::Fatal::from)
+}
+
+/// Sync with the active node, and follow its tip
+async fn sync_with_node(
+    main_client: &crate::rpc_client::Client,
     zmq_addr_sequence: &str,
     dbs: &Dbs,
-    event_tx: &Sender<Event>,
+    sync_progress: &SyncProgress,
+    event_tx: &Sender<(u64, Event)>,
+    prune_block_info_depth: Option<u32>,
+    deposit_confirmation_depths: &DepositConfirmationDepths,
+    record_fixtures_dir: Option<&Path>,
 ) -> Result<(), error::Fatal> {
     // FIXME: use this instead of polling
     let zmq_sequence = crate::zmq::subscribe_sequence(zmq_addr_sequence)
         .await
         .map_err(error::Fatal::from)?;
-    let () = initial_sync(dbs, event_tx, main_client)
+    sync_progress.set_zmq_connected(true);
+    let getblock_prevouts = main_client
+        .supports_getblock_prevouts()
         .await
-        .or_else(|err| {
-            let non_fatal: <error::Sync as fatality::Split>::Jfyi = err.split()?;
-            let non_fatal = anyhow::Error::from(non_fatal);
-
-            // In a way, this doesn't make sense. The initial sync exits, at
-            // this point. We'd need to restart it?
-            tracing::warn!("Non-fatal error during initial sync: {non_fatal:#}");
-            Ok::<(), error::Fatal>(())
-        })?;
-    zmq_sequence
+        .unwrap_or_else(|err| {
+            tracing::warn!("Failed to check if node supports `getblock` with prevouts: {err:#}");
+            false
+        });
+    if !getblock_prevouts {
+        tracing::info!("Node does not support `getblock` with prevouts, spends of treasury outputs other than CTIPs will only be detected for indexed treasury outputs");
+    }
+    let capabilities = NodeCapabilities { getblock_prevouts };
+    let () = initial_sync(
+        dbs,
+        sync_progress,
+        event_tx,
+        main_client,
+        prune_block_info_depth,
+        deposit_confirmation_depths,
+        record_fixtures_dir,
+        capabilities,
+    )
+    .await
+    .or_else(|err| {
+        let non_fatal = split_sync_error(err)?;
+        let non_fatal = anyhow::Error::from(non_fatal);
+
+        // In a way, this doesn't make sense. The initial sync exits, at
+        // this point. We'd need to restart it?
+        tracing::warn!("Non-fatal error during initial sync: {non_fatal:#}");
+        sync_progress.record_error(TaskErrorKind::NonFatal, &non_fatal);
+        Ok::<(), error::Fatal>(())
+    })?;
+    let res = zmq_sequence
         .err_into::<error::Fatal>()
         .try_for_each(|msg| async move {
             match msg {
                 SequenceMessage::BlockHashConnected(block_hash, _) => {
-                    let () = sync_to_tip(dbs, event_tx, main_client, block_hash)
-                        .await
-                        .or_else(|err| {
-                            let non_fatal: <error::Sync as fatality::Split>::Jfyi = err.split()?;
-                            let non_fatal = anyhow::Error::from(non_fatal);
-                            tracing::warn!("Error during sync to {block_hash}: {non_fatal:#}");
-                            Ok::<(), error::Fatal>(())
-                        })?;
+                    let () = sync_to_tip(
+                        dbs,
+                        sync_progress,
+                        event_tx,
+                        main_client,
+                        block_hash,
+                        prune_block_info_depth,
+                        deposit_confirmation_depths,
+                        record_fixtures_dir,
+                        capabilities,
+                    )
+                    .await
+                    .or_else(|err| {
+                        let non_fatal = split_sync_error(err)?;
+                        let non_fatal = anyhow::Error::from(non_fatal);
+                        tracing::warn!("Error during sync to {block_hash}: {non_fatal:#}");
+                        sync_progress.record_error(TaskErrorKind::NonFatal, &non_fatal);
+                        Ok::<(), error::Fatal>(())
+                    })?;
                     Ok(())
                 }
                 SequenceMessage::BlockHashDisconnected(block_hash, _) => {
                     let mut rwtxn = dbs.write_txn()?;
-                    let () = disconnect_block(&mut rwtxn, dbs, event_tx, block_hash)?;
+                    let mut events = Vec::new();
+                    let confirmation_changes = deposit_confirmations::disconnect_changes(
+                        &rwtxn,
+                        dbs,
+                        deposit_confirmation_depths,
+                        block_hash,
+                    )?;
+                    let () = disconnect_block(&mut rwtxn, dbs, &mut events, block_hash)?;
+                    let () = deposit_confirmations::append_disconnect_events(
+                        &mut rwtxn,
+                        dbs,
+                        &mut events,
+                        block_hash,
+                        confirmation_changes,
+                    )?;
+                    let () = rwtxn.commit()?;
+                    broadcast_events(event_tx, events);
                     Ok(())
                 }
                 SequenceMessage::TxHashAdded { .. } | SequenceMessage::TxHashRemoved { .. } => {
@@ -845,5 +1808,1420 @@ pub(super) async fn task(
             }
         })
         .await
-        .map_err(error::Fatal::from)
+        .map_err(error::Fatal::from);
+    sync_progress.set_zmq_connected(false);
+    res
+}
+
+/// `zmq_addrs_sequence` contains the ZMQ `sequence` endpoint for each node
+/// of `main_client`.
+/// If syncing with the active node fails, and the node is unreachable,
+/// fails over to a backup node and resyncs to its tip. Also resyncs if the
+/// client fails over or fails back to another node.
+async fn task(
+    main_client: &crate::rpc_client::Client,
+    zmq_addrs_sequence: &[String],
+    dbs: &Dbs,
+    sync_progress: &SyncProgress,
+    event_tx: &Sender<(u64, Event)>,
+    prune_block_info_depth: Option<u32>,
+    deposit_confirmation_depths: &DepositConfirmationDepths,
+    record_fixtures_dir: Option<&Path>,
+) -> Result<(), error::Fatal> {
+    let mut active_node_rx = main_client.subscribe_active_node();
+    loop {
+        let node = *active_node_rx.borrow_and_update();
+        let res = tokio::select! {
+            res = sync_with_node(
+                main_client,
+                &zmq_addrs_sequence[node],
+                dbs,
+                sync_progress,
+                event_tx,
+                prune_block_info_depth,
+                deposit_confirmation_depths,
+                record_fixtures_dir,
+            ) => res,
+            Ok(()) = active_node_rx.changed() => {
+                sync_progress.set_zmq_connected(false);
+                tracing::info!("Active node changed, resyncing");
+                continue;
+            }
+        };
+        let err = match res {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        if main_client.node_count() == 1 || main_client.is_healthy(node).await {
+            return Err(err);
+        }
+        tracing::error!("Lost connection to node: {:#}", anyhow::Error::from(err));
+        if main_client.fail_over(node).await.is_none() {
+            return Err(error::FatalInner::NoHealthyNodes.into());
+        }
+    }
+}
+
+/// Delay before restarting the sync task after the first recoverable error
+const RESTART_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Max delay before restarting the sync task. If the sync task fails after
+/// running for longer than this, the backoff is reset.
+const RESTART_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Runs the sync task, restarting it with exponential backoff if it fails
+/// with a recoverable error, eg. if the node is unreachable or ZMQ
+/// notifications were dropped.
+/// Only returns an error for errors that are not recoverable, such as DB
+/// errors.
+pub(super) async fn supervise(
+    main_client: &crate::rpc_client::Client,
+    zmq_addrs_sequence: &[String],
+    dbs: &Dbs,
+    sync_progress: &SyncProgress,
+    event_tx: &Sender<(u64, Event)>,
+    prune_block_info_depth: Option<u32>,
+    deposit_confirmation_depths: &DepositConfirmationDepths,
+    record_fixtures_dir: Option<&Path>,
+) -> Result<(), error::Fatal> {
+    let mut backoff = RESTART_INITIAL_BACKOFF;
+    loop {
+        sync_progress.set_task_state(TaskState::Running);
+        let started = Instant::now();
+        let err = match task(
+            main_client,
+            zmq_addrs_sequence,
+            dbs,
+            sync_progress,
+            event_tx,
+            prune_block_info_depth,
+            deposit_confirmation_depths,
+            record_fixtures_dir,
+        )
+        .await
+        {
+            Ok(()) => return Ok(()),
+            Err(err) if err.is_recoverable() => err,
+            Err(err) => return Err(err),
+        };
+        if started.elapsed() > RESTART_MAX_BACKOFF {
+            backoff = RESTART_INITIAL_BACKOFF;
+        }
+        let err = anyhow::Error::from(err);
+        sync_progress.set_task_state(TaskState::Degraded);
+        sync_progress.record_error(TaskErrorKind::Recoverable, &err);
+        sync_progress.sync_task_restarted();
+        tracing::error!(
+            "Sync task failed, restarting in {}s: {err:#}",
+            backoff.as_secs(),
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RESTART_MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        time::Duration,
+    };
+
+    use async_broadcast::{broadcast, Receiver, Sender};
+    use bip300301::jsonrpsee::core::ClientError;
+    use bitcoin::{
+        absolute::LockTime,
+        block::{Header, Version as BlockVersion},
+        constants::genesis_block,
+        hash_types::TxMerkleNode,
+        hashes::{sha256d, Hash as _},
+        opcodes::OP_0,
+        script::Builder as ScriptBuilder,
+        transaction::Version as TxVersion,
+        Amount, Block, BlockHash, Network, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid,
+    };
+    use fallible_iterator::FallibleIterator as _;
+    use fatality::Split as _;
+    use heed::RoTxn;
+    use proptest::{
+        collection::vec,
+        prelude::{any, prop_assert_eq, prop_oneof, proptest, Just, ProptestConfig, Strategy},
+        sample::Index,
+        test_runner::TestCaseError,
+    };
+    use tempfile::TempDir;
+    use tokio::time::error::Elapsed;
+
+    use super::{
+        connect_block,
+        deposit_confirmations::DepositConfirmationDepths,
+        disconnect_block, error, fixtures,
+        main_client::{MainchainClient, NodeCapabilities},
+        policy::{
+            check_transaction, CheckTransactionError, CheckedTransaction, TransactionRejection,
+        },
+        sync_to_tip, CONNECT_BATCH_MAX_BLOCKS, WITHDRAWAL_BUNDLE_MAX_AGE,
+    };
+    use crate::{
+        deposit_address::DepositDestination,
+        messages::{
+            create_m5_deposit_output, m6_to_id, parse_op_drivechain, CoinbaseBuilder, M4AckBundles,
+            ABSTAIN_ONE_BYTE, ABSTAIN_TWO_BYTES, ALARM_ONE_BYTE, ALARM_TWO_BYTES,
+        },
+        rpc_client::Prevouts,
+        types::{
+            Event, SidechainNumber, SidechainProposal, SidechainStats,
+            WithdrawalBundleFailureReason, WithdrawalBundleOutcome,
+        },
+        validator::{
+            dbs::{Dbs, UnitKey},
+            sync_progress::SyncProgress,
+        },
+    };
+
+    /// Sidechain slots used in generated blocks
+    const SIDECHAINS: u8 = 2;
+
+    const DESCRIPTIONS: [&[u8]; 2] = [b"sidechain a", b"sidechain b"];
+
+    /// DBs that are retained when a block is disconnected
+    const HEADER_DBS: [&str; 2] = ["block_hash_to_header", "block_hash_to_height"];
+
+    #[derive(Clone, Debug)]
+    enum MessageSpec {
+        ProposeSidechain {
+            sidechain_number: u8,
+            description: usize,
+        },
+        AckSidechain {
+            sidechain_number: u8,
+            description: usize,
+        },
+        /// Propose a bundle paying out from the current CTIP
+        ProposeBundle {
+            sidechain_number: u8,
+            payout_sats: u64,
+        },
+        AckBundles(M4AckBundles),
+        BmmAccept {
+            sidechain_number: u8,
+            sidechain_block_hash: [u8; 32],
+        },
+    }
+
+    #[derive(Clone, Debug)]
+    enum TxSpec {
+        Deposit {
+            sidechain_number: u8,
+            value_sats: u64,
+        },
+        /// Include the M6 for a previously proposed bundle
+        Withdrawal { bundle: Index },
+    }
+
+    #[derive(Clone, Debug)]
+    struct BlockSpec {
+        messages: Vec<MessageSpec>,
+        transactions: Vec<TxSpec>,
+    }
+
+    fn m4_strategy() -> impl Strategy<Value = M4AckBundles> {
+        let one_byte_vote = prop_oneof![
+            4 => Just(0),
+            1 => Just(1),
+            1 => Just(ABSTAIN_ONE_BYTE),
+            1 => Just(ALARM_ONE_BYTE),
+        ];
+        let two_bytes_vote = prop_oneof![
+            4 => Just(0),
+            1 => Just(ABSTAIN_TWO_BYTES),
+            1 => Just(ALARM_TWO_BYTES),
+        ];
+        prop_oneof![
+            Just(M4AckBundles::RepeatPrevious),
+            Just(M4AckBundles::LeadingBy50),
+            vec(one_byte_vote, 0..=SIDECHAINS as usize)
+                .prop_map(|upvotes| M4AckBundles::OneByte { upvotes }),
+            vec(two_bytes_vote, 0..=SIDECHAINS as usize)
+                .prop_map(|upvotes| M4AckBundles::TwoBytes { upvotes }),
+        ]
+    }
+
+    fn message_strategy() -> impl Strategy<Value = MessageSpec> {
+        let sidechain_number = 0..SIDECHAINS;
+        let description = 0..DESCRIPTIONS.len();
+        prop_oneof![
+            1 => (sidechain_number.clone(), description.clone()).prop_map(
+                |(sidechain_number, description)| MessageSpec::ProposeSidechain {
+                    sidechain_number,
+                    description,
+                }
+            ),
+            4 => (sidechain_number.clone(), description).prop_map(
+                |(sidechain_number, description)| MessageSpec::AckSidechain {
+                    sidechain_number,
+                    description,
+                }
+            ),
+            2 => (sidechain_number.clone(), 1..100_000u64).prop_map(
+                |(sidechain_number, payout_sats)| MessageSpec::ProposeBundle {
+                    sidechain_number,
+                    payout_sats,
+                }
+            ),
+            4 => m4_strategy().prop_map(MessageSpec::AckBundles),
+            1 => (sidechain_number, any::<[u8; 32]>()).prop_map(
+                |(sidechain_number, sidechain_block_hash)| MessageSpec::BmmAccept {
+                    sidechain_number,
+                    sidechain_block_hash,
+                }
+            ),
+        ]
+    }
+
+    fn tx_strategy() -> impl Strategy<Value = TxSpec> {
+        prop_oneof![
+            (0..SIDECHAINS, 1..1_000_000u64).prop_map(|(sidechain_number, value_sats)| {
+                TxSpec::Deposit {
+                    sidechain_number,
+                    value_sats,
+                }
+            }),
+            any::<Index>().prop_map(|bundle| TxSpec::Withdrawal { bundle }),
+        ]
+    }
+
+    fn block_strategy() -> impl Strategy<Value = BlockSpec> {
+        (vec(message_strategy(), 0..6), vec(tx_strategy(), 0..3)).prop_map(
+            |(messages, transactions)| BlockSpec {
+                messages,
+                transactions,
+            },
+        )
+    }
+
+    /// Blocks that propose and activate a sidechain in slot 0, so that
+    /// generated blocks can include deposits and withdrawals
+    fn activate_sidechain_blocks() -> Vec<BlockSpec> {
+        let propose = BlockSpec {
+            messages: vec![MessageSpec::ProposeSidechain {
+                sidechain_number: 0,
+                description: 0,
+            }],
+            transactions: Vec::new(),
+        };
+        let ack = BlockSpec {
+            messages: vec![MessageSpec::AckSidechain {
+                sidechain_number: 0,
+                description: 0,
+            }],
+            transactions: Vec::new(),
+        };
+        let mut blocks = vec![propose];
+        blocks.resize(7, ack);
+        blocks
+    }
+
+    /// Raw contents of all DBs except for the event journal, which is
+    /// append-only, and the DBs in `exclude`
+    fn dump_dbs(
+        rotxn: &RoTxn,
+        dbs: &Dbs,
+        exclude: &[&str],
+    ) -> BTreeMap<&'static str, Vec<(Vec<u8>, Vec<u8>)>> {
+        dbs.raw_dbs()
+            .into_iter()
+            .filter(|db| db.name() != "events" && !exclude.contains(&db.name()))
+            .map(|db| {
+                let entries = db
+                    .iter(rotxn)
+                    .unwrap()
+                    .map(|(key, value)| Ok((key.to_vec(), value.to_vec())))
+                    .collect()
+                    .unwrap();
+                (db.name(), entries)
+            })
+            .collect()
+    }
+
+    fn without_header_dbs(
+        mut dump: BTreeMap<&'static str, Vec<(Vec<u8>, Vec<u8>)>>,
+    ) -> BTreeMap<&'static str, Vec<(Vec<u8>, Vec<u8>)>> {
+        dump.retain(|name, _| !HEADER_DBS.contains(name));
+        dump
+    }
+
+    struct TestChain {
+        dbs: Dbs,
+        event_tx: Sender<(u64, Event)>,
+        /// Tip hash and height
+        tip: Option<(BlockHash, u32)>,
+        /// M6s for proposed bundles
+        bundles: Vec<Transaction>,
+        /// Used to create unique funding inputs for deposits
+        next_funding_vout: u32,
+        _data_dir: TempDir,
+    }
+
+    impl TestChain {
+        fn new() -> Self {
+            let data_dir = TempDir::new().unwrap();
+            let dbs = Dbs::new(data_dir.path(), Network::Regtest).unwrap();
+            let (event_tx, _event_rx) = broadcast(1);
+            Self {
+                dbs,
+                event_tx,
+                tip: None,
+                bundles: Vec::new(),
+                next_funding_vout: 0,
+                _data_dir: data_dir,
+            }
+        }
+
+        fn next_height(&self) -> u32 {
+            self.tip.map_or(0, |(_, height)| height + 1)
+        }
+
+        fn funding_input(&mut self) -> TxIn {
+            let previous_output = OutPoint {
+                txid: Txid::all_zeros(),
+                vout: self.next_funding_vout,
+            };
+            self.next_funding_vout += 1;
+            TxIn {
+                previous_output,
+                ..TxIn::default()
+            }
+        }
+
+        /// Build a block from the spec. CTIPs are tracked within the block,
+        /// so that multiple deposits and withdrawals can be included for
+        /// the same sidechain.
+        fn build_block(&mut self, spec: &BlockSpec) -> Block {
+            let mut ctips: BTreeMap<u8, (OutPoint, Amount)> = {
+                let rotxn = self.dbs.read_txn().unwrap();
+                (0..SIDECHAINS)
+                    .filter_map(|sidechain_number| {
+                        let ctip = self
+                            .dbs
+                            .active_sidechains
+                            .ctip
+                            .try_get(&rotxn, &SidechainNumber(sidechain_number))
+                            .unwrap()?;
+                        Some((sidechain_number, (ctip.outpoint, ctip.value)))
+                    })
+                    .collect()
+            };
+            let height = self.next_height();
+            let mut coinbase_builder = CoinbaseBuilder::new();
+            for message in &spec.messages {
+                coinbase_builder = match message {
+                    MessageSpec::ProposeSidechain {
+                        sidechain_number,
+                        description,
+                    } => coinbase_builder.propose_sidechain(SidechainProposal {
+                        sidechain_number: SidechainNumber(*sidechain_number),
+                        description: DESCRIPTIONS[*description].to_vec().into(),
+                    }),
+                    MessageSpec::AckSidechain {
+                        sidechain_number,
+                        description,
+                    } => coinbase_builder.ack_sidechain(
+                        SidechainNumber(*sidechain_number),
+                        sha256d::Hash::hash(DESCRIPTIONS[*description]),
+                    ),
+                    MessageSpec::ProposeBundle {
+                        sidechain_number,
+                        payout_sats,
+                    } => {
+                        let (ctip_outpoint, ctip_value) = ctips
+                            .get(sidechain_number)
+                            .copied()
+                            .unwrap_or((OutPoint::null(), Amount::ZERO));
+                        let payout = Amount::from_sat(*payout_sats).min(ctip_value);
+                        let m6 = Transaction {
+                            version: TxVersion::TWO,
+                            lock_time: LockTime::ZERO,
+                            input: vec![TxIn {
+                                previous_output: ctip_outpoint,
+                                ..TxIn::default()
+                            }],
+                            output: vec![
+                                create_m5_deposit_output(
+                                    SidechainNumber(*sidechain_number),
+                                    Amount::ZERO,
+                                    ctip_value - payout,
+                                ),
+                                TxOut {
+                                    value: payout,
+                                    script_pubkey: ScriptBuf::new(),
+                                },
+                            ],
+                        };
+                        let m6id = m6_to_id(&m6, ctip_value.to_sat()).unwrap();
+                        self.bundles.push(m6);
+                        coinbase_builder.propose_bundle(SidechainNumber(*sidechain_number), &m6id)
+                    }
+                    MessageSpec::AckBundles(m4) => coinbase_builder.ack_bundles(m4.clone()),
+                    MessageSpec::BmmAccept {
+                        sidechain_number,
+                        sidechain_block_hash,
+                    } => coinbase_builder
+                        .bmm_accept(SidechainNumber(*sidechain_number), sidechain_block_hash),
+                };
+            }
+            let coinbase = Transaction {
+                version: TxVersion::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    script_sig: ScriptBuilder::new()
+                        .push_int(height as i64)
+                        .push_opcode(OP_0)
+                        .into_script(),
+                    ..TxIn::default()
+                }],
+                output: coinbase_builder.build().unwrap(),
+            };
+            let mut txdata = vec![coinbase];
+            for tx in &spec.transactions {
+                let tx = match tx {
+                    TxSpec::Deposit {
+                        sidechain_number,
+                        value_sats,
+                    } => {
+                        let destination = DepositDestination {
+                            sidechain_number: SidechainNumber(*sidechain_number),
+                            address: vec![*sidechain_number; 20].try_into().unwrap(),
+                        };
+                        let mut input = vec![self.funding_input()];
+                        let ctip_value = if let Some((ctip_outpoint, ctip_value)) =
+                            ctips.get(sidechain_number)
+                        {
+                            input.push(TxIn {
+                                previous_output: *ctip_outpoint,
+                                ..TxIn::default()
+                            });
+                            *ctip_value
+                        } else {
+                            Amount::ZERO
+                        };
+                        Transaction {
+                            version: TxVersion::TWO,
+                            lock_time: LockTime::ZERO,
+                            input,
+                            output: destination
+                                .outputs(ctip_value, Amount::from_sat(*value_sats))
+                                .to_vec(),
+                        }
+                    }
+                    TxSpec::Withdrawal { bundle } => {
+                        if self.bundles.is_empty() {
+                            continue;
+                        }
+                        self.bundles[bundle.index(self.bundles.len())].clone()
+                    }
+                };
+                if let Ok((_, sidechain_number)) =
+                    parse_op_drivechain(tx.output[0].script_pubkey.as_bytes())
+                {
+                    let ctip_outpoint = OutPoint {
+                        txid: tx.compute_txid(),
+                        vout: 0,
+                    };
+                    ctips.insert(sidechain_number.0, (ctip_outpoint, tx.output[0].value));
+                }
+                txdata.push(tx);
+            }
+            let mut block = Block {
+                header: Header {
+                    version: BlockVersion::NO_SOFT_FORK_SIGNALLING,
+                    prev_blockhash: self.tip.map_or_else(BlockHash::all_zeros, |(tip, _)| tip),
+                    merkle_root: TxMerkleNode::all_zeros(),
+                    time: height,
+                    bits: genesis_block(Network::Regtest).header.bits,
+                    nonce: 0,
+                },
+                txdata,
+            };
+            block.header.merkle_root = block.compute_merkle_root().unwrap();
+            block
+        }
+    }
+
+    /// Connect each block, checking that disconnecting it restores the
+    /// previous state. Invalid blocks are skipped.
+    /// Then disconnect all blocks, checking that each disconnect restores
+    /// the state from before the block was connected.
+    fn check_connect_disconnect(blocks: Vec<BlockSpec>) -> Result<(), TestCaseError> {
+        let mut chain = TestChain::new();
+        let mut connected = Vec::new();
+        for spec in activate_sidechain_blocks().into_iter().chain(blocks) {
+            let block = chain.build_block(&spec);
+            let block_hash = block.block_hash();
+            let height = chain.next_height();
+            let dbs = &chain.dbs;
+            let mut rwtxn = dbs.write_txn().unwrap();
+            let () = dbs
+                .block_hashes
+                .put_header(&mut rwtxn, &block.header, height)
+                .unwrap();
+            let before = dump_dbs(&rwtxn, dbs, &[]);
+            match connect_block(&mut rwtxn, dbs, &mut Vec::new(), &block, None, height) {
+                Ok(()) => (),
+                Err(err) => {
+                    let _invalid_block = err
+                        .split()
+                        .map_err(|err| TestCaseError::fail(format!("{err:#}")))?;
+                    continue;
+                }
+            }
+            let () = disconnect_block(&mut rwtxn, dbs, &mut Vec::new(), block_hash).unwrap();
+            prop_assert_eq!(&dump_dbs(&rwtxn, dbs, &[]), &before);
+            let () = connect_block(&mut rwtxn, dbs, &mut Vec::new(), &block, None, height)
+                .map_err(|err| TestCaseError::fail(format!("{err:#}")))?;
+            let () = rwtxn.commit().unwrap();
+            chain.tip = Some((block_hash, height));
+            connected.push((block_hash, without_header_dbs(before)));
+        }
+        let dbs = &chain.dbs;
+        let mut rwtxn = dbs.write_txn().unwrap();
+        for (block_hash, before) in connected.into_iter().rev() {
+            let () = disconnect_block(&mut rwtxn, dbs, &mut Vec::new(), block_hash).unwrap();
+            prop_assert_eq!(dump_dbs(&rwtxn, dbs, &HEADER_DBS), before);
+        }
+        Ok(())
+    }
+
+    /// Record a fixture for each block that activates a sidechain and
+    /// deposits to it, and check that each fixture replays cleanly.
+    /// Blocks with BMM commitments only are not recorded.
+    #[test]
+    fn test_record_replay_fixtures() {
+        let mut chain = TestChain::new();
+        let fixtures_dir = TempDir::new().unwrap();
+        let deposit = BlockSpec {
+            messages: Vec::new(),
+            transactions: vec![TxSpec::Deposit {
+                sidechain_number: 0,
+                value_sats: 50_000,
+            }],
+        };
+        let bmm_accept = BlockSpec {
+            messages: vec![MessageSpec::BmmAccept {
+                sidechain_number: 0,
+                sidechain_block_hash: [1; 32],
+            }],
+            transactions: Vec::new(),
+        };
+        let specs: Vec<_> = activate_sidechain_blocks()
+            .into_iter()
+            .chain([deposit, bmm_accept])
+            .collect();
+        let mut recorded = Vec::new();
+        for spec in &specs {
+            let block = chain.build_block(spec);
+            let height = chain.next_height();
+            let dbs = &chain.dbs;
+            let mut rwtxn = dbs.write_txn().unwrap();
+            let () = dbs
+                .block_hashes
+                .put_header(&mut rwtxn, &block.header, height)
+                .unwrap();
+            let () = connect_block(&mut rwtxn, dbs, &mut Vec::new(), &block, None, height).unwrap();
+            let () = rwtxn.commit().unwrap();
+            let rotxn = dbs.read_txn().unwrap();
+            recorded.extend(
+                fixtures::record(&rotxn, dbs, fixtures_dir.path(), &block, None, height).unwrap(),
+            );
+            chain.tip = Some((block.block_hash(), height));
+        }
+        assert_eq!(recorded.len(), specs.len() - 1);
+        for path in recorded {
+            let fixture = fixtures::load(&path).unwrap();
+            assert_eq!(fixtures::replay(&fixture).unwrap(), Vec::<String>::new());
+        }
+    }
+
+    /// Connect a block built from the spec, committing if it is valid
+    fn try_connect(chain: &mut TestChain, spec: &BlockSpec) -> Result<(), error::ConnectBlock> {
+        let block = chain.build_block(spec);
+        try_connect_block(chain, &block)
+    }
+
+    /// Connect a block on the tip, committing if it is valid
+    fn try_connect_block(chain: &mut TestChain, block: &Block) -> Result<(), error::ConnectBlock> {
+        let height = chain.next_height();
+        let dbs = &chain.dbs;
+        let mut rwtxn = dbs.write_txn().unwrap();
+        let () = dbs
+            .block_hashes
+            .put_header(&mut rwtxn, &block.header, height)
+            .unwrap();
+        let () = connect_block(&mut rwtxn, dbs, &mut Vec::new(), block, None, height)?;
+        let () = rwtxn.commit().unwrap();
+        chain.tip = Some((block.block_hash(), height));
+        Ok(())
+    }
+
+    /// Treasury outputs are indexed when connected without prevouts, and
+    /// remain indexed after they are spent, so that spends of treasury
+    /// outputs other than CTIPs are detected on every fetch path
+    #[test]
+    fn test_treasury_outputs_indexed() {
+        let mut chain = TestChain::new();
+        for spec in activate_sidechain_blocks() {
+            let () = try_connect(&mut chain, &spec).unwrap();
+        }
+        let deposit = BlockSpec {
+            messages: Vec::new(),
+            transactions: vec![TxSpec::Deposit {
+                sidechain_number: 0,
+                value_sats: 1000,
+            }],
+        };
+        let ctip = |chain: &TestChain| {
+            let rotxn = chain.dbs.read_txn().unwrap();
+            chain
+                .dbs
+                .active_sidechains
+                .ctip
+                .get(&rotxn, &SidechainNumber(0))
+                .unwrap()
+        };
+        let () = try_connect(&mut chain, &deposit).unwrap();
+        let first_ctip = ctip(&chain);
+        let () = try_connect(&mut chain, &deposit).unwrap();
+        let second_ctip = ctip(&chain);
+        assert_ne!(first_ctip.outpoint, second_ctip.outpoint);
+        let indexed = |chain: &TestChain, outpoint: &OutPoint| {
+            let rotxn = chain.dbs.read_txn().unwrap();
+            chain
+                .dbs
+                .treasury_outpoint_to_sidechain
+                .try_get(&rotxn, outpoint)
+                .unwrap()
+        };
+        // The first CTIP was spent by the second deposit
+        assert_eq!(indexed(&chain, &first_ctip.outpoint), None);
+        assert_eq!(
+            indexed(&chain, &second_ctip.outpoint),
+            Some(SidechainNumber(0))
+        );
+        let (tip, _) = chain.tip.unwrap();
+        let mut rwtxn = chain.dbs.write_txn().unwrap();
+        let () = disconnect_block(&mut rwtxn, &chain.dbs, &mut Vec::new(), tip).unwrap();
+        let () = rwtxn.commit().unwrap();
+        assert_eq!(
+            indexed(&chain, &first_ctip.outpoint),
+            Some(SidechainNumber(0))
+        );
+        assert_eq!(indexed(&chain, &second_ctip.outpoint), None);
+    }
+
+    /// Proposing an M6ID that is already pending for the slot invalidates
+    /// the block, whether the duplicate is in the same block or a later one
+    #[test]
+    fn test_duplicate_m3_rejected() {
+        let mut chain = TestChain::new();
+        for spec in activate_sidechain_blocks() {
+            let () = try_connect(&mut chain, &spec).unwrap();
+        }
+        let deposit = BlockSpec {
+            messages: Vec::new(),
+            transactions: vec![TxSpec::Deposit {
+                sidechain_number: 0,
+                value_sats: 50_000,
+            }],
+        };
+        let () = try_connect(&mut chain, &deposit).unwrap();
+        let propose = MessageSpec::ProposeBundle {
+            sidechain_number: 0,
+            payout_sats: 10_000,
+        };
+        let duplicate_in_block = BlockSpec {
+            messages: vec![propose.clone(), propose.clone()],
+            transactions: Vec::new(),
+        };
+        assert!(matches!(
+            try_connect(&mut chain, &duplicate_in_block),
+            Err(error::ConnectBlock::M3ProposeBundle(
+                error::HandleM3ProposeBundle::DuplicateM6id { .. }
+            ))
+        ));
+        let propose_once = BlockSpec {
+            messages: vec![propose],
+            transactions: Vec::new(),
+        };
+        let () = try_connect(&mut chain, &propose_once).unwrap();
+        assert!(matches!(
+            try_connect(&mut chain, &propose_once),
+            Err(error::ConnectBlock::M3ProposeBundle(
+                error::HandleM3ProposeBundle::DuplicateM6id { .. }
+            ))
+        ));
+        let rotxn = chain.dbs.read_txn().unwrap();
+        let pending_m6ids = chain
+            .dbs
+            .active_sidechains
+            .pending_m6ids
+            .get(&rotxn, &SidechainNumber(0))
+            .unwrap();
+        assert_eq!(pending_m6ids.len(), 1);
+    }
+
+    /// Pending bundles fail once they are older than
+    /// [`WITHDRAWAL_BUNDLE_MAX_AGE`], regardless of their votes
+    #[test]
+    fn test_pending_bundle_expiry() {
+        let mut chain = TestChain::new();
+        for spec in activate_sidechain_blocks() {
+            let () = try_connect(&mut chain, &spec).unwrap();
+        }
+        let propose = BlockSpec {
+            messages: vec![MessageSpec::ProposeBundle {
+                sidechain_number: 0,
+                payout_sats: 0,
+            }],
+            transactions: Vec::new(),
+        };
+        let () = try_connect(&mut chain, &propose).unwrap();
+        let proposal_height = chain.tip.unwrap().1;
+        let upvote = BlockSpec {
+            messages: vec![MessageSpec::AckBundles(M4AckBundles::OneByte {
+                upvotes: vec![0],
+            })],
+            transactions: Vec::new(),
+        };
+        let pending_m6ids = |chain: &TestChain| {
+            let rotxn = chain.dbs.read_txn().unwrap();
+            chain
+                .dbs
+                .active_sidechains
+                .pending_m6ids
+                .try_get(&rotxn, &SidechainNumber(0))
+                .unwrap()
+                .unwrap_or_default()
+        };
+        for _ in 0..WITHDRAWAL_BUNDLE_MAX_AGE {
+            let () = try_connect(&mut chain, &upvote).unwrap();
+        }
+        let pending = pending_m6ids(&chain);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].proposal_height, proposal_height);
+        assert_eq!(pending[0].vote_count, WITHDRAWAL_BUNDLE_MAX_AGE);
+        let () = try_connect(&mut chain, &upvote).unwrap();
+        assert!(pending_m6ids(&chain).is_empty());
+        let rotxn = chain.dbs.read_txn().unwrap();
+        let outcomes: Vec<_> = chain
+            .dbs
+            .withdrawal_bundle_outcomes
+            .iter(&rotxn)
+            .unwrap()
+            .collect()
+            .unwrap();
+        assert_eq!(outcomes.len(), 1);
+        let ((sidechain_number, _m6id), outcomes) = &outcomes[0];
+        assert_eq!(*sidechain_number, SidechainNumber(0));
+        assert!(matches!(
+            outcomes.as_slice(),
+            [WithdrawalBundleOutcome::Failed {
+                reason: WithdrawalBundleFailureReason::Expired,
+                ..
+            }]
+        ));
+        let stats = chain
+            .dbs
+            .active_sidechains
+            .stats
+            .get(&rotxn, &SidechainNumber(0))
+            .unwrap();
+        assert_eq!(stats.failed_withdrawal_bundle_count, 1);
+        assert_eq!(stats.succeeded_withdrawal_bundle_count, 0);
+    }
+
+    /// Deposits are added to the sidechain stats, and removed again when
+    /// the block that included them is disconnected
+    #[test]
+    fn test_sidechain_stats() {
+        let mut chain = TestChain::new();
+        for spec in activate_sidechain_blocks() {
+            let () = try_connect(&mut chain, &spec).unwrap();
+        }
+        let deposit = |value_sats| BlockSpec {
+            messages: Vec::new(),
+            transactions: vec![TxSpec::Deposit {
+                sidechain_number: 0,
+                value_sats,
+            }],
+        };
+        let stats = |chain: &TestChain| {
+            let rotxn = chain.dbs.read_txn().unwrap();
+            chain
+                .dbs
+                .active_sidechains
+                .stats
+                .try_get(&rotxn, &SidechainNumber(0))
+                .unwrap()
+                .unwrap_or_default()
+        };
+        let () = try_connect(&mut chain, &deposit(1000)).unwrap();
+        let () = try_connect(&mut chain, &deposit(500)).unwrap();
+        let expected = SidechainStats {
+            total_deposited: Amount::from_sat(1500),
+            deposit_count: 2,
+            ..SidechainStats::default()
+        };
+        assert_eq!(stats(&chain), expected);
+        let (tip, _) = chain.tip.unwrap();
+        let mut rwtxn = chain.dbs.write_txn().unwrap();
+        let () = disconnect_block(&mut rwtxn, &chain.dbs, &mut Vec::new(), tip).unwrap();
+        let () = rwtxn.commit().unwrap();
+        let expected = SidechainStats {
+            total_deposited: Amount::from_sat(1000),
+            deposit_count: 1,
+            ..SidechainStats::default()
+        };
+        assert_eq!(stats(&chain), expected);
+    }
+
+    #[test]
+    fn test_check_transaction() {
+        let mut chain = TestChain::new();
+        for spec in activate_sidechain_blocks() {
+            let () = try_connect(&mut chain, &spec).unwrap();
+        }
+        let deposit = BlockSpec {
+            messages: Vec::new(),
+            transactions: vec![TxSpec::Deposit {
+                sidechain_number: 0,
+                value_sats: 1000,
+            }],
+        };
+        let () = try_connect(&mut chain, &deposit).unwrap();
+        let rotxn = chain.dbs.read_txn().unwrap();
+        let ctip = chain
+            .dbs
+            .active_sidechains
+            .ctip
+            .get(&rotxn, &SidechainNumber(0))
+            .unwrap();
+        let tx = |input: Vec<OutPoint>, output: Vec<TxOut>| Transaction {
+            version: TxVersion::TWO,
+            lock_time: LockTime::ZERO,
+            input: input
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    ..TxIn::default()
+                })
+                .collect(),
+            output,
+        };
+        let deposit_outputs = |sidechain_number: u8, ctip_value: Amount| {
+            let destination = DepositDestination {
+                sidechain_number: SidechainNumber(sidechain_number),
+                address: vec![sidechain_number; 20].try_into().unwrap(),
+            };
+            destination
+                .outputs(ctip_value, Amount::from_sat(500))
+                .to_vec()
+        };
+        let funding = OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 1,
+        };
+        let check = |tx: &Transaction| check_transaction(&rotxn, &chain.dbs, tx);
+        let rejection = |tx: &Transaction| match check(tx) {
+            Err(CheckTransactionError::Rejected(rejection)) => rejection,
+            res => panic!("expected rejection, got {res:?}"),
+        };
+
+        let valid_deposit = tx(vec![funding, ctip.outpoint], deposit_outputs(0, ctip.value));
+        assert!(matches!(
+            check(&valid_deposit).unwrap(),
+            CheckedTransaction::Deposit {
+                sidechain_number: SidechainNumber(0),
+                value,
+            } if value == Amount::from_sat(500)
+        ));
+        let ctip_unspent = tx(vec![funding], deposit_outputs(0, ctip.value));
+        assert!(matches!(
+            rejection(&ctip_unspent),
+            TransactionRejection::CtipUnspent { .. }
+        ));
+        let inactive = tx(vec![funding], deposit_outputs(1, Amount::ZERO));
+        assert!(matches!(
+            rejection(&inactive),
+            TransactionRejection::InactiveSidechain(SidechainNumber(1))
+        ));
+        let mut missing_address = valid_deposit.clone();
+        missing_address.output.truncate(1);
+        assert!(matches!(
+            rejection(&missing_address),
+            TransactionRejection::MissingDepositAddress { .. }
+        ));
+        let spends_ctip = tx(
+            vec![ctip.outpoint],
+            vec![TxOut {
+                value: ctip.value,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        );
+        assert!(matches!(
+            rejection(&spends_ctip),
+            TransactionRejection::CtipSpentWithoutTreasuryOutput { .. }
+        ));
+        let unapproved_m6 = tx(
+            vec![ctip.outpoint],
+            vec![
+                create_m5_deposit_output(
+                    SidechainNumber(0),
+                    Amount::ZERO,
+                    ctip.value - Amount::from_sat(100),
+                ),
+                TxOut {
+                    value: Amount::from_sat(100),
+                    script_pubkey: ScriptBuf::new(),
+                },
+            ],
+        );
+        assert!(matches!(
+            rejection(&unapproved_m6),
+            TransactionRejection::M6NotApproved { .. }
+        ));
+        let other = tx(
+            vec![funding],
+            vec![TxOut {
+                value: Amount::from_sat(100),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        );
+        assert!(matches!(check(&other).unwrap(), CheckedTransaction::Other));
+    }
+
+    /// Chain served by [`MockClient`]
+    #[derive(Default)]
+    struct MockChain {
+        headers: HashMap<BlockHash, (Header, u32)>,
+        blocks: HashMap<BlockHash, Block>,
+        tip: Option<(BlockHash, u32)>,
+        /// Number of requests to respond to before failing a request
+        fail_after: Option<usize>,
+    }
+
+    /// Serves a canned chain in place of a node. Requests can be made to
+    /// fail, or to respond slowly, and the tip can be switched to another
+    /// branch to simulate a reorg.
+    #[derive(Default)]
+    struct MockClient {
+        chain: parking_lot::Mutex<MockChain>,
+        /// Delay before each response
+        delay: Duration,
+    }
+
+    impl MockClient {
+        fn with_delay(delay: Duration) -> Self {
+            Self {
+                delay,
+                ..Self::default()
+            }
+        }
+
+        fn tip(&self) -> BlockHash {
+            self.chain.lock().tip.unwrap().0
+        }
+
+        /// Switch the tip to a known block, so that subsequent blocks are
+        /// mined on a different branch
+        fn set_tip(&self, block_hash: BlockHash) {
+            let mut chain = self.chain.lock();
+            let (_header, height) = chain.headers[&block_hash];
+            chain.tip = Some((block_hash, height));
+        }
+
+        /// Add a block on the tip
+        fn push_block(&self, block: Block) -> BlockHash {
+            let mut chain = self.chain.lock();
+            let height = chain.tip.map_or(0, |(_, tip_height)| tip_height + 1);
+            let block_hash = block.block_hash();
+            chain.headers.insert(block_hash, (block.header, height));
+            chain.blocks.insert(block_hash, block);
+            chain.tip = Some((block_hash, height));
+            block_hash
+        }
+
+        /// Respond to `count` requests, then fail the next request
+        fn fail_after(&self, count: usize) {
+            self.chain.lock().fail_after = Some(count);
+        }
+
+        /// Mine `count` coinbase-only blocks on the tip. `branch` is
+        /// included in each coinbase, so that blocks mined on different
+        /// branches at the same height are distinct.
+        fn mine(&self, count: usize, branch: i64) -> Vec<BlockHash> {
+            let mut chain = self.chain.lock();
+            let mut block_hashes = Vec::with_capacity(count);
+            for _ in 0..count {
+                let (prev_blockhash, height) = match chain.tip {
+                    Some((tip, tip_height)) => (tip, tip_height + 1),
+                    None => (BlockHash::all_zeros(), 0),
+                };
+                let coinbase = Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: LockTime::ZERO,
+                    input: vec![TxIn {
+                        script_sig: ScriptBuilder::new()
+                            .push_int(height as i64)
+                            .push_int(branch)
+                            .into_script(),
+                        ..TxIn::default()
+                    }],
+                    output: Vec::new(),
+                };
+                let mut block = Block {
+                    header: Header {
+                        version: BlockVersion::NO_SOFT_FORK_SIGNALLING,
+                        prev_blockhash,
+                        merkle_root: TxMerkleNode::all_zeros(),
+                        time: height,
+                        bits: genesis_block(Network::Regtest).header.bits,
+                        nonce: 0,
+                    },
+                    txdata: vec![coinbase],
+                };
+                block.header.merkle_root = block.compute_merkle_root().unwrap();
+                let block_hash = block.block_hash();
+                chain.headers.insert(block_hash, (block.header, height));
+                chain.blocks.insert(block_hash, block);
+                chain.tip = Some((block_hash, height));
+                block_hashes.push(block_hash);
+            }
+            block_hashes
+        }
+
+        async fn respond<T, F>(&self, method: &str, f: F) -> Result<T, error::Sync>
+        where
+            F: FnOnce(&MockChain) -> Option<T>,
+        {
+            if !self.delay.is_zero() {
+                tokio::time::sleep(self.delay).await;
+            }
+            let mut chain = self.chain.lock();
+            let err = |msg: &str| error::Sync::JsonRpc {
+                method: method.to_owned(),
+                source: ClientError::Custom(msg.to_owned()),
+            };
+            match chain.fail_after {
+                Some(0) => {
+                    chain.fail_after = None;
+                    return Err(err("injected failure"));
+                }
+                Some(count) => chain.fail_after = Some(count - 1),
+                None => (),
+            }
+            f(&chain).ok_or_else(|| err("not found"))
+        }
+    }
+
+    impl MainchainClient for MockClient {
+        async fn get_best_block_hash(&self) -> Result<BlockHash, error::Sync> {
+            self.respond("getbestblockhash", |chain| {
+                chain.tip.map(|(block_hash, _)| block_hash)
+            })
+            .await
+        }
+
+        async fn get_prune_height(&self) -> Result<Option<u32>, error::Sync> {
+            self.respond("getblockchaininfo", |_| Some(None)).await
+        }
+
+        async fn get_header(&self, block_hash: BlockHash) -> Result<(Header, u32), error::Sync> {
+            self.respond("getblockheader", |chain| {
+                chain.headers.get(&block_hash).copied()
+            })
+            .await
+        }
+
+        /// Returns up to 4 ancestors, so that both batched and single
+        /// header requests are exercised
+        async fn get_ancestor_headers(
+            &self,
+            header: &Header,
+            _height: u32,
+        ) -> Result<Vec<(Header, u32)>, error::Sync> {
+            self.respond("getblockheader", |chain| {
+                let mut res = Vec::new();
+                let mut prev_blockhash = header.prev_blockhash;
+                while res.len() < 4 {
+                    let Some((header, height)) = chain.headers.get(&prev_blockhash) else {
+                        break;
+                    };
+                    prev_blockhash = header.prev_blockhash;
+                    res.push((*header, *height));
+                }
+                Some(res)
+            })
+            .await
+        }
+
+        async fn get_blocks(
+            &self,
+            block_hashes: &[BlockHash],
+            _capabilities: NodeCapabilities,
+        ) -> Result<Vec<(Block, Option<Prevouts>)>, error::Sync> {
+            self.respond("getblock", |chain| {
+                block_hashes
+                    .iter()
+                    .map(|block_hash| Some((chain.blocks.get(block_hash)?.clone(), None)))
+                    .collect()
+            })
+            .await
+        }
+    }
+
+    async fn sync_mock(chain: &TestChain, client: &MockClient) -> Result<(), error::Sync> {
+        sync_to_tip(
+            &chain.dbs,
+            &SyncProgress::default(),
+            &chain.event_tx,
+            client,
+            client.tip(),
+            None,
+            &DepositConfirmationDepths::default(),
+            None,
+            NodeCapabilities::default(),
+        )
+        .await
+    }
+
+    /// Check that the validator tip and best chain match the mock client
+    fn assert_synced(chain: &TestChain, best_chain: &[BlockHash]) {
+        let rotxn = chain.dbs.read_txn().unwrap();
+        assert_eq!(
+            chain
+                .dbs
+                .current_chain_tip
+                .try_get(&rotxn, &UnitKey)
+                .unwrap(),
+            best_chain.last().copied()
+        );
+        let synced_best_chain: BTreeMap<u32, BlockHash> = chain
+            .dbs
+            .best_chain_height_to_block_hash
+            .iter(&rotxn)
+            .unwrap()
+            .collect()
+            .unwrap();
+        let expected_best_chain: BTreeMap<u32, BlockHash> =
+            (0..).zip(best_chain.iter().copied()).collect();
+        assert_eq!(synced_best_chain, expected_best_chain);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_mock_chain() {
+        let chain = TestChain::new();
+        let client = MockClient::default();
+        let block_hashes = client.mine(40, 0);
+        let () = sync_mock(&chain, &client).await.unwrap();
+        assert_synced(&chain, &block_hashes);
+        // Already synced
+        let () = sync_mock(&chain, &client).await.unwrap();
+        assert_synced(&chain, &block_hashes);
+    }
+
+    /// Events are broadcast in order once each batch of blocks is committed
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_batched_events() {
+        let chain = TestChain::new();
+        let client = MockClient::default();
+        let block_hashes = client.mine(CONNECT_BATCH_MAX_BLOCKS + 10, 0);
+        let (event_tx, mut event_rx) = broadcast(block_hashes.len());
+        let () = sync_to_tip(
+            &chain.dbs,
+            &SyncProgress::default(),
+            &event_tx,
+            &client,
+            client.tip(),
+            None,
+            &DepositConfirmationDepths::default(),
+            None,
+            NodeCapabilities::default(),
+        )
+        .await
+        .unwrap();
+        assert_synced(&chain, &block_hashes);
+        for (expected_sequence, block_hash) in (0..).zip(&block_hashes) {
+            let (sequence, event) = event_rx.try_recv().unwrap();
+            assert_eq!(sequence, expected_sequence);
+            let Event::ConnectBlock { header_info, .. } = event else {
+                panic!("expected `ConnectBlock` event, got {event:?}");
+            };
+            assert_eq!(header_info.block_hash, *block_hash);
+        }
+        assert!(event_rx.is_empty());
+    }
+
+    /// Failed requests abort the sync, and the sync resumes from where it
+    /// stopped on retry
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_rpc_failure() {
+        const MAX_ATTEMPTS: usize = 100;
+        let chain = TestChain::new();
+        let client = MockClient::default();
+        let mut block_hashes = client.mine(10, 0);
+        let () = sync_mock(&chain, &client).await.unwrap();
+        block_hashes.extend(client.mine(30, 0));
+        let mut failed_attempts = 0;
+        loop {
+            client.fail_after(2);
+            match sync_mock(&chain, &client).await {
+                Ok(()) => break,
+                Err(err) => assert!(matches!(err, error::Sync::JsonRpc { .. }), "{err:#}"),
+            }
+            failed_attempts += 1;
+            assert!(failed_attempts < MAX_ATTEMPTS, "sync did not make progress");
+        }
+        assert!(failed_attempts > 0);
+        assert_synced(&chain, &block_hashes);
+    }
+
+    /// Switching to a branch with more work updates the best chain
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_reorg() {
+        let chain = TestChain::new();
+        let client = MockClient::default();
+        let mut block_hashes = client.mine(10, 0);
+        let () = sync_mock(&chain, &client).await.unwrap();
+        assert_synced(&chain, &block_hashes);
+        // A shorter branch does not change the best chain
+        client.set_tip(block_hashes[5]);
+        let _: Vec<BlockHash> = client.mine(3, 1);
+        let () = sync_mock(&chain, &client).await.unwrap();
+        assert_synced(&chain, &block_hashes);
+        client.set_tip(block_hashes[5]);
+        block_hashes.truncate(6);
+        block_hashes.extend(client.mine(6, 2));
+        let () = sync_mock(&chain, &client).await.unwrap();
+        assert_synced(&chain, &block_hashes);
+    }
+
+    /// Deposit confirmation events, as the event kind, the block hash that
+    /// changed the tip, the deposit block hash, and the confirmations
+    fn deposit_events(
+        event_rx: &mut Receiver<(u64, Event)>,
+    ) -> Vec<(&'static str, BlockHash, BlockHash, u32)> {
+        std::iter::from_fn(|| event_rx.try_recv().ok())
+            .filter_map(|(_, event)| match event {
+                Event::DepositConfirmed {
+                    block_hash,
+                    confirmation,
+                } => Some(("confirmed", block_hash, confirmation)),
+                Event::DepositUnconfirmed {
+                    block_hash,
+                    confirmation,
+                } => Some(("unconfirmed", block_hash, confirmation)),
+                _ => None,
+            })
+            .map(|(kind, block_hash, confirmation)| {
+                (
+                    kind,
+                    block_hash,
+                    confirmation.deposit_block_hash,
+                    confirmation.confirmations,
+                )
+            })
+            .collect()
+    }
+
+    /// Reorgs to a branch with more work unconfirm deposits that are not in
+    /// the new best chain, and reorgs back confirm them again
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_reorg_deposit_confirmations() {
+        let chain = TestChain::new();
+        let client = MockClient::default();
+        let mut miner = TestChain::new();
+        let deposit = BlockSpec {
+            messages: Vec::new(),
+            transactions: vec![TxSpec::Deposit {
+                sidechain_number: 0,
+                value_sats: 50_000,
+            }],
+        };
+        let mut block_hashes = Vec::new();
+        for spec in activate_sidechain_blocks().into_iter().chain([deposit]) {
+            let block = miner.build_block(&spec);
+            let () = try_connect_block(&mut miner, &block).unwrap();
+            block_hashes.push(client.push_block(block));
+        }
+        let deposit_block_hash = *block_hashes.last().unwrap();
+        block_hashes.extend(client.mine(1, 0));
+        let depths = DepositConfirmationDepths::new(["2".parse().unwrap()]);
+        let (event_tx, mut event_rx) = broadcast(64);
+        let sync_progress = SyncProgress::default();
+        let sync = || {
+            sync_to_tip(
+                &chain.dbs,
+                &sync_progress,
+                &event_tx,
+                &client,
+                client.tip(),
+                None,
+                &depths,
+                None,
+                NodeCapabilities::default(),
+            )
+        };
+        let () = sync().await.unwrap();
+        assert_synced(&chain, &block_hashes);
+        assert_eq!(
+            deposit_events(&mut event_rx),
+            vec![("confirmed", block_hashes[8], deposit_block_hash, 2)]
+        );
+        // The tip only changes to a branch without the deposit once the
+        // branch has more work
+        client.set_tip(block_hashes[6]);
+        let branch = client.mine(3, 1);
+        let () = sync().await.unwrap();
+        let mut branch_best_chain = block_hashes[..7].to_vec();
+        branch_best_chain.extend(&branch);
+        assert_synced(&chain, &branch_best_chain);
+        assert_eq!(
+            deposit_events(&mut event_rx),
+            vec![("unconfirmed", branch[2], deposit_block_hash, 0)]
+        );
+        client.set_tip(block_hashes[8]);
+        block_hashes.extend(client.mine(2, 0));
+        let () = sync().await.unwrap();
+        assert_synced(&chain, &block_hashes);
+        assert_eq!(
+            deposit_events(&mut event_rx),
+            vec![("confirmed", block_hashes[10], deposit_block_hash, 4)]
+        );
+    }
+
+    /// Syncs that are cancelled while waiting for slow responses leave the
+    /// DBs consistent, so that a later sync completes
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sync_slow_responses() {
+        let chain = TestChain::new();
+        let client = MockClient::with_delay(Duration::from_millis(5));
+        let block_hashes = client.mine(40, 0);
+        for _ in 0..3 {
+            let _: Result<Result<(), error::Sync>, Elapsed> =
+                tokio::time::timeout(Duration::from_millis(20), sync_mock(&chain, &client)).await;
+        }
+        let () = sync_mock(&chain, &client).await.unwrap();
+        assert_synced(&chain, &block_hashes);
+        let problems = chain.dbs.check().unwrap();
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_connect_disconnect_inverse(blocks in vec(block_strategy(), 0..16)) {
+            check_connect_disconnect(blocks)?;
+        }
+    }
 }
@@ -0,0 +1,220 @@
+//! Contextual checks for BIP300/301 transactions, before they are
+//! broadcast.
+//!
+//! Checks mirror the rules applied when connecting blocks, against the
+//! state at the current tip. Transactions that pass may still be invalid
+//! by the time they are included in a block, eg. if a competing M6 is
+//! included first.
+
+use bitcoin::{hashes::Hash as _, Amount, OutPoint, Transaction};
+use heed::RoTxn;
+use miette::Diagnostic;
+use thiserror::Error;
+
+use super::WITHDRAWAL_BUNDLE_INCLUSION_THRESHOLD;
+use crate::{
+    deposit_address::parse_address,
+    messages::{m6_to_id, parse_m8_bmm_request, parse_op_drivechain, M6LayoutError},
+    types::{Hash256, SidechainNumber},
+    validator::dbs::{db_error, Dbs, UnitKey},
+};
+
+/// A transaction that passed [`check_transaction`]
+#[derive(Clone, Copy, Debug)]
+pub enum CheckedTransaction {
+    /// M5 deposit
+    Deposit {
+        sidechain_number: SidechainNumber,
+        value: Amount,
+    },
+    /// M6 withdrawal
+    Withdrawal {
+        sidechain_number: SidechainNumber,
+        m6id: Hash256,
+    },
+    /// M8 BMM request
+    BmmRequest { sidechain_number: SidechainNumber },
+    /// Not a BIP300/301 transaction
+    Other,
+}
+
+/// Reasons for which a BIP300/301 transaction would not be accepted at the
+/// current tip
+#[derive(Debug, Diagnostic, Error)]
+pub enum TransactionRejection {
+    #[error("BMM request commits to block `{prev_mainchain_block_hash}`, but the tip is `{tip}`")]
+    #[diagnostic(code(transaction_rejection::bmm_request_expired))]
+    BmmRequestExpired {
+        prev_mainchain_block_hash: bitcoin::BlockHash,
+        tip: bitcoin::BlockHash,
+    },
+    #[error(
+        "CTIP `{ctip}` for sidechain {sidechain_number} is spent without an OP_DRIVECHAIN output"
+    )]
+    #[diagnostic(code(transaction_rejection::ctip_spent_without_treasury_output))]
+    CtipSpentWithoutTreasuryOutput {
+        sidechain_number: SidechainNumber,
+        ctip: OutPoint,
+    },
+    #[error("CTIP `{ctip}` for sidechain {sidechain_number} is not spent")]
+    #[diagnostic(code(transaction_rejection::ctip_unspent))]
+    CtipUnspent {
+        sidechain_number: SidechainNumber,
+        ctip: OutPoint,
+    },
+    #[error("Deposit to sidechain {sidechain_number} does not increase the treasury value")]
+    #[diagnostic(code(transaction_rejection::empty_deposit))]
+    EmptyDeposit { sidechain_number: SidechainNumber },
+    #[error("Sidechain {0} is not active")]
+    #[diagnostic(code(transaction_rejection::inactive_sidechain))]
+    InactiveSidechain(SidechainNumber),
+    #[error("Invalid M6 layout")]
+    #[diagnostic(code(transaction_rejection::invalid_m6_layout))]
+    InvalidM6Layout(#[from] M6LayoutError),
+    #[error("Deposit to sidechain {sidechain_number} has no deposit address output")]
+    #[diagnostic(code(transaction_rejection::missing_deposit_address))]
+    MissingDepositAddress { sidechain_number: SidechainNumber },
+    #[error(
+        "M6 with M6ID `{}` for sidechain {sidechain_number} has not been approved",
+        hex::encode(m6id)
+    )]
+    #[diagnostic(code(transaction_rejection::m6_not_approved))]
+    M6NotApproved {
+        sidechain_number: SidechainNumber,
+        m6id: Hash256,
+    },
+}
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum CheckTransactionError {
+    #[error(transparent)]
+    DbTryGet(#[from] db_error::TryGet),
+    #[error(transparent)]
+    Rejected(#[from] TransactionRejection),
+}
+
+fn is_active(
+    rotxn: &RoTxn,
+    dbs: &Dbs,
+    sidechain_number: SidechainNumber,
+) -> Result<bool, db_error::TryGet> {
+    dbs.active_sidechains
+        .sidechain
+        .try_get(rotxn, &sidechain_number)
+        .map(|sidechain| sidechain.is_some())
+}
+
+/// Check a transaction against the state at the current tip
+pub fn check_transaction(
+    rotxn: &RoTxn,
+    dbs: &Dbs,
+    transaction: &Transaction,
+) -> Result<CheckedTransaction, CheckTransactionError> {
+    let first_output_script = transaction
+        .output
+        .first()
+        .map(|output| output.script_pubkey.as_bytes());
+    if let Some((_, bmm_request)) =
+        first_output_script.and_then(|script| parse_m8_bmm_request(script).ok())
+    {
+        let sidechain_number = bmm_request.sidechain_number;
+        if !is_active(rotxn, dbs, sidechain_number)? {
+            return Err(TransactionRejection::InactiveSidechain(sidechain_number).into());
+        }
+        // BMM requests are only valid in the block after the tip
+        if let Some(tip) = dbs.current_chain_tip.try_get(rotxn, &UnitKey)? {
+            let prev_mainchain_block_hash =
+                bitcoin::BlockHash::from_byte_array(bmm_request.prev_mainchain_block_hash);
+            if prev_mainchain_block_hash != tip {
+                let err = TransactionRejection::BmmRequestExpired {
+                    prev_mainchain_block_hash,
+                    tip,
+                };
+                return Err(err.into());
+            }
+        }
+        return Ok(CheckedTransaction::BmmRequest { sidechain_number });
+    }
+    let mut spent_ctips = Vec::new();
+    for input in &transaction.input {
+        if let Some(sidechain_number) = dbs
+            .ctip_outpoint_to_sidechain
+            .try_get(rotxn, &input.previous_output)?
+        {
+            spent_ctips.push((sidechain_number, input.previous_output));
+        }
+    }
+    let Some(sidechain_number) = first_output_script.and_then(|script| {
+        parse_op_drivechain(script)
+            .ok()
+            .map(|(_input, sidechain_number)| sidechain_number)
+    }) else {
+        if let Some((sidechain_number, ctip)) = spent_ctips.first() {
+            let err = TransactionRejection::CtipSpentWithoutTreasuryOutput {
+                sidechain_number: *sidechain_number,
+                ctip: *ctip,
+            };
+            return Err(err.into());
+        }
+        return Ok(CheckedTransaction::Other);
+    };
+    if !is_active(rotxn, dbs, sidechain_number)? {
+        return Err(TransactionRejection::InactiveSidechain(sidechain_number).into());
+    }
+    let old_total_value = match dbs
+        .active_sidechains
+        .ctip
+        .try_get(rotxn, &sidechain_number)?
+    {
+        Some(ctip) => {
+            if !spent_ctips
+                .iter()
+                .any(|(spent_sidechain, _)| *spent_sidechain == sidechain_number)
+            {
+                let err = TransactionRejection::CtipUnspent {
+                    sidechain_number,
+                    ctip: ctip.outpoint,
+                };
+                return Err(err.into());
+            }
+            ctip.value
+        }
+        None => Amount::ZERO,
+    };
+    let new_total_value = transaction.output[0].value;
+    if new_total_value < old_total_value {
+        let m6id =
+            m6_to_id(transaction, old_total_value.to_sat()).map_err(TransactionRejection::from)?;
+        let approved = dbs
+            .active_sidechains
+            .pending_m6ids
+            .try_get(rotxn, &sidechain_number)?
+            .unwrap_or_default()
+            .iter()
+            .any(|pending_m6id| {
+                pending_m6id.m6id == m6id
+                    && pending_m6id.vote_count > WITHDRAWAL_BUNDLE_INCLUSION_THRESHOLD
+            });
+        if !approved {
+            let err = TransactionRejection::M6NotApproved {
+                sidechain_number,
+                m6id,
+            };
+            return Err(err.into());
+        }
+        return Ok(CheckedTransaction::Withdrawal {
+            sidechain_number,
+            m6id,
+        });
+    }
+    if parse_address(&transaction.output).is_none() {
+        return Err(TransactionRejection::MissingDepositAddress { sidechain_number }.into());
+    }
+    if new_total_value == old_total_value {
+        return Err(TransactionRejection::EmptyDeposit { sidechain_number }.into());
+    }
+    Ok(CheckedTransaction::Deposit {
+        sidechain_number,
+        value: new_total_value - old_total_value,
+    })
+}
@@ -0,0 +1,183 @@
+//! Scanning of block transactions for BIP300/301 outputs.
+//!
+//! Scanning only parses transactions, and does not depend on validator
+//! state, so transactions in large blocks are scanned in parallel. State
+//! updates are applied afterwards, in transaction order.
+
+use bitcoin::{Block, OutPoint, Transaction, Txid};
+use rayon::iter::{IntoParallelRefIterator as _, ParallelIterator as _};
+
+use crate::{
+    messages::{parse_m8_bmm_request, parse_op_drivechain, M8BmmRequest},
+    rpc_client::Prevouts,
+    types::SidechainNumber,
+};
+
+/// Blocks with fewer transactions than this are scanned serially, since
+/// parallel scanning has more overhead than it saves for small blocks
+const PARALLEL_SCAN_MIN_TXS: usize = 256;
+
+/// A non-coinbase transaction, and the BIP300/301 outputs that it contains
+pub(in crate::validator::task) struct ScannedTx<'a> {
+    pub transaction: &'a Transaction,
+    pub txid: Txid,
+    /// Sidechain slot, if the first output is a valid OP_DRIVECHAIN output.
+    /// OP_DRIVECHAIN outputs that are invalid, eg. if OP_TRUE is missing,
+    /// are ignored.
+    pub drivechain_output: Option<SidechainNumber>,
+    /// Inputs that spend OP_DRIVECHAIN outputs, if prevouts are available.
+    /// Spends of indexed treasury outputs are detected without prevouts,
+    /// when the transaction is connected.
+    pub treasury_spends: Vec<(OutPoint, SidechainNumber)>,
+    /// BMM request, if the first output is an M8
+    pub bmm_request: Option<M8BmmRequest>,
+}
+
+fn scan_transaction<'a>(
+    transaction: &'a Transaction,
+    prevouts: Option<&Prevouts>,
+) -> ScannedTx<'a> {
+    let first_output_script = transaction
+        .output
+        .first()
+        .map(|output| output.script_pubkey.as_bytes());
+    let drivechain_output = first_output_script.and_then(|script| {
+        parse_op_drivechain(script)
+            .ok()
+            .map(|(_input, sidechain_number)| sidechain_number)
+    });
+    let bmm_request = first_output_script.and_then(|script| {
+        parse_m8_bmm_request(script)
+            .ok()
+            .map(|(_input, bmm_request)| bmm_request)
+    });
+    let treasury_spends = match prevouts {
+        Some(prevouts) => transaction
+            .input
+            .iter()
+            .filter_map(|input| {
+                let prevout = prevouts.get(&input.previous_output)?;
+                let (_, sidechain_number) =
+                    parse_op_drivechain(prevout.script_pubkey.as_bytes()).ok()?;
+                Some((input.previous_output, sidechain_number))
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    ScannedTx {
+        transaction,
+        txid: transaction.compute_txid(),
+        drivechain_output,
+        treasury_spends,
+        bmm_request,
+    }
+}
+
+/// Scan the non-coinbase transactions in a block, in order
+pub(in crate::validator::task) fn scan_block<'a>(
+    block: &'a Block,
+    prevouts: Option<&Prevouts>,
+) -> Vec<ScannedTx<'a>> {
+    let transactions = &block.txdata[1..];
+    if transactions.len() < PARALLEL_SCAN_MIN_TXS {
+        transactions
+            .iter()
+            .map(|transaction| scan_transaction(transaction, prevouts))
+            .collect()
+    } else {
+        transactions
+            .par_iter()
+            .map(|transaction| scan_transaction(transaction, prevouts))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        absolute::LockTime, block::Header, hashes::Hash as _, transaction::Version, Amount, Block,
+        OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid,
+    };
+
+    use super::{scan_block, scan_transaction, PARALLEL_SCAN_MIN_TXS};
+    use crate::{messages::create_m5_deposit_output, rpc_client::Prevouts, types::SidechainNumber};
+
+    /// Parallel scans of large blocks match serial scans, in order
+    #[test]
+    fn test_parallel_scan_matches_serial() {
+        let treasury_outpoint = OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 0,
+        };
+        let prevouts = Prevouts::from([(
+            treasury_outpoint,
+            create_m5_deposit_output(SidechainNumber(1), Amount::ZERO, Amount::ONE_BTC),
+        )]);
+        let txdata: Vec<Transaction> = (0..=PARALLEL_SCAN_MIN_TXS as u32 * 2)
+            .map(|index| {
+                let output = if index % 3 == 0 {
+                    create_m5_deposit_output(
+                        SidechainNumber((index % 7) as u8),
+                        Amount::ZERO,
+                        Amount::from_sat(index.into()),
+                    )
+                } else {
+                    TxOut {
+                        value: Amount::from_sat(index.into()),
+                        script_pubkey: ScriptBuf::new(),
+                    }
+                };
+                let previous_output = if index % 5 == 0 {
+                    treasury_outpoint
+                } else {
+                    OutPoint {
+                        txid: Txid::all_zeros(),
+                        vout: index,
+                    }
+                };
+                Transaction {
+                    version: Version::TWO,
+                    lock_time: LockTime::ZERO,
+                    input: vec![TxIn {
+                        previous_output,
+                        ..TxIn::default()
+                    }],
+                    output: vec![output],
+                }
+            })
+            .collect();
+        let block = Block {
+            header: Header {
+                version: bitcoin::block::Version::NO_SOFT_FORK_SIGNALLING,
+                prev_blockhash: bitcoin::BlockHash::all_zeros(),
+                merkle_root: bitcoin::TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: bitcoin::CompactTarget::from_consensus(0),
+                nonce: 0,
+            },
+            txdata,
+        };
+        let summarize = |scanned: &super::ScannedTx| {
+            (
+                scanned.txid,
+                scanned.drivechain_output,
+                scanned.treasury_spends.clone(),
+                scanned.bmm_request.is_some(),
+            )
+        };
+        let parallel: Vec<_> = scan_block(&block, Some(&prevouts))
+            .iter()
+            .map(summarize)
+            .collect();
+        let serial: Vec<_> = block.txdata[1..]
+            .iter()
+            .map(|transaction| summarize(&scan_transaction(transaction, Some(&prevouts))))
+            .collect();
+        assert_eq!(parallel, serial);
+        assert!(parallel
+            .iter()
+            .any(|(_, drivechain_output, treasury_spends, _)| {
+                drivechain_output.is_some() && !treasury_spends.is_empty()
+            }));
+    }
+}
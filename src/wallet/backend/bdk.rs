@@ -0,0 +1,453 @@
+//! Built-in BDK wallet backend
+
+use std::{
+    borrow::BorrowMut,
+    collections::HashMap,
+    io::Write as _,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bdk_electrum::{
+    electrum_client::{self, ElectrumApi},
+    BdkElectrumClient,
+};
+use bdk_wallet::{
+    self,
+    bitcoin::{
+        hashes::{sha256, Hash, HashEngine as _, Hmac, HmacEngine},
+        psbt::Psbt,
+        Address, Amount, FeeRate, Network, OutPoint, ScriptBuf, Transaction, TxIn, TxOut,
+    },
+    file_store,
+    keys::{
+        bip39::{Language, Mnemonic},
+        DerivableKey as _, ExtendedKey,
+    },
+    ChangeSet, KeychainKind,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use parking_lot::Mutex;
+
+use super::{DescriptorImport, FundingRequest, WalletBackend};
+use crate::cli::WalletConfig;
+
+/// Mnemonic that was used for all wallets before mnemonics were persisted.
+/// Existing wallets without a mnemonic file continue to use it.
+const LEGACY_MNEMONIC: &str =
+    "betray annual dog current tomorrow media ghost dynamic mule length sure salad";
+
+const MNEMONIC_FILE_NAME: &str = "mnemonic.txt";
+
+/// Load the wallet mnemonic from the configured mnemonic file, or from the
+/// wallet data dir. If neither exists, a new mnemonic is generated and
+/// written to the wallet data dir.
+fn load_or_create_mnemonic(data_dir: &Path, config: &WalletConfig) -> Result<Mnemonic> {
+    let parse = |phrase: &str| {
+        Mnemonic::parse_in_normalized(Language::English, phrase.trim())
+            .map_err(|err| miette!("failed to parse wallet mnemonic: {err:#}"))
+    };
+    if let Some(mnemonic_file) = &config.mnemonic_file {
+        let phrase = std::fs::read_to_string(mnemonic_file).map_err(|err| {
+            miette!(
+                "failed to read wallet mnemonic from {}: {err:#}",
+                mnemonic_file.display()
+            )
+        })?;
+        return parse(&phrase);
+    }
+    let mnemonic_file = data_dir.join(MNEMONIC_FILE_NAME);
+    if mnemonic_file.exists() {
+        let phrase = std::fs::read_to_string(&mnemonic_file).into_diagnostic()?;
+        return parse(&phrase);
+    }
+    let mnemonic = if data_dir.join("wallet.db").exists() {
+        tracing::warn!(
+            "Existing wallet uses the legacy hardcoded mnemonic, writing it to {}",
+            mnemonic_file.display()
+        );
+        parse(LEGACY_MNEMONIC)?
+    } else {
+        use rand::RngCore;
+        // 128 bits of entropy, for a 12 word mnemonic
+        let mut entropy = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut entropy);
+        tracing::info!(
+            "Generated new wallet mnemonic, writing it to {}",
+            mnemonic_file.display()
+        );
+        Mnemonic::from_entropy_in(Language::English, &entropy).into_diagnostic()?
+    };
+    let mut file_opts = std::fs::OpenOptions::new();
+    file_opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt as _;
+        file_opts.mode(0o600);
+    }
+    let mut file = file_opts.open(&mnemonic_file).into_diagnostic()?;
+    writeln!(file, "{mnemonic}").into_diagnostic()?;
+    Ok(mnemonic)
+}
+
+/// [`bdk_wallet::TxOrdering`] that keeps requested outputs in order, followed
+/// by change outputs. Inputs are shuffled.
+fn funding_txordering(outputs: &[TxOut]) -> bdk_wallet::TxOrdering {
+    let hmac_engine = {
+        let key = {
+            use rand::RngCore;
+            let mut bytes = vec![0u8; <sha256::Hash as Hash>::Engine::BLOCK_SIZE];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            bytes
+        };
+        HmacEngine::<sha256::Hash>::new(&key)
+    };
+    let input_sort = move |txin_l: &TxIn, txin_r: &TxIn| {
+        let hmac = |txin: &TxIn| {
+            use bdk_wallet::bitcoin::consensus::Encodable as _;
+            let mut engine = hmac_engine.clone();
+            txin.consensus_encode(&mut engine)
+                .expect("should encode correctly");
+            Hmac::<sha256::Hash>::from_engine(engine)
+        };
+        hmac(txin_l).cmp(&hmac(txin_r))
+    };
+    let output_positions: HashMap<ScriptBuf, usize> = outputs
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(idx, txout)| (txout.script_pubkey.clone(), idx))
+        .collect();
+    let output_sort = move |txout_l: &TxOut, txout_r: &TxOut| {
+        // Change outputs are not in the map, and sort last
+        let position = |txout: &TxOut| {
+            output_positions
+                .get(&txout.script_pubkey)
+                .copied()
+                .unwrap_or(usize::MAX)
+        };
+        position(txout_l).cmp(&position(txout_r))
+    };
+    bdk_wallet::TxOrdering::Custom {
+        input_sort: Arc::new(input_sort),
+        output_sort: Arc::new(output_sort),
+    }
+}
+
+/// Insert an unconfirmed tx into the wallet, if the wallet does not know of
+/// it yet. The tx may not have been seen by the Electrum server yet.
+fn insert_unconfirmed_tx(
+    wallet: &mut bdk_wallet::PersistedWallet<file_store::Store<ChangeSet>>,
+    tx: Transaction,
+) {
+    if wallet.get_tx(tx.compute_txid()).is_none() {
+        let last_seen = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        wallet.apply_unconfirmed_txs([(tx, last_seen)]);
+    }
+}
+
+/// Built-in wallet, tracking its own UTXOs via Electrum and signing locally
+pub struct BdkBackend {
+    bitcoin_wallet: Mutex<bdk_wallet::PersistedWallet<file_store::Store<ChangeSet>>>,
+    bitcoin_db: Mutex<file_store::Store<ChangeSet>>,
+    bitcoin_blockchain: BdkElectrumClient<bdk_electrum::electrum_client::Client>,
+}
+
+impl BdkBackend {
+    pub fn new(data_dir: &Path, config: &WalletConfig, network: Network) -> Result<Self> {
+        let mnemonic = load_or_create_mnemonic(data_dir, config)?;
+        // Generate the extended key
+        let xkey: ExtendedKey = mnemonic.clone().into_extended_key().into_diagnostic()?;
+        // Get xprv from the extended key
+        let xprv = xkey
+            .into_xprv(network)
+            .ok_or(miette!("couldn't get xprv"))?;
+
+        let mut wallet_database = file_store::Store::open_or_create_new(
+            b"bip300301_enforcer",
+            data_dir.join("wallet.db"),
+        )
+        .into_diagnostic()?;
+
+        // Create a BDK wallet structure using BIP 84 descriptor ("m/84h/1h/0h/0" and "m/84h/1h/0h/1")
+
+        let external_desc = format!("wpkh({xprv}/84'/1'/0'/0/*)");
+        let internal_desc = format!("wpkh({xprv}/84'/1'/0'/1/*)");
+
+        tracing::debug!("Attempting load of existing BDK wallet");
+        let bitcoin_wallet = bdk_wallet::Wallet::load()
+            .descriptor(KeychainKind::External, Some(external_desc.clone()))
+            .descriptor(KeychainKind::Internal, Some(internal_desc.clone()))
+            .extract_keys()
+            .check_network(network)
+            .load_wallet(&mut wallet_database)
+            .map_err(|err| miette!("failed to load wallet: {err:#}"))?;
+
+        let bitcoin_wallet = match bitcoin_wallet {
+            Some(wallet) => {
+                tracing::info!("Loaded existing BDK wallet");
+                wallet
+            }
+
+            None => {
+                tracing::info!("Creating new BDK wallet");
+
+                bdk_wallet::Wallet::create(external_desc, internal_desc)
+                    .network(network)
+                    .create_wallet(&mut wallet_database)
+                    .map_err(|err| miette!("failed to create wallet: {err:#}"))?
+            }
+        };
+
+        let bitcoin_blockchain = {
+            let (default_host, default_port) = match network {
+                Network::Signet => ("drivechain.live", 50001),
+                Network::Regtest => ("127.0.0.1", 60401), // Default for romanz/electrs
+                default => return Err(miette!("unsupported network: {default}")),
+            };
+
+            let electrum_host = config
+                .electrum_host
+                .clone()
+                .unwrap_or(default_host.to_string());
+            let electrum_port = config.electrum_port.unwrap_or(default_port);
+
+            let electrum_url = format!("{}:{}", electrum_host, electrum_port);
+
+            tracing::debug!("creating electrum client: {electrum_url}");
+
+            // Apply a reasonably short timeout to prevent the wallet from hanging
+            let timeout = 5;
+            let config = electrum_client::ConfigBuilder::new()
+                .timeout(Some(timeout))
+                .build();
+
+            let electrum_client = electrum_client::Client::from_config(&electrum_url, config)
+                .map_err(|err| miette!("failed to create electrum client: {err:#}"))?;
+
+            // let features = electrum_client.server_features().into_diagnostic()?;
+            let header = electrum_client.block_header(0).into_diagnostic()?;
+
+            // Verify the Electrum server is on the same chain as we are.
+            if header.block_hash().as_byte_array() != network.chain_hash().as_bytes() {
+                return Err(miette!(
+                    "Electrum server ({}) is not on the same chain as the wallet ({})",
+                    header.block_hash(),
+                    network.chain_hash(),
+                ));
+            }
+
+            BdkElectrumClient::new(electrum_client)
+        };
+
+        Ok(Self {
+            bitcoin_wallet: Mutex::new(bitcoin_wallet),
+            bitcoin_db: Mutex::new(wallet_database),
+            bitcoin_blockchain,
+        })
+    }
+
+    #[allow(
+        clippy::significant_drop_tightening,
+        reason = "false positive for `bitcoin_wallet`"
+    )]
+    fn build_psbt(&self, request: FundingRequest) -> Result<Psbt> {
+        let FundingRequest {
+            foreign_inputs,
+            outputs,
+            fee,
+            fee_rate,
+            locktime,
+        } = request;
+        let mut wallet = self.bitcoin_wallet.lock();
+        let mut builder = wallet.borrow_mut().build_tx();
+        for output in &outputs {
+            builder.add_recipient(output.script_pubkey.clone(), output.value);
+        }
+        if let Some(fee) = fee {
+            builder.fee_absolute(fee);
+        } else if let Some(fee_rate) = fee_rate {
+            builder.fee_rate(fee_rate);
+        }
+        if let Some(locktime) = locktime {
+            builder.nlocktime(locktime);
+        }
+        for (outpoint, prev_tx) in foreign_inputs {
+            let psbt_input = bdk_wallet::bitcoin::psbt::Input {
+                non_witness_utxo: Some(prev_tx),
+                ..bdk_wallet::bitcoin::psbt::Input::default()
+            };
+            // This might be wrong. Seems to work!
+            let satisfaction_weight = bdk_wallet::bitcoin::Weight::ZERO;
+            builder
+                .add_foreign_utxo(outpoint, psbt_input, satisfaction_weight)
+                .into_diagnostic()?;
+        }
+        builder.ordering(funding_txordering(&outputs));
+        builder.finish().into_diagnostic()
+    }
+
+    #[allow(
+        clippy::significant_drop_tightening,
+        reason = "false positive for `bitcoin_wallet`"
+    )]
+    fn build_fee_bump_psbt(&self, tx: Transaction, fee_rate: FeeRate) -> Result<Psbt> {
+        let txid = tx.compute_txid();
+        let lock_time = tx.lock_time;
+        let mut wallet = self.bitcoin_wallet.lock();
+        insert_unconfirmed_tx(&mut wallet, tx);
+        let mut builder = wallet.build_fee_bump(txid).into_diagnostic()?;
+        builder
+            .fee_rate(fee_rate)
+            .nlocktime(lock_time)
+            .ordering(bdk_wallet::TxOrdering::Untouched);
+        builder.finish().into_diagnostic()
+    }
+
+    #[allow(
+        clippy::significant_drop_tightening,
+        reason = "false positive for `bitcoin_wallet`"
+    )]
+    fn build_sweep_psbt(&self, parent: Transaction, vout: u32, fee: Amount) -> Result<Psbt> {
+        let outpoint = OutPoint {
+            txid: parent.compute_txid(),
+            vout,
+        };
+        let mut wallet = self.bitcoin_wallet.lock();
+        insert_unconfirmed_tx(&mut wallet, parent);
+        let drain_script = wallet
+            .next_unused_address(KeychainKind::Internal)
+            .script_pubkey();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_utxo(outpoint)
+            .into_diagnostic()?
+            .manually_selected_only()
+            .drain_to(drain_script)
+            .fee_absolute(fee);
+        builder.finish().into_diagnostic()
+    }
+
+    fn sign_transaction(&self, mut psbt: Psbt) -> Result<Transaction> {
+        if !self
+            .bitcoin_wallet
+            .lock()
+            .sign(&mut psbt, bdk_wallet::signer::SignOptions::default())
+            .into_diagnostic()?
+        {
+            return Err(miette!("failed to sign transaction"));
+        }
+
+        tracing::debug!("Signed PSBT: {psbt}",);
+
+        psbt.extract_tx().into_diagnostic()
+    }
+}
+
+#[tonic::async_trait]
+impl WalletBackend for BdkBackend {
+    async fn fund_and_sign(&self, request: FundingRequest) -> Result<Transaction> {
+        let psbt = self.build_psbt(request)?;
+        tracing::debug!("Created PSBT: {psbt}");
+        self.sign_transaction(psbt)
+    }
+
+    async fn fund_psbt(&self, request: FundingRequest) -> Result<Psbt> {
+        let psbt = self.build_psbt(request)?;
+        tracing::debug!("Created PSBT: {psbt}");
+        Ok(psbt)
+    }
+
+    async fn finalize_psbt(&self, mut psbt: Psbt) -> Result<Psbt> {
+        // Returns `false` if any inputs could not be finalized, eg. foreign
+        // inputs
+        let _finalized: bool = self
+            .bitcoin_wallet
+            .lock()
+            .finalize_psbt(&mut psbt, bdk_wallet::signer::SignOptions::default())
+            .into_diagnostic()?;
+        Ok(psbt)
+    }
+
+    async fn bump_fee(&self, tx: Transaction, fee_rate: FeeRate) -> Result<Transaction> {
+        let psbt = self.build_fee_bump_psbt(tx, fee_rate)?;
+        tracing::debug!("Created fee bump PSBT: {psbt}");
+        self.sign_transaction(psbt)
+    }
+
+    async fn sweep_to_self(
+        &self,
+        parent: Transaction,
+        vout: u32,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        let psbt = self.build_sweep_psbt(parent, vout, fee)?;
+        tracing::debug!("Created sweep PSBT: {psbt}");
+        self.sign_transaction(psbt)
+    }
+
+    async fn is_mine(&self, script_pubkey: ScriptBuf) -> Result<bool> {
+        Ok(self.bitcoin_wallet.lock().is_mine(script_pubkey))
+    }
+
+    #[allow(clippy::significant_drop_tightening)]
+    async fn new_address(&self) -> Result<Address> {
+        // Using next_unused_address here means that we get a new address
+        // when funds are received. Without this we'd need to take care not
+        // to cross the wallet scan gap.
+        let mut wallet = self.bitcoin_wallet.lock();
+        let info = wallet
+            .borrow_mut()
+            .next_unused_address(bdk_wallet::KeychainKind::External);
+
+        let mut bitcoin_db = self.bitcoin_db.lock();
+        let bitcoin_db = bitcoin_db.borrow_mut();
+        wallet.persist(bitcoin_db).into_diagnostic()?;
+        Ok(info.address)
+    }
+
+    /// The BDK wallet is derived from the mnemonic, and cannot track
+    /// additional descriptors
+    async fn import_descriptors(
+        &self,
+        _descriptors: Vec<DescriptorImport>,
+        _rescan_from_height: Option<u32>,
+    ) -> Result<()> {
+        Err(miette!(
+            "descriptor import is not supported by the BDK wallet backend, use the Bitcoin Core wallet backend instead"
+        ))
+    }
+
+    async fn sync(&self) -> Result<()> {
+        let start = SystemTime::now();
+        tracing::trace!("starting wallet sync");
+
+        let mut wallet_lock = self.bitcoin_wallet.lock();
+        let request = wallet_lock.start_sync_with_revealed_spks();
+
+        const BATCH_SIZE: usize = 5;
+        const FETCH_PREV_TXOUTS: bool = false;
+
+        let update = self
+            .bitcoin_blockchain
+            .sync(request, BATCH_SIZE, FETCH_PREV_TXOUTS)
+            .into_diagnostic()?;
+
+        wallet_lock.apply_update(update).into_diagnostic()?;
+
+        let mut database = self.bitcoin_db.lock();
+        wallet_lock.persist(&mut database).into_diagnostic()?;
+
+        tracing::debug!(
+            "wallet sync complete in {:?}",
+            start.elapsed().unwrap_or_default(),
+        );
+
+        drop(wallet_lock);
+        Ok(())
+    }
+}
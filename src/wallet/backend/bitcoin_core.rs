@@ -0,0 +1,371 @@
+//! Wallet backend using the wallet of the connected Bitcoin Core node
+
+use std::str::FromStr as _;
+
+use bdk_wallet::bitcoin::{
+    absolute::LockTime, consensus::encode, psbt::Psbt, transaction::Version as TxVersion, Address,
+    Amount, FeeRate, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use bip300301::jsonrpsee::core::{client::ClientT as _, rpc_params};
+use miette::{miette, IntoDiagnostic, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{DescriptorImport, FundingRequest, WalletBackend};
+
+/// Weight of a foreign input spent with an empty script sig and witness.
+/// 32 byte txid + 4 byte vout + 1 byte script length + 4 byte sequence,
+/// plus witness count for segwit txs.
+const FOREIGN_INPUT_WEIGHT: u64 = 41 * 4 + 1;
+
+#[derive(Deserialize)]
+struct FinalizePsbtResponse {
+    #[serde(default)]
+    psbt: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FundRawTransactionResponse {
+    hex: String,
+}
+
+#[derive(Deserialize)]
+struct GetDescriptorInfoResponse {
+    descriptor: String,
+}
+
+#[derive(Deserialize)]
+struct ImportDescriptorsError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ImportDescriptorsResult {
+    success: bool,
+    #[serde(default)]
+    warnings: Vec<String>,
+    #[serde(default)]
+    error: Option<ImportDescriptorsError>,
+}
+
+#[derive(Deserialize)]
+struct GetAddressInfoResponse {
+    ismine: bool,
+}
+
+#[derive(Deserialize)]
+struct PsbtBumpFeeResponse {
+    psbt: String,
+}
+
+#[derive(Deserialize)]
+struct WalletProcessPsbtResponse {
+    psbt: String,
+    complete: bool,
+}
+
+/// Funds transactions via `fundrawtransaction`, and signs them via
+/// `walletprocesspsbt`. Uses the default wallet of the connected node.
+pub struct CoreBackend {
+    main_client: crate::rpc_client::Client,
+    network: Network,
+}
+
+impl CoreBackend {
+    pub fn new(main_client: crate::rpc_client::Client, network: Network) -> Self {
+        Self {
+            main_client,
+            network,
+        }
+    }
+
+    /// Fund a transaction via `fundrawtransaction`.
+    /// Returns the unsigned PSBT, and the number of foreign inputs, which
+    /// precede all other inputs.
+    async fn fund(&self, request: FundingRequest) -> Result<(Psbt, usize)> {
+        let FundingRequest {
+            foreign_inputs,
+            outputs,
+            fee,
+            fee_rate,
+            locktime,
+        } = request;
+        if fee.is_some() {
+            return Err(miette!(
+                "absolute fees are not supported by the Bitcoin Core wallet backend"
+            ));
+        }
+        let n_outputs = outputs.len();
+        let unsigned_tx = Transaction {
+            version: TxVersion::TWO,
+            lock_time: locktime.unwrap_or(LockTime::ZERO),
+            input: foreign_inputs
+                .iter()
+                .map(|(outpoint, _)| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: ScriptBuf::new(),
+                    // Enables nLocktime
+                    sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: outputs,
+        };
+        let input_weights: Vec<_> = foreign_inputs
+            .iter()
+            .map(|(outpoint, _)| {
+                json!({
+                    "txid": outpoint.txid,
+                    "vout": outpoint.vout,
+                    "weight": FOREIGN_INPUT_WEIGHT,
+                })
+            })
+            .collect();
+        let mut options = json!({
+            "changePosition": n_outputs,
+            "input_weights": input_weights,
+            "lockUnspents": true,
+        });
+        if let Some(fee_rate) = fee_rate {
+            // sat/vB
+            options["fee_rate"] = json!(fee_rate.to_sat_per_vb_ceil());
+        }
+        let funded: FundRawTransactionResponse = self
+            .main_client
+            .request(
+                "fundrawtransaction",
+                rpc_params![encode::serialize_hex(&unsigned_tx), options],
+            )
+            .await
+            .map_err(|err| miette!("failed to call `fundrawtransaction`: {err:#}"))?;
+        let funded_tx: Transaction = encode::deserialize_hex(&funded.hex).into_diagnostic()?;
+        let mut psbt = Psbt::from_unsigned_tx(funded_tx).into_diagnostic()?;
+        for (psbt_input, (outpoint, prev_tx)) in psbt.inputs.iter_mut().zip(&foreign_inputs) {
+            debug_assert_eq!(prev_tx.compute_txid(), outpoint.txid);
+            psbt_input.non_witness_utxo = Some(prev_tx.clone());
+        }
+        Ok((psbt, foreign_inputs.len()))
+    }
+
+    /// Sign and finalize a PSBT with the node wallet, or only add UTXO and
+    /// key derivation information if `sign` is `false`.
+    /// Returns the processed PSBT, and `true` if all inputs were finalized.
+    async fn process_psbt(&self, psbt: &Psbt, sign: bool) -> Result<(Psbt, bool)> {
+        let sighash_type = "ALL";
+        let bip32_derivs = true;
+        let finalize = true;
+        let processed: WalletProcessPsbtResponse = self
+            .main_client
+            .request(
+                "walletprocesspsbt",
+                rpc_params![psbt.to_string(), sign, sighash_type, bip32_derivs, finalize],
+            )
+            .await
+            .map_err(|err| miette!("failed to call `walletprocesspsbt`: {err:#}"))?;
+        let psbt = Psbt::from_str(&processed.psbt).into_diagnostic()?;
+        tracing::debug!("Processed PSBT: {psbt}");
+        Ok((psbt, processed.complete))
+    }
+}
+
+#[tonic::async_trait]
+impl WalletBackend for CoreBackend {
+    async fn fund_and_sign(&self, request: FundingRequest) -> Result<Transaction> {
+        let (psbt, n_foreign_inputs) = self.fund(request).await?;
+        let (psbt, complete) = self.process_psbt(&psbt, true).await?;
+        // Foreign inputs cannot be finalized by the node wallet, so
+        // `complete` is false if there are any.
+        if !complete && n_foreign_inputs == 0 {
+            return Err(miette!("failed to sign transaction"));
+        }
+        let mut tx = psbt.unsigned_tx.clone();
+        for (idx, (txin, psbt_input)) in tx.input.iter_mut().zip(psbt.inputs).enumerate() {
+            if idx < n_foreign_inputs {
+                continue;
+            }
+            match (psbt_input.final_script_sig, psbt_input.final_script_witness) {
+                (None, None) => {
+                    return Err(miette!("failed to sign transaction input {idx}"));
+                }
+                (script_sig, witness) => {
+                    txin.script_sig = script_sig.unwrap_or_default();
+                    txin.witness = witness.unwrap_or_default();
+                }
+            }
+        }
+        Ok(tx)
+    }
+
+    async fn fund_psbt(&self, request: FundingRequest) -> Result<Psbt> {
+        let (psbt, _) = self.fund(request).await?;
+        let (psbt, _complete) = self.process_psbt(&psbt, false).await?;
+        Ok(psbt)
+    }
+
+    async fn finalize_psbt(&self, psbt: Psbt) -> Result<Psbt> {
+        let extract = false;
+        let finalized: FinalizePsbtResponse = self
+            .main_client
+            .request("finalizepsbt", rpc_params![psbt.to_string(), extract])
+            .await
+            .map_err(|err| miette!("failed to call `finalizepsbt`: {err:#}"))?;
+        let psbt = finalized
+            .psbt
+            .ok_or_else(|| miette!("missing PSBT in `finalizepsbt` response"))?;
+        Psbt::from_str(&psbt).into_diagnostic()
+    }
+
+    async fn bump_fee(&self, tx: Transaction, fee_rate: FeeRate) -> Result<Transaction> {
+        let options = json!({
+            // sat/vB
+            "fee_rate": fee_rate.to_sat_per_vb_ceil(),
+        });
+        let bumped: PsbtBumpFeeResponse = self
+            .main_client
+            .request("psbtbumpfee", rpc_params![tx.compute_txid(), options])
+            .await
+            .map_err(|err| miette!("failed to call `psbtbumpfee`: {err:#}"))?;
+        let psbt = Psbt::from_str(&bumped.psbt).into_diagnostic()?;
+        let (psbt, complete) = self.process_psbt(&psbt, true).await?;
+        if !complete {
+            return Err(miette!("failed to sign fee bump transaction"));
+        }
+        psbt.extract_tx().into_diagnostic()
+    }
+
+    async fn sweep_to_self(
+        &self,
+        parent: Transaction,
+        vout: u32,
+        fee: Amount,
+    ) -> Result<Transaction> {
+        let prev_txout = parent
+            .output
+            .get(vout as usize)
+            .ok_or_else(|| miette!("output {vout} does not exist"))?;
+        let value = prev_txout
+            .value
+            .checked_sub(fee)
+            .filter(|value| *value >= prev_txout.script_pubkey.minimal_non_dust())
+            .ok_or_else(|| miette!("fee ({fee}) exceeds spendable output value"))?;
+        let address = self.new_address().await?;
+        let unsigned_tx = Transaction {
+            version: TxVersion::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: parent.compute_txid(),
+                    vout,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let psbt = Psbt::from_unsigned_tx(unsigned_tx).into_diagnostic()?;
+        let (psbt, complete) = self.process_psbt(&psbt, true).await?;
+        if !complete {
+            return Err(miette!("failed to sign sweep transaction"));
+        }
+        psbt.extract_tx().into_diagnostic()
+    }
+
+    async fn is_mine(&self, script_pubkey: ScriptBuf) -> Result<bool> {
+        let Ok(address) = Address::from_script(&script_pubkey, self.network) else {
+            return Ok(false);
+        };
+        let info: GetAddressInfoResponse = self
+            .main_client
+            .request("getaddressinfo", rpc_params![address])
+            .await
+            .map_err(|err| miette!("failed to call `getaddressinfo`: {err:#}"))?;
+        Ok(info.ismine)
+    }
+
+    async fn new_address(&self) -> Result<Address> {
+        let address: String = self
+            .main_client
+            .request("getnewaddress", rpc_params![])
+            .await
+            .map_err(|err| miette!("failed to call `getnewaddress`: {err:#}"))?;
+        Address::from_str(&address)
+            .into_diagnostic()?
+            .require_network(self.network)
+            .into_diagnostic()
+    }
+
+    async fn import_descriptors(
+        &self,
+        descriptors: Vec<DescriptorImport>,
+        rescan_from_height: Option<u32>,
+    ) -> Result<()> {
+        let mut requests = Vec::with_capacity(descriptors.len());
+        for import in &descriptors {
+            // Adds a checksum if one is missing, and checks the checksum
+            // otherwise. Private keys are preserved in the original
+            // descriptor, so only the checksum is taken from the response.
+            let info: GetDescriptorInfoResponse = self
+                .main_client
+                .request("getdescriptorinfo", rpc_params![import.descriptor])
+                .await
+                .map_err(|err| miette!("failed to call `getdescriptorinfo`: {err:#}"))?;
+            let descriptor = match import.descriptor.split_once('#') {
+                Some(_) => import.descriptor.clone(),
+                None => {
+                    let (_, checksum) = info
+                        .descriptor
+                        .split_once('#')
+                        .ok_or_else(|| miette!("missing descriptor checksum"))?;
+                    format!("{}#{checksum}", import.descriptor)
+                }
+            };
+            let mut request = json!({
+                "desc": descriptor,
+                "internal": import.internal,
+                "active": import.active,
+                // Rescans are handled separately, by height
+                "timestamp": "now",
+            });
+            if import.active {
+                request["range"] = json!([0, 1000]);
+            }
+            requests.push(request);
+        }
+        let results: Vec<ImportDescriptorsResult> = self
+            .main_client
+            .request("importdescriptors", rpc_params![requests])
+            .await
+            .map_err(|err| miette!("failed to call `importdescriptors`: {err:#}"))?;
+        for (idx, result) in results.into_iter().enumerate() {
+            for warning in result.warnings {
+                tracing::warn!("descriptor import {idx}: {warning}");
+            }
+            if !result.success {
+                let message = result
+                    .error
+                    .map(|err| err.message)
+                    .unwrap_or_else(|| "unknown error".to_owned());
+                return Err(miette!("failed to import descriptor {idx}: {message}"));
+            }
+        }
+        tracing::info!("imported {} descriptor(s)", descriptors.len());
+        if let Some(start_height) = rescan_from_height {
+            tracing::info!("rescanning node wallet from height {start_height}");
+            let _: serde_json::Value = self
+                .main_client
+                .request("rescanblockchain", rpc_params![start_height])
+                .await
+                .map_err(|err| miette!("failed to call `rescanblockchain`: {err:#}"))?;
+        }
+        Ok(())
+    }
+
+    /// The node wallet is always in sync with the node
+    async fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
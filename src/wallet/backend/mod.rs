@@ -0,0 +1,98 @@
+//! Wallet backends, used to fund and sign transactions
+
+use bdk_wallet::bitcoin::{
+    absolute::LockTime, psbt::Psbt, Address, Amount, FeeRate, OutPoint, ScriptBuf, Transaction,
+    TxOut,
+};
+use clap::ValueEnum;
+use miette::Result;
+
+mod bdk;
+mod bitcoin_core;
+
+pub use self::{bdk::BdkBackend, bitcoin_core::CoreBackend};
+
+/// Selects the wallet backend
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum WalletBackendKind {
+    /// Built-in BDK wallet, synced via Electrum
+    #[default]
+    Bdk,
+    /// Wallet of the connected Bitcoin Core node
+    Core,
+}
+
+/// Transaction to be funded and signed by a [`WalletBackend`]
+#[derive(Debug)]
+pub struct FundingRequest {
+    /// Inputs that must be spent, along with their previous transactions.
+    /// These are not owned by the wallet, and are spent with an empty
+    /// script sig and witness (eg. sidechain CTIPs).
+    pub foreign_inputs: Vec<(OutPoint, Transaction)>,
+    /// Outputs, in order. Any change outputs are placed after these.
+    pub outputs: Vec<TxOut>,
+    /// Absolute fee. Takes precedence over `fee_rate` if set.
+    pub fee: Option<Amount>,
+    /// If neither `fee` nor `fee_rate` are set, the backend's default fee
+    /// rate is used.
+    pub fee_rate: Option<FeeRate>,
+    pub locktime: Option<LockTime>,
+}
+
+/// Output descriptor to import into a [`WalletBackend`]
+#[derive(Clone, Debug)]
+pub struct DescriptorImport {
+    /// Output descriptor, optionally with a checksum. May contain private
+    /// keys, or be watch-only.
+    pub descriptor: String,
+    /// If `true`, the descriptor is used for change outputs
+    pub internal: bool,
+    /// If `true`, the descriptor is used to generate new addresses
+    pub active: bool,
+}
+
+#[tonic::async_trait]
+pub trait WalletBackend: Send + Sync {
+    /// Fund and sign a transaction
+    async fn fund_and_sign(&self, request: FundingRequest) -> Result<Transaction>;
+
+    /// Fund a transaction without signing it. The returned PSBT includes the
+    /// information required to sign wallet inputs with an external signer.
+    async fn fund_psbt(&self, request: FundingRequest) -> Result<Psbt>;
+
+    /// Finalize the inputs of an externally signed PSBT that belong to the
+    /// wallet. Other inputs are left as is.
+    async fn finalize_psbt(&self, psbt: Psbt) -> Result<Psbt>;
+
+    /// Create a replacement for an unconfirmed transaction created by this
+    /// wallet, paying the specified fee rate. Outputs other than change are
+    /// preserved, in order.
+    async fn bump_fee(&self, tx: Transaction, fee_rate: FeeRate) -> Result<Transaction>;
+
+    /// Spend a wallet-owned output of an unconfirmed tx to a new wallet
+    /// address, paying exactly the specified fee. Used for CPFP.
+    async fn sweep_to_self(
+        &self,
+        parent: Transaction,
+        vout: u32,
+        fee: Amount,
+    ) -> Result<Transaction>;
+
+    /// Returns `true` if the script pubkey belongs to the wallet
+    async fn is_mine(&self, script_pubkey: ScriptBuf) -> Result<bool>;
+
+    /// Get a new receiving address
+    async fn new_address(&self) -> Result<Address>;
+
+    /// Import output descriptors. If `rescan_from_height` is set, the
+    /// wallet is rescanned from that height for transactions relevant to
+    /// the imported descriptors.
+    async fn import_descriptors(
+        &self,
+        descriptors: Vec<DescriptorImport>,
+        rescan_from_height: Option<u32>,
+    ) -> Result<()>;
+
+    /// Sync with the chain. No-op for backends that do not require syncing.
+    async fn sync(&self) -> Result<()>;
+}
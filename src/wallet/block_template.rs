@@ -0,0 +1,353 @@
+//! Augmenting `getblocktemplate` output with BIP300/301 coinbase messages
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr as _,
+};
+
+use bitcoin::{
+    consensus::encode,
+    hashes::Hash as _,
+    merkle_tree,
+    opcodes::all::{OP_PUSHBYTES_36, OP_RETURN},
+    Amount, Block, BlockHash, ScriptBuf, Transaction, Txid, WitnessMerkleNode, Wtxid,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{messages::parse_m8_bmm_request, types::SidechainNumber};
+
+/// Magic bytes of the witness commitment output, following the
+/// `OP_RETURN OP_PUSHBYTES_36` prefix
+const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid block template: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid previous block hash in block template")]
+    PrevBlockHash(#[source] bitcoin::hex::HexToArrayError),
+    #[error("invalid transaction {index} in block template")]
+    Transaction {
+        index: usize,
+        #[source]
+        source: encode::FromHexError,
+    },
+    #[error("dependency {depends} of transaction {index} does not precede it")]
+    Dependency { index: usize, depends: usize },
+    #[error("fees of removed transactions exceed the coinbase value")]
+    CoinbaseValue,
+}
+
+/// Transaction entry in a `getblocktemplate` response
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TemplateTransaction {
+    data: String,
+    /// 1-based indexes of transactions in the template that this
+    /// transaction depends on
+    #[serde(default)]
+    depends: Vec<usize>,
+    fee: u64,
+    /// Other fields are passed through as is
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Fields of a `getblocktemplate` response that are modified
+#[derive(Debug, Deserialize, Serialize)]
+struct BlockTemplate {
+    previousblockhash: String,
+    transactions: Vec<TemplateTransaction>,
+    coinbasevalue: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_witness_commitment: Option<String>,
+    /// Other fields are passed through as is
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// BMM request included in a block template
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TemplateBmmRequest {
+    pub txid: Txid,
+    pub sidechain_number: SidechainNumber,
+    pub sidechain_block_hash: [u8; 32],
+    /// Value of the M8 output
+    pub bid: Amount,
+    /// Fee paid to the miner by the M8 tx
+    pub fee: Amount,
+}
+
+impl TemplateBmmRequest {
+    /// BMM requests are ranked by the fee paid to the miner, and then by bid
+    fn is_better_than(&self, other: &Self) -> bool {
+        (self.fee, self.bid) > (other.fee, other.bid)
+    }
+}
+
+/// Block template with BMM requests filtered so that at most one BMM request
+/// is included per sidechain slot
+#[derive(Debug)]
+pub struct FilteredBlockTemplate {
+    template: BlockTemplate,
+    /// BMM requests that remain in the template. Each of these must be
+    /// accepted with an M7 in the coinbase tx.
+    pub bmm_requests: Vec<TemplateBmmRequest>,
+    pub prev_blockhash: BlockHash,
+}
+
+impl FilteredBlockTemplate {
+    /// Serialize the modified template
+    pub fn into_json(self) -> Result<serde_json::Value, Error> {
+        Ok(serde_json::to_value(self.template)?)
+    }
+}
+
+/// Compute the witness commitment output script for the transactions in a
+/// block template, excluding the coinbase tx
+fn witness_commitment_script<'a, Txs>(transactions: Txs) -> ScriptBuf
+where
+    Txs: IntoIterator<Item = &'a Transaction>,
+{
+    const WITNESS_RESERVED_VALUE: [u8; 32] = [0; 32];
+    let wtxids = std::iter::once(Wtxid::all_zeros())
+        .chain(transactions.into_iter().map(Transaction::compute_wtxid))
+        .map(|wtxid| wtxid.to_raw_hash());
+    let witness_root = WitnessMerkleNode::from_raw_hash(
+        merkle_tree::calculate_root(wtxids).expect("there is at least one wtxid"),
+    );
+    let witness_commitment =
+        Block::compute_witness_commitment(&witness_root, &WITNESS_RESERVED_VALUE);
+    let script_bytes = [
+        &[OP_RETURN.to_u8(), OP_PUSHBYTES_36.to_u8()][..],
+        &WITNESS_COMMITMENT_HEADER,
+        witness_commitment.as_byte_array(),
+    ]
+    .concat();
+    ScriptBuf::from_bytes(script_bytes)
+}
+
+/// Filter the BMM requests in a `getblocktemplate` response.
+/// For each active sidechain, the BMM request for the template's previous
+/// block that pays the highest fee is kept, with ties broken by bid. All other BMM requests are removed,
+/// since they would be invalid without a corresponding M7 accept, along with
+/// any transactions that depend on them. The coinbase value and default
+/// witness commitment are updated accordingly.
+pub fn filter_bmm_requests(
+    template: serde_json::Value,
+    active_sidechains: &HashSet<SidechainNumber>,
+) -> Result<FilteredBlockTemplate, Error> {
+    let mut template: BlockTemplate = serde_json::from_value(template)?;
+    let prev_blockhash =
+        BlockHash::from_str(&template.previousblockhash).map_err(Error::PrevBlockHash)?;
+    let transactions: Vec<Transaction> = template
+        .transactions
+        .iter()
+        .enumerate()
+        .map(|(index, template_tx)| {
+            encode::deserialize_hex(&template_tx.data)
+                .map_err(|source| Error::Transaction { index, source })
+        })
+        .collect::<Result<_, _>>()?;
+    // Best BMM request for each sidechain, with the index of the tx
+    let mut best_bids = HashMap::<SidechainNumber, (usize, TemplateBmmRequest)>::new();
+    let mut bmm_request_indexes = Vec::new();
+    for (index, tx) in transactions.iter().enumerate() {
+        let Some(output) = tx.output.first() else {
+            continue;
+        };
+        let Ok((_, request)) = parse_m8_bmm_request(output.script_pubkey.as_bytes()) else {
+            continue;
+        };
+        bmm_request_indexes.push(index);
+        if request.prev_mainchain_block_hash != prev_blockhash.to_byte_array()
+            || !active_sidechains.contains(&request.sidechain_number)
+        {
+            continue;
+        }
+        let bmm_request = TemplateBmmRequest {
+            txid: tx.compute_txid(),
+            sidechain_number: request.sidechain_number,
+            sidechain_block_hash: request.sidechain_block_hash,
+            bid: output.value,
+            fee: Amount::from_sat(template.transactions[index].fee),
+        };
+        match best_bids.get(&request.sidechain_number) {
+            Some((_, best)) if !bmm_request.is_better_than(best) => (),
+            _ => {
+                best_bids.insert(request.sidechain_number, (index, bmm_request));
+            }
+        }
+    }
+    let kept_bmm_requests: HashSet<usize> = best_bids.values().map(|(index, _)| *index).collect();
+    let mut removed: HashSet<usize> = bmm_request_indexes
+        .into_iter()
+        .filter(|index| !kept_bmm_requests.contains(index))
+        .collect();
+    // Dependencies always precede dependents, so a single pass suffices
+    for (index, template_tx) in template.transactions.iter().enumerate() {
+        for depends in &template_tx.depends {
+            if *depends == 0 || *depends > index {
+                return Err(Error::Dependency {
+                    index,
+                    depends: *depends,
+                });
+            }
+            if removed.contains(&(depends - 1)) {
+                removed.insert(index);
+            }
+        }
+    }
+    // BMM requests that depend on removed txs are also removed
+    let mut bmm_requests: Vec<_> = best_bids
+        .into_values()
+        .filter(|(index, _)| !removed.contains(index))
+        .collect();
+    bmm_requests.sort_by_key(|(index, _)| *index);
+    let bmm_requests = bmm_requests.into_iter().map(|(_, req)| req).collect();
+    if removed.is_empty() {
+        return Ok(FilteredBlockTemplate {
+            template,
+            bmm_requests,
+            prev_blockhash,
+        });
+    }
+    let removed_fees: u64 = removed
+        .iter()
+        .map(|index| template.transactions[*index].fee)
+        .sum();
+    template.coinbasevalue = template
+        .coinbasevalue
+        .checked_sub(removed_fees)
+        .ok_or(Error::CoinbaseValue)?;
+    // Map from old 1-based indexes to new 1-based indexes
+    let mut new_indexes = HashMap::<usize, usize>::new();
+    let mut kept_transactions = Vec::new();
+    let mut kept_template_txs = Vec::new();
+    for (index, (mut template_tx, tx)) in template
+        .transactions
+        .into_iter()
+        .zip(transactions)
+        .enumerate()
+    {
+        if removed.contains(&index) {
+            continue;
+        }
+        template_tx.depends = template_tx
+            .depends
+            .iter()
+            .map(|depends| new_indexes[depends])
+            .collect();
+        kept_template_txs.push(template_tx);
+        kept_transactions.push(tx);
+        new_indexes.insert(index + 1, kept_template_txs.len());
+    }
+    template.transactions = kept_template_txs;
+    if template.default_witness_commitment.is_some() {
+        let script = witness_commitment_script(&kept_transactions);
+        template.default_witness_commitment = Some(hex::encode(script.as_bytes()));
+    }
+    tracing::debug!(
+        "removed {} transaction(s) from block template, with fees of {removed_fees} sats",
+        removed.len()
+    );
+    Ok(FilteredBlockTemplate {
+        template,
+        bmm_requests,
+        prev_blockhash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        absolute::LockTime, script::PushBytesBuf, transaction::Version, OutPoint, TxIn, TxOut, Txid,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::messages::M8_BMM_REQUEST_TAG;
+
+    fn m8_tx(prev_blockhash: BlockHash, side_block_hash: [u8; 32], bid: u64) -> Transaction {
+        let message = [
+            &M8_BMM_REQUEST_TAG[..],
+            &[0],
+            &side_block_hash,
+            &prev_blockhash.to_byte_array(),
+        ]
+        .concat();
+        let script_pubkey = ScriptBuf::new_op_return(PushBytesBuf::try_from(message).unwrap());
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), u32::from(side_block_hash[0])),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(bid),
+                script_pubkey,
+            }],
+        }
+    }
+
+    fn template_tx(tx: &Transaction, depends: &[usize], fee: u64) -> serde_json::Value {
+        json!({
+            "data": encode::serialize_hex(tx),
+            "txid": tx.compute_txid().to_string(),
+            "depends": depends,
+            "fee": fee,
+        })
+    }
+
+    #[test]
+    fn test_filter_bmm_requests() {
+        let prev_blockhash = BlockHash::all_zeros();
+        let low_bid = m8_tx(prev_blockhash, [1; 32], 100);
+        let high_bid = m8_tx(prev_blockhash, [2; 32], 200);
+        // Highest bid, but with the lowest fee
+        let low_fee = m8_tx(prev_blockhash, [4; 32], 500);
+        // Spends the low bid tx
+        let child = Transaction {
+            input: vec![TxIn {
+                previous_output: OutPoint::new(low_bid.compute_txid(), 0),
+                ..TxIn::default()
+            }],
+            ..m8_tx(prev_blockhash, [3; 32], 0)
+        };
+        let template = json!({
+            "previousblockhash": prev_blockhash.to_string(),
+            "transactions": [
+                template_tx(&low_bid, &[], 10),
+                template_tx(&child, &[1], 20),
+                template_tx(&high_bid, &[], 30),
+                template_tx(&low_fee, &[], 5),
+            ],
+            "coinbasevalue": 1000,
+            "default_witness_commitment": "",
+            "height": 1,
+        });
+        let active_sidechains = HashSet::from([SidechainNumber(0)]);
+        let filtered = filter_bmm_requests(template, &active_sidechains).unwrap();
+        assert_eq!(
+            filtered.bmm_requests,
+            vec![TemplateBmmRequest {
+                txid: high_bid.compute_txid(),
+                sidechain_number: SidechainNumber(0),
+                sidechain_block_hash: [2; 32],
+                bid: Amount::from_sat(200),
+                fee: Amount::from_sat(30),
+            }]
+        );
+        let template = filtered.into_json().unwrap();
+        assert_eq!(template["coinbasevalue"], 965);
+        assert_eq!(template["height"], 1);
+        let transactions = template["transactions"].as_array().unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0]["txid"], high_bid.compute_txid().to_string());
+        assert_eq!(
+            template["default_witness_commitment"],
+            hex::encode(witness_commitment_script([&high_bid]).as_bytes())
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! BMM requests that are re-bid against each new mainchain tip, until they
+//! are accepted, cancelled, or a cap is reached.
+//!
+//! Re-bids are advanced by the wallet task, while they can be cancelled or
+//! replaced concurrently via RPCs. Changes made by the wallet task only
+//! apply if the re-bid has not been cancelled or replaced since it was
+//! read, so that a cancelled re-bid is not restored.
+
+use bitcoin::{hashes::Hash as _, Amount, BlockHash};
+use rusqlite::Connection;
+
+use super::BmmRequestStatus;
+use crate::types::SidechainNumber;
+
+/// A BMM request that is re-bid against each new mainchain tip until it is
+/// accepted, cancelled, or a cap is reached
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BmmRebid {
+    pub sidechain_number: SidechainNumber,
+    pub sidechain_block_hash: [u8; 32],
+    pub bid: Amount,
+    /// Max number of BMM requests to broadcast, including the first
+    pub max_attempts: u32,
+    /// Max total fees of all broadcast BMM requests
+    pub max_total_fee: Amount,
+    pub attempts: u32,
+    pub total_fee: Amount,
+    /// Previous mainchain block hash of the most recent BMM request
+    pub last_prev_block_hash: BlockHash,
+}
+
+/// Reasons for which re-bidding stops
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BmmRebidStop {
+    /// The sidechain block hash was accepted
+    Accepted { block_hash: BlockHash },
+    /// `max_attempts` BMM requests have been broadcast
+    MaxAttempts,
+    /// Another BMM request would bring the total fees above
+    /// `max_total_fee`
+    MaxTotalFee,
+}
+
+impl BmmRebid {
+    /// Whether to re-bid, given the status of the most recent BMM request.
+    /// Returns `Ok(false)` if the most recent request can still be accepted.
+    pub fn should_rebid(&self, status: BmmRequestStatus) -> Result<bool, BmmRebidStop> {
+        match status {
+            BmmRequestStatus::Pending => Ok(false),
+            BmmRequestStatus::Accepted { block_hash } => Err(BmmRebidStop::Accepted { block_hash }),
+            BmmRequestStatus::NotAccepted { .. } | BmmRequestStatus::Expired => {
+                if self.attempts >= self.max_attempts {
+                    Err(BmmRebidStop::MaxAttempts)
+                } else {
+                    Ok(true)
+                }
+            }
+        }
+    }
+
+    /// The re-bid after broadcasting another BMM request, that commits to
+    /// `prev_block_hash` and pays `fee`
+    pub fn with_attempt(
+        &self,
+        prev_block_hash: BlockHash,
+        fee: Amount,
+    ) -> Result<Self, BmmRebidStop> {
+        let total_fee = self.total_fee + fee;
+        if total_fee > self.max_total_fee {
+            return Err(BmmRebidStop::MaxTotalFee);
+        }
+        Ok(Self {
+            attempts: self.attempts + 1,
+            total_fee,
+            last_prev_block_hash: prev_block_hash,
+            ..*self
+        })
+    }
+}
+
+/// Store a re-bid, replacing any existing re-bid for the sidechain slot
+pub(super) fn put(connection: &Connection, rebid: &BmmRebid) -> rusqlite::Result<()> {
+    let _: usize = connection.execute(
+        "INSERT OR REPLACE INTO bmm_rebids (sidechain_number, side_block_hash, bid_sats, max_attempts, max_total_fee_sats, attempts, total_fee_sats, last_prev_block_hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            u8::from(rebid.sidechain_number),
+            rebid.sidechain_block_hash,
+            rebid.bid.to_sat(),
+            rebid.max_attempts,
+            rebid.max_total_fee.to_sat(),
+            rebid.attempts,
+            rebid.total_fee.to_sat(),
+            rebid.last_prev_block_hash.to_byte_array(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Delete the re-bid for the sidechain slot.
+/// Returns `false` if there was no re-bid for the sidechain slot.
+pub(super) fn cancel(
+    connection: &Connection,
+    sidechain_number: SidechainNumber,
+) -> rusqlite::Result<bool> {
+    let deleted = connection.execute(
+        "DELETE FROM bmm_rebids WHERE sidechain_number = ?1",
+        [u8::from(sidechain_number)],
+    )?;
+    Ok(deleted > 0)
+}
+
+pub(super) fn list(connection: &Connection) -> rusqlite::Result<Vec<BmmRebid>> {
+    let mut statement = connection.prepare(
+        "SELECT sidechain_number, side_block_hash, bid_sats, max_attempts, max_total_fee_sats, attempts, total_fee_sats, last_prev_block_hash FROM bmm_rebids ORDER BY sidechain_number",
+    )?;
+    let rows = statement
+        .query_map([], |row| {
+            let last_prev_block_hash: [u8; 32] = row.get(7)?;
+            Ok(BmmRebid {
+                sidechain_number: SidechainNumber(row.get(0)?),
+                sidechain_block_hash: row.get(1)?,
+                bid: Amount::from_sat(row.get(2)?),
+                max_attempts: row.get(3)?,
+                max_total_fee: Amount::from_sat(row.get(4)?),
+                attempts: row.get(5)?,
+                total_fee: Amount::from_sat(row.get(6)?),
+                last_prev_block_hash: BlockHash::from_byte_array(last_prev_block_hash),
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
+
+/// Replace `rebid` with `updated`.
+/// Returns `false` if `rebid` was cancelled or replaced since it was read.
+pub(super) fn update(
+    connection: &Connection,
+    rebid: &BmmRebid,
+    updated: &BmmRebid,
+) -> rusqlite::Result<bool> {
+    let updated_rows = connection.execute(
+        "UPDATE bmm_rebids SET attempts = ?1, total_fee_sats = ?2, last_prev_block_hash = ?3 WHERE sidechain_number = ?4 AND side_block_hash = ?5 AND last_prev_block_hash = ?6",
+        rusqlite::params![
+            updated.attempts,
+            updated.total_fee.to_sat(),
+            updated.last_prev_block_hash.to_byte_array(),
+            u8::from(rebid.sidechain_number),
+            rebid.sidechain_block_hash,
+            rebid.last_prev_block_hash.to_byte_array(),
+        ],
+    )?;
+    Ok(updated_rows > 0)
+}
+
+/// Delete `rebid`.
+/// Returns `false` if `rebid` was cancelled or replaced since it was read.
+pub(super) fn stop(connection: &Connection, rebid: &BmmRebid) -> rusqlite::Result<bool> {
+    let deleted = connection.execute(
+        "DELETE FROM bmm_rebids WHERE sidechain_number = ?1 AND side_block_hash = ?2 AND last_prev_block_hash = ?3",
+        rusqlite::params![
+            u8::from(rebid.sidechain_number),
+            rebid.sidechain_block_hash,
+            rebid.last_prev_block_hash.to_byte_array(),
+        ],
+    )?;
+    Ok(deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash as _, Amount, BlockHash};
+    use rusqlite::Connection;
+
+    use super::{cancel, list, put, stop, update, BmmRebid, BmmRebidStop};
+    use crate::{types::SidechainNumber, wallet::BmmRequestStatus};
+
+    fn rebid(sidechain_block_hash: u8) -> BmmRebid {
+        BmmRebid {
+            sidechain_number: SidechainNumber(1),
+            sidechain_block_hash: [sidechain_block_hash; 32],
+            bid: Amount::from_sat(1_000),
+            max_attempts: 3,
+            max_total_fee: Amount::from_sat(1_000),
+            attempts: 1,
+            total_fee: Amount::from_sat(400),
+            last_prev_block_hash: BlockHash::from_byte_array([1; 32]),
+        }
+    }
+
+    #[test]
+    fn test_caps() {
+        let block_hash = BlockHash::from_byte_array([2; 32]);
+        let first = rebid(0);
+        assert_eq!(first.should_rebid(BmmRequestStatus::Pending), Ok(false));
+        assert_eq!(
+            first.should_rebid(BmmRequestStatus::Accepted { block_hash }),
+            Err(BmmRebidStop::Accepted { block_hash })
+        );
+        assert_eq!(
+            first.should_rebid(BmmRequestStatus::NotAccepted { block_hash }),
+            Ok(true)
+        );
+        assert_eq!(first.should_rebid(BmmRequestStatus::Expired), Ok(true));
+        // Fees up to and including the max total fee are allowed
+        let second = first
+            .with_attempt(block_hash, Amount::from_sat(300))
+            .unwrap();
+        assert_eq!(second.attempts, 2);
+        assert_eq!(second.total_fee, Amount::from_sat(700));
+        assert_eq!(second.last_prev_block_hash, block_hash);
+        assert_eq!(
+            second.with_attempt(block_hash, Amount::from_sat(301)),
+            Err(BmmRebidStop::MaxTotalFee)
+        );
+        let third = second
+            .with_attempt(block_hash, Amount::from_sat(300))
+            .unwrap();
+        assert_eq!(third.total_fee, first.max_total_fee);
+        assert_eq!(
+            third.should_rebid(BmmRequestStatus::Expired),
+            Err(BmmRebidStop::MaxAttempts)
+        );
+        // Pending requests can still be accepted, even at the cap
+        assert_eq!(third.should_rebid(BmmRequestStatus::Pending), Ok(false));
+    }
+
+    /// Updates by the wallet task do not apply to re-bids that were
+    /// cancelled or replaced since they were read
+    #[test]
+    fn test_cancel() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        crate::wallet::migrations()
+            .to_latest(&mut connection)
+            .unwrap();
+        let first = rebid(0);
+        let () = put(&connection, &first).unwrap();
+        let updated = first
+            .with_attempt(BlockHash::from_byte_array([2; 32]), Amount::from_sat(100))
+            .unwrap();
+        assert!(update(&connection, &first, &updated).unwrap());
+        assert_eq!(list(&connection).unwrap(), vec![updated]);
+        // Stale reads do not apply
+        assert!(!update(&connection, &first, &updated).unwrap());
+        assert!(!stop(&connection, &first).unwrap());
+        // Replaced via `RequestBmm`
+        let replacement = BmmRebid {
+            last_prev_block_hash: BlockHash::from_byte_array([3; 32]),
+            ..rebid(1)
+        };
+        let () = put(&connection, &replacement).unwrap();
+        assert!(!update(&connection, &updated, &updated).unwrap());
+        assert!(!stop(&connection, &updated).unwrap());
+        assert_eq!(list(&connection).unwrap(), vec![replacement]);
+        // Cancelled via `CancelBmmRebid`
+        assert!(cancel(&connection, replacement.sidechain_number).unwrap());
+        assert!(!cancel(&connection, replacement.sidechain_number).unwrap());
+        let next = replacement
+            .with_attempt(BlockHash::from_byte_array([4; 32]), Amount::from_sat(100))
+            .unwrap();
+        assert!(!update(&connection, &replacement, &next).unwrap());
+        assert_eq!(list(&connection).unwrap(), Vec::new());
+        // Stopped by the wallet task
+        let () = put(&connection, &next).unwrap();
+        assert!(stop(&connection, &next).unwrap());
+        assert_eq!(list(&connection).unwrap(), Vec::new());
+    }
+}
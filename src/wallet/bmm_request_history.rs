@@ -0,0 +1,369 @@
+//! History of BMM requests created by the wallet or observed in block
+//! templates, for reporting BMM success rate and spend.
+//!
+//! A request moves from `created` to `broadcast` once it is in the node's
+//! mempool, and is resolved as `accepted`, `not_accepted`, `expired`, or
+//! `replaced` (by a fee bump). Resolved requests are pruned from the
+//! history after [`RETENTION`].
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin::{hashes::Hash as _, Amount, BlockHash, Txid};
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef},
+    Connection, Row,
+};
+
+use crate::types::SidechainNumber;
+
+/// How long resolved requests are kept in the history
+pub const RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Columns of the `bmm_request_history` table, in the order that they are
+/// read by [`BmmRequestRecord::from_row`]
+pub(super) const COLUMNS: &str = "txid, sidechain_number, prev_block_hash, side_block_hash, bid_sats, fee_sats, origin, state, created_at, broadcast_at, resolved_at, block_hash, replaced_by";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BmmRequestOrigin {
+    /// Created by the wallet
+    Created,
+    /// Seen in a block template
+    Observed,
+}
+
+impl BmmRequestOrigin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Observed => "observed",
+        }
+    }
+}
+
+impl FromSql for BmmRequestOrigin {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "created" => Ok(Self::Created),
+            "observed" => Ok(Self::Observed),
+            other => Err(FromSqlError::Other(
+                format!("unknown BMM request origin `{other}`").into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for BmmRequestOrigin {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BmmRequestState {
+    /// Signed, but not seen in the node's mempool yet
+    Created,
+    /// Seen in the node's mempool
+    Broadcast,
+    /// Included in a block with a matching BMM accept (M7)
+    Accepted,
+    /// Included in a block without a matching BMM accept (M7)
+    NotAccepted,
+    /// Not included in the block following the previous mainchain block that
+    /// the request commits to
+    Expired,
+    /// Replaced by a tx paying a higher fee
+    Replaced,
+}
+
+impl BmmRequestState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Broadcast => "broadcast",
+            Self::Accepted => "accepted",
+            Self::NotAccepted => "not_accepted",
+            Self::Expired => "expired",
+            Self::Replaced => "replaced",
+        }
+    }
+
+    /// Returns `true` if the state is final
+    pub fn is_resolved(self) -> bool {
+        match self {
+            Self::Created | Self::Broadcast => false,
+            Self::Accepted | Self::NotAccepted | Self::Expired | Self::Replaced => true,
+        }
+    }
+}
+
+impl FromSql for BmmRequestState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "created" => Ok(Self::Created),
+            "broadcast" => Ok(Self::Broadcast),
+            "accepted" => Ok(Self::Accepted),
+            "not_accepted" => Ok(Self::NotAccepted),
+            "expired" => Ok(Self::Expired),
+            "replaced" => Ok(Self::Replaced),
+            other => Err(FromSqlError::Other(
+                format!("unknown BMM request state `{other}`").into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for BmmRequestState {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+/// Timestamps are seconds since the unix epoch
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BmmRequestRecord {
+    pub txid: Txid,
+    pub sidechain_number: SidechainNumber,
+    pub prev_block_hash: BlockHash,
+    pub sidechain_block_hash: [u8; 32],
+    /// Value of the M8 output
+    pub bid: Amount,
+    /// Fee paid by the M8 tx. Estimated from the fee rate for requests
+    /// created by the wallet.
+    pub fee: Amount,
+    pub origin: BmmRequestOrigin,
+    pub state: BmmRequestState,
+    pub created_at: u64,
+    pub broadcast_at: Option<u64>,
+    pub resolved_at: Option<u64>,
+    /// Block that included the request, if it was included
+    pub block_hash: Option<BlockHash>,
+    /// Tx that replaced the request, if it was replaced
+    pub replaced_by: Option<Txid>,
+}
+
+impl BmmRequestRecord {
+    /// Read a record from a row with [`COLUMNS`]
+    pub(super) fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let txid: [u8; 32] = row.get(0)?;
+        let prev_block_hash: [u8; 32] = row.get(2)?;
+        let block_hash: Option<[u8; 32]> = row.get(11)?;
+        let replaced_by: Option<[u8; 32]> = row.get(12)?;
+        Ok(Self {
+            txid: Txid::from_byte_array(txid),
+            sidechain_number: SidechainNumber(row.get(1)?),
+            prev_block_hash: BlockHash::from_byte_array(prev_block_hash),
+            sidechain_block_hash: row.get(3)?,
+            bid: Amount::from_sat(row.get(4)?),
+            fee: Amount::from_sat(row.get(5)?),
+            origin: row.get(6)?,
+            state: row.get(7)?,
+            created_at: row.get(8)?,
+            broadcast_at: row.get(9)?,
+            resolved_at: row.get(10)?,
+            block_hash: block_hash.map(BlockHash::from_byte_array),
+            replaced_by: replaced_by.map(Txid::from_byte_array),
+        })
+    }
+}
+
+/// Aggregate counts and spend for BMM requests created by the wallet
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BmmRequestSummary {
+    pub accepted: u64,
+    pub not_accepted: u64,
+    pub expired: u64,
+    pub replaced: u64,
+    /// Requests that are created or broadcast, but not resolved yet
+    pub unresolved: u64,
+    /// Bids and fees paid by accepted requests. Other requests are not
+    /// included in blocks, so they do not spend anything.
+    pub spent: Amount,
+}
+
+impl BmmRequestSummary {
+    /// Requests observed in block templates are not counted, since they
+    /// were not paid for by the wallet
+    pub fn from_records<'a, I>(records: I) -> Self
+    where
+        I: IntoIterator<Item = &'a BmmRequestRecord>,
+    {
+        let mut summary = Self::default();
+        for record in records {
+            if record.origin != BmmRequestOrigin::Created {
+                continue;
+            }
+            match record.state {
+                BmmRequestState::Created | BmmRequestState::Broadcast => summary.unresolved += 1,
+                BmmRequestState::Accepted => {
+                    summary.accepted += 1;
+                    summary.spent += record.bid + record.fee;
+                }
+                BmmRequestState::NotAccepted => summary.not_accepted += 1,
+                BmmRequestState::Expired => summary.expired += 1,
+                BmmRequestState::Replaced => summary.replaced += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// Seconds since the unix epoch
+pub(super) fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Requests that are not resolved yet, oldest first
+pub(super) fn list_unresolved(connection: &Connection) -> rusqlite::Result<Vec<BmmRequestRecord>> {
+    let mut statement = connection.prepare(&format!(
+        "SELECT {COLUMNS} FROM bmm_request_history WHERE state IN (?1, ?2) ORDER BY created_at, rowid"
+    ))?;
+    let rows = statement
+        .query_map(
+            (BmmRequestState::Created, BmmRequestState::Broadcast),
+            BmmRequestRecord::from_row,
+        )?
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
+
+/// Delete requests that were resolved before `resolved_before`.
+/// Returns the number of deleted requests.
+pub(super) fn prune(connection: &Connection, resolved_before: u64) -> rusqlite::Result<usize> {
+    connection.execute(
+        "DELETE FROM bmm_request_history WHERE resolved_at < ?1",
+        [resolved_before],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash as _, Amount, BlockHash, Txid};
+    use rusqlite::Connection;
+
+    use super::{
+        list_unresolved, prune, BmmRequestOrigin, BmmRequestRecord, BmmRequestState,
+        BmmRequestSummary, COLUMNS,
+    };
+    use crate::types::SidechainNumber;
+
+    fn record(state: BmmRequestState, bid: u64, fee: u64) -> BmmRequestRecord {
+        BmmRequestRecord {
+            txid: Txid::all_zeros(),
+            sidechain_number: SidechainNumber(1),
+            prev_block_hash: BlockHash::all_zeros(),
+            sidechain_block_hash: [0; 32],
+            bid: Amount::from_sat(bid),
+            fee: Amount::from_sat(fee),
+            origin: BmmRequestOrigin::Created,
+            state,
+            created_at: 0,
+            broadcast_at: None,
+            resolved_at: None,
+            block_hash: None,
+            replaced_by: None,
+        }
+    }
+
+    #[test]
+    fn summary_only_counts_spend_of_accepted_requests() {
+        let records = [
+            record(BmmRequestState::Accepted, 1_000, 200),
+            record(BmmRequestState::Accepted, 2_000, 300),
+            record(BmmRequestState::Expired, 5_000, 500),
+            record(BmmRequestState::Replaced, 1_000, 100),
+            record(BmmRequestState::NotAccepted, 1_000, 100),
+            record(BmmRequestState::Broadcast, 1_000, 100),
+            record(BmmRequestState::Created, 1_000, 100),
+        ];
+        let summary = BmmRequestSummary::from_records(&records);
+        assert_eq!(
+            summary,
+            BmmRequestSummary {
+                accepted: 2,
+                not_accepted: 1,
+                expired: 1,
+                replaced: 1,
+                unresolved: 2,
+                spent: Amount::from_sat(3_500),
+            }
+        );
+    }
+
+    #[test]
+    fn summary_only_counts_created_requests() {
+        let observed = BmmRequestRecord {
+            origin: BmmRequestOrigin::Observed,
+            ..record(BmmRequestState::Accepted, 1_000, 200)
+        };
+        let records = [observed, record(BmmRequestState::Expired, 1_000, 200)];
+        let summary = BmmRequestSummary::from_records(&records);
+        assert_eq!(
+            summary,
+            BmmRequestSummary {
+                expired: 1,
+                ..BmmRequestSummary::default()
+            }
+        );
+    }
+
+    #[test]
+    fn prune_only_removes_old_resolved_requests() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        crate::wallet::migrations()
+            .to_latest(&mut connection)
+            .unwrap();
+        let records = [
+            (BmmRequestState::Accepted, Some(10)),
+            (BmmRequestState::Expired, Some(20)),
+            (BmmRequestState::Replaced, Some(30)),
+            (BmmRequestState::Broadcast, None),
+            (BmmRequestState::Created, None),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (state, resolved_at))| BmmRequestRecord {
+            txid: Txid::from_byte_array([idx as u8; 32]),
+            created_at: idx as u64,
+            resolved_at,
+            ..record(state, 1_000, 200)
+        })
+        .collect::<Vec<_>>();
+        for record in &records {
+            connection
+                .execute(
+                    &format!(
+                        "INSERT INTO bmm_request_history ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, NULL, NULL)"
+                    ),
+                    rusqlite::params![
+                        record.txid.to_byte_array(),
+                        record.sidechain_number.0,
+                        record.prev_block_hash.to_byte_array(),
+                        record.sidechain_block_hash,
+                        record.bid.to_sat(),
+                        record.fee.to_sat(),
+                        record.origin,
+                        record.state,
+                        record.created_at,
+                        record.broadcast_at,
+                        record.resolved_at,
+                    ],
+                )
+                .unwrap();
+        }
+        assert_eq!(list_unresolved(&connection).unwrap(), records[3..]);
+        assert_eq!(prune(&connection, 25).unwrap(), 2);
+        let remaining: Vec<BmmRequestRecord> = connection
+            .prepare(&format!(
+                "SELECT {COLUMNS} FROM bmm_request_history ORDER BY rowid"
+            ))
+            .unwrap()
+            .query_map([], BmmRequestRecord::from_row)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(remaining, records[2..]);
+    }
+}
@@ -38,3 +38,28 @@ pub struct BitcoinCoreRPC {
 #[error("failed to consensus encode block")]
 #[diagnostic(code(encode_block_error))]
 pub struct EncodeBlock(#[from] pub bitcoin::io::Error);
+
+#[derive(Debug, Diagnostic, Error)]
+pub enum ProposeWithdrawalBundle {
+    #[error("failed to read CTIP: {0:#}")]
+    GetCtip(miette::Report),
+    #[error("invalid withdrawal bundle")]
+    InvalidBundle(#[source] crate::messages::M6LayoutError),
+    #[error("sidechain {0} has no treasury UTXO")]
+    NoCtip(crate::types::SidechainNumber),
+    #[error("failed to store bundle proposal")]
+    StoreProposal(#[source] rusqlite::Error),
+}
+
+impl From<ProposeWithdrawalBundle> for tonic::Status {
+    fn from(error: ProposeWithdrawalBundle) -> Self {
+        let code = match error {
+            ProposeWithdrawalBundle::InvalidBundle(_) => tonic::Code::InvalidArgument,
+            ProposeWithdrawalBundle::NoCtip(_) => tonic::Code::FailedPrecondition,
+            ProposeWithdrawalBundle::GetCtip(_) | ProposeWithdrawalBundle::StoreProposal(_) => {
+                tonic::Code::Internal
+            }
+        };
+        Self::new(code, format!("{:#}", anyhow::Error::from(error)))
+    }
+}
@@ -0,0 +1,93 @@
+//! Fee estimation via `estimatesmartfee`
+
+use bdk_wallet::bitcoin::{FeeRate, Network};
+use bip300301::jsonrpsee::core::{client::ClientT as _, rpc_params};
+use serde::Deserialize;
+
+/// Confirmation target for deposits
+pub const DEPOSIT_CONF_TARGET: u16 = 6;
+
+/// Confirmation target for BMM requests. BMM requests are only valid for
+/// the next block.
+pub const BMM_REQUEST_CONF_TARGET: u16 = 1;
+
+#[derive(Deserialize)]
+struct EstimateSmartFeeResponse {
+    /// Fee rate in BTC/kvB
+    #[serde(default)]
+    feerate: Option<f64>,
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+/// Default fallback fee rate, used if the node cannot estimate fees
+fn default_fallback_fee_rate(network: Network) -> FeeRate {
+    match network {
+        Network::Bitcoin => FeeRate::from_sat_per_vb_unchecked(10),
+        _ => FeeRate::from_sat_per_vb_unchecked(1),
+    }
+}
+
+pub struct FeeEstimator {
+    main_client: crate::rpc_client::Client,
+    fallback_fee_rate: FeeRate,
+}
+
+impl FeeEstimator {
+    /// If `fallback_sat_per_vb` is `None`, a default for the network is used
+    pub fn new(
+        main_client: crate::rpc_client::Client,
+        network: Network,
+        fallback_sat_per_vb: Option<u64>,
+    ) -> Self {
+        let fallback_fee_rate = fallback_sat_per_vb
+            .map(FeeRate::from_sat_per_vb_unchecked)
+            .unwrap_or_else(|| default_fallback_fee_rate(network));
+        Self {
+            main_client,
+            fallback_fee_rate,
+        }
+    }
+
+    /// Estimate a fee rate for confirmation within `conf_target` blocks.
+    /// Falls back to the configured fallback fee rate if the node cannot
+    /// estimate fees, eg. on regtest or with too little mempool data.
+    pub async fn estimate(&self, conf_target: u16) -> FeeRate {
+        let resp: EstimateSmartFeeResponse = match self
+            .main_client
+            .request("estimatesmartfee", rpc_params![conf_target])
+            .await
+        {
+            Ok(resp) => resp,
+            Err(err) => {
+                tracing::warn!(
+                    "failed to call `estimatesmartfee`, using fallback fee rate: {err:#}"
+                );
+                return self.fallback_fee_rate;
+            }
+        };
+        let fee_rate = resp
+            .feerate
+            .and_then(crate::rpc_client::fee_rate_from_btc_per_kvb);
+        match fee_rate {
+            Some(fee_rate) => {
+                let fee_rate = fee_rate.max(FeeRate::BROADCAST_MIN);
+                tracing::debug!(
+                    conf_target,
+                    "estimated fee rate: {} sat/vB",
+                    fee_rate.to_sat_per_vb_ceil()
+                );
+                fee_rate
+            }
+            None => {
+                tracing::debug!(
+                    conf_target,
+                    errors = ?resp.errors,
+                    "fee estimation unavailable, using fallback fee rate: {} sat/vB",
+                    self.fallback_fee_rate.to_sat_per_vb_ceil()
+                );
+                self.fallback_fee_rate
+            }
+        }
+    }
+}
@@ -1,30 +1,17 @@
 use std::{
-    borrow::BorrowMut,
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     path::Path,
     str::FromStr,
     sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use bdk_electrum::{
-    electrum_client::{self, ElectrumApi},
-    BdkElectrumClient,
-};
-use bdk_wallet::{
-    self, file_store,
-    keys::{
-        bip39::{Language, Mnemonic},
-        DerivableKey as _, ExtendedKey,
-    },
-    ChangeSet, KeychainKind,
-};
 use bip300301::{
     client::{
         BlockchainInfo, BoolWitness, GetRawMempoolClient, GetRawTransactionClient,
         GetRawTransactionVerbose,
     },
-    jsonrpsee::http_client::HttpClient,
+    jsonrpsee::core::{client::ClientT as _, rpc_params},
     MainClient,
 };
 use bitcoin::{
@@ -33,7 +20,7 @@ use bitcoin::{
     consensus::Encodable as _,
     constants::{genesis_block, SUBSIDY_HALVING_INTERVAL},
     hash_types::TxMerkleNode,
-    hashes::{sha256, sha256d, Hash as _, HashEngine},
+    hashes::{sha256d, Hash as _},
     merkle_tree,
     opcodes::{
         all::{OP_PUSHBYTES_36, OP_RETURN},
@@ -43,18 +30,45 @@ use bitcoin::{
     Amount, Block, Network, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
 };
 use miette::{miette, IntoDiagnostic, Result};
-use parking_lot::{Mutex, RwLock};
-use rusqlite::Connection;
+use parking_lot::Mutex;
+use rusqlite::{Connection, OptionalExtension as _};
+use serde::Deserialize;
 
 use crate::{
     cli::WalletConfig,
     convert,
-    messages::{self, CoinbaseBuilder, M8_BMM_REQUEST_TAG},
-    types::{Ctip, SidechainAck, SidechainNumber, SidechainProposal},
+    deposit_address::DepositDestination,
+    messages::{self, CoinbaseBuilder, M4AckBundles, M8_BMM_REQUEST_TAG},
+    types::{Hash256, SidechainAck, SidechainNumber, SidechainProposal},
     validator::Validator,
 };
 
+use self::{
+    backend::{
+        BdkBackend, CoreBackend, DescriptorImport, FundingRequest, WalletBackend, WalletBackendKind,
+    },
+    block_template::{FilteredBlockTemplate, TemplateBmmRequest},
+    bmm_rebid::{BmmRebid, BmmRebidStop},
+    bmm_request_history::{BmmRequestOrigin, BmmRequestRecord, BmmRequestState},
+    fee_estimator::FeeEstimator,
+    voting_policy::{BundleVote, VotingPolicy},
+};
+
+pub mod backend;
+pub mod block_template;
+pub mod bmm_rebid;
+pub mod bmm_request_history;
 pub mod error;
+pub mod fee_estimator;
+pub mod voting_policy;
+
+/// Block generated on regtest
+#[derive(Debug)]
+pub struct GeneratedBlock {
+    pub block_hash: bitcoin::BlockHash,
+    /// Coinbase outputs containing BIP300/301 messages
+    pub coinbase_outputs: Vec<TxOut>,
+}
 
 #[derive(Debug)]
 pub struct Deposit {
@@ -78,31 +92,135 @@ fn get_block_value(height: u32, fees: Amount, network: Network) -> Amount {
     }
 }
 
+/// Migrations for the wallet's SQLite DB
+fn migrations() -> rusqlite_migration::Migrations<'static> {
+    use rusqlite_migration::{Migrations, M};
+    Migrations::new(vec![
+        M::up(
+            "CREATE TABLE sidechain_proposals
+           (number INTEGER NOT NULL,
+            data BLOB NOT NULL,
+            UNIQUE(number, data));",
+        ),
+        M::up(
+            "CREATE TABLE sidechain_acks
+           (number INTEGER NOT NULl,
+            data_hash BLOB NOT NULL,
+            UNIQUE(number, data_hash));",
+        ),
+        M::up(
+            "CREATE TABLE bundle_proposals
+           (sidechain_number INTEGER NOT NULL,
+            bundle_hash BLOB NOT NULL,
+            UNIQUE(sidechain_number, bundle_hash));",
+        ),
+        M::up(
+            "CREATE TABLE bundle_acks
+           (sidechain_number INTEGER NOT NULL,
+            bundle_hash BLOB NOT NULL,
+            UNIQUE(sidechain_number, bundle_hash));",
+        ),
+        M::up(
+            "CREATE TABLE bmm_requests
+            (sidechain_number INTEGER NOT NULL,
+             prev_block_hash BLOB NOT NULL,
+             side_block_hash BLOB NOT NULL,
+             UNIQUE(sidechain_number, prev_block_hash));",
+        ),
+        M::up("ALTER TABLE bmm_requests ADD COLUMN tx BLOB;"),
+        M::up("ALTER TABLE bmm_requests ADD COLUMN fee_rate_sat_per_kwu INTEGER;"),
+        M::up(
+            "CREATE TABLE bundle_votes
+            (sidechain_number INTEGER NOT NULL PRIMARY KEY,
+             vote TEXT NOT NULL,
+             m6id BLOB);",
+        ),
+        M::up(
+            "CREATE TABLE bmm_request_history
+            (txid BLOB NOT NULL PRIMARY KEY,
+             sidechain_number INTEGER NOT NULL,
+             prev_block_hash BLOB NOT NULL,
+             side_block_hash BLOB NOT NULL,
+             bid_sats INTEGER NOT NULL,
+             fee_sats INTEGER NOT NULL,
+             origin TEXT NOT NULL,
+             state TEXT NOT NULL,
+             created_at INTEGER NOT NULL,
+             broadcast_at INTEGER,
+             resolved_at INTEGER,
+             block_hash BLOB,
+             replaced_by BLOB);",
+        ),
+        M::up(
+            "CREATE TABLE bmm_rebids
+            (sidechain_number INTEGER NOT NULL PRIMARY KEY,
+             side_block_hash BLOB NOT NULL,
+             bid_sats INTEGER NOT NULL,
+             max_attempts INTEGER NOT NULL,
+             max_total_fee_sats INTEGER NOT NULL,
+             attempts INTEGER NOT NULL,
+             total_fee_sats INTEGER NOT NULL,
+             last_prev_block_hash BLOB NOT NULL);",
+        ),
+        M::up("CREATE INDEX bmm_request_history_state ON bmm_request_history (state);"),
+    ])
+}
+
+/// Values of the `vote` and `m6id` columns in the `bundle_votes` table
+fn bundle_vote_columns(vote: &BundleVote) -> (&'static str, Option<&Hash256>) {
+    match vote {
+        BundleVote::Upvote { m6id } => ("upvote", Some(m6id)),
+        BundleVote::Abstain => ("abstain", None),
+        BundleVote::Alarm => ("alarm", None),
+    }
+}
+
+#[derive(Deserialize)]
+struct MempoolEntryFees {
+    /// Fees of in-mempool ancestors, including this tx, in BTC
+    ancestor: f64,
+}
+
+#[derive(Deserialize)]
+struct MempoolEntry {
+    /// Size of in-mempool ancestors, including this tx, in vB
+    ancestorsize: u64,
+    fees: MempoolEntryFees,
+}
+
+/// Outcome of a BMM request, for a sidechain slot and previous mainchain
+/// block hash
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BmmRequestStatus {
+    /// The previous mainchain block is the current mainchain tip, so the
+    /// request can still be included in the next block
+    Pending,
+    /// The next mainchain block accepted the requested sidechain block hash
+    Accepted { block_hash: bitcoin::BlockHash },
+    /// The next mainchain block did not accept the requested sidechain
+    /// block hash
+    NotAccepted { block_hash: bitcoin::BlockHash },
+    /// The previous mainchain block is no longer in the best chain
+    Expired,
+}
+
 pub struct Wallet {
-    main_client: HttpClient,
+    main_client: crate::rpc_client::Client,
     validator: Validator,
-    bitcoin_wallet: Mutex<bdk_wallet::PersistedWallet<file_store::Store<ChangeSet>>>,
-    bitcoin_db: Mutex<file_store::Store<ChangeSet>>,
+    backend: Box<dyn WalletBackend>,
+    fee_estimator: FeeEstimator,
+    /// BMM request txs are not bumped above this fee rate automatically
+    bmm_max_fee_rate: bdk_wallet::bitcoin::FeeRate,
     db_connection: Arc<Mutex<rusqlite::Connection>>,
-    bitcoin_blockchain: BdkElectrumClient<bdk_electrum::electrum_client::Client>,
-    last_sync: Arc<RwLock<Option<SystemTime>>>,
 }
 
 impl Wallet {
     pub async fn new(
         data_dir: &Path,
         config: &WalletConfig,
-        main_client: HttpClient,
+        main_client: crate::rpc_client::Client,
         validator: Validator,
     ) -> Result<Self> {
-        let mnemonic = Mnemonic::parse_in_normalized(
-            Language::English,
-            "betray annual dog current tomorrow media ghost dynamic mule length sure salad",
-        )
-        .into_diagnostic()?;
-        // Generate the extended key
-        let xkey: ExtendedKey = mnemonic.clone().into_extended_key().into_diagnostic()?;
-        // Get xprv from the extended key
         let network = {
             let validator_network = validator.network();
             bdk_wallet::bitcoin::Network::from_str(validator_network.to_string().as_str())
@@ -115,124 +233,20 @@ impl Wallet {
             data_dir.display()
         );
 
-        let xprv = xkey
-            .into_xprv(network)
-            .ok_or(miette!("couldn't get xprv"))?;
-
-        let mut wallet_database = file_store::Store::open_or_create_new(
-            b"bip300301_enforcer",
-            data_dir.join("wallet.db"),
-        )
-        .into_diagnostic()?;
-
-        // Create a BDK wallet structure using BIP 84 descriptor ("m/84h/1h/0h/0" and "m/84h/1h/0h/1")
-
-        let external_desc = format!("wpkh({xprv}/84'/1'/0'/0/*)");
-        let internal_desc = format!("wpkh({xprv}/84'/1'/0'/1/*)");
-
-        tracing::debug!("Attempting load of existing BDK wallet");
-        let bitcoin_wallet = bdk_wallet::Wallet::load()
-            .descriptor(KeychainKind::External, Some(external_desc.clone()))
-            .descriptor(KeychainKind::Internal, Some(internal_desc.clone()))
-            .extract_keys()
-            .check_network(network)
-            .load_wallet(&mut wallet_database)
-            .map_err(|err| miette!("failed to load wallet: {err:#}"))?;
-
-        let bitcoin_wallet = match bitcoin_wallet {
-            Some(wallet) => {
-                tracing::info!("Loaded existing BDK wallet");
-                wallet
-            }
-
-            None => {
-                tracing::info!("Creating new BDK wallet");
-
-                bdk_wallet::Wallet::create(external_desc, internal_desc)
-                    .network(network)
-                    .create_wallet(&mut wallet_database)
-                    .map_err(|err| miette!("failed to create wallet: {err:#}"))?
-            }
-        };
-
-        let bitcoin_blockchain = {
-            let (default_host, default_port) = match network {
-                Network::Signet => ("drivechain.live", 50001),
-                Network::Regtest => ("127.0.0.1", 60401), // Default for romanz/electrs
-                default => return Err(miette!("unsupported network: {default}")),
-            };
-
-            let electrum_host = config
-                .electrum_host
-                .clone()
-                .unwrap_or(default_host.to_string());
-            let electrum_port = config.electrum_port.unwrap_or(default_port);
-
-            let electrum_url = format!("{}:{}", electrum_host, electrum_port);
-
-            tracing::debug!("creating electrum client: {electrum_url}");
-
-            // Apply a reasonably short timeout to prevent the wallet from hanging
-            let timeout = 5;
-            let config = electrum_client::ConfigBuilder::new()
-                .timeout(Some(timeout))
-                .build();
-
-            let electrum_client = electrum_client::Client::from_config(&electrum_url, config)
-                .map_err(|err| miette!("failed to create electrum client: {err:#}"))?;
-
-            // let features = electrum_client.server_features().into_diagnostic()?;
-            let header = electrum_client.block_header(0).into_diagnostic()?;
-
-            // Verify the Electrum server is on the same chain as we are.
-            if header.block_hash().as_byte_array() != network.chain_hash().as_bytes() {
-                return Err(miette!(
-                    "Electrum server ({}) is not on the same chain as the wallet ({})",
-                    header.block_hash(),
-                    network.chain_hash(),
-                ));
+        let backend: Box<dyn WalletBackend> = match config.backend {
+            WalletBackendKind::Bdk => Box::new(BdkBackend::new(data_dir, config, network)?),
+            WalletBackendKind::Core => {
+                tracing::info!("Using the wallet of the connected Bitcoin Core node");
+                Box::new(CoreBackend::new(main_client.clone(), network))
             }
-
-            BdkElectrumClient::new(electrum_client)
         };
 
-        use rusqlite_migration::{Migrations, M};
+        let fee_estimator =
+            FeeEstimator::new(main_client.clone(), network, config.fallback_fee_rate);
 
         let db_connection = {
             // 1️⃣ Define migrations
-            let migrations = Migrations::new(vec![
-                M::up(
-                    "CREATE TABLE sidechain_proposals
-                   (number INTEGER NOT NULL,
-                    data BLOB NOT NULL,
-                    UNIQUE(number, data));",
-                ),
-                M::up(
-                    "CREATE TABLE sidechain_acks
-                   (number INTEGER NOT NULl,
-                    data_hash BLOB NOT NULL,
-                    UNIQUE(number, data_hash));",
-                ),
-                M::up(
-                    "CREATE TABLE bundle_proposals
-                   (sidechain_number INTEGER NOT NULL,
-                    bundle_hash BLOB NOT NULL,
-                    UNIQUE(sidechain_number, bundle_hash));",
-                ),
-                M::up(
-                    "CREATE TABLE bundle_acks
-                   (sidechain_number INTEGER NOT NULL,
-                    bundle_hash BLOB NOT NULL,
-                    UNIQUE(sidechain_number, bundle_hash));",
-                ),
-                M::up(
-                    "CREATE TABLE bmm_requests
-                    (sidechain_number INTEGER NOT NULL,
-                     prev_block_hash BLOB NOT NULL,
-                     side_block_hash BLOB NOT NULL,
-                     UNIQUE(sidechain_number, prev_block_hash));",
-                ),
-            ]);
+            let migrations = migrations();
 
             let db_name = "db.sqlite";
             let path = data_dir.join(db_name);
@@ -249,14 +263,18 @@ impl Wallet {
         let wallet = Self {
             main_client,
             validator,
-            // bitcoin_wallet: Arc::new(Mutex::new(bitcoin_wallet)),
-            bitcoin_wallet: Mutex::new(bitcoin_wallet),
-            bitcoin_db: Mutex::new(wallet_database),
+            backend,
+            fee_estimator,
+            bmm_max_fee_rate: bdk_wallet::bitcoin::FeeRate::from_sat_per_vb_unchecked(
+                config.bmm_max_fee_rate,
+            ),
             db_connection: Arc::new(Mutex::new(db_connection)),
-            bitcoin_blockchain,
-
-            last_sync: Arc::new(RwLock::new(None)),
         };
+        if let Some(path) = &config.voting_policy_file {
+            let voting_policy = VotingPolicy::load(path).into_diagnostic()?;
+            wallet.set_voting_policy(&voting_policy)?;
+            tracing::info!("Loaded voting policy from {}", path.display());
+        }
         Ok(wallet)
     }
 
@@ -269,7 +287,7 @@ impl Wallet {
         coinbase_outputs: &[TxOut],
         transactions: Vec<Transaction>,
     ) -> Result<Block> {
-        let addr = self.get_new_address()?;
+        let addr = self.get_new_address().await?;
 
         tracing::debug!("Generate block: fetched address: {}", addr);
 
@@ -371,7 +389,7 @@ impl Wallet {
         Ok(block)
     }
 
-    /// Returns pending sidechain proposals from the wallet. These are not yet
+    /// Returns queued sidechain proposals from the wallet. These are not yet
     /// active on the chain, and not possible to vote on.
     fn get_our_sidechain_proposals(&self) -> Result<Vec<SidechainProposal>, rusqlite::Error> {
         // Satisfy clippy with a single function call per lock
@@ -395,6 +413,49 @@ impl Wallet {
         with_connection(&self.db_connection.lock())
     }
 
+    /// Returns queued sidechain proposals that have not yet been included in
+    /// a block seen by the validator, to be included in the next block.
+    /// Queued proposals that have been included are removed from the queue.
+    /// They continue to be acked via the ack that was added when the
+    /// proposal was queued, until they are activated or fail.
+    fn get_unincluded_sidechain_proposals(&self) -> Result<Vec<SidechainProposal>> {
+        let our_proposals = self.get_our_sidechain_proposals().into_diagnostic()?;
+        if our_proposals.is_empty() {
+            return Ok(our_proposals);
+        }
+        let mut included_description_hashes: HashSet<sha256d::Hash> = self
+            .validator
+            .get_sidechains()?
+            .into_iter()
+            .map(|(description_hash, _sidechain)| description_hash)
+            .collect();
+        included_description_hashes.extend(
+            self.validator
+                .get_active_sidechains()?
+                .iter()
+                .map(|sidechain| sidechain.proposal.description.sha256d_hash()),
+        );
+        let (included, unincluded): (Vec<_>, Vec<_>) =
+            our_proposals.into_iter().partition(|proposal| {
+                included_description_hashes.contains(&proposal.description.sha256d_hash())
+            });
+        for proposal in included {
+            tracing::info!(
+                sidechain_slot = proposal.sidechain_number.0,
+                "Sidechain proposal `{}` was included in a block, removing from queue",
+                proposal.description.sha256d_hash()
+            );
+            self.db_connection
+                .lock()
+                .execute(
+                    "DELETE FROM sidechain_proposals WHERE number = ?1 AND data = ?2",
+                    (proposal.sidechain_number.0, &proposal.description.0),
+                )
+                .into_diagnostic()?;
+        }
+        Ok(unincluded)
+    }
+
     fn get_sidechain_acks(&self) -> Result<Vec<SidechainAck>> {
         // Satisfy clippy with a single function call per lock
         let with_connection = |connection: &Connection| -> Result<_> {
@@ -473,6 +534,123 @@ impl Wallet {
         }
     }
 
+    /// Persist a voting policy. Sidechain acks are added to existing acks,
+    /// and bundle votes replace all existing bundle votes.
+    pub fn set_voting_policy(&self, voting_policy: &VotingPolicy) -> Result<()> {
+        let mut connection = self.db_connection.lock();
+        let tx = connection.transaction().into_diagnostic()?;
+        for ack in &voting_policy.sidechain_acks {
+            tx.execute(
+                "INSERT OR IGNORE INTO sidechain_acks (number, data_hash) VALUES (?1, ?2)",
+                (ack.sidechain_number.0, ack.description_hash.as_byte_array()),
+            )
+            .into_diagnostic()?;
+        }
+        tx.execute("DELETE FROM bundle_votes", ())
+            .into_diagnostic()?;
+        for (sidechain_number, vote) in &voting_policy.bundle_votes {
+            let (vote, m6id) = bundle_vote_columns(vote);
+            tx.execute(
+                "INSERT INTO bundle_votes (sidechain_number, vote, m6id) VALUES (?1, ?2, ?3)",
+                (sidechain_number.0, vote, m6id),
+            )
+            .into_diagnostic()?;
+        }
+        tx.commit().into_diagnostic()?;
+        Ok(())
+    }
+
+    /// Update the persisted voting policy. `ack_sidechains` are added to
+    /// existing acks, and `nack_sidechains` are removed. Bundle votes replace
+    /// existing bundle votes for the same sidechain, and votes for other
+    /// sidechains are kept.
+    /// Changes apply to coinbase messages produced after the update.
+    pub fn update_voting_policy(
+        &self,
+        ack_sidechains: &[SidechainAck],
+        nack_sidechains: &[SidechainAck],
+        bundle_votes: &BTreeMap<SidechainNumber, BundleVote>,
+    ) -> Result<()> {
+        voting_policy::update(
+            &mut self.db_connection.lock(),
+            ack_sidechains,
+            nack_sidechains,
+            bundle_votes,
+        )
+        .into_diagnostic()
+    }
+
+    /// The persisted voting policy
+    pub fn get_voting_policy(&self) -> Result<VotingPolicy> {
+        Ok(VotingPolicy {
+            sidechain_acks: self.get_sidechain_acks()?,
+            bundle_votes: self.get_bundle_votes()?,
+        })
+    }
+
+    fn get_bundle_votes(&self) -> Result<BTreeMap<SidechainNumber, BundleVote>> {
+        // Satisfy clippy with a single function call per lock
+        let with_connection = |connection: &Connection| -> Result<_> {
+            let mut statement = connection
+                .prepare("SELECT sidechain_number, vote, m6id FROM bundle_votes")
+                .into_diagnostic()?;
+            let rows: Vec<(u8, String, Option<[u8; 32]>)> = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .into_diagnostic()?
+                .collect::<Result<_, _>>()
+                .into_diagnostic()?;
+            Ok(rows)
+        };
+        let rows = with_connection(&self.db_connection.lock())?;
+        rows.into_iter()
+            .map(|(sidechain_number, vote, m6id)| {
+                let vote = match (vote.as_str(), m6id) {
+                    ("upvote", Some(m6id)) => BundleVote::Upvote { m6id },
+                    ("abstain", None) => BundleVote::Abstain,
+                    ("alarm", None) => BundleVote::Alarm,
+                    _ => return Err(miette!("invalid bundle vote in DB: `{vote}`")),
+                };
+                Ok((SidechainNumber(sidechain_number), vote))
+            })
+            .collect()
+    }
+
+    /// M4 message for the next block, for the current pending M6IDs and
+    /// according to the voting policy. The most compact encoding of the votes
+    /// is used. Returns `None` if no M4 message is needed.
+    fn m4_ack_bundles(&self) -> Result<Option<M4AckBundles>> {
+        let bundle_votes = self.get_bundle_votes()?;
+        let mut pending_m6ids = BTreeMap::new();
+        for sidechain in self.validator.get_active_sidechains()? {
+            let sidechain_number = sidechain.proposal.sidechain_number;
+            let sidechain_pending_m6ids = self.validator.get_pending_m6ids(sidechain_number)?;
+            if !sidechain_pending_m6ids.is_empty() {
+                pending_m6ids.insert(sidechain_number, sidechain_pending_m6ids);
+            }
+        }
+        let upvotes = voting_policy::bundle_upvotes(&bundle_votes, &pending_m6ids);
+        let previous_upvotes = self.validator.get_previous_votes()?;
+        let leading_by_50_upvotes = messages::leading_by_50_upvotes(&pending_m6ids);
+        Ok(M4AckBundles::compact(
+            &upvotes,
+            &previous_upvotes,
+            &leading_by_50_upvotes,
+        ))
+    }
+
+    /// Coinbase output with the M4 message for the next block, if an M4
+    /// message is needed. See [`Self::m4_ack_bundles`].
+    pub fn next_m4_output(&self) -> Result<Option<TxOut>> {
+        let Some(m4) = self.m4_ack_bundles()? else {
+            return Ok(None);
+        };
+        let outputs = CoinbaseBuilder::new()
+            .ack_bundles(m4)
+            .build()
+            .into_diagnostic()?;
+        Ok(outputs.into_iter().next())
+    }
+
     fn delete_sidechain_ack(&self, ack: &SidechainAck) -> Result<()> {
         self.db_connection
             .lock()
@@ -514,11 +692,10 @@ impl Wallet {
     }
 
     // Gets wiped upon generating a new block.
-    // TODO: how will this work for non-regtest?
-    fn delete_pending_sidechain_proposals(&self) -> Result<()> {
+    fn delete_bundle_proposals(&self) -> Result<()> {
         self.db_connection
             .lock()
-            .execute("DELETE FROM sidechain_proposals;", ())
+            .execute("DELETE FROM bundle_proposals;", ())
             .into_diagnostic()?;
         Ok(())
     }
@@ -536,7 +713,125 @@ impl Wallet {
         Ok(())
     }
 
-    async fn mine(&self, coinbase_outputs: &[TxOut], transactions: Vec<Transaction>) -> Result<()> {
+    /// Fetch a block template from the node if `template` is not set, and
+    /// filter the BMM requests in it, so that the best BMM request for each
+    /// active sidechain is kept.
+    async fn filtered_block_template(
+        &self,
+        template: Option<serde_json::Value>,
+    ) -> Result<FilteredBlockTemplate> {
+        let template = match template {
+            Some(template) => template,
+            None => self
+                .main_client
+                .request(
+                    "getblocktemplate",
+                    rpc_params![serde_json::json!({"rules": ["segwit"]})],
+                )
+                .await
+                .map_err(|err| error::BitcoinCoreRPC {
+                    method: "getblocktemplate".to_string(),
+                    error: err,
+                })?,
+        };
+        let active_sidechains = self
+            .validator
+            .get_active_sidechains()?
+            .into_iter()
+            .map(|sidechain| sidechain.proposal.sidechain_number)
+            .collect();
+        let template =
+            block_template::filter_bmm_requests(template, &active_sidechains).into_diagnostic()?;
+        let now = bmm_request_history::unix_timestamp();
+        for bmm_request in &template.bmm_requests {
+            self.insert_bmm_request_record(&BmmRequestRecord {
+                txid: bmm_request.txid,
+                sidechain_number: bmm_request.sidechain_number,
+                prev_block_hash: template.prev_blockhash,
+                sidechain_block_hash: bmm_request.sidechain_block_hash,
+                bid: bmm_request.bid,
+                fee: bmm_request.fee,
+                origin: BmmRequestOrigin::Observed,
+                state: BmmRequestState::Broadcast,
+                created_at: now,
+                broadcast_at: Some(now),
+                resolved_at: None,
+                block_hash: None,
+                replaced_by: None,
+            })?;
+        }
+        let mainchain_tip = self.validator.get_mainchain_tip()?;
+        if template.prev_blockhash != mainchain_tip {
+            tracing::warn!(
+                "block template builds on `{}`, but the enforcer tip is `{mainchain_tip}`",
+                template.prev_blockhash
+            );
+        }
+        Ok(template)
+    }
+
+    /// Select the BMM request that pays the highest fee for each active
+    /// sidechain, from the BMM requests in the node's mempool that can be
+    /// included in the next block. Returns the previous block hash of the
+    /// block template, and the selected BMM requests.
+    pub async fn best_bmm_requests(&self) -> Result<(bitcoin::BlockHash, Vec<TemplateBmmRequest>)> {
+        let template = self.filtered_block_template(None).await?;
+        Ok((template.prev_blockhash, template.bmm_requests))
+    }
+
+    /// Augment a `getblocktemplate` response for mining with BIP300/301
+    /// messages. If `template` is not set, a template is fetched from the
+    /// node.
+    /// BMM requests in the template are filtered so that the BMM request that
+    /// pays the highest fee for each active sidechain is accepted. Returns the
+    /// modified template, and coinbase outputs containing our sidechain
+    /// proposals, valid sidechain acks, and BMM accepts, to be appended to the
+    /// coinbase tx.
+    pub async fn augment_block_template(
+        &self,
+        template: Option<serde_json::Value>,
+    ) -> Result<(serde_json::Value, Vec<TxOut>)> {
+        let template = self.filtered_block_template(template).await?;
+
+        let mut coinbase_builder = CoinbaseBuilder::new();
+        for sidechain_proposal in self.get_unincluded_sidechain_proposals()? {
+            coinbase_builder = coinbase_builder.propose_sidechain(sidechain_proposal);
+        }
+        let active_sidechain_proposals = self.get_active_sidechain_proposals().await?;
+        for sidechain_ack in self.get_sidechain_acks()? {
+            if self.validate_sidechain_ack(&sidechain_ack, &active_sidechain_proposals) {
+                coinbase_builder = coinbase_builder.ack_sidechain(
+                    sidechain_ack.sidechain_number,
+                    sidechain_ack.description_hash,
+                );
+            }
+        }
+        for bmm_request in &template.bmm_requests {
+            tracing::debug!(
+                sidechain_slot = u8::from(bmm_request.sidechain_number),
+                "accepting BMM request with fee {}, bid {} and hash {}",
+                bmm_request.fee,
+                bmm_request.bid,
+                hex::encode(bmm_request.sidechain_block_hash)
+            );
+            coinbase_builder = coinbase_builder.bmm_accept(
+                bmm_request.sidechain_number,
+                &bmm_request.sidechain_block_hash,
+            );
+        }
+        if let Some(m4) = self.m4_ack_bundles()? {
+            coinbase_builder = coinbase_builder.ack_bundles(m4);
+        }
+        let coinbase_outputs = coinbase_builder.build().into_diagnostic()?;
+        let template = template.into_json().into_diagnostic()?;
+        Ok((template, coinbase_outputs))
+    }
+
+    async fn mine(
+        &self,
+        coinbase_outputs: &[TxOut],
+        transactions: Vec<Transaction>,
+    ) -> Result<bitcoin::BlockHash> {
         let transaction_count = transactions.len();
 
         let mut block = self.generate_block(coinbase_outputs, transactions).await?;
@@ -567,19 +862,28 @@ impl Wallet {
         );
 
         std::thread::sleep(Duration::from_millis(500));
-        Ok(())
+        Ok(block.header.block_hash())
     }
 
-    pub async fn generate(&self, count: u32, ack_all_proposals: bool) -> Result<()> {
+    /// Generate blocks on regtest, including queued coinbase messages: our
+    /// sidechain proposals, sidechain acks, queued bundle proposals, bundle
+    /// votes and BMM accepts.
+    /// Returns the hash and coinbase message outputs of each generated block.
+    pub async fn generate(
+        &self,
+        count: u32,
+        ack_all_proposals: bool,
+    ) -> Result<Vec<GeneratedBlock>> {
         tracing::info!("Generate: creating {} blocks", count);
 
+        let mut generated_blocks = Vec::new();
         for _ in 0..count {
-            // This is a list of pending sidechain proposals from /our/ wallet, fetched from
-            // the DB.
-            let sidechain_proposals = self.get_our_sidechain_proposals().into_diagnostic()?;
+            // This is a list of queued sidechain proposals from /our/ wallet, that have
+            // not yet been included in a block.
+            let sidechain_proposals = self.get_unincluded_sidechain_proposals()?;
             let mut coinbase_builder = CoinbaseBuilder::new();
-            for sidechain_proposal in sidechain_proposals {
-                coinbase_builder = coinbase_builder.propose_sidechain(sidechain_proposal);
+            for sidechain_proposal in &sidechain_proposals {
+                coinbase_builder = coinbase_builder.propose_sidechain(sidechain_proposal.clone());
             }
 
             let mut sidechain_acks = self.get_sidechain_acks()?;
@@ -620,6 +924,14 @@ impl Wallet {
             }
 
             for sidechain_ack in sidechain_acks {
+                // Acks for our own proposals are kept until the proposal has
+                // been included in a block
+                if sidechain_proposals.iter().any(|proposal| {
+                    proposal.sidechain_number == sidechain_ack.sidechain_number
+                        && proposal.description.sha256d_hash() == sidechain_ack.description_hash
+                }) {
+                    continue;
+                }
                 if !self.validate_sidechain_ack(&sidechain_ack, &active_sidechain_proposals) {
                     self.delete_sidechain_ack(&sidechain_ack)?;
                     tracing::info!(
@@ -640,6 +952,23 @@ impl Wallet {
                 );
             }
 
+            for (sidechain_number, m6id) in self.get_bundle_proposals()? {
+                if !self.is_sidechain_active(sidechain_number)? {
+                    tracing::warn!(
+                        sidechain_slot = sidechain_number.0,
+                        "Generate: not proposing bundle {} for inactive sidechain",
+                        hex::encode(m6id)
+                    );
+                    continue;
+                }
+                tracing::debug!(
+                    sidechain_slot = sidechain_number.0,
+                    "Generate: adding bundle proposal {}",
+                    hex::encode(m6id)
+                );
+                coinbase_builder = coinbase_builder.propose_bundle(sidechain_number, &m6id);
+            }
+
             let mainchain_tip = self.validator.get_mainchain_tip()?;
             let bmm_hashes = self.get_bmm_requests(&mainchain_tip)?;
             for (sidechain_number, bmm_hash) in &bmm_hashes {
@@ -651,6 +980,11 @@ impl Wallet {
                 coinbase_builder = coinbase_builder.bmm_accept(*sidechain_number, bmm_hash);
             }
 
+            if let Some(m4) = self.m4_ack_bundles()? {
+                tracing::debug!("Generate: adding M4 bundle votes: {m4:?}");
+                coinbase_builder = coinbase_builder.ack_bundles(m4);
+            }
+
             let coinbase_outputs = coinbase_builder.build().into_diagnostic()?;
 
             // We want to include all transactions from the mempool into our newly generated block.
@@ -682,27 +1016,15 @@ impl Wallet {
                 mempool_transactions.len()
             );
 
-            self.mine(&coinbase_outputs, mempool_transactions).await?;
-            self.delete_pending_sidechain_proposals()?;
+            let block_hash = self.mine(&coinbase_outputs, mempool_transactions).await?;
+            self.delete_bundle_proposals()?;
             self.delete_bmm_requests(&mainchain_tip)?;
+            generated_blocks.push(GeneratedBlock {
+                block_hash,
+                coinbase_outputs,
+            });
         }
-        Ok(())
-    }
-
-    fn create_deposit_op_drivechain_output(
-        sidechain_number: SidechainNumber,
-        sidechain_ctip_amount: Amount,
-        value: Amount,
-    ) -> bdk_wallet::bitcoin::TxOut {
-        let deposit_txout =
-            messages::create_m5_deposit_output(sidechain_number, sidechain_ctip_amount, value);
-
-        bdk_wallet::bitcoin::TxOut {
-            script_pubkey: bdk_wallet::bitcoin::ScriptBuf::from_bytes(
-                deposit_txout.script_pubkey.to_bytes(),
-            ),
-            value: deposit_txout.value,
-        }
+        Ok(generated_blocks)
     }
 
     async fn fetch_transaction(&self, txid: Txid) -> Result<bdk_wallet::bitcoin::Transaction> {
@@ -724,130 +1046,37 @@ impl Wallet {
         convert::bitcoin_tx_to_bdk_tx(transaction).into_diagnostic()
     }
 
-    /// [`bdk_wallet::TxOrdering`] for deposit txs
-    fn deposit_txordering(
-        sidechain_addrs: HashMap<Vec<u8>, SidechainNumber>,
-    ) -> bdk_wallet::TxOrdering {
-        use bitcoin::hashes::{Hash, Hmac, HmacEngine};
-        use std::cmp::Ordering;
-        let hmac_engine = || {
-            let key = {
-                use rand::RngCore;
-                let mut bytes = vec![0u8; <sha256::Hash as Hash>::Engine::BLOCK_SIZE];
-                rand::thread_rng().fill_bytes(&mut bytes);
-                bytes
-            };
-            HmacEngine::<sha256::Hash>::new(&key)
-        };
-        fn hmac_sha256<T>(mut engine: HmacEngine<sha256::Hash>, value: &T) -> Hmac<sha256::Hash>
-        where
-            T: bitcoin::consensus::Encodable,
-        {
-            value
-                .consensus_encode(&mut engine)
-                .expect("should encode correctly");
-            Hmac::<sha256::Hash>::from_engine(engine)
-        }
-        let input_sort = {
-            let hmac_engine = hmac_engine();
-            move |txin_l: &bdk_wallet::bitcoin::TxIn, txin_r: &bdk_wallet::bitcoin::TxIn| {
-                let txin_l_hmac = hmac_sha256(hmac_engine.clone(), txin_l);
-                let txin_r_hmac = hmac_sha256(hmac_engine.clone(), txin_r);
-                txin_l_hmac.cmp(&txin_r_hmac)
-            }
-        };
-        enum TxOutKind {
-            OpDrivechain(SidechainNumber),
-            OpReturnAddress(SidechainNumber),
-            Other,
-        }
-        // classify as an op_drivechain output or an
-        // op_return address
-        fn classify_txout(
-            sidechain_addrs: &HashMap<Vec<u8>, SidechainNumber>,
-            txout: &bdk_wallet::bitcoin::TxOut,
-        ) -> TxOutKind {
-            if let Ok((_, sidechain_id)) =
-                crate::messages::parse_op_drivechain(txout.script_pubkey.as_bytes())
-            {
-                return TxOutKind::OpDrivechain(sidechain_id);
-            }
-            if let Some(address) =
-                crate::messages::try_parse_op_return_address(&txout.script_pubkey)
-            {
-                if let Some(sidechain_id) = sidechain_addrs.get(&address) {
-                    return TxOutKind::OpReturnAddress(*sidechain_id);
-                }
-            }
-            TxOutKind::Other
-        }
-        let output_sort = {
-            let hmac_engine = hmac_engine();
-            move |txout_l: &bdk_wallet::bitcoin::TxOut, txout_r: &bdk_wallet::bitcoin::TxOut| match (
-                classify_txout(&sidechain_addrs, txout_l),
-                classify_txout(&sidechain_addrs, txout_r),
-            ) {
-                (TxOutKind::OpDrivechain(_) | TxOutKind::OpReturnAddress(_), TxOutKind::Other) => {
-                    Ordering::Less
-                }
-                (TxOutKind::Other, TxOutKind::OpDrivechain(_) | TxOutKind::OpReturnAddress(_)) => {
-                    Ordering::Greater
-                }
-                (
-                    TxOutKind::OpDrivechain(sidechain_id_l),
-                    TxOutKind::OpDrivechain(sidechain_id_r),
-                )
-                | (
-                    TxOutKind::OpReturnAddress(sidechain_id_l),
-                    TxOutKind::OpReturnAddress(sidechain_id_r),
-                ) => sidechain_id_l.cmp(&sidechain_id_r),
-                (
-                    TxOutKind::OpDrivechain(sidechain_id_l),
-                    TxOutKind::OpReturnAddress(sidechain_id_r),
-                ) => match sidechain_id_l.cmp(&sidechain_id_r) {
-                    Ordering::Equal => Ordering::Less,
-                    ordering => ordering,
-                },
-                (
-                    TxOutKind::OpReturnAddress(sidechain_id_l),
-                    TxOutKind::OpDrivechain(sidechain_id_r),
-                ) => match sidechain_id_l.cmp(&sidechain_id_r) {
-                    Ordering::Equal => Ordering::Greater,
-                    ordering => ordering,
-                },
-                (TxOutKind::Other, TxOutKind::Other) => {
-                    let txout_l_hmac = hmac_sha256(hmac_engine.clone(), txout_l);
-                    let txout_r_hmac = hmac_sha256(hmac_engine.clone(), txout_r);
-                    txout_l_hmac.cmp(&txout_r_hmac)
-                }
-            }
-        };
-        bdk_wallet::TxOrdering::Custom {
-            input_sort: Arc::new(input_sort),
-            output_sort: Arc::new(output_sort),
-        }
-    }
-
-    #[allow(
-        clippy::significant_drop_tightening,
-        reason = "false positive for `bitcoin_wallet`"
-    )]
-    async fn create_deposit_psbt(
+    /// Build the funding request for a deposit tx.
+    /// This is also known as a M5 message, in BIP300 nomenclature.
+    /// If `fee` is not set, the fee rate is estimated, and the estimated fee
+    /// rate is returned along with the funding request.
+    ///
+    /// https://github.com/bitcoin/bips/blob/master/bip-0300.mediawiki#m5----deposit-btc-from-l1-to-l2
+    async fn deposit_funding_request(
         &self,
-        op_drivechain_output: bdk_wallet::bitcoin::TxOut,
-        sidechain_address_data: bdk_wallet::bitcoin::script::PushBytesBuf,
-        sidechain_ctip: Option<&Ctip>,
+        destination: &DepositDestination,
+        value: Amount,
         fee: Option<Amount>,
-    ) -> Result<bdk_wallet::bitcoin::psbt::Psbt> {
-        let sidechain_number = match crate::messages::parse_op_drivechain(
-            op_drivechain_output.script_pubkey.as_bytes(),
-        ) {
-            Ok((_, sidechain_number)) => sidechain_number,
-            Err(_) => return Err(miette::miette!("Failed to parse sidechain number")),
-        };
+    ) -> Result<(FundingRequest, Option<bdk_wallet::bitcoin::FeeRate>)> {
+        // If this is None, there's been no deposit to this sidechain yet. We're the first one!
+        let sidechain_ctip = self.validator.try_get_ctip(destination.sidechain_number)?;
+
+        let sidechain_ctip_amount = sidechain_ctip
+            .as_ref()
+            .map(|ctip| ctip.value)
+            .unwrap_or(Amount::ZERO);
+
+        let deposit_outputs = destination.outputs(sidechain_ctip_amount, value);
+
+        tracing::debug!(
+            "Created OP_DRIVECHAIN output with value `{}`, spk `{}` ",
+            deposit_outputs[0].value,
+            deposit_outputs[0].script_pubkey.to_asm_string(),
+        );
+
         // If the sidechain has a Ctip (i.e. treasury UTXO), the BIP300 rules mandate that we spend the previous
         // Ctip.
-        let ctip_foreign_utxo = match sidechain_ctip {
+        let foreign_inputs = match sidechain_ctip {
             Some(sidechain_ctip) => {
                 let outpoint = bdk_wallet::bitcoin::OutPoint {
                     txid: convert::bitcoin_txid_to_bdk_txid(sidechain_ctip.outpoint.txid),
@@ -856,104 +1085,44 @@ impl Wallet {
 
                 let ctip_transaction = self.fetch_transaction(sidechain_ctip.outpoint.txid).await?;
 
-                let psbt_input = bdk_wallet::bitcoin::psbt::Input {
-                    non_witness_utxo: Some(ctip_transaction),
-                    ..bdk_wallet::bitcoin::psbt::Input::default()
-                };
-
-                Some((psbt_input, outpoint))
+                vec![(outpoint, ctip_transaction)]
             }
-            None => None,
+            None => Vec::new(),
         };
 
-        let psbt = {
-            let mut wallet = self.bitcoin_wallet.lock();
-            let mut builder = wallet.borrow_mut().build_tx();
-
-            builder
-                // important: the M5 OP_DRIVECHAIN output must come directly before the OP_RETURN sidechain address output.
-                .add_recipient(
-                    op_drivechain_output.script_pubkey,
-                    op_drivechain_output.value,
-                )
-                .add_data(&sidechain_address_data);
-
-            if let Some(fee) = fee {
-                builder.fee_absolute(fee);
-            }
-
-            if let Some((ctip_psbt_input, outpoint)) = ctip_foreign_utxo {
-                // This might be wrong. Seems to work!
-                let satisfaction_weight = bdk_wallet::bitcoin::Weight::ZERO;
-
-                builder
-                    .add_foreign_utxo(outpoint, ctip_psbt_input, satisfaction_weight)
-                    .into_diagnostic()?;
-            }
-
-            builder.ordering(Self::deposit_txordering(
-                [(
-                    sidechain_address_data.as_bytes().to_owned(),
-                    sidechain_number,
-                )]
-                .into_iter()
-                .collect(),
-            ));
-
-            builder.finish().into_diagnostic()?
+        let fee_rate = match fee {
+            Some(_) => None,
+            None => Some(
+                self.fee_estimator
+                    .estimate(fee_estimator::DEPOSIT_CONF_TARGET)
+                    .await,
+            ),
         };
 
-        Ok(psbt)
+        let request = FundingRequest {
+            foreign_inputs,
+            // important: the M5 OP_DRIVECHAIN output must come directly before the OP_RETURN sidechain address output.
+            outputs: deposit_outputs.into(),
+            fee,
+            fee_rate,
+            locktime: None,
+        };
+        Ok((request, fee_rate))
     }
 
     /// Creates a deposit transaction, persists it to the database, and returns the TXID.
-    /// This is also known as a M5 message, in BIP300 nomenclature.
-    ///
-    /// https://github.com/bitcoin/bips/blob/master/bip-0300.mediawiki#m5----deposit-btc-from-l1-to-l2
+    /// If `fee` is not set, the fee rate is estimated, and the estimated fee
+    /// rate is returned along with the TXID.
     pub async fn create_deposit(
         &self,
-        sidechain_number: SidechainNumber,
-        sidechain_address: Vec<u8>,
+        destination: DepositDestination,
         value: Amount,
         fee: Option<Amount>,
-    ) -> Result<bitcoin::Txid> {
-        // If this is None, there's been no deposit to this sidechain yet. We're the first one!
-        let sidechain_ctip = self.validator.try_get_ctip(sidechain_number)?;
-        let sidechain_ctip = sidechain_ctip.as_ref();
-
-        let sidechain_ctip_amount = sidechain_ctip
-            .map(|ctip| ctip.value)
-            .unwrap_or(Amount::ZERO);
-
-        let op_drivechain_output = Self::create_deposit_op_drivechain_output(
-            sidechain_number,
-            sidechain_ctip_amount,
-            value,
-        );
-
-        tracing::debug!(
-            "Created OP_DRIVECHAIN output with value `{}`, spk `{}` ",
-            op_drivechain_output.value,
-            op_drivechain_output.script_pubkey.to_asm_string(),
-        );
-
-        let sidechain_address_data = bdk_wallet::bitcoin::script::PushBytesBuf::try_from(
-            sidechain_address,
-        )
-        .map_err(|err| miette!("failed to convert sidechain address to PushBytesBuf: {err:#}"))?;
-
-        let psbt = self
-            .create_deposit_psbt(
-                op_drivechain_output,
-                sidechain_address_data,
-                sidechain_ctip,
-                fee,
-            )
+    ) -> Result<(bitcoin::Txid, Option<bdk_wallet::bitcoin::FeeRate>)> {
+        let (request, fee_rate) = self
+            .deposit_funding_request(&destination, value, fee)
             .await?;
-
-        tracing::debug!("Created deposit PSBT: {psbt}",);
-
-        let tx = self.sign_transaction(psbt)?;
+        let tx = self.backend.fund_and_sign(request).await?;
         let txid = tx.compute_txid();
 
         tracing::info!("Signed deposit transaction: `{txid}`",);
@@ -967,86 +1136,220 @@ impl Wallet {
 
         tracing::info!("Broadcasted deposit transaction: `{txid}`",);
 
-        Ok(convert::bdk_txid_to_bitcoin_txid(txid))
+        Ok((convert::bdk_txid_to_bitcoin_txid(txid), fee_rate))
     }
 
-    pub fn get_balance(&self) -> Result<()> {
-        if self.last_sync.read().is_none() {
-            return Err(miette!("get balance: wallet not synced"));
-        }
-
-        let balance = self.bitcoin_wallet.lock().balance();
-
-        tracing::trace!("Confirmed: {}", balance.confirmed);
-        tracing::trace!("Immature: {}", balance.immature);
-        tracing::trace!("Untrusted pending: {}", balance.untrusted_pending);
-        tracing::trace!("Trusted pending: {}", balance.trusted_pending);
-        Ok(())
+    /// Creates an unsigned deposit PSBT, to be signed by an external signer
+    /// and submitted with [`Self::finalize_and_broadcast_psbt`].
+    /// If `fee` is not set, the fee rate is estimated, and the estimated fee
+    /// rate is returned along with the PSBT.
+    pub async fn create_deposit_psbt(
+        &self,
+        destination: DepositDestination,
+        value: Amount,
+        fee: Option<Amount>,
+    ) -> Result<(
+        bdk_wallet::bitcoin::psbt::Psbt,
+        Option<bdk_wallet::bitcoin::FeeRate>,
+    )> {
+        let (request, fee_rate) = self
+            .deposit_funding_request(&destination, value, fee)
+            .await?;
+        let psbt = self.backend.fund_psbt(request).await?;
+        tracing::info!(
+            "Created unsigned deposit PSBT: `{}`",
+            psbt.unsigned_tx.compute_txid()
+        );
+        Ok((psbt, fee_rate))
     }
 
-    pub fn sync(&self) -> Result<()> {
-        let start = SystemTime::now();
-        tracing::trace!("starting wallet sync");
-
-        let mut wallet_lock = self.bitcoin_wallet.lock();
-        let mut last_sync_write = self.last_sync.write();
-        let request = wallet_lock.start_sync_with_revealed_spks();
-
-        const BATCH_SIZE: usize = 5;
-        const FETCH_PREV_TXOUTS: bool = false;
-
-        let update = self
-            .bitcoin_blockchain
-            .sync(request, BATCH_SIZE, FETCH_PREV_TXOUTS)
-            .into_diagnostic()?;
-
-        wallet_lock.apply_update(update).into_diagnostic()?;
-
-        let mut database = self.bitcoin_db.lock();
-        wallet_lock.persist(&mut database).into_diagnostic()?;
+    /// Finalize a PSBT that was signed by an external signer, and broadcast
+    /// the resulting tx. Inputs spending an OP_DRIVECHAIN output (ie. a CTIP)
+    /// are spent with an empty script sig and witness.
+    pub async fn finalize_and_broadcast_psbt(
+        &self,
+        psbt: bdk_wallet::bitcoin::psbt::Psbt,
+    ) -> Result<bdk_wallet::bitcoin::Transaction> {
+        let psbt = self.backend.finalize_psbt(psbt).await?;
+        let mut tx = psbt.unsigned_tx.clone();
+        for (idx, (txin, psbt_input)) in tx.input.iter_mut().zip(&psbt.inputs).enumerate() {
+            let spent_script = psbt
+                .spend_utxo(idx)
+                .map(|txout| txout.script_pubkey.clone())
+                .into_diagnostic()?;
+            if messages::parse_op_drivechain(spent_script.as_bytes()).is_ok() {
+                continue;
+            }
+            match (
+                &psbt_input.final_script_sig,
+                &psbt_input.final_script_witness,
+            ) {
+                (None, None) => {
+                    return Err(miette!("PSBT input {idx} is not signed"));
+                }
+                (script_sig, witness) => {
+                    txin.script_sig = script_sig.clone().unwrap_or_default();
+                    txin.witness = witness.clone().unwrap_or_default();
+                }
+            }
+        }
+        self.broadcast_transaction(tx.clone()).await?;
+        tracing::info!(
+            "Broadcasted externally signed transaction: `{}`",
+            tx.compute_txid()
+        );
+        Ok(tx)
+    }
 
-        tracing::debug!(
-            "wallet sync complete in {:?}",
-            start.elapsed().unwrap_or_default(),
+    /// Bump the fee rate of an unconfirmed deposit tx via CPFP, by spending
+    /// the deposit's change output to ourselves.
+    /// A stuck deposit blocks all subsequent deposits to the same sidechain,
+    /// since they must spend the CTIP created by the stuck deposit.
+    /// If `fee_rate` is not set, the fee rate is estimated.
+    /// Returns the child tx and the resulting fee rate of the package.
+    pub async fn bump_deposit_fee(
+        &self,
+        txid: bitcoin::Txid,
+        fee_rate: Option<bdk_wallet::bitcoin::FeeRate>,
+    ) -> Result<(
+        bdk_wallet::bitcoin::Transaction,
+        bdk_wallet::bitcoin::FeeRate,
+    )> {
+        // Initial estimate for the size of a 1-input, 1-output tx
+        const CHILD_VSIZE_ESTIMATE: u64 = 150;
+
+        let mempool_entry: MempoolEntry = self
+            .main_client
+            .request("getmempoolentry", rpc_params![txid])
+            .await
+            .map_err(|err| miette!("deposit tx `{txid}` is not in the mempool: {err:#}"))?;
+        let parent = self.fetch_transaction(txid).await?;
+        if !parent.output.iter().any(|txout| {
+            crate::messages::parse_op_drivechain(txout.script_pubkey.as_bytes()).is_ok()
+        }) {
+            return Err(miette!("tx `{txid}` is not a deposit tx"));
+        }
+        let mut change_vout = None;
+        for (vout, txout) in parent.output.iter().enumerate() {
+            if self.backend.is_mine(txout.script_pubkey.clone()).await? {
+                change_vout = Some(vout as u32);
+                break;
+            }
+        }
+        let change_vout =
+            change_vout.ok_or_else(|| miette!("deposit tx `{txid}` has no change output"))?;
+        let fee_rate = match fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => {
+                self.fee_estimator
+                    .estimate(fee_estimator::DEPOSIT_CONF_TARGET)
+                    .await
+            }
+        };
+        let ancestor_fees = Amount::from_btc(mempool_entry.fees.ancestor).into_diagnostic()?;
+        // The fee required for the package of unconfirmed ancestors and the
+        // child to pay `fee_rate`
+        let child_fee = |child_vsize: u64| -> Result<Amount> {
+            let package_fee = fee_rate
+                .fee_vb(mempool_entry.ancestorsize + child_vsize)
+                .ok_or_else(|| miette!("fee overflow"))?;
+            package_fee
+                .checked_sub(ancestor_fees)
+                .filter(|fee| *fee > Amount::ZERO)
+                .ok_or_else(|| {
+                    miette!(
+                        "deposit tx `{txid}` already pays at least {} sat/vB",
+                        fee_rate.to_sat_per_vb_ceil()
+                    )
+                })
+        };
+        let mut child = self
+            .backend
+            .sweep_to_self(
+                parent.clone(),
+                change_vout,
+                child_fee(CHILD_VSIZE_ESTIMATE)?,
+            )
+            .await?;
+        if child.vsize() as u64 > CHILD_VSIZE_ESTIMATE {
+            child = self
+                .backend
+                .sweep_to_self(parent, change_vout, child_fee(child.vsize() as u64)?)
+                .await?;
+        }
+        let child_txid = child.compute_txid();
+        tracing::info!(
+            "Bumping deposit tx `{txid}` with CPFP tx `{child_txid}` at {} sat/vB",
+            fee_rate.to_sat_per_vb_ceil()
         );
+        self.broadcast_transaction(child.clone()).await?;
+        Ok((child, fee_rate))
+    }
 
-        *last_sync_write = Some(SystemTime::now());
-        drop(last_sync_write);
-        drop(wallet_lock);
-        Ok(())
+    pub async fn sync(&self) -> Result<()> {
+        self.backend.sync().await
     }
 
-    #[allow(
-        clippy::significant_drop_tightening,
-        reason = "false positive for `bitcoin_wallet`"
-    )]
-    fn get_utxos(&self) -> Result<()> {
-        if self.last_sync.read().is_none() {
-            return Err(miette!("get utxos: wallet not synced"));
-        }
+    /// Queue an M3 bundle proposal for a withdrawal bundle, to be included in
+    /// the next generated block. Returns the M6ID of the bundle.
+    pub fn propose_withdrawal_bundle(
+        &self,
+        sidechain_number: SidechainNumber,
+        bundle: &Transaction,
+    ) -> Result<[u8; 32], error::ProposeWithdrawalBundle> {
+        let ctip = self
+            .validator
+            .try_get_ctip(sidechain_number)
+            .map_err(error::ProposeWithdrawalBundle::GetCtip)?
+            .ok_or(error::ProposeWithdrawalBundle::NoCtip(sidechain_number))?;
+        let m6id = messages::m6_to_id(bundle, ctip.value.to_sat())
+            .map_err(error::ProposeWithdrawalBundle::InvalidBundle)?;
+        let sidechain_number_u8: u8 = sidechain_number.into();
+        self.db_connection
+            .lock()
+            .execute(
+                "INSERT OR IGNORE INTO bundle_proposals (sidechain_number, bundle_hash) VALUES (?1, ?2)",
+                (sidechain_number_u8, &m6id),
+            )
+            .map_err(error::ProposeWithdrawalBundle::StoreProposal)?;
+        Ok(m6id)
+    }
 
-        let wallet_lock = self.bitcoin_wallet.lock();
-        let utxos = wallet_lock.list_unspent();
-        for utxo in utxos {
-            tracing::trace!(
-                "address: {}, value: {}",
-                utxo.txout.script_pubkey,
-                utxo.txout.value
-            );
-        }
-        Ok(())
+    fn get_bundle_proposals(&self) -> Result<Vec<(SidechainNumber, [u8; 32])>> {
+        // Satisfy clippy with a single function call per lock
+        let with_connection = |connection: &Connection| -> Result<_, rusqlite::Error> {
+            let mut statement =
+                connection.prepare("SELECT sidechain_number, bundle_hash FROM bundle_proposals")?;
+            let proposals = statement
+                .query_map([], |row| {
+                    let sidechain_number: u8 = row.get(0)?;
+                    let m6id: [u8; 32] = row.get(1)?;
+                    Ok((SidechainNumber(sidechain_number), m6id))
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(proposals)
+        };
+        with_connection(&self.db_connection.lock()).into_diagnostic()
     }
 
-    /// Persists a sidechain proposal into our database.
-    /// On regtest: picked up by the next block generation.
-    /// On signet: TBD, but needs some way of getting communicated to the miner.
+    /// Queue a sidechain proposal, to be included in generated blocks and
+    /// block templates until it has been included in a block. The proposal
+    /// is also acked, so that it is acked in subsequent blocks according to
+    /// the voting policy, unless the ack is removed.
     pub fn propose_sidechain(&self, proposal: &SidechainProposal) -> Result<(), rusqlite::Error> {
         let sidechain_number: u8 = proposal.sidechain_number.into();
-        self.db_connection.lock().execute(
+        let description_hash = proposal.description.sha256d_hash();
+        let mut connection = self.db_connection.lock();
+        let tx = connection.transaction()?;
+        tx.execute(
             "INSERT INTO sidechain_proposals (number, data) VALUES (?1, ?2)",
             (sidechain_number, &proposal.description.0),
         )?;
-        Ok(())
+        tx.execute(
+            "INSERT OR IGNORE INTO sidechain_acks (number, data_hash) VALUES (?1, ?2)",
+            (sidechain_number, description_hash.as_byte_array()),
+        )?;
+        tx.commit()
     }
 
     pub fn nack_sidechain(&self, sidechain_number: u8, data_hash: &[u8; 32]) -> Result<()> {
@@ -1088,24 +1391,6 @@ impl Wallet {
         Ok(active)
     }
 
-    fn sign_transaction(
-        &self,
-        mut psbt: bdk_wallet::bitcoin::psbt::Psbt,
-    ) -> Result<bdk_wallet::bitcoin::Transaction> {
-        if !self
-            .bitcoin_wallet
-            .lock()
-            .sign(&mut psbt, bdk_wallet::signer::SignOptions::default())
-            .into_diagnostic()?
-        {
-            return Err(miette!("failed to sign transaction"));
-        }
-
-        tracing::debug!("Signed PSBT: {psbt}",);
-
-        psbt.extract_tx().into_diagnostic()
-    }
-
     fn bmm_request_message(
         sidechain_number: SidechainNumber,
         prev_mainchain_block_hash: bdk_wallet::bitcoin::BlockHash,
@@ -1123,18 +1408,15 @@ impl Wallet {
         Ok(bdk_wallet::bitcoin::ScriptBuf::new_op_return(&bytes))
     }
 
-    #[allow(
-        clippy::significant_drop_tightening,
-        reason = "false positive for `bitcoin_wallet`"
-    )]
-    fn build_bmm_tx(
+    async fn build_bmm_tx(
         &self,
         sidechain_number: SidechainNumber,
         prev_mainchain_block_hash: bdk_wallet::bitcoin::BlockHash,
         sidechain_block_hash: [u8; 32],
         bid_amount: bdk_wallet::bitcoin::Amount,
         locktime: bdk_wallet::bitcoin::absolute::LockTime,
-    ) -> Result<bdk_wallet::bitcoin::psbt::Psbt> {
+        fee_rate: bdk_wallet::bitcoin::FeeRate,
+    ) -> Result<bdk_wallet::bitcoin::Transaction> {
         // https://github.com/LayerTwo-Labs/bip300_bip301_specifications/blob/master/bip301.md#m8-bmm-request
         let message = Self::bmm_request_message(
             sidechain_number,
@@ -1142,16 +1424,18 @@ impl Wallet {
             sidechain_block_hash,
         )?;
 
-        let psbt = {
-            let mut bitcoin_wallet = self.bitcoin_wallet.lock();
-            let mut builder = bitcoin_wallet.build_tx();
-            builder
-                .nlocktime(locktime)
-                .add_recipient(message, bid_amount);
-            builder.finish().into_diagnostic()?
+        // The M8 output must be the first output
+        let request = FundingRequest {
+            foreign_inputs: Vec::new(),
+            outputs: vec![bdk_wallet::bitcoin::TxOut {
+                script_pubkey: message,
+                value: bid_amount,
+            }],
+            fee: None,
+            fee_rate: Some(fee_rate),
+            locktime: Some(locktime),
         };
-
-        Ok(psbt)
+        self.backend.fund_and_sign(request).await
     }
 
     /// Returns `true` if a BMM request was inserted, `false` if a BMM request
@@ -1161,17 +1445,21 @@ impl Wallet {
         sidechain_number: SidechainNumber,
         prev_blockhash: bdk_wallet::bitcoin::BlockHash,
         side_block_hash: [u8; 32],
+        tx: &bdk_wallet::bitcoin::Transaction,
+        fee_rate: bdk_wallet::bitcoin::FeeRate,
     ) -> Result<bool> {
         // Satisfy clippy with a single function call per lock
         let with_connection = |connection: &Connection| -> Result<bool, rusqlite::Error> {
             connection
                 .prepare(
-                    "INSERT OR ABORT INTO bmm_requests (sidechain_number, prev_block_hash, side_block_hash) VALUES (?1, ?2, ?3)",
+                    "INSERT OR ABORT INTO bmm_requests (sidechain_number, prev_block_hash, side_block_hash, tx, fee_rate_sat_per_kwu) VALUES (?1, ?2, ?3, ?4, ?5)",
                 )?
                 .execute((
                     u8::from(sidechain_number),
                     prev_blockhash.to_byte_array(),
                     side_block_hash,
+                    bdk_wallet::bitcoin::consensus::serialize(tx),
+                    fee_rate.to_sat_per_kwu(),
                 ))
                 .map_or_else(
                     |err| if err.sqlite_error_code() == Some(rusqlite::ErrorCode::ConstraintViolation) {
@@ -1185,40 +1473,765 @@ impl Wallet {
         with_connection(&self.db_connection.lock()).into_diagnostic()
     }
 
-    /// Creates a BMM request transaction. Does NOT broadcast.
-    /// Returns `Some(tx)` if the BMM request was stored, `None` if the BMM
+    /// Signs a BMM request transaction, using an estimated fee rate.
+    /// Does NOT store or broadcast.
+    async fn sign_bmm_request(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_mainchain_block_hash: bdk_wallet::bitcoin::BlockHash,
+        sidechain_block_hash: [u8; 32],
+        bid_amount: bdk_wallet::bitcoin::Amount,
+        locktime: bdk_wallet::bitcoin::absolute::LockTime,
+    ) -> Result<(
+        bdk_wallet::bitcoin::Transaction,
+        bdk_wallet::bitcoin::FeeRate,
+    )> {
+        let fee_rate = self
+            .fee_estimator
+            .estimate(fee_estimator::BMM_REQUEST_CONF_TARGET)
+            .await;
+        let tx = self
+            .build_bmm_tx(
+                sidechain_number,
+                prev_mainchain_block_hash,
+                sidechain_block_hash,
+                bid_amount,
+                locktime,
+                fee_rate,
+            )
+            .await?;
+        tracing::info!("BMM request tx signed successfully");
+        Ok((tx, fee_rate))
+    }
+
+    /// Stores a signed BMM request transaction, and records it in the BMM
+    /// request history.
+    /// Returns `false` if the BMM request was not stored due to pre-existing
+    /// request with the same `sidechain_number` and
+    /// `prev_mainchain_block_hash`.
+    fn store_bmm_request(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_mainchain_block_hash: bdk_wallet::bitcoin::BlockHash,
+        sidechain_block_hash: [u8; 32],
+        bid_amount: bdk_wallet::bitcoin::Amount,
+        tx: &bdk_wallet::bitcoin::Transaction,
+        fee_rate: bdk_wallet::bitcoin::FeeRate,
+    ) -> Result<bool> {
+        if !self.insert_new_bmm_request(
+            sidechain_number,
+            prev_mainchain_block_hash,
+            sidechain_block_hash,
+            tx,
+            fee_rate,
+        )? {
+            tracing::warn!("Ignored BMM request; request exists with same sidechain slot and previous block hash");
+            return Ok(false);
+        }
+        tracing::info!("inserted new bmm request into db");
+        self.insert_bmm_request_record(&BmmRequestRecord {
+            txid: convert::bdk_txid_to_bitcoin_txid(tx.compute_txid()),
+            sidechain_number,
+            prev_block_hash: convert::bdk_block_hash_to_bitcoin_block_hash(
+                prev_mainchain_block_hash,
+            ),
+            sidechain_block_hash,
+            bid: Amount::from_sat(bid_amount.to_sat()),
+            fee: Self::bmm_request_fee(tx, fee_rate),
+            origin: BmmRequestOrigin::Created,
+            state: BmmRequestState::Created,
+            created_at: bmm_request_history::unix_timestamp(),
+            broadcast_at: None,
+            resolved_at: None,
+            block_hash: None,
+            replaced_by: None,
+        })?;
+        Ok(true)
+    }
+
+    /// Fee paid by a BMM request tx that was funded at `fee_rate`
+    pub fn bmm_request_fee(
+        tx: &bdk_wallet::bitcoin::Transaction,
+        fee_rate: bdk_wallet::bitcoin::FeeRate,
+    ) -> Amount {
+        Amount::from_sat(fee_rate.fee_wu(tx.weight()).unwrap_or_default().to_sat())
+    }
+
+    /// Creates a BMM request transaction, using an estimated fee rate.
+    /// Does NOT broadcast.
+    /// Returns `Some((tx, fee_rate))` if the BMM request was stored, `None` if the BMM
     /// request was not stored due to pre-existing request with the same
     /// `sidechain_number` and `prev_mainchain_block_hash`.
-    pub fn create_bmm_request(
+    pub async fn create_bmm_request(
         &self,
         sidechain_number: SidechainNumber,
         prev_mainchain_block_hash: bdk_wallet::bitcoin::BlockHash,
         sidechain_block_hash: [u8; 32],
         bid_amount: bdk_wallet::bitcoin::Amount,
         locktime: bdk_wallet::bitcoin::absolute::LockTime,
-    ) -> Result<Option<bdk_wallet::bitcoin::Transaction>> {
-        let psbt = self.build_bmm_tx(
+    ) -> Result<
+        Option<(
+            bdk_wallet::bitcoin::Transaction,
+            bdk_wallet::bitcoin::FeeRate,
+        )>,
+    > {
+        let (tx, fee_rate) = self
+            .sign_bmm_request(
+                sidechain_number,
+                prev_mainchain_block_hash,
+                sidechain_block_hash,
+                bid_amount,
+                locktime,
+            )
+            .await?;
+        if self.store_bmm_request(
             sidechain_number,
             prev_mainchain_block_hash,
             sidechain_block_hash,
             bid_amount,
-            locktime,
-        )?;
-        let tx = self.sign_transaction(psbt)?;
-        tracing::info!("BMM request psbt signed successfully");
-        if self.insert_new_bmm_request(
+            &tx,
+            fee_rate,
+        )? {
+            Ok(Some((tx, fee_rate)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get the stored tx and fee rate for a BMM request, if the BMM request
+    /// exists and was created with a stored tx
+    fn get_bmm_request_tx(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_blockhash: &bdk_wallet::bitcoin::BlockHash,
+    ) -> Result<
+        Option<(
+            bdk_wallet::bitcoin::Transaction,
+            bdk_wallet::bitcoin::FeeRate,
+        )>,
+    > {
+        // Satisfy clippy with a single function call per lock
+        let with_connection = |connection: &Connection| -> Result<_, rusqlite::Error> {
+            connection
+                .prepare(
+                    "SELECT tx, fee_rate_sat_per_kwu FROM bmm_requests WHERE sidechain_number = ?1 AND prev_block_hash = ?2 AND tx IS NOT NULL",
+                )?
+                .query_row(
+                    (u8::from(sidechain_number), prev_blockhash.to_byte_array()),
+                    |row| {
+                        let tx: Vec<u8> = row.get(0)?;
+                        let fee_rate: u64 = row.get(1)?;
+                        Ok((tx, fee_rate))
+                    },
+                )
+                .optional()
+        };
+        let Some((tx, fee_rate)) = with_connection(&self.db_connection.lock()).into_diagnostic()?
+        else {
+            return Ok(None);
+        };
+        let tx = bdk_wallet::bitcoin::consensus::deserialize(&tx).into_diagnostic()?;
+        Ok(Some((
+            tx,
+            bdk_wallet::bitcoin::FeeRate::from_sat_per_kwu(fee_rate),
+        )))
+    }
+
+    fn update_bmm_request_tx(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_blockhash: &bdk_wallet::bitcoin::BlockHash,
+        tx: &bdk_wallet::bitcoin::Transaction,
+        fee_rate: bdk_wallet::bitcoin::FeeRate,
+    ) -> Result<()> {
+        self.db_connection
+            .lock()
+            .execute(
+                "UPDATE bmm_requests SET tx = ?1, fee_rate_sat_per_kwu = ?2 WHERE sidechain_number = ?3 AND prev_block_hash = ?4",
+                (
+                    bdk_wallet::bitcoin::consensus::serialize(tx),
+                    fee_rate.to_sat_per_kwu(),
+                    u8::from(sidechain_number),
+                    prev_blockhash.to_byte_array(),
+                ),
+            )
+            .into_diagnostic()?;
+        Ok(())
+    }
+
+    /// Replace a BMM request tx with a tx paying a higher fee rate, and
+    /// broadcast the replacement.
+    /// If `fee_rate` is not set, the fee rate is estimated, and increased if
+    /// necessary so that it exceeds the fee rate of the tx being replaced.
+    /// Returns the replacement tx and its fee rate.
+    pub async fn bump_bmm_request(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_mainchain_block_hash: bdk_wallet::bitcoin::BlockHash,
+        fee_rate: Option<bdk_wallet::bitcoin::FeeRate>,
+    ) -> Result<(
+        bdk_wallet::bitcoin::Transaction,
+        bdk_wallet::bitcoin::FeeRate,
+    )> {
+        let Some((tx, current_fee_rate)) =
+            self.get_bmm_request_tx(sidechain_number, &prev_mainchain_block_hash)?
+        else {
+            return Err(miette!(
+                "no BMM request tx for sidechain {sidechain_number} at {prev_mainchain_block_hash}"
+            ));
+        };
+        let fee_rate = match fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => self
+                .fee_estimator
+                .estimate(fee_estimator::BMM_REQUEST_CONF_TARGET)
+                .await
+                .max(Self::min_replacement_fee_rate(current_fee_rate)),
+        };
+        if fee_rate <= current_fee_rate {
+            return Err(miette!(
+                "fee rate ({} sat/vB) must exceed the current fee rate ({} sat/vB)",
+                fee_rate.to_sat_per_vb_ceil(),
+                current_fee_rate.to_sat_per_vb_ceil(),
+            ));
+        }
+        self.replace_bmm_request_tx(sidechain_number, prev_mainchain_block_hash, tx, fee_rate)
+            .await
+    }
+
+    /// Minimum fee rate for a replacement, assuming the default incremental
+    /// relay fee of 1 sat/vB
+    fn min_replacement_fee_rate(
+        fee_rate: bdk_wallet::bitcoin::FeeRate,
+    ) -> bdk_wallet::bitcoin::FeeRate {
+        bdk_wallet::bitcoin::FeeRate::from_sat_per_kwu(
+            fee_rate.to_sat_per_kwu()
+                + bdk_wallet::bitcoin::FeeRate::BROADCAST_MIN.to_sat_per_kwu(),
+        )
+    }
+
+    async fn replace_bmm_request_tx(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_mainchain_block_hash: bdk_wallet::bitcoin::BlockHash,
+        tx: bdk_wallet::bitcoin::Transaction,
+        fee_rate: bdk_wallet::bitcoin::FeeRate,
+    ) -> Result<(
+        bdk_wallet::bitcoin::Transaction,
+        bdk_wallet::bitcoin::FeeRate,
+    )> {
+        let original_txid = tx.compute_txid();
+        let replacement = self.backend.bump_fee(tx, fee_rate).await?;
+        tracing::info!(
+            sidechain_slot = u8::from(sidechain_number),
+            "Replacing BMM request tx `{original_txid}` with `{}` at {} sat/vB",
+            replacement.compute_txid(),
+            fee_rate.to_sat_per_vb_ceil(),
+        );
+        self.broadcast_transaction(replacement.clone()).await?;
+        self.update_bmm_request_tx(
             sidechain_number,
+            &prev_mainchain_block_hash,
+            &replacement,
+            fee_rate,
+        )?;
+        self.record_bmm_request_replacement(
+            convert::bdk_txid_to_bitcoin_txid(original_txid),
+            convert::bdk_txid_to_bitcoin_txid(replacement.compute_txid()),
+            Self::bmm_request_fee(&replacement, fee_rate),
+        )?;
+        Ok((replacement, fee_rate))
+    }
+
+    /// Returns `true` if the tx is in the node's mempool
+    async fn is_in_mempool(&self, txid: bdk_wallet::bitcoin::Txid) -> bool {
+        self.main_client
+            .request::<serde_json::Value, _>("getmempoolentry", rpc_params![txid])
+            .await
+            .is_ok()
+    }
+
+    /// Bump the fee rate of unconfirmed BMM request txs for the current
+    /// mainchain tip, if the estimated fee rate for the next block exceeds
+    /// their fee rate. Fee rates are never bumped above the configured max
+    /// fee rate.
+    pub async fn bump_bmm_requests(&self) -> Result<()> {
+        let mainchain_tip = self.validator.get_mainchain_tip()?;
+        let bmm_requests = self.get_bmm_requests(&mainchain_tip)?;
+        if bmm_requests.is_empty() {
+            return Ok(());
+        }
+        let prev_mainchain_block_hash =
+            convert::bitcoin_block_hash_to_bdk_block_hash(mainchain_tip);
+        let estimated_fee_rate = self
+            .fee_estimator
+            .estimate(fee_estimator::BMM_REQUEST_CONF_TARGET)
+            .await;
+        for (sidechain_number, _) in bmm_requests {
+            let Some((tx, current_fee_rate)) =
+                self.get_bmm_request_tx(sidechain_number, &prev_mainchain_block_hash)?
+            else {
+                continue;
+            };
+            if estimated_fee_rate <= current_fee_rate
+                || current_fee_rate >= self.bmm_max_fee_rate
+                || !self.is_in_mempool(tx.compute_txid()).await
+            {
+                continue;
+            }
+            let fee_rate = estimated_fee_rate
+                .max(Self::min_replacement_fee_rate(current_fee_rate))
+                .min(self.bmm_max_fee_rate);
+            if let Err(err) = self
+                .replace_bmm_request_tx(sidechain_number, prev_mainchain_block_hash, tx, fee_rate)
+                .await
+            {
+                tracing::warn!(
+                    sidechain_slot = u8::from(sidechain_number),
+                    "failed to bump BMM request fee: {err:#}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Locktime for BMM requests that commit to `mainchain_tip`
+    fn bmm_request_locktime(
+        &self,
+        mainchain_tip: &bitcoin::BlockHash,
+    ) -> Result<bdk_wallet::bitcoin::absolute::LockTime> {
+        let tip_height = self.validator.get_header_info(mainchain_tip)?.height;
+        // Only valid for inclusion in the block after the mainchain tip
+        bdk_wallet::bitcoin::absolute::LockTime::from_height(tip_height).into_diagnostic()
+    }
+
+    /// Create, sign and broadcast a BMM request for the next mainchain block.
+    /// Returns the broadcast tx, the previous mainchain block hash that the
+    /// request commits to, and the fee rate.
+    /// Returns `None` if a BMM request already exists for the sidechain slot
+    /// and current mainchain tip.
+    pub async fn request_bmm(
+        &self,
+        sidechain_number: SidechainNumber,
+        sidechain_block_hash: [u8; 32],
+        bid_amount: bdk_wallet::bitcoin::Amount,
+    ) -> Result<
+        Option<(
+            bdk_wallet::bitcoin::Transaction,
+            bitcoin::BlockHash,
+            bdk_wallet::bitcoin::FeeRate,
+        )>,
+    > {
+        let mainchain_tip = self.validator.get_mainchain_tip()?;
+        let locktime = self.bmm_request_locktime(&mainchain_tip)?;
+        let Some((tx, fee_rate)) = self
+            .create_bmm_request(
+                sidechain_number,
+                convert::bitcoin_block_hash_to_bdk_block_hash(mainchain_tip),
+                sidechain_block_hash,
+                bid_amount,
+                locktime,
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+        self.broadcast_transaction(tx.clone()).await?;
+        self.set_bmm_request_state(
+            convert::bdk_txid_to_bitcoin_txid(tx.compute_txid()),
+            BmmRequestState::Broadcast,
+            None,
+        )?;
+        tracing::info!(
+            sidechain_slot = u8::from(sidechain_number),
+            txid = %tx.compute_txid(),
+            "broadcast BMM request for mainchain tip {mainchain_tip}"
+        );
+        Ok(Some((tx, mainchain_tip, fee_rate)))
+    }
+
+    /// Store a BMM rebid, replacing any existing BMM rebid for the sidechain
+    /// slot
+    pub fn put_bmm_rebid(&self, rebid: &BmmRebid) -> Result<()> {
+        bmm_rebid::put(&self.db_connection.lock(), rebid).into_diagnostic()
+    }
+
+    /// Stop re-bidding for the sidechain slot.
+    /// Returns `false` if there was no BMM rebid for the sidechain slot.
+    pub fn cancel_bmm_rebid(&self, sidechain_number: SidechainNumber) -> Result<bool> {
+        bmm_rebid::cancel(&self.db_connection.lock(), sidechain_number).into_diagnostic()
+    }
+
+    fn get_bmm_rebids(&self) -> Result<Vec<BmmRebid>> {
+        bmm_rebid::list(&self.db_connection.lock()).into_diagnostic()
+    }
+
+    /// Stop re-bidding, unless the BMM rebid was cancelled or replaced since
+    /// it was read
+    fn stop_bmm_rebid(&self, rebid: &BmmRebid, stop: BmmRebidStop) -> Result<()> {
+        let sidechain_slot = u8::from(rebid.sidechain_number);
+        match stop {
+            BmmRebidStop::Accepted { block_hash } => tracing::info!(
+                sidechain_slot,
+                "BMM request accepted in `{block_hash}` after {} attempt(s), stopped re-bidding",
+                rebid.attempts
+            ),
+            BmmRebidStop::MaxAttempts => tracing::warn!(
+                sidechain_slot,
+                "BMM request not accepted after {} attempt(s), stopped re-bidding",
+                rebid.attempts
+            ),
+            BmmRebidStop::MaxTotalFee => tracing::warn!(
+                sidechain_slot,
+                "re-bidding would exceed the max total fee of {}, stopped re-bidding",
+                rebid.max_total_fee
+            ),
+        }
+        let _: bool = bmm_rebid::stop(&self.db_connection.lock(), rebid).into_diagnostic()?;
+        Ok(())
+    }
+
+    /// Delete a stored BMM request that could not be broadcast, and its
+    /// record in the BMM request history, so that it can be created again
+    fn remove_bmm_request(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_blockhash: bdk_wallet::bitcoin::BlockHash,
+        txid: Txid,
+    ) -> Result<()> {
+        // Satisfy clippy with a single function call per lock
+        let with_connection = |connection: &mut Connection| -> Result<(), rusqlite::Error> {
+            let tx = connection.transaction()?;
+            let _: usize = tx.execute(
+                "DELETE FROM bmm_requests WHERE sidechain_number = ?1 AND prev_block_hash = ?2",
+                (u8::from(sidechain_number), prev_blockhash.to_byte_array()),
+            )?;
+            let _: usize = tx.execute(
+                "DELETE FROM bmm_request_history WHERE txid = ?1",
+                [txid.to_byte_array()],
+            )?;
+            tx.commit()
+        };
+        with_connection(&mut self.db_connection.lock()).into_diagnostic()
+    }
+
+    /// Re-bid a BMM request against the current mainchain tip, if the most
+    /// recent BMM request for it was not accepted. Stops re-bidding once the
+    /// sidechain block hash is accepted, or if broadcasting another BMM
+    /// request would exceed the max attempts or max total fee.
+    async fn rebid_bmm_request(
+        &self,
+        rebid: BmmRebid,
+        mainchain_tip: bitcoin::BlockHash,
+    ) -> Result<()> {
+        let sidechain_slot = u8::from(rebid.sidechain_number);
+        let status = self.get_bmm_request_status(
+            rebid.sidechain_number,
+            rebid.last_prev_block_hash,
+            rebid.sidechain_block_hash,
+        )?;
+        match rebid.should_rebid(status) {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(stop) => return self.stop_bmm_rebid(&rebid, stop),
+        }
+        let prev_mainchain_block_hash =
+            convert::bitcoin_block_hash_to_bdk_block_hash(mainchain_tip);
+        let bid_amount = bdk_wallet::bitcoin::Amount::from_sat(rebid.bid.to_sat());
+        let (tx, fee_rate) = self
+            .sign_bmm_request(
+                rebid.sidechain_number,
+                prev_mainchain_block_hash,
+                rebid.sidechain_block_hash,
+                bid_amount,
+                self.bmm_request_locktime(&mainchain_tip)?,
+            )
+            .await?;
+        let updated = match rebid.with_attempt(mainchain_tip, Self::bmm_request_fee(&tx, fee_rate))
+        {
+            Ok(updated) => updated,
+            Err(stop) => return self.stop_bmm_rebid(&rebid, stop),
+        };
+        if !self.store_bmm_request(
+            rebid.sidechain_number,
             prev_mainchain_block_hash,
-            sidechain_block_hash,
+            rebid.sidechain_block_hash,
+            bid_amount,
+            &tx,
+            fee_rate,
         )? {
-            tracing::info!("inserted new bmm request into db");
-            Ok(Some(tx))
+            // A BMM request for the mainchain tip already exists, so wait for
+            // its outcome without counting it as an attempt
+            let waiting = BmmRebid {
+                last_prev_block_hash: mainchain_tip,
+                ..rebid
+            };
+            let _: bool = bmm_rebid::update(&self.db_connection.lock(), &rebid, &waiting)
+                .into_diagnostic()?;
+            return Ok(());
+        }
+        let txid = convert::bdk_txid_to_bitcoin_txid(tx.compute_txid());
+        if let Err(err) = self.broadcast_transaction(tx).await {
+            // Retry in the next round, rather than waiting for the outcome of
+            // a BMM request that was never broadcast
+            self.remove_bmm_request(rebid.sidechain_number, prev_mainchain_block_hash, txid)?;
+            return Err(err);
+        }
+        self.set_bmm_request_state(txid, BmmRequestState::Broadcast, None)?;
+        if !bmm_rebid::update(&self.db_connection.lock(), &rebid, &updated).into_diagnostic()? {
+            tracing::info!(
+                sidechain_slot,
+                %txid,
+                "BMM rebid was cancelled or replaced while re-bidding for mainchain tip {mainchain_tip}"
+            );
+            return Ok(());
+        }
+        tracing::info!(
+            sidechain_slot,
+            %txid,
+            "re-bid BMM request for mainchain tip {mainchain_tip} (attempt {}/{})",
+            updated.attempts,
+            updated.max_attempts
+        );
+        Ok(())
+    }
+
+    /// Re-bid BMM requests that were not accepted against the current
+    /// mainchain tip
+    pub async fn rebid_bmm_requests(&self) -> Result<()> {
+        let mainchain_tip = self.validator.get_mainchain_tip()?;
+        for rebid in self.get_bmm_rebids()? {
+            if rebid.last_prev_block_hash == mainchain_tip {
+                continue;
+            }
+            let sidechain_number = rebid.sidechain_number;
+            if let Err(err) = self.rebid_bmm_request(rebid, mainchain_tip).await {
+                tracing::warn!(
+                    sidechain_slot = u8::from(sidechain_number),
+                    "failed to re-bid BMM request: {err:#}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the mainchain block following `prev_blockhash` accepted
+    /// the sidechain block hash for the sidechain slot
+    pub fn get_bmm_request_status(
+        &self,
+        sidechain_number: SidechainNumber,
+        prev_blockhash: bitcoin::BlockHash,
+        sidechain_block_hash: [u8; 32],
+    ) -> Result<BmmRequestStatus> {
+        let prev_height = self.validator.get_header_info(&prev_blockhash)?.height;
+        let best_chain_prev = self
+            .validator
+            .try_get_best_chain_block_hash(prev_height)
+            .into_diagnostic()?;
+        if best_chain_prev != Some(prev_blockhash) {
+            return Ok(BmmRequestStatus::Expired);
+        }
+        let Some(block_hash) = self
+            .validator
+            .try_get_best_chain_block_hash(prev_height + 1)
+            .into_diagnostic()?
+        else {
+            return Ok(BmmRequestStatus::Pending);
+        };
+        let accepted = self
+            .validator
+            .try_get_bmm_commitments(&block_hash)
+            .into_diagnostic()?
+            .and_then(|commitments| commitments.get(&sidechain_number).copied())
+            == Some(sidechain_block_hash);
+        if accepted {
+            Ok(BmmRequestStatus::Accepted { block_hash })
         } else {
-            tracing::warn!("Ignored BMM request; request exists with same sidechain slot and previous block hash");
-            Ok(None)
+            Ok(BmmRequestStatus::NotAccepted { block_hash })
         }
     }
 
+    /// Record a BMM request in the history, if it is not recorded yet
+    fn insert_bmm_request_record(&self, record: &BmmRequestRecord) -> Result<()> {
+        self.db_connection
+            .lock()
+            .execute(
+                "INSERT OR IGNORE INTO bmm_request_history (txid, sidechain_number, prev_block_hash, side_block_hash, bid_sats, fee_sats, origin, state, created_at, broadcast_at, resolved_at, block_hash, replaced_by) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                rusqlite::params![
+                    record.txid.to_byte_array(),
+                    u8::from(record.sidechain_number),
+                    record.prev_block_hash.to_byte_array(),
+                    record.sidechain_block_hash,
+                    record.bid.to_sat(),
+                    record.fee.to_sat(),
+                    record.origin,
+                    record.state,
+                    record.created_at,
+                    record.broadcast_at,
+                    record.resolved_at,
+                    record.block_hash.map(|block_hash| block_hash.to_byte_array()),
+                    record.replaced_by.map(|txid| txid.to_byte_array()),
+                ],
+            )
+            .into_diagnostic()?;
+        Ok(())
+    }
+
+    /// Set the state of a BMM request in the history, and the time at which
+    /// it was broadcast or resolved
+    fn set_bmm_request_state(
+        &self,
+        txid: Txid,
+        state: BmmRequestState,
+        block_hash: Option<bitcoin::BlockHash>,
+    ) -> Result<()> {
+        let now = bmm_request_history::unix_timestamp();
+        let (broadcast_at, resolved_at) = if state.is_resolved() {
+            (None, Some(now))
+        } else {
+            (Some(now), None)
+        };
+        self.db_connection
+            .lock()
+            .execute(
+                "UPDATE bmm_request_history SET state = ?1, broadcast_at = COALESCE(broadcast_at, ?2), resolved_at = ?3, block_hash = ?4 WHERE txid = ?5",
+                rusqlite::params![
+                    state,
+                    broadcast_at,
+                    resolved_at,
+                    block_hash.map(|block_hash| block_hash.to_byte_array()),
+                    txid.to_byte_array(),
+                ],
+            )
+            .into_diagnostic()?;
+        Ok(())
+    }
+
+    /// Mark a BMM request as replaced, and record the broadcast replacement
+    fn record_bmm_request_replacement(
+        &self,
+        original_txid: Txid,
+        replacement_txid: Txid,
+        fee: Amount,
+    ) -> Result<()> {
+        let now = bmm_request_history::unix_timestamp();
+        let mut connection = self.db_connection.lock();
+        let tx = connection.transaction().into_diagnostic()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO bmm_request_history (txid, sidechain_number, prev_block_hash, side_block_hash, bid_sats, fee_sats, origin, state, created_at, broadcast_at) SELECT ?1, sidechain_number, prev_block_hash, side_block_hash, bid_sats, ?2, origin, ?3, ?4, ?4 FROM bmm_request_history WHERE txid = ?5",
+            rusqlite::params![
+                replacement_txid.to_byte_array(),
+                fee.to_sat(),
+                BmmRequestState::Broadcast,
+                now,
+                original_txid.to_byte_array(),
+            ],
+        )
+        .into_diagnostic()?;
+        tx.execute(
+            "UPDATE bmm_request_history SET state = ?1, resolved_at = ?2, replaced_by = ?3 WHERE txid = ?4",
+            rusqlite::params![
+                BmmRequestState::Replaced,
+                now,
+                replacement_txid.to_byte_array(),
+                original_txid.to_byte_array(),
+            ],
+        )
+        .into_diagnostic()?;
+        tx.commit().into_diagnostic()?;
+        Ok(())
+    }
+
+    /// BMM requests in the history, most recent first, optionally filtered
+    /// by sidechain slot
+    pub fn list_bmm_request_history(
+        &self,
+        sidechain_number: Option<SidechainNumber>,
+    ) -> Result<Vec<BmmRequestRecord>> {
+        // Satisfy clippy with a single function call per lock
+        let with_connection = |connection: &Connection| -> Result<_, rusqlite::Error> {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM bmm_request_history WHERE ?1 IS NULL OR sidechain_number = ?1 ORDER BY created_at DESC, rowid DESC",
+                bmm_request_history::COLUMNS
+            ))?;
+            let rows = statement
+                .query_map([sidechain_number.map(u8::from)], BmmRequestRecord::from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        };
+        with_connection(&self.db_connection.lock()).into_diagnostic()
+    }
+
+    /// Advance unresolved BMM requests in the history. Requests that were
+    /// created but not broadcast by the wallet are marked as broadcast once
+    /// they are in the node's mempool. Requests for previous mainchain
+    /// blocks are resolved from the BIP300/301 txs indexed by the validator.
+    /// Requests that were resolved longer than
+    /// [`bmm_request_history::RETENTION`] ago are pruned.
+    pub async fn update_bmm_request_history(&self) -> Result<()> {
+        let mainchain_tip = self.validator.get_mainchain_tip()?;
+        let unresolved =
+            bmm_request_history::list_unresolved(&self.db_connection.lock()).into_diagnostic()?;
+        for record in unresolved {
+            if record.prev_block_hash == mainchain_tip {
+                if record.state == BmmRequestState::Created
+                    && self
+                        .is_in_mempool(convert::bitcoin_txid_to_bdk_txid(record.txid))
+                        .await
+                {
+                    self.set_bmm_request_state(record.txid, BmmRequestState::Broadcast, None)?;
+                }
+                continue;
+            }
+            let bip300_transaction = self
+                .validator
+                .try_get_bip300_transaction(&record.txid)
+                .into_diagnostic()?;
+            let (state, block_hash) = match bip300_transaction {
+                Some(crate::types::Bip300Transaction {
+                    block_hash,
+                    kind: crate::types::Bip300TransactionKind::M8BmmRequest { accepted },
+                }) => {
+                    let state = if accepted {
+                        BmmRequestState::Accepted
+                    } else {
+                        BmmRequestState::NotAccepted
+                    };
+                    (state, Some(block_hash))
+                }
+                Some(_) | None => {
+                    // Not included in the block following the previous
+                    // mainchain block, unless that block is not synced yet
+                    match self.get_bmm_request_status(
+                        record.sidechain_number,
+                        record.prev_block_hash,
+                        record.sidechain_block_hash,
+                    )? {
+                        BmmRequestStatus::Pending => continue,
+                        BmmRequestStatus::Accepted { .. }
+                        | BmmRequestStatus::NotAccepted { .. }
+                        | BmmRequestStatus::Expired => (BmmRequestState::Expired, None),
+                    }
+                }
+            };
+            tracing::debug!(
+                sidechain_slot = u8::from(record.sidechain_number),
+                txid = %record.txid,
+                "BMM request is {}",
+                state.as_str()
+            );
+            self.set_bmm_request_state(record.txid, state, block_hash)?;
+        }
+        let resolved_before = bmm_request_history::unix_timestamp()
+            .saturating_sub(bmm_request_history::RETENTION.as_secs());
+        let pruned = bmm_request_history::prune(&self.db_connection.lock(), resolved_before)
+            .into_diagnostic()?;
+        if pruned > 0 {
+            tracing::debug!("Pruned {pruned} resolved BMM requests from the history");
+        }
+        Ok(())
+    }
+
     // Broadcasts a transaction to the Bitcoin network.
     pub async fn broadcast_transaction(&self, tx: bdk_wallet::bitcoin::Transaction) -> Result<()> {
         // Note: there's a `broadcast` method on `bitcoin_blockchain`. We're NOT using that,
@@ -1249,20 +2262,20 @@ impl Wallet {
         Ok(())
     }
 
-    #[allow(clippy::significant_drop_tightening)]
-    pub fn get_new_address(&self) -> Result<bdk_wallet::bitcoin::Address> {
-        // Using next_unused_address here means that we get a new address
-        // when funds are received. Without this we'd need to take care not
-        // to cross the wallet scan gap.
-        let mut wallet = self.bitcoin_wallet.lock();
-        let info = wallet
-            .borrow_mut()
-            .next_unused_address(bdk_wallet::KeychainKind::External);
-
-        let mut bitcoin_db = self.bitcoin_db.lock();
-        let bitcoin_db = bitcoin_db.borrow_mut();
-        wallet.persist(bitcoin_db).into_diagnostic()?;
-        Ok(info.address)
+    /// Import output descriptors into the wallet, optionally rescanning from
+    /// the specified height
+    pub async fn import_descriptors(
+        &self,
+        descriptors: Vec<DescriptorImport>,
+        rescan_from_height: Option<u32>,
+    ) -> Result<()> {
+        self.backend
+            .import_descriptors(descriptors, rescan_from_height)
+            .await
+    }
+
+    pub async fn get_new_address(&self) -> Result<bdk_wallet::bitcoin::Address> {
+        self.backend.new_address().await
     }
 }
 
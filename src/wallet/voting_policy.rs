@@ -0,0 +1,335 @@
+//! Operator-configured voting policy, for sidechain proposals and withdrawal
+//! bundles.
+//!
+//! The policy is read from a JSON file, eg.
+//!
+//! ```json
+//! {
+//!   "sidechain_acks": [
+//!     { "sidechain_number": 1, "description_hash": "<32 byte hex>" }
+//!   ],
+//!   "bundle_votes": [
+//!     { "sidechain_number": 1, "vote": "upvote", "m6id": "<32 byte hex>" },
+//!     { "sidechain_number": 2, "vote": "alarm" }
+//!   ]
+//! }
+//! ```
+
+use std::{collections::BTreeMap, path::Path};
+
+use bitcoin::hashes::{sha256d, Hash as _};
+use rusqlite::Connection;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    messages::{ABSTAIN_TWO_BYTES, ALARM_TWO_BYTES},
+    types::{Hash256, PendingM6id, SidechainAck, SidechainNumber},
+};
+
+/// How to vote on the pending withdrawal bundles of a sidechain
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BundleVote {
+    /// Upvote the pending bundle with this M6ID
+    Upvote {
+        m6id: Hash256,
+    },
+    Abstain,
+    /// Downvote all pending bundles
+    Alarm,
+}
+
+#[derive(Debug, Error)]
+pub enum LoadVotingPolicyError {
+    #[error("failed to read voting policy file `{path}`")]
+    Read {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("failed to parse voting policy file `{path}`")]
+    Parse {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("invalid hash `{hash}` in voting policy, expected 32 bytes hex")]
+    InvalidHash { hash: String },
+    #[error("`m6id` is required to upvote bundles for sidechain {sidechain_number}")]
+    MissingM6id { sidechain_number: SidechainNumber },
+    #[error("multiple bundle votes for sidechain {sidechain_number}")]
+    DuplicateBundleVote { sidechain_number: SidechainNumber },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum BundleVoteKind {
+    Upvote,
+    Abstain,
+    Alarm,
+}
+
+#[derive(Debug, Deserialize)]
+struct SidechainAckEntry {
+    sidechain_number: SidechainNumber,
+    description_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BundleVoteEntry {
+    sidechain_number: SidechainNumber,
+    vote: BundleVoteKind,
+    #[serde(default)]
+    m6id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VotingPolicyFile {
+    #[serde(default)]
+    sidechain_acks: Vec<SidechainAckEntry>,
+    #[serde(default)]
+    bundle_votes: Vec<BundleVoteEntry>,
+}
+
+fn parse_hash(hash: &str) -> Result<Hash256, LoadVotingPolicyError> {
+    hex::FromHex::from_hex(hash).map_err(|_| LoadVotingPolicyError::InvalidHash {
+        hash: hash.to_owned(),
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VotingPolicy {
+    /// Sidechain proposals to ack
+    pub sidechain_acks: Vec<SidechainAck>,
+    /// Votes on withdrawal bundles. Sidechains without a vote abstain.
+    pub bundle_votes: BTreeMap<SidechainNumber, BundleVote>,
+}
+
+impl VotingPolicy {
+    pub fn load(path: &Path) -> Result<Self, LoadVotingPolicyError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|source| LoadVotingPolicyError::Read {
+                path: path.display().to_string(),
+                source,
+            })?;
+        let file: VotingPolicyFile =
+            serde_json::from_str(&contents).map_err(|source| LoadVotingPolicyError::Parse {
+                path: path.display().to_string(),
+                source,
+            })?;
+        let sidechain_acks = file
+            .sidechain_acks
+            .into_iter()
+            .map(|entry| {
+                let description_hash = parse_hash(&entry.description_hash)?;
+                Ok(SidechainAck {
+                    sidechain_number: entry.sidechain_number,
+                    description_hash: sha256d::Hash::from_byte_array(description_hash),
+                })
+            })
+            .collect::<Result<_, LoadVotingPolicyError>>()?;
+        let mut bundle_votes = BTreeMap::new();
+        for entry in file.bundle_votes {
+            let sidechain_number = entry.sidechain_number;
+            let vote = match entry.vote {
+                BundleVoteKind::Upvote => {
+                    let m6id = entry
+                        .m6id
+                        .ok_or(LoadVotingPolicyError::MissingM6id { sidechain_number })?;
+                    BundleVote::Upvote {
+                        m6id: parse_hash(&m6id)?,
+                    }
+                }
+                BundleVoteKind::Abstain => BundleVote::Abstain,
+                BundleVoteKind::Alarm => BundleVote::Alarm,
+            };
+            if bundle_votes.insert(sidechain_number, vote).is_some() {
+                return Err(LoadVotingPolicyError::DuplicateBundleVote { sidechain_number });
+            }
+        }
+        Ok(Self {
+            sidechain_acks,
+            bundle_votes,
+        })
+    }
+}
+
+/// Two byte upvotes for bundle votes, one per sidechain slot up to the
+/// highest slot with pending M6IDs, given the pending M6IDs for each active
+/// sidechain.
+/// The M4 message can be encoded with
+/// [`crate::messages::M4AckBundles::compact`].
+pub fn bundle_upvotes(
+    bundle_votes: &BTreeMap<SidechainNumber, BundleVote>,
+    pending_m6ids: &BTreeMap<SidechainNumber, Vec<PendingM6id>>,
+) -> Vec<u16> {
+    let Some(max_sidechain_number) = pending_m6ids.keys().next_back() else {
+        return Vec::new();
+    };
+    (0..=max_sidechain_number.0)
+        .map(|sidechain_number| {
+            let sidechain_number = SidechainNumber(sidechain_number);
+            let Some(pending_m6ids) = pending_m6ids.get(&sidechain_number) else {
+                return ABSTAIN_TWO_BYTES;
+            };
+            match bundle_votes.get(&sidechain_number) {
+                None | Some(BundleVote::Abstain) => ABSTAIN_TWO_BYTES,
+                Some(BundleVote::Alarm) => ALARM_TWO_BYTES,
+                Some(BundleVote::Upvote { m6id }) => {
+                    match pending_m6ids
+                        .iter()
+                        .position(|pending| pending.m6id == *m6id)
+                    {
+                        Some(index) => index as u16,
+                        None => {
+                            tracing::debug!(
+                                sidechain_slot = sidechain_number.0,
+                                "M6ID {} in voting policy is not pending, abstaining",
+                                hex::encode(m6id)
+                            );
+                            ABSTAIN_TWO_BYTES
+                        }
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Update the voting policy persisted in the wallet DB, in a single
+/// transaction. See [`super::Wallet::update_voting_policy`].
+pub(super) fn update(
+    connection: &mut Connection,
+    ack_sidechains: &[SidechainAck],
+    nack_sidechains: &[SidechainAck],
+    bundle_votes: &BTreeMap<SidechainNumber, BundleVote>,
+) -> Result<(), rusqlite::Error> {
+    let tx = connection.transaction()?;
+    for ack in ack_sidechains {
+        tx.execute(
+            "INSERT OR IGNORE INTO sidechain_acks (number, data_hash) VALUES (?1, ?2)",
+            (ack.sidechain_number.0, ack.description_hash.as_byte_array()),
+        )?;
+    }
+    for nack in nack_sidechains {
+        tx.execute(
+            "DELETE FROM sidechain_acks WHERE number = ?1 AND data_hash = ?2",
+            (
+                nack.sidechain_number.0,
+                nack.description_hash.as_byte_array(),
+            ),
+        )?;
+    }
+    for (sidechain_number, vote) in bundle_votes {
+        let (vote, m6id) = super::bundle_vote_columns(vote);
+        tx.execute(
+            "INSERT OR REPLACE INTO bundle_votes (sidechain_number, vote, m6id) VALUES (?1, ?2, ?3)",
+            (sidechain_number.0, vote, m6id),
+        )?;
+    }
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ack(sidechain_number: u8, description_hash: u8) -> SidechainAck {
+        SidechainAck {
+            sidechain_number: SidechainNumber(sidechain_number),
+            description_hash: sha256d::Hash::from_byte_array([description_hash; 32]),
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::super::migrations()
+            .to_latest(&mut connection)
+            .unwrap();
+        let acks = |connection: &Connection| -> Vec<(u8, [u8; 32])> {
+            let mut statement = connection
+                .prepare("SELECT number, data_hash FROM sidechain_acks ORDER BY number")
+                .unwrap();
+            statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        let votes = |connection: &Connection| -> Vec<(u8, String, Option<[u8; 32]>)> {
+            let mut statement = connection
+                .prepare(
+                    "SELECT sidechain_number, vote, m6id FROM bundle_votes ORDER BY sidechain_number",
+                )
+                .unwrap();
+            statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        let () = update(
+            &mut connection,
+            &[ack(1, 1), ack(2, 2)],
+            &[],
+            &BTreeMap::from([
+                (SidechainNumber(1), BundleVote::Upvote { m6id: [1; 32] }),
+                (SidechainNumber(2), BundleVote::Alarm),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(acks(&connection), vec![(1, [1; 32]), (2, [2; 32])]);
+        // Acks are added to existing acks, and nacks remove them. Bundle
+        // votes replace votes for the same sidechain only.
+        let () = update(
+            &mut connection,
+            &[ack(3, 3)],
+            &[ack(1, 1), ack(4, 4)],
+            &BTreeMap::from([(SidechainNumber(1), BundleVote::Abstain)]),
+        )
+        .unwrap();
+        assert_eq!(acks(&connection), vec![(2, [2; 32]), (3, [3; 32])]);
+        assert_eq!(
+            votes(&connection),
+            vec![
+                (1, "abstain".to_owned(), None),
+                (2, "alarm".to_owned(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bundle_upvotes() {
+        let pending = |m6ids: &[Hash256]| {
+            m6ids
+                .iter()
+                .map(|m6id| PendingM6id {
+                    m6id: *m6id,
+                    vote_count: 0,
+                    proposal_height: 0,
+                })
+                .collect::<Vec<_>>()
+        };
+        let pending_m6ids = BTreeMap::from([
+            (SidechainNumber(0), pending(&[[0; 32], [1; 32]])),
+            (SidechainNumber(2), pending(&[[2; 32]])),
+            (SidechainNumber(3), pending(&[[3; 32]])),
+        ]);
+        let bundle_votes = BTreeMap::from([
+            (SidechainNumber(0), BundleVote::Upvote { m6id: [1; 32] }),
+            (SidechainNumber(1), BundleVote::Alarm),
+            (SidechainNumber(2), BundleVote::Alarm),
+            (SidechainNumber(3), BundleVote::Upvote { m6id: [4; 32] }),
+        ]);
+        assert_eq!(
+            bundle_upvotes(&bundle_votes, &pending_m6ids),
+            vec![1, ABSTAIN_TWO_BYTES, ALARM_TWO_BYTES, ABSTAIN_TWO_BYTES]
+        );
+        assert_eq!(
+            bundle_upvotes(&BTreeMap::new(), &pending_m6ids),
+            vec![ABSTAIN_TWO_BYTES; 4]
+        );
+        assert!(bundle_upvotes(&bundle_votes, &BTreeMap::new()).is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+//! Webhook notifications for deposit confirmation events
+
+use std::time::Duration;
+
+use futures::{Stream, StreamExt as _};
+use reqwest::header::CONTENT_TYPE;
+
+use crate::types::Event;
+
+/// POSTs `DepositConfirmed` and `DepositUnconfirmed` events to a URL, with
+/// the same JSON bodies as the REST and JSON-RPC event streams
+pub struct DepositWebhook {
+    client: reqwest::Client,
+    url: reqwest::Url,
+}
+
+impl DepositWebhook {
+    const MAX_ATTEMPTS: u32 = 3;
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+    const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(url: reqwest::Url) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Self::REQUEST_TIMEOUT)
+            .build()?;
+        tracing::info!("Posting deposit confirmation events to {url}");
+        Ok(Self { client, url })
+    }
+
+    async fn post(&self, body: &serde_json::Value) -> Result<(), reqwest::Error> {
+        let _resp: reqwest::Response = self
+            .client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Retries failed requests, up to [`Self::MAX_ATTEMPTS`] attempts
+    pub async fn notify(&self, sequence: u64, event: &Event) -> Result<(), reqwest::Error> {
+        let (Event::DepositConfirmed { .. } | Event::DepositUnconfirmed { .. }) = event else {
+            return Ok(());
+        };
+        let Some(body) = crate::json::event(sequence, event, None) else {
+            return Ok(());
+        };
+        let mut attempt = 1;
+        loop {
+            match self.post(&body).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt >= Self::MAX_ATTEMPTS => return Err(err),
+                Err(err) => {
+                    tracing::debug!(
+                        "Failed to post event {sequence} to deposit webhook (attempt {attempt}): {err:#}"
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(Self::RETRY_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Post events until the events stream ends or fails
+    pub async fn run<S, E>(self, events: S)
+    where
+        S: Stream<Item = Result<(u64, Event), E>>,
+        E: std::error::Error,
+    {
+        let mut events = std::pin::pin!(events);
+        while let Some(res) = events.next().await {
+            let (sequence, event) = match res {
+                Ok(event) => event,
+                Err(err) => {
+                    tracing::error!("Error in events stream, stopping deposit webhook: {err:#}");
+                    return;
+                }
+            };
+            if let Err(err) = self.notify(sequence, &event).await {
+                tracing::warn!("Failed to post event {sequence} to deposit webhook: {err:#}");
+            }
+        }
+    }
+}
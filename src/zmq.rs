@@ -1,13 +1,16 @@
 use std::ops::Add;
 
-use bitcoin::{hashes::Hash as _, BlockHash, Txid};
+use bitcoin::{hashes::Hash as _, BlockHash, Transaction, Txid};
 use either::Either;
 use futures::{
     stream::{self, BoxStream},
     Stream, StreamExt, TryStreamExt as _,
 };
+use serde_json::json;
 use thiserror::Error;
-use zeromq::{Socket as _, SocketRecv as _, ZmqError, ZmqMessage};
+use zeromq::{Socket as _, SocketRecv as _, SocketSend as _, ZmqError, ZmqMessage};
+
+use crate::types::{Event, WithdrawalBundleEventKind};
 
 #[derive(Clone, Copy, Debug)]
 pub enum SequenceMessage {
@@ -239,3 +242,186 @@ pub async fn subscribe_sequence<'a>(
     .boxed();
     Ok(SequenceStream(inner))
 }
+
+#[derive(Debug, Error)]
+pub enum RawTxStreamError {
+    #[error("Error decoding transaction")]
+    Decode(#[from] bitcoin::consensus::encode::Error),
+    #[error("Missing transaction (frame 1)")]
+    MissingTx,
+    #[error("ZMQ error")]
+    Zmq(#[from] ZmqError),
+}
+
+/// Subscribe to transactions published by the node via `rawtx`. These
+/// include transactions added to the mempool, and transactions in connected
+/// blocks.
+#[tracing::instrument]
+pub async fn subscribe_rawtx<'a>(
+    zmq_addr_rawtx: &str,
+) -> Result<BoxStream<'a, Result<Transaction, RawTxStreamError>>, ZmqError> {
+    let mut socket = zeromq::SubSocket::new();
+    socket.connect(zmq_addr_rawtx).await?;
+    socket.subscribe("rawtx").await?;
+    tracing::info!("Subscribed to `rawtx`");
+    let res = stream::try_unfold(socket, |mut socket| async {
+        let msg = socket.recv().await?;
+        let tx_bytes = msg.get(1).ok_or(RawTxStreamError::MissingTx)?;
+        let tx: Transaction = bitcoin::consensus::deserialize(tx_bytes)?;
+        Ok(Some((tx, socket)))
+    })
+    .boxed();
+    Ok(res)
+}
+
+/// Topics and JSON bodies of the messages published for an event.
+/// Sidechain-specific topics end in `/`, so that subscribing to eg.
+/// `deposit/1/` does not also match `deposit/10/`.
+fn event_messages(event: &Event) -> Vec<(String, serde_json::Value)> {
+    match event {
+        Event::ConnectBlock {
+            header_info,
+            block_info,
+        } => {
+            let block_hash = header_info.block_hash.to_string();
+            let height = header_info.height;
+            let mut messages = vec![(
+                "connectblock".to_owned(),
+                json!({
+                    "block_hash": block_hash,
+                    "prev_block_hash": header_info.prev_block_hash.to_string(),
+                    "height": height,
+                }),
+            )];
+            messages.extend(block_info.deposits.iter().map(|deposit| {
+                let body = json!({
+                    "block_hash": block_hash,
+                    "height": height,
+                    "sidechain_number": deposit.sidechain_id.0,
+                    "sequence_number": deposit.sequence_number,
+                    "outpoint": deposit.outpoint.to_string(),
+                    "address": hex::encode(&deposit.address),
+                    "value_sats": deposit.value.to_sat(),
+                });
+                (format!("deposit/{}/", deposit.sidechain_id.0), body)
+            }));
+            messages.extend(
+                block_info
+                    .withdrawal_bundle_events
+                    .iter()
+                    .map(|bundle_event| {
+                        let kind = match bundle_event.kind {
+                            WithdrawalBundleEventKind::Submitted => "submitted",
+                            WithdrawalBundleEventKind::Failed => "failed",
+                            WithdrawalBundleEventKind::Succeeded => "succeeded",
+                        };
+                        let body = json!({
+                            "block_hash": block_hash,
+                            "height": height,
+                            "sidechain_number": bundle_event.sidechain_id.0,
+                            "m6id": hex::encode(bundle_event.m6id),
+                            "kind": kind,
+                        });
+                        (
+                            format!("withdrawalbundle/{}/", bundle_event.sidechain_id.0),
+                            body,
+                        )
+                    }),
+            );
+            messages.extend(block_info.bmm_commitments.iter().map(
+                |(sidechain_number, sidechain_block_hash)| {
+                    let body = json!({
+                        "block_hash": block_hash,
+                        "height": height,
+                        "sidechain_number": sidechain_number.0,
+                        "sidechain_block_hash": hex::encode(sidechain_block_hash),
+                    });
+                    (format!("bmmaccept/{}/", sidechain_number.0), body)
+                },
+            ));
+            messages
+        }
+        Event::DisconnectBlock { block_hash } => vec![(
+            "disconnectblock".to_owned(),
+            json!({ "block_hash": block_hash.to_string() }),
+        )],
+        Event::WithdrawalBundleAlert { block_hash, alert } => {
+            let body = json!({
+                "block_hash": block_hash.to_string(),
+                "sidechain_number": alert.sidechain_id.0,
+                "m6id": hex::encode(alert.m6id),
+                "previous_vote_count": alert.previous_vote_count,
+                "vote_count": alert.vote_count,
+                "leading": alert.leading,
+            });
+            vec![(
+                format!("withdrawalbundlealert/{}/", alert.sidechain_id.0),
+                body,
+            )]
+        }
+        Event::DepositConfirmed {
+            block_hash,
+            confirmation,
+        } => vec![(
+            format!("depositconfirmed/{}/", confirmation.deposit.sidechain_id.0),
+            crate::json::deposit_confirmation(block_hash, confirmation),
+        )],
+        Event::DepositUnconfirmed {
+            block_hash,
+            confirmation,
+        } => vec![(
+            format!(
+                "depositunconfirmed/{}/",
+                confirmation.deposit.sidechain_id.0
+            ),
+            crate::json::deposit_confirmation(block_hash, confirmation),
+        )],
+    }
+}
+
+/// Publishes enforcer events on a ZMQ PUB socket.
+/// Messages have three frames, similar to Bitcoin Core's ZMQ notifications:
+/// the topic, a JSON body, and the event's sequence number in the event
+/// journal as an 8 byte little-endian integer.
+pub struct EventPublisher(zeromq::PubSocket);
+
+impl EventPublisher {
+    #[tracing::instrument]
+    pub async fn bind(zmq_addr: &str) -> Result<Self, ZmqError> {
+        let mut socket = zeromq::PubSocket::new();
+        let _endpoint = socket.bind(zmq_addr).await?;
+        tracing::info!("Publishing events via ZMQ on {zmq_addr}");
+        Ok(Self(socket))
+    }
+
+    pub async fn publish(&mut self, sequence: u64, event: &Event) -> Result<(), ZmqError> {
+        for (topic, body) in event_messages(event) {
+            let mut msg = ZmqMessage::from(sequence.to_le_bytes().to_vec());
+            msg.prepend(&ZmqMessage::from(body.to_string()));
+            msg.prepend(&ZmqMessage::from(topic));
+            let () = self.0.send(msg).await?;
+        }
+        Ok(())
+    }
+
+    /// Publish events until the events stream ends or fails
+    pub async fn run<S, E>(mut self, events: S)
+    where
+        S: Stream<Item = Result<(u64, Event), E>>,
+        E: std::error::Error,
+    {
+        let mut events = std::pin::pin!(events);
+        while let Some(res) = events.next().await {
+            let (sequence, event) = match res {
+                Ok(event) => event,
+                Err(err) => {
+                    tracing::error!("Error in events stream, stopping ZMQ publisher: {err:#}");
+                    return;
+                }
+            };
+            if let Err(err) = self.publish(sequence, &event).await {
+                tracing::warn!("Failed to publish event {sequence} via ZMQ: {err:#}");
+            }
+        }
+    }
+}
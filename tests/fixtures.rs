@@ -0,0 +1,29 @@
+//! Replays the block fixtures in `tests/fixtures`.
+//!
+//! Fixtures are recorded by running the enforcer with
+//! `--record-fixtures-dir`. Copy fixtures for blocks that should be covered
+//! by regression tests into `tests/fixtures`.
+
+use std::path::Path;
+
+use bip300301_enforcer::validator::fixtures;
+
+#[test]
+fn replay_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return;
+    };
+    let mut failures = Vec::new();
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        let fixture = fixtures::load(&path).unwrap();
+        for problem in fixtures::replay(&fixture).unwrap() {
+            failures.push(format!("{}: {problem}", path.display()));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
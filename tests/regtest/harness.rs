@@ -0,0 +1,333 @@
+//! Runs `bitcoind` in regtest, with an enforcer connected to it.
+//! Blocks are built and mined by the harness, so that tests can include
+//! arbitrary coinbase messages and transactions.
+
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    process::{Child, Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bip300301::MainClient as _;
+use bip300301_enforcer::{
+    cli::NodeRpcConfig,
+    messages::CoinbaseBuilder,
+    rpc_client,
+    types::{BlockInfo, Event},
+    validator::{Backpressure, DepositConfirmationDepths, EventStream, SubscribeEventsOptions},
+    Validator,
+};
+use bitcoin::{
+    absolute::LockTime,
+    block::{Header, Version as BlockVersion},
+    consensus::encode,
+    constants::genesis_block,
+    hash_types::TxMerkleNode,
+    hashes::Hash as _,
+    opcodes::{all::OP_PUSHNUM_1, OP_0},
+    script::Builder as ScriptBuilder,
+    transaction::Version as TxVersion,
+    Amount, Block, BlockHash, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Witness,
+};
+use futures::StreamExt as _;
+use miette::{miette, IntoDiagnostic as _, Result};
+use tempfile::TempDir;
+
+const RPC_USER: &str = "regtest";
+const RPC_PASS: &str = "regtest";
+
+/// Regtest halving interval
+const SUBSIDY_HALVING_INTERVAL: u32 = 150;
+
+const COINBASE_MATURITY: u32 = 100;
+
+/// Max time to wait for bitcoind to start, and for the enforcer to connect
+/// a block
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Script that can be spent with an empty script sig
+pub fn op_true_script() -> ScriptBuf {
+    ScriptBuilder::new().push_opcode(OP_PUSHNUM_1).into_script()
+}
+
+fn free_port() -> Result<u16> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).into_diagnostic()?;
+    Ok(listener.local_addr().into_diagnostic()?.port())
+}
+
+fn block_subsidy(height: u32) -> Amount {
+    let halvings = height / SUBSIDY_HALVING_INTERVAL;
+    if halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount::from_sat(Amount::from_int_btc(50).to_sat() >> halvings)
+}
+
+/// `bitcoind` process, killed on drop
+pub struct Bitcoind {
+    process: Child,
+    pub rpc_addr: SocketAddr,
+    pub zmq_addr_sequence: String,
+    _data_dir: TempDir,
+}
+
+impl Bitcoind {
+    /// Spawn `bitcoind` in regtest. The binary is read from the `BITCOIND`
+    /// env var, and defaults to `bitcoind` in `PATH`.
+    pub fn spawn() -> Result<Self> {
+        let bin = std::env::var("BITCOIND").unwrap_or_else(|_| "bitcoind".to_owned());
+        let data_dir = TempDir::new().into_diagnostic()?;
+        let rpc_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, free_port()?));
+        let zmq_addr_sequence = format!("tcp://127.0.0.1:{}", free_port()?);
+        let process = Command::new(&bin)
+            .arg("-regtest")
+            .arg(format!("-datadir={}", data_dir.path().display()))
+            .arg("-listen=0")
+            .arg("-disablewallet")
+            .arg(format!("-rpcport={}", rpc_addr.port()))
+            .arg(format!("-rpcuser={RPC_USER}"))
+            .arg(format!("-rpcpassword={RPC_PASS}"))
+            .arg(format!("-zmqpubsequence={zmq_addr_sequence}"))
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|err| miette!("failed to spawn `{bin}`: {err:#}"))?;
+        Ok(Self {
+            process,
+            rpc_addr,
+            zmq_addr_sequence,
+            _data_dir: data_dir,
+        })
+    }
+
+    pub fn rpc_config(&self) -> NodeRpcConfig {
+        NodeRpcConfig {
+            addr: Some(self.rpc_addr),
+            cookie_path: None,
+            user: Some(RPC_USER.to_owned()),
+            pass: Some(RPC_PASS.to_owned()),
+            timeout_secs: TIMEOUT.as_secs(),
+            max_retries: 0,
+            rest_url: None,
+            p2p_addr: None,
+            p2p_magic: None,
+        }
+    }
+}
+
+impl Drop for Bitcoind {
+    fn drop(&mut self) {
+        let _kill_res: std::io::Result<()> = self.process.kill();
+        let _wait_res: std::io::Result<_> = self.process.wait();
+    }
+}
+
+/// Coinbase output that can be spent by tests once it matures
+struct CoinbaseUtxo {
+    height: u32,
+    outpoint: OutPoint,
+    value: Amount,
+}
+
+pub struct Harness {
+    pub validator: Validator,
+    pub client: rpc_client::Client,
+    events: EventStream,
+    tip: BlockHash,
+    height: u32,
+    last_block_time: u32,
+    coinbase_utxos: VecDeque<CoinbaseUtxo>,
+    _enforcer_data_dir: TempDir,
+    // Dropped last, so that the enforcer shuts down first
+    _bitcoind: Bitcoind,
+}
+
+impl Harness {
+    /// Start `bitcoind` and an enforcer, and mine enough blocks for the
+    /// first coinbase output to mature
+    pub async fn new() -> Result<Self> {
+        let bitcoind = Bitcoind::spawn()?;
+        let client = rpc_client::create_client(&bitcoind.rpc_config(), &[], Network::Regtest)?;
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+        let blockchain_info = loop {
+            match client.get_blockchain_info().await {
+                Ok(blockchain_info) => break blockchain_info,
+                Err(err) if tokio::time::Instant::now() >= deadline => {
+                    return Err(miette!("bitcoind did not start: {err:#}"))
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        };
+        let enforcer_data_dir = TempDir::new().into_diagnostic()?;
+        let validator = Validator::new(
+            client.clone(),
+            vec![bitcoind.zmq_addr_sequence.clone()],
+            enforcer_data_dir.path(),
+            None,
+            DepositConfirmationDepths::default(),
+            None,
+            |err| async move { panic!("enforcer failed: {err:#}") },
+        )
+        .await
+        .into_diagnostic()?;
+        let events = validator
+            .subscribe_events_with_options(SubscribeEventsOptions {
+                backpressure: Backpressure::Unbounded,
+                ..Default::default()
+            })
+            .into_diagnostic()?;
+        let mut harness = Self {
+            validator,
+            client,
+            events,
+            tip: blockchain_info.best_blockhash,
+            height: blockchain_info.blocks,
+            last_block_time: genesis_block(Network::Regtest).header.time,
+            coinbase_utxos: VecDeque::new(),
+            _enforcer_data_dir: enforcer_data_dir,
+            _bitcoind: bitcoind,
+        };
+        for _ in 0..=COINBASE_MATURITY {
+            let _: (BlockHash, BlockInfo) =
+                harness.mine(CoinbaseBuilder::new(), Vec::new()).await?;
+        }
+        Ok(harness)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Take the oldest coinbase output that can be spent in the next block.
+    /// Coinbase outputs are locked to [`op_true_script`].
+    pub fn take_spendable(&mut self) -> Result<(OutPoint, Amount)> {
+        let next_height = self.height + 1;
+        let utxo = match self.coinbase_utxos.pop_front() {
+            Some(utxo) if utxo.height + COINBASE_MATURITY <= next_height => utxo,
+            Some(utxo) => {
+                self.coinbase_utxos.push_front(utxo);
+                return Err(miette!("no mature coinbase outputs"));
+            }
+            None => return Err(miette!("no coinbase outputs")),
+        };
+        Ok((utxo.outpoint, utxo.value))
+    }
+
+    /// Build, mine and submit a block with the specified coinbase messages
+    /// and transactions, and wait for the enforcer to connect it.
+    /// Returns the block hash, and the block info from the enforcer's
+    /// `ConnectBlock` event.
+    pub async fn mine(
+        &mut self,
+        coinbase_messages: CoinbaseBuilder,
+        transactions: Vec<Transaction>,
+    ) -> Result<(BlockHash, BlockInfo)> {
+        let height = self.height + 1;
+        let coinbase_messages = coinbase_messages.build().into_diagnostic()?;
+        let subsidy = block_subsidy(height);
+        let coinbase = Transaction {
+            version: TxVersion::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuilder::new()
+                    .push_int(height as i64)
+                    .push_opcode(OP_0)
+                    .into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: std::iter::once(TxOut {
+                value: subsidy,
+                script_pubkey: op_true_script(),
+            })
+            .chain(coinbase_messages)
+            .collect(),
+        };
+        let coinbase_txid = coinbase.compute_txid();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .into_diagnostic()?
+            .as_secs() as u32;
+        let time = now.max(self.last_block_time + 1);
+        let mut block = Block {
+            header: Header {
+                version: BlockVersion::NO_SOFT_FORK_SIGNALLING,
+                prev_blockhash: self.tip,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time,
+                bits: genesis_block(Network::Regtest).header.bits,
+                nonce: 0,
+            },
+            txdata: std::iter::once(coinbase).chain(transactions).collect(),
+        };
+        block.header.merkle_root = block
+            .compute_merkle_root()
+            .ok_or_else(|| miette!("failed to compute merkle root"))?;
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+        let block_hash = block.block_hash();
+        let () = self
+            .client
+            .submit_block(encode::serialize_hex(&block))
+            .await
+            .map_err(|err| miette!("failed to submit block `{block_hash}`: {err:#}"))?;
+        self.tip = block_hash;
+        self.height = height;
+        self.last_block_time = time;
+        if subsidy > Amount::ZERO {
+            self.coinbase_utxos.push_back(CoinbaseUtxo {
+                height,
+                outpoint: OutPoint {
+                    txid: coinbase_txid,
+                    vout: 0,
+                },
+                value: subsidy,
+            });
+        }
+        let block_info = self.wait_for_block(block_hash).await?;
+        Ok((block_hash, block_info))
+    }
+
+    /// Wait for the enforcer to emit a `ConnectBlock` event for the block
+    async fn wait_for_block(&mut self, block_hash: BlockHash) -> Result<BlockInfo> {
+        let wait = async {
+            while let Some(event) = self.events.next().await {
+                let (_sequence, event) = event?;
+                if let Event::ConnectBlock {
+                    header_info,
+                    block_info,
+                } = event
+                {
+                    if header_info.block_hash == block_hash {
+                        return Ok(block_info);
+                    }
+                }
+            }
+            Err(miette!("event stream ended"))
+        };
+        tokio::time::timeout(TIMEOUT, wait)
+            .await
+            .map_err(|_| miette!("timed out waiting for block `{block_hash}`"))?
+    }
+}
+
+/// Transaction spending outputs locked to [`op_true_script`]
+pub fn spend_op_true(inputs: &[OutPoint], outputs: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: TxVersion::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|outpoint| TxIn {
+                previous_output: *outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs,
+    }
+}
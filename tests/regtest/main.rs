@@ -0,0 +1,184 @@
+//! Integration tests against `bitcoind` in regtest.
+//!
+//! These require a `bitcoind` binary, which is read from the `BITCOIND` env
+//! var and defaults to `bitcoind` in `PATH`. They are ignored by default, run
+//! them with `cargo test --test regtest -- --ignored`.
+
+mod harness;
+
+use bip300301_enforcer::{
+    messages::{create_m5_deposit_output, m6_to_id, CoinbaseBuilder, M4AckBundles},
+    types::{BlockInfo, SidechainNumber, SidechainProposal, WithdrawalBundleEventKind},
+};
+use bitcoin::{script::PushBytes, Amount, BlockHash, OutPoint, ScriptBuf, TxOut};
+use miette::{ensure, miette, IntoDiagnostic as _, Result};
+
+use harness::{op_true_script, spend_op_true, Harness};
+
+/// Acks required to activate a sidechain in an unused slot
+const ACTIVATION_ACKS: usize = 6;
+
+/// Upvotes required to include a withdrawal bundle
+const INCLUSION_UPVOTES: usize = 6;
+
+const FEE: Amount = Amount::from_sat(1_000);
+
+/// M1 -> M2 -> M5 -> M3 -> M4 -> M6
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "requires bitcoind"]
+async fn sidechain_lifecycle() -> Result<()> {
+    let mut harness = Harness::new().await?;
+    let sidechain_number = SidechainNumber(0);
+
+    // M1: propose sidechain
+    let proposal = SidechainProposal {
+        sidechain_number,
+        description: b"regtest sidechain".to_vec().into(),
+    };
+    let description_hash = proposal.description.sha256d_hash();
+    let proposal_height = harness.height() + 1;
+    let (_, block_info) = harness
+        .mine(
+            CoinbaseBuilder::new().propose_sidechain(proposal.clone()),
+            Vec::new(),
+        )
+        .await?;
+    ensure!(
+        block_info.sidechain_proposals.len() == 1,
+        "expected sidechain proposal in block info"
+    );
+
+    // M2: ack sidechain until it activates
+    for _ in 0..ACTIVATION_ACKS {
+        let _: (BlockHash, BlockInfo) = harness
+            .mine(
+                CoinbaseBuilder::new().ack_sidechain(sidechain_number, description_hash),
+                Vec::new(),
+            )
+            .await?;
+    }
+    let sidechains = harness.validator.get_active_sidechains()?;
+    let [sidechain] = sidechains.as_slice() else {
+        return Err(miette!("expected one active sidechain, got {sidechains:?}"));
+    };
+    ensure!(
+        sidechain.proposal == proposal,
+        "unexpected sidechain proposal"
+    );
+    ensure!(sidechain.status.proposal_height == proposal_height);
+    ensure!(sidechain.status.activation_height == Some(harness.height()));
+
+    // M5: deposit
+    let deposit_value = Amount::from_int_btc(1);
+    let address = b"sidechain address".to_vec();
+    let address_push_bytes = <&PushBytes>::try_from(address.as_slice()).into_diagnostic()?;
+    let (funding_outpoint, funding_value) = harness.take_spendable()?;
+    let deposit_tx = spend_op_true(
+        &[funding_outpoint],
+        vec![
+            create_m5_deposit_output(sidechain_number, Amount::ZERO, deposit_value),
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new_op_return(address_push_bytes),
+            },
+            TxOut {
+                value: funding_value - deposit_value - FEE,
+                script_pubkey: op_true_script(),
+            },
+        ],
+    );
+    let deposit_outpoint = OutPoint {
+        txid: deposit_tx.compute_txid(),
+        vout: 0,
+    };
+    let (_, block_info) = harness
+        .mine(CoinbaseBuilder::new(), vec![deposit_tx])
+        .await?;
+    let [deposit] = block_info.deposits.as_slice() else {
+        return Err(miette!(
+            "expected one deposit, got {:?}",
+            block_info.deposits
+        ));
+    };
+    ensure!(deposit.sidechain_id == sidechain_number);
+    ensure!(deposit.outpoint == deposit_outpoint);
+    ensure!(deposit.address == address);
+    ensure!(deposit.value == deposit_value);
+    let ctip = harness
+        .validator
+        .try_get_ctip(sidechain_number)?
+        .ok_or_else(|| miette!("missing CTIP after deposit"))?;
+    ensure!(ctip.outpoint == deposit_outpoint);
+    ensure!(ctip.value == deposit_value);
+
+    // M3: propose withdrawal bundle.
+    // The first output of the M6 is the new treasury UTXO.
+    let payout_value = Amount::from_sat(50_000_000);
+    let m6 = spend_op_true(
+        &[deposit_outpoint],
+        vec![
+            create_m5_deposit_output(
+                sidechain_number,
+                Amount::ZERO,
+                deposit_value - payout_value - FEE,
+            ),
+            TxOut {
+                value: payout_value,
+                script_pubkey: op_true_script(),
+            },
+        ],
+    );
+    let m6id = m6_to_id(&m6, deposit_value.to_sat()).into_diagnostic()?;
+    let (_, block_info) = harness
+        .mine(
+            CoinbaseBuilder::new().propose_bundle(sidechain_number, &m6id),
+            Vec::new(),
+        )
+        .await?;
+    ensure!(
+        block_info.withdrawal_bundle_events.iter().any(|event| {
+            event.m6id == m6id && matches!(event.kind, WithdrawalBundleEventKind::Submitted)
+        }),
+        "expected bundle submitted event"
+    );
+
+    // M4: upvote the bundle until it can be included
+    for _ in 0..INCLUSION_UPVOTES {
+        let _: (BlockHash, BlockInfo) = harness
+            .mine(
+                CoinbaseBuilder::new().ack_bundles(M4AckBundles::OneByte { upvotes: vec![0] }),
+                Vec::new(),
+            )
+            .await?;
+    }
+    let pending_m6ids = harness.validator.get_pending_m6ids(sidechain_number)?;
+    let [pending_m6id] = pending_m6ids.as_slice() else {
+        return Err(miette!("expected one pending M6ID, got {pending_m6ids:?}"));
+    };
+    ensure!(pending_m6id.m6id == m6id);
+    ensure!(pending_m6id.vote_count as usize == INCLUSION_UPVOTES);
+
+    // M6: withdrawal
+    let m6_outpoint = OutPoint {
+        txid: m6.compute_txid(),
+        vout: 0,
+    };
+    let (_, block_info) = harness.mine(CoinbaseBuilder::new(), vec![m6]).await?;
+    ensure!(
+        block_info.withdrawal_bundle_events.iter().any(|event| {
+            event.m6id == m6id && matches!(event.kind, WithdrawalBundleEventKind::Succeeded)
+        }),
+        "expected bundle succeeded event"
+    );
+    ensure!(harness
+        .validator
+        .get_pending_m6ids(sidechain_number)?
+        .is_empty());
+    let ctip = harness
+        .validator
+        .try_get_ctip(sidechain_number)?
+        .ok_or_else(|| miette!("missing CTIP after withdrawal"))?;
+    ensure!(ctip.outpoint == m6_outpoint);
+    ensure!(ctip.value == deposit_value - payout_value - FEE);
+    Ok(())
+}